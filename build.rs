@@ -3,6 +3,12 @@ fn main() {
     println!("cargo:rerun-if-changed=src/graph.cc");
     println!("cargo:rerun-if-changed=include/tensat.h");
 
+    // There's no C++ toolchain for wasm, the pure Rust paths don't need the
+    // glue (see the wasm section of the README)
+    if std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("wasm32") {
+        return;
+    }
+
     // C++ graph input bindings
     cxx_build::bridge("src/input.rs")
         .flag_if_supported("-std=c++20")
@@ -2,6 +2,29 @@ from __future__ import print_function
 from ortools.linear_solver import pywraplp
 import json
 import argparse
+import sys
+
+
+# Exit codes, also written (by name) to the error field of ./tmp/solved.json
+ERROR_CODES = {
+    'internal': 1,
+    'solver_unavailable': 2,
+    'infeasible': 3,
+    'no_solution': 4,
+    'invalid_data': 5,
+}
+
+SOLVERS = {
+    'cbc': 'CBC',
+    'gurobi': 'GUROBI',
+    'scip': 'SCIP',
+}
+
+
+class ExtractionError(Exception):
+    def __init__(self, code, message):
+        super().__init__(message)
+        self.code = code
 
 
 def get_args():
@@ -16,10 +39,18 @@ def get_args():
         help='No ordering constraints')
     parser.add_argument('--num_thread', type=int, default=1, metavar='N',
         help='Number of thread for the solver (default: 1)')
+    parser.add_argument('--solver', choices=sorted(SOLVERS), default='scip',
+        help='MIP solver (default: scip)')
+    parser.add_argument('--mip_gap', type=float, default=None, metavar='GAP',
+        help='Stop at this relative gap to the optimum (default: the solver default)')
     parser.add_argument('--print_solution', action='store_true', default=False,
         help='To print out solution')
     parser.add_argument('--initialize', action='store_true', default=False,
         help='initialize with greedy solution')
+    parser.add_argument('--remat', action='store_true', default=False,
+        help='Let consumers pick which node of an eclass computes it, with a memory term (needs mem_i and remat_weight)')
+    parser.add_argument('--sharing', action='store_true', default=False,
+        help='Reward tensors with several consumers (needs tensor_m and sharing_bonus)')
 
     return parser.parse_args()
 
@@ -33,15 +64,17 @@ def main():
     # - e: e[m] is the set of nodes within eclass m
     # - h: h[i] is the set of children eclasses for node i
     # - g: g(i) gives the eclass of node i
-    with open('./tmp/ilp_data.json') as f:
-        data = json.load(f)
-
-    costs = data['cost_i']
-    e = data['e_m']
-    h = data['h_i']
-    g = data['g_i']
-    root_m = data['root_m']
-    blacklist_i = data['blacklist_i']
+    try:
+        with open('./tmp/ilp_data.json') as f:
+            data = json.load(f)
+        costs = data['cost_i']
+        e = data['e_m']
+        h = data['h_i']
+        g = data['g_i']
+        root_m = data['root_m']
+        blacklist_i = data['blacklist_i']
+    except (OSError, ValueError, KeyError) as err:
+        raise ExtractionError('invalid_data', 'Unable to load ./tmp/ilp_data.json: {}'.format(err))
     num_nodes = len(costs)
     num_classes = len(e)
 
@@ -52,7 +85,10 @@ def main():
         A = 2
 
     # Create solver
-    solver = pywraplp.Solver('simple_mip_program', pywraplp.Solver.SCIP_MIXED_INTEGER_PROGRAMMING)
+    solver = pywraplp.Solver.CreateSolver(SOLVERS[args.solver])
+    if solver is None:
+        raise ExtractionError('solver_unavailable',
+                              'Solver {} is not available in this build of OR-Tools'.format(args.solver))
     if args.num_thread != 1:
         print("Set number of threads to {}".format(args.num_thread))
         solver.SetNumThreads(args.num_thread)
@@ -84,7 +120,7 @@ def main():
     # Root
     solver.Add(sum([x[j] for j in e[root_m]]) == 1)
 
-    if args.eclass_constraint:
+    if args.eclass_constraint or args.sharing:
         # eclass_constraints are optional because in most cases, the solution that minimizes
         # the total cost will only contain 1 picked node for each picked eclass, so we don't 
         # have to explicity include this.
@@ -110,8 +146,64 @@ def main():
     for j in blacklist_i:
         solver.Add(x[j] == 0)
 
+    # Rematerialization
+    # - z: z[i, m, j] = 1 means node i takes its child eclass m from node j. Several nodes of
+    #      an eclass can be picked, each recomputing the eclass for some of its consumers.
+    # Every consumer of a node beyond the first keeps its result alive for longer, which
+    # costs remat_weight per byte of the result. Picking another node of the eclass for a
+    # consumer trades that memory for the cost of recomputing it.
+    z = {}
+    if args.remat:
+        print("Add rematerialization variables")
+        mem = data['mem_i']
+        remat_weight = data['remat_weight']
+        consumers = {j: [] for j in range(num_nodes)}
+        for i in range(num_nodes):
+            for m in set(h[i]):
+                for j in e[m]:
+                    z[i, m, j] = solver.IntVar(0, 1, 'z[%i,%i,%i]' % (i, m, j))
+                    solver.Add(x[j] - z[i, m, j] >= 0)
+                    consumers[j].append(z[i, m, j])
+                solver.Add(sum([z[i, m, j] for j in e[m]]) - x[i] >= 0)
+        for j in range(num_nodes):
+            # A picked node must serve a consumer, unless it is the root
+            if g[j] != root_m:
+                solver.Add(sum(consumers[j]) - x[j] >= 0)
+        # Random values are drawn once: recomputing them would give their consumers
+        # different draws
+        for m in data['single_m']:
+            solver.Add(sum([x[j] for j in e[m]]) <= 1)
+
+    # Sharing
+    # Every consumer of a tensor eclass beyond the first earns sharing_bonus, so forms reusing
+    # a subgraph beat cheaper forms duplicating it. With at most one node per eclass, the
+    # consumers of eclass m are the picked nodes having m as a child. A picked eclass must
+    # have a consumer (unless it is the root), so the bonus can't be collected by picking
+    # nodes the root doesn't need.
+    uses = {}
+    if args.sharing:
+        print("Add sharing bonuses")
+        tensor_m = data['tensor_m']
+        sharing_bonus = data['sharing_bonus']
+        parents = {m: [] for m in range(num_classes)}
+        for i in range(num_nodes):
+            for m in set(h[i]):
+                parents[m].append(i)
+        for m in range(num_classes):
+            if m != root_m:
+                solver.Add(sum([x[i] for i in parents[m]]) - sum([x[j] for j in e[m]]) >= 0)
+        for m in tensor_m:
+            uses[m] = sum([x[i] for i in parents[m]]) - sum([x[j] for j in e[m]])
+
     # Define objective
     obj_expr = [costs[j] * x[j] for j in range(num_nodes)]
+    if args.remat:
+        for j in range(num_nodes):
+            if g[j] != root_m and mem[j] > 0:
+                obj_expr.append(remat_weight * mem[j] * (sum(consumers[j]) - x[j]))
+    if args.sharing:
+        for m in tensor_m:
+            obj_expr.append(-sharing_bonus * uses[m])
     solver.Minimize(sum(obj_expr))
 
     # Set initial solutions
@@ -143,7 +235,11 @@ def main():
         solver.SetHint(i_var_list + t_var_list, i_init_val_list + t_init_val_list)
 
     # Solve
-    status = solver.Solve()
+    params = pywraplp.MPSolverParameters()
+    if args.mip_gap is not None:
+        print("Set relative MIP gap to {}".format(args.mip_gap))
+        params.SetDoubleParam(params.RELATIVE_MIP_GAP, args.mip_gap)
+    status = solver.Solve(params)
     solve_time = solver.wall_time()
     if status == pywraplp.Solver.OPTIMAL:
         print('Objective value =', solver.Objective().Value())
@@ -157,6 +253,12 @@ def main():
             for j in range(num_classes):
                 print(t[j].name(), ' = ', t[j].solution_value())
                 
+    elif status == pywraplp.Solver.INFEASIBLE:
+        raise ExtractionError('infeasible', 'The extraction ILP is infeasible')
+    elif status != pywraplp.Solver.FEASIBLE:
+        raise ExtractionError('no_solution',
+                              'The solver found no solution (status {}) in {} seconds'.format(
+                                  status, args.time_lim_sec))
     else:
         print('The problem does not have an optimal solution.')
         print(status)
@@ -169,16 +271,26 @@ def main():
     solved_x = [int(x[j].solution_value()) for j in range(num_nodes)]
     result_dict = {}
     result_dict["solved_x"] = solved_x
+    if args.remat:
+        result_dict["solved_z"] = [[i, m, j] for (i, m, j), var in z.items()
+                                   if int(round(var.solution_value())) == 1]
     result_dict["cost"] = solver.Objective().Value()
     result_dict["time"] = solve_time / 1000
     with open('./tmp/solved.json', 'w') as f:
         json.dump(result_dict, f)
 
 
+def write_error(code, message):
+    print('ERROR ({}): {}'.format(code, message))
+    with open('./tmp/solved.json', 'w') as f:
+        json.dump({"error": code, "message": message}, f)
+    sys.exit(ERROR_CODES[code])
+
+
 if __name__ == '__main__':
     try:
         main()
+    except ExtractionError as err:
+        write_error(err.code, str(err))
     except Exception as err:
-        print(err)
-        import pdb
-        pdb.post_mortem()
\ No newline at end of file
+        write_error('internal', repr(err))
\ No newline at end of file
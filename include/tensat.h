@@ -1,5 +1,5 @@
 namespace tensat {
   struct CppGraphConverter;
-  struct TensorInfo;
+  struct TensorHandle;
   struct Mdl;
 }
@@ -0,0 +1,235 @@
+#ifndef TENSAT_C_H
+#define TENSAT_C_H
+
+/* Generated with cbindgen from src/c_api.rs, do not edit */
+
+#include <stdbool.h>
+#include <stdint.h>
+#include <stddef.h>
+
+typedef struct TensatConverter TensatConverter;
+
+/**
+ * Graph returned by `tensat_optimize`
+ */
+typedef struct TensatGraph TensatGraph;
+
+/**
+ * Handle of a tensor of a converter, see ffi::TensorHandle
+ */
+typedef struct TensatTensor {
+  /**
+   * Index of the tensor in the graph
+   */
+  int32_t id;
+} TensatTensor;
+
+/**
+ * Called with the metric name, whether it is a histogram (a counter
+ * otherwise), the value and the `user_data` given to
+ * `tensat_set_metrics_callback`
+ */
+typedef void (*TensatMetricsCallback)(const char *name,
+                                      bool is_histogram,
+                                      double value,
+                                      void *user_data);
+
+TensatConverter *tensat_converter_new(void);
+
+void tensat_converter_free(TensatConverter *converter);
+
+void tensat_set_rule_file(TensatConverter *converter, const char *path);
+
+void tensat_set_limits(TensatConverter *converter,
+                       uint64_t n_sec,
+                       uintptr_t iter_limit,
+                       uintptr_t node_limit);
+
+/**
+ * Load the settings of optimize from a TOML file, see
+ * OptimizeConfig::from_toml. Returns false (and prints why) if the file can't
+ * be loaded, leaving the settings unchanged
+ */
+bool tensat_load_config_toml(TensatConverter *converter, const char *path);
+
+/**
+ * `dtype` is an ffi::Type discriminant: 0 for i32, 1 for f32, 2 for i1, 3 for
+ * bf16 and 4 for c64
+ */
+TensatTensor tensat_new_input(TensatConverter *converter,
+                              int32_t block_arg_number,
+                              const int32_t *dims,
+                              uintptr_t n_dims,
+                              int32_t dtype);
+
+/**
+ * Like tensat_new_input, for an input holding a weight (known at compile time)
+ */
+TensatTensor tensat_new_weight(TensatConverter *converter,
+                               int32_t block_arg_number,
+                               const int32_t *dims,
+                               uintptr_t n_dims,
+                               int32_t dtype);
+
+TensatTensor tensat_new_add_op(TensatConverter *converter,
+                               TensatTensor lhs,
+                               TensatTensor rhs,
+                               const int32_t *dims,
+                               uintptr_t n_dims);
+
+TensatTensor tensat_new_subtract_op(TensatConverter *converter,
+                                    TensatTensor lhs,
+                                    TensatTensor rhs,
+                                    const int32_t *dims,
+                                    uintptr_t n_dims);
+
+TensatTensor tensat_new_mul_op(TensatConverter *converter,
+                               TensatTensor lhs,
+                               TensatTensor rhs,
+                               const int32_t *dims,
+                               uintptr_t n_dims);
+
+TensatTensor tensat_new_div_op(TensatConverter *converter,
+                               TensatTensor lhs,
+                               TensatTensor rhs,
+                               const int32_t *dims,
+                               uintptr_t n_dims);
+
+TensatTensor tensat_new_min_op(TensatConverter *converter,
+                               TensatTensor lhs,
+                               TensatTensor rhs,
+                               const int32_t *dims,
+                               uintptr_t n_dims);
+
+TensatTensor tensat_new_max_op(TensatConverter *converter,
+                               TensatTensor lhs,
+                               TensatTensor rhs,
+                               const int32_t *dims,
+                               uintptr_t n_dims);
+
+TensatTensor tensat_new_neg_op(TensatConverter *converter,
+                               TensatTensor inpt,
+                               const int32_t *dims,
+                               uintptr_t n_dims);
+
+TensatTensor tensat_new_tanh_op(TensatConverter *converter,
+                                TensatTensor inpt,
+                                const int32_t *dims,
+                                uintptr_t n_dims);
+
+TensatTensor tensat_new_exp_op(TensatConverter *converter,
+                               TensatTensor inpt,
+                               const int32_t *dims,
+                               uintptr_t n_dims);
+
+TensatTensor tensat_new_erf_op(TensatConverter *converter,
+                               TensatTensor inpt,
+                               const int32_t *dims,
+                               uintptr_t n_dims);
+
+TensatTensor tensat_new_reshape_op(TensatConverter *converter,
+                                   TensatTensor inpt,
+                                   const int32_t *dims,
+                                   uintptr_t n_dims);
+
+TensatTensor tensat_new_transpose_op(TensatConverter *converter,
+                                     TensatTensor inpt,
+                                     const int64_t *permutation,
+                                     uintptr_t n_permutation,
+                                     const int32_t *dims,
+                                     uintptr_t n_dims);
+
+TensatTensor tensat_new_broadcast_in_dim(TensatConverter *converter,
+                                         TensatTensor inpt,
+                                         const int64_t *dimensions,
+                                         uintptr_t n_dimensions,
+                                         const int32_t *dims,
+                                         uintptr_t n_dims);
+
+/**
+ * DotGeneralOp. The four dimension lists (lhs batching, rhs batching, lhs
+ * contracting, rhs contracting) are passed as `dimension_lists` and their
+ * lengths as `n_dimension_lists`
+ */
+TensatTensor tensat_new_dot_general_op(TensatConverter *converter,
+                                       TensatTensor lhs,
+                                       TensatTensor rhs,
+                                       const int64_t *const *dimension_lists,
+                                       const uintptr_t *n_dimension_lists,
+                                       const int32_t *dims,
+                                       uintptr_t n_dims);
+
+TensatTensor tensat_new_slice_op(TensatConverter *converter,
+                                 TensatTensor inpt,
+                                 const int64_t *start_indices,
+                                 const int64_t *limit_indices,
+                                 const int64_t *strides,
+                                 const int32_t *dims,
+                                 uintptr_t n_dims);
+
+TensatTensor tensat_new_concatenate_op(TensatConverter *converter,
+                                       const TensatTensor *inputs,
+                                       uintptr_t n_inputs,
+                                       int32_t dimension,
+                                       const int32_t *dims,
+                                       uintptr_t n_dims);
+
+TensatTensor tensat_new_return_op(TensatConverter *converter,
+                                  const TensatTensor *outputs,
+                                  uintptr_t n_outputs);
+
+/**
+ * Optimize the graph built so far
+ */
+TensatGraph *tensat_optimize(const TensatConverter *converter);
+
+void tensat_graph_free(TensatGraph *graph);
+
+uintptr_t tensat_graph_num_nodes(const TensatGraph *graph);
+
+int32_t tensat_graph_root(const TensatGraph *graph);
+
+/**
+ * Name of node `i`, owned by the graph
+ */
+const char *tensat_graph_node_name(const TensatGraph *graph, uintptr_t i);
+
+/**
+ * Label of node `i`, owned by the graph
+ */
+const char *tensat_graph_node_label(const TensatGraph *graph, uintptr_t i);
+
+/**
+ * Operands of node `i`, owned by the graph. Their number is written to
+ * `n_operands`
+ */
+const int32_t *tensat_graph_node_operands(const TensatGraph *graph,
+                                          uintptr_t i,
+                                          uintptr_t *n_operands);
+
+/**
+ * Value of node `i` if it is a Num, 0 otherwise
+ */
+int64_t tensat_graph_node_value(const TensatGraph *graph, uintptr_t i);
+
+/**
+ * The graph as text, see graph_to_string. Free with `tensat_string_free`
+ */
+char *tensat_graph_to_string(const TensatGraph *graph);
+
+void tensat_string_free(char *s);
+
+/**
+ * Metrics of the optimize calls so far in the Prometheus text format, see
+ * metrics::prometheus_text. Free with `tensat_string_free`
+ */
+char *tensat_metrics_prometheus_text(void);
+
+/**
+ * Forward metric updates to `callback`, or stop forwarding them if it is null.
+ * `user_data` must stay valid (and be usable from any thread) until the
+ * callback is replaced
+ */
+void tensat_set_metrics_callback(TensatMetricsCallback callback, void *user_data);
+
+#endif /* TENSAT_C_H */
@@ -1 +1 @@
-g++ -std=c++20 -o tensatcpp src/graph.cc -I target/cxxbridge -I target/debug/build/tensat/out -L target/debug -ltensat -lc++ -lc++abi && ./tensatcpp
+cargo build --features no-cxx-costmodel && g++ -std=c++20 -o tensatcpp src/graph.cc -I target/cxxbridge -I target/debug/build/tensat/out -L target/debug -ltensat -lc++ -lc++abi && ./tensatcpp
@@ -0,0 +1,118 @@
+//! Splitting one time budget between saturation and extraction
+//!
+//! With `time_budget_sec` set, optimize doesn't use the fixed `n_sec` and
+//! `ilp_time_sec` limits. Saturation may use up to MAX_SATURATION_SHARE of the
+//! budget, but stops as soon as the e-graph stabilizes or the next iteration
+//! is predicted to overrun its share. Extraction gets whatever is left, so
+//! small graphs that saturate quickly leave most of the budget to the solver,
+//! and huge graphs don't starve it.
+//!
+//! The budget also covers the regions of IfOps and WhileOps, which are
+//! optimized first: each region gets an equal share of what is left for
+//! itself, the regions after it and the enclosing graph (see region_share),
+//! and the enclosing graph gets the rest.
+
+use crate::growth::predict_next_size;
+use crate::model::*;
+use egg::*;
+use std::time::{Duration, Instant};
+
+/// Largest share of the budget saturation may use
+pub const MAX_SATURATION_SHARE: f64 = 0.5;
+
+/// Saturation stops once an iteration grows the e-graph by less than this
+/// fraction of its size
+pub const STABLE_GROWTH: f64 = 0.01;
+
+/// Time budget of an optimize call, started when it is created
+#[derive(Clone, Copy, Debug)]
+pub struct TimeBudget {
+    start: Instant,
+    total: Duration,
+}
+
+impl TimeBudget {
+    pub fn new(total: Duration) -> Self {
+        TimeBudget {
+            start: Instant::now(),
+            total,
+        }
+    }
+
+    /// Time limit of saturation, from the start of the budget
+    pub fn saturation_limit(&self) -> Duration {
+        self.total.mul_f64(MAX_SATURATION_SHARE)
+    }
+
+    /// Time left of the budget
+    pub fn remaining(&self) -> Duration {
+        self.total.saturating_sub(self.start.elapsed())
+    }
+
+    /// Time left for extraction, in whole seconds and at least 1
+    pub fn extraction_secs(&self) -> u64 {
+        self.remaining().as_secs().max(1)
+    }
+
+    /// Runner hook stopping saturation when `stop_saturation` says so
+    pub fn saturation_hook(
+        &self,
+    ) -> impl FnMut(&mut Runner<Mdl, TensorAnalysis, ()>) -> Result<(), String> {
+        let deadline = self.start + self.saturation_limit();
+        let mut sizes = Vec::new();
+        move |runner| {
+            sizes.push(runner.egraph.total_size());
+            let iteration_secs: Vec<f64> = runner.iterations.iter().map(|i| i.total_time).collect();
+            let secs_left = deadline
+                .saturating_duration_since(Instant::now())
+                .as_secs_f64();
+            match stop_saturation(&sizes, &iteration_secs, secs_left) {
+                Some(reason) => Err(reason),
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+/// Budget in whole seconds of the next region to optimize, with `remaining`
+/// left and `regions_left` regions (this one included) then the enclosing
+/// graph still to optimize. At least 1
+pub fn region_share(remaining: Duration, regions_left: usize) -> u64 {
+    (remaining.as_secs() / (regions_left as u64 + 1)).max(1)
+}
+
+/// Whether saturation should stop before the next iteration, and why
+///
+/// # Parameters
+///
+/// - `sizes`: the e-graph size before each iteration so far, and now
+/// - `iteration_secs`: the time each iteration so far took
+/// - `secs_left`: the time left for saturation
+///
+/// Saturation stops if the last iteration grew the e-graph by less than
+/// STABLE_GROWTH, or if the next iteration is predicted to take longer than
+/// `secs_left`. The time of an iteration is assumed proportional to the size
+/// of the e-graph, with the size predicted by `predict_next_size`.
+pub fn stop_saturation(sizes: &[usize], iteration_secs: &[f64], secs_left: f64) -> Option<String> {
+    let (n, last_secs) = match (sizes.len(), iteration_secs.last()) {
+        (n, Some(last_secs)) if n >= 2 => (n, *last_secs),
+        _ => return None,
+    };
+    let (before, now) = (sizes[n - 2], sizes[n - 1]);
+    let growth = now.saturating_sub(before) as f64 / before.max(1) as f64;
+    if growth < STABLE_GROWTH {
+        return Some(format!(
+            "E-graph stabilized: the last iteration grew it by {:.2}%",
+            growth * 100.0
+        ));
+    }
+    let predicted = predict_next_size(sizes)?;
+    let predicted_secs = last_secs * predicted as f64 / now.max(1) as f64;
+    if predicted_secs > secs_left {
+        return Some(format!(
+            "Time budget: the next iteration is predicted to take {:.1}s, {:.1}s are left for saturation",
+            predicted_secs, secs_left
+        ));
+    }
+    None
+}
@@ -0,0 +1,486 @@
+//! Plain C API, built with the `c-api` feature
+//!
+//! Mirrors the converter functions of the cxx bridge with C types only, so
+//! hosts other than C++ (C, Julia, Go, ...) can embed tensat. Converters,
+//! graphs and strings are handed out as owning pointers and must be released
+//! with the matching `tensat_*_free` function. Tensors are handles, passed by
+//! value, and stay valid as long as the converter that made them. The header is
+//! generated with `cbindgen --config cbindgen.toml --output include/tensat_c.h`.
+//!
+//! Shapes and attribute lists are passed as a pointer and a length. A null
+//! pointer is only allowed together with a zero length.
+
+// Every function takes pointers handed out by this module, as described above
+#![allow(clippy::missing_safety_doc)]
+
+use crate::input::{ffi, graph_to_string, CppGraphConverter};
+use crate::metrics::{self, MetricKind};
+use crate::model::dtype_from_num;
+use std::ffi::{c_void, CStr, CString};
+use std::os::raw::c_char;
+use std::slice;
+
+/// Graph returned by `tensat_optimize`
+pub struct TensatGraph {
+    graph: ffi::Graph,
+    /// Names and labels of the nodes, kept alive for the accessors
+    names: Vec<CString>,
+    labels: Vec<CString>,
+}
+
+unsafe fn ints<'a, T>(ptr: *const T, len: usize) -> &'a [T] {
+    if len == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(ptr, len)
+    }
+}
+
+unsafe fn str_arg<'a>(ptr: *const c_char) -> &'a str {
+    CStr::from_ptr(ptr)
+        .to_str()
+        .expect("Argument is not valid UTF-8")
+}
+
+/// Handle of a tensor of a converter, see ffi::TensorHandle
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TensatTensor {
+    /// Index of the tensor in the graph
+    pub id: i32,
+}
+
+impl From<ffi::TensorHandle> for TensatTensor {
+    fn from(handle: ffi::TensorHandle) -> Self {
+        TensatTensor { id: handle.id }
+    }
+}
+
+impl From<TensatTensor> for ffi::TensorHandle {
+    fn from(tensor: TensatTensor) -> Self {
+        ffi::TensorHandle { id: tensor.id }
+    }
+}
+
+fn tensor(handle: ffi::TensorHandle) -> TensatTensor {
+    handle.into()
+}
+
+unsafe fn handles(tensors: *const TensatTensor, len: usize) -> Vec<ffi::TensorHandle> {
+    ints(tensors, len).iter().map(|t| (*t).into()).collect()
+}
+
+#[no_mangle]
+pub extern "C" fn tensat_converter_new() -> *mut CppGraphConverter {
+    Box::into_raw(Box::new(CppGraphConverter::default()))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_converter_free(converter: *mut CppGraphConverter) {
+    if !converter.is_null() {
+        drop(Box::from_raw(converter));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_set_rule_file(
+    converter: *mut CppGraphConverter,
+    path: *const c_char,
+) {
+    (*converter).config_mut().rule_file = str_arg(path).to_string();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_set_limits(
+    converter: *mut CppGraphConverter,
+    n_sec: u64,
+    iter_limit: usize,
+    node_limit: usize,
+) {
+    let config = (*converter).config_mut();
+    config.n_sec = n_sec;
+    config.iter_limit = iter_limit;
+    config.node_limit = node_limit;
+}
+
+/// Load the settings of optimize from a TOML file, see
+/// OptimizeConfig::from_toml. Returns false (and prints why) if the file can't
+/// be loaded, leaving the settings unchanged
+#[no_mangle]
+pub unsafe extern "C" fn tensat_load_config_toml(
+    converter: *mut CppGraphConverter,
+    path: *const c_char,
+) -> bool {
+    match (*converter).load_config_toml(str_arg(path)) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("{}", e);
+            false
+        }
+    }
+}
+
+/// `dtype` is an ffi::Type discriminant: 0 for i32, 1 for f32, 2 for i1, 3 for
+/// bf16 and 4 for c64
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_input(
+    converter: *mut CppGraphConverter,
+    block_arg_number: i32,
+    dims: *const i32,
+    n_dims: usize,
+    dtype: i32,
+) -> TensatTensor {
+    let dtype = dtype_from_num(dtype.into());
+    tensor((*converter).new_input(block_arg_number, ints(dims, n_dims), dtype))
+}
+
+/// Like tensat_new_input, for an input holding a weight (known at compile time)
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_weight(
+    converter: *mut CppGraphConverter,
+    block_arg_number: i32,
+    dims: *const i32,
+    n_dims: usize,
+    dtype: i32,
+) -> TensatTensor {
+    let dtype = dtype_from_num(dtype.into());
+    tensor((*converter).new_weight(block_arg_number, ints(dims, n_dims), dtype))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_add_op(
+    converter: *mut CppGraphConverter,
+    lhs: TensatTensor,
+    rhs: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_add_op(lhs.into(), rhs.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_subtract_op(
+    converter: *mut CppGraphConverter,
+    lhs: TensatTensor,
+    rhs: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_subtract_op(lhs.into(), rhs.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_mul_op(
+    converter: *mut CppGraphConverter,
+    lhs: TensatTensor,
+    rhs: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_mul_op(lhs.into(), rhs.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_div_op(
+    converter: *mut CppGraphConverter,
+    lhs: TensatTensor,
+    rhs: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_div_op(lhs.into(), rhs.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_min_op(
+    converter: *mut CppGraphConverter,
+    lhs: TensatTensor,
+    rhs: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_min_op(lhs.into(), rhs.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_max_op(
+    converter: *mut CppGraphConverter,
+    lhs: TensatTensor,
+    rhs: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_max_op(lhs.into(), rhs.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_neg_op(
+    converter: *mut CppGraphConverter,
+    inpt: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_neg_op(inpt.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_tanh_op(
+    converter: *mut CppGraphConverter,
+    inpt: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_tanh_op(inpt.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_exp_op(
+    converter: *mut CppGraphConverter,
+    inpt: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_exp_op(inpt.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_erf_op(
+    converter: *mut CppGraphConverter,
+    inpt: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_erf_op(inpt.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_reshape_op(
+    converter: *mut CppGraphConverter,
+    inpt: TensatTensor,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_reshape_op(inpt.into(), ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_transpose_op(
+    converter: *mut CppGraphConverter,
+    inpt: TensatTensor,
+    permutation: *const i64,
+    n_permutation: usize,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_transpose_op(
+        inpt.into(),
+        ints(permutation, n_permutation),
+        ints(dims, n_dims),
+    ))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_broadcast_in_dim(
+    converter: *mut CppGraphConverter,
+    inpt: TensatTensor,
+    dimensions: *const i64,
+    n_dimensions: usize,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_broadcast_in_dim(
+        inpt.into(),
+        ints(dimensions, n_dimensions),
+        ints(dims, n_dims),
+    ))
+}
+
+/// DotGeneralOp. The four dimension lists (lhs batching, rhs batching, lhs
+/// contracting, rhs contracting) are passed as `dimension_lists` and their
+/// lengths as `n_dimension_lists`
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_dot_general_op(
+    converter: *mut CppGraphConverter,
+    lhs: TensatTensor,
+    rhs: TensatTensor,
+    dimension_lists: *const *const i64,
+    n_dimension_lists: *const usize,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    let lists = slice::from_raw_parts(dimension_lists, 4);
+    let lens = slice::from_raw_parts(n_dimension_lists, 4);
+    tensor((*converter).new_dot_general_op(
+        lhs.into(),
+        rhs.into(),
+        ints(lists[0], lens[0]),
+        ints(lists[1], lens[1]),
+        ints(lists[2], lens[2]),
+        ints(lists[3], lens[3]),
+        &[],
+        ints(dims, n_dims),
+    ))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_slice_op(
+    converter: *mut CppGraphConverter,
+    inpt: TensatTensor,
+    start_indices: *const i64,
+    limit_indices: *const i64,
+    strides: *const i64,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    tensor((*converter).new_slice_op(
+        inpt.into(),
+        ints(start_indices, n_dims),
+        ints(limit_indices, n_dims),
+        ints(strides, n_dims),
+        ints(dims, n_dims),
+    ))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_concatenate_op(
+    converter: *mut CppGraphConverter,
+    inputs: *const TensatTensor,
+    n_inputs: usize,
+    dimension: i32,
+    dims: *const i32,
+    n_dims: usize,
+) -> TensatTensor {
+    let inputs = handles(inputs, n_inputs);
+    tensor((*converter).new_concatenate_op(&inputs, dimension, ints(dims, n_dims)))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_new_return_op(
+    converter: *mut CppGraphConverter,
+    outputs: *const TensatTensor,
+    n_outputs: usize,
+) -> TensatTensor {
+    let outputs = handles(outputs, n_outputs);
+    tensor((*converter).new_return_op(&outputs))
+}
+
+/// Optimize the graph built so far
+#[no_mangle]
+pub unsafe extern "C" fn tensat_optimize(converter: *const CppGraphConverter) -> *mut TensatGraph {
+    let graph = (*converter).optimize();
+    let c_string = |s: &str| CString::new(s).expect("Node string contains a nul byte");
+    Box::into_raw(Box::new(TensatGraph {
+        names: graph.nodes.iter().map(|n| c_string(&n.name)).collect(),
+        labels: graph.nodes.iter().map(|n| c_string(&n.label)).collect(),
+        graph,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_graph_free(graph: *mut TensatGraph) {
+    if !graph.is_null() {
+        drop(Box::from_raw(graph));
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_graph_num_nodes(graph: *const TensatGraph) -> usize {
+    (*graph).graph.nodes.len()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_graph_root(graph: *const TensatGraph) -> i32 {
+    (*graph).graph.root
+}
+
+/// Name of node `i`, owned by the graph
+#[no_mangle]
+pub unsafe extern "C" fn tensat_graph_node_name(
+    graph: *const TensatGraph,
+    i: usize,
+) -> *const c_char {
+    (*graph).names[i].as_ptr()
+}
+
+/// Label of node `i`, owned by the graph
+#[no_mangle]
+pub unsafe extern "C" fn tensat_graph_node_label(
+    graph: *const TensatGraph,
+    i: usize,
+) -> *const c_char {
+    (*graph).labels[i].as_ptr()
+}
+
+/// Operands of node `i`, owned by the graph. Their number is written to
+/// `n_operands`
+#[no_mangle]
+pub unsafe extern "C" fn tensat_graph_node_operands(
+    graph: *const TensatGraph,
+    i: usize,
+    n_operands: *mut usize,
+) -> *const i32 {
+    let operands = &(*graph).graph.nodes[i].operands;
+    *n_operands = operands.len();
+    operands.as_ptr()
+}
+
+/// Value of node `i` if it is a Num, 0 otherwise
+#[no_mangle]
+pub unsafe extern "C" fn tensat_graph_node_value(graph: *const TensatGraph, i: usize) -> i64 {
+    (*graph).graph.nodes[i].value
+}
+
+/// The graph as text, see graph_to_string. Free with `tensat_string_free`
+#[no_mangle]
+pub unsafe extern "C" fn tensat_graph_to_string(graph: *const TensatGraph) -> *mut c_char {
+    CString::new(graph_to_string(&(*graph).graph))
+        .expect("Graph string contains a nul byte")
+        .into_raw()
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tensat_string_free(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// Metrics of the optimize calls so far in the Prometheus text format, see
+/// metrics::prometheus_text. Free with `tensat_string_free`
+#[no_mangle]
+pub extern "C" fn tensat_metrics_prometheus_text() -> *mut c_char {
+    CString::new(metrics::prometheus_text())
+        .expect("Metrics contain a nul byte")
+        .into_raw()
+}
+
+/// Called with the metric name, whether it is a histogram (a counter
+/// otherwise), the value and the `user_data` given to
+/// `tensat_set_metrics_callback`
+pub type TensatMetricsCallback =
+    extern "C" fn(name: *const c_char, is_histogram: bool, value: f64, user_data: *mut c_void);
+
+/// Forward metric updates to `callback`, or stop forwarding them if it is null.
+/// `user_data` must stay valid (and be usable from any thread) until the
+/// callback is replaced
+#[no_mangle]
+pub unsafe extern "C" fn tensat_set_metrics_callback(
+    callback: Option<TensatMetricsCallback>,
+    user_data: *mut c_void,
+) {
+    let callback = match callback {
+        Some(callback) => callback,
+        None => return metrics::clear_metrics_callback(),
+    };
+    // Raw pointers are not Send, pass the address instead
+    let user_data = user_data as usize;
+    metrics::set_metrics_callback(Box::new(move |name, kind, value| {
+        let name = CString::new(name).expect("Metric name contains a nul byte");
+        callback(
+            name.as_ptr(),
+            kind == MetricKind::Histogram,
+            value,
+            user_data as *mut c_void,
+        );
+    }));
+}
@@ -0,0 +1,79 @@
+//! Checkpoints of saturation
+//!
+//! Saturating a large graph can take longer than a single compiler invocation
+//! can afford. With `save_state` set, optimize writes the saturated e-graph
+//! (as a `SerializedEGraph`) and the number of iterations it took to a file.
+//! With `resume_from` set, a later optimize of the same graph starts saturation
+//! from that e-graph instead of from the graph, with the rules and limits of
+//! its own config: the exploration can be continued for more iterations, or
+//! with a rule file adding rules, in another process or on another machine.
+//!
+//! The analysis data (shapes, ...) is not saved but recomputed as the e-graph
+//! is rebuilt, and the limits apply to the resumed run only. Blacklisted enodes
+//! are left out of the checkpoint, see SerializedEGraph::new.
+
+use crate::merge::{merge_egraphs, MergeMode, SerializedEGraph};
+use crate::model::*;
+use egg::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{read_to_string, write};
+use std::slice;
+
+/// Saturated e-graph, saved to be resumed from
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SaturationState {
+    pub egraph: SerializedEGraph,
+    /// Number of iterations run to get the e-graph, over all resumed runs
+    pub iterations: usize,
+}
+
+impl SaturationState {
+    pub fn new(egraph: &EGraph<Mdl, TensorAnalysis>, root: Id, iterations: usize) -> Self {
+        SaturationState {
+            egraph: SerializedEGraph::new(egraph, root),
+            iterations,
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Fail to convert saturation state to json")
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let state: SaturationState =
+            serde_json::from_str(text).map_err(|e| format!("Invalid saturation state: {}", e))?;
+        state.egraph.validate()?;
+        Ok(state)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        write(path, self.to_json()).map_err(|e| format!("Unable to write {}: {}", path, e))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        Self::from_json(&text)
+    }
+
+    /// Rebuild the e-graph with `analysis`
+    ///
+    /// # Returns
+    ///
+    /// The e-graph and its root. Fails if the e-graph wasn't saturated from
+    /// `start`, i.e. `start` isn't in the eclass of the root.
+    pub fn restore(
+        &self,
+        start: &RecExpr<Mdl>,
+        analysis: TensorAnalysis,
+    ) -> Result<(EGraph<Mdl, TensorAnalysis>, Id), String> {
+        let (mut egraph, root) =
+            merge_egraphs(slice::from_ref(&self.egraph), MergeMode::Union, analysis)?;
+        let start_root = egraph.add_expr(start);
+        egraph.rebuild();
+        let root = egraph.find(root);
+        if egraph.find(start_root) != root {
+            return Err("The state was saved from a different graph".to_string());
+        }
+        Ok((egraph, root))
+    }
+}
@@ -0,0 +1,108 @@
+//! Attributes of the collective ops
+//!
+//! AllReduceOp, AllGatherOp, ReduceScatterOp and AllToAllOp communicate
+//! within groups of replicas. The replica_groups of stablehlo are kept as a
+//! Vec of the replica ids of every group in turn, all groups having
+//! group_size replicas. AllReduceOp and ReduceScatterOp combine the values of
+//! the group with a Reduction, instead of a region.
+
+/// How AllReduceOp and ReduceScatterOp combine the values of a group
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reduction {
+    Add = 0,
+    Mul = 1,
+    Min = 2,
+    Max = 3,
+}
+
+impl Reduction {
+    pub fn from_num(num: i64) -> Result<Self, String> {
+        match num {
+            0 => Ok(Reduction::Add),
+            1 => Ok(Reduction::Mul),
+            2 => Ok(Reduction::Min),
+            3 => Ok(Reduction::Max),
+            _ => Err(format!("unknown reduction {}", num)),
+        }
+    }
+}
+
+/// The groups of the flattened `replica_groups`
+///
+/// Fails unless they split into groups of `group_size` replicas, each replica
+/// in at most one group.
+pub fn replica_groups(replica_groups: &[i64], group_size: i64) -> Result<Vec<Vec<i64>>, String> {
+    if group_size <= 0 || replica_groups.len() as i64 % group_size != 0 {
+        return Err(format!(
+            "{} replicas in groups of {}",
+            replica_groups.len(),
+            group_size
+        ));
+    }
+    let mut seen = replica_groups.to_vec();
+    seen.sort_unstable();
+    seen.dedup();
+    if seen.len() != replica_groups.len() || seen.first().map_or(false, |id| *id < 0) {
+        return Err(format!("invalid replica ids {:?}", replica_groups));
+    }
+    Ok(replica_groups
+        .chunks(group_size as usize)
+        .map(|group| group.to_vec())
+        .collect())
+}
+
+fn check_dimension(dims: &[i64], dimension: i64) -> Result<usize, String> {
+    if dimension < 0 || dimension as usize >= dims.len() {
+        return Err(format!(
+            "dimension {} of an operand of rank {}",
+            dimension,
+            dims.len()
+        ));
+    }
+    Ok(dimension as usize)
+}
+
+/// Dimensions of the result of an AllGatherOp concatenating the operands of a
+/// group of `group_size` along `dimension`
+pub fn all_gather_dims(dims: &[i64], dimension: i64, group_size: i64) -> Result<Vec<i64>, String> {
+    let dimension = check_dimension(dims, dimension)?;
+    let mut result = dims.to_vec();
+    result[dimension] *= group_size;
+    Ok(result)
+}
+
+/// Dimensions of the result of a ReduceScatterOp splitting the reduced operand
+/// along `dimension` between a group of `group_size`
+///
+/// Fails if the dimension doesn't split evenly.
+pub fn reduce_scatter_dims(
+    dims: &[i64],
+    dimension: i64,
+    group_size: i64,
+) -> Result<Vec<i64>, String> {
+    let dimension = check_dimension(dims, dimension)?;
+    if group_size <= 0 || dims[dimension] % group_size != 0 {
+        return Err(format!(
+            "dimension {} of size {} doesn't split between {} replicas",
+            dimension, dims[dimension], group_size
+        ));
+    }
+    let mut result = dims.to_vec();
+    result[dimension] /= group_size;
+    Ok(result)
+}
+
+/// Dimensions of the result of an AllToAllOp splitting the operand along
+/// `split_dimension` between a group of `group_size`, and concatenating the
+/// parts received along `concat_dimension`
+pub fn all_to_all_dims(
+    dims: &[i64],
+    split_dimension: i64,
+    concat_dimension: i64,
+    group_size: i64,
+) -> Result<Vec<i64>, String> {
+    let mut result = reduce_scatter_dims(dims, split_dimension, group_size)?;
+    let concat_dimension = check_dimension(dims, concat_dimension)?;
+    result[concat_dimension] *= group_size;
+    Ok(result)
+}
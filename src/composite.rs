@@ -0,0 +1,74 @@
+use crate::model::*;
+use crate::naming::NamedExpansion;
+use egg::{rewrite as rw, *};
+use itertools::Itertools;
+
+/// Function computing the output metadata of a composite op from the metadata
+/// of its operands.
+pub type CompositeShapeFn = fn(&[&TensorData]) -> TensorData;
+
+/// Definition of a user-registered composite op
+///
+/// A composite op (e.g. GELU, LayerNorm, rotary embedding) is kept as a single
+/// `CompositeOp` node during saturation, so rules only have to match one node
+/// instead of the whole decomposition. The expansion rewrite adds the
+/// decomposition into base ops to the same eclass, and extraction decides
+/// whether the expanded form is better.
+#[derive(Clone, Debug)]
+pub struct CompositeOpDef {
+    /// Name of the composite op, also used as the label of the output node
+    pub name: String,
+    /// Number of tensor operands
+    pub num_operands: usize,
+    /// Computes the output shapes and element type from those of the operands
+    pub shape_fn: CompositeShapeFn,
+    /// Pattern of the expansion into base ops. Operands are referred to as
+    /// ?x0, ?x1, ...
+    pub expansion: String,
+    /// Estimated cost of the composite op as a whole
+    pub cost: f32,
+}
+
+/// Shape function for composite ops whose result has the shape and element type
+/// of their first operand (e.g. activations and normalizations)
+pub fn same_shape_as_first_operand(operands: &[&TensorData]) -> TensorData {
+    let first = operands
+        .first()
+        .expect("Composite op needs at least one operand to infer its shape");
+    TensorData {
+        shapes: vec![first.shapes[0]],
+        n_dims: vec![first.n_dims[0]],
+        name: None,
+        dtype: first.dtype,
+        constant: operands.iter().all(|operand| operand.constant),
+    }
+}
+
+/// Pattern matching the composite op with index `index`, with operands bound to
+/// ?x0, ?x1, ...
+fn composite_pattern(def: &CompositeOpDef, index: usize) -> Pattern<Mdl> {
+    let operands = (0..def.num_operands).map(|i| format!("?x{}", i)).join(" ");
+    format!("(CompositeOp {} {})", operands, index)
+        .parse()
+        .unwrap()
+}
+
+/// Build the expansion rewrites for the registered composite ops. The index of
+/// each definition is the one stored in its `CompositeOp` nodes.
+pub fn composite_expansion_rules(defs: &[CompositeOpDef]) -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    defs.iter()
+        .enumerate()
+        .map(|(index, def)| {
+            let lhs = composite_pattern(def, index);
+            let rhs: Pattern<Mdl> = def
+                .expansion
+                .parse()
+                .unwrap_or_else(|_| panic!("Malformed expansion for composite op {}", def.name));
+            let rule_name = format!("expand-{}", def.name);
+            rw!(rule_name; { lhs } => { NamedExpansion {
+                expansion: rhs,
+                name: def.name.clone(),
+            } })
+        })
+        .collect()
+}
@@ -0,0 +1,269 @@
+use crate::scheduler::RuleBudget;
+use serde::Deserialize;
+use std::fs::read_to_string;
+
+/// How optimize extracts the optimized graph from the e-graph
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExtractorKind {
+    /// Solve the extraction ILP with extractor/extract.py. Falls back to greedy
+    /// extraction without the `ilp-extractor` feature
+    Ilp,
+    Greedy,
+}
+
+/// MIP solver of extractor/extract.py, which must be available in the
+/// installed OR-Tools
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IlpSolver {
+    Cbc,
+    Gurobi,
+    Scip,
+}
+
+impl IlpSolver {
+    /// Value of the --solver argument of extract.py
+    pub fn name(&self) -> &'static str {
+        match self {
+            IlpSolver::Cbc => "cbc",
+            IlpSolver::Gurobi => "gurobi",
+            IlpSolver::Scip => "scip",
+        }
+    }
+}
+
+/// What the extracted graph minimizes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Objective {
+    /// The cost of the graph, i.e. its estimated runtime
+    Cost,
+    /// The number of ops, to cut the compile time or the binary size of the
+    /// backend rather than the runtime
+    NodeCount,
+}
+
+/// Which ops the mixed-precision rules may move from f32 to bf16, see
+/// precision.rs
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MixedPrecision {
+    /// Keep every op in the precision of the input graph
+    Off,
+    /// DotGeneralOps only
+    Dot,
+    /// DotGeneralOps, additions, subtractions, multiplications, minimums,
+    /// maximums and negations. Divisions, transcendental ops and reductions
+    /// stay in f32
+    Elementwise,
+}
+
+/// Settings for CppGraphConverter::optimize
+///
+/// Can be loaded from a TOML file with `from_toml`, whose keys are the field
+/// names below. Missing keys keep their default; the rule budgets can't be set
+/// from a file.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OptimizeConfig {
+    /// File with the learned rewrite rules, one "lhs=>rhs" per line
+    pub rule_file: String,
+    /// Seconds for timeout
+    pub n_sec: u64,
+    /// If set, one time budget in seconds for both saturation and extraction,
+    /// split between them as they go (see budget.rs). Replaces `n_sec` and
+    /// `ilp_time_sec`
+    pub time_budget_sec: Option<u64>,
+    /// Whether to use multi patterns
+    pub use_multi: bool,
+    /// Is our graph by definition acyclic?
+    pub no_cycle: bool,
+    /// Vanilla filtering or efficient filtering
+    pub filter_after: bool,
+    /// Max number of iterations for egg to run
+    pub iter_limit: usize,
+    /// Max nodes in e-graph
+    pub node_limit: usize,
+    /// Max number of iterations to apply multi-pattern rules
+    pub iter_multi: usize,
+    /// Max number of nodes added by multi-pattern rules
+    pub node_multi: usize,
+    /// If set, write a report of the differences between the original and the
+    /// optimized graph to this file
+    pub diff_report: Option<String>,
+    /// Whether to record the rules each output node was derived with. This
+    /// applies substitutions one at a time, so saturation is slower
+    pub track_provenance: bool,
+    /// If set, write a JSONL trace of the substitutions that survive into the
+    /// extracted graph to this file. Also records substitutions one at a time
+    pub rule_trace: Option<String>,
+    /// If set, save the e-graph (as dot) to this directory during saturation
+    pub snapshot_dir: Option<String>,
+    /// Save a snapshot every this many iterations
+    pub snapshot_every: usize,
+    /// Max number of snapshots to save
+    pub max_snapshots: usize,
+    /// Also take the statistics of the e-graph every this many iterations of
+    /// saturation (see CppGraphConverter::egraph_stats), 0 for only after it
+    pub stats_every: usize,
+    /// Skip saturation and extraction and return the input graph as is, to test
+    /// the conversion between C++ and Rust on its own
+    pub identity_mode: bool,
+    /// If set, write the extraction ILP in LP format to this file
+    pub lp_file: Option<String>,
+    /// Max number of matches a rule applies per iteration, for rules without a
+    /// `max_matches` option in the rule file
+    pub max_matches: Option<usize>,
+    /// Max time in milliseconds a rule may spend e-matching per iteration, for
+    /// rules without a `max_match_ms` option in the rule file. Rules over it
+    /// are skipped for the iteration
+    pub max_match_ms: Option<u64>,
+    /// Stop saturation early when the e-graph growth predicts that the next
+    /// iteration will exceed the node limit
+    pub predict_growth: bool,
+    /// If set, start saturation from the e-graph saved in this file (see
+    /// checkpoint.rs) instead of from the graph
+    pub resume_from: Option<String>,
+    /// If set, save the saturated e-graph to this file, to be resumed from
+    pub save_state: Option<String>,
+    /// Per-iteration budget of the cheap (canonicalization) rules
+    #[serde(skip)]
+    pub cheap_budget: RuleBudget,
+    /// Per-iteration budget of the expensive (exploration) rules
+    #[serde(skip)]
+    pub expensive_budget: RuleBudget,
+    /// File to write the time spent in each phase of optimize to, as JSON
+    pub profile_file: Option<String>,
+    /// Write the profile in Chrome trace format instead
+    pub profile_chrome_trace: bool,
+    /// Evaluate the input and optimized graphs with the reference interpreter
+    /// and return the input graph if their results differ
+    pub numeric_check: bool,
+    /// Max absolute difference allowed by the numeric check
+    pub numeric_tolerance: f32,
+    /// Check the ILP solution is no worse than greedy extraction
+    pub check_against_greedy: bool,
+    pub extractor: ExtractorKind,
+    pub objective: Objective,
+    /// If set, with the node count objective, fall back to extracting by cost
+    /// when the graph with the fewest ops costs more than this fraction above
+    /// the input graph, e.g. 0.05 for 5%
+    pub max_cost_regression: Option<f32>,
+    /// Time limit of the ILP solver, in seconds
+    pub ilp_time_sec: u64,
+    /// Number of threads of the ILP solver
+    pub ilp_num_threads: usize,
+    pub ilp_solver: IlpSolver,
+    /// If set, stop solving once the solution is within this relative gap of
+    /// the optimum, e.g. 0.01
+    pub ilp_mip_gap: Option<f32>,
+    /// Use integer variables for the topological order
+    pub ilp_order_var_int: bool,
+    /// Constrain each eclass to at most one picked node
+    pub ilp_class_constraint: bool,
+    /// Leave out the acyclicity constraints (the e-graph is kept acyclic
+    /// during saturation instead)
+    pub ilp_no_order: bool,
+    /// If set, log (egg's logs included) with this env_logger filter, e.g.
+    /// "egg=info". Ignored if the host already installed a logger
+    pub log_filter: Option<String>,
+    /// Seed of the random inputs of the numeric check
+    pub seed: u64,
+    /// If set, extract with the rematerialization ILP: an eclass may be
+    /// computed by several of its nodes, each serving some of its consumers,
+    /// and every consumer beyond the first of a node costs this much per byte
+    /// of its result (keeping the result alive for it). Trades recomputation
+    /// for lower peak activation memory. Only used by ILP extraction
+    pub remat_weight: Option<f32>,
+    /// If set, extract with a bonus of this much (in cost units) for every
+    /// consumer of a tensor beyond the first, so forms sharing common subgraphs
+    /// (e.g. across outputs) are preferred over cheaper forms duplicating them.
+    /// Useful when the backend can't CSE across the boundaries of the
+    /// optimized graph. Only used by ILP extraction, and ignored with
+    /// `remat_weight`
+    pub sharing_bonus: Option<f32>,
+    /// If set, charge ops whose operands are sharded differently (see
+    /// set_sharding) this much per byte of the operands to reshard
+    pub resharding_cost: Option<f32>,
+    /// Search over the layouts of DotGeneralOp operands, with the layout rules
+    /// and the layout-aware cost model (see layout.rs)
+    pub layout_search: bool,
+    /// Let saturation lower the ops allowed by this policy to bf16, with the
+    /// cost model choosing where (see precision.rs). Lowering changes the
+    /// results slightly, so with `numeric_check` the tolerance should allow
+    /// for bf16 rounding
+    pub mixed_precision: MixedPrecision,
+    /// Let saturation switch between the exact (erf) and approximate (tanh)
+    /// GELU, with the cost model choosing. As with `mixed_precision`, the
+    /// results change slightly
+    pub approximate_gelu: bool,
+}
+
+impl OptimizeConfig {
+    /// Load the settings from a TOML file, see OptimizeConfig
+    pub fn from_toml(path: &str) -> Result<Self, String> {
+        let text = read_to_string(path).map_err(|e| format!("Unable to read {}: {}", path, e))?;
+        Self::from_toml_str(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn from_toml_str(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|e| format!("Invalid config: {}", e))
+    }
+}
+
+impl Default for OptimizeConfig {
+    fn default() -> Self {
+        OptimizeConfig {
+            rule_file: "src/enzyme_ad/jax/deps/tensat/converted.txt".to_string(),
+            n_sec: 30,
+            time_budget_sec: None,
+            use_multi: false,
+            no_cycle: true,
+            filter_after: false,
+            iter_limit: 10000,
+            node_limit: 5000000,
+            iter_multi: 2,
+            node_multi: 30000,
+            diff_report: None,
+            track_provenance: false,
+            rule_trace: None,
+            snapshot_dir: None,
+            snapshot_every: 1,
+            max_snapshots: 10,
+            stats_every: 0,
+            identity_mode: false,
+            lp_file: None,
+            max_matches: None,
+            max_match_ms: None,
+            predict_growth: true,
+            resume_from: None,
+            save_state: None,
+            cheap_budget: RuleBudget::default(),
+            expensive_budget: RuleBudget::default(),
+            profile_file: None,
+            profile_chrome_trace: false,
+            numeric_check: false,
+            numeric_tolerance: 1e-3,
+            check_against_greedy: true,
+            extractor: ExtractorKind::Ilp,
+            objective: Objective::Cost,
+            max_cost_regression: None,
+            ilp_time_sec: 1000,
+            ilp_num_threads: 8,
+            ilp_solver: IlpSolver::Scip,
+            ilp_mip_gap: None,
+            ilp_order_var_int: false,
+            ilp_class_constraint: false,
+            ilp_no_order: true,
+            log_filter: None,
+            seed: 0,
+            remat_weight: None,
+            sharing_bonus: None,
+            resharding_cost: None,
+            layout_search: false,
+            mixed_precision: MixedPrecision::Off,
+            approximate_gelu: false,
+        }
+    }
+}
@@ -0,0 +1,153 @@
+//! Attributes of ConvolutionOp
+//!
+//! A ConvolutionOp node is `(ConvolutionOp lhs rhs window_strides padding
+//! lhs_dilation rhs_dilation dimension_numbers feature_group_count
+//! batch_group_count precision_config)`, with the attributes of
+//! stablehlo.convolution laid out as Vecs and Nums:
+//!
+//! - `padding` has the low and high padding of each spatial dimension, in
+//!   turn: `(Vec low0 high0 low1 high1 ...)`;
+//! - `dimension_numbers` has the dimensions of the input, then of the kernel,
+//!   then of the output: `(Vec input_batch input_feature input_spatial...
+//!   kernel_input_feature kernel_output_feature kernel_spatial... output_batch
+//!   output_feature output_spatial...)`.
+//!
+//! The number of spatial dimensions is the length of `window_strides`. Window
+//! reversal isn't represented, convolutions reversing their window stay
+//! blackboxes.
+
+/// Dimension numbers of a convolution, see the module documentation
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ConvDimensionNumbers {
+    pub input_batch: usize,
+    pub input_feature: usize,
+    pub input_spatial: Vec<usize>,
+    pub kernel_input_feature: usize,
+    pub kernel_output_feature: usize,
+    pub kernel_spatial: Vec<usize>,
+    pub output_batch: usize,
+    pub output_feature: usize,
+    pub output_spatial: Vec<usize>,
+}
+
+impl ConvDimensionNumbers {
+    /// Parse the flattened dimension numbers of a convolution with
+    /// `num_spatial` spatial dimensions
+    pub fn from_flat(flat: &[i64], num_spatial: usize) -> Result<Self, String> {
+        if flat.len() != 3 * (num_spatial + 2) {
+            return Err(format!(
+                "{} dimension numbers for {} spatial dimensions, expected {}",
+                flat.len(),
+                num_spatial,
+                3 * (num_spatial + 2)
+            ));
+        }
+        let dims: Vec<usize> = flat
+            .iter()
+            .map(|d| {
+                usize::try_from(*d)
+                    .ok()
+                    .filter(|d| *d < num_spatial + 2)
+                    .ok_or_else(|| format!("dimension {} out of range", d))
+            })
+            .collect::<Result<_, _>>()?;
+        let group = |k: usize| &dims[k * (num_spatial + 2)..(k + 1) * (num_spatial + 2)];
+        let (input, kernel, output) = (group(0), group(1), group(2));
+        Ok(ConvDimensionNumbers {
+            input_batch: input[0],
+            input_feature: input[1],
+            input_spatial: input[2..].to_vec(),
+            kernel_input_feature: kernel[0],
+            kernel_output_feature: kernel[1],
+            kernel_spatial: kernel[2..].to_vec(),
+            output_batch: output[0],
+            output_feature: output[1],
+            output_spatial: output[2..].to_vec(),
+        })
+    }
+}
+
+/// Dimensions of the result of a ConvolutionOp of `lhs` by the kernel `rhs`
+///
+/// Empty dilations stand for dilations of 1. Fails if the attributes don't
+/// match the number of spatial dimensions, or the features or batch don't
+/// divide into the groups.
+#[allow(clippy::too_many_arguments)]
+pub fn convolution_dims(
+    lhs: &[i64],
+    rhs: &[i64],
+    window_strides: &[i64],
+    padding: &[i64],
+    lhs_dilation: &[i64],
+    rhs_dilation: &[i64],
+    dimension_numbers: &[i64],
+    feature_group_count: i64,
+    batch_group_count: i64,
+) -> Result<Vec<i64>, String> {
+    let n = window_strides.len();
+    let dims = ConvDimensionNumbers::from_flat(dimension_numbers, n)?;
+    let rank = n + 2;
+    if lhs.len() != rank || rhs.len() != rank {
+        return Err(format!(
+            "operands of rank {} and {} for {} spatial dimensions",
+            lhs.len(),
+            rhs.len(),
+            n
+        ));
+    }
+    if padding.len() != 2 * n {
+        return Err(format!(
+            "{} padding values for {} spatial dimensions",
+            padding.len(),
+            n
+        ));
+    }
+    let dilation = |dilation: &[i64], i: usize| -> Result<i64, String> {
+        match dilation.len() {
+            0 => Ok(1),
+            len if len == n => Ok(dilation[i]),
+            len => Err(format!("{} dilations for {} spatial dimensions", len, n)),
+        }
+    };
+    if feature_group_count < 1 || batch_group_count < 1 {
+        return Err(format!(
+            "group counts {} and {}",
+            feature_group_count, batch_group_count
+        ));
+    }
+    if lhs[dims.input_feature] % feature_group_count != 0 {
+        return Err(format!(
+            "{} input features in {} groups",
+            lhs[dims.input_feature], feature_group_count
+        ));
+    }
+    if lhs[dims.input_batch] % batch_group_count != 0 {
+        return Err(format!(
+            "batch of {} in {} groups",
+            lhs[dims.input_batch], batch_group_count
+        ));
+    }
+
+    let mut result = vec![0; rank];
+    result[dims.output_batch] = lhs[dims.input_batch] / batch_group_count;
+    result[dims.output_feature] = rhs[dims.kernel_output_feature];
+    for i in 0..n {
+        if window_strides[i] < 1 {
+            return Err(format!("window stride {}", window_strides[i]));
+        }
+        let input = lhs[dims.input_spatial[i]];
+        let window = rhs[dims.kernel_spatial[i]];
+        let dilated_input = match input {
+            0 => 0,
+            input => (input - 1) * dilation(lhs_dilation, i)? + 1,
+        };
+        let padded = padding[2 * i] + dilated_input + padding[2 * i + 1];
+        let dilated_window = (window - 1).max(0) * dilation(rhs_dilation, i)? + 1;
+        result[dims.output_spatial[i]] = if padded < dilated_window {
+            0
+        } else {
+            (padded - dilated_window) / window_strides[i] + 1
+        };
+    }
+    Ok(result)
+}
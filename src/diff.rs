@@ -0,0 +1,142 @@
+use crate::{model::*, optimize::*, provenance::canonical_nodes};
+use egg::*;
+use std::collections::HashMap;
+use std::fmt;
+
+/// One aligned position of the original and optimized graphs
+#[derive(Clone, Debug)]
+pub struct DiffEntry {
+    /// EClass the ops belong to after saturation
+    pub eclass: Id,
+    /// Op in the original graph with its cost, if any
+    pub before: Option<(String, f64)>,
+    /// Op in the optimized graph with its cost, if any
+    pub after: Option<(String, f64)>,
+}
+
+/// Differences between the original and the optimized graph
+#[derive(Clone, Debug, Default)]
+pub struct GraphDiff {
+    /// Ops only in the optimized graph
+    pub added: Vec<DiffEntry>,
+    /// Ops only in the original graph
+    pub removed: Vec<DiffEntry>,
+    /// Positions computed by a different op after optimization
+    pub changed: Vec<DiffEntry>,
+    /// Number of ops kept as they were
+    pub num_unchanged: usize,
+    /// Total cost of the original graph
+    pub cost_before: f64,
+    /// Total cost of the optimized graph
+    pub cost_after: f64,
+}
+
+/// Ops worth reporting: everything except names, numbers and vectors
+fn is_op(node: &Mdl) -> bool {
+    !matches!(node, Mdl::Num(_) | Mdl::Var(_) | Mdl::Vec(_))
+}
+
+/// Op and cost of each eclass computed by an op in `expr`. Nodes not in the
+/// e-graph (and the nodes using them) are left out, the e-graph isn't changed
+fn ops_by_class(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    expr: &RecExpr<Mdl>,
+    cost_model: &CostModel,
+) -> (Vec<Id>, HashMap<Id, (String, f64)>) {
+    let mut order = vec![];
+    let mut ops = HashMap::new();
+    for (node, class) in canonical_nodes(egraph, expr).into_iter().flatten() {
+        if is_op(&node) && !ops.contains_key(&class) {
+            let cost = cost_model.get_self_cost(egraph, &node);
+            ops.insert(class, (node.display_op().to_string(), cost));
+            order.push(class);
+        }
+    }
+    (order, ops)
+}
+
+/// Align the original and optimized graphs and report what changed
+///
+/// Saturation keeps every expression equivalent to an original op in the eclass
+/// of that op, so ops of both graphs are aligned by the eclass they belong to.
+///
+/// # Parameters
+///
+/// - `egraph`: saturated e-graph both graphs were taken from
+/// - `original`: graph saturation started from
+/// - `optimized`: extracted graph
+/// - `cost_model`: cost model used to cost each op
+pub fn diff_graphs(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    original: &RecExpr<Mdl>,
+    optimized: &RecExpr<Mdl>,
+    cost_model: &CostModel,
+) -> GraphDiff {
+    let (before_order, before) = ops_by_class(egraph, original, cost_model);
+    let (after_order, after) = ops_by_class(egraph, optimized, cost_model);
+
+    let mut diff = GraphDiff::default();
+    diff.cost_before = before.values().map(|(_, cost)| *cost).fold(0.0, add_costs);
+    diff.cost_after = after.values().map(|(_, cost)| *cost).fold(0.0, add_costs);
+
+    for eclass in before_order {
+        let entry = DiffEntry {
+            eclass,
+            before: before.get(&eclass).cloned(),
+            after: after.get(&eclass).cloned(),
+        };
+        match &entry.after {
+            None => diff.removed.push(entry),
+            Some((op, _)) if *op != entry.before.as_ref().unwrap().0 => diff.changed.push(entry),
+            Some(_) => diff.num_unchanged += 1,
+        }
+    }
+    for eclass in after_order {
+        if !before.contains_key(&eclass) {
+            diff.added.push(DiffEntry {
+                eclass,
+                before: None,
+                after: after.get(&eclass).cloned(),
+            });
+        }
+    }
+    diff
+}
+
+impl fmt::Display for GraphDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Cost: {} -> {} ({:+})",
+            self.cost_before,
+            self.cost_after,
+            self.cost_after - self.cost_before
+        )?;
+        writeln!(
+            f,
+            "{} added, {} removed, {} changed, {} unchanged",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len(),
+            self.num_unchanged
+        )?;
+        for entry in self.added.iter() {
+            let (op, cost) = entry.after.as_ref().unwrap();
+            writeln!(f, "+ [{}] {} (cost {})", entry.eclass, op, cost)?;
+        }
+        for entry in self.removed.iter() {
+            let (op, cost) = entry.before.as_ref().unwrap();
+            writeln!(f, "- [{}] {} (cost {})", entry.eclass, op, cost)?;
+        }
+        for entry in self.changed.iter() {
+            let (op_before, cost_before) = entry.before.as_ref().unwrap();
+            let (op_after, cost_after) = entry.after.as_ref().unwrap();
+            writeln!(
+                f,
+                "~ [{}] {} (cost {}) -> {} (cost {})",
+                entry.eclass, op_before, cost_before, op_after, cost_after
+            )?;
+        }
+        Ok(())
+    }
+}
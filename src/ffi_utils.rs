@@ -5,6 +5,13 @@ use crate::{
 };
 use egg::*;
 
+// Shape inference and costs come from the C++ side, unless it's replaced by the
+// Rust stand-ins
+#[cfg(not(feature = "no-cxx-costmodel"))]
+pub use crate::input::ffi::{get_cost, get_shape};
+#[cfg(feature = "no-cxx-costmodel")]
+pub use crate::mock_cost::{get_cost, get_shape};
+
 fn dim_to_i64_vec(input: &[i32; MAX_DIM]) -> ffi::Shape {
     ffi::Shape {
         shape: input
@@ -15,36 +22,31 @@ fn dim_to_i64_vec(input: &[i32; MAX_DIM]) -> ffi::Shape {
     }
 }
 
-fn map_to_i64(vec: Vec<i32>) -> Vec<i64> {
-    vec.into_iter().map(|x| x as i64).collect()
-}
-
 fn process_enode_args(
     egraph: &EGraph<Mdl, TensorAnalysis>,
     enode: &Mdl,
 ) -> (Vec<ffi::Shape>, Vec<ffi::Type>, Vec<ffi::Shape>, Vec<i64>) {
     let mut args: Vec<ffi::Shape> = vec![];
+    let mut arg_types: Vec<ffi::Type> = vec![];
     let mut other_vecs: Vec<ffi::Shape> = vec![];
     let mut int_args: Vec<i64> = vec![];
 
     for child in enode.children().iter() {
         if let Some(other_vec) = get_vec_of_nums_option(egraph, &egraph[*child]) {
-            other_vecs.push(ffi::Shape {
-                shape: map_to_i64(other_vec),
-            })
+            other_vecs.push(ffi::Shape { shape: other_vec })
         } else if let Some(vec) = get_vec_option(&egraph[*child]) {
-            vec.iter()
-                .for_each(|&id| args.push(dim_to_i64_vec(&egraph[id].data.shapes[0])))
+            vec.iter().for_each(|&id| {
+                args.push(dim_to_i64_vec(&egraph[id].data.shapes[0]));
+                arg_types.push(egraph[id].data.dtype);
+            })
         } else if let Some(num) = get_num_option(&egraph[*child]) {
-            int_args.push(num as i64)
+            int_args.push(num)
         } else {
-            args.push(dim_to_i64_vec(&egraph[*child].data.shapes[0]))
+            args.push(dim_to_i64_vec(&egraph[*child].data.shapes[0]));
+            arg_types.push(egraph[*child].data.dtype);
         }
     }
 
-    // TODO: need to handle types
-    let arg_types: Vec<ffi::Type> = args.iter().map(|_| ffi::Type::f32).collect();
-
     (args, arg_types, other_vecs, int_args)
 }
 
@@ -60,6 +62,7 @@ pub fn convert_mdl_to_ffi_op(enode: &Mdl) -> ffi::Ops {
         Mdl::SelectOp(_) => ffi::Ops::SelectOp,
         Mdl::ConcatenateOp(_) => ffi::Ops::ConcatenateOp,
         Mdl::DotGeneralOp(_) => ffi::Ops::DotGeneralOp,
+        Mdl::ConvolutionOp(_) => ffi::Ops::ConvolutionOp,
         Mdl::PadOp(_) => ffi::Ops::PadOp,
         Mdl::SliceOp(_) => ffi::Ops::SliceOp,
         Mdl::TransposeOp(_) => ffi::Ops::TransposeOp,
@@ -69,13 +72,58 @@ pub fn convert_mdl_to_ffi_op(enode: &Mdl) -> ffi::Ops {
         Mdl::SubtractOp(_) => ffi::Ops::SubtractOp,
         Mdl::MinOp(_) => ffi::Ops::MinOp,
         Mdl::MaxOp(_) => ffi::Ops::MaxOp,
+        Mdl::ClampOp(_) => ffi::Ops::ClampOp,
+        Mdl::PowOp(_) => ffi::Ops::PowOp,
+        Mdl::RemainderOp(_) => ffi::Ops::RemainderOp,
+        Mdl::ComplexOp(_) => ffi::Ops::ComplexOp,
+        Mdl::RealOp(_) => ffi::Ops::RealOp,
+        Mdl::ImagOp(_) => ffi::Ops::ImagOp,
+        Mdl::FftOp(_) => ffi::Ops::FftOp,
+        Mdl::CholeskyOp(_) => ffi::Ops::CholeskyOp,
+        Mdl::TriangularSolveOp(_) => ffi::Ops::TriangularSolveOp,
+        Mdl::BatchNormInferenceOp(_) => ffi::Ops::BatchNormInferenceOp,
+        Mdl::BatchNormTrainingOp(_) => ffi::Ops::BatchNormTrainingOp,
+        Mdl::BatchNormGradOp(_) => ffi::Ops::BatchNormGradOp,
+        Mdl::AllReduceOp(_) => ffi::Ops::AllReduceOp,
+        Mdl::AllGatherOp(_) => ffi::Ops::AllGatherOp,
+        Mdl::ReduceScatterOp(_) => ffi::Ops::ReduceScatterOp,
+        Mdl::AllToAllOp(_) => ffi::Ops::AllToAllOp,
+        Mdl::TupleOp(_) => ffi::Ops::TupleOp,
+        Mdl::OptimizationBarrierOp(_) => ffi::Ops::OptimizationBarrierOp,
+        Mdl::GetTupleElementOp(_) => ffi::Ops::GetTupleElementOp,
+        Mdl::AndOp(_) => ffi::Ops::AndOp,
+        Mdl::OrOp(_) => ffi::Ops::OrOp,
+        Mdl::XorOp(_) => ffi::Ops::XorOp,
+        Mdl::NotOp(_) => ffi::Ops::NotOp,
+        Mdl::ShiftLeftOp(_) => ffi::Ops::ShiftLeftOp,
+        Mdl::ShiftRightLogicalOp(_) => ffi::Ops::ShiftRightLogicalOp,
+        Mdl::ShiftRightArithmeticOp(_) => ffi::Ops::ShiftRightArithmeticOp,
         Mdl::NegOp(_) => ffi::Ops::NegOp,
         Mdl::TanhOp(_) => ffi::Ops::TanhOp,
         Mdl::ExpOp(_) => ffi::Ops::ExpOp,
+        Mdl::SqrtOp(_) => ffi::Ops::SqrtOp,
+        Mdl::RsqrtOp(_) => ffi::Ops::RsqrtOp,
+        Mdl::LogOp(_) => ffi::Ops::LogOp,
+        Mdl::SineOp(_) => ffi::Ops::SineOp,
+        Mdl::CosineOp(_) => ffi::Ops::CosineOp,
+        Mdl::AbsOp(_) => ffi::Ops::AbsOp,
+        Mdl::SignOp(_) => ffi::Ops::SignOp,
+        Mdl::FloorOp(_) => ffi::Ops::FloorOp,
+        Mdl::CeilOp(_) => ffi::Ops::CeilOp,
+        Mdl::RoundOp(_) => ffi::Ops::RoundOp,
+        Mdl::ErfOp(_) => ffi::Ops::ErfOp,
+        Mdl::GeluOp(_) => ffi::Ops::GeluOp,
         Mdl::IotaOp(_) => ffi::Ops::IotaOp,
         Mdl::DynamicUpdateSliceOp(_) => ffi::Ops::DynamicUpdateSliceOp,
         Mdl::DynamicSliceOp(_) => ffi::Ops::DynamicSliceOp,
         Mdl::ScatterOp(_) => ffi::Ops::ScatterOp,
+        Mdl::SelectAndScatterOp(_) => ffi::Ops::SelectAndScatterOp,
+        Mdl::SortOp(_) => ffi::Ops::SortOp,
+        Mdl::WhileOp(_) => ffi::Ops::WhileOp,
+        Mdl::IfOp(_) => ffi::Ops::IfOp,
+        Mdl::CustomCallOp(_) => ffi::Ops::CustomCallOp,
+        Mdl::RngBitGeneratorOp(_) => ffi::Ops::RngBitGeneratorOp,
+        Mdl::RngOp(_) => ffi::Ops::RngOp,
         _ => panic!("Unsupported op for creating StableHLO op"),
     }
 }
@@ -0,0 +1,77 @@
+//! Attributes of FftOp
+//!
+//! An FftOp node is `(FftOp operand fft_type fft_length)`, with the fft_type
+//! of stablehlo.fft as a Num (see FftType) and fft_length as a Vec. The
+//! transform is over the last `fft_length.len()` dimensions of the operand.
+//! FFT and IFFT take and give complex (c64) tensors, RFFT takes real ones and
+//! gives the non-negative frequencies of the last dimension, IRFFT goes back.
+
+use crate::input::ffi;
+
+/// Kinds of FftOp, numbered as in StableHLO
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FftType {
+    Fft = 0,
+    Ifft = 1,
+    Rfft = 2,
+    Irfft = 3,
+}
+
+impl FftType {
+    pub fn from_num(num: i64) -> Result<Self, String> {
+        match num {
+            0 => Ok(FftType::Fft),
+            1 => Ok(FftType::Ifft),
+            2 => Ok(FftType::Rfft),
+            3 => Ok(FftType::Irfft),
+            _ => Err(format!("unknown fft_type {}", num)),
+        }
+    }
+
+    /// Element type of the results
+    pub fn result_dtype(self) -> ffi::Type {
+        match self {
+            FftType::Irfft => ffi::Type::f32,
+            _ => ffi::Type::c64,
+        }
+    }
+}
+
+/// Dimensions of the result of an FftOp of `fft_type` over an operand with
+/// dimensions `dims`
+///
+/// Fails if `fft_length` is empty, longer than the rank of the operand, or
+/// doesn't match its last dimensions (for RFFT, IRFFT giving `fft_length` back
+/// from its non-negative frequencies).
+pub fn fft_dims(dims: &[i64], fft_type: FftType, fft_length: &[i64]) -> Result<Vec<i64>, String> {
+    let n = fft_length.len();
+    if n == 0 || n > 3 || n > dims.len() {
+        return Err(format!(
+            "fft_length of {} dimensions for an operand of rank {}",
+            n,
+            dims.len()
+        ));
+    }
+    let last = dims.len() - 1;
+    let expected: Vec<i64> = match fft_type {
+        FftType::Irfft => {
+            let mut expected = fft_length.to_vec();
+            expected[n - 1] = fft_length[n - 1] / 2 + 1;
+            expected
+        }
+        _ => fft_length.to_vec(),
+    };
+    if dims[dims.len() - n..] != expected[..] {
+        return Err(format!(
+            "fft_length {:?} for the dimensions {:?}",
+            fft_length, dims
+        ));
+    }
+    let mut result = dims.to_vec();
+    match fft_type {
+        FftType::Rfft => result[last] = fft_length[n - 1] / 2 + 1,
+        FftType::Irfft => result[last] = fft_length[n - 1],
+        FftType::Fft | FftType::Ifft => {}
+    }
+    Ok(result)
+}
@@ -0,0 +1,119 @@
+use crate::model::*;
+use egg::*;
+
+/// Ops computed independently per element, which can be fused with their
+/// producers and consumers
+fn is_elementwise(node: &Mdl) -> bool {
+    matches!(
+        node,
+        Mdl::MulOp(_)
+            | Mdl::AddOp(_)
+            | Mdl::DivOp(_)
+            | Mdl::SubtractOp(_)
+            | Mdl::MinOp(_)
+            | Mdl::MaxOp(_)
+            | Mdl::ClampOp(_)
+            | Mdl::PowOp(_)
+            | Mdl::RemainderOp(_)
+            | Mdl::AndOp(_)
+            | Mdl::OrOp(_)
+            | Mdl::XorOp(_)
+            | Mdl::NotOp(_)
+            | Mdl::ShiftLeftOp(_)
+            | Mdl::ShiftRightLogicalOp(_)
+            | Mdl::ShiftRightArithmeticOp(_)
+            | Mdl::NegOp(_)
+            | Mdl::TanhOp(_)
+            | Mdl::ExpOp(_)
+            | Mdl::SqrtOp(_)
+            | Mdl::RsqrtOp(_)
+            | Mdl::LogOp(_)
+            | Mdl::SineOp(_)
+            | Mdl::CosineOp(_)
+            | Mdl::AbsOp(_)
+            | Mdl::SignOp(_)
+            | Mdl::FloorOp(_)
+            | Mdl::CeilOp(_)
+            | Mdl::RoundOp(_)
+            | Mdl::ErfOp(_)
+            | Mdl::ComplexOp(_)
+            | Mdl::RealOp(_)
+            | Mdl::ImagOp(_)
+            | Mdl::GeluOp(_)
+            | Mdl::SelectOp(_)
+            | Mdl::CompareOp(_)
+            | Mdl::ConvertOp(_)
+    )
+}
+
+fn find(parent: &mut Vec<usize>, i: usize) -> usize {
+    let mut root = i;
+    while parent[root] != root {
+        root = parent[root];
+    }
+    parent[i] = root;
+    root
+}
+
+/// Compute producer-consumer fusion groups of an extracted graph
+///
+/// An elementwise op is fused into its consumer if the consumer is elementwise
+/// or a reduce (of which it is an input), and the op has no other users. Chains of such ops form a group,
+/// so a group is an elementwise chain optionally ending in a reduce.
+///
+/// # Returns
+///
+/// The group id of each node in `expr`, in the same order. Groups are numbered
+/// from 0 in order of appearance; nodes that are not fused with anything get -1.
+pub fn fusion_groups(expr: &RecExpr<Mdl>) -> Vec<i32> {
+    let nodes = expr.as_ref();
+    let mut num_users = vec![0; nodes.len()];
+    for node in nodes {
+        for child in node.children() {
+            num_users[usize::from(*child)] += 1;
+        }
+    }
+
+    let mut parent: Vec<usize> = (0..nodes.len()).collect();
+    for (i, node) in nodes.iter().enumerate() {
+        if !is_elementwise(node) && !matches!(node, Mdl::ReduceOp(_)) {
+            continue;
+        }
+        let operands = match node {
+            Mdl::ReduceOp([inputs, ..]) => match &nodes[usize::from(*inputs)] {
+                Mdl::Vec(inputs) => inputs.as_slice(),
+                _ => &[],
+            },
+            _ => node.children(),
+        };
+        for child in operands {
+            let producer = usize::from(*child);
+            if is_elementwise(&nodes[producer]) && num_users[producer] == 1 {
+                let root = find(&mut parent, producer);
+                parent[root] = find(&mut parent, i);
+            }
+        }
+    }
+
+    let roots: Vec<usize> = (0..nodes.len()).map(|i| find(&mut parent, i)).collect();
+    let mut group_size = vec![0; nodes.len()];
+    for root in roots.iter() {
+        group_size[*root] += 1;
+    }
+    let mut group_id = vec![-1; nodes.len()];
+    let mut num_groups = 0;
+    roots
+        .iter()
+        .map(|root| {
+            if group_size[*root] < 2 {
+                -1
+            } else {
+                if group_id[*root] < 0 {
+                    group_id[*root] = num_groups;
+                    num_groups += 1;
+                }
+                group_id[*root]
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,380 @@
+use crate::input::{ffi, CppGraphConverter};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A node of an exported PyTorch FX graph
+#[derive(Deserialize, Debug)]
+pub struct FxNode {
+    pub name: String,
+    /// "placeholder", "call_function", "get_attr" or "output"
+    pub op: String,
+    /// The aten op of call_function nodes, e.g. "aten.mm.default"
+    #[serde(default)]
+    pub target: String,
+    /// Arguments, with nodes referenced as `{"node": name}`
+    #[serde(default)]
+    pub args: Vec<Value>,
+    /// Keyword arguments, e.g. `approximate` of gelu
+    #[serde(default)]
+    pub kwargs: HashMap<String, Value>,
+    /// Shape of the result, from `node.meta["val"]`
+    #[serde(default)]
+    pub shape: Vec<i32>,
+}
+
+/// An FX graph as dumped to JSON, e.g. from `torch.export` with
+///
+/// ```python
+/// def arg(a):
+///     if isinstance(a, torch.fx.Node):
+///         return {"node": a.name}
+///     return [arg(x) for x in a] if isinstance(a, (list, tuple)) else a
+/// gm = torch.export.export(model, args).run_decompositions().module()
+/// json.dump({"nodes": [
+///     {"name": n.name, "op": n.op, "target": str(n.target), "args": arg(n.args),
+///      "kwargs": {k: arg(v) for k, v in n.kwargs.items()},
+///      "shape": list(n.meta["val"].shape) if "val" in n.meta and hasattr(n.meta["val"], "shape") else []}
+///     for n in gm.graph.nodes]}, f)
+/// ```
+#[derive(Deserialize, Debug)]
+pub struct FxGraph {
+    pub nodes: Vec<FxNode>,
+}
+
+/// Build the graph of an FX graph (JSON, see FxGraph) with a CppGraphConverter
+///
+/// Placeholders and get_attr nodes (parameters) become the block arguments of
+/// the graph, in order, and the arguments of the output node the operands of
+/// the ReturnOp. Scalar arguments become splat constants of the shape of the
+/// node's result.
+///
+/// The elementwise aten ops (add, sub, mul, div, maximum, minimum, pow, fmod,
+/// neg, tanh, exp, erf, sqrt, rsqrt, log, sin, cos, abs, sign, floor, ceil,
+/// relu, gelu), mm, bmm, addmm, linear, t, transpose, permute, view/reshape,
+/// unsqueeze, expand, cat, slice and sum.dim_IntList are lowered to Mdl ops.
+/// Other ops become BlackBoxes.
+pub fn import_fx(json: &str) -> Result<CppGraphConverter, String> {
+    let graph: FxGraph =
+        serde_json::from_str(json).map_err(|e| format!("Invalid FX graph: {}", e))?;
+    let mut importer = FxImporter {
+        converter: CppGraphConverter::default(),
+        tensors: HashMap::new(),
+        num_inputs: 0,
+        num_blackboxes: 0,
+    };
+    for node in &graph.nodes {
+        match node.op.as_str() {
+            "placeholder" | "get_attr" => {
+                let tensor =
+                    importer
+                        .converter
+                        .new_input(importer.num_inputs, &node.shape, ffi::Type::f32);
+                importer.num_inputs += 1;
+                importer.tensors.insert(node.name.clone(), tensor);
+            }
+            "call_function" => {
+                let tensor = importer.lower(node)?;
+                importer.tensors.insert(node.name.clone(), tensor);
+            }
+            "output" => {
+                let mut outputs = vec![];
+                for arg in node.args.iter().flat_map(flatten) {
+                    outputs.push(importer.tensor(arg, &[])?);
+                }
+                importer.converter.new_return_op(&outputs);
+                return Ok(importer.converter);
+            }
+            other => return Err(format!("Unsupported FX node kind {}", other)),
+        }
+    }
+    Err("FX graph has no output node".to_string())
+}
+
+/// Args of the output node are wrapped in a tuple, possibly nested
+fn flatten(arg: &Value) -> Vec<&Value> {
+    match arg {
+        Value::Array(args) => args.iter().flat_map(flatten).collect(),
+        arg => vec![arg],
+    }
+}
+
+/// Op name of an aten target, without namespace and overload:
+/// "aten.mm.default" -> "mm"
+fn op_name(target: &str) -> &str {
+    let target = target.strip_prefix("aten.").unwrap_or(target);
+    target.split('.').next().unwrap_or(target)
+}
+
+struct FxImporter {
+    converter: CppGraphConverter,
+    tensors: HashMap<String, ffi::TensorHandle>,
+    num_inputs: i32,
+    num_blackboxes: i32,
+}
+
+impl FxImporter {
+    /// The tensor `arg` refers to. Scalars become splat constants of `shape`
+    fn tensor(&mut self, arg: &Value, shape: &[i32]) -> Result<ffi::TensorHandle, String> {
+        if let Some(name) = arg["node"].as_str() {
+            return self
+                .tensors
+                .get(name)
+                .copied()
+                .ok_or_else(|| format!("Node {} is undefined", name));
+        }
+        match arg.as_f64() {
+            Some(value) => Ok(self.converter.new_splat_constant_op(value as f32, shape)),
+            None => Err(format!("Expected a tensor, got {}", arg)),
+        }
+    }
+
+    fn shape_of(&self, arg: &Value) -> Result<Vec<i32>, String> {
+        let name = arg["node"]
+            .as_str()
+            .ok_or_else(|| format!("Expected a tensor, got {}", arg))?;
+        let tensor = self
+            .tensors
+            .get(name)
+            .ok_or_else(|| format!("Node {} is undefined", name))?;
+        Ok(self.converter.tensor_shape(*tensor))
+    }
+
+    fn lower(&mut self, node: &FxNode) -> Result<ffi::TensorHandle, String> {
+        let shape = &node.shape[..];
+        let rank = shape.len() as i64;
+        let arg = |i: usize| {
+            node.args
+                .get(i)
+                .ok_or_else(|| format!("{} ({}) is missing argument {}", node.name, node.target, i))
+        };
+        let int_arg = |i: usize| -> Result<i64, String> {
+            arg(i)?
+                .as_i64()
+                .ok_or_else(|| format!("{}: argument {} is not an integer", node.name, i))
+        };
+        let ints_arg = |i: usize| -> Result<Vec<i64>, String> {
+            arg(i)?
+                .as_array()
+                .ok_or_else(|| format!("{}: argument {} is not a list", node.name, i))?
+                .iter()
+                .map(|v| {
+                    v.as_i64().ok_or_else(|| {
+                        format!("{}: argument {} is not a list of integers", node.name, i)
+                    })
+                })
+                .collect()
+        };
+        // Negative dims count from the back, in the rank of the result
+        let dim = |d: i64| (if d < 0 { d + rank } else { d }) as i32;
+
+        let tensor = match op_name(&node.target) {
+            op @ ("add" | "sub" | "mul" | "div" | "maximum" | "minimum" | "pow" | "fmod") => {
+                let lhs = self.tensor(arg(0)?, shape)?;
+                let rhs = self.tensor(arg(1)?, shape)?;
+                if !self.same_shape(lhs, shape) || !self.same_shape(rhs, shape) {
+                    // Implicit broadcasting isn't modeled
+                    return self.blackbox(node);
+                }
+                match op {
+                    "add" => self.converter.new_add_op(lhs, rhs, shape),
+                    "sub" => self.converter.new_subtract_op(lhs, rhs, shape),
+                    "mul" => self.converter.new_mul_op(lhs, rhs, shape),
+                    "div" => self.converter.new_div_op(lhs, rhs, shape),
+                    "maximum" => self.converter.new_max_op(lhs, rhs, shape),
+                    "pow" => self.converter.new_pow_op(lhs, rhs, shape),
+                    "fmod" => self.converter.new_remainder_op(lhs, rhs, shape),
+                    _ => self.converter.new_min_op(lhs, rhs, shape),
+                }
+            }
+            op @ ("neg" | "tanh" | "exp" | "erf" | "sqrt" | "rsqrt" | "log" | "sin" | "cos"
+            | "abs" | "sign" | "floor" | "ceil") => {
+                let x = self.tensor(arg(0)?, shape)?;
+                match op {
+                    "neg" => self.converter.new_neg_op(x, shape),
+                    "tanh" => self.converter.new_tanh_op(x, shape),
+                    "exp" => self.converter.new_exp_op(x, shape),
+                    "sqrt" => self.converter.new_sqrt_op(x, shape),
+                    "rsqrt" => self.converter.new_rsqrt_op(x, shape),
+                    "log" => self.converter.new_log_op(x, shape),
+                    "sin" => self.converter.new_sine_op(x, shape),
+                    "cos" => self.converter.new_cosine_op(x, shape),
+                    "abs" => self.converter.new_abs_op(x, shape),
+                    "sign" => self.converter.new_sign_op(x, shape),
+                    "floor" => self.converter.new_floor_op(x, shape),
+                    "ceil" => self.converter.new_ceil_op(x, shape),
+                    _ => self.converter.new_erf_op(x, shape),
+                }
+            }
+            "relu" => {
+                let x = self.tensor(arg(0)?, shape)?;
+                let zero = self.converter.new_splat_constant_op(0.0, shape);
+                self.converter.new_max_op(x, zero, shape)
+            }
+            "gelu" => {
+                let x = self.tensor(arg(0)?, shape)?;
+                let approximate = node.kwargs.get("approximate").or(node.args.get(1));
+                let approximate = match approximate.and_then(|a| a.as_str()) {
+                    Some("tanh") => 1,
+                    _ => 0,
+                };
+                self.converter.new_gelu_op(x, approximate, shape)
+            }
+            "mm" => {
+                let lhs = self.tensor(arg(0)?, shape)?;
+                let rhs = self.tensor(arg(1)?, shape)?;
+                self.converter
+                    .new_dot_general_op(lhs, rhs, &[], &[], &[1], &[0], &[], shape)
+            }
+            "bmm" => {
+                let lhs = self.tensor(arg(0)?, shape)?;
+                let rhs = self.tensor(arg(1)?, shape)?;
+                self.converter
+                    .new_dot_general_op(lhs, rhs, &[0], &[0], &[2], &[1], &[], shape)
+            }
+            "addmm" | "linear" => {
+                // addmm(bias, x, w) = x @ w + bias, linear(x, w, bias) = x @ w.T + bias
+                let (bias, x, w) = if op_name(&node.target) == "addmm" {
+                    (node.args.first(), arg(1)?, arg(2)?)
+                } else {
+                    (node.args.get(2), arg(0)?, arg(1)?)
+                };
+                if rank != 2 {
+                    return self.blackbox(node);
+                }
+                let x = self.tensor(x, shape)?;
+                let w = self.tensor(w, shape)?;
+                let w_contract = if op_name(&node.target) == "addmm" {
+                    0
+                } else {
+                    1
+                };
+                let mm = self.converter.new_dot_general_op(
+                    x,
+                    w,
+                    &[],
+                    &[],
+                    &[1],
+                    &[w_contract],
+                    &[],
+                    shape,
+                );
+                match bias {
+                    Some(bias) if !bias.is_null() => {
+                        let bias_rank = self.shape_of(bias)?.len() as i64;
+                        let bias = self.tensor(bias, shape)?;
+                        let dims: Vec<i64> = (2 - bias_rank..2).collect();
+                        let bias = self.converter.new_broadcast_in_dim(bias, &dims, shape);
+                        self.converter.new_add_op(mm, bias, shape)
+                    }
+                    _ => mm,
+                }
+            }
+            "t" | "transpose" | "permute" => {
+                let x = self.tensor(arg(0)?, shape)?;
+                let permutation: Vec<i64> = match op_name(&node.target) {
+                    "t" => vec![1, 0],
+                    "transpose" => {
+                        let (a, b) = (dim(int_arg(1)?), dim(int_arg(2)?));
+                        let mut permutation: Vec<i64> = (0..rank).collect();
+                        permutation.swap(a as usize, b as usize);
+                        permutation
+                    }
+                    _ => ints_arg(1)?.into_iter().map(|d| dim(d).into()).collect(),
+                };
+                self.converter.new_transpose_op(x, &permutation, shape)
+            }
+            "view" | "reshape" | "_unsafe_view" | "unsqueeze" | "squeeze" | "flatten" => {
+                let x = self.tensor(arg(0)?, shape)?;
+                self.converter.new_reshape_op(x, shape)
+            }
+            "expand" => {
+                let x_shape = self.shape_of(arg(0)?)?;
+                let x = self.tensor(arg(0)?, shape)?;
+                // The operand's dims line up with the trailing dims of the result
+                let offset = rank - x_shape.len() as i64;
+                let dims: Vec<i64> = (offset..rank).collect();
+                self.converter.new_broadcast_in_dim(x, &dims, shape)
+            }
+            "cat" => {
+                let inputs = arg(0)?
+                    .as_array()
+                    .ok_or_else(|| format!("{}: argument 0 is not a list", node.name))?;
+                let axis = match node.args.get(1) {
+                    Some(axis) => dim(axis.as_i64().unwrap_or(0)),
+                    None => 0,
+                };
+                let mut tensors = Vec::with_capacity(inputs.len());
+                for input in inputs {
+                    tensors.push(self.tensor(input, shape)?);
+                }
+                self.converter.new_concatenate_op(&tensors, axis, shape)
+            }
+            "slice" => {
+                let x_shape = self.shape_of(arg(0)?)?;
+                let x = self.tensor(arg(0)?, shape)?;
+                let d = dim(node.args.get(1).and_then(|a| a.as_i64()).unwrap_or(0)) as usize;
+                let size = x_shape[d] as i64;
+                // end may be a huge sentinel (sys.maxsize) for "to the end"
+                let clamp = |i: i64| (if i < 0 { i + size } else { i }).clamp(0, size);
+                let start = clamp(node.args.get(2).and_then(|a| a.as_i64()).unwrap_or(0));
+                let end = clamp(node.args.get(3).and_then(|a| a.as_i64()).unwrap_or(size));
+                let step = node.args.get(4).and_then(|a| a.as_i64()).unwrap_or(1);
+                let mut starts = vec![0; x_shape.len()];
+                let mut limits: Vec<i64> = x_shape.iter().map(|d| i64::from(*d)).collect();
+                let mut strides = vec![1; x_shape.len()];
+                starts[d] = start;
+                limits[d] = end;
+                strides[d] = step;
+                self.converter
+                    .new_slice_op(x, &starts, &limits, &strides, shape)
+            }
+            "sum" if !node.target.ends_with(".default") => {
+                let x_shape = self.shape_of(arg(0)?)?;
+                let x_rank = x_shape.len() as i64;
+                let keepdim = node.args.get(2).and_then(|a| a.as_bool()) == Some(true);
+                let mut dims: Vec<i64> = ints_arg(1)?
+                    .into_iter()
+                    .map(|d| if d < 0 { d + x_rank } else { d })
+                    .collect();
+                dims.sort_unstable();
+                let reduced_shape: Vec<i32> = (0..x_rank)
+                    .filter(|d| !dims.contains(d))
+                    .map(|d| x_shape[d as usize])
+                    .collect();
+                let x = self.tensor(arg(0)?, shape)?;
+                let shapes = vec![ffi::Shape {
+                    shape: reduced_shape.iter().map(|d| *d as i64).collect(),
+                }];
+                let sum = self.converter.new_reduce_sum(x, &dims, &shapes);
+                if keepdim {
+                    self.converter.new_reshape_op(sum, shape)
+                } else {
+                    sum
+                }
+            }
+            _ => self.blackbox(node)?,
+        };
+        Ok(tensor)
+    }
+
+    fn same_shape(&self, tensor: ffi::TensorHandle, shape: &[i32]) -> bool {
+        self.converter.tensor_shape(tensor) == shape
+    }
+
+    /// BlackBox taking the tensor arguments of `node`
+    fn blackbox(&mut self, node: &FxNode) -> Result<ffi::TensorHandle, String> {
+        let mut tensors = vec![];
+        for arg in node.args.iter().flat_map(flatten) {
+            if arg["node"].is_string() {
+                tensors.push(self.tensor(arg, &[])?);
+            }
+        }
+        let shapes = vec![ffi::Shape {
+            shape: node.shape.iter().map(|d| *d as i64).collect(),
+        }];
+        let cpp_num = self.num_blackboxes;
+        self.num_blackboxes += 1;
+        Ok(self.converter.new_blackbox_op(&tensors, cpp_num, &shapes))
+    }
+}
@@ -1,18 +1,19 @@
 #include "rust/cxx.h"
 #include "cxxbridge/deps/tensat/src/input.rs.h"
-#include <utility>
+#include <iostream>
 
 int main() {
     auto graphBox = tensat::new_converter();
-    // int dims[2] = {1024, 1024};
-    int dims_1[0] = {};
-    int dims_2[0] = {};
-    auto input_slice_1 = rust::Slice<const int32_t>{dims_1, 0};
-    auto input_slice_2 = rust::Slice<const int32_t>{dims_2, 0};
-    auto inp1 = graphBox->new_input(input_slice_1);
-    auto inp2 = graphBox->new_input(input_slice_2);
-    auto mul = graphBox->new_mul_op(*inp1, *inp2, 0);
-    // auto relu = graphBox->new_tanh_op(*inp, 0);
+    int32_t dims[2] = {2, 3};
+    auto shape = rust::Slice<const int32_t>{dims, 2};
+    auto inp1 = graphBox->new_input(0, shape, tensat::Type::f32);
+    auto inp2 = graphBox->new_input(1, shape, tensat::Type::f32);
+    auto mul = graphBox->new_mul_op(inp1, inp2, shape);
+    // auto relu = graphBox->new_tanh_op(mul, shape);
 
+    for (auto dim : graphBox->tensor_shape(mul)) {
+        std::cout << dim << " ";
+    }
+    std::cout << std::endl;
     graphBox->print_rec_expr();
 }
@@ -0,0 +1,442 @@
+use crate::input::{ffi, CppGraphConverter};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// A TensorFlow GraphDef in the protobuf JSON mapping
+///
+/// Get it from a frozen graph or a SavedModel with
+///
+/// ```python
+/// from google.protobuf import json_format
+/// graph_def = tf.function(f).get_concrete_function(*specs).graph.as_graph_def(add_shapes=True)
+/// # or, for a SavedModel: tf.saved_model.load(path).signatures["serving_default"]
+/// #     .graph.as_graph_def(add_shapes=True)
+/// open("graph.json", "w").write(json_format.MessageToJson(graph_def))
+/// ```
+///
+/// `add_shapes=True` is required: the importer takes result shapes from the
+/// `_output_shapes` attribute instead of running TF's shape inference.
+#[derive(Deserialize, Debug)]
+pub struct GraphDef {
+    #[serde(default)]
+    pub node: Vec<NodeDef>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NodeDef {
+    pub name: String,
+    pub op: String,
+    #[serde(default)]
+    pub input: Vec<String>,
+    #[serde(default)]
+    pub attr: HashMap<String, Value>,
+}
+
+/// Build the graph of a GraphDef (JSON, see GraphDef) with a CppGraphConverter
+///
+/// Placeholders become the block arguments of the graph, in the order they
+/// appear. `outputs` names the nodes returned by the graph; if empty, every
+/// node whose result isn't used (other than Consts) is returned.
+///
+/// MatMul, BatchMatMul(V2), BiasAdd, Relu, Concat(V2), Reshape, Transpose and
+/// Identity are lowered to Mdl ops, and scalar Consts to splat constants. Every
+/// other op, including Conv2D (Mdl has no convolution), becomes a BlackBox with
+/// the shapes from `_output_shapes`.
+pub fn import_graphdef(json: &str, outputs: &[&str]) -> Result<CppGraphConverter, String> {
+    let graph: GraphDef =
+        serde_json::from_str(json).map_err(|e| format!("Invalid GraphDef: {}", e))?;
+    let mut importer = Importer {
+        nodes: graph.node.iter().map(|n| (n.name.as_str(), n)).collect(),
+        converter: CppGraphConverter::default(),
+        tensors: HashMap::new(),
+        num_inputs: 0,
+        num_blackboxes: 0,
+    };
+    for node in topological_order(&graph)? {
+        let tensor = importer.lower(node)?;
+        importer.tensors.insert(node.name.as_str(), tensor);
+    }
+
+    let output_names: Vec<&str> = if outputs.is_empty() {
+        let used: HashSet<&str> = graph
+            .node
+            .iter()
+            .flat_map(|n| n.input.iter().map(|i| split_input(i).0))
+            .collect();
+        graph
+            .node
+            .iter()
+            .filter(|n| n.op != "Const" && !used.contains(n.name.as_str()))
+            .map(|n| n.name.as_str())
+            .collect()
+    } else {
+        outputs.to_vec()
+    };
+    let mut results = Vec::with_capacity(output_names.len());
+    for name in output_names {
+        results.push(importer.input(name)?);
+    }
+    importer.converter.new_return_op(&results);
+    Ok(importer.converter)
+}
+
+/// Node name and result index of an input reference ("name", "name:1" or
+/// "^name" for control dependencies)
+fn split_input(input: &str) -> (&str, usize) {
+    let input = input.trim_start_matches('^');
+    match input.rsplit_once(':') {
+        Some((name, index)) => (name, index.parse().unwrap_or(0)),
+        None => (input, 0),
+    }
+}
+
+fn is_control_input(input: &str) -> bool {
+    input.starts_with('^')
+}
+
+/// Nodes of `graph` ordered so that every node comes after its inputs.
+/// GraphDefs don't guarantee any order
+fn topological_order(graph: &GraphDef) -> Result<Vec<&NodeDef>, String> {
+    let index: HashMap<&str, usize> = graph
+        .node
+        .iter()
+        .enumerate()
+        .map(|(i, n)| (n.name.as_str(), i))
+        .collect();
+    let mut num_pending = vec![0; graph.node.len()];
+    let mut users: Vec<Vec<usize>> = vec![vec![]; graph.node.len()];
+    for (i, node) in graph.node.iter().enumerate() {
+        for input in &node.input {
+            let name = split_input(input).0;
+            let j = *index
+                .get(name)
+                .ok_or_else(|| format!("{} uses undefined node {}", node.name, name))?;
+            num_pending[i] += 1;
+            users[j].push(i);
+        }
+    }
+    let mut ready: VecDeque<usize> = (0..graph.node.len())
+        .filter(|i| num_pending[*i] == 0)
+        .collect();
+    let mut order = Vec::with_capacity(graph.node.len());
+    while let Some(i) = ready.pop_front() {
+        order.push(&graph.node[i]);
+        for &user in &users[i] {
+            num_pending[user] -= 1;
+            if num_pending[user] == 0 {
+                ready.push_back(user);
+            }
+        }
+    }
+    if order.len() != graph.node.len() {
+        return Err("GraphDef has a cycle (control flow is not supported)".to_string());
+    }
+    Ok(order)
+}
+
+struct Importer<'a> {
+    nodes: HashMap<&'a str, &'a NodeDef>,
+    converter: CppGraphConverter,
+    /// Lowered nodes, by name
+    tensors: HashMap<&'a str, ffi::TensorHandle>,
+    num_inputs: i32,
+    num_blackboxes: i32,
+}
+
+impl<'a> Importer<'a> {
+    /// The tensor an input reference of a node refers to
+    fn input(&mut self, input: &str) -> Result<ffi::TensorHandle, String> {
+        let (name, index) = split_input(input);
+        let tensor = *self
+            .tensors
+            .get(name)
+            .ok_or_else(|| format!("Node {} is undefined", name))?;
+        if self.converter.tensor(tensor).tensor_data.shapes.len() > 1 {
+            Ok(self.converter.new_index(index as i32, tensor))
+        } else if index == 0 {
+            Ok(tensor)
+        } else {
+            Err(format!(
+                "{} has a single result, {} is out of range",
+                name, input
+            ))
+        }
+    }
+
+    /// Value of a Const node of integers, e.g. an axis or a permutation
+    fn const_ints(&self, input: &str) -> Result<Vec<i64>, String> {
+        let name = split_input(input).0;
+        let node = self
+            .nodes
+            .get(name)
+            .ok_or_else(|| format!("Node {} is undefined", name))?;
+        if node.op != "Const" {
+            return Err(format!("{} must be a Const, not a {}", name, node.op));
+        }
+        let tensor = &attr(node, "value")["tensor"];
+        if let Some(content) = tensor["tensorContent"].as_str() {
+            let bytes = decode_base64(content)?;
+            return Ok(match tensor["dtype"].as_str() {
+                Some("DT_INT64") => bytes
+                    .chunks_exact(8)
+                    .map(|b| i64::from_le_bytes(b.try_into().unwrap()))
+                    .collect(),
+                _ => bytes
+                    .chunks_exact(4)
+                    .map(|b| i32::from_le_bytes(b.try_into().unwrap()) as i64)
+                    .collect(),
+            });
+        }
+        let values = match (&tensor["intVal"], &tensor["int64Val"]) {
+            (Value::Array(values), _) | (_, Value::Array(values)) => values,
+            _ => return Err(format!("Const {} has no integer value", name)),
+        };
+        values
+            .iter()
+            .map(|v| match v {
+                // int64 values are strings in the JSON mapping
+                Value::String(s) => s.parse::<i64>().map_err(|e| e.to_string()),
+                v => v.as_i64().ok_or_else(|| format!("{} is not an integer", v)),
+            })
+            .collect()
+    }
+
+    fn lower(&mut self, node: &'a NodeDef) -> Result<ffi::TensorHandle, String> {
+        let inputs: Vec<&String> = node.input.iter().filter(|i| !is_control_input(i)).collect();
+        let arity = |n: usize| {
+            if inputs.len() == n {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{} ({}) takes {} inputs, got {}",
+                    node.name,
+                    node.op,
+                    n,
+                    inputs.len()
+                ))
+            }
+        };
+        let flag = |name: &str| node.attr.get(name).and_then(|a| a["b"].as_bool()) == Some(true);
+
+        if node.op == "Placeholder" {
+            let shape = shape_from_proto(&attr(node, "shape")["shape"]);
+            let dtype = match attr(node, "dtype")["type"].as_str() {
+                Some("DT_INT32") => ffi::Type::i32,
+                Some("DT_BOOL") => ffi::Type::i1,
+                Some("DT_BFLOAT16") => ffi::Type::bf16,
+                Some("DT_COMPLEX64") => ffi::Type::c64,
+                _ => ffi::Type::f32,
+            };
+            let tensor = self.converter.new_input(self.num_inputs, &shape, dtype);
+            self.num_inputs += 1;
+            return Ok(tensor);
+        }
+        if node.op == "Const" {
+            return self.lower_const(node);
+        }
+
+        let shapes = output_shapes(node)?;
+        let shape: Vec<i32> = shapes[0].clone();
+        let tensor = match node.op.as_str() {
+            "Identity" => {
+                arity(1)?;
+                self.input(inputs[0])?
+            }
+            "MatMul" => {
+                arity(2)?;
+                let lhs = self.input(inputs[0])?;
+                let rhs = self.input(inputs[1])?;
+                let lhs_contract = if flag("transpose_a") { 0 } else { 1 };
+                let rhs_contract = if flag("transpose_b") { 1 } else { 0 };
+                self.converter.new_dot_general_op(
+                    lhs,
+                    rhs,
+                    &[],
+                    &[],
+                    &[lhs_contract],
+                    &[rhs_contract],
+                    &[],
+                    &shape,
+                )
+            }
+            "BatchMatMul" | "BatchMatMulV2" | "BatchMatMulV3" => {
+                arity(2)?;
+                let lhs = self.input(inputs[0])?;
+                let rhs = self.input(inputs[1])?;
+                let rank = self.converter.tensor_shape(lhs).len() as i64;
+                if rank < 2 || self.converter.tensor_shape(rhs).len() as i64 != rank {
+                    return Err(format!(
+                        "{}: batch matmul of operands of different ranks is not supported",
+                        node.name
+                    ));
+                }
+                let batch: Vec<i64> = (0..rank - 2).collect();
+                let lhs_contract = if flag("adj_x") { rank - 2 } else { rank - 1 };
+                let rhs_contract = if flag("adj_y") { rank - 1 } else { rank - 2 };
+                self.converter.new_dot_general_op(
+                    lhs,
+                    rhs,
+                    &batch,
+                    &batch,
+                    &[lhs_contract],
+                    &[rhs_contract],
+                    &[],
+                    &shape,
+                )
+            }
+            "BiasAdd" => {
+                arity(2)?;
+                let x = self.input(inputs[0])?;
+                let bias = self.input(inputs[1])?;
+                let channel_dim = match node.attr.get("data_format").and_then(|a| a["s"].as_str()) {
+                    // "NCHW", base64 encoded
+                    Some("TkNIVw==") => 1,
+                    _ => shape.len() as i64 - 1,
+                };
+                let bias = self
+                    .converter
+                    .new_broadcast_in_dim(bias, &[channel_dim], &shape);
+                self.converter.new_add_op(x, bias, &shape)
+            }
+            "Relu" => {
+                arity(1)?;
+                let x = self.input(inputs[0])?;
+                let zero = self.converter.new_splat_constant_op(0.0, &shape);
+                self.converter.new_max_op(x, zero, &shape)
+            }
+            "Concat" | "ConcatV2" => {
+                if inputs.len() < 2 {
+                    return Err(format!("{} ({}) has no values", node.name, node.op));
+                }
+                // The axis is the first input of Concat and the last of ConcatV2
+                let (axis, values) = if node.op == "Concat" {
+                    (inputs[0], &inputs[1..])
+                } else {
+                    (inputs[inputs.len() - 1], &inputs[..inputs.len() - 1])
+                };
+                let axis = *self
+                    .const_ints(axis)?
+                    .first()
+                    .ok_or_else(|| format!("{}: empty axis", node.name))?;
+                let axis = if axis < 0 {
+                    axis + shape.len() as i64
+                } else {
+                    axis
+                };
+                let mut tensors = Vec::with_capacity(values.len());
+                for value in values {
+                    tensors.push(self.input(value)?);
+                }
+                self.converter
+                    .new_concatenate_op(&tensors, axis as i32, &shape)
+            }
+            "Reshape" => {
+                arity(2)?;
+                let x = self.input(inputs[0])?;
+                self.converter.new_reshape_op(x, &shape)
+            }
+            "Transpose" => {
+                arity(2)?;
+                let x = self.input(inputs[0])?;
+                let perm = self.const_ints(inputs[1])?;
+                self.converter.new_transpose_op(x, &perm, &shape)
+            }
+            _ => self.blackbox(&inputs, &shapes)?,
+        };
+        Ok(tensor)
+    }
+
+    /// Consts holding a single value become splat constants, others are
+    /// blackboxed as their values aren't modeled
+    fn lower_const(&mut self, node: &NodeDef) -> Result<ffi::TensorHandle, String> {
+        let tensor = &attr(node, "value")["tensor"];
+        let shape = shape_from_proto(&tensor["tensorShape"]);
+        let splat = ["floatVal", "intVal"]
+            .iter()
+            .find_map(|key| match &tensor[*key] {
+                Value::Array(values) if values.len() == 1 => values[0].as_f64(),
+                _ => None,
+            });
+        match splat {
+            Some(value) if tensor["tensorContent"].is_null() => {
+                Ok(self.converter.new_splat_constant_op(value as f32, &shape))
+            }
+            _ => self.blackbox(&[], &[shape]),
+        }
+    }
+
+    fn blackbox(
+        &mut self,
+        inputs: &[&String],
+        shapes: &[Vec<i32>],
+    ) -> Result<ffi::TensorHandle, String> {
+        let mut tensors = Vec::with_capacity(inputs.len());
+        for input in inputs {
+            tensors.push(self.input(input)?);
+        }
+        let shapes: Vec<ffi::Shape> = shapes
+            .iter()
+            .map(|s| ffi::Shape {
+                shape: s.iter().map(|d| *d as i64).collect(),
+            })
+            .collect();
+        let cpp_num = self.num_blackboxes;
+        self.num_blackboxes += 1;
+        Ok(self.converter.new_blackbox_op(&tensors, cpp_num, &shapes))
+    }
+}
+
+/// Attribute `name` of `node`, null if not set
+fn attr<'a>(node: &'a NodeDef, name: &str) -> &'a Value {
+    node.attr.get(name).unwrap_or(&Value::Null)
+}
+
+/// Dimensions of a TensorShapeProto. Unknown sizes are -1, as in new_input
+fn shape_from_proto(shape: &Value) -> Vec<i32> {
+    match &shape["dim"] {
+        Value::Array(dims) => dims
+            .iter()
+            .map(|d| match &d["size"] {
+                // int64 sizes are strings in the JSON mapping
+                Value::String(s) => s.parse().unwrap_or(-1),
+                size => size.as_i64().unwrap_or(-1) as i32,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+fn output_shapes(node: &NodeDef) -> Result<Vec<Vec<i32>>, String> {
+    match &node.attr.get("_output_shapes").map(|a| &a["list"]["shape"]) {
+        Some(Value::Array(shapes)) if !shapes.is_empty() => {
+            Ok(shapes.iter().map(shape_from_proto).collect())
+        }
+        _ => Err(format!(
+            "{} has no _output_shapes, export the graph with add_shapes=True",
+            node.name
+        )),
+    }
+}
+
+fn decode_base64(s: &str) -> Result<Vec<u8>, String> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Ok(c - b'A'),
+        b'a'..=b'z' => Ok(c - b'a' + 26),
+        b'0'..=b'9' => Ok(c - b'0' + 52),
+        b'+' | b'-' => Ok(62),
+        b'/' | b'_' => Ok(63),
+        _ => Err(format!("Invalid base64 character {}", c as char)),
+    };
+    let mut bytes = Vec::with_capacity(s.len() * 3 / 4);
+    let (mut acc, mut bits) = (0u32, 0);
+    for c in s.bytes().filter(|c| *c != b'=') {
+        acc = (acc << 6) | value(c)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((acc >> bits) as u8);
+        }
+    }
+    Ok(bytes)
+}
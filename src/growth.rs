@@ -0,0 +1,51 @@
+use crate::model::*;
+use egg::*;
+
+/// Predict the e-graph size after the next iteration from the sizes observed
+/// before each iteration so far.
+///
+/// The growth of the next iteration is extrapolated from the last one, scaled
+/// by the ratio between the last two growths when growth is accelerating.
+/// Returns None if fewer than two sizes were observed.
+pub fn predict_next_size(sizes: &[usize]) -> Option<usize> {
+    let n = sizes.len();
+    if n < 2 {
+        return None;
+    }
+    let last_growth = sizes[n - 1].saturating_sub(sizes[n - 2]) as f64;
+    let ratio = if n >= 3 {
+        let prev_growth = sizes[n - 2].saturating_sub(sizes[n - 3]) as f64;
+        if prev_growth > 0.0 {
+            (last_growth / prev_growth).max(1.0)
+        } else {
+            1.0
+        }
+    } else {
+        1.0
+    };
+    Some(sizes[n - 1] + (last_growth * ratio) as usize)
+}
+
+/// Runner hook stopping saturation before an iteration that is predicted to
+/// exceed `node_limit`.
+///
+/// egg only checks the node limit after an iteration completes, so the last
+/// iteration often spends most of the time budget building nodes that are
+/// thrown away by the limit anyway.
+pub fn growth_hook(
+    node_limit: usize,
+) -> impl FnMut(&mut Runner<Mdl, TensorAnalysis, ()>) -> Result<(), String> {
+    let mut sizes = Vec::new();
+    move |runner| {
+        sizes.push(runner.egraph.total_size());
+        match predict_next_size(&sizes) {
+            Some(predicted) if predicted > node_limit => Err(format!(
+                "Predicted growth: {} nodes now, about {} after the next iteration (node limit {})",
+                sizes[sizes.len() - 1],
+                predicted,
+                node_limit
+            )),
+            _ => Ok(()),
+        }
+    }
+}
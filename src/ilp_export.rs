@@ -0,0 +1,294 @@
+use crate::model::*;
+use egg::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{prelude::*, BufWriter};
+
+/// The optional parts of the extraction ILP, as enabled by the flags of
+/// extractor/extract.py
+#[derive(Clone, Debug, Default)]
+pub struct LpOptions<'a> {
+    /// Ordering constraints, unless `--no_order`
+    pub order: bool,
+    /// Integer order variables (`--order_var_int`)
+    pub order_var_int: bool,
+    /// At most one node per eclass (`--eclass_constraint`)
+    pub eclass_constraint: bool,
+    /// Rematerialization variables and memory term (`--remat`)
+    pub remat: Option<LpRemat<'a>>,
+    /// Sharing bonuses (`--sharing`)
+    pub sharing: Option<LpSharing<'a>>,
+}
+
+/// The data of `--remat`: the bytes of the result of each node, the weight
+/// per byte, and the eclasses computed by a single node
+#[derive(Clone, Debug)]
+pub struct LpRemat<'a> {
+    pub mem_i: &'a [f64],
+    pub remat_weight: f64,
+    pub single_m: &'a [usize],
+}
+
+/// The data of `--sharing`: the eclasses of tensors, and the bonus per
+/// consumer beyond the first
+#[derive(Clone, Debug)]
+pub struct LpSharing<'a> {
+    pub tensor_m: &'a [usize],
+    pub sharing_bonus: f64,
+}
+
+/// Linear expression, as (coefficient, variable) terms
+type Terms = Vec<(f64, String)>;
+
+fn x_terms(nodes: &[usize], coefficient: f64) -> Terms {
+    nodes
+        .iter()
+        .map(|j| (coefficient, format!("x{}", j)))
+        .collect()
+}
+
+/// Write `terms`, one per line since LP readers limit the length of a line.
+/// Terms of the same variable are combined.
+fn write_terms(lp: &mut impl Write, terms: &[(f64, String)]) -> std::io::Result<()> {
+    let mut combined: Vec<(f64, &str)> = vec![];
+    let mut index: HashMap<&str, usize> = HashMap::new();
+    for (coefficient, var) in terms {
+        let var = var.as_str();
+        match index.get(var) {
+            Some(k) => combined[*k].0 += coefficient,
+            None => {
+                index.insert(var, combined.len());
+                combined.push((*coefficient, var));
+            }
+        }
+    }
+    // LP readers need at least one term
+    if combined.iter().all(|(coefficient, _)| *coefficient == 0.0) {
+        return writeln!(lp, "    0 x0");
+    }
+    let mut first = true;
+    for (coefficient, var) in combined {
+        if coefficient == 0.0 {
+            continue;
+        }
+        let sign = match (first, coefficient < 0.0) {
+            (_, true) => "- ",
+            (true, false) => "",
+            (false, false) => "+ ",
+        };
+        if coefficient.abs() == 1.0 {
+            writeln!(lp, "    {}{}", sign, var)?;
+        } else {
+            writeln!(lp, "    {}{} {}", sign, coefficient.abs(), var)?;
+        }
+        first = false;
+    }
+    Ok(())
+}
+
+fn write_row(
+    lp: &mut impl Write,
+    name: &str,
+    terms: &[(f64, String)],
+    sense: &str,
+    rhs: f64,
+) -> std::io::Result<()> {
+    writeln!(lp, "  {}:", name)?;
+    write_terms(lp, terms)?;
+    writeln!(lp, "    {} {}", sense, rhs)
+}
+
+/// Write the extraction ILP in CPLEX LP format
+///
+/// The formulation is the one extractor/extract.py solves with the flags
+/// `options` stands for: a binary variable x<i> per node i, minimizing the
+/// total cost of the picked nodes, such that one node of the root eclass is
+/// picked, every picked node has a picked node in each child eclass, and
+/// blacklisted nodes are not picked. With the options come the order
+/// variables t<m> per eclass m, the rematerialization variables
+/// z<i>_<m>_<j> (node i takes its child eclass m from node j), and their
+/// constraints and objective terms. Next to it, `<lp_file>.map` lists for
+/// every x and t variable the eclass and enode it stands for.
+///
+/// # Parameters
+///
+/// The ILP data as returned by `prep_ilp_data`, the optional parts of the ILP,
+/// and the file to write to.
+#[allow(clippy::too_many_arguments)]
+pub fn write_lp(
+    lp_file: &str,
+    m_id_map: &[Id],
+    e_m: &[Vec<usize>],
+    h_i: &[Vec<usize>],
+    cost_i: &[f64],
+    g_i: &[usize],
+    root_m: usize,
+    i_to_nodes: &[&Mdl],
+    blacklist_i: &[usize],
+    options: &LpOptions,
+) -> std::io::Result<()> {
+    let mut lp = BufWriter::new(File::create(lp_file)?);
+    let num_nodes = cost_i.len();
+    let num_classes = e_m.len();
+    // Child eclasses of each node without repeats, as used by the remat and
+    // sharing constraints
+    let children_i: Vec<Vec<usize>> = h_i
+        .iter()
+        .map(|children| {
+            let mut children = children.clone();
+            children.sort_unstable();
+            children.dedup();
+            children
+        })
+        .collect();
+    let mut parents_m: Vec<Vec<usize>> = vec![vec![]; num_classes];
+    for (i, children) in children_i.iter().enumerate() {
+        for m in children {
+            parents_m[*m].push(i);
+        }
+    }
+
+    let mut objective: Terms = cost_i
+        .iter()
+        .enumerate()
+        .map(|(i, cost)| (*cost, format!("x{}", i)))
+        .collect();
+    let mut z_vars: Vec<String> = vec![];
+    let mut rows: Vec<(String, Terms, &str, f64)> = vec![];
+
+    rows.push(("root".to_string(), x_terms(&e_m[root_m], 1.0), "=", 1.0));
+    if options.eclass_constraint || options.sharing.is_some() {
+        for (m, nodes) in e_m.iter().enumerate() {
+            rows.push((format!("eclass_{}", m), x_terms(nodes, 1.0), "<=", 1.0));
+        }
+    }
+    let (a, epsilon) = if options.order_var_int {
+        (num_classes as f64, 0.0)
+    } else {
+        (2.0, 1.0 / (10.0 * num_classes as f64))
+    };
+    for (i, children) in h_i.iter().enumerate() {
+        for (k, m) in children.iter().enumerate() {
+            let mut terms = x_terms(&e_m[*m], 1.0);
+            terms.push((-1.0, format!("x{}", i)));
+            rows.push((format!("child_{}_{}", i, k), terms, ">=", 0.0));
+            if options.order {
+                // t[g(i)] - t[m] + A (1 - x[i]) >= 1 (integer) or epsilon
+                let terms = vec![
+                    (1.0, format!("t{}", g_i[i])),
+                    (-1.0, format!("t{}", m)),
+                    (-a, format!("x{}", i)),
+                ];
+                let rhs = if options.order_var_int { 1.0 } else { epsilon };
+                rows.push((format!("order_{}_{}", i, k), terms, ">=", rhs - a));
+            }
+        }
+    }
+    for i in blacklist_i {
+        rows.push((format!("blacklist_{}", i), x_terms(&[*i], 1.0), "=", 0.0));
+    }
+
+    if let Some(remat) = &options.remat {
+        let mut consumers: Vec<Terms> = vec![vec![]; num_nodes];
+        for (i, children) in children_i.iter().enumerate() {
+            for m in children {
+                let mut served = vec![(-1.0, format!("x{}", i))];
+                for j in &e_m[*m] {
+                    let z = format!("z{}_{}_{}", i, m, j);
+                    rows.push((
+                        format!("remat_pick_{}_{}_{}", i, m, j),
+                        vec![(1.0, format!("x{}", j)), (-1.0, z.clone())],
+                        ">=",
+                        0.0,
+                    ));
+                    consumers[*j].push((1.0, z.clone()));
+                    served.push((1.0, z.clone()));
+                    z_vars.push(z);
+                }
+                rows.push((format!("remat_child_{}_{}", i, m), served, ">=", 0.0));
+            }
+        }
+        for (j, terms) in consumers.iter().enumerate() {
+            if g_i[j] == root_m {
+                continue;
+            }
+            let mut used = terms.clone();
+            used.push((-1.0, format!("x{}", j)));
+            rows.push((format!("remat_used_{}", j), used.clone(), ">=", 0.0));
+            if remat.mem_i[j] > 0.0 {
+                let weight = remat.remat_weight * remat.mem_i[j];
+                objective.extend(used.into_iter().map(|(c, var)| (weight * c, var)));
+            }
+        }
+        for m in remat.single_m {
+            rows.push((format!("single_{}", m), x_terms(&e_m[*m], 1.0), "<=", 1.0));
+        }
+    }
+
+    if let Some(sharing) = &options.sharing {
+        // Consumers of eclass m beyond the first, with at most one node per eclass
+        let uses = |m: usize| -> Terms {
+            let mut terms = x_terms(&parents_m[m], 1.0);
+            terms.extend(x_terms(&e_m[m], -1.0));
+            terms
+        };
+        for m in (0..num_classes).filter(|m| *m != root_m) {
+            rows.push((format!("consumed_{}", m), uses(m), ">=", 0.0));
+        }
+        for m in sharing.tensor_m {
+            let bonus = -sharing.sharing_bonus;
+            objective.extend(uses(*m).into_iter().map(|(c, var)| (bonus * c, var)));
+        }
+    }
+
+    writeln!(
+        lp,
+        "\\ Extraction ILP, see {}.map for the variables",
+        lp_file
+    )?;
+    writeln!(lp, "Minimize")?;
+    writeln!(lp, "  obj:")?;
+    write_terms(&mut lp, &objective)?;
+    writeln!(lp, "Subject To")?;
+    for (name, terms, sense, rhs) in &rows {
+        write_row(&mut lp, name, terms, sense, *rhs)?;
+    }
+    if options.order {
+        let max_t = if options.order_var_int {
+            num_classes.saturating_sub(1) as f64
+        } else {
+            1.0
+        };
+        writeln!(lp, "Bounds")?;
+        for m in 0..num_classes {
+            writeln!(lp, "  0 <= t{} <= {}", m, max_t)?;
+        }
+        if options.order_var_int {
+            writeln!(lp, "General")?;
+            for m in 0..num_classes {
+                writeln!(lp, "  t{}", m)?;
+            }
+        }
+    }
+    writeln!(lp, "Binary")?;
+    for i in 0..num_nodes {
+        writeln!(lp, "  x{}", i)?;
+    }
+    for z in &z_vars {
+        writeln!(lp, "  {}", z)?;
+    }
+    writeln!(lp, "End")?;
+    lp.flush()?;
+
+    let mut map = BufWriter::new(File::create(format!("{}.map", lp_file))?);
+    writeln!(map, "variable\teclass\tenode")?;
+    for (i, node) in i_to_nodes.iter().enumerate() {
+        writeln!(map, "x{}\t{}\t{}", i, m_id_map[g_i[i]], node.display_op())?;
+    }
+    if options.order {
+        for (m, id) in m_id_map.iter().enumerate() {
+            writeln!(map, "t{}\t{}\t", m, id)?;
+        }
+    }
+    map.flush()
+}
@@ -1,23 +1,64 @@
+use crate::budget::{region_share, TimeBudget};
+use crate::checkpoint::SaturationState;
+use crate::collective::{replica_groups, Reduction};
+use crate::composite::*;
+use crate::config::*;
+use crate::convolution::convolution_dims;
+use crate::diff::*;
+use crate::fft::FftType;
+use crate::fusion::*;
+use crate::growth::*;
+use crate::ilp_export::*;
+use crate::interpreter::*;
+use crate::layout::layout_rules;
+use crate::liveness::{check_schedule, memory_schedule};
+use crate::metrics::{self, names};
 use crate::model::*;
+use crate::naming::{named_dot, node_names};
 use crate::optimize::*;
+use crate::passes::*;
+use crate::precision::precision_rules;
+use crate::profile::*;
+use crate::provenance::*;
+use crate::region::cut_region;
+use crate::replay::*;
 use crate::rewrites::*;
+use crate::scheduler::*;
+use crate::session::*;
+use crate::sharding::*;
+use crate::trace::*;
+use crate::weights::weight_rules;
 use cxx::CxxVector;
 use egg::*;
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
+#[cfg(feature = "ilp-extractor")]
 use serde_json::json;
+use std::cell::RefCell;
 use std::fs::*;
+#[cfg(feature = "ilp-extractor")]
 use std::process::{Command, Stdio};
+use std::rc::Rc;
+use std::sync::Arc;
 use std::time::*;
-use std::{borrow::Borrow, collections::HashMap};
+use std::{
+    borrow::Borrow,
+    collections::{HashMap, HashSet, VecDeque},
+};
 
 #[cxx::bridge(namespace = "tensat")]
 pub mod ffi {
+    // The discriminants are also the output types of ConvertOp (a Num child)
+    #[derive(Debug, Hash)]
     enum Type {
         i32,
         f32,
+        i1,
+        bf16,
+        c64,
     }
 
+    #[derive(Hash)]
     enum Ops {
         Input,
         CompareOp,
@@ -29,6 +70,7 @@ pub mod ffi {
         SelectOp,
         ConcatenateOp,
         DotGeneralOp,
+        ConvolutionOp,
         PadOp,
         SliceOp,
         TransposeOp,
@@ -41,19 +83,190 @@ pub mod ffi {
         NegOp,
         TanhOp,
         ExpOp,
+        ErfOp,
+        GeluOp,
         IotaOp,
-        // ConstantOp,
+        ConstantOp,
+        DenseConstantOp,
         DynamicUpdateSliceOp,
         DynamicSliceOp,
         ScatterOp,
+        SelectAndScatterOp,
+        SortOp,
+        WhileOp,
+        IfOp,
+        CustomCallOp,
+        RngBitGeneratorOp,
+        RngOp,
+        ClampOp,
+        SqrtOp,
+        RsqrtOp,
+        LogOp,
+        SineOp,
+        CosineOp,
+        PowOp,
+        AbsOp,
+        SignOp,
+        FloorOp,
+        CeilOp,
+        RoundOp,
+        AndOp,
+        OrOp,
+        XorOp,
+        NotOp,
+        ShiftLeftOp,
+        ShiftRightLogicalOp,
+        ShiftRightArithmeticOp,
+        RemainderOp,
+        ComplexOp,
+        RealOp,
+        ImagOp,
+        FftOp,
+        CholeskyOp,
+        TriangularSolveOp,
+        BatchNormInferenceOp,
+        BatchNormTrainingOp,
+        BatchNormGradOp,
+        AllReduceOp,
+        AllGatherOp,
+        ReduceScatterOp,
+        AllToAllOp,
+        TupleOp,
+        GetTupleElementOp,
+        OptimizationBarrierOp,
         BlackBoxOp,
         ReturnOp,
+        // Only used by build_graph, to take one result of a multi-result op
+        Index,
     }
 
+    #[derive(Clone)]
     struct Node {
         name: String,
+        // Name of Var nodes, definition of CompositeOp nodes, and name of the
+        // tensors introduced by rewrites (see naming.rs), empty otherwise
         label: String,
         operands: Vec<i32>,
+        // Value of Num nodes, 0 for other nodes
+        value: i64,
+        // Id of the producer-consumer fusion group the node belongs to, -1 if none
+        fusion_group: i32,
+        // Names of the rules the node was derived with. Only filled in when
+        // provenance tracking is enabled
+        provenance: Vec<String>,
+        // Inferred shape of each result, empty for non-tensor nodes
+        shape: Vec<Shape>,
+        // Inferred element type of the results
+        dtype: Type,
+        // Id of the TensorHandle this node was created as, if it survived
+        // optimization unchanged, -1 otherwise
+        origin: i32,
+        // Block argument number of Input nodes and their Var, -1 for other nodes.
+        // Together with shape and dtype this replaces parsing the Var label
+        block_arg: i32,
+        // Why the node is in the optimized graph, to attach as an MLIR remark,
+        // e.g. "rewritten by rule12; estimated 1.3x faster". Only filled in when
+        // provenance tracking is enabled, empty for nodes of the input graph
+        remark: String,
+        // Sharding annotation (as given to set_sharding) of the tensor the node
+        // computes, empty if it has none
+        sharding: String,
+    }
+
+    // Tensor built by a CppGraphConverter. The converter keeps the record of
+    // the tensor (see CppGraphConverter::tensor), so it can grow without
+    // changing the bridge
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    struct TensorHandle {
+        // Index of the node computing the tensor in the graph, which is also
+        // its creation ordinal
+        id: i32,
+    }
+
+    // Description of one op for build_graph
+    struct OpDescriptor {
+        op: Ops,
+        // Tensor operands, as indices of earlier descriptors in the same batch
+        operands: Vec<i32>,
+        // Integer attributes (and Type arguments, as their discriminant), in the
+        // order of the new_* function arguments
+        int_args: Vec<i64>,
+        // Vector attributes, in the order of the new_* function arguments
+        vec_args: Vec<Shape>,
+        // Result shapes
+        shapes: Vec<Shape>,
+    }
+
+    // Graph returned by optimize. Nodes are topologically ordered: every operand
+    // index of a node is smaller than its own index
+    #[derive(Clone)]
+    struct Graph {
+        nodes: Vec<Node>,
+        // Index of the root node
+        root: i32,
+        // Indices of the values computed by the graph: the returned values if the
+        // root is a ReturnOp, the root itself otherwise
+        results: Vec<i32>,
+        // Indices of the op nodes (all but Num, Vec and Var) in the order to
+        // run them: each after the ops it uses, keeping the estimated peak
+        // memory of live tensors low
+        schedule: Vec<i32>,
+    }
+
+    // Returned by optimize_with_original
+    struct OptimizeResult {
+        optimized: Graph,
+        // Canonicalized input graph, which saturation started from
+        original: Graph,
+        // Estimated costs of the two graphs
+        original_cost: f64,
+        optimized_cost: f64,
+        // Block argument numbers of the inputs the optimized graph no longer
+        // uses, so the host can drop the parameters
+        unused_inputs: Vec<i32>,
+        // Ids of the TensorHandles of the constants the optimized graph no
+        // longer uses
+        unused_constants: Vec<i32>,
+    }
+
+    // Returned by extract_greedy
+    struct ExtractResult {
+        graph: Graph,
+        // Estimated cost of the graph
+        cost: f64,
+    }
+
+    // Returned by optimize_region
+    struct RegionResult {
+        // The optimized region. Its Input nodes stand for the tensors of
+        // boundary, by block argument number, and its results are the
+        // consumers, in order
+        graph: Graph,
+        // Ids of the TensorHandles the Inputs of graph stand for: the
+        // producers, then the other tensors the region uses from outside of it
+        boundary: Vec<i32>,
+    }
+
+    // Custom call registered with register_custom_call
+    #[derive(Clone, Debug)]
+    struct CustomCall {
+        // Name of the kernel, e.g. "cublas_gemm"
+        call_target: String,
+        // Opaque configuration of the kernel, passed through unchanged
+        backend_config: String,
+    }
+
+    // Statistics of the e-graph, see egraph_stats
+    #[derive(Clone, Debug)]
+    struct EGraphStats {
+        // Number of saturation iterations run when the statistics were taken
+        iteration: i32,
+        num_enodes: usize,
+        num_classes: usize,
+        avg_nodes_per_class: f32,
+        num_edges: usize,
+        // log2 of the number of programs the e-graph represents
+        num_programs: f32,
     }
 
     // CXX won't let me construct a Vec<Vec<i32>>, so we use Vec<ffi::Shape> instead
@@ -61,7 +274,7 @@ pub mod ffi {
     // to Vec<Shape> or similar. rust::Slice in CXX is quite error prone, because
     // a common pattern is to create a std::vector then create a slice out of it,
     // but the data is easily corrupted by the vector going out of scope.
-    #[derive(Debug)]
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
     struct Shape {
         shape: Vec<i64>,
     }
@@ -71,205 +284,587 @@ pub mod ffi {
         type Mdl;
         type CppGraphConverter;
         type TensorData;
-        type TensorInfo;
+        type OptimizerSession;
         fn new_converter() -> Box<CppGraphConverter>;
-        // Exposing the constructor functions with Box<TensorInfo>
+        fn metrics_prometheus_text() -> String;
+        fn new_session() -> Box<OptimizerSession>;
+        fn new_session_from_toml(path: &str) -> Result<Box<OptimizerSession>>;
+        fn optimize_graph(self: &mut OptimizerSession, converter: &mut CppGraphConverter) -> Graph;
+        fn build_graph(self: &mut CppGraphConverter, ops: &Vec<OpDescriptor>) -> Result<Vec<i32>>;
+        // Exposing the constructor functions with TensorHandles
         fn new_input(
             self: &mut CppGraphConverter,
             block_arg_number: i32,
             dims: &[i32],
-        ) -> Box<TensorInfo>;
-        fn new_index(
+            dtype: Type,
+        ) -> TensorHandle;
+        fn new_weight(
             self: &mut CppGraphConverter,
-            index: i32,
-            inpt: &TensorInfo,
-        ) -> Box<TensorInfo>;
+            block_arg_number: i32,
+            dims: &[i32],
+            dtype: Type,
+        ) -> TensorHandle;
+        fn new_index(self: &mut CppGraphConverter, index: i32, inpt: TensorHandle) -> TensorHandle;
         fn new_compare_op(
             self: &mut CppGraphConverter,
-            inpt_1: &TensorInfo,
-            inpt_2: &TensorInfo,
+            inpt_1: TensorHandle,
+            inpt_2: TensorHandle,
             comparison_direction: i32,
             comparison_type: i32,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_broadcast_in_dim(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
-            dimensions: &[i32],
+            inpt: TensorHandle,
+            dimensions: &[i64],
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_convert_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
+            inpt: TensorHandle,
             output_type: i32,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_reduce_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
-            dimensions: &[i32],
+            inputs: &[TensorHandle],
+            init_values: &[TensorHandle],
+            dimensions: &[i64],
+            body: i32,
             shapes: &Vec<Shape>,
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_reshape_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
+            inpt: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_gather_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
-            start_indices: &TensorInfo,
-            offset_dims: &[i32],
-            collapsed_slice_dims: &[i32],
-            operand_batching_dims: &[i32],
-            start_indices_batching_dims: &[i32],
-            start_index_map: &[i32],
+            inpt: TensorHandle,
+            start_indices: TensorHandle,
+            offset_dims: &[i64],
+            collapsed_slice_dims: &[i64],
+            operand_batching_dims: &[i64],
+            start_indices_batching_dims: &[i64],
+            start_index_map: &[i64],
             index_vector_dim: i32,
-            slice_sizes: &[i32],
+            slice_sizes: &[i64],
             indices_are_sorted: i32,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_select_op(
             self: &mut CppGraphConverter,
-            pred: &TensorInfo,
-            on_true: &TensorInfo,
-            on_false: &TensorInfo,
+            pred: TensorHandle,
+            on_true: TensorHandle,
+            on_false: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_concatenate_op(
             self: &mut CppGraphConverter,
-            inputs: &[*mut TensorInfo],
+            inputs: &[TensorHandle],
             dimension: i32,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_dot_general_op(
             self: &mut CppGraphConverter,
-            lhs: &TensorInfo,
-            rhs: &TensorInfo,
-            lhs_batching_dimensions: &[i32],
-            rhs_batching_dimensions: &[i32],
-            lhs_contracting_dimensions: &[i32],
-            rhs_contracting_dimensions: &[i32],
-            precision_config: &[i32],
+            lhs: TensorHandle,
+            rhs: TensorHandle,
+            lhs_batching_dimensions: &[i64],
+            rhs_batching_dimensions: &[i64],
+            lhs_contracting_dimensions: &[i64],
+            rhs_contracting_dimensions: &[i64],
+            precision_config: &[i64],
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
+        fn new_convolution_op(
+            self: &mut CppGraphConverter,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
+            window_strides: &[i64],
+            padding: &[i64],
+            lhs_dilation: &[i64],
+            rhs_dilation: &[i64],
+            dimension_numbers: &[i64],
+            feature_group_count: i32,
+            batch_group_count: i32,
+            precision_config: &[i64],
+            shape: &[i32],
+        ) -> TensorHandle;
         fn new_pad_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
-            padding_value: &TensorInfo,
-            edge_padding_low: &[i32],
-            edge_padding_high: &[i32],
-            interior_padding: &[i32],
+            inpt: TensorHandle,
+            padding_value: TensorHandle,
+            edge_padding_low: &[i64],
+            edge_padding_high: &[i64],
+            interior_padding: &[i64],
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_slice_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
-            start_indices: &[i32],
-            limit_indices: &[i32],
-            strides: &[i32],
+            inpt: TensorHandle,
+            start_indices: &[i64],
+            limit_indices: &[i64],
+            strides: &[i64],
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_transpose_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
-            permutation: &[i32],
+            inpt: TensorHandle,
+            permutation: &[i64],
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_mul_op(
             self: &mut CppGraphConverter,
-            lhs: &TensorInfo,
-            rhs: &TensorInfo,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_add_op(
             self: &mut CppGraphConverter,
-            lhs: &TensorInfo,
-            rhs: &TensorInfo,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_div_op(
             self: &mut CppGraphConverter,
-            lhs: &TensorInfo,
-            rhs: &TensorInfo,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_subtract_op(
             self: &mut CppGraphConverter,
-            lhs: &TensorInfo,
-            rhs: &TensorInfo,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_min_op(
             self: &mut CppGraphConverter,
-            lhs: &TensorInfo,
-            rhs: &TensorInfo,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_max_op(
             self: &mut CppGraphConverter,
-            lhs: &TensorInfo,
-            rhs: &TensorInfo,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_and_op(
+            self: &mut CppGraphConverter,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_or_op(
+            self: &mut CppGraphConverter,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_xor_op(
+            self: &mut CppGraphConverter,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_not_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_shift_left_op(
+            self: &mut CppGraphConverter,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
+        fn new_shift_right_logical_op(
+            self: &mut CppGraphConverter,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_shift_right_arithmetic_op(
+            self: &mut CppGraphConverter,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_clamp_op(
+            self: &mut CppGraphConverter,
+            min: TensorHandle,
+            operand: TensorHandle,
+            max: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_pow_op(
+            self: &mut CppGraphConverter,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_remainder_op(
+            self: &mut CppGraphConverter,
+            lhs: TensorHandle,
+            rhs: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
         fn new_neg_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
+            inpt: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_tanh_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
+            inpt: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_exp_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_sqrt_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_rsqrt_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_log_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_sine_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_cosine_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_abs_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_sign_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_floor_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_ceil_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_round_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_erf_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
+        fn new_complex_op(
+            self: &mut CppGraphConverter,
+            real: TensorHandle,
+            imag: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_real_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_imag_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_fft_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            fft_type: i32,
+            fft_length: &[i64],
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_cholesky_op(
+            self: &mut CppGraphConverter,
+            a: TensorHandle,
+            lower: bool,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_triangular_solve_op(
+            self: &mut CppGraphConverter,
+            a: TensorHandle,
+            b: TensorHandle,
+            left_side: bool,
+            lower: bool,
+            unit_diagonal: bool,
+            transpose_a: i32,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_batch_norm_inference_op(
+            self: &mut CppGraphConverter,
+            operand: TensorHandle,
+            scale: TensorHandle,
+            offset: TensorHandle,
+            mean: TensorHandle,
+            variance: TensorHandle,
+            epsilon: f32,
+            feature_index: i32,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_batch_norm_training_op(
+            self: &mut CppGraphConverter,
+            operand: TensorHandle,
+            scale: TensorHandle,
+            offset: TensorHandle,
+            epsilon: f32,
+            feature_index: i32,
+            shapes: &Vec<Shape>,
+        ) -> TensorHandle;
+        fn new_batch_norm_grad_op(
+            self: &mut CppGraphConverter,
+            operand: TensorHandle,
+            scale: TensorHandle,
+            mean: TensorHandle,
+            variance: TensorHandle,
+            grad_output: TensorHandle,
+            epsilon: f32,
+            feature_index: i32,
+            shapes: &Vec<Shape>,
+        ) -> TensorHandle;
+        fn new_all_reduce_op(
+            self: &mut CppGraphConverter,
+            operand: TensorHandle,
+            replica_groups: &[i64],
+            group_size: i32,
+            reduction: i32,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_all_gather_op(
+            self: &mut CppGraphConverter,
+            operand: TensorHandle,
+            all_gather_dim: i32,
+            replica_groups: &[i64],
+            group_size: i32,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_reduce_scatter_op(
+            self: &mut CppGraphConverter,
+            operand: TensorHandle,
+            scatter_dimension: i32,
+            replica_groups: &[i64],
+            group_size: i32,
+            reduction: i32,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_all_to_all_op(
+            self: &mut CppGraphConverter,
+            operand: TensorHandle,
+            split_dimension: i32,
+            concat_dimension: i32,
+            replica_groups: &[i64],
+            group_size: i32,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_gelu_op(
+            self: &mut CppGraphConverter,
+            inpt: TensorHandle,
+            approximate: i32,
+            shape: &[i32],
+        ) -> TensorHandle;
         fn new_iota_op(
             self: &mut CppGraphConverter,
             iota_dimension: i32,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
-        // fn new_constant_op(self: &mut CppGraphConverter, shape: &[i32]) -> Box<TensorInfo>;
+        ) -> TensorHandle;
+        fn new_splat_constant_op(
+            self: &mut CppGraphConverter,
+            value: f32,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn register_dense_constant(
+            self: &mut CppGraphConverter,
+            handle: i32,
+            dtype: Type,
+            data: &[u8],
+        ) -> Result<()>;
+        fn new_dense_constant_op(
+            self: &mut CppGraphConverter,
+            handle: i32,
+            shape: &[i32],
+        ) -> Result<TensorHandle>;
         fn new_dynamic_update_slice_op(
             self: &mut CppGraphConverter,
-            operand: &TensorInfo,
-            update: &TensorInfo,
-            start_indices: &TensorInfo,
+            operand: TensorHandle,
+            update: TensorHandle,
+            start_indices: TensorHandle,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_dynamic_slice_op(
             self: &mut CppGraphConverter,
-            operand: &TensorInfo,
-            start_indices: &TensorInfo,
+            operand: TensorHandle,
+            start_indices: TensorHandle,
             slice_sizes: i32,
             shape: &[i32],
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
         fn new_scatter_op(
             self: &mut CppGraphConverter,
-            inpt: &TensorInfo,
-            scatter_indices: &TensorInfo,
-            updates: &TensorInfo,
+            inpt: TensorHandle,
+            scatter_indices: TensorHandle,
+            updates: TensorHandle,
             dimension_numbers: i32,
             shapes: &Vec<Shape>,
-        ) -> Box<TensorInfo>;
+        ) -> TensorHandle;
+        fn new_select_and_scatter_op(
+            self: &mut CppGraphConverter,
+            operand: TensorHandle,
+            source: TensorHandle,
+            init_value: TensorHandle,
+            window_dimensions: &[i64],
+            window_strides: &[i64],
+            padding: &[i64],
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn begin_region(self: &mut CppGraphConverter);
+        fn end_region(self: &mut CppGraphConverter, results: &[TensorHandle]) -> i32;
+        fn region_graph(self: &CppGraphConverter, region: i32) -> Graph;
+        fn new_while_op(
+            self: &mut CppGraphConverter,
+            inputs: &[TensorHandle],
+            cond: i32,
+            body: i32,
+            shapes: &Vec<Shape>,
+        ) -> TensorHandle;
+        fn new_if_op(
+            self: &mut CppGraphConverter,
+            pred: TensorHandle,
+            inputs: &[TensorHandle],
+            true_branch: i32,
+            false_branch: i32,
+        ) -> TensorHandle;
+        fn register_custom_call(
+            self: &mut CppGraphConverter,
+            call_target: &str,
+            backend_config: &str,
+        ) -> i32;
+        fn custom_call(self: &CppGraphConverter, custom_call: i32) -> CustomCall;
+        fn new_custom_call_op(
+            self: &mut CppGraphConverter,
+            inputs: &[TensorHandle],
+            custom_call: i32,
+            dtype: Type,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_rng_bit_generator_op(
+            self: &mut CppGraphConverter,
+            initial_state: TensorHandle,
+            algorithm: i32,
+            dtype: Type,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_rng_op(
+            self: &mut CppGraphConverter,
+            a: TensorHandle,
+            b: TensorHandle,
+            rng_distribution: i32,
+            shape: &[i32],
+        ) -> TensorHandle;
+        fn new_sort_op(
+            self: &mut CppGraphConverter,
+            inputs: &[TensorHandle],
+            dimension: i32,
+            is_stable: bool,
+            comparison_direction: i32,
+            comparison_type: i32,
+            shapes: &Vec<Shape>,
+        ) -> TensorHandle;
+        fn new_tuple_op(self: &mut CppGraphConverter, inputs: &[TensorHandle]) -> TensorHandle;
+        fn new_get_tuple_element_op(
+            self: &mut CppGraphConverter,
+            tuple: TensorHandle,
+            index: i32,
+        ) -> TensorHandle;
+        fn new_optimization_barrier_op(
+            self: &mut CppGraphConverter,
+            inputs: &[TensorHandle],
+        ) -> TensorHandle;
         fn new_blackbox_op(
             self: &mut CppGraphConverter,
-            inpts: &[*mut TensorInfo],
+            inpts: &[TensorHandle],
             cpp_num: i32,
             shapes: &Vec<Shape>,
-        ) -> Box<TensorInfo>;
-        fn new_return_op(
+        ) -> TensorHandle;
+        fn new_return_op(self: &mut CppGraphConverter, inpts: &[TensorHandle]) -> TensorHandle;
+        fn register_composite_op(
+            self: &mut CppGraphConverter,
+            name: &str,
+            num_operands: i32,
+            expansion: &str,
+            cost: f32,
+        ) -> i32;
+        fn new_composite_op(
+            self: &mut CppGraphConverter,
+            inpts: &[TensorHandle],
+            composite_id: i32,
+            shapes: &Vec<Shape>,
+        ) -> Result<TensorHandle>;
+        fn set_diff_report(self: &mut CppGraphConverter, path: &str);
+        fn load_config_toml(self: &mut CppGraphConverter, path: &str) -> Result<()>;
+        fn set_sharding(self: &mut CppGraphConverter, tensor: TensorHandle, sharding: &str);
+        fn set_no_rewrite(self: &mut CppGraphConverter, tensor: TensorHandle);
+        fn tensor_shape(self: &CppGraphConverter, tensor: TensorHandle) -> Vec<i32>;
+        fn tensor_dtype(self: &CppGraphConverter, tensor: TensorHandle) -> Type;
+        fn set_track_provenance(self: &mut CppGraphConverter, enable: bool);
+        fn set_rule_trace(self: &mut CppGraphConverter, path: &str);
+        fn set_lp_file(self: &mut CppGraphConverter, path: &str);
+        fn set_resume_from(self: &mut CppGraphConverter, path: &str);
+        fn set_save_state(self: &mut CppGraphConverter, path: &str);
+        fn set_identity_mode(self: &mut CppGraphConverter, enable: bool);
+        fn set_predict_growth(self: &mut CppGraphConverter, enable: bool);
+        fn set_profile(self: &mut CppGraphConverter, path: &str, chrome_trace: bool);
+        fn set_numeric_check(self: &mut CppGraphConverter, enable: bool, tolerance: f32);
+        fn set_snapshots(
             self: &mut CppGraphConverter,
-            inpts: &[*mut TensorInfo],
-        ) -> Box<TensorInfo>;
-        fn optimize(self: &CppGraphConverter) -> Vec<Node>;
+            dir: &str,
+            every: i32,
+            max_count: i32,
+        ) -> Result<()>;
+        fn set_stats_every(self: &mut CppGraphConverter, every: i32);
+        fn egraph_stats(self: &CppGraphConverter) -> Vec<EGraphStats>;
+        fn optimize(self: &CppGraphConverter) -> Graph;
+        fn optimize_with_original(self: &CppGraphConverter) -> OptimizeResult;
+        fn extract_greedy(self: &CppGraphConverter) -> ExtractResult;
+        fn optimize_region(
+            self: &CppGraphConverter,
+            producers: &[TensorHandle],
+            consumers: &[TensorHandle],
+        ) -> RegionResult;
+        fn optimize_streaming(self: &mut CppGraphConverter) -> Graph;
+        fn next_nodes(self: &mut CppGraphConverter, batch_size: i32) -> Vec<Node>;
+        fn verify_by_replay(self: &CppGraphConverter) -> i32;
+        fn check_determinism(self: &CppGraphConverter) -> bool;
         fn print_rec_expr(self: &CppGraphConverter);
         fn pretty_print_rec_expr(self: &CppGraphConverter, width: i32);
     }
 
+    #[cfg(not(feature = "no-cxx-costmodel"))]
     unsafe extern "C++" {
         fn get_cost(
             op: Ops,
@@ -280,6 +875,7 @@ pub mod ffi {
         ) -> u64;
     }
 
+    #[cfg(not(feature = "no-cxx-costmodel"))]
     unsafe extern "C++" {
         include!("EqualitySaturation.h");
 
@@ -293,6 +889,14 @@ pub mod ffi {
     }
 }
 
+/// Custom rules that only canonicalize the graph
+const CHEAP_CUSTOM_RULES: [&str; 4] = [
+    "transpose-of-transpose",
+    "flatten-concat",
+    "merge-slices",
+    "slice-of-pad",
+];
+
 /// Struct for converting a model specified using our Rust interface to RecExpr
 ///
 /// The RecExpr is growed on the fly when member functions are called. Uses a
@@ -301,30 +905,139 @@ pub mod ffi {
 #[derive(Default)]
 pub struct CppGraphConverter {
     rec_expr: RecExpr<Mdl>,
-    scalar_map: HashMap<i32, Id>,
+    scalar_map: HashMap<i64, Id>,
     name_gen: NameGen,
-    blackbox_cpp_num_to_tensorinfo: HashMap<i32, TensorInfo>,
+    blackbox_cpp_num_to_tensorinfo: HashMap<i64, TensorInfo>,
+    /// Record of each tensor built so far, by the node computing it. The
+    /// TensorHandles handed out refer to these
+    tensors: HashMap<Id, TensorInfo>,
+    composite_ops: Vec<CompositeOpDef>,
+    config: OptimizeConfig,
+    /// Optimized nodes not yet fetched with next_nodes
+    pending_nodes: VecDeque<ffi::Node>,
+    /// Sharding annotations of the nodes of rec_expr, see sharding.rs
+    shardings: HashMap<Id, String>,
+    /// Nodes of rec_expr whose ops optimize keeps, see set_no_rewrite
+    protected: HashSet<Id>,
+    /// ReturnOp declaring the results of the graph, see new_return_op
+    returned: Option<Id>,
+    /// Statistics of the e-graph of the last optimize, see egraph_stats
+    stats: RefCell<Vec<ffi::EGraphStats>>,
+    /// E-graph saturated by the last optimize (or extract_greedy) and its root,
+    /// for extract_greedy to reuse
+    saturated: RefCell<Option<(EGraph<Mdl, TensorAnalysis>, Id)>>,
+    /// State shared with the other graphs of the OptimizerSession optimizing
+    /// this one, if any
+    session: Option<SharedState>,
+    /// Regions (conditions and bodies of WhileOps, branches of IfOps, bodies
+    /// of ReduceOps) built so far, by index. Each is a graph of its own, see begin_region
+    regions: Vec<RecExpr<Mdl>>,
+    /// Metadata of the values each region yields, by index
+    region_results: Vec<Vec<TensorData>>,
+    /// Values of the DenseConstantOps registered so far, by handle
+    dense_constants: HashMap<i64, DenseConstant>,
+    /// Custom calls registered so far, indexed by the second child of
+    /// CustomCallOp nodes
+    custom_calls: Vec<ffi::CustomCall>,
+    /// Number of RngOps built so far, each the index of the next one's site
+    rng_sites: i32,
+    /// Regions adding two scalars, the bodies of the sums of new_reduce_sum,
+    /// by the number of their dtype
+    sum_regions: HashMap<i64, i32>,
+    /// Graphs the regions being built are nested in, innermost last
+    outer_graphs: Vec<OuterGraph>,
+    /// Regions optimized by the last optimize, by index
+    optimized_regions: RefCell<Vec<ffi::Graph>>,
+}
+
+/// Graph set aside by begin_region while one of its regions is built
+struct OuterGraph {
+    rec_expr: RecExpr<Mdl>,
+    scalar_map: HashMap<i64, Id>,
+    tensors: HashMap<Id, TensorInfo>,
 }
 
 pub fn new_converter() -> Box<CppGraphConverter> {
     Box::new(CppGraphConverter::default())
 }
 
+/// Metrics of the optimize calls so far, see metrics::prometheus_text
+pub fn metrics_prometheus_text() -> String {
+    metrics::prometheus_text()
+}
+
 /// The APIs of GraphConverter are (intended to) match TASO's so that we can easily
 /// construct TASO graphs using this class
 impl CppGraphConverter {
+    /// Converter for an already built graph, e.g. one read from a file
+    pub fn from_rec_expr(rec_expr: RecExpr<Mdl>) -> Self {
+        CppGraphConverter {
+            rec_expr,
+            ..Default::default()
+        }
+    }
+
     pub fn rec_expr(self) -> RecExpr<Mdl> {
         self.rec_expr
     }
 
-    fn vec_node(&mut self, seq: &[i32]) -> Id {
+    /// The graph built so far
+    pub fn rec_expr_ref(&self) -> &RecExpr<Mdl> {
+        &self.rec_expr
+    }
+
+    pub fn config(&self) -> &OptimizeConfig {
+        &self.config
+    }
+
+    pub fn config_mut(&mut self) -> &mut OptimizeConfig {
+        &mut self.config
+    }
+
+    /// Record of the tensor `tensor` refers to
+    pub fn tensor(&self, tensor: ffi::TensorHandle) -> &TensorInfo {
+        self.tensors
+            .get(&Id::from(tensor.id as usize))
+            .unwrap_or_else(|| panic!("Unknown tensor handle {}", tensor.id))
+    }
+
+    /// Dimensions of (the first result of) `tensor`
+    pub fn tensor_shape(&self, tensor: ffi::TensorHandle) -> Vec<i32> {
+        let data = &self.tensor(tensor).tensor_data;
+        match (data.shapes.first(), data.n_dims.first()) {
+            (Some(shape), Some(n_dims)) => shape[..*n_dims].to_vec(),
+            _ => vec![],
+        }
+    }
+
+    /// Element type of `tensor`
+    pub fn tensor_dtype(&self, tensor: ffi::TensorHandle) -> ffi::Type {
+        self.tensor(tensor).tensor_data.dtype
+    }
+
+    /// Keep the record of a tensor just built, and hand out its handle
+    fn intern(&mut self, tensor: TensorInfo) -> ffi::TensorHandle {
+        let handle = ffi::TensorHandle {
+            id: tensor.tensor_id(),
+        };
+        self.tensors.insert(tensor.id, tensor);
+        handle
+    }
+
+    fn vec_node(&mut self, seq: &[i64]) -> Id {
         let vec: Vec<Id> = seq.iter().map(|n| self.add_or_get_val(*n)).collect();
         let node = Mdl::Vec(vec);
         let id = self.rec_expr.add(node);
         id
     }
 
-    fn add_or_get_val(&mut self, val: i32) -> Id {
+    /// Vec of the dims of `shape`, e.g. the output shape of a ReshapeOp
+    fn shape_node(&mut self, shape: &[i32]) -> Id {
+        let dims: Vec<i64> = shape.iter().map(|d| i64::from(*d)).collect();
+        self.vec_node(&dims)
+    }
+
+    fn add_or_get_val(&mut self, val: i64) -> Id {
         match self.scalar_map.get(&val) {
             Some(id) => *id,
             None => {
@@ -361,13 +1074,44 @@ impl CppGraphConverter {
     }
 
     // Wrapper functions for C++ side
-    pub fn new_input(&mut self, block_arg_number: i32, shape: &[i32]) -> Box<TensorInfo> {
-        // Check if the shape array is empty and replace it with a shape of 0 if needed
-        let shape = if shape.is_empty() { &[0] } else { shape };
-        let name = format!("input_{}", block_arg_number) + "@" + &shape.iter().join("_");
-        let node = Mdl::Var(Symbol::from(name));
+    /// Input of the graph with elements of type `dtype`. An empty shape is a
+    /// rank-0 tensor, and -1 marks a dimension whose size is unknown. Cost
+    /// estimates count unknown dimensions as 1, and the interpreter refuses to
+    /// evaluate them.
+    pub fn new_input(
+        &mut self,
+        block_arg_number: i32,
+        shape: &[i32],
+        dtype: ffi::Type,
+    ) -> ffi::TensorHandle {
+        let name = format!("input_{}", block_arg_number);
+        self.add_input(&name, block_arg_number, shape, dtype, false)
+    }
+
+    /// Input of the graph holding a weight, i.e. a tensor known at compile
+    /// time. Rewrites of `weights::weight_rules` only apply to ops on weights,
+    /// and ops on weights alone are folded at compile time.
+    pub fn new_weight(
+        &mut self,
+        block_arg_number: i32,
+        shape: &[i32],
+        dtype: ffi::Type,
+    ) -> ffi::TensorHandle {
+        let name = self.name_gen.new_weight_name();
+        self.add_input(&name, block_arg_number, shape, dtype, true)
+    }
+
+    fn add_input(
+        &mut self,
+        name: &str,
+        block_arg_number: i32,
+        shape: &[i32],
+        dtype: ffi::Type,
+        constant: bool,
+    ) -> ffi::TensorHandle {
+        let node = Mdl::Var(Symbol::from(input_var_name(name, shape, dtype)));
         let name_id = self.rec_expr.add(node);
-        let block_arg_node_id = self.add_or_get_val(block_arg_number);
+        let block_arg_node_id = self.add_or_get_val(block_arg_number.into());
         let new_node = Mdl::Input([name_id, block_arg_node_id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
@@ -376,13 +1120,16 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype,
+                constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
-    pub fn new_index(&mut self, index: i32, inpt: &TensorInfo) -> Box<TensorInfo> {
-        let index_num_node = self.add_or_get_val(index);
+    pub fn new_index(&mut self, index: i32, inpt: ffi::TensorHandle) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let index_num_node = self.add_or_get_val(index.into());
         let new_node = Mdl::Index([index_num_node, inpt.id]);
         let res = TensorInfo {
             id: self.rec_expr.add(new_node),
@@ -390,21 +1137,25 @@ impl CppGraphConverter {
                 shapes: vec![inpt.tensor_data.shapes[index as usize]],
                 n_dims: vec![inpt.tensor_data.n_dims[index as usize]],
                 name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_compare_op(
         &mut self,
-        inpt_1: &TensorInfo,
-        inpt_2: &TensorInfo,
+        inpt_1: ffi::TensorHandle,
+        inpt_2: ffi::TensorHandle,
         comparison_direction: i32,
         comparison_type: i32,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
-        let comparison_direction_node = self.add_or_get_val(comparison_direction);
-        let comparison_type_node = self.add_or_get_val(comparison_type);
+    ) -> ffi::TensorHandle {
+        let inpt_1 = self.tensor(inpt_1).clone();
+        let inpt_2 = self.tensor(inpt_2).clone();
+        let comparison_direction_node = self.add_or_get_val(comparison_direction.into());
+        let comparison_type_node = self.add_or_get_val(comparison_type.into());
         let new_node = Mdl::CompareOp([
             inpt_1.id,
             inpt_2.id,
@@ -418,17 +1169,20 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: ffi::Type::i1,
+                constant: inpt_1.tensor_data.constant && inpt_2.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_broadcast_in_dim(
         &mut self,
-        inpt: &TensorInfo,
-        dimensions: &[i32],
+        inpt: ffi::TensorHandle,
+        dimensions: &[i64],
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
         let dimensions_id = self.vec_node(dimensions);
         let new_node = Mdl::BroadcastInDimOp([inpt.id, dimensions_id]);
 
@@ -439,18 +1193,21 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_convert_op(
         &mut self,
-        inpt: &TensorInfo,
+        inpt: ffi::TensorHandle,
         output_type: i32,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
-        let output_type_node = self.add_or_get_val(output_type);
+    ) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let output_type_node = self.add_or_get_val(output_type.into());
         let new_node = Mdl::ConvertOp([inpt.id, output_type_node]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
@@ -459,19 +1216,48 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: dtype_from_num(output_type.into()),
+                constant: inpt.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
+    /// stablehlo.reduce of `inputs` over `dimensions`, starting from one init
+    /// value per input. `body` is the index of the region combining them (see
+    /// begin_region), taking the accumulators then the elements as block
+    /// arguments. The results are the reduced inputs, read with Index.
     pub fn new_reduce_op(
         &mut self,
-        inpt: &TensorInfo,
-        dimensions: &[i32],
+        inputs: &[ffi::TensorHandle],
+        init_values: &[ffi::TensorHandle],
+        dimensions: &[i64],
+        body: i32,
         shapes: &Vec<ffi::Shape>,
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        if inputs.is_empty() || inputs.len() != init_values.len() {
+            panic!(
+                "Invalid ReduceOp: {} inputs with {} init values",
+                inputs.len(),
+                init_values.len()
+            );
+        }
+        if body < 0 || body as usize >= self.regions.len() {
+            panic!("Unknown region {} in ReduceOp", body);
+        }
+        let tensor_infos: Vec<TensorInfo> =
+            inputs.iter().map(|t| self.tensor(*t).clone()).collect();
+        let init_infos: Vec<TensorInfo> = init_values
+            .iter()
+            .map(|t| self.tensor(*t).clone())
+            .collect();
+        let inputs_node = Mdl::Vec(tensor_infos.iter().map(|i| i.id).collect());
+        let inputs_id = self.rec_expr.add(inputs_node);
+        let init_values_node = Mdl::Vec(init_infos.iter().map(|i| i.id).collect());
+        let init_values_id = self.rec_expr.add(init_values_node);
         let dimensions_id = self.vec_node(dimensions);
-        let new_node = Mdl::ReduceOp([inpt.id, dimensions_id]);
+        let body_id = self.add_or_get_val(body.into());
+        let new_node = Mdl::ReduceOp([inputs_id, init_values_id, dimensions_id, body_id]);
         let (shapes, n_dims) = self.shape_from_dim(shapes);
         let res = TensorInfo {
             id: self.rec_expr.add(new_node),
@@ -479,13 +1265,49 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: tensor_infos[0].tensor_data.dtype,
+                constant: tensor_infos
+                    .iter()
+                    .chain(init_infos.iter())
+                    .all(|i| i.tensor_data.constant),
             },
         };
-        Box::new(res)
+        self.intern(res)
+    }
+
+    /// Sum of `inpt` over `dimensions`: a ReduceOp from 0 whose body adds
+    pub fn new_reduce_sum(
+        &mut self,
+        inpt: ffi::TensorHandle,
+        dimensions: &[i64],
+        shapes: &Vec<ffi::Shape>,
+    ) -> ffi::TensorHandle {
+        let dtype = self.tensor_dtype(inpt);
+        let body = self.sum_region(dtype);
+        let mut zero = self.new_splat_constant_op(0.0, &[]);
+        if dtype != ffi::Type::f32 {
+            zero = self.new_convert_op(zero, dtype_to_num(dtype) as i32, &[]);
+        }
+        self.new_reduce_op(&[inpt], &[zero], dimensions, body, shapes)
     }
 
-    pub fn new_reshape_op(&mut self, inpt: &TensorInfo, shape: &[i32]) -> Box<TensorInfo> {
-        let shape_id = self.vec_node(shape);
+    /// Index of the region adding two scalars of `dtype`, built on first use
+    fn sum_region(&mut self, dtype: ffi::Type) -> i32 {
+        if let Some(region) = self.sum_regions.get(&dtype_to_num(dtype)) {
+            return *region;
+        }
+        self.begin_region();
+        let accumulator = self.new_input(0, &[], dtype);
+        let element = self.new_input(1, &[], dtype);
+        let sum = self.new_add_op(accumulator, element, &[]);
+        let region = self.end_region(&[sum]);
+        self.sum_regions.insert(dtype_to_num(dtype), region);
+        region
+    }
+
+    pub fn new_reshape_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let shape_id = self.shape_node(shape);
         let new_node = Mdl::ReshapeOp([inpt.id, shape_id]);
         let (shapes_new, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
@@ -494,33 +1316,37 @@ impl CppGraphConverter {
                 shapes: shapes_new,
                 n_dims,
                 name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     fn new_gather_op(
         self: &mut CppGraphConverter,
-        inpt: &TensorInfo,
-        start_indices: &TensorInfo,
-        offset_dims: &[i32],
-        collapsed_slice_dims: &[i32],
-        operand_batching_dims: &[i32],
-        start_indices_batching_dims: &[i32],
-        start_index_map: &[i32],
+        inpt: ffi::TensorHandle,
+        start_indices: ffi::TensorHandle,
+        offset_dims: &[i64],
+        collapsed_slice_dims: &[i64],
+        operand_batching_dims: &[i64],
+        start_indices_batching_dims: &[i64],
+        start_index_map: &[i64],
         index_vector_dim: i32,
-        slice_sizes: &[i32],
+        slice_sizes: &[i64],
         indices_are_sorted: i32,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let start_indices = self.tensor(start_indices).clone();
         let offset_dims_id = self.vec_node(offset_dims);
         let collapsed_slice_dims_id = self.vec_node(collapsed_slice_dims);
         let operand_batching_dims_id = self.vec_node(operand_batching_dims);
         let start_indices_batching_dims_id = self.vec_node(start_indices_batching_dims);
         let start_index_map_id = self.vec_node(start_index_map);
         let slice_sizes_id = self.vec_node(slice_sizes);
-        let index_vector_dim_id = self.add_or_get_val(index_vector_dim);
-        let indices_are_sorted_id = self.add_or_get_val(indices_are_sorted);
+        let index_vector_dim_id = self.add_or_get_val(index_vector_dim.into());
+        let indices_are_sorted_id = self.add_or_get_val(indices_are_sorted.into());
 
         let new_node = Mdl::GatherOp([
             inpt.id,
@@ -542,18 +1368,23 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_select_op(
         &mut self,
-        pred: &TensorInfo,
-        on_true: &TensorInfo,
-        on_false: &TensorInfo,
+        pred: ffi::TensorHandle,
+        on_true: ffi::TensorHandle,
+        on_false: ffi::TensorHandle,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let pred = self.tensor(pred).clone();
+        let on_true = self.tensor(on_true).clone();
+        let on_false = self.tensor(on_false).clone();
         let new_node = Mdl::SelectOp([pred.id, on_true.id, on_false.id]);
 
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
@@ -563,21 +1394,26 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: on_true.tensor_data.dtype,
+                constant: pred.tensor_data.constant
+                    && on_true.tensor_data.constant
+                    && on_false.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_concatenate_op(
         &mut self,
-        inputs: &[*mut TensorInfo],
+        inputs: &[ffi::TensorHandle],
         dimension: i32,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
-        let tensor_infos: Vec<&TensorInfo> = inputs.iter().map(|&ptr| unsafe { &*ptr }).collect();
+    ) -> ffi::TensorHandle {
+        let tensor_infos: Vec<TensorInfo> =
+            inputs.iter().map(|t| self.tensor(*t).clone()).collect();
         let inputs_node = Mdl::Vec(tensor_infos.iter().map(|i| i.id).collect());
         let inputs_id = self.rec_expr.add(inputs_node);
-        let dimension_id = self.add_or_get_val(dimension);
+        let dimension_id = self.add_or_get_val(dimension.into());
         let new_node = Mdl::ConcatenateOp([inputs_id, dimension_id]);
 
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
@@ -587,22 +1423,28 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: tensor_infos
+                    .first()
+                    .map_or(ffi::Type::f32, |i| i.tensor_data.dtype),
+                constant: tensor_infos.iter().all(|i| i.tensor_data.constant),
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_dot_general_op(
         self: &mut CppGraphConverter,
-        lhs: &TensorInfo,
-        rhs: &TensorInfo,
-        lhs_batching_dimensions: &[i32],
-        rhs_batching_dimensions: &[i32],
-        lhs_contracting_dimensions: &[i32],
-        rhs_contracting_dimensions: &[i32],
-        precision_config: &[i32],
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
+        lhs_batching_dimensions: &[i64],
+        rhs_batching_dimensions: &[i64],
+        lhs_contracting_dimensions: &[i64],
+        rhs_contracting_dimensions: &[i64],
+        precision_config: &[i64],
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let lhs = self.tensor(lhs).clone();
+        let rhs = self.tensor(rhs).clone();
         // This produces ugly empty nodes when there's no batch dimension
         let lhs_batch_dim_name_id = self.vec_node(lhs_batching_dimensions);
         let rhs_batch_dim_name_id = self.vec_node(rhs_batching_dimensions);
@@ -627,20 +1469,105 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: lhs.tensor_data.dtype,
+                constant: lhs.tensor_data.constant && rhs.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.convolution, with the padding and dimension numbers flattened
+    /// as described in convolution.rs
+    pub fn new_convolution_op(
+        self: &mut CppGraphConverter,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
+        window_strides: &[i64],
+        padding: &[i64],
+        lhs_dilation: &[i64],
+        rhs_dilation: &[i64],
+        dimension_numbers: &[i64],
+        feature_group_count: i32,
+        batch_group_count: i32,
+        precision_config: &[i64],
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let lhs = self.tensor(lhs).clone();
+        let rhs = self.tensor(rhs).clone();
+        let dims = |t: &TensorInfo| -> Vec<i64> {
+            t.tensor_data.shapes[0][..t.tensor_data.n_dims[0]]
+                .iter()
+                .map(|d| i64::from(*d))
+                .collect()
+        };
+        if let Err(e) = convolution_dims(
+            &dims(&lhs),
+            &dims(&rhs),
+            window_strides,
+            padding,
+            lhs_dilation,
+            rhs_dilation,
+            dimension_numbers,
+            i64::from(feature_group_count),
+            i64::from(batch_group_count),
+        ) {
+            panic!("Invalid ConvolutionOp: {}", e);
+        }
+        let window_strides_id = self.vec_node(window_strides);
+        let padding_id = self.vec_node(padding);
+        let lhs_dilation_id = self.vec_node(lhs_dilation);
+        let rhs_dilation_id = self.vec_node(rhs_dilation);
+        let dimension_numbers_id = self.vec_node(dimension_numbers);
+        let feature_group_count_id = self.add_or_get_val(feature_group_count.into());
+        let batch_group_count_id = self.add_or_get_val(batch_group_count.into());
+        let precision_config_id = self.vec_node(precision_config);
+
+        let new_node = Mdl::ConvolutionOp([
+            lhs.id,
+            rhs.id,
+            window_strides_id,
+            padding_id,
+            lhs_dilation_id,
+            rhs_dilation_id,
+            dimension_numbers_id,
+            feature_group_count_id,
+            batch_group_count_id,
+            precision_config_id,
+        ]);
+
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: lhs.tensor_data.dtype,
+                constant: lhs.tensor_data.constant && rhs.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_pad_op(
         self: &mut CppGraphConverter,
-        inpt: &TensorInfo,
-        padding_value: &TensorInfo,
-        edge_padding_low: &[i32],
-        edge_padding_high: &[i32],
-        interior_padding: &[i32],
+        inpt: ffi::TensorHandle,
+        padding_value: ffi::TensorHandle,
+        edge_padding_low: &[i64],
+        edge_padding_high: &[i64],
+        interior_padding: &[i64],
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let padding_value = self.tensor(padding_value).clone();
+        let dims: Vec<i64> = inpt.tensor_data.shapes[0][..inpt.tensor_data.n_dims[0]]
+            .iter()
+            .map(|d| i64::from(*d))
+            .collect();
+        if let Err(e) = padded_dims(&dims, edge_padding_low, edge_padding_high, interior_padding) {
+            panic!("Invalid PadOp: {}", e);
+        }
         let edge_padding_low_id = self.vec_node(edge_padding_low);
         let edge_padding_high_id = self.vec_node(edge_padding_high);
         let interior_padding_id = self.vec_node(interior_padding);
@@ -661,19 +1588,22 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant && padding_value.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_slice_op(
         &mut self,
-        inpt: &TensorInfo,
-        start_indices: &[i32],
-        limit_indices: &[i32],
-        strides: &[i32],
+        inpt: ffi::TensorHandle,
+        start_indices: &[i64],
+        limit_indices: &[i64],
+        strides: &[i64],
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
         let start_indices_id = self.vec_node(start_indices);
         let limit_indices_id = self.vec_node(limit_indices);
         let strides_id = self.vec_node(strides);
@@ -685,17 +1615,20 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_transpose_op(
         &mut self,
-        inpt: &TensorInfo,
-        permutation: &[i32],
+        inpt: ffi::TensorHandle,
+        permutation: &[i64],
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
         let permutation_id = self.vec_node(permutation);
         let new_node = Mdl::TransposeOp([inpt.id, permutation_id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
@@ -705,17 +1638,21 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_mul_op(
         &mut self,
-        lhs: &TensorInfo,
-        rhs: &TensorInfo,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let lhs = self.tensor(lhs).clone();
+        let rhs = self.tensor(rhs).clone();
         let new_node = Mdl::MulOp([lhs.id, rhs.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
@@ -724,17 +1661,21 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: lhs.tensor_data.dtype,
+                constant: lhs.tensor_data.constant && rhs.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_add_op(
         &mut self,
-        lhs: &TensorInfo,
-        rhs: &TensorInfo,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let lhs = self.tensor(lhs).clone();
+        let rhs = self.tensor(rhs).clone();
         let new_node = Mdl::AddOp([lhs.id, rhs.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
@@ -743,17 +1684,21 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: lhs.tensor_data.dtype,
+                constant: lhs.tensor_data.constant && rhs.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_div_op(
         &mut self,
-        lhs: &TensorInfo,
-        rhs: &TensorInfo,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let lhs = self.tensor(lhs).clone();
+        let rhs = self.tensor(rhs).clone();
         let new_node = Mdl::DivOp([lhs.id, rhs.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
@@ -762,17 +1707,21 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: lhs.tensor_data.dtype,
+                constant: lhs.tensor_data.constant && rhs.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_subtract_op(
         &mut self,
-        lhs: &TensorInfo,
-        rhs: &TensorInfo,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let lhs = self.tensor(lhs).clone();
+        let rhs = self.tensor(rhs).clone();
         let new_node = Mdl::SubtractOp([lhs.id, rhs.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
@@ -781,17 +1730,21 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: lhs.tensor_data.dtype,
+                constant: lhs.tensor_data.constant && rhs.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_min_op(
         &mut self,
-        lhs: &TensorInfo,
-        rhs: &TensorInfo,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let lhs = self.tensor(lhs).clone();
+        let rhs = self.tensor(rhs).clone();
         let new_node = Mdl::MinOp([lhs.id, rhs.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
@@ -800,17 +1753,21 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: lhs.tensor_data.dtype,
+                constant: lhs.tensor_data.constant && rhs.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
     pub fn new_max_op(
         &mut self,
-        lhs: &TensorInfo,
-        rhs: &TensorInfo,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
         shape: &[i32],
-    ) -> Box<TensorInfo> {
+    ) -> ffi::TensorHandle {
+        let lhs = self.tensor(lhs).clone();
+        let rhs = self.tensor(rhs).clone();
         let new_node = Mdl::MaxOp([lhs.id, rhs.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
@@ -819,13 +1776,23 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: lhs.tensor_data.dtype,
+                constant: lhs.tensor_data.constant && rhs.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
-    pub fn new_neg_op(&mut self, inpt: &TensorInfo, shape: &[i32]) -> Box<TensorInfo> {
-        let new_node = Mdl::NegOp([inpt.id]);
+    /// stablehlo.power of `lhs` to the elementwise exponent `rhs`
+    pub fn new_pow_op(
+        &mut self,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let lhs = self.tensor(lhs).clone();
+        let rhs = self.tensor(rhs).clone();
+        let new_node = Mdl::PowOp([lhs.id, rhs.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
             id: self.rec_expr.add(new_node),
@@ -833,13 +1800,23 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: lhs.tensor_data.dtype,
+                constant: lhs.tensor_data.constant && rhs.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
-    pub fn new_tanh_op(&mut self, inpt: &TensorInfo, shape: &[i32]) -> Box<TensorInfo> {
-        let new_node = Mdl::TanhOp([inpt.id]);
+    /// stablehlo.remainder of `lhs` divided by `rhs`, with the sign of `lhs`
+    pub fn new_remainder_op(
+        &mut self,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let lhs = self.tensor(lhs).clone();
+        let rhs = self.tensor(rhs).clone();
+        let new_node = Mdl::RemainderOp([lhs.id, rhs.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
             id: self.rec_expr.add(new_node),
@@ -847,29 +1824,118 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: lhs.tensor_data.dtype,
+                constant: lhs.tensor_data.constant && rhs.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
-    pub fn new_exp_op(&mut self, inpt: &TensorInfo, shape: &[i32]) -> Box<TensorInfo> {
-        let new_node = Mdl::ExpOp([inpt.id]);
+    /// Adds the bitwise or shift op `node` on `operands`, whose element type
+    /// it must take (see check_operand_dtype)
+    fn new_bitwise_op(
+        &mut self,
+        node: Mdl,
+        operands: &[ffi::TensorHandle],
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let operands: Vec<TensorInfo> = operands.iter().map(|t| self.tensor(*t).clone()).collect();
+        let dtype = operands[0].tensor_data.dtype;
+        if let Err(e) = check_operand_dtype(&node, dtype) {
+            panic!("Invalid {}: {}", node, e);
+        }
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
-            id: self.rec_expr.add(new_node),
+            id: self.rec_expr.add(node),
             tensor_data: TensorData {
                 shapes,
                 n_dims,
                 name: None,
+                dtype,
+                constant: operands.iter().all(|t| t.tensor_data.constant),
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
-    pub fn new_iota_op(&mut self, iota_dimension: i32, shape: &[i32]) -> Box<TensorInfo> {
-        let iota_dim_id = self.add_or_get_val(iota_dimension);
-        let shape_id = self.vec_node(shape);
-        let new_node = Mdl::IotaOp([iota_dim_id, shape_id]);
+    pub fn new_and_op(
+        &mut self,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let node = Mdl::AndOp([self.tensor(lhs).id, self.tensor(rhs).id]);
+        self.new_bitwise_op(node, &[lhs, rhs], shape)
+    }
+
+    pub fn new_or_op(
+        &mut self,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let node = Mdl::OrOp([self.tensor(lhs).id, self.tensor(rhs).id]);
+        self.new_bitwise_op(node, &[lhs, rhs], shape)
+    }
+
+    pub fn new_xor_op(
+        &mut self,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let node = Mdl::XorOp([self.tensor(lhs).id, self.tensor(rhs).id]);
+        self.new_bitwise_op(node, &[lhs, rhs], shape)
+    }
+
+    pub fn new_not_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let node = Mdl::NotOp([self.tensor(inpt).id]);
+        self.new_bitwise_op(node, &[inpt], shape)
+    }
+
+    pub fn new_shift_left_op(
+        &mut self,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let node = Mdl::ShiftLeftOp([self.tensor(lhs).id, self.tensor(rhs).id]);
+        self.new_bitwise_op(node, &[lhs, rhs], shape)
+    }
+
+    pub fn new_shift_right_logical_op(
+        &mut self,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let node = Mdl::ShiftRightLogicalOp([self.tensor(lhs).id, self.tensor(rhs).id]);
+        self.new_bitwise_op(node, &[lhs, rhs], shape)
+    }
+
+    pub fn new_shift_right_arithmetic_op(
+        &mut self,
+        lhs: ffi::TensorHandle,
+        rhs: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let node = Mdl::ShiftRightArithmeticOp([self.tensor(lhs).id, self.tensor(rhs).id]);
+        self.new_bitwise_op(node, &[lhs, rhs], shape)
+    }
+
+    /// stablehlo.clamp of `operand` between `min` and `max`, which are scalars
+    /// or of the shape of `operand`
+    pub fn new_clamp_op(
+        &mut self,
+        min: ffi::TensorHandle,
+        operand: ffi::TensorHandle,
+        max: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let min = self.tensor(min).clone();
+        let operand = self.tensor(operand).clone();
+        let max = self.tensor(max).clone();
+        let new_node = Mdl::ClampOp([min.id, operand.id, max.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
             id: self.rec_expr.add(new_node),
@@ -877,19 +1943,18 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: operand.tensor_data.dtype,
+                constant: min.tensor_data.constant
+                    && operand.tensor_data.constant
+                    && max.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
-    pub fn new_dynamic_update_slice_op(
-        &mut self,
-        operand: &TensorInfo,
-        update: &TensorInfo,
-        start_indices: &TensorInfo,
-        shape: &[i32],
-    ) -> Box<TensorInfo> {
-        let new_node = Mdl::DynamicUpdateSliceOp([operand.id, update.id, start_indices.id]);
+    pub fn new_neg_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::NegOp([inpt.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
             id: self.rec_expr.add(new_node),
@@ -897,21 +1962,16 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
-    pub fn new_dynamic_slice_op(
-        &mut self,
-        operand: &TensorInfo,
-        start_indices: &TensorInfo,
-        slice_sizes: i32,
-        shape: &[i32],
-    ) -> Box<TensorInfo> {
-        let slice_sizes_id = self.add_or_get_val(slice_sizes);
-        let new_node = Mdl::DynamicSliceOp([operand.id, start_indices.id, slice_sizes_id]);
-
+    pub fn new_tanh_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::TanhOp([inpt.id]);
         let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
         let res = TensorInfo {
             id: self.rec_expr.add(new_node),
@@ -919,21 +1979,797 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
             },
         };
-        Box::new(res)
+        self.intern(res)
     }
 
-    pub fn new_scatter_op(
-        &mut self,
-        inpt: &TensorInfo,
-        scatter_indices: &TensorInfo,
-        updates: &TensorInfo,
-        dimension_numbers: i32,
-        shapes: &Vec<ffi::Shape>,
-    ) -> Box<TensorInfo> {
-        let dimension_numbers_id = self.add_or_get_val(dimension_numbers);
-        let new_node = Mdl::ScatterOp([
+    pub fn new_exp_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::ExpOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_sqrt_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::SqrtOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_rsqrt_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::RsqrtOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_log_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::LogOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_sine_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::SineOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_cosine_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::CosineOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_abs_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::AbsOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_sign_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::SignOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_floor_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::FloorOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_ceil_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::CeilOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.round_nearest_afz: rounds to the nearest integer, halfway
+    /// cases away from zero
+    pub fn new_round_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::RoundOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_erf_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::ErfOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.complex of the f32 tensors `real` and `imag`
+    pub fn new_complex_op(
+        &mut self,
+        real: ffi::TensorHandle,
+        imag: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let real = self.tensor(real).clone();
+        let imag = self.tensor(imag).clone();
+        let new_node = Mdl::ComplexOp([real.id, imag.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: ffi::Type::c64,
+                constant: real.tensor_data.constant && imag.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// Real part of the c64 tensor `inpt`, as f32
+    pub fn new_real_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::RealOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: ffi::Type::f32,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// Imaginary part of the c64 tensor `inpt`, as f32
+    pub fn new_imag_op(&mut self, inpt: ffi::TensorHandle, shape: &[i32]) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let new_node = Mdl::ImagOp([inpt.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: ffi::Type::f32,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.fft of `inpt` over its last `fft_length.len()` dimensions.
+    /// `fft_type` is a FftType discriminant (0 for FFT, 1 for IFFT, 2 for RFFT
+    /// and 3 for IRFFT), which also decides the element type of the result.
+    pub fn new_fft_op(
+        &mut self,
+        inpt: ffi::TensorHandle,
+        fft_type: i32,
+        fft_length: &[i64],
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let dtype = match FftType::from_num(fft_type.into()) {
+            Ok(fft_type) => fft_type.result_dtype(),
+            Err(e) => panic!("Invalid FftOp: {}", e),
+        };
+        let inpt = self.tensor(inpt).clone();
+        let fft_type_id = self.add_or_get_val(fft_type.into());
+        let fft_length_id = self.vec_node(fft_length);
+        let new_node = Mdl::FftOp([inpt.id, fft_type_id, fft_length_id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.cholesky of the (batched) square matrices `a`, giving the
+    /// lower triangular factor if `lower`, else the upper one
+    pub fn new_cholesky_op(
+        &mut self,
+        a: ffi::TensorHandle,
+        lower: bool,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let a = self.tensor(a).clone();
+        let lower_id = self.add_or_get_val(i64::from(lower));
+        let new_node = Mdl::CholeskyOp([a.id, lower_id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: a.tensor_data.dtype,
+                constant: a.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.triangular_solve of `op(a) x = b` (or `x op(a) = b` unless
+    /// `left_side`), with `a` triangular. `transpose_a` is a stablehlo
+    /// Transpose discriminant: 1 for NO_TRANSPOSE, 2 for TRANSPOSE and 3 for
+    /// ADJOINT. The result has the shape of `b`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_triangular_solve_op(
+        &mut self,
+        a: ffi::TensorHandle,
+        b: ffi::TensorHandle,
+        left_side: bool,
+        lower: bool,
+        unit_diagonal: bool,
+        transpose_a: i32,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        if !(1..=3).contains(&transpose_a) {
+            panic!("Invalid TriangularSolveOp: transpose_a {}", transpose_a);
+        }
+        let a = self.tensor(a).clone();
+        let b = self.tensor(b).clone();
+        let left_side_id = self.add_or_get_val(i64::from(left_side));
+        let lower_id = self.add_or_get_val(i64::from(lower));
+        let unit_diagonal_id = self.add_or_get_val(i64::from(unit_diagonal));
+        let transpose_a_id = self.add_or_get_val(transpose_a.into());
+        let new_node = Mdl::TriangularSolveOp([
+            a.id,
+            b.id,
+            left_side_id,
+            lower_id,
+            unit_diagonal_id,
+            transpose_a_id,
+        ]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: b.tensor_data.dtype,
+                constant: a.tensor_data.constant && b.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// Adds the batch norm `node` of `operands` (the tensor children of
+    /// `node`, the operand first), with results of `shapes`
+    fn new_batch_norm_op(
+        &mut self,
+        node: Mdl,
+        operands: &[TensorInfo],
+        shapes: &Vec<ffi::Shape>,
+    ) -> ffi::TensorHandle {
+        let (shapes, n_dims) = self.shape_from_dim(shapes);
+        let res = TensorInfo {
+            id: self.rec_expr.add(node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: operands[0].tensor_data.dtype,
+                constant: operands.iter().all(|o| o.tensor_data.constant),
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.batch_norm_inference: `operand` normalized with the given
+    /// `mean` and `variance` of each feature (along `feature_index`), then
+    /// scaled and offset
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_batch_norm_inference_op(
+        &mut self,
+        operand: ffi::TensorHandle,
+        scale: ffi::TensorHandle,
+        offset: ffi::TensorHandle,
+        mean: ffi::TensorHandle,
+        variance: ffi::TensorHandle,
+        epsilon: f32,
+        feature_index: i32,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let operands: Vec<TensorInfo> = [operand, scale, offset, mean, variance]
+            .iter()
+            .map(|t| self.tensor(*t).clone())
+            .collect();
+        let epsilon_id = self.add_or_get_val(i64::from(epsilon.to_bits() as i32));
+        let feature_index_id = self.add_or_get_val(feature_index.into());
+        let new_node = Mdl::BatchNormInferenceOp([
+            operands[0].id,
+            operands[1].id,
+            operands[2].id,
+            operands[3].id,
+            operands[4].id,
+            epsilon_id,
+            feature_index_id,
+        ]);
+        self.new_batch_norm_op(new_node, &operands, &self.single_shape_vec(shape))
+    }
+
+    /// stablehlo.batch_norm_training: `operand` normalized with the mean and
+    /// variance of each feature over the other dimensions, then scaled and
+    /// offset. The results are the output, the means and the variances.
+    pub fn new_batch_norm_training_op(
+        &mut self,
+        operand: ffi::TensorHandle,
+        scale: ffi::TensorHandle,
+        offset: ffi::TensorHandle,
+        epsilon: f32,
+        feature_index: i32,
+        shapes: &Vec<ffi::Shape>,
+    ) -> ffi::TensorHandle {
+        let operands: Vec<TensorInfo> = [operand, scale, offset]
+            .iter()
+            .map(|t| self.tensor(*t).clone())
+            .collect();
+        let epsilon_id = self.add_or_get_val(i64::from(epsilon.to_bits() as i32));
+        let feature_index_id = self.add_or_get_val(feature_index.into());
+        let new_node = Mdl::BatchNormTrainingOp([
+            operands[0].id,
+            operands[1].id,
+            operands[2].id,
+            epsilon_id,
+            feature_index_id,
+        ]);
+        self.new_batch_norm_op(new_node, &operands, shapes)
+    }
+
+    /// stablehlo.batch_norm_grad: gradients of a batch_norm_training of
+    /// `operand` with the means `mean` and variances `variance`, given the
+    /// gradient `grad_output` of its output. The results are the gradients of
+    /// the operand, the scale and the offset.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_batch_norm_grad_op(
+        &mut self,
+        operand: ffi::TensorHandle,
+        scale: ffi::TensorHandle,
+        mean: ffi::TensorHandle,
+        variance: ffi::TensorHandle,
+        grad_output: ffi::TensorHandle,
+        epsilon: f32,
+        feature_index: i32,
+        shapes: &Vec<ffi::Shape>,
+    ) -> ffi::TensorHandle {
+        let operands: Vec<TensorInfo> = [operand, scale, mean, variance, grad_output]
+            .iter()
+            .map(|t| self.tensor(*t).clone())
+            .collect();
+        let epsilon_id = self.add_or_get_val(i64::from(epsilon.to_bits() as i32));
+        let feature_index_id = self.add_or_get_val(feature_index.into());
+        let new_node = Mdl::BatchNormGradOp([
+            operands[0].id,
+            operands[1].id,
+            operands[2].id,
+            operands[3].id,
+            operands[4].id,
+            epsilon_id,
+            feature_index_id,
+        ]);
+        self.new_batch_norm_op(new_node, &operands, shapes)
+    }
+
+    /// Nodes of the replica groups of the collective op `op`, panicking
+    /// unless they split into groups of `group_size`
+    fn replica_groups_nodes(&mut self, op: &str, groups: &[i64], group_size: i32) -> (Id, Id) {
+        if let Err(e) = replica_groups(groups, group_size.into()) {
+            panic!("Invalid {}: {}", op, e);
+        }
+        (
+            self.vec_node(groups),
+            self.add_or_get_val(group_size.into()),
+        )
+    }
+
+    fn reduction_node(&mut self, op: &str, reduction: i32) -> Id {
+        if let Err(e) = Reduction::from_num(reduction.into()) {
+            panic!("Invalid {}: {}", op, e);
+        }
+        self.add_or_get_val(reduction.into())
+    }
+
+    /// Collective op `node` of `operand`, with the result `shape`
+    fn new_collective_op(
+        &mut self,
+        node: Mdl,
+        operand: TensorInfo,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: operand.tensor_data.dtype,
+                constant: false,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.all_reduce of `operand` within each of the `replica_groups`
+    /// (flattened, see collective.rs), combining the values with `reduction`
+    pub fn new_all_reduce_op(
+        &mut self,
+        operand: ffi::TensorHandle,
+        replica_groups: &[i64],
+        group_size: i32,
+        reduction: i32,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let operand = self.tensor(operand).clone();
+        let (groups_id, group_size_id) =
+            self.replica_groups_nodes("AllReduceOp", replica_groups, group_size);
+        let reduction_id = self.reduction_node("AllReduceOp", reduction);
+        let new_node = Mdl::AllReduceOp([operand.id, groups_id, group_size_id, reduction_id]);
+        self.new_collective_op(new_node, operand, shape)
+    }
+
+    /// stablehlo.all_gather of `operand` along `all_gather_dim` within each of
+    /// the `replica_groups`
+    pub fn new_all_gather_op(
+        &mut self,
+        operand: ffi::TensorHandle,
+        all_gather_dim: i32,
+        replica_groups: &[i64],
+        group_size: i32,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let operand = self.tensor(operand).clone();
+        let dim_id = self.add_or_get_val(all_gather_dim.into());
+        let (groups_id, group_size_id) =
+            self.replica_groups_nodes("AllGatherOp", replica_groups, group_size);
+        let new_node = Mdl::AllGatherOp([operand.id, dim_id, groups_id, group_size_id]);
+        self.new_collective_op(new_node, operand, shape)
+    }
+
+    /// stablehlo.reduce_scatter of `operand` within each of the
+    /// `replica_groups`: the values are combined with `reduction`, and each
+    /// replica keeps its part along `scatter_dimension`
+    pub fn new_reduce_scatter_op(
+        &mut self,
+        operand: ffi::TensorHandle,
+        scatter_dimension: i32,
+        replica_groups: &[i64],
+        group_size: i32,
+        reduction: i32,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let operand = self.tensor(operand).clone();
+        let dim_id = self.add_or_get_val(scatter_dimension.into());
+        let (groups_id, group_size_id) =
+            self.replica_groups_nodes("ReduceScatterOp", replica_groups, group_size);
+        let reduction_id = self.reduction_node("ReduceScatterOp", reduction);
+        let new_node =
+            Mdl::ReduceScatterOp([operand.id, dim_id, groups_id, group_size_id, reduction_id]);
+        self.new_collective_op(new_node, operand, shape)
+    }
+
+    /// stablehlo.all_to_all of `operand` within each of the `replica_groups`,
+    /// with as many parts as replicas in a group
+    pub fn new_all_to_all_op(
+        &mut self,
+        operand: ffi::TensorHandle,
+        split_dimension: i32,
+        concat_dimension: i32,
+        replica_groups: &[i64],
+        group_size: i32,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let operand = self.tensor(operand).clone();
+        let split_id = self.add_or_get_val(split_dimension.into());
+        let concat_id = self.add_or_get_val(concat_dimension.into());
+        let (groups_id, group_size_id) =
+            self.replica_groups_nodes("AllToAllOp", replica_groups, group_size);
+        let new_node = Mdl::AllToAllOp([operand.id, split_id, concat_id, groups_id, group_size_id]);
+        self.new_collective_op(new_node, operand, shape)
+    }
+
+    pub fn new_gelu_op(
+        &mut self,
+        inpt: ffi::TensorHandle,
+        approximate: i32,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let approximate_id = self.add_or_get_val(approximate.into());
+        let new_node = Mdl::GeluOp([inpt.id, approximate_id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_iota_op(&mut self, iota_dimension: i32, shape: &[i32]) -> ffi::TensorHandle {
+        let iota_dim_id = self.add_or_get_val(iota_dimension.into());
+        let shape_id = self.shape_node(shape);
+        let new_node = Mdl::IotaOp([iota_dim_id, shape_id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: ffi::Type::f32,
+                constant: true,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// Constant tensor with every element equal to `value`. The value is stored
+    /// as the bits of the f32 so it fits in a Num node.
+    pub fn new_splat_constant_op(&mut self, value: f32, shape: &[i32]) -> ffi::TensorHandle {
+        let value_id = self.add_or_get_val(i64::from(value.to_bits() as i32));
+        let shape_id = self.shape_node(shape);
+        let new_node = Mdl::ConstantOp([value_id, shape_id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: ffi::Type::f32,
+                constant: true,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// Make `data` (row-major `dtype` values, in their in-memory layout) the
+    /// values of the DenseConstantOps with the given handle. The C++ side
+    /// keeps the data and gets the handle back in the optimized graph; the
+    /// copy here is for the rewrites depending on constant values
+    pub fn register_dense_constant(
+        &mut self,
+        handle: i32,
+        dtype: ffi::Type,
+        data: &[u8],
+    ) -> Result<(), String> {
+        let constant = DenseConstant::from_bytes(dtype, data)
+            .map_err(|e| format!("Invalid dense constant {}: {}", handle, e))?;
+        self.dense_constants.insert(handle.into(), constant);
+        Ok(())
+    }
+
+    /// Constant tensor with the values registered under `handle` (see
+    /// register_dense_constant), of their element type
+    pub fn new_dense_constant_op(
+        &mut self,
+        handle: i32,
+        shape: &[i32],
+    ) -> Result<ffi::TensorHandle, String> {
+        let constant = self
+            .dense_constants
+            .get(&handle.into())
+            .ok_or_else(|| format!("Invalid DenseConstantOp: unknown handle {}", handle))?;
+        let (num_values, dtype) = (constant.values.len(), constant.dtype);
+        let num_elements: i64 = shape.iter().map(|d| *d as i64).product();
+        if shape.iter().any(|d| *d < 0) || num_elements != num_values as i64 {
+            return Err(format!(
+                "Invalid DenseConstantOp: {} values for shape {:?}",
+                num_values, shape
+            ));
+        }
+        let handle_id = self.add_or_get_val(handle.into());
+        let shape_id = self.shape_node(shape);
+        let dtype_id = self.add_or_get_val(dtype_to_num(dtype));
+        let new_node = Mdl::DenseConstantOp([handle_id, shape_id, dtype_id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype,
+                constant: true,
+            },
+        };
+        Ok(self.intern(res))
+    }
+
+    pub fn new_dynamic_update_slice_op(
+        &mut self,
+        operand: ffi::TensorHandle,
+        update: ffi::TensorHandle,
+        start_indices: ffi::TensorHandle,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let operand = self.tensor(operand).clone();
+        let update = self.tensor(update).clone();
+        let start_indices = self.tensor(start_indices).clone();
+        let new_node = Mdl::DynamicUpdateSliceOp([operand.id, update.id, start_indices.id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: operand.tensor_data.dtype,
+                constant: operand.tensor_data.constant
+                    && update.tensor_data.constant
+                    && start_indices.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_dynamic_slice_op(
+        &mut self,
+        operand: ffi::TensorHandle,
+        start_indices: ffi::TensorHandle,
+        slice_sizes: i32,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let operand = self.tensor(operand).clone();
+        let start_indices = self.tensor(start_indices).clone();
+        let slice_sizes_id = self.add_or_get_val(slice_sizes.into());
+        let new_node = Mdl::DynamicSliceOp([operand.id, start_indices.id, slice_sizes_id]);
+
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: operand.tensor_data.dtype,
+                constant: operand.tensor_data.constant && start_indices.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_scatter_op(
+        &mut self,
+        inpt: ffi::TensorHandle,
+        scatter_indices: ffi::TensorHandle,
+        updates: ffi::TensorHandle,
+        dimension_numbers: i32,
+        shapes: &Vec<ffi::Shape>,
+    ) -> ffi::TensorHandle {
+        let inpt = self.tensor(inpt).clone();
+        let scatter_indices = self.tensor(scatter_indices).clone();
+        let updates = self.tensor(updates).clone();
+        let dimension_numbers_id = self.add_or_get_val(dimension_numbers.into());
+        let new_node = Mdl::ScatterOp([
             inpt.id,
             scatter_indices.id,
             updates.id,
@@ -946,197 +2782,1471 @@ impl CppGraphConverter {
                 shapes,
                 n_dims,
                 name: None,
+                dtype: inpt.tensor_data.dtype,
+                constant: inpt.tensor_data.constant
+                    && scatter_indices.tensor_data.constant
+                    && updates.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.select_and_scatter, with the padding flattened as the low
+    /// and high padding of each dimension in turn. The select and scatter
+    /// regions aren't represented, as for the update region of ScatterOp.
+    pub fn new_select_and_scatter_op(
+        &mut self,
+        operand: ffi::TensorHandle,
+        source: ffi::TensorHandle,
+        init_value: ffi::TensorHandle,
+        window_dimensions: &[i64],
+        window_strides: &[i64],
+        padding: &[i64],
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let operand = self.tensor(operand).clone();
+        let source = self.tensor(source).clone();
+        let init_value = self.tensor(init_value).clone();
+        let window_dimensions_id = self.vec_node(window_dimensions);
+        let window_strides_id = self.vec_node(window_strides);
+        let padding_id = self.vec_node(padding);
+        let new_node = Mdl::SelectAndScatterOp([
+            operand.id,
+            source.id,
+            init_value.id,
+            window_dimensions_id,
+            window_strides_id,
+            padding_id,
+        ]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: operand.tensor_data.dtype,
+                constant: operand.tensor_data.constant
+                    && source.tensor_data.constant
+                    && init_value.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.sort of `inputs` along `dimension`, whose comparator compares
+    /// the elements of the first input with `comparison_direction` (as in
+    /// CompareOp). Sorts with other comparators stay blackboxes. The results
+    /// are the sorted inputs, read with Index.
+    pub fn new_sort_op(
+        &mut self,
+        inputs: &[ffi::TensorHandle],
+        dimension: i32,
+        is_stable: bool,
+        comparison_direction: i32,
+        comparison_type: i32,
+        shapes: &Vec<ffi::Shape>,
+    ) -> ffi::TensorHandle {
+        let tensor_infos: Vec<TensorInfo> =
+            inputs.iter().map(|t| self.tensor(*t).clone()).collect();
+        let inputs_node = Mdl::Vec(tensor_infos.iter().map(|i| i.id).collect());
+        let inputs_id = self.rec_expr.add(inputs_node);
+        let dimension_id = self.add_or_get_val(dimension.into());
+        let is_stable_id = self.add_or_get_val(i64::from(is_stable));
+        let comparison_direction_id = self.add_or_get_val(comparison_direction.into());
+        let comparison_type_id = self.add_or_get_val(comparison_type.into());
+        let new_node = Mdl::SortOp([
+            inputs_id,
+            dimension_id,
+            is_stable_id,
+            comparison_direction_id,
+            comparison_type_id,
+        ]);
+        let (shapes, n_dims) = self.shape_from_dim(shapes);
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: tensor_infos
+                    .first()
+                    .map_or(ffi::Type::f32, |i| i.tensor_data.dtype),
+                constant: tensor_infos.iter().all(|i| i.tensor_data.constant),
+            },
+        };
+        self.intern(res)
+    }
+
+    /// Start building a region, e.g. the body of a loop. Until the matching
+    /// end_region, tensors are built in a graph of their own, whose Inputs (see
+    /// new_input) are the block arguments of the region. Handles of tensors of
+    /// the enclosing graph can't be used meanwhile, and the handles of the
+    /// region's tensors are only valid until end_region. Regions may nest.
+    pub fn begin_region(&mut self) {
+        let outer = OuterGraph {
+            rec_expr: std::mem::take(&mut self.rec_expr),
+            scalar_map: std::mem::take(&mut self.scalar_map),
+            tensors: std::mem::take(&mut self.tensors),
+        };
+        self.outer_graphs.push(outer);
+    }
+
+    /// Finish the region started by the last begin_region, yielding `results`,
+    /// and return its index for new_while_op, new_if_op or new_reduce_op
+    pub fn end_region(&mut self, results: &[ffi::TensorHandle]) -> i32 {
+        let yielded = results
+            .iter()
+            .map(|t| {
+                let data = &self.tensor(*t).tensor_data;
+                TensorData {
+                    shapes: vec![data.shapes[0]],
+                    n_dims: vec![data.n_dims[0]],
+                    name: None,
+                    dtype: data.dtype,
+                    constant: data.constant,
+                }
+            })
+            .collect();
+        self.region_results.push(yielded);
+        self.new_return_op(results);
+        let outer = self
+            .outer_graphs
+            .pop()
+            .expect("end_region without a matching begin_region");
+        self.scalar_map = outer.scalar_map;
+        self.tensors = outer.tensors;
+        let region = std::mem::replace(&mut self.rec_expr, outer.rec_expr);
+        self.regions.push(region);
+        self.regions.len() as i32 - 1
+    }
+
+    /// stablehlo.while over the loop-carried values `inputs`. `cond` and
+    /// `body` are the indices of its regions, both taking the loop-carried
+    /// values as block arguments. The results are the final values, read with
+    /// Index.
+    pub fn new_while_op(
+        &mut self,
+        inputs: &[ffi::TensorHandle],
+        cond: i32,
+        body: i32,
+        shapes: &Vec<ffi::Shape>,
+    ) -> ffi::TensorHandle {
+        for region in [cond, body] {
+            if region < 0 || region as usize >= self.regions.len() {
+                panic!("Unknown region {} in WhileOp", region);
+            }
+        }
+        let tensor_infos: Vec<TensorInfo> =
+            inputs.iter().map(|t| self.tensor(*t).clone()).collect();
+        let inputs_node = Mdl::Vec(tensor_infos.iter().map(|i| i.id).collect());
+        let inputs_id = self.rec_expr.add(inputs_node);
+        let cond_id = self.add_or_get_val(cond.into());
+        let body_id = self.add_or_get_val(body.into());
+        let new_node = Mdl::WhileOp([inputs_id, cond_id, body_id]);
+        let (shapes, n_dims) = self.shape_from_dim(shapes);
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: tensor_infos
+                    .first()
+                    .map_or(ffi::Type::f32, |i| i.tensor_data.dtype),
+                constant: false,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.if choosing between the regions `true_branch` and
+    /// `false_branch` on the i1 `pred`. Both branches take `inputs`, the values
+    /// of this graph they use, as block arguments. The results are the values
+    /// the chosen branch yields, read with Index. Their shapes unify those of
+    /// both branches, see unify_branch_results
+    pub fn new_if_op(
+        &mut self,
+        pred: ffi::TensorHandle,
+        inputs: &[ffi::TensorHandle],
+        true_branch: i32,
+        false_branch: i32,
+    ) -> ffi::TensorHandle {
+        let branches: Vec<&[TensorData]> = [true_branch, false_branch]
+            .iter()
+            .map(|region| match self.region_results.get(*region as usize) {
+                Some(results) if *region >= 0 => results.as_slice(),
+                _ => panic!("Unknown region {} in IfOp", region),
+            })
+            .collect();
+        let tensor_data = match unify_branch_results(&branches) {
+            Ok(data) => data,
+            Err(e) => panic!("Invalid IfOp: {}", e),
+        };
+        let pred = self.tensor(pred).clone();
+        let tensor_infos: Vec<TensorInfo> =
+            inputs.iter().map(|t| self.tensor(*t).clone()).collect();
+        let inputs_node = Mdl::Vec(tensor_infos.iter().map(|i| i.id).collect());
+        let inputs_id = self.rec_expr.add(inputs_node);
+        let true_branch_id = self.add_or_get_val(true_branch.into());
+        let false_branch_id = self.add_or_get_val(false_branch.into());
+        let new_node = Mdl::IfOp([pred.id, inputs_id, true_branch_id, false_branch_id]);
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data,
+        };
+        self.intern(res)
+    }
+
+    /// The regions built so far, by index
+    pub fn regions(&self) -> &[RecExpr<Mdl>] {
+        &self.regions
+    }
+
+    /// Graph of the region `region`, as optimized by the last optimize, or as
+    /// built if optimize hasn't run. The origins of its nodes refer to the
+    /// nodes of the region
+    pub fn region_graph(&self, region: i32) -> ffi::Graph {
+        if let Some(graph) = self.optimized_regions.borrow().get(region as usize) {
+            return graph.clone();
+        }
+        let expr = self
+            .regions
+            .get(region as usize)
+            .unwrap_or_else(|| panic!("Unknown region {}", region));
+        nodes_to_graph(self.convert_to_node(expr.clone()))
+    }
+
+    /// Register a custom call (a kernel the graph calls by name), and return
+    /// its index for new_custom_call_op. Register each call site once: the
+    /// index tells calls apart, so calls with the same operands are only
+    /// merged if they were built with the same index.
+    pub fn register_custom_call(&mut self, call_target: &str, backend_config: &str) -> i32 {
+        self.custom_calls.push(ffi::CustomCall {
+            call_target: call_target.to_string(),
+            backend_config: backend_config.to_string(),
+        });
+        self.custom_calls.len() as i32 - 1
+    }
+
+    /// The custom call registered with index `custom_call`
+    pub fn custom_call(&self, custom_call: i32) -> ffi::CustomCall {
+        self.custom_calls
+            .get(custom_call as usize)
+            .unwrap_or_else(|| panic!("Unknown custom call {}", custom_call))
+            .clone()
+    }
+
+    /// stablehlo.custom_call of the registered call `custom_call` on `inputs`,
+    /// with a result of `shape` and `dtype`. Rewrites never look inside the
+    /// call, but can move the ops around it.
+    pub fn new_custom_call_op(
+        &mut self,
+        inputs: &[ffi::TensorHandle],
+        custom_call: i32,
+        dtype: ffi::Type,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        if custom_call < 0 || custom_call as usize >= self.custom_calls.len() {
+            panic!("Unknown custom call {}", custom_call);
+        }
+        let ids: Vec<Id> = inputs.iter().map(|t| self.tensor(*t).id).collect();
+        let inputs_id = self.rec_expr.add(Mdl::Vec(ids));
+        let custom_call_id = self.add_or_get_val(custom_call.into());
+        let shape_id = self.shape_node(shape);
+        let dtype_id = self.add_or_get_val(dtype_to_num(dtype));
+        let new_node = Mdl::CustomCallOp([inputs_id, custom_call_id, shape_id, dtype_id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype,
+                constant: false,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.rng_bit_generator of `initial_state` with `algorithm`, with
+    /// random bits of `shape` and `dtype`. Result 0 is the new state, result 1
+    /// the bits.
+    pub fn new_rng_bit_generator_op(
+        &mut self,
+        initial_state: ffi::TensorHandle,
+        algorithm: i32,
+        dtype: ffi::Type,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let state = self.tensor(initial_state).clone();
+        let algorithm_id = self.add_or_get_val(algorithm.into());
+        let shape_id = self.shape_node(shape);
+        let dtype_id = self.add_or_get_val(dtype_to_num(dtype));
+        let new_node = Mdl::RngBitGeneratorOp([state.id, algorithm_id, shape_id, dtype_id]);
+        let (mut shapes, mut n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        shapes.insert(0, state.tensor_data.shapes[0]);
+        n_dims.insert(0, state.tensor_data.n_dims[0]);
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype,
+                constant: false,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.rng drawing values of `shape` between `a` and `b` from
+    /// `rng_distribution` (1 uniform, 2 normal). Each call gets a site of its
+    /// own, so two RngOps are never merged.
+    pub fn new_rng_op(
+        &mut self,
+        a: ffi::TensorHandle,
+        b: ffi::TensorHandle,
+        rng_distribution: i32,
+        shape: &[i32],
+    ) -> ffi::TensorHandle {
+        let a = self.tensor(a).clone();
+        let b_id = self.tensor(b).id;
+        let shape_id = self.shape_node(shape);
+        let distribution_id = self.add_or_get_val(rng_distribution.into());
+        let site = self.rng_sites;
+        self.rng_sites += 1;
+        let site_id = self.add_or_get_val(site.into());
+        let new_node = Mdl::RngOp([a.id, b_id, shape_id, distribution_id, site_id]);
+        let (shapes, n_dims) = self.shape_from_dim(&self.single_shape_vec(shape));
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: a.tensor_data.dtype,
+                constant: false,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.tuple of `inputs`. Its results are the inputs, read with
+    /// new_get_tuple_element_op (or Index).
+    pub fn new_tuple_op(&mut self, inputs: &[ffi::TensorHandle]) -> ffi::TensorHandle {
+        let tensor_infos: Vec<TensorInfo> =
+            inputs.iter().map(|t| self.tensor(*t).clone()).collect();
+        let inputs_node = Mdl::Vec(tensor_infos.iter().map(|i| i.id).collect());
+        let inputs_id = self.rec_expr.add(inputs_node);
+        let new_node = Mdl::TupleOp([inputs_id]);
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes: tensor_infos
+                    .iter()
+                    .map(|i| i.tensor_data.shapes[0])
+                    .collect(),
+                n_dims: tensor_infos
+                    .iter()
+                    .map(|i| i.tensor_data.n_dims[0])
+                    .collect(),
+                name: None,
+                dtype: tensor_infos
+                    .first()
+                    .map_or(ffi::Type::f32, |i| i.tensor_data.dtype),
+                constant: tensor_infos.iter().all(|i| i.tensor_data.constant),
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.optimization_barrier: yields `inputs` unchanged, read with
+    /// new_index. No rule rewrites across the barrier, and optimize keeps the
+    /// ops computing `inputs` as with set_no_rewrite, so frontends can fence
+    /// numerically sensitive code off while the rest is optimized
+    pub fn new_optimization_barrier_op(
+        &mut self,
+        inputs: &[ffi::TensorHandle],
+    ) -> ffi::TensorHandle {
+        let tensor_infos: Vec<TensorInfo> =
+            inputs.iter().map(|t| self.tensor(*t).clone()).collect();
+        let inputs_node = Mdl::Vec(tensor_infos.iter().map(|i| i.id).collect());
+        let inputs_id = self.rec_expr.add(inputs_node);
+        let new_node = Mdl::OptimizationBarrierOp([inputs_id]);
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes: tensor_infos
+                    .iter()
+                    .map(|i| i.tensor_data.shapes[0])
+                    .collect(),
+                n_dims: tensor_infos
+                    .iter()
+                    .map(|i| i.tensor_data.n_dims[0])
+                    .collect(),
+                name: None,
+                dtype: tensor_infos
+                    .first()
+                    .map_or(ffi::Type::f32, |i| i.tensor_data.dtype),
+                constant: false,
+            },
+        };
+        self.intern(res)
+    }
+
+    /// stablehlo.get_tuple_element: element `index` of `tuple`, a TupleOp or
+    /// another op with several results
+    pub fn new_get_tuple_element_op(
+        &mut self,
+        tuple: ffi::TensorHandle,
+        index: i32,
+    ) -> ffi::TensorHandle {
+        let tuple = self.tensor(tuple).clone();
+        let elements = tuple.tensor_data.shapes.len();
+        if index < 0 || index as usize >= elements {
+            panic!(
+                "Invalid GetTupleElementOp: index {} of a tuple of {} elements",
+                index, elements
+            );
+        }
+        let index_id = self.add_or_get_val(index.into());
+        // The elements of a tuple may have different element types
+        let nodes = self.rec_expr.as_ref();
+        let element = match &nodes[usize::from(tuple.id)] {
+            Mdl::TupleOp([inputs]) => match &nodes[usize::from(*inputs)] {
+                Mdl::Vec(inputs) => self.tensors.get(&inputs[index as usize]),
+                _ => None,
+            },
+            _ => None,
+        };
+        let dtype = element.map_or(tuple.tensor_data.dtype, |e| e.tensor_data.dtype);
+        let new_node = Mdl::GetTupleElementOp([tuple.id, index_id]);
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes: vec![tuple.tensor_data.shapes[index as usize]],
+                n_dims: vec![tuple.tensor_data.n_dims[index as usize]],
+                name: None,
+                dtype,
+                constant: tuple.tensor_data.constant,
+            },
+        };
+        self.intern(res)
+    }
+
+    pub fn new_blackbox_op(
+        &mut self,
+        inpts: &[ffi::TensorHandle],
+        cpp_num: i32,
+        shapes: &Vec<ffi::Shape>,
+    ) -> ffi::TensorHandle {
+        let tensor_infos: Vec<TensorInfo> = inpts.iter().map(|t| self.tensor(*t).clone()).collect();
+        let cpp_num_node = self.add_or_get_val(cpp_num.into());
+        let mut ids: Vec<Id> = tensor_infos.iter().map(|inpt| inpt.id).collect();
+        ids.push(cpp_num_node);
+
+        // Convert the vector of Ids to a boxed slice and create the BlackBox node
+        let new_node = Mdl::BlackBox(ids.into_boxed_slice());
+
+        let (shapes, n_dims) = self.shape_from_dim(shapes);
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes,
+                n_dims,
+                name: None,
+                dtype: ffi::Type::f32,
+                constant: false,
+            },
+        };
+        self.blackbox_cpp_num_to_tensorinfo
+            .insert(cpp_num.into(), res.clone());
+        self.intern(res)
+    }
+
+    /// Declare `inpts` the results of the graph (of the region, within
+    /// begin_region and end_region). The ReturnOp is the root optimize
+    /// saturates and extracts from, even if nodes are added after it; without
+    /// one, the root is the last node added. The last ReturnOp declared wins
+    pub fn new_return_op(&mut self, inpts: &[ffi::TensorHandle]) -> ffi::TensorHandle {
+        let tensor_infos: Vec<TensorInfo> = inpts.iter().map(|t| self.tensor(*t).clone()).collect();
+        let inputs_node = Mdl::Vec(tensor_infos.iter().map(|i| i.id).collect());
+        let inputs_id = self.rec_expr.add(inputs_node);
+        let new_node = Mdl::ReturnOp([inputs_id]);
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                shapes: vec![],
+                n_dims: vec![],
+                name: None,
+                dtype: ffi::Type::f32,
+                constant: false,
+            },
+        };
+        if self.outer_graphs.is_empty() {
+            self.returned = Some(res.id);
+        }
+        self.intern(res)
+    }
+
+    /// Register a composite op from the C++ side. The result has the shape of
+    /// the first operand; use `register_composite_op_def` for other shape functions.
+    ///
+    /// Returns the id to pass to `new_composite_op`.
+    pub fn register_composite_op(
+        &mut self,
+        name: &str,
+        num_operands: i32,
+        expansion: &str,
+        cost: f32,
+    ) -> i32 {
+        self.register_composite_op_def(CompositeOpDef {
+            name: name.to_string(),
+            num_operands: num_operands as usize,
+            shape_fn: same_shape_as_first_operand,
+            expansion: expansion.to_string(),
+            cost,
+        })
+    }
+
+    pub fn register_composite_op_def(&mut self, def: CompositeOpDef) -> i32 {
+        self.composite_ops.push(def);
+        (self.composite_ops.len() - 1) as i32
+    }
+
+    /// Composite op `composite_id` (see register_composite_op) of `inpts`.
+    /// `shapes` must be the ones the shape function of the op infers, since
+    /// saturation uses the inferred ones.
+    pub fn new_composite_op(
+        &mut self,
+        inpts: &[ffi::TensorHandle],
+        composite_id: i32,
+        shapes: &Vec<ffi::Shape>,
+    ) -> Result<ffi::TensorHandle, String> {
+        let def = usize::try_from(composite_id)
+            .ok()
+            .and_then(|index| self.composite_ops.get(index))
+            .ok_or_else(|| format!("Invalid CompositeOp: unknown composite op {}", composite_id))?;
+        if inpts.len() != def.num_operands {
+            return Err(format!(
+                "Invalid CompositeOp: {} expects {} operands, got {}",
+                def.name,
+                def.num_operands,
+                inpts.len()
+            ));
+        }
+        let tensor_infos: Vec<TensorInfo> = inpts.iter().map(|t| self.tensor(*t).clone()).collect();
+        let operand_data: Vec<&TensorData> = tensor_infos.iter().map(|i| &i.tensor_data).collect();
+        let inferred = (def.shape_fn)(&operand_data);
+        let inferred_dims: Vec<Vec<i64>> = inferred
+            .shapes
+            .iter()
+            .zip(&inferred.n_dims)
+            .map(|(shape, n_dims)| shape[..*n_dims].iter().map(|d| i64::from(*d)).collect())
+            .collect();
+        let dims: Vec<Vec<i64>> = shapes.iter().map(|shape| shape.shape.clone()).collect();
+        if dims != inferred_dims {
+            return Err(format!(
+                "Invalid CompositeOp: {} has shapes {:?}, not {:?}",
+                def.name, inferred_dims, dims
+            ));
+        }
+        let composite_id_node = self.add_or_get_val(composite_id.into());
+        let mut ids: Vec<Id> = tensor_infos.iter().map(|inpt| inpt.id).collect();
+        ids.push(composite_id_node);
+
+        let new_node = Mdl::CompositeOp(ids.into_boxed_slice());
+        let res = TensorInfo {
+            id: self.rec_expr.add(new_node),
+            tensor_data: TensorData {
+                name: None,
+                constant: tensor_infos.iter().all(|i| i.tensor_data.constant),
+                ..inferred
             },
         };
-        Box::new(res)
+        Ok(self.intern(res))
+    }
+
+    pub fn print_rec_expr(&self) {
+        println!("{:?}", self.rec_expr)
+    }
+
+    pub fn pretty_print_rec_expr(&self, width: i32) {
+        println!("{}", self.rec_expr.pretty(width as usize))
+    }
+
+    /// Build a whole batch of ops in one call, instead of one FFI call per op.
+    ///
+    /// Each descriptor is built with the corresponding new_* function, taking
+    /// its tensor arguments from `operands` and its other arguments from
+    /// `int_args` and `vec_args` in order. The result shape is `shapes[0]`, or
+    /// all of `shapes` for multi-result ops. ConstantOp is a splat constant
+    /// whose value is given by its f32 bits in `int_args[0]`. The operands of
+    /// ReduceOp are its inputs, then as many init values.
+    ///
+    /// Returns the id of the handle of the tensor built for each descriptor, or
+    /// an error for a descriptor with an operand that isn't an earlier
+    /// descriptor, too few arguments, or an unknown op.
+    pub fn build_graph(&mut self, ops: &Vec<ffi::OpDescriptor>) -> Result<Vec<i32>, String> {
+        let mut built: Vec<ffi::TensorHandle> = Vec::with_capacity(ops.len());
+        for (index, desc) in ops.iter().enumerate() {
+            let missing = |what: &str, k: usize| format!("Op {}: missing {} {}", index, what, k);
+            let operand = |i: i32| -> Result<ffi::TensorHandle, String> {
+                built
+                    .get(i as usize)
+                    .copied()
+                    .ok_or_else(|| format!("Op {}: operand {} isn't an earlier op", index, i))
+            };
+            let t = |k: usize| match desc.operands.get(k) {
+                Some(i) => operand(*i),
+                None => Err(missing("operand", k)),
+            };
+            let all = || -> Result<Vec<ffi::TensorHandle>, String> {
+                desc.operands.iter().map(|i| operand(*i)).collect()
+            };
+            let int = |k: usize| {
+                desc.int_args
+                    .get(k)
+                    .copied()
+                    .ok_or_else(|| missing("int_arg", k))
+            };
+            let n = |k: usize| int(k).map(|arg| arg as i32);
+            let v = |k: usize| -> Result<Vec<i64>, String> {
+                let arg = desc.vec_args.get(k).ok_or_else(|| missing("vec_arg", k))?;
+                Ok(arg.shape.clone())
+            };
+            let shapes = |k: usize| -> Result<Vec<i32>, String> {
+                let shape = desc.shapes.get(k).ok_or_else(|| missing("shape", k))?;
+                Ok(shape.shape.iter().map(|x| *x as i32).collect())
+            };
+            let shape: Vec<i32> = desc
+                .shapes
+                .first()
+                .map_or(vec![], |s| s.shape.iter().map(|x| *x as i32).collect());
+            let shape = &shape[..];
+
+            let tensor = match desc.op {
+                ffi::Ops::Input => self.new_input(n(0)?, shape, dtype_from_num(int(1)?)),
+                ffi::Ops::Index => self.new_index(n(0)?, t(0)?),
+                ffi::Ops::CompareOp => self.new_compare_op(t(0)?, t(1)?, n(0)?, n(1)?, shape),
+                ffi::Ops::BroadcastInDimOp => self.new_broadcast_in_dim(t(0)?, &v(0)?, shape),
+                ffi::Ops::ConvertOp => self.new_convert_op(t(0)?, n(0)?, shape),
+                ffi::Ops::ReduceOp => {
+                    let operands = all()?;
+                    if operands.is_empty() || operands.len() % 2 != 0 {
+                        return Err(format!("Op {}: unpaired ReduceOp init values", index));
+                    }
+                    let (inputs, init_values) = operands.split_at(operands.len() / 2);
+                    self.new_reduce_op(inputs, init_values, &v(0)?, n(0)?, &desc.shapes)
+                }
+                ffi::Ops::ReshapeOp => self.new_reshape_op(t(0)?, shape),
+                ffi::Ops::GatherOp => self.new_gather_op(
+                    t(0)?,
+                    t(1)?,
+                    &v(0)?,
+                    &v(1)?,
+                    &v(2)?,
+                    &v(3)?,
+                    &v(4)?,
+                    n(0)?,
+                    &v(5)?,
+                    n(1)?,
+                    shape,
+                ),
+                ffi::Ops::SelectOp => self.new_select_op(t(0)?, t(1)?, t(2)?, shape),
+                ffi::Ops::ConcatenateOp => self.new_concatenate_op(&all()?, n(0)?, shape),
+                ffi::Ops::DotGeneralOp => self.new_dot_general_op(
+                    t(0)?,
+                    t(1)?,
+                    &v(0)?,
+                    &v(1)?,
+                    &v(2)?,
+                    &v(3)?,
+                    &v(4)?,
+                    shape,
+                ),
+                ffi::Ops::ConvolutionOp => self.new_convolution_op(
+                    t(0)?,
+                    t(1)?,
+                    &v(0)?,
+                    &v(1)?,
+                    &v(2)?,
+                    &v(3)?,
+                    &v(4)?,
+                    n(0)?,
+                    n(1)?,
+                    &v(5)?,
+                    shape,
+                ),
+                ffi::Ops::PadOp => self.new_pad_op(t(0)?, t(1)?, &v(0)?, &v(1)?, &v(2)?, shape),
+                ffi::Ops::SliceOp => self.new_slice_op(t(0)?, &v(0)?, &v(1)?, &v(2)?, shape),
+                ffi::Ops::TransposeOp => self.new_transpose_op(t(0)?, &v(0)?, shape),
+                ffi::Ops::MulOp => self.new_mul_op(t(0)?, t(1)?, shape),
+                ffi::Ops::AddOp => self.new_add_op(t(0)?, t(1)?, shape),
+                ffi::Ops::DivOp => self.new_div_op(t(0)?, t(1)?, shape),
+                ffi::Ops::SubtractOp => self.new_subtract_op(t(0)?, t(1)?, shape),
+                ffi::Ops::MinOp => self.new_min_op(t(0)?, t(1)?, shape),
+                ffi::Ops::MaxOp => self.new_max_op(t(0)?, t(1)?, shape),
+                ffi::Ops::ClampOp => self.new_clamp_op(t(0)?, t(1)?, t(2)?, shape),
+                ffi::Ops::PowOp => self.new_pow_op(t(0)?, t(1)?, shape),
+                ffi::Ops::RemainderOp => self.new_remainder_op(t(0)?, t(1)?, shape),
+                ffi::Ops::AndOp => self.new_and_op(t(0)?, t(1)?, shape),
+                ffi::Ops::OrOp => self.new_or_op(t(0)?, t(1)?, shape),
+                ffi::Ops::XorOp => self.new_xor_op(t(0)?, t(1)?, shape),
+                ffi::Ops::NotOp => self.new_not_op(t(0)?, shape),
+                ffi::Ops::ShiftLeftOp => self.new_shift_left_op(t(0)?, t(1)?, shape),
+                ffi::Ops::ShiftRightLogicalOp => {
+                    self.new_shift_right_logical_op(t(0)?, t(1)?, shape)
+                }
+                ffi::Ops::ShiftRightArithmeticOp => {
+                    self.new_shift_right_arithmetic_op(t(0)?, t(1)?, shape)
+                }
+                ffi::Ops::NegOp => self.new_neg_op(t(0)?, shape),
+                ffi::Ops::TanhOp => self.new_tanh_op(t(0)?, shape),
+                ffi::Ops::ExpOp => self.new_exp_op(t(0)?, shape),
+                ffi::Ops::SqrtOp => self.new_sqrt_op(t(0)?, shape),
+                ffi::Ops::RsqrtOp => self.new_rsqrt_op(t(0)?, shape),
+                ffi::Ops::LogOp => self.new_log_op(t(0)?, shape),
+                ffi::Ops::SineOp => self.new_sine_op(t(0)?, shape),
+                ffi::Ops::CosineOp => self.new_cosine_op(t(0)?, shape),
+                ffi::Ops::AbsOp => self.new_abs_op(t(0)?, shape),
+                ffi::Ops::SignOp => self.new_sign_op(t(0)?, shape),
+                ffi::Ops::FloorOp => self.new_floor_op(t(0)?, shape),
+                ffi::Ops::CeilOp => self.new_ceil_op(t(0)?, shape),
+                ffi::Ops::RoundOp => self.new_round_op(t(0)?, shape),
+                ffi::Ops::ErfOp => self.new_erf_op(t(0)?, shape),
+                ffi::Ops::ComplexOp => self.new_complex_op(t(0)?, t(1)?, shape),
+                ffi::Ops::RealOp => self.new_real_op(t(0)?, shape),
+                ffi::Ops::ImagOp => self.new_imag_op(t(0)?, shape),
+                ffi::Ops::FftOp => self.new_fft_op(t(0)?, n(0)?, &v(0)?, shape),
+                ffi::Ops::CholeskyOp => self.new_cholesky_op(t(0)?, n(0)? != 0, shape),
+                ffi::Ops::TriangularSolveOp => self.new_triangular_solve_op(
+                    t(0)?,
+                    t(1)?,
+                    n(0)? != 0,
+                    n(1)? != 0,
+                    n(2)? != 0,
+                    n(3)?,
+                    shape,
+                ),
+                ffi::Ops::BatchNormInferenceOp => {
+                    let epsilon = f32::from_bits(int(0)? as u32);
+                    self.new_batch_norm_inference_op(
+                        t(0)?,
+                        t(1)?,
+                        t(2)?,
+                        t(3)?,
+                        t(4)?,
+                        epsilon,
+                        n(1)?,
+                        shape,
+                    )
+                }
+                ffi::Ops::BatchNormTrainingOp => {
+                    let epsilon = f32::from_bits(int(0)? as u32);
+                    self.new_batch_norm_training_op(
+                        t(0)?,
+                        t(1)?,
+                        t(2)?,
+                        epsilon,
+                        n(1)?,
+                        &desc.shapes,
+                    )
+                }
+                ffi::Ops::BatchNormGradOp => {
+                    let epsilon = f32::from_bits(int(0)? as u32);
+                    self.new_batch_norm_grad_op(
+                        t(0)?,
+                        t(1)?,
+                        t(2)?,
+                        t(3)?,
+                        t(4)?,
+                        epsilon,
+                        n(1)?,
+                        &desc.shapes,
+                    )
+                }
+                ffi::Ops::GeluOp => self.new_gelu_op(t(0)?, n(0)?, shape),
+                ffi::Ops::IotaOp => self.new_iota_op(n(0)?, shape),
+                ffi::Ops::ConstantOp => {
+                    self.new_splat_constant_op(f32::from_bits(int(0)? as u32), shape)
+                }
+                ffi::Ops::DenseConstantOp => self.new_dense_constant_op(n(0)?, shape)?,
+                ffi::Ops::DynamicUpdateSliceOp => {
+                    self.new_dynamic_update_slice_op(t(0)?, t(1)?, t(2)?, shape)
+                }
+                ffi::Ops::DynamicSliceOp => self.new_dynamic_slice_op(t(0)?, t(1)?, n(0)?, shape),
+                ffi::Ops::ScatterOp => {
+                    self.new_scatter_op(t(0)?, t(1)?, t(2)?, n(0)?, &desc.shapes)
+                }
+                ffi::Ops::SelectAndScatterOp => self.new_select_and_scatter_op(
+                    t(0)?,
+                    t(1)?,
+                    t(2)?,
+                    &v(0)?,
+                    &v(1)?,
+                    &v(2)?,
+                    shape,
+                ),
+                ffi::Ops::SortOp => {
+                    self.new_sort_op(&all()?, n(0)?, n(1)? != 0, n(2)?, n(3)?, &desc.shapes)
+                }
+                ffi::Ops::WhileOp => self.new_while_op(&all()?, n(0)?, n(1)?, &desc.shapes),
+                ffi::Ops::IfOp => self.new_if_op(t(0)?, &all()?[1..], n(0)?, n(1)?),
+                ffi::Ops::CustomCallOp => {
+                    self.new_custom_call_op(&all()?, n(0)?, dtype_from_num(int(1)?), shape)
+                }
+                ffi::Ops::RngBitGeneratorOp => {
+                    let output = shapes(1)?;
+                    let dtype = dtype_from_num(int(1)?);
+                    self.new_rng_bit_generator_op(t(0)?, n(0)?, dtype, &output)
+                }
+                ffi::Ops::RngOp => self.new_rng_op(t(0)?, t(1)?, n(0)?, shape),
+                ffi::Ops::AllReduceOp => self.new_all_reduce_op(t(0)?, &v(0)?, n(0)?, n(1)?, shape),
+                ffi::Ops::AllGatherOp => self.new_all_gather_op(t(0)?, n(0)?, &v(0)?, n(1)?, shape),
+                ffi::Ops::ReduceScatterOp => {
+                    self.new_reduce_scatter_op(t(0)?, n(0)?, &v(0)?, n(1)?, n(2)?, shape)
+                }
+                ffi::Ops::AllToAllOp => {
+                    self.new_all_to_all_op(t(0)?, n(0)?, n(1)?, &v(0)?, n(2)?, shape)
+                }
+                ffi::Ops::TupleOp => self.new_tuple_op(&all()?),
+                ffi::Ops::GetTupleElementOp => self.new_get_tuple_element_op(t(0)?, n(0)?),
+                ffi::Ops::OptimizationBarrierOp => self.new_optimization_barrier_op(&all()?),
+                ffi::Ops::BlackBoxOp => self.new_blackbox_op(&all()?, n(0)?, &desc.shapes),
+                ffi::Ops::ReturnOp => self.new_return_op(&all()?),
+                _ => return Err(format!("Op {}: unknown op {}", index, desc.op.repr)),
+            };
+            built.push(tensor);
+        }
+        Ok(built.iter().map(|tensor| tensor.id).collect())
+    }
+
+    /// Convert a graph to the nodes returned to C++, with the shapes and dtypes
+    /// inferred for it
+    pub fn convert_to_node(&self, rec_expr: RecExpr<Mdl>) -> Vec<ffi::Node> {
+        let rec_expr = prune_unreachable(&rec_expr);
+        let mut nodes = rec_expr_to_nodes(&rec_expr, &self.composite_ops);
+
+        // Use the shapes the analysis inferred, so the C++ side builds exactly
+        // the types we optimized for
+        let mut egraph = EGraph::<Mdl, TensorAnalysis>::new(self.analysis());
+        let mut class_ids: Vec<Id> = Vec::with_capacity(nodes.len());
+        let region_dtypes: Vec<Vec<ffi::Type>> = self
+            .region_results
+            .iter()
+            .map(|results| results.iter().map(|data| data.dtype).collect())
+            .collect();
+        let dtypes = infer_dtypes_with_regions(&rec_expr, &region_dtypes);
+        for (mdl, dtype) in rec_expr.as_ref().iter().zip(dtypes) {
+            let class_id = egraph.add(mdl.clone().map_children(|c| class_ids[usize::from(c)]));
+            class_ids.push(class_id);
+            let node = &mut nodes[class_ids.len() - 1];
+            node.dtype = dtype;
+            // Vars carry the shape of the input they name
+            if !matches!(mdl, Mdl::Num(_) | Mdl::Vec(_)) {
+                let data = &egraph[class_id].data;
+                node.shape = data
+                    .shapes
+                    .iter()
+                    .zip(data.n_dims.iter())
+                    .map(|(shape, n_dims)| ffi::Shape {
+                        shape: shape[..*n_dims].iter().map(|d| *d as i64).collect(),
+                    })
+                    .collect();
+            }
+        }
+        nodes
+    }
+
+    /// Save the e-graph to `dir` every `every` iterations of optimize, at most
+    /// `max_count` times
+    pub fn set_snapshots(&mut self, dir: &str, every: i32, max_count: i32) -> Result<(), String> {
+        let every = usize::try_from(every)
+            .ok()
+            .filter(|every| *every > 0)
+            .ok_or_else(|| format!("Invalid snapshot interval {}", every))?;
+        let max_count = usize::try_from(max_count)
+            .map_err(|_| format!("Invalid snapshot count {}", max_count))?;
+        self.config.snapshot_dir = Some(dir.to_string());
+        self.config.snapshot_every = every;
+        self.config.max_snapshots = max_count;
+        Ok(())
+    }
+
+    /// Write a trace of the substitutions that survive into the output of
+    /// optimize to `path`
+    pub fn set_rule_trace(&mut self, path: &str) {
+        self.config.rule_trace = Some(path.to_string());
+    }
+
+    /// Write the extraction ILP of optimize in LP format to `path`
+    pub fn set_lp_file(&mut self, path: &str) {
+        self.config.lp_file = Some(path.to_string());
+    }
+
+    /// Start the saturation of optimize from the e-graph saved (by an optimize
+    /// of the same graph with set_save_state) in `path`
+    pub fn set_resume_from(&mut self, path: &str) {
+        self.config.resume_from = Some(path.to_string());
+    }
+
+    /// Save the saturated e-graph of optimize to `path`, to be resumed from
+    pub fn set_save_state(&mut self, path: &str) {
+        self.config.save_state = Some(path.to_string());
+    }
+
+    /// Make optimize return the input graph without optimizing it
+    pub fn set_identity_mode(&mut self, enable: bool) {
+        self.config.identity_mode = enable;
+    }
+
+    /// Also take the statistics of the e-graph every `every` iterations of
+    /// saturation, not only after it (see egraph_stats). 0 turns this off
+    pub fn set_stats_every(&mut self, every: i32) {
+        self.config.stats_every = every.max(0) as usize;
+    }
+
+    /// Statistics of the e-graph of the last optimize run on this converter:
+    /// the ones taken during saturation (see set_stats_every), then the ones of
+    /// the saturated e-graph. Empty in identity mode
+    pub fn egraph_stats(&self) -> Vec<ffi::EGraphStats> {
+        self.stats.borrow().clone()
+    }
+
+    /// Stop saturation once the next iteration is predicted to exceed the node limit
+    pub fn set_predict_growth(&mut self, enable: bool) {
+        self.config.predict_growth = enable;
+    }
+
+    /// Write the time spent in each phase of optimize to `path`
+    pub fn set_profile(&mut self, path: &str, chrome_trace: bool) {
+        self.config.profile_file = Some(path.to_string());
+        self.config.profile_chrome_trace = chrome_trace;
+    }
+
+    /// Check the optimized graph against the input with the reference
+    /// interpreter, returning the input graph if they differ by more than
+    /// `tolerance`
+    pub fn set_numeric_check(&mut self, enable: bool, tolerance: f32) {
+        self.config.numeric_check = enable;
+        self.config.numeric_tolerance = tolerance;
+    }
+
+    /// Record the rules each output node of optimize was derived with
+    pub fn set_track_provenance(&mut self, enable: bool) {
+        self.config.track_provenance = enable;
+    }
+
+    /// Write a report of what optimize changed in the graph to `path`
+    pub fn set_diff_report(&mut self, path: &str) {
+        self.config.diff_report = Some(path.to_string());
+    }
+
+    /// Annotate `tensor` with its SPMD sharding (e.g. the `mhlo.sharding`
+    /// attribute of its op). Optimize keeps the annotated tensor in the graph
+    /// and copies the annotation to the node computing it
+    pub fn set_sharding(&mut self, tensor: ffi::TensorHandle, sharding: &str) {
+        let id = self.tensor(tensor).id;
+        self.shardings.insert(id, sharding.to_string());
+    }
+
+    /// Keep the op computing `tensor` as it is (e.g. a hand-tuned kernel or a
+    /// numerically sensitive op): optimize doesn't replace it, but can still
+    /// rewrite its operands and the rest of the graph
+    pub fn set_no_rewrite(&mut self, tensor: ffi::TensorHandle) {
+        let id = self.tensor(tensor).id;
+        self.protected.insert(id);
+    }
+
+    /// Replace the settings of optimize with the ones in the TOML file at
+    /// `path`, see OptimizeConfig::from_toml
+    pub fn load_config_toml(&mut self, path: &str) -> Result<(), String> {
+        self.config = OptimizeConfig::from_toml(path)?;
+        Ok(())
+    }
+
+    /// Optimize with the config of the session and the state it shares, see
+    /// OptimizerSession::optimize_graph
+    pub(crate) fn join_session(&mut self, config: OptimizeConfig, shared: SharedState) {
+        self.config = config;
+        self.session = Some(shared);
+    }
+
+    /// Key of the optimized graph in the cache of a session: the input nodes,
+    /// their shardings and the protected nodes. None if optimize depends on
+    /// more than that, i.e. the graph has blackboxes, composite ops or dense
+    /// constants
+    pub(crate) fn session_key(&self) -> Option<String> {
+        if !self.blackbox_cpp_num_to_tensorinfo.is_empty()
+            || !self.composite_ops.is_empty()
+            || !self.dense_constants.is_empty()
+        {
+            return None;
+        }
+        let shardings: Vec<_> = self.shardings.iter().sorted().collect();
+        let protected: Vec<_> = self.protected.iter().sorted().collect();
+        Some(format!(
+            "{:?} {:?} {:?}",
+            self.rooted_expr().as_ref(),
+            shardings,
+            protected
+        ))
+    }
+
+    /// rec_expr with the declared ReturnOp (if any) as its root, the last node.
+    /// Nodes added after the ReturnOp are kept at their ids, the ReturnOp is
+    /// added again after them
+    fn rooted_expr(&self) -> RecExpr<Mdl> {
+        let mut expr = self.rec_expr.clone();
+        if let Some(returned) = self.returned {
+            if usize::from(returned) + 1 != expr.as_ref().len() {
+                expr.add(expr[returned].clone());
+            }
+        }
+        expr
+    }
+
+    /// Cost model of optimize, layout-aware if the layout is searched over and
+    /// charging for resharding if configured
+    fn cost_model(&self) -> CostModel {
+        let cost_model = if self.config.layout_search {
+            CostModel::layout_aware()
+        } else {
+            CostModel::new()
+        };
+        let cost_model = cost_model.with_resharding_cost(self.config.resharding_cost);
+        match &self.session {
+            Some(session) => cost_model.with_measured_costs(session.costs.clone()),
+            None => cost_model,
+        }
     }
 
-    pub fn new_blackbox_op(
-        &mut self,
-        inpts: &[*mut TensorInfo],
-        cpp_num: i32,
-        shapes: &Vec<ffi::Shape>,
-    ) -> Box<TensorInfo> {
-        let tensor_infos: Vec<&TensorInfo> = inpts.iter().map(|&ptr| unsafe { &*ptr }).collect();
-        let cpp_num_node = self.add_or_get_val(cpp_num);
-        let mut ids: Vec<Id> = tensor_infos.iter().map(|inpt| inpt.id).collect();
-        ids.push(cpp_num_node);
+    fn analysis(&self) -> TensorAnalysis {
+        let analysis =
+            TensorAnalysis::new(&self.blackbox_cpp_num_to_tensorinfo, &self.composite_ops)
+                .with_region_results(self.region_results.clone())
+                .with_dense_constants(self.dense_constants.clone());
+        match &self.session {
+            Some(session) => analysis.with_shape_cache(session.shapes.clone()),
+            None => analysis,
+        }
+    }
 
-        // Convert the vector of Ids to a boxed slice and create the BlackBox node
-        let new_node = Mdl::BlackBox(ids.into_boxed_slice());
+    /// Graph saturation starts from: the converter's RecExpr without the nodes
+    /// left over from construction (e.g. scalars and vectors of abandoned
+    /// construction paths), densely renumbered and then canonicalized
+    fn start_graph(&self) -> RecExpr<Mdl> {
+        if let Err(e) = validate_vecs(&self.rec_expr) {
+            panic!("Invalid input graph: {}", e);
+        }
+        let rooted = self.rooted_expr();
+        let compacted = prune_unreachable(&rooted);
+        println!(
+            "Compacted input graph: {} -> {} nodes",
+            self.rec_expr.as_ref().len(),
+            compacted.as_ref().len()
+        );
+        // The protected and sharded nodes are looked up in the e-graph as they
+        // are in rec_expr, so canonicalization leaves them alone
+        let new_ids: HashMap<Id, Id> = reachable_indices(&rooted)
+            .into_iter()
+            .enumerate()
+            .map(|(new, old)| (Id::from(old), Id::from(new)))
+            .collect();
+        let keep: HashSet<Id> = self
+            .protected
+            .iter()
+            .chain(&barrier_operands(&self.rec_expr))
+            .chain(self.shardings.keys())
+            .filter_map(|id| new_ids.get(id).copied())
+            .collect();
+        let start = canonicalize_graph(&compacted, self.analysis(), &keep);
+        println!(
+            "Canonicalized input graph: {} -> {} nodes",
+            compacted.as_ref().len(),
+            start.as_ref().len()
+        );
+        start
+    }
 
-        let (shapes, n_dims) = self.shape_from_dim(shapes);
-        let res = TensorInfo {
-            id: self.rec_expr.add(new_node),
-            tensor_data: TensorData {
-                shapes,
-                n_dims,
-                name: None,
-            },
+    fn learned_rules(&self) -> String {
+        match &self.session {
+            Some(session) => session.learned_rules.to_string(),
+            None => read_learned_rules(&self.config),
+        }
+    }
+
+    /// Scheduler applying the per-iteration match caps and budgets of the rules
+    fn scheduler(&self) -> CappedScheduler {
+        let learned_rules = self.learned_rules();
+        let rules = split_rules(&learned_rules);
+        let mut options = rule_options_from_str(&rules);
+        for name in CHEAP_CUSTOM_RULES {
+            options.entry(name.to_string()).or_default().class = RuleClass::Cheap;
+        }
+        CappedScheduler::new(options, self.config.max_matches)
+            .with_match_limit(self.config.max_match_ms.map(Duration::from_millis))
+            .with_patterns(rule_patterns_from_str(&rules))
+            .with_budget(RuleClass::Cheap, self.config.cheap_budget)
+            .with_budget(RuleClass::Expensive, self.config.expensive_budget)
+    }
+
+    /// Collect the rules used for saturation: the rules of base_rules and the
+    /// expansions of the registered composite ops
+    fn saturation_rules(&self) -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+        let mut rules = match &self.session {
+            Some(session) => session.rules.as_ref().clone(),
+            None => base_rules(&self.config, &self.learned_rules()),
         };
-        self.blackbox_cpp_num_to_tensorinfo
-            .insert(cpp_num, res.clone());
-        Box::new(res)
+        rules.append(&mut composite_expansion_rules(&self.composite_ops));
+        rules
     }
 
-    pub fn new_return_op(&mut self, inpts: &[*mut TensorInfo]) -> Box<TensorInfo> {
-        let tensor_infos: Vec<&TensorInfo> = inpts.iter().map(|&ptr| unsafe { &*ptr }).collect();
-        let inputs_node = Mdl::Vec(tensor_infos.iter().map(|i| i.id).collect());
-        let inputs_id = self.rec_expr.add(inputs_node);
-        let new_node = Mdl::ReturnOp([inputs_id]);
-        let res = TensorInfo {
-            id: self.rec_expr.add(new_node),
-            tensor_data: TensorData {
-                shapes: vec![],
-                n_dims: vec![],
-                name: None,
-            },
+    /// Create a runner on `start` with the limits from the config, and `n_sec`
+    /// seconds for saturation and the multi-pattern rules (no time limit if
+    /// None)
+    fn new_runner(
+        &self,
+        start: &RecExpr<Mdl>,
+        n_sec: Option<u64>,
+    ) -> Runner<Mdl, TensorAnalysis, ()> {
+        let config = &self.config;
+        let n_sec = n_sec.unwrap_or(u64::MAX);
+        let mut runner = Runner::<Mdl, TensorAnalysis, ()>::new(self.analysis())
+            .with_node_limit(config.node_limit)
+            .with_time_limit(Duration::new(n_sec, 0))
+            .with_iter_limit(config.iter_limit)
+            .with_expr(start)
+            .with_scheduler(self.scheduler());
+        if config.use_multi {
+            let mut multi_patterns = MultiPatterns::with_rules(
+                PRE_DEFINED_MULTI.to_vec(),
+                config.no_cycle,
+                config.iter_multi,
+                config.filter_after,
+                config.node_multi,
+                n_sec,
+            );
+            runner = runner.with_hook(move |runner| multi_patterns.run_one(runner));
+        }
+        if let Some(snapshot_dir) = &config.snapshot_dir {
+            // Hooks run before each iteration, so the snapshot at iteration i is the
+            // e-graph after i iterations
+            let snapshot_dir = snapshot_dir.clone();
+            let snapshot_every = config.snapshot_every.max(1);
+            let mut snapshots_left = config.max_snapshots;
+            runner = runner.with_hook(move |runner| {
+                let iteration = runner.iterations.len();
+                if snapshots_left > 0 && iteration % snapshot_every == 0 {
+                    // A failed snapshot doesn't stop saturation
+                    let path = format!("{}/egraph_iter_{}.dot", snapshot_dir, iteration);
+                    if let Err(e) = write(&path, named_dot(&runner.egraph)) {
+                        println!("WARNING: unable to write snapshot {}: {}", path, e);
+                    }
+                    snapshots_left -= 1;
+                }
+                Ok(())
+            });
+        }
+        if config.predict_growth {
+            runner = runner.with_hook(growth_hook(config.node_limit));
+        }
+        runner
+    }
+
+    /// Replace the e-graph of `runner` (built on `start`) by the one saved in
+    /// `resume_from`, if set. Starts over from `start` if the saved e-graph
+    /// can't be read or was saturated from another graph
+    ///
+    /// # Returns
+    ///
+    /// The runner and the number of iterations run to get its e-graph
+    fn resume(
+        &self,
+        mut runner: Runner<Mdl, TensorAnalysis, ()>,
+        start: &RecExpr<Mdl>,
+    ) -> (Runner<Mdl, TensorAnalysis, ()>, usize) {
+        let path = match &self.config.resume_from {
+            Some(path) => path,
+            None => return (runner, 0),
         };
-        Box::new(res)
+        let resumed = SaturationState::load(path).and_then(|state| {
+            let (egraph, root) = state.restore(start, self.analysis())?;
+            Ok((egraph, root, state.iterations))
+        });
+        match resumed {
+            Ok((egraph, root, iterations)) => {
+                println!(
+                    "Resuming saturation from {} after {} iterations",
+                    path, iterations
+                );
+                runner.egraph = egraph;
+                runner.roots = vec![root];
+                (runner, iterations)
+            }
+            Err(e) => {
+                println!("Could not resume saturation from {}: {}", path, e);
+                (runner, 0)
+            }
+        }
     }
 
-    pub fn print_rec_expr(&self) {
-        println!("{:?}", self.rec_expr)
+    pub fn optimize<'a>(&'a self) -> ffi::Graph {
+        let (graph, _, _) = self.run_optimize();
+        graph
     }
 
-    pub fn pretty_print_rec_expr(&self, width: i32) {
-        println!("{}", self.rec_expr.pretty(width as usize))
+    /// Optimize, keeping the nodes of the result to be fetched in batches with
+    /// `next_nodes` instead of returning them all at once.
+    ///
+    /// Returns the optimized graph without its nodes (only `root` and `results`
+    /// are filled in).
+    pub fn optimize_streaming(&mut self) -> ffi::Graph {
+        let (mut graph, _, _) = self.run_optimize();
+        self.pending_nodes = std::mem::take(&mut graph.nodes).into();
+        graph
     }
 
-    fn convert_to_node(&self, rec_expr: RecExpr<Mdl>) -> Vec<ffi::Node> {
-        let mut res: Vec<ffi::Node> = Vec::new();
+    /// Get the next (at most) `batch_size` nodes of the graph optimized by
+    /// `optimize_streaming`, in order. Returns an empty Vec when all nodes have
+    /// been fetched.
+    pub fn next_nodes(&mut self, batch_size: i32) -> Vec<ffi::Node> {
+        let batch_size = (batch_size.max(1) as usize).min(self.pending_nodes.len());
+        self.pending_nodes.drain(..batch_size).collect()
+    }
 
-        let index = |id: Id| (usize::from(id) as i32); // TODO: this is probably wrong
-        let convert = |operands: &[Id]| {
-            operands
-                .iter()
-                .map(|id: &Id| index(*id))
-                .collect::<Vec<i32>>()
-        };
-        let new_node = |name: &str, operands: &[Id]| ffi::Node {
-            name: name.to_string(),
-            label: "".to_string(),
-            operands: convert(operands),
-        };
+    /// Optimize, and also return the graph saturation started from (the
+    /// canonicalized input) and the estimated cost of both graphs, so the caller
+    /// can decide whether the predicted improvement is worth taking, and the
+    /// inputs and constants the optimized graph doesn't use anymore.
+    pub fn optimize_with_original(&self) -> ffi::OptimizeResult {
+        let (optimized, start, best) = self.run_optimize();
+        let cost_model = self.cost_model();
+        let original_cost = graph_cost(&start, self.analysis(), &cost_model);
+        let optimized_cost = graph_cost(&best, self.analysis(), &cost_model);
+        println!("Estimated cost: {} -> {}", original_cost, optimized_cost);
+        let origins: Vec<i32> = optimized.nodes.iter().map(|n| n.origin).collect();
+        let (unused_inputs, unused_constants) = unused_leaves(&self.rec_expr, &origins);
+        if !unused_inputs.is_empty() || !unused_constants.is_empty() {
+            println!(
+                "Unused after optimization: {} inputs, {} constants",
+                unused_inputs.len(),
+                unused_constants.len()
+            );
+        }
+        ffi::OptimizeResult {
+            optimized,
+            original: nodes_to_graph(self.convert_to_node(start)),
+            original_cost,
+            optimized_cost,
+            unused_inputs,
+            unused_constants,
+        }
+    }
 
-        let rec_expr_ref = rec_expr.as_ref();
+    /// Extract the cheapest graph greedily (with egg's Extractor and
+    /// TensorCost) from the e-graph saturated by the last optimize or
+    /// extract_greedy, saturating first if there is none. Unlike optimize, the
+    /// graph isn't checked or post-processed, so this works without the ILP
+    /// solver installed
+    pub fn extract_greedy(&self) -> ffi::ExtractResult {
+        if self.saturated.borrow().is_none() {
+            let start = self.start_graph();
+            let rules = self.saturation_rules();
+            let (mut runner, _) =
+                self.resume(self.new_runner(&start, Some(self.config.n_sec)), &start);
+            if !self.shardings.is_empty() {
+                runner.egraph.analysis.shardings =
+                    sharded_classes(&runner.egraph, &self.rec_expr, &self.shardings);
+            }
+            let mut runner = runner.run(&rules[..]);
+            if self.config.no_cycle && self.config.filter_after {
+                remove_cycle_by_order(&mut runner);
+            }
+            println!("Runner complete!");
+            println!("  Nodes: {}", runner.egraph.total_size());
+            println!("  Stopped: {:?}", runner.stop_reason.unwrap());
+            *self.saturated.borrow_mut() = Some((runner.egraph, runner.roots[0]));
+        }
+        let saturated = self.saturated.borrow();
+        let (egraph, root) = saturated.as_ref().unwrap();
+        let cost_model = self.cost_model();
+        let (best, _) = extract_by_greedy(egraph, *root, &cost_model);
+        let cost = graph_cost(&best, self.analysis(), &cost_model);
+        let mut nodes = self.convert_to_node(best.clone());
+        let origins = node_origins(egraph, &self.rec_expr, &best);
+        for (node, origin) in nodes.iter_mut().zip(origins) {
+            node.origin = origin;
+        }
+        ffi::ExtractResult {
+            graph: nodes_to_graph(nodes),
+            cost,
+        }
+    }
 
-        for mdl in rec_expr_ref.iter() {
-            let node = match mdl {
-                Mdl::Var(label) => ffi::Node {
-                    name: "Var".to_string(),
-                    label: label.to_string(),
-                    operands: vec![],
-                },
-                Mdl::Num(num) => ffi::Node {
-                    name: "Num".to_string(),
-                    label: "".to_string(),
-                    operands: vec![*num],
-                },
-                // TODO: More clever pattern matching
-                Mdl::Vec(ops) => new_node("Vec", ops),
-                Mdl::Input(ops) => new_node("Input", ops),
-                Mdl::Index(ops) => new_node("Index", ops),
-                // Mdl::ConstantOp(ops) => new_node("ConstantOp", ops),
-                Mdl::ReshapeOp(ops) => new_node("ReshapeOp", ops),
-                Mdl::ConcatenateOp(ops) => new_node("ConcatenateOp", ops),
-                Mdl::DotGeneralOp(ops) => new_node("DotGeneralOp", ops),
-                Mdl::SliceOp(ops) => new_node("SliceOp", ops),
-                Mdl::TransposeOp(ops) => new_node("TransposeOp", ops),
-                Mdl::MulOp(ops) => new_node("MulOp", ops),
-                Mdl::AddOp(ops) => new_node("AddOp", ops),
-                Mdl::DivOp(ops) => new_node("DivOp", ops),
-                Mdl::SubtractOp(ops) => new_node("SubtractOp", ops),
-                Mdl::MinOp(ops) => new_node("MinOp", ops),
-                Mdl::MaxOp(ops) => new_node("MaxOp", ops),
-                Mdl::NegOp(ops) => new_node("NegOp", ops),
-                Mdl::TanhOp(ops) => new_node("TanhOp", ops),
-                Mdl::ExpOp(ops) => new_node("ExpOp", ops),
-                Mdl::IotaOp(ops) => new_node("IotaOp", ops),
-                Mdl::PadOp(ops) => new_node("PadOp", ops),
-                Mdl::ReturnOp(ops) => new_node("ReturnOp", ops),
-                Mdl::BlackBox(ops) => new_node("blackbox", ops),
-                _ => unimplemented!(),
+    /// Optimize only the ops between `producers` and `consumers` (see
+    /// region.rs), with the settings of optimize, and return the replacement
+    /// of the region. The origins of its nodes refer to the tensors of this
+    /// converter
+    pub fn optimize_region(
+        &self,
+        producers: &[ffi::TensorHandle],
+        consumers: &[ffi::TensorHandle],
+    ) -> ffi::RegionResult {
+        let ids = |tensors: &[ffi::TensorHandle]| -> Vec<Id> {
+            tensors.iter().map(|t| self.tensor(*t).id).collect()
+        };
+        // Boundary inputs holding constants are weights, as in the full graph
+        let boundary_var = |k: usize, id: Id| {
+            let tensor = ffi::TensorHandle {
+                id: usize::from(id) as i32,
             };
-
-            res.push(node);
-        }
-
-        res
-    }
-
-    pub fn optimize<'a>(&'a self) -> Vec<ffi::Node> {
-        let start = &self.rec_expr;
-
-        // Configuration
-        let n_sec = 30; // seconds for timeout
-        let use_multi = false; // whether to use multi patterns
-        let no_cycle = true; // is our graph by definition acyclic?
-        let filter_after = false; // vanilla filtering or efficient filtering
-        let iter_limit = 10000;
-        let node_limit = 5000000; // max nodes in e-graph
-
-        let path = std::env::current_dir().unwrap();
-        println!("The current directory is {}", path.display());
-        let rule_file = "src/enzyme_ad/jax/deps/tensat/converted.txt";
-
-        let learned_rules =
-            read_to_string(rule_file).expect("Something went wrong reading the rule file");
-        let time_limit_sec = Duration::new(n_sec, 0);
-        let pre_defined_rules = PRE_DEFINED_RULES.iter().map(|&x| x);
-        let split_rules: Vec<&str> = learned_rules.split("\n").chain(pre_defined_rules).collect();
-        let do_filter_after = no_cycle && filter_after;
-        let analysis = TensorAnalysis::new(&self.blackbox_cpp_num_to_tensorinfo);
-        let runner = Runner::<Mdl, TensorAnalysis, ()>::new(analysis)
-            .with_node_limit(node_limit)
-            .with_time_limit(time_limit_sec)
-            .with_iter_limit(iter_limit)
-            .with_expr(&start);
-        // .with_hook(move |runner| multi_patterns.run_one(runner));
-        let mut rules = rules_from_str(split_rules, do_filter_after);
-
-        let mut custom_rules: Vec<Rewrite<Mdl, TensorAnalysis>> = vec![
-            rewrite!("transpose-of-transpose";
-                     "(TransposeOp (TransposeOp ?x ?p) ?p)" => "?x" if decreasing_perm("?p")),
-            rewrite!("flatten-concat";
-                     "(ConcatenateOp ?v ?d)" => { FlattenConcat {
-                     vec: "?v".parse().unwrap(),
-                     dim: "?d".parse().unwrap(),
-            }}),
-            rewrite!("merge-slices";
-                     "(ConcatenateOp (Vec (SliceOp ?x ?s1 ?l1 ?s) (SliceOp ?x ?s2 ?l2 ?s)) ?d)" => { MergeSlices {
-                     x: "?x".parse().unwrap(),
-                     s1: "?s1".parse().unwrap(),
-                     s2: "?s2".parse().unwrap(),
-                     l1: "?l1".parse().unwrap(),
-                     l2: "?l2".parse().unwrap(),
-                     strides: "?s".parse().unwrap(),
-                    dim: "?d".parse().unwrap()
-            }}),
-            rewrite!("concat-dot";
-                     "(DotGeneralOp (ConcatenateOp (Vec ?a ?b) ?d1) (ConcatenateOp (Vec ?c ?d) ?d2) ?lb ?rb ?lc ?rc ?p)"
-                     => "(AddOp (DotGeneralOp ?a ?c ?lb ?rb ?lc ?rc ?p) (DotGeneralOp ?b ?d ?lb ?rb ?lc ?rc ?p))"
-                     if concat_dot_compatible("?lc", "?d1", "?rc", "?d2")),
-        ];
-
-        rules.append(&mut custom_rules);
-
-        let iter_multi = 2;
-        let node_multi = 30000;
-        let multi_rules: Vec<(&str, bool)> = PRE_DEFINED_MULTI
+            let data = &self.tensor(tensor).tensor_data;
+            let name = if data.constant {
+                format!("w_{}", k)
+            } else {
+                format!("input_{}", k)
+            };
+            input_var_name(&name, &self.tensor_shape(tensor), data.dtype)
+        };
+        let region = cut_region(
+            &self.rec_expr,
+            &ids(producers),
+            &ids(consumers),
+            boundary_var,
+        );
+        println!(
+            "Optimizing a region of {} nodes with {} boundary tensors",
+            region.expr.as_ref().len(),
+            region.boundary.len()
+        );
+        let new_ids: HashMap<Id, Id> = region
+            .origins
             .iter()
-            .map(|&x| (x, /*symmetric=*/ false))
+            .enumerate()
+            .filter_map(|(i, origin)| origin.map(|o| (Id::from(o), Id::from(i))))
             .collect();
-        let mut multi_patterns = MultiPatterns::with_rules(
-            multi_rules,
-            no_cycle,
-            iter_multi,
-            filter_after,
-            node_multi,
-            n_sec,
-        );
+        let converter = CppGraphConverter {
+            rec_expr: region.expr,
+            region_results: self.region_results.clone(),
+            dense_constants: self.dense_constants.clone(),
+            blackbox_cpp_num_to_tensorinfo: self.blackbox_cpp_num_to_tensorinfo.clone(),
+            composite_ops: self.composite_ops.clone(),
+            config: self.config.clone(),
+            shardings: self
+                .shardings
+                .iter()
+                .filter_map(|(id, sharding)| Some((*new_ids.get(id)?, sharding.clone())))
+                .collect(),
+            protected: self
+                .protected
+                .iter()
+                .filter_map(|id| new_ids.get(id).copied())
+                .collect(),
+            session: self.session.clone(),
+            ..Default::default()
+        };
+        let mut graph = converter.optimize();
+        for node in graph.nodes.iter_mut() {
+            node.origin = match region.origins.get(node.origin as usize) {
+                Some(Some(origin)) if node.origin >= 0 => *origin as i32,
+                _ => -1,
+            };
+        }
+        ffi::RegionResult {
+            graph,
+            boundary: region
+                .boundary
+                .iter()
+                .map(|id| usize::from(*id) as i32)
+                .collect(),
+        }
+    }
+
+    /// Run saturation and extraction
+    ///
+    /// # Returns
+    ///
+    /// The optimized graph for the C++ side, the RecExpr saturation started from
+    /// and the extracted RecExpr.
+    fn run_optimize(&self) -> (ffi::Graph, RecExpr<Mdl>, RecExpr<Mdl>) {
+        self.stats.borrow_mut().clear();
+        let budget = self
+            .config
+            .time_budget_sec
+            .map(|secs| TimeBudget::new(Duration::from_secs(secs)));
+        self.optimize_regions(budget.as_ref());
+        if self.config.identity_mode {
+            println!("Identity mode: returning the input graph");
+            let input = self.rooted_expr();
+            let mut nodes = self.convert_to_node(input.clone());
+            // Every node survives, so its origin is its index in the input (the
+            // declared ReturnOp's, for the root added again after it)
+            let num_nodes = self.rec_expr.as_ref().len();
+            for (node, origin) in nodes.iter_mut().zip(reachable_indices(&input)) {
+                node.origin = match self.returned {
+                    Some(returned) if origin >= num_nodes => usize::from(returned) as i32,
+                    _ => origin as i32,
+                };
+            }
+            let graph = nodes_to_graph(nodes);
+            if let Err(e) = validate_graph(&graph) {
+                panic!("Invalid input graph: {}", e);
+            }
+            return (graph, input.clone(), input);
+        }
+
+        if let Some(filter) = &self.config.log_filter {
+            // Fails if the host (or an earlier call) installed a logger already
+            let _ = env_logger::Builder::new().parse_filters(filter).try_init();
+        }
+        let mut profiler = Profiler::new();
+        // What the regions left of the budget
+        let budget = budget.map(|budget| TimeBudget::new(budget.remaining()));
+        let start = profiler.phase("convert input", || self.start_graph());
+        let rules = self.saturation_rules();
+        let do_filter_after = self.config.no_cycle && self.config.filter_after;
 
         let start_time = Instant::now();
+        let applied = Rc::new(RefCell::new(Vec::new()));
+        let (mut runner, resumed_iterations) =
+            self.resume(self.new_runner(&start, Some(self.config.n_sec)), &start);
+        if let Some(budget) = &budget {
+            runner = runner
+                .with_time_limit(budget.saturation_limit())
+                .with_hook(budget.saturation_hook());
+        }
+        if !self.shardings.is_empty() {
+            runner.egraph.analysis.shardings =
+                sharded_classes(&runner.egraph, &self.rec_expr, &self.shardings);
+        }
+        if self.config.track_provenance || self.config.rule_trace.is_some() {
+            let learned_rules = self.learned_rules();
+            let rules = split_rules(&learned_rules);
+            runner = runner.with_scheduler(
+                RecordingScheduler::new(applied.clone(), None, self.scheduler())
+                    .with_patterns(rule_sides_from_str(&rules)),
+            );
+        }
+        let stats = Rc::new(RefCell::new(Vec::new()));
+        if self.config.stats_every > 0 {
+            // Like snapshots, the statistics at iteration i are the ones of the
+            // e-graph after i iterations
+            let stats = stats.clone();
+            let stats_every = self.config.stats_every;
+            runner = runner.with_hook(move |runner| {
+                let iteration = runner.iterations.len();
+                if iteration % stats_every == 0 {
+                    stats
+                        .borrow_mut()
+                        .push(get_stats(&runner.egraph, iteration));
+                }
+                Ok(())
+            });
+        }
         let mut runner = runner.run(&rules[..]);
         if do_filter_after {
             // Do cycle removal after the final iteration
@@ -1144,6 +4254,21 @@ impl CppGraphConverter {
         }
         let sat_duration = start_time.elapsed();
         let num_iter_sat = runner.iterations.len() - 1;
+        if let Some(path) = &self.config.save_state {
+            let iterations = resumed_iterations + num_iter_sat;
+            let state = SaturationState::new(&runner.egraph, runner.roots[0], iterations);
+            match state.save(path) {
+                Ok(()) => println!("Saved the saturation state to {}", path),
+                Err(e) => println!("Could not save the saturation state: {}", e),
+            }
+        }
+        profiler.record("saturation", start_time, sat_duration);
+        record_iterations(&mut profiler, &runner, start_time);
+        profiler.record(
+            "shape inference",
+            start_time,
+            runner.egraph.analysis.shape_time(),
+        );
 
         println!("Runner complete!");
         println!("  Nodes: {}", runner.egraph.total_size());
@@ -1152,20 +4277,705 @@ impl CppGraphConverter {
         println!("  Time taken: {:?}", sat_duration);
         println!("  Number of iterations: {:?}", num_iter_sat);
 
-        let (num_enodes, num_classes, avg_nodes_per_class, num_edges, num_programs) =
-            get_stats(&runner.egraph);
-        println!("  Average nodes per class: {}", avg_nodes_per_class);
-        println!("  Number of edges: {}", num_edges);
-        println!("  Number of programs: {}", num_programs);
+        let final_stats = get_stats(&runner.egraph, runner.iterations.len());
+        println!(
+            "  Average nodes per class: {}",
+            final_stats.avg_nodes_per_class
+        );
+        println!("  Number of edges: {}", final_stats.num_edges);
+        println!("  Number of programs: {}", final_stats.num_programs);
+        metrics::increment(names::OPTIMIZATIONS, 1.0);
+        metrics::observe(names::SATURATION_SECONDS, sat_duration.as_secs_f64());
+        metrics::observe(names::SATURATION_ITERATIONS, num_iter_sat as f64);
+        metrics::observe(names::EGRAPH_KNODES, final_stats.num_enodes as f64 / 1000.0);
+        stats.borrow_mut().push(final_stats);
+        *self.stats.borrow_mut() = stats.take();
+
+        let (mut egraph, root) = (runner.egraph, runner.roots[0]);
+        let mut protected = self.protected.clone();
+        protected.extend(barrier_operands(&self.rec_expr));
+        if !protected.is_empty() {
+            let num_blacklisted = protect_nodes(&mut egraph, &self.rec_expr, &protected);
+            println!(
+                "Blacklisted {} enodes replacing protected ops",
+                num_blacklisted
+            );
+        }
+        let cost_model = self.cost_model();
+        let by_ilp = cfg!(feature = "ilp-extractor") && self.config.extractor == ExtractorKind::Ilp;
+        // Rematerialization trades extra compute for memory, and sharing
+        // bonuses trade it for fewer duplicated subgraphs, so these solutions
+        // are expected to cost more than greedy extraction
+        let check_greedy = by_ilp
+            && self.config.check_against_greedy
+            && self.config.remat_weight.is_none()
+            && self.config.sharing_bonus.is_none();
+        // The solver gets what is left of the time budget
+        let ilp_config = match &budget {
+            Some(budget) => OptimizeConfig {
+                ilp_time_sec: budget.extraction_secs(),
+                ..self.config.clone()
+            },
+            None => self.config.clone(),
+        };
+        let extract = |model: &CostModel, profiler: &mut Profiler| match self.config.extractor {
+            #[cfg(feature = "ilp-extractor")]
+            ExtractorKind::Ilp => match extract_by_ilp(&egraph, root, model, &ilp_config, profiler)
+            {
+                Ok(extracted) => extracted,
+                Err(e) => {
+                    eprintln!("ERROR: {}, extracting greedily instead", e);
+                    profiler.phase("greedy extraction", || {
+                        extract_by_greedy(&egraph, root, model)
+                    })
+                }
+            },
+            _ => profiler.phase("greedy extraction", || {
+                extract_by_greedy(&egraph, root, model)
+            }),
+        };
+        // The objective only picks what extraction minimizes, the rest of
+        // optimize (remarks, reports, ...) is still in terms of cost
+        let objective_model = match self.config.objective {
+            Objective::Cost => None,
+            Objective::NodeCount => Some(CostModel::op_count()),
+        };
+        let extraction_model = objective_model.as_ref().unwrap_or(&cost_model);
+        let (best, ext_secs) = extract(extraction_model, &mut profiler);
+        metrics::observe(names::EXTRACTION_SECONDS, ext_secs as f64);
+        let best = if check_greedy {
+            profiler.phase("greedy check", || {
+                self.check_against_greedy(&egraph, root, extraction_model, best)
+            })
+        } else {
+            best
+        };
+        let best = match (&objective_model, self.config.max_cost_regression) {
+            (Some(_), Some(max_regression)) => {
+                let original_cost = graph_cost(&start, self.analysis(), &cost_model);
+                let cost = graph_cost(&best, self.analysis(), &cost_model);
+                if cost > original_cost * (1.0 + max_regression as f64) {
+                    println!(
+                        "Graph with the fewest ops costs {} against {} for the input graph, extracting by cost instead",
+                        cost, original_cost
+                    );
+                    extract(&cost_model, &mut profiler).0
+                } else {
+                    best
+                }
+            }
+            _ => best,
+        };
+        // The peephole pass also prunes unreachable nodes, so the per-node data
+        // below lines up with the nodes convert_to_node emits
+        let best = profiler.phase("peephole", || peephole(&best, self.analysis()));
+        let best = if self.config.numeric_check {
+            profiler.phase("numeric check", || self.numeric_check(&start, best))
+        } else {
+            best
+        };
+        let provenance = if self.config.track_provenance {
+            node_provenance(&egraph, &start, &best, &applied.borrow())
+        } else {
+            vec![vec![]; best.as_ref().len()]
+        };
+        let remarks = if self.config.track_provenance {
+            node_remarks(&egraph, &start, &best, &provenance, &cost_model)
+        } else {
+            vec![String::new(); best.as_ref().len()]
+        };
+        let origins = node_origins(&egraph, &self.rec_expr, &best);
+        let shardings = node_shardings(&egraph, &best);
+        let tensor_names = node_names(&egraph, &best);
+
+        if let Some(trace_file) = &self.config.rule_trace {
+            let num_written = write_rule_trace(
+                &egraph,
+                &start,
+                &best,
+                &applied.borrow(),
+                &cost_model,
+                trace_file,
+            )
+            .expect("Unable to write rule trace");
+            println!("Wrote {} substitutions to {}", num_written, trace_file);
+        }
 
-        let (egraph, root) = (runner.egraph, runner.roots[0]);
-        let cost_model: CostModel = CostModel::new();
-        let (best, ext_secs) = extract_by_ilp(&egraph, root, &cost_model);
-        // let (best, ext_secs) = extract_by_greedy(&egraph, root, &cost_model);
+        if let Some(diff_file) = &self.config.diff_report {
+            let diff = diff_graphs(&egraph, &start, &best, &cost_model);
+            write(diff_file, diff.to_string()).expect("Unable to write diff report");
+            println!("Wrote graph diff to {}", diff_file);
+        }
 
         // println!("{}", best);
-        self.convert_to_node(best)
+        let mut nodes = profiler.phase("convert output", || self.convert_to_node(best.clone()));
+        for (((((node, rules), remark), origin), sharding), name) in nodes
+            .iter_mut()
+            .zip(provenance)
+            .zip(remarks)
+            .zip(origins)
+            .zip(shardings)
+            .zip(tensor_names)
+        {
+            node.provenance = rules;
+            node.remark = remark;
+            node.origin = origin;
+            node.sharding = sharding;
+            if let (true, Some(name)) = (node.label.is_empty(), name) {
+                node.label = name;
+            }
+        }
+        let graph = nodes_to_graph(nodes);
+        if let Err(e) = validate_graph(&graph) {
+            panic!("Invalid optimized graph: {}", e);
+        }
+        if let Some(profile_file) = &self.config.profile_file {
+            profiler
+                .write(profile_file, self.config.profile_chrome_trace)
+                .expect("Unable to write profile");
+            println!("Wrote profile to {}", profile_file);
+        }
+        let optimized_cost = graph_cost(&best, self.analysis(), &cost_model);
+        if optimized_cost > 0.0 {
+            let original_cost = graph_cost(&start, self.analysis(), &cost_model);
+            metrics::observe(names::COST_IMPROVEMENT, original_cost / optimized_cost);
+        }
+        *self.saturated.borrow_mut() = Some((egraph, root));
+        (graph, start, best)
+    }
+
+    /// Optimize every region on its own, with the settings of optimize and a
+    /// share of its time budget, for region_graph. WhileOps don't change, so
+    /// the regions they refer to stay valid
+    fn optimize_regions(&self, budget: Option<&TimeBudget>) {
+        let optimized = self
+            .regions
+            .iter()
+            .enumerate()
+            .map(|(k, region)| {
+                println!("Optimizing region {} of {} nodes", k, region.as_ref().len());
+                // Saturation states and snapshots are the ones of the
+                // enclosing graph, and the time budget is shared with it
+                let regions_left = self.regions.len() - k;
+                let config = OptimizeConfig {
+                    resume_from: None,
+                    save_state: None,
+                    snapshot_dir: None,
+                    time_budget_sec: budget
+                        .map(|budget| region_share(budget.remaining(), regions_left)),
+                    ..self.config.clone()
+                };
+                // The region's own IfOps need the results of the other regions
+                let converter = CppGraphConverter {
+                    rec_expr: region.clone(),
+                    region_results: self.region_results.clone(),
+                    dense_constants: self.dense_constants.clone(),
+                    blackbox_cpp_num_to_tensorinfo: self.blackbox_cpp_num_to_tensorinfo.clone(),
+                    composite_ops: self.composite_ops.clone(),
+                    config,
+                    session: self.session.clone(),
+                    ..Default::default()
+                };
+                converter.optimize()
+            })
+            .collect();
+        *self.optimized_regions.borrow_mut() = optimized;
     }
+
+    /// Compare the cost of the ILP solution with greedy extraction on the same
+    /// e-graph. The ILP optimum can never be worse, so a worse solution points to
+    /// a bug in the formulation or the data preparation; it is reported and the
+    /// greedy graph is used instead.
+    fn check_against_greedy(
+        &self,
+        egraph: &EGraph<Mdl, TensorAnalysis>,
+        root: Id,
+        cost_model: &CostModel,
+        ilp_best: RecExpr<Mdl>,
+    ) -> RecExpr<Mdl> {
+        let (greedy_best, _) = extract_by_greedy(egraph, root, cost_model);
+        let ilp_cost = graph_cost(&ilp_best, self.analysis(), cost_model);
+        let greedy_cost = graph_cost(&greedy_best, self.analysis(), cost_model);
+        if ilp_cost > greedy_cost * (1.0 + 1e-4) {
+            eprintln!(
+                "ERROR: ILP extraction cost {} is worse than greedy extraction cost {}, using the greedy graph",
+                ilp_cost, greedy_cost
+            );
+            greedy_best
+        } else {
+            ilp_best
+        }
+    }
+
+    /// Run optimize twice and check both runs return exactly the same nodes
+    ///
+    /// Guards against nondeterminism creeping in through hash map iteration order
+    /// or parallelism. Prints the first differing node if the runs disagree.
+    pub fn check_determinism(&self) -> bool {
+        let first = graph_to_string(&self.run_optimize().0);
+        let second = graph_to_string(&self.run_optimize().0);
+        if first == second {
+            return true;
+        }
+        if let Some((a, b)) = first.lines().zip(second.lines()).find(|(a, b)| a != b) {
+            println!("Runs differ:\n  {}\n  {}", a, b);
+        } else {
+            println!("Runs differ in their number of nodes");
+        }
+        false
+    }
+
+    /// Fall back to `start` if `best` doesn't compute the same results as the
+    /// input graph (rooted at its declared results) on random inputs. Graphs
+    /// the interpreter can't evaluate are let through.
+    fn numeric_check(&self, start: &RecExpr<Mdl>, best: RecExpr<Mdl>) -> RecExpr<Mdl> {
+        let result = numerically_equivalent(
+            &self.rooted_expr(),
+            &best,
+            || self.analysis(),
+            self.config.numeric_tolerance,
+            self.config.seed,
+        );
+        match result {
+            Ok(true) => best,
+            Ok(false) => {
+                println!("Optimized graph computes different results, returning the input graph");
+                start.clone()
+            }
+            Err(e) => {
+                println!("Skipping the numeric check: {}", e);
+                best
+            }
+        }
+    }
+
+    /// Check the substitutions applied during saturation with the reference
+    /// interpreter. Saturation is replayed with growing prefixes of the applied
+    /// substitutions to find the first one after which the (greedily) extracted
+    /// graph computes different outputs on random inputs.
+    ///
+    /// Returns the index of that substitution in application order, or -1 if the
+    /// outputs are preserved (or the graph uses ops the interpreter can't run).
+    pub fn verify_by_replay(&self) -> i32 {
+        let start = self.start_graph();
+        let rules = self.saturation_rules();
+        let cost_model = self.cost_model();
+        // Replays are only comparable if they stop at the same point, so they
+        // are limited by iterations and substitutions but not by time
+        let result = bisect_substitutions(
+            &start,
+            || self.new_runner(&start, None),
+            || self.scheduler(),
+            || self.analysis(),
+            &rules,
+            &cost_model,
+            /*tolerance=*/ 1e-3,
+            /*seed=*/ 0,
+        );
+        match result {
+            Ok(Some((index, applied))) => {
+                println!(
+                    "Substitution {} ({} at eclass {} in iteration {}) changes the outputs",
+                    index, applied.rule, applied.eclass, applied.iteration
+                );
+                index as i32
+            }
+            Ok(None) => {
+                println!("All substitutions preserve the outputs");
+                -1
+            }
+            Err(e) => {
+                println!("Could not verify by replay: {}", e);
+                -1
+            }
+        }
+    }
+}
+
+/// Contents of the rule file of `config`
+pub(crate) fn read_learned_rules(config: &OptimizeConfig) -> String {
+    read_to_string(&config.rule_file).expect("Something went wrong reading the rule file")
+}
+
+fn split_rules(learned_rules: &str) -> Vec<&str> {
+    let pre_defined_rules = PRE_DEFINED_RULES.iter().map(|&x| x);
+    learned_rules.split("\n").chain(pre_defined_rules).collect()
+}
+
+/// The rules of optimize that don't depend on the graph: the learned rules
+/// (the contents of the rule file), the pre-defined rules, the custom appliers
+/// and the rules of the enabled searches
+pub(crate) fn base_rules(
+    config: &OptimizeConfig,
+    learned_rules: &str,
+) -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    let path = std::env::current_dir().unwrap();
+    println!("The current directory is {}", path.display());
+
+    let split_rules = split_rules(learned_rules);
+    let do_filter_after = config.no_cycle && config.filter_after;
+    let mut rules = rules_from_str(split_rules, do_filter_after);
+
+    let mut custom_rules: Vec<Rewrite<Mdl, TensorAnalysis>> = vec![
+        rewrite!("transpose-of-transpose";
+                 "(TransposeOp (TransposeOp ?x ?p) ?p)" => "?x" if decreasing_perm("?p")),
+        rewrite!("flatten-concat";
+                 "(ConcatenateOp ?v ?d)" => { FlattenConcat {
+                 vec: "?v".parse().unwrap(),
+                 dim: "?d".parse().unwrap(),
+        }}),
+        rewrite!("merge-slices";
+                 "(ConcatenateOp (Vec (SliceOp ?x ?s1 ?l1 ?s) (SliceOp ?x ?s2 ?l2 ?s)) ?d)" => { MergeSlices {
+                 x: "?x".parse().unwrap(),
+                 s1: "?s1".parse().unwrap(),
+                 s2: "?s2".parse().unwrap(),
+                 l1: "?l1".parse().unwrap(),
+                 l2: "?l2".parse().unwrap(),
+                 strides: "?s".parse().unwrap(),
+                dim: "?d".parse().unwrap()
+        }}),
+        rewrite!("concat-dot";
+                 "(DotGeneralOp (ConcatenateOp (Vec ?a ?b) ?d1) (ConcatenateOp (Vec ?c ?d) ?d2) ?lb ?rb ?lc ?rc ?p)"
+                 => "(AddOp (DotGeneralOp ?a ?c ?lb ?rb ?lc ?rc ?p) (DotGeneralOp ?b ?d ?lb ?rb ?lc ?rc ?p))"
+                 if concat_dot_compatible("?lc", "?d1", "?rc", "?d2")),
+        rewrite!("slice-of-pad";
+                 "(SliceOp (PadOp ?x ?v ?lo ?hi ?int) ?start ?limit ?s)" => "?x"
+                 if slice_removes_padding("?x", "?lo", "?int", "?start", "?limit", "?s")),
+    ];
+
+    rules.append(&mut custom_rules);
+    rules.append(&mut gelu_rules());
+    if config.approximate_gelu {
+        rules.append(&mut gelu_approximation_rules());
+    }
+    rules.append(&mut clamp_rules());
+    rules.append(&mut unary_rules());
+    rules.append(&mut bitwise_rules());
+    rules.append(&mut remainder_rules());
+    rules.append(&mut complex_rules());
+    rules.append(&mut tuple_rules());
+    rules.append(&mut collective_rules());
+    rules.append(&mut constant_rules());
+    if config.layout_search {
+        rules.append(&mut layout_rules());
+    }
+    rules.append(&mut precision_rules(config.mixed_precision));
+    rules.append(&mut weight_rules());
+    rules
+}
+
+/// Name of the op of `node` in ffi::Node. This is the op name from the Mdl
+/// definition, except for the few ops the C++ side knows under another name,
+/// and Var and Num, whose display_op is their data.
+fn ffi_op_name(node: &Mdl) -> String {
+    match node {
+        Mdl::Input(_) => "Input".to_string(),
+        Mdl::BlackBox(_) => "blackbox".to_string(),
+        Mdl::Var(_) => "Var".to_string(),
+        Mdl::Num(_) => "Num".to_string(),
+        x => x.display_op().to_string(),
+    }
+}
+
+/// Convert a RecExpr into the node list passed to the C++ side
+///
+/// Only nodes reachable from the root (the last node) are emitted, in their
+/// original order. Operands of a node are indices into the returned list. Var nodes carry their
+/// name as label, Num nodes carry their value in `value`, and
+/// CompositeOp nodes carry the name of their definition in `composite_ops` as
+/// label (the registry index is dropped from the operands).
+pub fn rec_expr_to_nodes(
+    rec_expr: &RecExpr<Mdl>,
+    composite_ops: &[CompositeOpDef],
+) -> Vec<ffi::Node> {
+    let rec_expr = &prune_unreachable(rec_expr);
+    let rec_expr_ref = rec_expr.as_ref();
+    let convert = |operands: &[Id]| {
+        operands
+            .iter()
+            .map(|id| usize::from(*id) as i32)
+            .collect::<Vec<i32>>()
+    };
+
+    let mut res: Vec<ffi::Node> = rec_expr_ref
+        .iter()
+        .map(|mdl| {
+            let (label, operands) = match mdl {
+                Mdl::Var(label) => (label.to_string(), vec![]),
+                Mdl::CompositeOp(ops) => {
+                    let (index, operands) = ops.split_last().unwrap();
+                    let index = match rec_expr_ref[usize::from(*index)] {
+                        Mdl::Num(n) => n as usize,
+                        _ => panic!("CompositeOp index is not a Num"),
+                    };
+                    (composite_ops[index].name.clone(), convert(operands))
+                }
+                x => ("".to_string(), convert(x.children())),
+            };
+            ffi::Node {
+                name: ffi_op_name(mdl),
+                label,
+                operands,
+                value: match mdl {
+                    Mdl::Num(num) => *num,
+                    _ => 0,
+                },
+                fusion_group: -1,
+                provenance: vec![],
+                shape: vec![],
+                dtype: ffi::Type::f32,
+                origin: -1,
+                block_arg: -1,
+                remark: String::new(),
+                sharding: String::new(),
+            }
+        })
+        .collect();
+
+    for (i, mdl) in rec_expr_ref.iter().enumerate() {
+        if let Mdl::Input([name, block_arg]) = mdl {
+            if let Mdl::Num(block_arg) = rec_expr_ref[usize::from(*block_arg)] {
+                res[i].block_arg = block_arg as i32;
+                res[usize::from(*name)].block_arg = block_arg as i32;
+            }
+        }
+    }
+
+    for (node, group) in res.iter_mut().zip(fusion_groups(rec_expr)) {
+        node.fusion_group = group;
+    }
+    res
+}
+
+/// Values computed by the graph with the given root: the elements of the Vec
+/// returned by a ReturnOp root, the root itself otherwise
+fn graph_results(nodes: &[ffi::Node], root: i32) -> Vec<i32> {
+    let node = &nodes[root as usize];
+    if node.name != "ReturnOp" {
+        return vec![root];
+    }
+    let returned = &nodes[node.operands[0] as usize];
+    if returned.name == "Vec" {
+        returned.operands.clone()
+    } else {
+        node.operands.clone()
+    }
+}
+
+/// Wrap the nodes converted from a RecExpr (whose root is the last node) into a
+/// Graph with explicit root and results
+pub fn nodes_to_graph(nodes: Vec<ffi::Node>) -> ffi::Graph {
+    let root = nodes.len() as i32 - 1;
+    let results = if nodes.is_empty() {
+        vec![]
+    } else {
+        graph_results(&nodes, root)
+    };
+    let schedule = memory_schedule(&nodes, &results);
+    ffi::Graph {
+        nodes,
+        root,
+        results,
+        schedule,
+    }
+}
+
+/// Check the guarantees of ffi::Graph: nodes are topologically ordered, the
+/// root and results are valid indices, the results are the outputs of the
+/// root, and the schedule is a valid execution order.
+pub fn validate_graph(graph: &ffi::Graph) -> Result<(), String> {
+    let num_nodes = graph.nodes.len() as i32;
+    for (i, node) in graph.nodes.iter().enumerate() {
+        if let Some(operand) = node.operands.iter().find(|&&o| o < 0 || o >= i as i32) {
+            return Err(format!(
+                "Node {} ({}) uses node {}, which does not come before it",
+                i, node.name, operand
+            ));
+        }
+    }
+    if graph.root < 0 || graph.root >= num_nodes {
+        return Err(format!("Root {} is out of range", graph.root));
+    }
+    let expected_results = graph_results(&graph.nodes, graph.root);
+    if graph.results != expected_results {
+        return Err(format!(
+            "Results {:?} are not the outputs of the root {:?}",
+            graph.results, expected_results
+        ));
+    }
+    check_schedule(&graph.nodes, &graph.schedule)
+}
+
+/// Text form of a graph with one line per node, listing every field. Equal
+/// graphs give byte-identical strings.
+pub fn graph_to_string(graph: &ffi::Graph) -> String {
+    let mut s = format!(
+        "root {} results {:?} schedule {:?}\n",
+        graph.root, graph.results, graph.schedule
+    );
+    for (i, node) in graph.nodes.iter().enumerate() {
+        s += &format!(
+            "{}: {} {} {:?} value {} fusion {} provenance {:?} shape {:?} dtype {} origin {} block_arg {}\n",
+            i,
+            node.name,
+            node.label,
+            node.operands,
+            node.value,
+            node.fusion_group,
+            node.provenance,
+            node.shape,
+            node.dtype.repr,
+            node.origin,
+            node.block_arg
+        );
+    }
+    s
+}
+
+/// Infer the element type of every node of `rec_expr`
+///
+/// Like the e-graph analysis (`TensorData::dtype`), inputs have the type in the
+/// name of their Var (see input_var_name), and the other tensors entering the
+/// graph (constants, iotas, blackbox results) are f32. CompareOp produces i1,
+/// ConvertOp produces its output type (given with the discriminants of
+/// ffi::Type), and other ops keep the type of their first tensor operand.
+/// Non-tensor nodes get f32, and so do IfOps and their results, whose branches
+/// aren't known here (see infer_dtypes_with_regions).
+pub fn infer_dtypes(rec_expr: &RecExpr<Mdl>) -> Vec<ffi::Type> {
+    infer_dtypes_with_regions(rec_expr, &[])
+}
+
+/// Infer the element type of every node of `rec_expr` like infer_dtypes, with
+/// the results of IfOps typed by the values their branches yield, given by
+/// `region_dtypes` for each region
+pub fn infer_dtypes_with_regions(
+    rec_expr: &RecExpr<Mdl>,
+    region_dtypes: &[Vec<ffi::Type>],
+) -> Vec<ffi::Type> {
+    let nodes = rec_expr.as_ref();
+    let yielded = |op: &Id, i: usize| match &nodes[usize::from(*op)] {
+        Mdl::IfOp([_, _, true_branch, _]) => match nodes[usize::from(*true_branch)] {
+            Mdl::Num(region) => region_dtypes.get(region as usize)?.get(i).copied(),
+            _ => None,
+        },
+        _ => None,
+    };
+    let mut dtypes: Vec<ffi::Type> = Vec::with_capacity(nodes.len());
+    for (k, node) in nodes.iter().enumerate() {
+        let operand = |id: &Id| dtypes[usize::from(*id)];
+        let dtype = match node {
+            Mdl::CompareOp(_) => ffi::Type::i1,
+            Mdl::ComplexOp(_) => ffi::Type::c64,
+            Mdl::RealOp(_) | Mdl::ImagOp(_) => ffi::Type::f32,
+            Mdl::FftOp([_, fft_type, _]) => match nodes[usize::from(*fft_type)] {
+                Mdl::Num(num) => {
+                    FftType::from_num(num).map_or(ffi::Type::f32, FftType::result_dtype)
+                }
+                _ => ffi::Type::f32,
+            },
+            Mdl::ConvertOp([_, output_type]) | Mdl::DenseConstantOp([_, _, output_type]) => {
+                match nodes[usize::from(*output_type)] {
+                    Mdl::Num(num) => dtype_from_num(num),
+                    _ => ffi::Type::f32,
+                }
+            }
+            Mdl::SelectOp([_, on_true, _]) => operand(on_true),
+            Mdl::ConcatenateOp([inputs, _])
+            | Mdl::SortOp([inputs, ..])
+            | Mdl::WhileOp([inputs, ..])
+            | Mdl::ReduceOp([inputs, ..])
+            | Mdl::TupleOp([inputs])
+            | Mdl::OptimizationBarrierOp([inputs]) => match &nodes[usize::from(*inputs)] {
+                Mdl::Vec(inputs) if !inputs.is_empty() => operand(&inputs[0]),
+                _ => ffi::Type::f32,
+            },
+            Mdl::Var(name) => var_dtype(name.as_str()),
+            Mdl::Input([name, _]) => operand(name),
+            Mdl::IotaOp(_) | Mdl::ConstantOp(_) | Mdl::BlackBox(_) | Mdl::Num(_) | Mdl::Vec(_) => {
+                ffi::Type::f32
+            }
+            Mdl::IfOp(_) => yielded(&Id::from(k), 0).unwrap_or(ffi::Type::f32),
+            Mdl::CustomCallOp([_, _, _, dtype]) | Mdl::RngBitGeneratorOp([_, _, _, dtype]) => {
+                match nodes[usize::from(*dtype)] {
+                    Mdl::Num(num) => dtype_from_num(num),
+                    _ => ffi::Type::f32,
+                }
+            }
+            Mdl::Index([index, input]) => {
+                let yielded = match nodes[usize::from(*index)] {
+                    Mdl::Num(i) => yielded(input, i as usize),
+                    _ => None,
+                };
+                yielded.unwrap_or_else(|| {
+                    operand(&forwarded_input(nodes, *index, *input).unwrap_or(*input))
+                })
+            }
+            Mdl::GetTupleElementOp([tuple, index]) => {
+                operand(&forwarded_input(nodes, *index, *tuple).unwrap_or(*tuple))
+            }
+            x => operand(&x.children()[0]),
+        };
+        dtypes.push(dtype);
+    }
+    dtypes
+}
+
+/// Input of `op` whose (sorted, updated or reduced) value is its result
+/// `index`, if `op` is a SortOp, WhileOp, ReduceOp, TupleOp or
+/// OptimizationBarrierOp, or the state the new state of a RngBitGeneratorOp
+/// comes from
+fn forwarded_input(nodes: &[Mdl], index: Id, op: Id) -> Option<Id> {
+    match (&nodes[usize::from(index)], &nodes[usize::from(op)]) {
+        (Mdl::Num(0), Mdl::RngBitGeneratorOp([initial_state, ..])) => Some(*initial_state),
+        (
+            Mdl::Num(i),
+            Mdl::SortOp([inputs, ..])
+            | Mdl::WhileOp([inputs, ..])
+            | Mdl::ReduceOp([inputs, ..])
+            | Mdl::TupleOp([inputs])
+            | Mdl::OptimizationBarrierOp([inputs]),
+        ) => match &nodes[usize::from(*inputs)] {
+            Mdl::Vec(inputs) => inputs.get(*i as usize).copied(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Convert a node list produced by `rec_expr_to_nodes` back into a RecExpr
+pub fn nodes_to_rec_expr(
+    nodes: &[ffi::Node],
+    composite_ops: &[CompositeOpDef],
+) -> Result<RecExpr<Mdl>, String> {
+    let mut rec_expr = RecExpr::default();
+    // Id in rec_expr of each node, since CompositeOps add their index as a node
+    let mut ids: Vec<Id> = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let children = || {
+            node.operands
+                .iter()
+                .map(|i| ids[*i as usize])
+                .collect::<Vec<Id>>()
+        };
+        let mdl = match node.name.as_str() {
+            "Var" => Mdl::Var(Symbol::from(&node.label)),
+            "Num" => Mdl::Num(node.value),
+            "CompositeOp" => {
+                let index = composite_ops
+                    .iter()
+                    .position(|def| def.name == node.label)
+                    .ok_or_else(|| format!("Unknown composite op {}", node.label))?;
+                let mut operands = children();
+                operands.push(rec_expr.add(Mdl::Num(index as i64)));
+                Mdl::CompositeOp(operands.into())
+            }
+            "Input" => Mdl::from_op_str("input", children())?,
+            "blackbox" => Mdl::from_op_str("BlackBox", children())?,
+            name => Mdl::from_op_str(name, children())?,
+        };
+        ids.push(rec_expr.add(mdl));
+    }
+    Ok(rec_expr)
 }
 
 fn extract_by_greedy(
@@ -1177,6 +4987,7 @@ fn extract_by_greedy(
     let start_time = Instant::now();
     let mut extractor = Extractor::new(egraph, tnsr_cost);
     let (best_cost, best) = extractor.find_best(root);
+    let best = share_eclasses(egraph, &best);
     let duration = start_time.elapsed();
 
     println!("Extractor complete!");
@@ -1187,17 +4998,102 @@ fn extract_by_greedy(
     (best, ext_secs)
 }
 
+/// Record the search, apply and rebuild time of each saturation iteration.
+/// egg only keeps durations, so the iterations are laid out back to back from
+/// `start_time`.
+fn record_iterations(
+    profiler: &mut Profiler,
+    runner: &Runner<Mdl, TensorAnalysis, ()>,
+    start_time: Instant,
+) {
+    let mut iteration_start = start_time;
+    for (i, iteration) in runner.iterations.iter().enumerate() {
+        let mut phase_start = iteration_start;
+        for (phase, secs) in [
+            ("search", iteration.search_time),
+            ("apply", iteration.apply_time),
+            ("rebuild", iteration.rebuild_time),
+        ] {
+            let duration = Duration::from_secs_f64(secs);
+            profiler.record(&format!("iteration {} {}", i, phase), phase_start, duration);
+            phase_start += duration;
+        }
+        iteration_start += Duration::from_secs_f64(iteration.total_time);
+    }
+}
+
+#[cfg(feature = "ilp-extractor")]
 fn extract_by_ilp(
     egraph: &EGraph<Mdl, TensorAnalysis>,
     root: Id,
     cost_model: &CostModel,
-) -> (RecExpr<Mdl>, f32) {
+    config: &OptimizeConfig,
+    profiler: &mut Profiler,
+) -> Result<(RecExpr<Mdl>, f32), IlpError> {
     // Prepare data for ILP formulation, save to json
     let (m_id_map, e_m, h_i, cost_i, g_i, root_m, i_to_nodes, blacklist_i) =
-        prep_ilp_data(egraph, root, cost_model);
+        profiler.phase("ilp prep", || prep_ilp_data(egraph, root, cost_model));
+
+    // The data of the optional parts of the ILP
+    let remat = config.remat_weight.map(|remat_weight| {
+        let mem_i: Vec<f64> = i_to_nodes
+            .iter()
+            .zip(g_i.iter())
+            .map(|(node, m)| output_bytes(egraph, m_id_map[*m], node))
+            .collect();
+        // Eclasses of random values are computed by a single node
+        let single_m: Vec<usize> = (0..e_m.len())
+            .filter(|m| e_m[*m].iter().any(|i| is_random(i_to_nodes[*i])))
+            .collect();
+        (mem_i, remat_weight, single_m)
+    });
+    // Rematerialization picks several nodes per eclass, which sharing bonuses
+    // are not defined for
+    let sharing = config
+        .sharing_bonus
+        .filter(|_| config.remat_weight.is_none())
+        .map(|sharing_bonus| {
+            let tensor_m: Vec<usize> = (0..e_m.len())
+                .filter(|m| output_bytes(egraph, m_id_map[*m], i_to_nodes[e_m[*m][0]]) > 0.0)
+                .collect();
+            (tensor_m, sharing_bonus)
+        });
+
+    if let Some(lp_file) = &config.lp_file {
+        let options = LpOptions {
+            order: !config.ilp_no_order,
+            order_var_int: config.ilp_order_var_int,
+            eclass_constraint: config.ilp_class_constraint,
+            remat: remat
+                .as_ref()
+                .map(|(mem_i, remat_weight, single_m)| LpRemat {
+                    mem_i,
+                    remat_weight: f64::from(*remat_weight),
+                    single_m,
+                }),
+            sharing: sharing.as_ref().map(|(tensor_m, sharing_bonus)| LpSharing {
+                tensor_m,
+                sharing_bonus: f64::from(*sharing_bonus),
+            }),
+        };
+        write_lp(
+            lp_file,
+            &m_id_map,
+            &e_m,
+            &h_i,
+            &cost_i,
+            &g_i,
+            root_m,
+            &i_to_nodes,
+            &blacklist_i,
+            &options,
+        )
+        .map_err(|e| IlpError::Failed(format!("Unable to write {}: {}", lp_file, e)))?;
+        println!("Wrote extraction ILP to {}", lp_file);
+    }
 
     println!("prepped ilp data");
-    let data = json!({
+    let mut data = json!({
         "e_m": e_m,
         "h_i": h_i,
         "cost_i": cost_i,
@@ -1205,69 +5101,119 @@ fn extract_by_ilp(
         "root_m": root_m,
         "blacklist_i": blacklist_i,
     });
+    if let Some((mem_i, remat_weight, single_m)) = &remat {
+        data["mem_i"] = json!(mem_i);
+        data["remat_weight"] = json!(remat_weight);
+        data["single_m"] = json!(single_m);
+    }
+    if let Some((tensor_m, sharing_bonus)) = &sharing {
+        data["tensor_m"] = json!(tensor_m);
+        data["sharing_bonus"] = json!(sharing_bonus);
+    }
     let data_str = serde_json::to_string(&data).expect("Fail to convert json to string");
     create_dir_all("./tmp");
     write("./tmp/ilp_data.json", data_str).expect("Unable to write file");
 
     // Call python script to run ILP
-    let order_var_int = false;
-    let class_constraint = false;
-    let no_order = true;
+    let time_lim = config.ilp_time_sec.to_string();
+    let num_thread = config.ilp_num_threads.to_string();
     let mut arg_vec = vec!["src/enzyme_ad/jax/deps/tensat/extractor/extract.py"];
-    if order_var_int {
+    if config.ilp_order_var_int {
         arg_vec.push("--order_var_int");
     }
-    if class_constraint {
+    if config.ilp_class_constraint {
         arg_vec.push("--eclass_constraint");
     }
-    if no_order {
+    if config.ilp_no_order {
         arg_vec.push("--no_order");
     }
-    let time_lim = "1000";
-    let num_thread = "8";
+    if config.remat_weight.is_some() {
+        arg_vec.push("--remat");
+    }
+    if sharing.is_some() {
+        arg_vec.push("--sharing");
+    }
     arg_vec.push("--time_lim_sec");
-    arg_vec.push(time_lim);
+    arg_vec.push(&time_lim);
     arg_vec.push("--num_thread");
-    arg_vec.push(num_thread);
-    let child = Command::new("python")
-        .args(&arg_vec)
-        .spawn()
-        .expect("failed to execute child");
-    let output = child.wait_with_output().expect("failed to get output");
-
-    if output.status.success() {
-        // Read back solved results, construct optimized graph
-        let solved_str = read_to_string("./tmp/solved.json")
-            .expect("Something went wrong reading the solved file");
-        let solved_data: SolvedResults =
-            serde_json::from_str(&solved_str).expect("JSON was not well-formatted");
-
-        let mut node_picked: HashMap<Id, Mdl> = HashMap::new();
-        for (i, x_i) in solved_data.solved_x.iter().enumerate() {
-            if *x_i == 1 {
-                let eclass_id = m_id_map[g_i[i]];
-                if node_picked.contains_key(&eclass_id) {
-                    println!("Duplicate node in eclass");
-                    println!("{}", node_picked.get(&eclass_id).unwrap().display_op());
-                    println!("{}", i_to_nodes[i].display_op());
-                    continue;
-                }
-                //assert!(!node_picked.contains_key(&eclass_id));
-                node_picked.insert(eclass_id, i_to_nodes[i].clone());
-            }
+    arg_vec.push(&num_thread);
+    arg_vec.push("--solver");
+    arg_vec.push(config.ilp_solver.name());
+    let mip_gap = config.ilp_mip_gap.map(|gap| gap.to_string());
+    if let Some(mip_gap) = &mip_gap {
+        arg_vec.push("--mip_gap");
+        arg_vec.push(mip_gap);
+    }
+    // Don't read back the results of an earlier run if the script fails
+    // before writing any
+    let _ = remove_file("./tmp/solved.json");
+    let output = profiler.phase("ilp solve", || {
+        let child = Command::new("python")
+            .args(&arg_vec)
+            .spawn()
+            .map_err(|e| IlpError::Failed(format!("Unable to run python: {}", e)))?;
+        child
+            .wait_with_output()
+            .map_err(|e| IlpError::Failed(format!("Unable to wait for python: {}", e)))
+    })?;
+
+    // Read back solved results, construct optimized graph. The script writes
+    // its error there if it fails
+    let solved_data = match read_to_string("./tmp/solved.json") {
+        Ok(solved_str) => parse_solved(&solved_str)?,
+        Err(_) => {
+            return Err(IlpError::Failed(format!(
+                "exited with {} without writing results",
+                output.status
+            )))
         }
+    };
+    if config.remat_weight.is_some() {
+        check_blacklist(
+            &solved_data.solved_x,
+            &m_id_map,
+            &g_i,
+            &i_to_nodes,
+            &blacklist_i,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
+        let expr = profiler.phase("reconstruction", || {
+            construct_remat_rec(
+                &solved_data.solved_x,
+                &solved_data.solved_z,
+                &m_id_map,
+                &e_m,
+                &h_i,
+                &g_i,
+                &i_to_nodes,
+                root_m,
+            )
+            .unwrap_or_else(|e| panic!("Failed to reconstruct the extracted graph: {}", e))
+        });
+        return Ok((expr, solved_data.time));
+    }
+
+    let node_picked = picked_nodes(
+        &solved_data.solved_x,
+        &m_id_map,
+        &g_i,
+        &i_to_nodes,
+        &blacklist_i,
+    )
+    .unwrap_or_else(|e| panic!("{}", e));
 
+    let expr = profiler.phase("reconstruction", || {
         let mut expr = RecExpr::default();
         let mut added_memo: HashMap<Id, Id> = Default::default();
-        let _ = construct_best_rec(&node_picked, root, &mut added_memo, egraph, &mut expr);
-        (expr, solved_data.time)
-    } else {
-        panic!("Python script failed");
-    }
+        construct_best_rec(&node_picked, root, &mut added_memo, egraph, &mut expr)
+            .unwrap_or_else(|e| panic!("Failed to reconstruct the extracted graph: {}", e));
+        expr
+    });
+    Ok((expr, solved_data.time))
 }
 
 // this is copied from main.rs
-fn get_stats(egraph: &EGraph<Mdl, TensorAnalysis>) -> (usize, usize, f32, usize, f32) {
+fn get_stats(egraph: &EGraph<Mdl, TensorAnalysis>, iteration: usize) -> ffi::EGraphStats {
     let num_enodes = egraph.total_size();
     let num_classes = egraph.number_of_classes();
     let avg_nodes_per_class = num_enodes as f32 / (num_classes as f32);
@@ -1277,22 +5223,25 @@ fn get_stats(egraph: &EGraph<Mdl, TensorAnalysis>) -> (usize, usize, f32, usize,
     let num_programs = egraph
         .classes()
         .fold(0.0, |acc, c| acc + (c.len() as f32).log2());
-    (
+    ffi::EGraphStats {
+        iteration: iteration as i32,
         num_enodes,
         num_classes,
         avg_nodes_per_class,
         num_edges,
         num_programs,
-    )
+    }
 }
 
 /// Struct for generating new names for weight tensors in the model
 ///
-/// Generates names like w1, w2...
-#[derive(Default)]
+/// Generates names like w_0, w_1... (see model::is_weight_var), and names of
+/// the tensors introduced by rewrites (see naming.rs)
+#[derive(Clone, Debug, Default)]
 pub struct NameGen {
     count_input: i32,
     count_weight: i32,
+    count_tensor: i32,
 }
 
 impl NameGen {
@@ -1307,4 +5256,12 @@ impl NameGen {
         self.count_input += 1;
         name
     }
+
+    /// Name of a tensor introduced by a rewrite, e.g. w_0.transposed_3 for
+    /// `base` w_0.transposed
+    pub fn new_tensor_name(&mut self, base: &str) -> String {
+        let name = format!("{}_{}", base, self.count_tensor);
+        self.count_tensor += 1;
+        name
+    }
 }
@@ -0,0 +1,485 @@
+use crate::input::ffi;
+use crate::model::*;
+use crate::precision::round_to_bf16;
+use egg::*;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::collections::HashMap;
+
+/// Dense f32 tensor in row-major order, used by the reference interpreter
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tensor {
+    pub shape: Vec<usize>,
+    pub data: Vec<f32>,
+}
+
+impl Tensor {
+    pub fn new(shape: Vec<usize>, data: Vec<f32>) -> Self {
+        assert!(
+            shape.iter().product::<usize>() == data.len(),
+            "Shape {:?} does not match {} elements",
+            shape,
+            data.len()
+        );
+        Tensor { shape, data }
+    }
+
+    pub fn splat(shape: Vec<usize>, value: f32) -> Self {
+        let len = shape.iter().product();
+        Tensor::new(shape, vec![value; len])
+    }
+
+    pub fn random(shape: Vec<usize>, rng: &mut StdRng) -> Self {
+        let len = shape.iter().product();
+        let data = (0..len).map(|_| rng.gen_range(-1.0f32, 1.0f32)).collect();
+        Tensor::new(shape, data)
+    }
+
+    /// Largest elementwise difference, or infinity if the shapes differ
+    pub fn max_abs_diff(&self, other: &Tensor) -> f32 {
+        if self.shape != other.shape {
+            return f32::INFINITY;
+        }
+        self.data
+            .iter()
+            .zip(other.data.iter())
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max)
+    }
+
+    fn map(&self, f: impl Fn(f32) -> f32) -> Tensor {
+        Tensor::new(self.shape.clone(), self.data.iter().map(|x| f(*x)).collect())
+    }
+
+    fn zip(&self, other: &Tensor, f: impl Fn(f32, f32) -> f32) -> Result<Tensor, String> {
+        if self.shape != other.shape {
+            return Err(format!(
+                "Elementwise op on mismatched shapes {:?} and {:?}",
+                self.shape, other.shape
+            ));
+        }
+        let data = self
+            .data
+            .iter()
+            .zip(other.data.iter())
+            .map(|(a, b)| f(*a, *b))
+            .collect();
+        Ok(Tensor::new(self.shape.clone(), data))
+    }
+
+    /// Build a tensor of the given shape from a function of the multi-index
+    fn from_fn(shape: Vec<usize>, f: impl Fn(&[usize]) -> f32) -> Tensor {
+        let len: usize = shape.iter().product();
+        let data = (0..len).map(|flat| f(&unravel(flat, &shape))).collect();
+        Tensor::new(shape, data)
+    }
+
+    fn at(&self, index: &[usize]) -> f32 {
+        self.data[ravel(index, &self.shape)]
+    }
+}
+
+fn unravel(mut flat: usize, shape: &[usize]) -> Vec<usize> {
+    let mut index = vec![0; shape.len()];
+    for (i, dim) in shape.iter().enumerate().rev() {
+        index[i] = flat % dim;
+        flat /= dim;
+    }
+    index
+}
+
+fn ravel(index: &[usize], shape: &[usize]) -> usize {
+    index
+        .iter()
+        .zip(shape.iter())
+        .fold(0, |acc, (i, dim)| acc * dim + i)
+}
+
+/// Error function, Abramowitz and Stegun 7.1.26 (absolute error below 1.5e-7)
+fn erf(x: f32) -> f32 {
+    let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+    let poly = t
+        * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+    let y = 1.0 - poly * (-x * x).exp();
+    if x >= 0.0 {
+        y
+    } else {
+        -y
+    }
+}
+
+fn gelu(x: f32, approximate: bool) -> f32 {
+    if approximate {
+        0.5 * x * (1.0 + (0.79788456 * (x + 0.044715 * x * x * x)).tanh())
+    } else {
+        0.5 * x * (1.0 + erf(x * 0.70710678))
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Value {
+    Name,
+    Num(i64),
+    Vec(Vec<Id>),
+    Tensors(Vec<Tensor>),
+}
+
+/// Evaluate a RecExpr on concrete inputs
+///
+/// This is a slow reference implementation used to check that rewrites preserve
+/// semantics. Output shapes that can't be read off the node (e.g. for
+/// BroadcastInDimOp) are taken from TensorAnalysis, so `analysis` must be set up
+/// the same way as for saturation.
+///
+/// # Parameters
+///
+/// - `expr`: the graph to evaluate
+/// - `analysis`: analysis used to infer the shape of every node
+/// - `inputs`: map from block argument number to the value of that input
+///
+/// # Returns
+///
+/// The results of the root node (all operands for a ReturnOp).
+pub fn evaluate(
+    expr: &RecExpr<Mdl>,
+    analysis: TensorAnalysis,
+    inputs: &HashMap<i32, Tensor>,
+) -> Result<Vec<Tensor>, String> {
+    let mut egraph = EGraph::<Mdl, TensorAnalysis>::new(analysis);
+    let mut class_ids: Vec<Id> = Vec::with_capacity(expr.as_ref().len());
+    let mut values: Vec<Value> = Vec::with_capacity(expr.as_ref().len());
+
+    for node in expr.as_ref().iter() {
+        let class_id = egraph.add(node.clone().map_children(|c| class_ids[usize::from(c)]));
+        class_ids.push(class_id);
+        let data = &egraph[class_id].data;
+        let unknown_dims = data
+            .shapes
+            .iter()
+            .zip(data.n_dims.iter())
+            .any(|(shape, n_dims)| shape[..*n_dims].iter().any(|d| *d < 0));
+        if unknown_dims {
+            return Err(format!(
+                "{} has a dimension of unknown size",
+                node.display_op()
+            ));
+        }
+        let value = eval_node(node, &values, data, &egraph.analysis, inputs)?;
+        values.push(value);
+    }
+
+    match values.pop() {
+        Some(Value::Tensors(tensors)) => Ok(tensors),
+        _ => Err("Root of the expression is not a tensor".to_string()),
+    }
+}
+
+fn eval_node(
+    node: &Mdl,
+    values: &[Value],
+    data: &TensorData,
+    analysis: &TensorAnalysis,
+    inputs: &HashMap<i32, Tensor>,
+) -> Result<Value, String> {
+    let results = |id: &Id| -> Result<&Vec<Tensor>, String> {
+        match &values[usize::from(*id)] {
+            Value::Tensors(ts) => Ok(ts),
+            _ => Err(format!("Operand {} is not a tensor", id)),
+        }
+    };
+    let tensor = |id: &Id| -> Result<&Tensor, String> { results(id).map(|ts| &ts[0]) };
+    let num = |id: &Id| -> Result<i64, String> {
+        match &values[usize::from(*id)] {
+            Value::Num(n) => Ok(*n),
+            _ => Err(format!("Operand {} is not a number", id)),
+        }
+    };
+    let children = |id: &Id| -> Result<&Vec<Id>, String> {
+        match &values[usize::from(*id)] {
+            Value::Vec(ids) => Ok(ids),
+            _ => Err(format!("Operand {} is not a vector", id)),
+        }
+    };
+    let nums = |id: &Id| -> Result<Vec<usize>, String> {
+        children(id)?
+            .iter()
+            .map(|c| num(c).map(|n| n as usize))
+            .collect()
+    };
+    let out_shape = || -> Vec<usize> {
+        data.shapes[0][..data.n_dims[0]]
+            .iter()
+            .map(|d| *d as usize)
+            .collect()
+    };
+    let single = |t: Tensor| -> Result<Value, String> { Ok(Value::Tensors(vec![t])) };
+
+    match node {
+        Mdl::Var(_) => Ok(Value::Name),
+        Mdl::Num(n) => Ok(Value::Num(*n)),
+        Mdl::Vec(ids) => Ok(Value::Vec(ids.clone())),
+        Mdl::Input([_, arg]) => {
+            let arg = num(arg)? as i32;
+            match inputs.get(&arg) {
+                Some(t) => single(t.clone()),
+                None => Err(format!("No value given for input {}", arg)),
+            }
+        }
+        Mdl::Index([i, x]) | Mdl::GetTupleElementOp([x, i]) => {
+            single(results(x)?[num(i)? as usize].clone())
+        }
+        Mdl::TupleOp([inputs]) | Mdl::OptimizationBarrierOp([inputs]) => {
+            let inputs = children(inputs)?
+                .iter()
+                .map(|id| tensor(id).map(|t| t.clone()))
+                .collect::<Result<Vec<Tensor>, String>>()?;
+            Ok(Value::Tensors(inputs))
+        }
+        Mdl::ConstantOp([value, _]) => single(Tensor::splat(
+            out_shape(),
+            f32::from_bits(num(value)? as u32),
+        )),
+        Mdl::DenseConstantOp([handle, ..]) => match analysis.dense_values(num(handle)?) {
+            Some(values) => single(Tensor::new(
+                out_shape(),
+                values.iter().map(|v| *v as f32).collect(),
+            )),
+            None => Err(format!("Unknown dense constant {}", num(handle)?)),
+        },
+        Mdl::NegOp([x]) => single(tensor(x)?.map(|a| -a)),
+        Mdl::TanhOp([x]) => single(tensor(x)?.map(f32::tanh)),
+        Mdl::ExpOp([x]) => single(tensor(x)?.map(f32::exp)),
+        Mdl::SqrtOp([x]) => single(tensor(x)?.map(f32::sqrt)),
+        Mdl::RsqrtOp([x]) => single(tensor(x)?.map(|a| 1.0 / a.sqrt())),
+        Mdl::LogOp([x]) => single(tensor(x)?.map(f32::ln)),
+        Mdl::SineOp([x]) => single(tensor(x)?.map(f32::sin)),
+        Mdl::CosineOp([x]) => single(tensor(x)?.map(f32::cos)),
+        Mdl::AbsOp([x]) => single(tensor(x)?.map(f32::abs)),
+        // Unlike f32::signum, the sign of zero is zero
+        Mdl::SignOp([x]) => single(tensor(x)?.map(|a| if a == 0.0 { a } else { a.signum() })),
+        Mdl::FloorOp([x]) => single(tensor(x)?.map(f32::floor)),
+        Mdl::CeilOp([x]) => single(tensor(x)?.map(f32::ceil)),
+        Mdl::RoundOp([x]) => single(tensor(x)?.map(f32::round)),
+        Mdl::ErfOp([x]) => single(tensor(x)?.map(erf)),
+        Mdl::GeluOp([x, approximate]) => {
+            let approximate = num(approximate)? != 0;
+            single(tensor(x)?.map(|a| gelu(a, approximate)))
+        }
+        Mdl::AddOp([a, b]) => single(tensor(a)?.zip(tensor(b)?, |x, y| x + y)?),
+        Mdl::SubtractOp([a, b]) => single(tensor(a)?.zip(tensor(b)?, |x, y| x - y)?),
+        Mdl::MulOp([a, b]) => single(tensor(a)?.zip(tensor(b)?, |x, y| x * y)?),
+        Mdl::DivOp([a, b]) => single(tensor(a)?.zip(tensor(b)?, |x, y| x / y)?),
+        Mdl::MinOp([a, b]) => single(tensor(a)?.zip(tensor(b)?, f32::min)?),
+        Mdl::MaxOp([a, b]) => single(tensor(a)?.zip(tensor(b)?, f32::max)?),
+        Mdl::PowOp([a, b]) => single(tensor(a)?.zip(tensor(b)?, f32::powf)?),
+        Mdl::RemainderOp([a, b]) => single(tensor(a)?.zip(tensor(b)?, |x, y| x % y)?),
+        Mdl::ClampOp([min, x, max]) => {
+            let (min, x, max) = (tensor(min)?, tensor(x)?, tensor(max)?);
+            // Scalar bounds apply to every element
+            let bound = |t: &Tensor, index: &[usize]| {
+                if t.shape.is_empty() {
+                    t.data[0]
+                } else {
+                    t.at(index)
+                }
+            };
+            single(Tensor::from_fn(x.shape.clone(), |index| {
+                x.at(index).max(bound(min, index)).min(bound(max, index))
+            }))
+        }
+        Mdl::BatchNormInferenceOp([x, scale, offset, mean, variance, epsilon, feature]) => {
+            let x = tensor(x)?;
+            let (scale, offset) = (tensor(scale)?, tensor(offset)?);
+            let (mean, variance) = (tensor(mean)?, tensor(variance)?);
+            let epsilon = f32::from_bits(num(epsilon)? as u32);
+            let feature = num(feature)? as usize;
+            single(Tensor::from_fn(x.shape.clone(), |index| {
+                let f = &[index[feature]];
+                (x.at(index) - mean.at(f)) * scale.at(f) / (variance.at(f) + epsilon).sqrt()
+                    + offset.at(f)
+            }))
+        }
+        Mdl::SelectOp([pred, on_true, on_false]) => {
+            let pred = tensor(pred)?;
+            let on_true = tensor(on_true)?;
+            let on_false = tensor(on_false)?;
+            single(Tensor::from_fn(on_true.shape.clone(), |index| {
+                if pred.at(index) != 0.0 {
+                    on_true.at(index)
+                } else {
+                    on_false.at(index)
+                }
+            }))
+        }
+        Mdl::ConvertOp([x, _]) => match data.dtype {
+            ffi::Type::f32 => single(tensor(x)?.clone()),
+            ffi::Type::bf16 => single(tensor(x)?.map(round_to_bf16)),
+            _ => Err("Only float ConvertOps are supported by the interpreter".to_string()),
+        },
+        Mdl::ReshapeOp([x, _]) => single(Tensor::new(out_shape(), tensor(x)?.data.clone())),
+        Mdl::TransposeOp([x, perm]) => {
+            let x = tensor(x)?;
+            let perm = nums(perm)?;
+            let shape = perm.iter().map(|p| x.shape[*p]).collect();
+            single(Tensor::from_fn(shape, |index| {
+                let mut in_index = vec![0; index.len()];
+                for (i, p) in perm.iter().enumerate() {
+                    in_index[*p] = index[i];
+                }
+                x.at(&in_index)
+            }))
+        }
+        Mdl::BroadcastInDimOp([x, dims]) => {
+            let x = tensor(x)?;
+            let dims = nums(dims)?;
+            single(Tensor::from_fn(out_shape(), |index| {
+                let in_index: Vec<usize> = dims
+                    .iter()
+                    .enumerate()
+                    .map(|(j, d)| if x.shape[j] == 1 { 0 } else { index[*d] })
+                    .collect();
+                x.at(&in_index)
+            }))
+        }
+        Mdl::IotaOp([dim, _]) => {
+            let dim = num(dim)? as usize;
+            single(Tensor::from_fn(out_shape(), |index| index[dim] as f32))
+        }
+        Mdl::ConcatenateOp([inputs, dim]) => {
+            let parts = children(inputs)?
+                .iter()
+                .map(|id| tensor(id))
+                .collect::<Result<Vec<&Tensor>, String>>()?;
+            let dim = num(dim)? as usize;
+            single(Tensor::from_fn(out_shape(), |index| {
+                let mut in_index = index.to_vec();
+                for part in parts.iter() {
+                    if in_index[dim] < part.shape[dim] {
+                        return part.at(&in_index);
+                    }
+                    in_index[dim] -= part.shape[dim];
+                }
+                unreachable!()
+            }))
+        }
+        Mdl::SliceOp([x, start, _, strides]) => {
+            let x = tensor(x)?;
+            let start = nums(start)?;
+            let strides = nums(strides)?;
+            single(Tensor::from_fn(out_shape(), |index| {
+                let in_index: Vec<usize> = index
+                    .iter()
+                    .enumerate()
+                    .map(|(i, o)| start[i] + o * strides[i])
+                    .collect();
+                x.at(&in_index)
+            }))
+        }
+        Mdl::DotGeneralOp([lhs, rhs, lb, rb, lc, rc, _]) => {
+            let lhs = tensor(lhs)?;
+            let rhs = tensor(rhs)?;
+            let (lb, rb, lc, rc) = (nums(lb)?, nums(rb)?, nums(lc)?, nums(rc)?);
+            let lhs_free: Vec<usize> = (0..lhs.shape.len())
+                .filter(|d| !lb.contains(d) && !lc.contains(d))
+                .collect();
+            let rhs_free: Vec<usize> = (0..rhs.shape.len())
+                .filter(|d| !rb.contains(d) && !rc.contains(d))
+                .collect();
+            let contract_shape: Vec<usize> = lc.iter().map(|d| lhs.shape[*d]).collect();
+            let contract_len: usize = contract_shape.iter().product();
+            let shape: Vec<usize> = lb
+                .iter()
+                .map(|d| lhs.shape[*d])
+                .chain(lhs_free.iter().map(|d| lhs.shape[*d]))
+                .chain(rhs_free.iter().map(|d| rhs.shape[*d]))
+                .collect();
+            single(Tensor::from_fn(shape, |index| {
+                let (batch, rest) = index.split_at(lb.len());
+                let (lhs_rest, rhs_rest) = rest.split_at(lhs_free.len());
+                let mut lhs_index = vec![0; lhs.shape.len()];
+                let mut rhs_index = vec![0; rhs.shape.len()];
+                for (i, b) in batch.iter().enumerate() {
+                    lhs_index[lb[i]] = *b;
+                    rhs_index[rb[i]] = *b;
+                }
+                for (i, d) in lhs_free.iter().enumerate() {
+                    lhs_index[*d] = lhs_rest[i];
+                }
+                for (i, d) in rhs_free.iter().enumerate() {
+                    rhs_index[*d] = rhs_rest[i];
+                }
+                let mut sum = 0.0;
+                for flat in 0..contract_len {
+                    let c = unravel(flat, &contract_shape);
+                    for (i, k) in c.iter().enumerate() {
+                        lhs_index[lc[i]] = *k;
+                        rhs_index[rc[i]] = *k;
+                    }
+                    sum += lhs.at(&lhs_index) * rhs.at(&rhs_index);
+                }
+                sum
+            }))
+        }
+        Mdl::ReturnOp([outputs]) => {
+            let outputs = children(outputs)?
+                .iter()
+                .map(|id| tensor(id).map(|t| t.clone()))
+                .collect::<Result<Vec<Tensor>, String>>()?;
+            Ok(Value::Tensors(outputs))
+        }
+        x => Err(format!("{} is not supported by the interpreter", x.display_op())),
+    }
+}
+
+/// Random values for every Input node of the expression, keyed by block argument number
+///
+/// Returns an error for inputs with a dimension of unknown size (-1), which
+/// can't be given values.
+pub fn random_inputs(expr: &RecExpr<Mdl>, seed: u64) -> Result<HashMap<i32, Tensor>, String> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let nodes = expr.as_ref();
+    let mut inputs = HashMap::new();
+    for node in nodes.iter() {
+        if let Mdl::Input([name, arg]) = node {
+            let (name, arg) = match (&nodes[usize::from(*name)], &nodes[usize::from(*arg)]) {
+                (Mdl::Var(name), Mdl::Num(arg)) => (name.as_str(), *arg as i32),
+                _ => panic!("Malformed Input node"),
+            };
+            let shape: Vec<usize> = name.split('@').collect::<Vec<&str>>()[1]
+                .split('_')
+                .filter(|d| !d.is_empty())
+                .map(|d| match d.parse::<usize>() {
+                    Ok(d) => Ok(d),
+                    Err(_) => Err(format!("Input {} has a dimension of unknown size", arg)),
+                })
+                .filter(|d| *d != Ok(0))
+                .collect::<Result<_, String>>()?;
+            inputs
+                .entry(arg)
+                .or_insert_with(|| Tensor::random(shape, &mut rng));
+        }
+    }
+    Ok(inputs)
+}
+
+/// True if both lists of results have the same shapes and agree within `tolerance`
+pub fn outputs_match(a: &[Tensor], b: &[Tensor], tolerance: f32) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| x.max_abs_diff(y) <= tolerance)
+}
+
+/// Evaluate two graphs on the same random inputs and check their results agree
+/// within `tolerance`
+///
+/// Returns an error if either graph can't be evaluated, e.g. because it uses
+/// an op the interpreter doesn't support.
+pub fn numerically_equivalent(
+    a: &RecExpr<Mdl>,
+    b: &RecExpr<Mdl>,
+    new_analysis: impl Fn() -> TensorAnalysis,
+    tolerance: f32,
+    seed: u64,
+) -> Result<bool, String> {
+    let inputs = random_inputs(a, seed)?;
+    let expected = evaluate(a, new_analysis(), &inputs)?;
+    let actual = evaluate(b, new_analysis(), &inputs)?;
+    Ok(outputs_match(&expected, &actual, tolerance))
+}
@@ -0,0 +1,326 @@
+use crate::input::{ffi, CppGraphConverter};
+use crate::model::dtype_from_num;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// A variable of a jaxpr, with its abstract value
+#[derive(Deserialize, Debug)]
+pub struct JaxprVar {
+    pub name: String,
+    pub shape: Vec<i32>,
+    #[serde(default)]
+    pub dtype: Option<String>,
+}
+
+/// Operand of an equation: a variable or a scalar literal
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+pub enum JaxprAtom {
+    Var(String),
+    Literal { literal: f64 },
+}
+
+#[derive(Deserialize, Debug)]
+pub struct JaxprEqn {
+    pub primitive: String,
+    pub invars: Vec<JaxprAtom>,
+    pub outvars: Vec<JaxprVar>,
+    #[serde(default)]
+    pub params: Value,
+}
+
+/// A jaxpr as dumped to JSON, e.g. with
+///
+/// ```python
+/// closed = jax.make_jaxpr(f)(*args)
+/// var = lambda v: {"name": str(v), "shape": list(v.aval.shape), "dtype": str(v.aval.dtype)}
+/// atom = lambda a: {"literal": float(a.val)} if isinstance(a, jax.core.Literal) else str(a)
+/// json.dump({
+///     "invars": [var(v) for v in closed.jaxpr.invars],
+///     "eqns": [{"primitive": e.primitive.name, "invars": [atom(a) for a in e.invars],
+///               "outvars": [var(v) for v in e.outvars], "params": jsonable(e.params)}
+///              for e in closed.jaxpr.eqns],
+///     "outvars": [atom(a) for a in closed.jaxpr.outvars],
+/// }, f)
+/// ```
+///
+/// where `jsonable` turns the tuples of the params into lists.
+#[derive(Deserialize, Debug)]
+pub struct Jaxpr {
+    pub invars: Vec<JaxprVar>,
+    pub eqns: Vec<JaxprEqn>,
+    pub outvars: Vec<JaxprAtom>,
+}
+
+/// Build the graph of a jaxpr (JSON, see Jaxpr) with a CppGraphConverter
+///
+/// The inputs of the jaxpr become the block arguments of the graph, in order,
+/// and its outputs the operands of the ReturnOp. Scalar literals become splat
+/// constants of the shape of the equation's result.
+///
+/// Supported primitives: add, sub, mul, div, max, min, pow, rem, complex, neg,
+/// tanh, exp, erf, sqrt, rsqrt, log, sin, cos, abs, sign, floor, ceil, real,
+/// imag, fft, cholesky, triangular_solve, dot_general, transpose, reshape,
+/// broadcast_in_dim, reduce_sum, concatenate, slice, iota and
+/// convert_element_type. Other primitives (including ones with sub-jaxprs,
+/// like pjit and scan) are rejected with an error.
+pub fn import_jaxpr(json: &str) -> Result<CppGraphConverter, String> {
+    let jaxpr: Jaxpr = serde_json::from_str(json).map_err(|e| format!("Invalid jaxpr: {}", e))?;
+    let mut converter = CppGraphConverter::default();
+    let mut env: HashMap<String, ffi::TensorHandle> = HashMap::new();
+
+    for (i, var) in jaxpr.invars.iter().enumerate() {
+        // Inputs without a dtype are f32
+        let dtype = var.dtype.as_deref().map_or(Ok(1), dtype_to_type)?;
+        let tensor = converter.new_input(i as i32, &var.shape, dtype_from_num(dtype.into()));
+        env.insert(var.name.clone(), tensor);
+    }
+    for eqn in &jaxpr.eqns {
+        if eqn.outvars.len() != 1 {
+            return Err(format!(
+                "{} has {} results, only single-result primitives are supported",
+                eqn.primitive,
+                eqn.outvars.len()
+            ));
+        }
+        let out = &eqn.outvars[0];
+        let mut operands = Vec::with_capacity(eqn.invars.len());
+        for atom in &eqn.invars {
+            operands.push(match atom {
+                JaxprAtom::Var(name) => env
+                    .get(name)
+                    .copied()
+                    .ok_or_else(|| format!("{} uses undefined variable {}", eqn.primitive, name))?,
+                JaxprAtom::Literal { literal } => {
+                    converter.new_splat_constant_op(*literal as f32, &out.shape)
+                }
+            });
+        }
+        let tensor = lower_eqn(&mut converter, eqn, &operands, &out.shape)?;
+        env.insert(out.name.clone(), tensor);
+    }
+
+    let mut outputs = Vec::with_capacity(jaxpr.outvars.len());
+    for atom in &jaxpr.outvars {
+        match atom {
+            JaxprAtom::Var(name) => outputs.push(
+                env.get(name)
+                    .copied()
+                    .ok_or_else(|| format!("Output {} is undefined", name))?,
+            ),
+            JaxprAtom::Literal { .. } => return Err("Literal outputs are not supported".into()),
+        }
+    }
+    converter.new_return_op(&outputs);
+    Ok(converter)
+}
+
+fn lower_eqn(
+    converter: &mut CppGraphConverter,
+    eqn: &JaxprEqn,
+    operands: &[ffi::TensorHandle],
+    shape: &[i32],
+) -> Result<ffi::TensorHandle, String> {
+    let arity = |n: usize| {
+        if operands.len() == n {
+            Ok(())
+        } else {
+            Err(format!(
+                "{} takes {} operands, got {}",
+                eqn.primitive,
+                n,
+                operands.len()
+            ))
+        }
+    };
+    let params = &eqn.params;
+    let tensor = match eqn.primitive.as_str() {
+        "add" | "sub" | "mul" | "div" | "max" | "min" | "pow" | "rem" | "complex" => {
+            arity(2)?;
+            let (lhs, rhs) = (operands[0], operands[1]);
+            match eqn.primitive.as_str() {
+                "add" => converter.new_add_op(lhs, rhs, shape),
+                "sub" => converter.new_subtract_op(lhs, rhs, shape),
+                "mul" => converter.new_mul_op(lhs, rhs, shape),
+                "div" => converter.new_div_op(lhs, rhs, shape),
+                "max" => converter.new_max_op(lhs, rhs, shape),
+                "pow" => converter.new_pow_op(lhs, rhs, shape),
+                "rem" => converter.new_remainder_op(lhs, rhs, shape),
+                "complex" => converter.new_complex_op(lhs, rhs, shape),
+                _ => converter.new_min_op(lhs, rhs, shape),
+            }
+        }
+        "neg" | "tanh" | "exp" | "erf" | "sqrt" | "rsqrt" | "log" | "sin" | "cos" | "abs"
+        | "sign" | "floor" | "ceil" | "real" | "imag" => {
+            arity(1)?;
+            let x = operands[0];
+            match eqn.primitive.as_str() {
+                "neg" => converter.new_neg_op(x, shape),
+                "tanh" => converter.new_tanh_op(x, shape),
+                "exp" => converter.new_exp_op(x, shape),
+                "sqrt" => converter.new_sqrt_op(x, shape),
+                "rsqrt" => converter.new_rsqrt_op(x, shape),
+                "log" => converter.new_log_op(x, shape),
+                "sin" => converter.new_sine_op(x, shape),
+                "cos" => converter.new_cosine_op(x, shape),
+                "abs" => converter.new_abs_op(x, shape),
+                "sign" => converter.new_sign_op(x, shape),
+                "floor" => converter.new_floor_op(x, shape),
+                "ceil" => converter.new_ceil_op(x, shape),
+                "real" => converter.new_real_op(x, shape),
+                "imag" => converter.new_imag_op(x, shape),
+                _ => converter.new_erf_op(x, shape),
+            }
+        }
+        "fft" => {
+            arity(1)?;
+            let fft_type = fft_type(eqn, param(eqn, "fft_type")?)?;
+            let fft_lengths = int_list(eqn, param(eqn, "fft_lengths")?)?;
+            converter.new_fft_op(operands[0], fft_type, &fft_lengths, shape)
+        }
+        "cholesky" => {
+            arity(1)?;
+            // lax.linalg.cholesky gives the lower factor
+            converter.new_cholesky_op(operands[0], true, shape)
+        }
+        "triangular_solve" => {
+            arity(2)?;
+            let flag = |name: &str| params[name].as_bool() == Some(true);
+            // stablehlo Transpose: 1 for none, 2 for transpose, 3 for adjoint
+            let transpose_a = match (flag("transpose_a"), flag("conjugate_a")) {
+                (false, _) => 1,
+                (true, false) => 2,
+                (true, true) => 3,
+            };
+            converter.new_triangular_solve_op(
+                operands[0],
+                operands[1],
+                flag("left_side"),
+                flag("lower"),
+                flag("unit_diagonal"),
+                transpose_a,
+                shape,
+            )
+        }
+        "dot_general" => {
+            arity(2)?;
+            // ((lhs_contracting, rhs_contracting), (lhs_batch, rhs_batch))
+            let numbers = param(eqn, "dimension_numbers")?;
+            let dims = |i: usize, j: usize| int_list(eqn, &numbers[i][j]);
+            converter.new_dot_general_op(
+                operands[0],
+                operands[1],
+                &dims(1, 0)?,
+                &dims(1, 1)?,
+                &dims(0, 0)?,
+                &dims(0, 1)?,
+                &[],
+                shape,
+            )
+        }
+        "transpose" => {
+            arity(1)?;
+            let permutation = int_list(eqn, param(eqn, "permutation")?)?;
+            converter.new_transpose_op(operands[0], &permutation, shape)
+        }
+        "reshape" => {
+            arity(1)?;
+            converter.new_reshape_op(operands[0], shape)
+        }
+        "broadcast_in_dim" => {
+            arity(1)?;
+            let dimensions = int_list(eqn, param(eqn, "broadcast_dimensions")?)?;
+            converter.new_broadcast_in_dim(operands[0], &dimensions, shape)
+        }
+        "reduce_sum" => {
+            arity(1)?;
+            let axes = int_list(eqn, param(eqn, "axes")?)?;
+            let shapes = vec![ffi::Shape {
+                shape: shape.iter().map(|d| *d as i64).collect(),
+            }];
+            converter.new_reduce_sum(operands[0], &axes, &shapes)
+        }
+        "concatenate" => {
+            let dimension = int(eqn, param(eqn, "dimension")?)?;
+            converter.new_concatenate_op(operands, dimension, shape)
+        }
+        "slice" => {
+            arity(1)?;
+            let start = int_list(eqn, param(eqn, "start_indices")?)?;
+            let limit = int_list(eqn, param(eqn, "limit_indices")?)?;
+            let strides = match &params["strides"] {
+                Value::Null => vec![1; start.len()],
+                strides => int_list(eqn, strides)?,
+            };
+            converter.new_slice_op(operands[0], &start, &limit, &strides, shape)
+        }
+        "iota" => {
+            arity(0)?;
+            let dimension = int(eqn, param(eqn, "dimension")?)?;
+            converter.new_iota_op(dimension, shape)
+        }
+        "convert_element_type" => {
+            arity(1)?;
+            let new_dtype = param(eqn, "new_dtype")?
+                .as_str()
+                .ok_or_else(|| format!("{}: new_dtype is not a string", eqn.primitive))?;
+            converter.new_convert_op(operands[0], dtype_to_type(new_dtype)?, shape)
+        }
+        other => return Err(format!("Unsupported primitive {}", other)),
+    };
+    Ok(tensor)
+}
+
+fn param<'a>(eqn: &'a JaxprEqn, name: &str) -> Result<&'a Value, String> {
+    match &eqn.params[name] {
+        Value::Null => Err(format!("{} is missing param {}", eqn.primitive, name)),
+        value => Ok(value),
+    }
+}
+
+fn int(eqn: &JaxprEqn, value: &Value) -> Result<i32, String> {
+    value
+        .as_i64()
+        .map(|v| v as i32)
+        .ok_or_else(|| format!("{}: expected an integer, got {}", eqn.primitive, value))
+}
+
+fn int_list(eqn: &JaxprEqn, value: &Value) -> Result<Vec<i64>, String> {
+    value
+        .as_array()
+        .ok_or_else(|| format!("{}: expected a list, got {}", eqn.primitive, value))?
+        .iter()
+        .map(|v| {
+            v.as_i64()
+                .ok_or_else(|| format!("{}: expected an integer, got {}", eqn.primitive, v))
+        })
+        .collect()
+}
+
+/// FftType discriminant of the fft_type param, given as its number or its name
+/// (e.g. "FftType.RFFT")
+fn fft_type(eqn: &JaxprEqn, value: &Value) -> Result<i32, String> {
+    if let Some(name) = value.as_str() {
+        return match name.rsplit('.').next() {
+            Some("FFT") => Ok(0),
+            Some("IFFT") => Ok(1),
+            Some("RFFT") => Ok(2),
+            Some("IRFFT") => Ok(3),
+            _ => Err(format!("{}: unknown fft_type {}", eqn.primitive, name)),
+        };
+    }
+    int(eqn, value)
+}
+
+/// ffi::Type discriminant of a numpy dtype name, as taken by new_convert_op
+fn dtype_to_type(dtype: &str) -> Result<i32, String> {
+    match dtype {
+        "int32" => Ok(0),
+        "float32" => Ok(1),
+        "bool" => Ok(2),
+        "bfloat16" => Ok(3),
+        "complex64" => Ok(4),
+        other => Err(format!("Unsupported dtype {}", other)),
+    }
+}
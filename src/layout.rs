@@ -0,0 +1,299 @@
+use crate::model::*;
+use crate::naming::{add_named, derived_name};
+use crate::rewrites::{finish_apply, get_num, get_vec, make_num, make_vec};
+use egg::*;
+
+/// Extra cost of a DotGeneralOp, relative to its cost in the canonical layout,
+/// for each operand that isn't in the canonical layout (see `canonical_perm`).
+/// A rough estimate of the strided reads the backend pays for, or of the
+/// transpose it inserts itself
+pub const LAYOUT_PENALTY: f64 = 0.25;
+
+/// Operand of a DotGeneralOp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operand {
+    Lhs,
+    Rhs,
+}
+
+/// Dimension numbers of a DotGeneralOp operand
+struct OperandDims {
+    rank: usize,
+    batch: Vec<i64>,
+    contract: Vec<i64>,
+}
+
+impl OperandDims {
+    fn free(&self) -> Vec<i64> {
+        (0..self.rank as i64)
+            .filter(|d| !self.batch.contains(d) && !self.contract.contains(d))
+            .collect()
+    }
+}
+
+fn nums(egraph: &EGraph<Mdl, TensorAnalysis>, vec: Id) -> Vec<i64> {
+    get_vec(&egraph[vec])
+        .iter()
+        .map(|id| *get_num(&egraph[*id]))
+        .collect()
+}
+
+fn rank(egraph: &EGraph<Mdl, TensorAnalysis>, id: Id) -> usize {
+    egraph[id].data.n_dims[0]
+}
+
+fn num_vec(egraph: &mut EGraph<Mdl, TensorAnalysis>, seq: &[i64]) -> Id {
+    let ids: Vec<Id> = seq.iter().map(|n| make_num(egraph, *n)).collect();
+    make_vec(egraph, &ids)
+}
+
+/// Permutation transposing an operand into the canonical layout of a row-major
+/// matmul: `[batch..., free..., contract...]` for the lhs and
+/// `[batch..., contract..., free...]` for the rhs. Batch and contracting
+/// dimensions keep their order in the dimension numbers, free dimensions keep
+/// theirs, so the result of the DotGeneralOp is unchanged.
+fn canonical_perm(dims: &OperandDims, operand: Operand) -> Vec<i64> {
+    let mut perm = dims.batch.clone();
+    match operand {
+        Operand::Lhs => {
+            perm.extend(dims.free());
+            perm.extend(&dims.contract);
+        }
+        Operand::Rhs => {
+            perm.extend(&dims.contract);
+            perm.extend(dims.free());
+        }
+    }
+    perm
+}
+
+fn is_identity(perm: &[i64]) -> bool {
+    perm.iter().enumerate().all(|(i, p)| *p == i as i64)
+}
+
+/// Operand dimensions of a DotGeneralOp, given its children
+fn operand_dims(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    children: &[Id; 7],
+    operand: Operand,
+) -> OperandDims {
+    let (input, batch, contract) = match operand {
+        Operand::Lhs => (children[0], children[2], children[4]),
+        Operand::Rhs => (children[1], children[3], children[5]),
+    };
+    OperandDims {
+        rank: rank(egraph, input),
+        batch: nums(egraph, batch),
+        contract: nums(egraph, contract),
+    }
+}
+
+/// Cost multiplier of `enode` in the layout-aware cost model: 1 plus
+/// LAYOUT_PENALTY for each operand of a DotGeneralOp not in the canonical
+/// layout, 1 for other ops
+pub fn layout_factor(egraph: &EGraph<Mdl, TensorAnalysis>, enode: &Mdl) -> f64 {
+    let children = match enode {
+        Mdl::DotGeneralOp(children) => children,
+        _ => return 1.0,
+    };
+    let mut factor = 1.0;
+    for operand in [Operand::Lhs, Operand::Rhs] {
+        let dims = operand_dims(egraph, children, operand);
+        if !is_identity(&canonical_perm(&dims, operand)) {
+            factor += LAYOUT_PENALTY;
+        }
+    }
+    factor
+}
+
+/// Rules making the layout of DotGeneralOp operands part of the search: a
+/// transpose can be inserted to bring an operand into the canonical layout,
+/// transposes feeding a DotGeneralOp can be folded into its dimension
+/// numbers, and chains of transposes are composed (and dropped when they
+/// cancel out). Meant to be used with the layout-aware cost model (see
+/// `layout_factor`), so the extracted layout trades transposes against
+/// strided DotGeneralOps.
+///
+/// Mdl has no convolution op, so convolution layouts are left to the backend.
+pub fn layout_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    let dot = |lhs: &str, rhs: &str| -> Pattern<Mdl> {
+        format!("(DotGeneralOp {} {} ?lb ?rb ?lc ?rc ?prec)", lhs, rhs)
+            .parse()
+            .unwrap()
+    };
+    vec![
+        rewrite!("dot-general-canonical-lhs"; { dot("?x", "?y") } => {
+            CanonicalizeDotOperand { operand: Operand::Lhs }
+        }),
+        rewrite!("dot-general-canonical-rhs"; { dot("?x", "?y") } => {
+            CanonicalizeDotOperand { operand: Operand::Rhs }
+        }),
+        rewrite!("dot-general-fold-transpose-lhs"; { dot("(TransposeOp ?x ?p)", "?y") } => {
+            FoldTransposeIntoDot { operand: Operand::Lhs }
+        }),
+        rewrite!("dot-general-fold-transpose-rhs"; { dot("?x", "(TransposeOp ?y ?p)") } => {
+            FoldTransposeIntoDot { operand: Operand::Rhs }
+        }),
+        rewrite!("compose-transposes"; "(TransposeOp (TransposeOp ?x ?p) ?q)" => {
+            ComposeTransposes
+        }),
+    ]
+}
+
+fn var(name: &str) -> Var {
+    name.parse().unwrap()
+}
+
+/// Adds the matched DotGeneralOp with `new_input` as `operand`, and `batch`
+/// and `contract` as the dimension numbers of that operand
+fn add_dot(
+    egraph: &mut EGraph<Mdl, TensorAnalysis>,
+    subst: &Subst,
+    operand: Operand,
+    new_input: Id,
+    batch: &[i64],
+    contract: &[i64],
+) -> Id {
+    let batch = num_vec(egraph, batch);
+    let contract = num_vec(egraph, contract);
+    let (lhs, rhs, lb, rb, lc, rc) = match operand {
+        Operand::Lhs => (
+            new_input,
+            subst[var("?y")],
+            batch,
+            subst[var("?rb")],
+            contract,
+            subst[var("?rc")],
+        ),
+        Operand::Rhs => (
+            subst[var("?x")],
+            new_input,
+            subst[var("?lb")],
+            batch,
+            subst[var("?lc")],
+            contract,
+        ),
+    };
+    let precision = subst[var("?prec")];
+    egraph.add(Mdl::DotGeneralOp([lhs, rhs, lb, rb, lc, rc, precision]))
+}
+
+/// Transposes an operand of a DotGeneralOp into the canonical layout (see
+/// `canonical_perm`), unless it already is
+#[derive(Debug, Clone, PartialEq)]
+pub struct CanonicalizeDotOperand {
+    pub operand: Operand,
+}
+
+impl Applier<Mdl, TensorAnalysis> for CanonicalizeDotOperand {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let (input, batch, contract) = match self.operand {
+            Operand::Lhs => (subst[var("?x")], subst[var("?lb")], subst[var("?lc")]),
+            Operand::Rhs => (subst[var("?y")], subst[var("?rb")], subst[var("?rc")]),
+        };
+        let dims = OperandDims {
+            rank: rank(egraph, input),
+            batch: nums(egraph, batch),
+            contract: nums(egraph, contract),
+        };
+        let perm = canonical_perm(&dims, self.operand);
+        if is_identity(&perm) {
+            return vec![];
+        }
+
+        let perm_id = num_vec(egraph, &perm);
+        let name = derived_name(egraph, input, "transposed");
+        let transposed = add_named(egraph, Mdl::TransposeOp([input, perm_id]), &name);
+        let (num_batch, num_contract) = (dims.batch.len() as i64, dims.contract.len() as i64);
+        let new_batch: Vec<i64> = (0..num_batch).collect();
+        let contract_start = match self.operand {
+            Operand::Lhs => dims.rank as i64 - num_contract,
+            Operand::Rhs => num_batch,
+        };
+        let new_contract: Vec<i64> = (contract_start..contract_start + num_contract).collect();
+        let id = add_dot(
+            egraph,
+            subst,
+            self.operand,
+            transposed,
+            &new_batch,
+            &new_contract,
+        );
+        finish_apply(egraph, matched_id, id)
+    }
+}
+
+/// Folds a transpose feeding an operand of a DotGeneralOp into its dimension
+/// numbers. Only applies if the transpose keeps the order of the free
+/// dimensions, which decides the layout of the result
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoldTransposeIntoDot {
+    pub operand: Operand,
+}
+
+impl Applier<Mdl, TensorAnalysis> for FoldTransposeIntoDot {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let (input, batch, contract) = match self.operand {
+            Operand::Lhs => (subst[var("?x")], subst[var("?lb")], subst[var("?lc")]),
+            Operand::Rhs => (subst[var("?y")], subst[var("?rb")], subst[var("?rc")]),
+        };
+        // Dimension d of the transpose is dimension perm[d] of its input
+        let perm = nums(egraph, subst[var("?p")]);
+        let dims = OperandDims {
+            rank: perm.len(),
+            batch: nums(egraph, batch),
+            contract: nums(egraph, contract),
+        };
+        let free: Vec<i64> = dims.free().iter().map(|d| perm[*d as usize]).collect();
+        if free.windows(2).any(|w| w[0] > w[1]) {
+            return vec![];
+        }
+
+        let through =
+            |dims: &[i64]| -> Vec<i64> { dims.iter().map(|d| perm[*d as usize]).collect() };
+        let id = add_dot(
+            egraph,
+            subst,
+            self.operand,
+            input,
+            &through(&dims.batch),
+            &through(&dims.contract),
+        );
+        finish_apply(egraph, matched_id, id)
+    }
+}
+
+/// Composes two transposes into one, or into nothing if they cancel out
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComposeTransposes;
+
+impl Applier<Mdl, TensorAnalysis> for ComposeTransposes {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let x = subst[var("?x")];
+        let inner = nums(egraph, subst[var("?p")]);
+        let outer = nums(egraph, subst[var("?q")]);
+        let composed: Vec<i64> = outer.iter().map(|q| inner[*q as usize]).collect();
+        let id = if is_identity(&composed) {
+            x
+        } else {
+            let perm_id = num_vec(egraph, &composed);
+            egraph.add(Mdl::TransposeOp([x, perm_id]))
+        };
+        finish_apply(egraph, matched_id, id)
+    }
+}
@@ -1,8 +1,46 @@
+pub mod budget;
+#[cfg(feature = "c-api")]
+pub mod c_api;
+pub mod checkpoint;
+pub mod collective;
+pub mod composite;
+pub mod config;
+pub mod convolution;
+pub mod diff;
+pub mod fft;
 pub mod ffi_utils;
+pub mod fusion;
+pub mod fx;
+pub mod graphdef;
+pub mod growth;
+pub mod ilp_export;
 pub mod input;
+pub mod interpreter;
+pub mod jaxpr;
+pub mod layout;
+pub mod merge;
+pub mod liveness;
+pub mod metrics;
+pub mod mock_cost;
 pub mod model;
+pub mod naming;
 pub mod optimize;
 pub mod parse;
+pub mod passes;
+pub mod pdll;
+pub mod precision;
+pub mod profile;
+pub mod provenance;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod region;
+pub mod replay;
 pub mod rewrites;
+pub mod scheduler;
+pub mod session;
+pub mod sharding;
+pub mod taso_convert;
+pub mod trace;
 pub mod verify;
+pub mod weights;
 
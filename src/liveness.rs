@@ -0,0 +1,211 @@
+//! Execution order of the optimized graph keeping peak memory low
+//!
+//! The nodes of an ffi::Graph are topologically ordered, but the order is the
+//! one extraction happened to emit them in, which can keep many tensors alive
+//! at once. `memory_schedule` picks another topological order with a list
+//! scheduling heuristic: among the nodes whose operands are computed, run the
+//! one that grows the live memory the least, counting the operands it is the
+//! last user of as freed. Sizes come from the inferred shapes and dtypes of
+//! the nodes; the results of the graph and its inputs stay alive throughout.
+
+use crate::input::ffi;
+
+/// Whether the node is an op to execute, not an attribute (Num, Vec) or the
+/// name of an input (Var)
+fn is_op(node: &ffi::Node) -> bool {
+    !matches!(node.name.as_str(), "Num" | "Vec" | "Var")
+}
+
+/// Bytes of the results of `node`, zero if its shape wasn't inferred
+pub fn node_bytes(node: &ffi::Node) -> f64 {
+    let element_bytes = match node.dtype {
+        ffi::Type::c64 => 8.0,
+        ffi::Type::bf16 => 2.0,
+        ffi::Type::i1 => 1.0,
+        _ => 4.0,
+    };
+    node.shape
+        .iter()
+        .map(|shape| {
+            shape
+                .shape
+                .iter()
+                .map(|d| (*d).max(1) as f64)
+                .product::<f64>()
+        })
+        .sum::<f64>()
+        * element_bytes
+}
+
+/// Ops whose results `node` uses, looking through Vec operands, without
+/// duplicates
+fn op_operands(nodes: &[ffi::Node], node: &ffi::Node) -> Vec<usize> {
+    let mut operands = Vec::new();
+    let mut stack: Vec<usize> = node.operands.iter().map(|o| *o as usize).collect();
+    while let Some(operand) = stack.pop() {
+        let operand_node = &nodes[operand];
+        if operand_node.name == "Vec" {
+            stack.extend(operand_node.operands.iter().map(|o| *o as usize));
+        } else if is_op(operand_node) && !operands.contains(&operand) {
+            operands.push(operand);
+        }
+    }
+    operands
+}
+
+/// Live memory while running the ops of `nodes` in `order`
+struct Liveness<'a> {
+    nodes: &'a [ffi::Node],
+    operands: Vec<Vec<usize>>,
+    /// Number of users of each op not run yet
+    remaining_uses: Vec<usize>,
+    /// Whether each op stays alive until the end
+    pinned: Vec<bool>,
+    live: f64,
+    peak: f64,
+}
+
+impl<'a> Liveness<'a> {
+    fn new(nodes: &'a [ffi::Node], results: &[i32]) -> Self {
+        let operands: Vec<Vec<usize>> = nodes.iter().map(|n| op_operands(nodes, n)).collect();
+        let mut remaining_uses = vec![0; nodes.len()];
+        for (i, node) in nodes.iter().enumerate() {
+            if is_op(node) {
+                for operand in &operands[i] {
+                    remaining_uses[*operand] += 1;
+                }
+            }
+        }
+        let mut pinned: Vec<bool> = nodes.iter().map(|n| n.name == "Input").collect();
+        for result in results {
+            pinned[*result as usize] = true;
+        }
+        Liveness {
+            nodes,
+            operands,
+            remaining_uses,
+            pinned,
+            live: 0.0,
+            peak: 0.0,
+        }
+    }
+
+    /// Change of the live memory if op `i` ran next
+    fn delta(&self, i: usize) -> f64 {
+        let freed: f64 = self.operands[i]
+            .iter()
+            .filter(|o| !self.pinned[**o] && self.remaining_uses[**o] == 1)
+            .map(|o| node_bytes(&self.nodes[*o]))
+            .sum();
+        node_bytes(&self.nodes[i]) - freed
+    }
+
+    fn run(&mut self, i: usize) {
+        // The result is allocated while the operands are still alive
+        self.live += node_bytes(&self.nodes[i]);
+        self.peak = self.peak.max(self.live);
+        for operand in &self.operands[i] {
+            self.remaining_uses[*operand] -= 1;
+            if self.remaining_uses[*operand] == 0 && !self.pinned[*operand] {
+                self.live -= node_bytes(&self.nodes[*operand]);
+            }
+        }
+        // Results nobody uses are freed right away, unless they are kept
+        if self.remaining_uses[i] == 0 && !self.pinned[i] {
+            self.live -= node_bytes(&self.nodes[i]);
+        }
+    }
+}
+
+/// Estimated peak memory (bytes of live tensors) of running the ops of
+/// `nodes` in `order`
+pub fn peak_memory(nodes: &[ffi::Node], results: &[i32], order: &[i32]) -> f64 {
+    let mut liveness = Liveness::new(nodes, results);
+    for i in order {
+        liveness.run(*i as usize);
+    }
+    liveness.peak
+}
+
+/// Execution order of the ops of `nodes` (every node but Num, Vec and Var),
+/// each after the ops it uses, keeping the estimated peak memory low. Inputs
+/// come first. Falls back to the order of `nodes` if it has a lower peak
+pub fn memory_schedule(nodes: &[ffi::Node], results: &[i32]) -> Vec<i32> {
+    let emission_order: Vec<i32> = (0..nodes.len())
+        .filter(|i| is_op(&nodes[*i]))
+        .map(|i| i as i32)
+        .collect();
+
+    let mut liveness = Liveness::new(nodes, results);
+    let mut users: Vec<Vec<usize>> = vec![Vec::new(); nodes.len()];
+    let mut waiting_on: Vec<usize> = vec![0; nodes.len()];
+    for i in emission_order.iter().map(|i| *i as usize) {
+        waiting_on[i] = liveness.operands[i].len();
+        for operand in &liveness.operands[i] {
+            users[*operand].push(i);
+        }
+    }
+
+    let mut order: Vec<i32> = Vec::with_capacity(emission_order.len());
+    let mut ready: Vec<usize> = emission_order
+        .iter()
+        .map(|i| *i as usize)
+        .filter(|i| waiting_on[*i] == 0)
+        .collect();
+    while !ready.is_empty() {
+        // Inputs first, then the smallest growth, then emission order
+        let pos = (0..ready.len())
+            .min_by(|a, b| {
+                let key = |p: usize| {
+                    let i = ready[p];
+                    (nodes[i].name != "Input", liveness.delta(i), i)
+                };
+                key(*a).partial_cmp(&key(*b)).unwrap()
+            })
+            .unwrap();
+        let i = ready.swap_remove(pos);
+        liveness.run(i);
+        order.push(i as i32);
+        for user in &users[i] {
+            waiting_on[*user] -= 1;
+            if waiting_on[*user] == 0 {
+                ready.push(*user);
+            }
+        }
+    }
+    assert!(
+        order.len() == emission_order.len(),
+        "Graph nodes have a cycle"
+    );
+
+    if liveness.peak <= peak_memory(nodes, results, &emission_order) {
+        order
+    } else {
+        emission_order
+    }
+}
+
+/// Check that `schedule` lists every op of `nodes` once, after the ops it uses
+pub fn check_schedule(nodes: &[ffi::Node], schedule: &[i32]) -> Result<(), String> {
+    let mut done = vec![false; nodes.len()];
+    for i in schedule {
+        let node = nodes
+            .get(*i as usize)
+            .filter(|node| is_op(node))
+            .ok_or_else(|| format!("Scheduled node {} is not an op", i))?;
+        if done[*i as usize] {
+            return Err(format!("Node {} is scheduled twice", i));
+        }
+        if let Some(operand) = op_operands(nodes, node).iter().find(|o| !done[**o]) {
+            return Err(format!(
+                "Node {} ({}) is scheduled before node {}, which it uses",
+                i, node.name, operand
+            ));
+        }
+        done[*i as usize] = true;
+    }
+    match (0..nodes.len()).find(|i| is_op(&nodes[*i]) && !done[*i]) {
+        Some(i) => Err(format!("Node {} ({}) is not scheduled", i, nodes[i].name)),
+        None => Ok(()),
+    }
+}
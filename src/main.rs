@@ -5,9 +5,16 @@ use std::env::*;
 use std::fs::*;
 use std::time::*;
 use std::time::{Duration, Instant};
+use tensat::config::*;
+use tensat::diff::*;
+use tensat::input::{graph_to_string, CppGraphConverter};
+use tensat::merge::*;
 use tensat::model::*;
 use tensat::optimize::*;
+use tensat::pdll::*;
 use tensat::rewrites::*;
+use tensat::session::OptimizerSession;
+use tensat::taso_convert::*;
 use tensat::{parse::*, verify::*};
 
 use serde::{Deserialize, Serialize};
@@ -29,7 +36,9 @@ fn main() {
                 .long("mode")
                 .takes_value(true)
                 .default_value("optimize")
-                .help("Mode to run, can be verify, optimize, test, convert"),
+                .help(
+                    "Mode to run, can be verify, optimize, optimize_config, merge, test, convert, convert_stablehlo, export_pdll",
+                ),
         )
         .arg(
             Arg::with_name("model")
@@ -52,6 +61,18 @@ fn main() {
                 .takes_value(true)
                 .help("Provide a output file name. For mode convert, it's for converted rules; for mode optimize, it's for measured runtime"),
         )
+        .arg(
+            Arg::with_name("config")
+                .long("config")
+                .takes_value(true)
+                .help("TOML file with the settings of mode optimize_config, see OptimizeConfig"),
+        )
+        .arg(
+            Arg::with_name("diff_file")
+                .long("diff_file")
+                .takes_value(true)
+                .help("Write a report of the differences between the original and optimized graph"),
+        )
         .arg(
             Arg::with_name("export_model")
                 .short("x")
@@ -148,6 +169,19 @@ fn main() {
                 .takes_value(true)
                 .help("Number of threads for ILP solver"),
         )
+        .arg(
+            Arg::with_name("ilp_solver")
+                .long("ilp_solver")
+                .takes_value(true)
+                .possible_values(&["cbc", "gurobi", "scip"])
+                .help("MIP solver for the ILP, must be available in OR-Tools"),
+        )
+        .arg(
+            Arg::with_name("ilp_mip_gap")
+                .long("ilp_mip_gap")
+                .takes_value(true)
+                .help("Relative gap to the optimum at which the ILP solver stops"),
+        )
         .arg(
             Arg::with_name("iter_multi")
                 .long("iter_multi")
@@ -177,11 +211,35 @@ fn main() {
                 .long("all_weight_only")
                 .help("Treat zero cost for all weight concat only"),
         )
+        .arg(
+            Arg::with_name("egraph_out")
+                .long("egraph_out")
+                .takes_value(true)
+                .help("Write the saturated e-graph to this file, to be combined with mode merge"),
+        )
+        .arg(
+            Arg::with_name("egraph_files")
+                .long("egraph_files")
+                .takes_value(true)
+                .help("Comma separated e-graph files (written with --egraph_out) for mode merge"),
+        )
+        .arg(
+            Arg::with_name("merge")
+                .long("merge")
+                .takes_value(true)
+                .default_value("union")
+                .help("How mode merge combines the e-graphs, can be union, intersect"),
+        )
         .arg(
             Arg::with_name("saturation_only")
                 .long("saturation_only")
                 .help("Run saturation only"),
         )
+        .arg(
+            Arg::with_name("watch")
+                .long("watch")
+                .help("With mode optimize_config, optimize again whenever the rule file changes"),
+        )
         .get_matches();
 
     let run_mode = matches.value_of("mode").unwrap();
@@ -189,9 +247,13 @@ fn main() {
 
     match run_mode {
         "optimize" => optimize(matches),
+        "optimize_config" => optimize_config(matches),
+        "merge" => merge(matches),
         "verify" => prove_taso_rules(matches),
         "test" => test(matches),
         "convert" => convert_learned_rules(matches),
+        "convert_stablehlo" => convert_learned_rules_to_stablehlo(matches),
+        "export_pdll" => export_pdll(matches),
         _ => panic!("Running mode not supported"),
     }
 }
@@ -210,6 +272,139 @@ fn convert_learned_rules(matches: clap::ArgMatches) {
     write(outf, converted).expect("Unable to write file");
 }
 
+/// Convert TASO's learned rules into rules over the StableHLO ops (the format of
+/// converted.txt), reporting the substitutions that have no StableHLO equivalent.
+fn convert_learned_rules_to_stablehlo(matches: clap::ArgMatches) {
+    env_logger::init();
+
+    let file = matches
+        .value_of("rules")
+        .expect("Pls supply taso rules file.");
+    let outf = matches.value_of("out_file").unwrap_or("converted.txt");
+    let taso_rules = read_to_string(file).expect("Something went wrong reading the file");
+
+    let converted = convert_taso_rules(&taso_rules);
+    println!("Converted {} rules", converted.rules.len());
+    println!("Skipped {} rules", converted.skipped.len());
+    for (rule, reason) in converted.skipped.iter() {
+        println!("WARNING: skipped {}: {}", rule, reason);
+    }
+
+    write(outf, converted.rules.join("\n")).expect("Unable to write file");
+}
+
+/// Export the rules of a rule file (e.g. converted.txt) that have a direct
+/// StableHLO equivalent as MLIR PDLL patterns
+fn export_pdll(matches: clap::ArgMatches) {
+    let file = matches.value_of("rules").expect("Pls supply rules file.");
+    let outf = matches.value_of("out_file").unwrap_or("rules.pdll");
+    let rules = read_to_string(file).expect("Something went wrong reading the file");
+    let rules: Vec<&str> = rules.lines().filter(|l| !l.trim().is_empty()).collect();
+
+    let export = rules_to_pdll(&rules);
+    println!("Exported {} rules", export.num_exported);
+    println!("Skipped {} rules", export.skipped.len());
+    for (rule, reason) in export.skipped.iter() {
+        println!("  {}: {}", rule, reason);
+    }
+
+    write(outf, export.text).expect("Unable to write file");
+}
+
+/// Optimize the graph in the model file the way the C++ pass does, with the
+/// settings of a TOML config file, and write the optimized graph as text
+fn optimize_config(matches: clap::ArgMatches) {
+    let config_file = matches.value_of("config").expect("Pls supply config file.");
+    let model_file = matches
+        .value_of("model_file")
+        .expect("Pls supply input graph file.");
+    let outf = matches.value_of("out_file").unwrap_or("optimized.txt");
+    let config = OptimizeConfig::from_toml(config_file).unwrap_or_else(|e| panic!("{}", e));
+    if config.log_filter.is_none() {
+        env_logger::init();
+    }
+
+    let input_graph =
+        read_to_string(model_file).expect("Something went wrong reading the model file");
+    let mut converter = CppGraphConverter::from_rec_expr(input_graph.parse().unwrap());
+    if !matches.is_present("watch") {
+        *converter.config_mut() = config;
+        let graph = converter.optimize();
+        write(outf, graph_to_string(&graph)).expect("Unable to write file");
+        return;
+    }
+
+    // The session keeps the inferred shapes and measured costs across reloads,
+    // so only saturation and extraction are run again
+    let rule_file = config.rule_file.clone();
+    let mut session = OptimizerSession::new(config);
+    let mut modified = modified_time(&rule_file);
+    loop {
+        let start_time = Instant::now();
+        let graph = session.optimize_graph(&mut converter);
+        write(outf, graph_to_string(&graph)).expect("Unable to write file");
+        println!(
+            "Wrote {} in {:?}, watching {} for changes",
+            outf,
+            start_time.elapsed(),
+            rule_file
+        );
+        loop {
+            thread::sleep(Duration::from_millis(500));
+            let now = modified_time(&rule_file);
+            if now.is_some() && now != modified {
+                modified = now;
+                break;
+            }
+        }
+        println!("Rule file changed, reloading");
+        session.reload_rules();
+    }
+}
+
+/// Last modification time of `path`, None while it can't be read (e.g. an
+/// editor replacing it)
+fn modified_time(path: &str) -> Option<SystemTime> {
+    metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Combine e-graphs saturated with different rule sets (mode optimize with
+/// --egraph_out) and extract the best graph of the combination greedily, see
+/// merge.rs. The graph is written to the export_model file
+fn merge(matches: clap::ArgMatches) {
+    let egraph_files = matches
+        .value_of("egraph_files")
+        .expect("Pls supply e-graph files.");
+    let mode: MergeMode = matches
+        .value_of("merge")
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|e| panic!("{}", e));
+    let graphs: Vec<SerializedEGraph> = egraph_files
+        .split(',')
+        .map(|file| {
+            let text = read_to_string(file).expect("Something went wrong reading the e-graph file");
+            SerializedEGraph::from_json(&text).unwrap_or_else(|e| panic!("{}: {}", file, e))
+        })
+        .collect();
+    let analysis = TensorAnalysis::new(&HashMap::new(), &[]);
+    let (egraph, root) = merge_egraphs(&graphs, mode, analysis).unwrap_or_else(|e| panic!("{}", e));
+    println!("Merged {} e-graphs", graphs.len());
+    println!("  Nodes: {}", egraph.total_size());
+    println!("  Classes: {}", egraph.number_of_classes());
+
+    let cost_model = CostModel::default();
+    let tnsr_cost = TensorCost {
+        egraph: &egraph,
+        cost_model: &cost_model,
+    };
+    let (best_cost, best) = Extractor::new(&egraph, tnsr_cost).find_best(root);
+    println!("  Best cost: {:?}", best_cost);
+    if let Some(exportf) = matches.value_of("export_model") {
+        write(exportf, best.to_string()).expect("Unable to write file");
+    }
+}
+
 fn test(matches: clap::ArgMatches) {}
 
 /// Main procedure to run optimization
@@ -275,19 +470,13 @@ fn optimize(matches: clap::ArgMatches) {
     let mut multi_patterns = if let Some(rule_file) = matches.value_of("multi_rules") {
         let learned_rules =
             read_to_string(rule_file).expect("Something went wrong reading the rule file");
-        let pre_defined_multi = PRE_DEFINED_MULTI.iter().map(|&x| (x, /*symmetric=*/ false));
-        // The learned rules we have are symmetric. Predefined ones are not
-        let multi_rules: Vec<(&str, bool)> = learned_rules
+        let multi_rules: Vec<&str> = learned_rules
             .split("\n")
-            .map(|x| (x, /*symmetric=*/ true))
-            .chain(pre_defined_multi)
+            .chain(PRE_DEFINED_MULTI.iter().copied())
             .collect();
         MultiPatterns::with_rules(multi_rules, no_cycle, iter_multi, filter_after, node_multi, n_sec)
     } else {
-        let multi_rules: Vec<(&str, bool)> = PRE_DEFINED_MULTI
-            .iter()
-            .map(|&x| (x, /*symmetric=*/ false))
-            .collect();
+        let multi_rules: Vec<&str> = PRE_DEFINED_MULTI.to_vec();
         MultiPatterns::with_rules(multi_rules, no_cycle, iter_multi, filter_after, node_multi, n_sec)
     };
 
@@ -348,6 +537,10 @@ fn optimize(matches: clap::ArgMatches) {
     if save_graph == "all" {
         egraph.dot().to_svg("target/tensat.svg").unwrap();
     }
+    if let Some(egraph_out) = matches.value_of("egraph_out") {
+        let serialized = SerializedEGraph::new(&egraph, root);
+        write(egraph_out, serialized.to_json()).expect("Unable to write e-graph");
+    }
 
     if matches.is_present("saturation_only") {
         if let Some(outf) = matches.value_of("out_file") {
@@ -399,6 +592,11 @@ fn optimize(matches: clap::ArgMatches) {
             _ => panic!("Extracting mode not supported"),
         };
 
+        if let Some(diff_file) = matches.value_of("diff_file") {
+            let diff = diff_graphs(&egraph, &start, &best, &cost_model);
+            write(diff_file, diff.to_string()).expect("Unable to write diff report");
+        }
+
         // Evaluation starting and extracted graph runtime, save graphs
         let runner_start = Runner::<Mdl, TensorAnalysis, ()>::default().with_expr(&start);
         let runner_ext = Runner::<Mdl, TensorAnalysis, ()>::default().with_expr(&best);
@@ -485,10 +683,10 @@ fn extract_by_ilp(
     let initialize = matches.is_present("initial_with_greedy");
     if initialize {
         // Get node_to_i map
-        let node_to_i: HashMap<Mdl, usize> = (&i_to_nodes)
+        let node_to_i: HashMap<&Mdl, usize> = i_to_nodes
             .iter()
             .enumerate()
-            .map(|(i, node)| (node.clone(), i))
+            .map(|(i, node)| (*node, i))
             .collect();
 
         let tnsr_cost = TensorCost {
@@ -533,6 +731,14 @@ fn extract_by_ilp(
         arg_vec.push("--num_thread");
         arg_vec.push(num_thread);
     }
+    if let Some(solver) = matches.value_of("ilp_solver") {
+        arg_vec.push("--solver");
+        arg_vec.push(solver);
+    }
+    if let Some(mip_gap) = matches.value_of("ilp_mip_gap") {
+        arg_vec.push("--mip_gap");
+        arg_vec.push(mip_gap);
+    }
     let child = Command::new("python")
         .args(&arg_vec)
         .spawn()
@@ -543,30 +749,27 @@ fn extract_by_ilp(
         // Read back solved results, construct optimized graph
         let solved_str = read_to_string("./tmp/solved.json")
             .expect("Something went wrong reading the solved file");
-        let solved_data: SolvedResults =
-            serde_json::from_str(&solved_str).expect("JSON was not well-formatted");
-
-        let mut node_picked: HashMap<Id, Mdl> = HashMap::new();
-        for (i, x_i) in solved_data.solved_x.iter().enumerate() {
-            if *x_i == 1 {
-                let eclass_id = m_id_map[g_i[i]];
-                if node_picked.contains_key(&eclass_id) {
-                    println!("Duplicate node in eclass");
-                    println!("{}", node_picked.get(&eclass_id).unwrap().display_op());
-                    println!("{}", i_to_nodes[i].display_op());
-                    continue;
-                }
-                //assert!(!node_picked.contains_key(&eclass_id));
-                node_picked.insert(eclass_id, i_to_nodes[i].clone());
-            }
-        }
+        let solved_data = parse_solved(&solved_str).unwrap_or_else(|e| panic!("{}", e));
+
+        let node_picked = picked_nodes(
+            &solved_data.solved_x,
+            &m_id_map,
+            &g_i,
+            &i_to_nodes,
+            &blacklist_i,
+        )
+        .unwrap_or_else(|e| panic!("{}", e));
 
         let mut expr = RecExpr::default();
         let mut added_memo: HashMap<Id, Id> = Default::default();
-        let _ = construct_best_rec(&node_picked, root, &mut added_memo, egraph, &mut expr);
+        construct_best_rec(&node_picked, root, &mut added_memo, egraph, &mut expr)
+            .unwrap_or_else(|e| panic!("Failed to reconstruct the extracted graph: {}", e));
         (expr, solved_data.time)
     } else {
-        panic!("Python script failed");
+        match read_to_string("./tmp/solved.json").map(|text| parse_solved(&text)) {
+            Ok(Err(e)) => panic!("{}", e),
+            _ => panic!("Python script failed with {}", output.status),
+        }
     }
 }
 
@@ -0,0 +1,209 @@
+//! Combining e-graphs saturated with different rule sets
+//!
+//! With all rule families enabled at once the e-graph can blow up long before
+//! saturation gets to the interesting rewrites. Instead, the input graph can be
+//! saturated once per rule set (e.g. in separate processes, with a rule file
+//! each), every e-graph written out as a `SerializedEGraph`, and the e-graphs
+//! combined for a single extraction:
+//!
+//! - `MergeMode::Union` keeps the enodes of all e-graphs. Eclasses sharing an
+//!   enode are merged, so the extracted graph can mix rewrites found by
+//!   different runs.
+//! - `MergeMode::Intersect` only lets extraction pick enodes found by every
+//!   run (compared in the merged e-graph), the others are blacklisted.
+//!
+//! Merged e-graphs can have cycles through enodes of different runs, even if
+//! every run filtered its cycles, so they are extracted greedily.
+
+use crate::model::*;
+use egg::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// How `merge_egraphs` combines the e-graphs
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MergeMode {
+    Union,
+    Intersect,
+}
+
+impl FromStr for MergeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "union" => Ok(MergeMode::Union),
+            "intersect" => Ok(MergeMode::Intersect),
+            _ => Err(format!("Unknown merge mode {}, can be union, intersect", s)),
+        }
+    }
+}
+
+/// E-graph in a form that can be written to a file and read by another process
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SerializedEGraph {
+    /// Enodes of each eclass, as their op (see Language::display_op) and the
+    /// indices of the eclasses of their children
+    pub classes: Vec<Vec<(String, Vec<usize>)>>,
+    /// Index of the eclass of the root
+    pub root: usize,
+}
+
+impl SerializedEGraph {
+    /// Serialize `egraph`, without its blacklisted enodes
+    pub fn new(egraph: &EGraph<Mdl, TensorAnalysis>, root: Id) -> Self {
+        let index: HashMap<Id, usize> = egraph
+            .classes()
+            .enumerate()
+            .map(|(i, class)| (class.id, i))
+            .collect();
+        let classes = egraph
+            .classes()
+            .map(|class| {
+                class
+                    .iter()
+                    .filter(|node| !egraph.analysis.blacklist_nodes.contains(*node))
+                    .map(|node| {
+                        let children = node
+                            .children()
+                            .iter()
+                            .map(|c| index[&egraph.find(*c)])
+                            .collect();
+                        (node.display_op().to_string(), children)
+                    })
+                    .collect()
+            })
+            .collect();
+        SerializedEGraph {
+            classes,
+            root: index[&egraph.find(root)],
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("Fail to convert e-graph to json")
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let graph: SerializedEGraph =
+            serde_json::from_str(text).map_err(|e| format!("Invalid e-graph: {}", e))?;
+        graph.validate()?;
+        Ok(graph)
+    }
+
+    /// Check that the eclass indices are in range
+    pub fn validate(&self) -> Result<(), String> {
+        let num_classes = self.classes.len();
+        let mut children = self.classes.iter().flatten().flat_map(|(_, c)| c);
+        if self.root >= num_classes || children.any(|c| *c >= num_classes) {
+            return Err("Invalid e-graph: eclass index out of range".to_string());
+        }
+        Ok(())
+    }
+
+    /// The enode with `op` and the eclasses `class_ids` gives for `children`
+    fn enode(op: &str, children: &[usize], class_ids: &[Id]) -> Result<Mdl, String> {
+        Mdl::from_op_str(op, children.iter().map(|c| class_ids[*c]).collect())
+    }
+}
+
+/// Add the enodes of `graph` to `egraph`, returning the eclass of `egraph`
+/// each of its eclasses ended up in
+///
+/// An enode can only be added after its children, so enodes are added in
+/// rounds until all are: cycles are fine as long as every eclass has an enode
+/// outside of them, which holds for any e-graph grown from a graph.
+fn add_serialized(
+    egraph: &mut EGraph<Mdl, TensorAnalysis>,
+    graph: &SerializedEGraph,
+) -> Result<Vec<Id>, String> {
+    let mut class_ids: Vec<Option<Id>> = vec![None; graph.classes.len()];
+    let mut added: Vec<Vec<bool>> = graph.classes.iter().map(|c| vec![false; c.len()]).collect();
+    let mut progress = true;
+    while progress {
+        progress = false;
+        for (m, class) in graph.classes.iter().enumerate() {
+            for (k, (op, children)) in class.iter().enumerate() {
+                if added[m][k] {
+                    continue;
+                }
+                let children = match children
+                    .iter()
+                    .map(|c| class_ids[*c])
+                    .collect::<Option<_>>()
+                {
+                    Some(children) => children,
+                    None => continue,
+                };
+                let id = egraph.add(Mdl::from_op_str(op, children)?);
+                if let Some(class) = class_ids[m] {
+                    egraph.union(class, id);
+                }
+                class_ids[m] = Some(id);
+                added[m][k] = true;
+                progress = true;
+            }
+        }
+    }
+    egraph.rebuild();
+    class_ids
+        .iter()
+        .enumerate()
+        .map(|(m, id)| {
+            id.map(|id| egraph.find(id))
+                .ok_or_else(|| format!("Eclass {} has no enode outside of a cycle", m))
+        })
+        .collect()
+}
+
+/// Combine e-graphs saturated from the same graph into one
+///
+/// # Returns
+///
+/// The combined e-graph and its root. Fails if the e-graphs don't have the
+/// same root, i.e. they were saturated from different graphs.
+pub fn merge_egraphs(
+    graphs: &[SerializedEGraph],
+    mode: MergeMode,
+    analysis: TensorAnalysis,
+) -> Result<(EGraph<Mdl, TensorAnalysis>, Id), String> {
+    let mut egraph = EGraph::new(analysis);
+    let mut all_class_ids = Vec::with_capacity(graphs.len());
+    for graph in graphs {
+        all_class_ids.push(add_serialized(&mut egraph, graph)?);
+    }
+    let mut roots: HashSet<Id> = graphs
+        .iter()
+        .zip(&all_class_ids)
+        .map(|(graph, class_ids)| egraph.find(class_ids[graph.root]))
+        .collect();
+    let root = match roots.len() {
+        0 => return Err("No e-graphs to merge".to_string()),
+        1 => roots.drain().next().unwrap(),
+        _ => return Err("The e-graphs were saturated from different graphs".to_string()),
+    };
+
+    if mode == MergeMode::Intersect {
+        // Number of runs that found each enode
+        let mut found: HashMap<Mdl, usize> = HashMap::new();
+        for (graph, class_ids) in graphs.iter().zip(&all_class_ids) {
+            let class_ids: Vec<Id> = class_ids.iter().map(|id| egraph.find(*id)).collect();
+            let mut nodes = HashSet::new();
+            for (op, children) in graph.classes.iter().flatten() {
+                nodes.insert(SerializedEGraph::enode(op, children, &class_ids)?);
+            }
+            for node in nodes {
+                *found.entry(node).or_default() += 1;
+            }
+        }
+        let not_found_by_all: Vec<Mdl> = egraph
+            .classes()
+            .flat_map(|class| class.iter())
+            .filter(|node| found.get(*node) != Some(&graphs.len()))
+            .cloned()
+            .collect();
+        egraph.analysis.blacklist_nodes.extend(not_found_by_all);
+    }
+    Ok((egraph, root))
+}
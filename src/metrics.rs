@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+
+/// Upper bounds of the histogram buckets, shared by all histograms. Wide
+/// enough for both seconds and (in thousands) e-graph sizes
+const BUCKETS: [f64; 12] = [
+    0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 300.0, 1e3, 1e4,
+];
+
+/// Kind of a metric, as passed to the callback
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MetricKind {
+    /// Incremented by the value
+    Counter,
+    /// The value is one observation
+    Histogram,
+}
+
+/// Called with the name, kind and value of each metric update
+pub type MetricsCallback = Box<dyn Fn(&str, MetricKind, f64) + Send + Sync>;
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    /// Observations in each bucket of BUCKETS (not cumulative)
+    counts: [u64; BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        if let Some(bucket) = BUCKETS.iter().position(|bound| value <= *bound) {
+            self.counts[bucket] += 1;
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+struct Registry {
+    counters: BTreeMap<String, f64>,
+    histograms: BTreeMap<String, Histogram>,
+    callback: Option<MetricsCallback>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    counters: BTreeMap::new(),
+    histograms: BTreeMap::new(),
+    callback: None,
+});
+
+fn registry() -> std::sync::MutexGuard<'static, Registry> {
+    // A panicking callback must not disable metrics for the rest of the process
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Forward every metric update to `callback`, e.g. to feed the host's
+/// telemetry. Replaces the previous callback. The metrics are still kept for
+/// `prometheus_text`. The callback runs with the metrics locked, so it must not
+/// record or read metrics itself
+pub fn set_metrics_callback(callback: MetricsCallback) {
+    registry().callback = Some(callback);
+}
+
+pub fn clear_metrics_callback() {
+    registry().callback = None;
+}
+
+/// Add `by` to the counter `name`
+pub fn increment(name: &str, by: f64) {
+    let mut registry = registry();
+    *registry.counters.entry(name.to_string()).or_insert(0.0) += by;
+    if let Some(callback) = &registry.callback {
+        callback(name, MetricKind::Counter, by);
+    }
+}
+
+/// Record `value` in the histogram `name`
+pub fn observe(name: &str, value: f64) {
+    let mut registry = registry();
+    registry
+        .histograms
+        .entry(name.to_string())
+        .or_default()
+        .observe(value);
+    if let Some(callback) = &registry.callback {
+        callback(name, MetricKind::Histogram, value);
+    }
+}
+
+/// Forget all metrics recorded so far (the callback is kept)
+pub fn reset_metrics() {
+    let mut registry = registry();
+    registry.counters.clear();
+    registry.histograms.clear();
+}
+
+/// Value of the counter `name`, zero if it was never incremented
+pub fn counter_value(name: &str) -> f64 {
+    registry().counters.get(name).copied().unwrap_or(0.0)
+}
+
+/// Number of observations of the histogram `name`
+pub fn histogram_count(name: &str) -> u64 {
+    registry().histograms.get(name).map_or(0, |h| h.count)
+}
+
+/// The metrics recorded so far in the Prometheus text exposition format, to be
+/// served on the host's metrics endpoint
+pub fn prometheus_text() -> String {
+    let registry = registry();
+    let mut text = String::new();
+    for (name, value) in &registry.counters {
+        writeln!(text, "# TYPE {} counter", name).unwrap();
+        writeln!(text, "{} {}", name, value).unwrap();
+    }
+    for (name, histogram) in &registry.histograms {
+        writeln!(text, "# TYPE {} histogram", name).unwrap();
+        let mut cumulative = 0;
+        for (bound, count) in BUCKETS.iter().zip(histogram.counts.iter()) {
+            cumulative += count;
+            writeln!(text, "{}_bucket{{le=\"{}\"}} {}", name, bound, cumulative).unwrap();
+        }
+        writeln!(text, "{}_bucket{{le=\"+Inf\"}} {}", name, histogram.count).unwrap();
+        writeln!(text, "{}_sum {}", name, histogram.sum).unwrap();
+        writeln!(text, "{}_count {}", name, histogram.count).unwrap();
+    }
+    text
+}
+
+/// Metrics recorded by each optimize call
+pub mod names {
+    /// Counter of optimize calls that ran saturation
+    pub const OPTIMIZATIONS: &str = "tensat_optimizations_total";
+    pub const SATURATION_SECONDS: &str = "tensat_saturation_seconds";
+    pub const EXTRACTION_SECONDS: &str = "tensat_extraction_seconds";
+    /// Number of enodes in the saturated e-graph, in thousands
+    pub const EGRAPH_KNODES: &str = "tensat_egraph_knodes";
+    pub const SATURATION_ITERATIONS: &str = "tensat_saturation_iterations";
+    /// Estimated cost of the input graph over the cost of the optimized graph
+    pub const COST_IMPROVEMENT: &str = "tensat_cost_improvement_ratio";
+    /// Counter of OptimizerSession calls answered with a graph optimized before
+    pub const SESSION_CACHE_HITS: &str = "tensat_session_cache_hits_total";
+    /// Counter of rules skipped for an iteration because e-matching them took
+    /// longer than their time limit
+    pub const RULE_MATCH_TIMEOUTS: &str = "tensat_rule_match_timeouts_total";
+}
@@ -0,0 +1,243 @@
+//! Rust stand-ins for the C++ shape inference and cost model
+//!
+//! With the `no-cxx-costmodel` feature these replace `ffi::get_shape` and
+//! `ffi::get_cost`, so the crate builds and optimizes without the Enzyme-JAX
+//! C++ sources. Shapes follow the StableHLO rules for the ops rewrites touch;
+//! costs are a deterministic estimate from the shapes, not measurements.
+
+use crate::collective::{all_gather_dims, all_to_all_dims, reduce_scatter_dims};
+use crate::convolution::{convolution_dims, ConvDimensionNumbers};
+use crate::fft::{fft_dims, FftType};
+use crate::input::ffi;
+use crate::rewrites::padded_dims;
+
+fn shape(dims: Vec<i64>) -> ffi::Shape {
+    ffi::Shape { shape: dims }
+}
+
+fn num_elements(shape: &ffi::Shape) -> u64 {
+    shape.shape.iter().map(|d| (*d).max(1) as u64).product()
+}
+
+/// Dimensions of `shape` not listed in `batch` or `contract`
+fn free_dims(shape: &ffi::Shape, batch: &ffi::Shape, contract: &ffi::Shape) -> Vec<i64> {
+    (0..shape.shape.len() as i64)
+        .filter(|d| !batch.shape.contains(d) && !contract.shape.contains(d))
+        .map(|d| shape.shape[d as usize])
+        .collect()
+}
+
+/// Shapes of the results of `op`, with the arguments laid out as by
+/// `create_stablehlo_op`: tensor operands, vector attributes and integer
+/// attributes, each in operand order.
+///
+/// Ops whose result shape isn't determined by the arguments (e.g.
+/// BroadcastInDimOp) get the shape of their first operand, ClampOp and
+/// TriangularSolveOp that of their second. SortOp, TupleOp and
+/// OptimizationBarrierOp have one result per operand, of its shape, ReduceOp
+/// one per input (the first half of its operands, the others are its init
+/// values). BatchNormTrainingOp and BatchNormGradOp have the shape of their
+/// operand, then two of its feature dimension.
+pub fn get_shape(
+    op: ffi::Ops,
+    operand_dims: Vec<ffi::Shape>,
+    _operands_types: Vec<ffi::Type>,
+    other_vector_args: Vec<ffi::Shape>,
+    int_args: Vec<i64>,
+) -> Vec<ffi::Shape> {
+    if op == ffi::Ops::SortOp || op == ffi::Ops::TupleOp || op == ffi::Ops::OptimizationBarrierOp {
+        return operand_dims;
+    }
+    if op == ffi::Ops::BatchNormTrainingOp || op == ffi::Ops::BatchNormGradOp {
+        let features = shape(vec![operand_dims[0].shape[int_args[1] as usize]]);
+        return vec![operand_dims[0].clone(), features.clone(), features];
+    }
+    if op == ffi::Ops::ReduceOp {
+        let dimensions = &other_vector_args[0].shape;
+        return operand_dims[..operand_dims.len() / 2]
+            .iter()
+            .map(|input| {
+                shape(
+                    (0..input.shape.len() as i64)
+                        .filter(|d| !dimensions.contains(d))
+                        .map(|d| input.shape[d as usize])
+                        .collect(),
+                )
+            })
+            .collect();
+    }
+    let args = &operand_dims;
+    let vecs = &other_vector_args;
+    let result = match op {
+        ffi::Ops::TransposeOp => {
+            shape(vecs[0].shape.iter().map(|p| args[0].shape[*p as usize]).collect())
+        }
+        ffi::Ops::ReshapeOp | ffi::Ops::IotaOp => vecs[0].clone(),
+        ffi::Ops::ClampOp | ffi::Ops::TriangularSolveOp => args[1].clone(),
+        ffi::Ops::ConcatenateOp => {
+            let dim = int_args[0] as usize;
+            let mut dims = args[0].shape.clone();
+            dims[dim] = args.iter().map(|a| a.shape[dim]).sum();
+            shape(dims)
+        }
+        ffi::Ops::SliceOp => {
+            let (start, limit, strides) = (&vecs[0].shape, &vecs[1].shape, &vecs[2].shape);
+            shape(
+                (0..start.len())
+                    .map(|i| (limit[i] - start[i] + strides[i] - 1) / strides[i])
+                    .collect(),
+            )
+        }
+        ffi::Ops::PadOp => {
+            let (low, high, interior) = (&vecs[0].shape, &vecs[1].shape, &vecs[2].shape);
+            match padded_dims(&args[0].shape, low, high, interior) {
+                Ok(dims) => shape(dims),
+                Err(e) => panic!("Invalid PadOp: {}", e),
+            }
+        }
+        ffi::Ops::DotGeneralOp => {
+            let (lb, rb, lc, rc) = (&vecs[0], &vecs[1], &vecs[2], &vecs[3]);
+            shape(
+                lb.shape
+                    .iter()
+                    .map(|d| args[0].shape[*d as usize])
+                    .chain(free_dims(&args[0], lb, lc))
+                    .chain(free_dims(&args[1], rb, rc))
+                    .collect(),
+            )
+        }
+        ffi::Ops::ConvolutionOp => match convolution_dims(
+            &args[0].shape,
+            &args[1].shape,
+            &vecs[0].shape,
+            &vecs[1].shape,
+            &vecs[2].shape,
+            &vecs[3].shape,
+            &vecs[4].shape,
+            int_args[0],
+            int_args[1],
+        ) {
+            Ok(dims) => shape(dims),
+            Err(e) => panic!("Invalid ConvolutionOp: {}", e),
+        },
+        ffi::Ops::FftOp => match FftType::from_num(int_args[0])
+            .and_then(|fft_type| fft_dims(&args[0].shape, fft_type, &vecs[0].shape))
+        {
+            Ok(dims) => shape(dims),
+            Err(e) => panic!("Invalid FftOp: {}", e),
+        },
+        ffi::Ops::AllGatherOp => match all_gather_dims(&args[0].shape, int_args[0], int_args[1]) {
+            Ok(dims) => shape(dims),
+            Err(e) => panic!("Invalid AllGatherOp: {}", e),
+        },
+        ffi::Ops::ReduceScatterOp => {
+            match reduce_scatter_dims(&args[0].shape, int_args[0], int_args[1]) {
+                Ok(dims) => shape(dims),
+                Err(e) => panic!("Invalid ReduceScatterOp: {}", e),
+            }
+        }
+        ffi::Ops::AllToAllOp => {
+            match all_to_all_dims(&args[0].shape, int_args[0], int_args[1], int_args[2]) {
+                Ok(dims) => shape(dims),
+                Err(e) => panic!("Invalid AllToAllOp: {}", e),
+            }
+        }
+        _ => args[0].clone(),
+    };
+    vec![result]
+}
+
+/// Estimated cost of `op`: the number of result elements, times the size of
+/// the contracted dimensions for DotGeneralOp (of the kernel window, over the
+/// input features, for ConvolutionOp, of the window for SelectAndScatterOp,
+/// the log of the sorted dimension for SortOp, of the transform size for
+/// FftOp, the size of the matrices for CholeskyOp and TriangularSolveOp),
+/// halved for ops on bf16 operands. Reshapes are free. Collectives cost the
+/// elements each replica sends, see communicated_elements.
+pub fn get_cost(
+    op: ffi::Ops,
+    operand_dims: Vec<ffi::Shape>,
+    operands_types: Vec<ffi::Type>,
+    other_vector_args: Vec<ffi::Shape>,
+    int_args: Vec<i64>,
+) -> u64 {
+    if op == ffi::Ops::ReshapeOp {
+        return 0;
+    }
+    if let Some(elements) = communicated_elements(op, &operand_dims, &int_args) {
+        return elements * COMMUNICATION_COST;
+    }
+    let contracted = match op {
+        ffi::Ops::DotGeneralOp => other_vector_args[2]
+            .shape
+            .iter()
+            .map(|d| operand_dims[0].shape[*d as usize].max(1) as u64)
+            .product(),
+        ffi::Ops::ConvolutionOp => {
+            let kernel = &operand_dims[1];
+            let num_spatial = other_vector_args[0].shape.len();
+            match ConvDimensionNumbers::from_flat(&other_vector_args[4].shape, num_spatial) {
+                Ok(dims) => {
+                    num_elements(kernel) / kernel.shape[dims.kernel_output_feature].max(1) as u64
+                }
+                Err(e) => panic!("Invalid ConvolutionOp: {}", e),
+            }
+        }
+        ffi::Ops::SelectAndScatterOp => num_elements(&other_vector_args[0]),
+        ffi::Ops::SortOp => {
+            let size = operand_dims[0].shape[int_args[0] as usize].max(2) as u64;
+            u64::BITS as u64 - (size - 1).leading_zeros() as u64
+        }
+        ffi::Ops::FftOp => {
+            let size = num_elements(&other_vector_args[0]).max(2);
+            u64::BITS as u64 - (size - 1).leading_zeros() as u64
+        }
+        // n^3 for each n x n matrix, n^2 for each column of b
+        ffi::Ops::CholeskyOp | ffi::Ops::TriangularSolveOp => operand_dims[0]
+            .shape
+            .last()
+            .map_or(1, |n| (*n).max(1) as u64),
+        _ => 1,
+    };
+    let half_width = operands_types.first() == Some(&ffi::Type::bf16);
+    let results = get_shape(
+        op,
+        operand_dims,
+        operands_types,
+        other_vector_args,
+        int_args,
+    );
+    let cost = results.iter().map(num_elements).sum::<u64>() * contracted;
+    if half_width {
+        cost / 2
+    } else {
+        cost
+    }
+}
+
+/// Cost of sending an element to another replica, relative to computing one
+const COMMUNICATION_COST: u64 = 4;
+
+/// Elements each replica sends for the collective `op` with the ring
+/// algorithms, if `op` is a collective. A group of n replicas all reduces in
+/// a reduce scatter then an all gather, each sending (n-1)/n of the operand.
+fn communicated_elements(
+    op: ffi::Ops,
+    operand_dims: &[ffi::Shape],
+    int_args: &[i64],
+) -> Option<u64> {
+    let group_size = match op {
+        ffi::Ops::AllReduceOp => int_args[0],
+        ffi::Ops::AllGatherOp | ffi::Ops::ReduceScatterOp => int_args[1],
+        ffi::Ops::AllToAllOp => int_args[2],
+        _ => return None,
+    };
+    let n = group_size.max(1) as u64;
+    let elements = num_elements(&operand_dims[0]);
+    Some(match op {
+        ffi::Ops::AllReduceOp => 2 * elements * (n - 1) / n,
+        // Each replica sends its whole operand to the n-1 others
+        ffi::Ops::AllGatherOp => elements * (n - 1),
+        _ => elements * (n - 1) / n,
+    })
+}
@@ -5,11 +5,15 @@
 //use rand::prelude::*;
 use rand;
 use std::convert::TryInto;
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use std::{collections::HashMap, collections::HashSet};
 use {
+    crate::composite::CompositeOpDef,
     crate::ffi_utils::*,
+    crate::fft::FftType,
     crate::input::ffi::{self, Shape},
+    crate::input::NameGen,
     crate::rewrites::*,
 };
 
@@ -29,20 +33,26 @@ pub const SHUFFLE: i32 = 1;
 
 define_language! {
   pub enum Mdl {
-      "input"              = Input([Id; 2]),  // takes Var: name@dim1_dim2, block_arg_number
+      "input"              = Input([Id; 2]),  // takes Var: name@dim1_dim2[@dtype], block_arg_number
       "CompareOp"          = CompareOp([Id; 4]), // input1, input2, comparison_direction,
                                                            // comparsion_type
       "BroadcastInDimOp"   = BroadcastInDimOp([Id; 2]), // input, broadcast_dimensions
       // TODO: we might need the input type as well.
       "ConvertOp"          = ConvertOp([Id; 2]), // input, output_tyoe.
-      // TODO: we probably won't have any rewrites for reduces. Maybe function pointers for the
-      // body
-      "ReduceOp"           = ReduceOp([Id; 2]), // input, init_values, dimensions, body
+      // One result per input, read with Index. The body is a region of the
+      // converter (like the body of WhileOp) taking an accumulator per input
+      // then an element per input
+      "ReduceOp"           = ReduceOp([Id; 4]), // inputs, init_values, dimensions, body
       "ReshapeOp"          = ReshapeOp([Id; 2]), // input, shape
       "GatherOp"           = GatherOp([Id; 10]),
       "SelectOp"           = SelectOp([Id; 3]), // pred, on_true, on_false
       "ConcatenateOp"      = ConcatenateOp([Id; 2]), // inputs, dimension
       "DotGeneralOp"       = DotGeneralOp([Id; 7]), // lhs, rhs, ..., shape
+      "ConvolutionOp"      = ConvolutionOp([Id; 10]), // lhs, rhs, window_strides, padding,
+                                                       // lhs_dilation, rhs_dilation,
+                                                       // dimension_numbers, feature_group_count,
+                                                       // batch_group_count, precision_config
+                                                       // (see convolution.rs)
       "PadOp"              = PadOp([Id; 5]), // input, padding_value, edge_padding_low,
                                                        // edge_padding_high, interior_padding
       "SliceOp"            = SliceOp([Id; 4]), // input, start_indices, limit_indices, strides
@@ -54,23 +64,120 @@ define_language! {
       "SubtractOp"         = SubtractOp([Id; 2]),
       "MinOp"              = MinOp([Id; 2]),
       "MaxOp"              = MaxOp([Id; 2]),
+      "PowOp"              = PowOp([Id; 2]), // base, exponent
+      // Has the sign of the dividend, like C's fmod and %
+      "RemainderOp"        = RemainderOp([Id; 2]), // dividend, divisor
+      // Bitwise ops take booleans or integers, shifts only integers
+      "AndOp"              = AndOp([Id; 2]),
+      "OrOp"               = OrOp([Id; 2]),
+      "XorOp"              = XorOp([Id; 2]),
+      "NotOp"              = NotOp([Id; 1]), // input
+      "ShiftLeftOp"        = ShiftLeftOp([Id; 2]), // input, shift amount
+      "ShiftRightLogicalOp" = ShiftRightLogicalOp([Id; 2]), // input, shift amount
+      "ShiftRightArithmeticOp" = ShiftRightArithmeticOp([Id; 2]), // input, shift amount
+      // Bounds are scalars or of the operand's shape
+      "ClampOp"            = ClampOp([Id; 3]), // min, operand, max
       // UNARY OPS
       "NegOp"              = NegOp([Id; 1]), // input
       "TanhOp"             = TanhOp([Id; 1]), // input
       "ExpOp"              = ExpOp([Id; 1]), // input
+      "SqrtOp"             = SqrtOp([Id; 1]), // input
+      "RsqrtOp"            = RsqrtOp([Id; 1]), // input
+      "LogOp"              = LogOp([Id; 1]), // input
+      "SineOp"             = SineOp([Id; 1]), // input
+      "CosineOp"           = CosineOp([Id; 1]), // input
+      "AbsOp"              = AbsOp([Id; 1]), // input
+      "SignOp"             = SignOp([Id; 1]), // input
+      "FloorOp"            = FloorOp([Id; 1]), // input
+      "CeilOp"             = CeilOp([Id; 1]), // input
+      // Rounds halfway cases away from zero
+      "RoundOp"            = RoundOp([Id; 1]), // input
+      "ErfOp"              = ErfOp([Id; 1]), // input
+      // COMPLEX OPS
+      "ComplexOp"          = ComplexOp([Id; 2]), // real, imag
+      "RealOp"             = RealOp([Id; 1]), // input
+      "ImagOp"             = ImagOp([Id; 1]), // input
+      "FftOp"              = FftOp([Id; 3]), // input, fft_type, fft_length (see fft.rs)
+      // LINEAR ALGEBRA OPS
+      // On the last two dimensions, the others are batch dimensions. Flags
+      // are Nums of 0 or 1, transpose_a is a stablehlo Transpose (1: none,
+      // 2: transpose, 3: adjoint)
+      "CholeskyOp"         = CholeskyOp([Id; 2]), // a, lower
+      "TriangularSolveOp"  = TriangularSolveOp([Id; 6]), // a, b, left_side, lower,
+                                                         // unit_diagonal, transpose_a
+      // NORMALIZATION OPS
+      // Per feature of the operand's dimension feature_index, the epsilon is
+      // the bits of an f32 as Num (like ConstantOp). Training and Grad have
+      // three results, read with Index: one of the operand's shape, then two
+      // per feature (batch mean and variance, grad of scale and offset)
+      "BatchNormInferenceOp" = BatchNormInferenceOp([Id; 7]), // operand, scale, offset, mean,
+                                                              // variance, epsilon, feature_index
+      "BatchNormTrainingOp" = BatchNormTrainingOp([Id; 5]), // operand, scale, offset, epsilon,
+                                                            // feature_index
+      "BatchNormGradOp"    = BatchNormGradOp([Id; 7]), // operand, scale, mean, variance,
+                                                       // grad_output, epsilon, feature_index
+      // COLLECTIVE OPS
+      // replica_groups is the Vec of the replica ids of every group in turn,
+      // reduction a Num (see collective.rs)
+      "AllReduceOp"        = AllReduceOp([Id; 4]), // operand, replica_groups, group_size,
+                                                   // reduction
+      "AllGatherOp"        = AllGatherOp([Id; 4]), // operand, all_gather_dim, replica_groups,
+                                                   // group_size
+      "ReduceScatterOp"    = ReduceScatterOp([Id; 5]), // operand, scatter_dimension,
+                                                       // replica_groups, group_size, reduction
+      "AllToAllOp"         = AllToAllOp([Id; 5]), // operand, split_dimension, concat_dimension,
+                                                  // replica_groups, group_size
+      // COMPOSITE OPS
+      "GeluOp"             = GeluOp([Id; 2]), // input, approximate (0: erf, 1: tanh)
       // MISC OPS
       "IotaOp"             = IotaOp([Id; 2]), // iota_dimension, output_shape
-      // "ConstantOp"         = ConstantOp([Id; 0]),
+      "ConstantOp"         = ConstantOp([Id; 2]), // splat value (f32 bits as Num), shape
+      "DenseConstantOp"    = DenseConstantOp([Id; 3]), // handle of the values (Num, see
+                                                       // TensorAnalysis::dense_constants), shape,
+                                                       // element type (Num, see dtype_to_num)
       "DynamicUpdateSliceOp" = DynamicUpdateSliceOp([Id; 3]), // operand, update, start_indices
       "DynamicSliceOp"     = DynamicSliceOp([Id; 3]), // operand, start_indices, slice_sizes
       // Complete pain, has arity 12
       "ScatterOp"          = ScatterOp([Id; 4]), // input, scatter_indices, updates, dimension_numbers
+      "SelectAndScatterOp" = SelectAndScatterOp([Id; 6]), // operand, source, init_value,
+                                                            // window_dimensions, window_strides,
+                                                            // padding (low and high of each dim)
+      // One result per input. The comparator compares the elements of the
+      // first input, like CompareOp
+      "SortOp"             = SortOp([Id; 5]), // inputs, dimension, is_stable,
+                                              // comparison_direction, comparison_type
+      // One result per loop-carried value. The condition and body are regions
+      // of the converter (see CppGraphConverter::begin_region), referred to
+      // by index
+      "WhileOp"            = WhileOp([Id; 3]), // inputs, cond, body
+      // One result per value the branches yield. The branches are regions
+      // whose block arguments are the inputs, so they use values of the
+      // enclosing graph explicitly
+      "IfOp"               = IfOp([Id; 4]), // pred, inputs, true_branch, false_branch
+      "CustomCallOp"       = CustomCallOp([Id; 4]), // inputs, custom_call (index into the
+                                                    // converter's custom calls), shape, dtype
+      // Results are the new state and the random bits. The bits are a
+      // function of the state, so generators of the same state may be merged
+      "RngBitGeneratorOp"  = RngBitGeneratorOp([Id; 4]), // initial_state, algorithm, shape, dtype
+      // Draws from a hidden seed. Each call site has its own index, so calls
+      // with the same operands are never merged
+      "RngOp"              = RngOp([Id; 5]), // a, b, shape, rng_distribution, site
+      // TUPLE OPS
+      // A tuple has one result per element, like a multi-result op, and
+      // GetTupleElementOp reads one of them like Index does
+      "TupleOp"            = TupleOp([Id; 1]), // inputs
+      "GetTupleElementOp"  = GetTupleElementOp([Id; 2]), // tuple, index
+      // Yields its inputs unchanged, read with Index. No rule sees through it,
+      // and extraction keeps the ops computing its inputs (see
+      // optimize::barrier_operands)
+      "OptimizationBarrierOp" = OptimizationBarrierOp([Id; 1]), // inputs
        "ReturnOp"            = ReturnOp([Id; 1]),
        "BlackBox"           = BlackBox(Box<[Id]>),
+       "CompositeOp"        = CompositeOp(Box<[Id]>), // inputs..., index into the composite op registry
        "Vec"                = Vec(Vec<Id>),
        "Index"              = Index([Id; 2]),
        Var(Symbol),
-       Num(i32),
+       Num(i64),
   }
 }
 
@@ -111,16 +218,192 @@ pub struct TensorData {
     pub n_dims: Vec<usize>,
     /// The name string of this eclass if it is a Name type
     pub name: Option<&'static str>,
+    /// Element type of the results, following the rules of `infer_dtypes`
+    pub dtype: ffi::Type,
+    /// Whether the tensor is known at compile time: weights, constants, and
+    /// ops whose operands are all known at compile time
+    pub constant: bool,
 }
 
-// Struct for storing information of a tensor. This is passed between functions
-// during graph creation.
+/// Element type of a ConvertOp output type (a Num child), by its ffi::Type
+/// discriminant. Unknown discriminants are taken as f32
+pub fn dtype_from_num(num: i64) -> ffi::Type {
+    match num {
+        0 => ffi::Type::i32,
+        2 => ffi::Type::i1,
+        3 => ffi::Type::bf16,
+        4 => ffi::Type::c64,
+        _ => ffi::Type::f32,
+    }
+}
+
+/// Num child giving `dtype` as the output type of a ConvertOp
+pub fn dtype_to_num(dtype: ffi::Type) -> i64 {
+    i64::from(dtype.repr)
+}
+
+/// Checks that `dtype`, the element type of the operands of `node`, is one
+/// `node` takes: bitwise ops take booleans or integers, shifts only integers.
+/// Other ops take any element type.
+pub fn check_operand_dtype(node: &Mdl, dtype: ffi::Type) -> Result<(), String> {
+    match node {
+        Mdl::AndOp(_) | Mdl::OrOp(_) | Mdl::XorOp(_) | Mdl::NotOp(_)
+            if dtype != ffi::Type::i1 && dtype != ffi::Type::i32 =>
+        {
+            Err("bitwise ops take booleans or integers".to_string())
+        }
+        Mdl::ShiftLeftOp(_) | Mdl::ShiftRightLogicalOp(_) | Mdl::ShiftRightArithmeticOp(_)
+            if dtype != ffi::Type::i32 =>
+        {
+            Err("shifts take integers".to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Name of the Var of an input: its name, its dims joined with _ and, unless it
+/// is f32, its element type, separated by @ (e.g. x@4_3 or x@4_3@bf16)
+pub fn input_var_name(name: &str, dims: &[i32], dtype: ffi::Type) -> String {
+    let dims = dims
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<_>>()
+        .join("_");
+    match dtype {
+        ffi::Type::i32 => format!("{}@{}@i32", name, dims),
+        ffi::Type::i1 => format!("{}@{}@i1", name, dims),
+        ffi::Type::bf16 => format!("{}@{}@bf16", name, dims),
+        ffi::Type::c64 => format!("{}@{}@c64", name, dims),
+        _ => format!("{}@{}", name, dims),
+    }
+}
+
+/// Element type of the input whose Var is named `name`, see input_var_name
+pub fn var_dtype(name: &str) -> ffi::Type {
+    match name.split('@').nth(2) {
+        Some("i32") => ffi::Type::i32,
+        Some("i1") => ffi::Type::i1,
+        Some("bf16") => ffi::Type::bf16,
+        Some("c64") => ffi::Type::c64,
+        _ => ffi::Type::f32,
+    }
+}
+
+/// Metadata of the results of a conditional, from the values each of its
+/// `branches` yields
+///
+/// Each result has the element type and rank of the values the branches yield
+/// in its place. Dimensions the branches disagree on are unknown (-1). Fails if
+/// the branches yield different numbers of values, or values of different
+/// ranks or element types.
+pub fn unify_branch_results(branches: &[&[TensorData]]) -> Result<TensorData, String> {
+    let (first, others) = branches
+        .split_first()
+        .ok_or_else(|| "no branches".to_string())?;
+    if let Some(branch) = others.iter().find(|b| b.len() != first.len()) {
+        return Err(format!(
+            "branches yield {} and {} values",
+            first.len(),
+            branch.len()
+        ));
+    }
+    let mut shapes = Vec::with_capacity(first.len());
+    let mut n_dims = Vec::with_capacity(first.len());
+    for (i, value) in first.iter().enumerate() {
+        let mut shape = value.shapes[0];
+        for other in others.iter().map(|branch| &branch[i]) {
+            if other.n_dims[0] != value.n_dims[0] || other.dtype != value.dtype {
+                return Err(format!(
+                    "branches disagree on the rank or type of value {}",
+                    i
+                ));
+            }
+            for d in 0..value.n_dims[0] {
+                if other.shapes[0][d] != shape[d] {
+                    shape[d] = -1;
+                }
+            }
+        }
+        shapes.push(shape);
+        n_dims.push(value.n_dims[0]);
+    }
+    Ok(TensorData {
+        shapes,
+        n_dims,
+        name: None,
+        dtype: first.first().map_or(ffi::Type::f32, |value| value.dtype),
+        constant: false,
+    })
+}
+
+/// Whether `node` draws random values. Extraction computes each of these
+/// once, so that all its consumers see the same draw.
+pub fn is_random(node: &Mdl) -> bool {
+    matches!(node, Mdl::RngBitGeneratorOp(_) | Mdl::RngOp(_))
+}
+
+/// Whether the Var `name` is the name of a weight (see NameGen), as opposed
+/// to an activation
+pub fn is_weight_var(name: &str) -> bool {
+    name.starts_with("w_")
+}
+
+// Struct for storing information of a tensor. The converter keeps one per
+// tensor built, and hands out an ffi::TensorHandle referring to it.
 #[derive(Clone)]
 pub struct TensorInfo {
     /// Id into the RecExpr constructed
     pub id: Id,
     pub tensor_data: TensorData,
 }
+
+impl TensorInfo {
+    /// Index of the tensor in the RecExpr, which is also its creation ordinal
+    pub fn tensor_id(&self) -> i32 {
+        usize::from(self.id) as i32
+    }
+}
+/// Values of a DenseConstantOp, row-major. They are widened to f64, which
+/// holds every value of the element types a DenseConstantOp takes exactly
+#[derive(Clone, Debug)]
+pub struct DenseConstant {
+    pub dtype: ffi::Type,
+    pub values: Arc<[f64]>,
+}
+
+impl DenseConstant {
+    /// Values of `dtype` from their raw (native-endian) bytes, as laid out in
+    /// memory on the C++ side
+    pub fn from_bytes(dtype: ffi::Type, data: &[u8]) -> Result<Self, String> {
+        let size = match dtype {
+            ffi::Type::i1 => 1,
+            ffi::Type::bf16 => 2,
+            ffi::Type::i32 | ffi::Type::f32 => 4,
+            _ => return Err(format!("unsupported element type {}", dtype.repr)),
+        };
+        if data.len() % size != 0 {
+            return Err(format!(
+                "{} bytes aren't a whole number of {}-byte values",
+                data.len(),
+                size
+            ));
+        }
+        let values = data
+            .chunks_exact(size)
+            .map(|bytes| match dtype {
+                ffi::Type::i1 => f64::from(u8::from(bytes[0] != 0)),
+                ffi::Type::bf16 => {
+                    let bits = u16::from_ne_bytes([bytes[0], bytes[1]]);
+                    f64::from(f32::from_bits(u32::from(bits) << 16))
+                }
+                ffi::Type::i32 => f64::from(i32::from_ne_bytes(bytes.try_into().unwrap())),
+                _ => f64::from(f32::from_ne_bytes(bytes.try_into().unwrap())),
+            })
+            .collect();
+        Ok(DenseConstant { dtype, values })
+    }
+}
+
 /// Struct for metadata analysis
 ///
 /// In this analysis, it calls functions on the TASO side (e.g. graph.matmul())
@@ -131,16 +414,117 @@ pub struct TensorAnalysis {
     pub blacklist_nodes: HashSet<Mdl>,
     /// Newly added nodes by order
     pub newly_added: Vec<Mdl>,
-    pub blackbox_cpp_num_to_shape: HashMap<i32, TensorInfo>,
+    pub blackbox_cpp_num_to_shape: HashMap<i64, TensorInfo>,
+    /// Registered composite ops, indexed by the last child of CompositeOp nodes
+    pub composite_ops: Vec<CompositeOpDef>,
+    /// Eclasses of the tensors annotated with a sharding, see sharding.rs
+    pub shardings: Vec<(Id, String)>,
+    /// Eclasses of the tensors introduced and named by rewrites, see naming.rs
+    pub names: Vec<(Id, String)>,
+    pub name_gen: NameGen,
+    /// Metadata of the values each region (see CppGraphConverter::begin_region)
+    /// yields, by index, for the results of IfOps
+    pub region_results: Vec<Vec<TensorData>>,
+    /// Values of the DenseConstantOps, by handle. The data is owned by the C++
+    /// side, these are copies for the rewrites depending on constant values
+    pub dense_constants: HashMap<i64, DenseConstant>,
+    /// Shapes inferred on the C++ side, by op and arguments
+    shape_cache: OpCache<Vec<ffi::Shape>>,
+    /// Total time spent in C++ shape inference
+    shape_time: Mutex<Duration>,
 }
 
+/// Arguments of a C++ shape inference or cost call: op, operand shapes,
+/// operand types, vector arguments and integer arguments
+pub type OpQuery = (
+    ffi::Ops,
+    Vec<ffi::Shape>,
+    Vec<ffi::Type>,
+    Vec<ffi::Shape>,
+    Vec<i64>,
+);
+
+/// Results of C++ calls by query. They only depend on the query, not on the
+/// e-graph, so a cache can be shared by many analyses (or cost models), see
+/// session::OptimizerSession
+pub type OpCache<T> = Arc<Mutex<HashMap<OpQuery, T>>>;
+
 impl<'a> TensorAnalysis {
-    pub fn new(blackbox_cpp_num_to_shape: &HashMap<i32, TensorInfo>) -> Self {
+    pub fn new(
+        blackbox_cpp_num_to_shape: &HashMap<i64, TensorInfo>,
+        composite_ops: &[CompositeOpDef],
+    ) -> Self {
         TensorAnalysis {
             blacklist_nodes: HashSet::<Mdl>::new(),
             newly_added: Vec::<Mdl>::new(),
             blackbox_cpp_num_to_shape: blackbox_cpp_num_to_shape.clone(),
+            composite_ops: composite_ops.to_vec(),
+            shardings: Vec::new(),
+            names: Vec::new(),
+            name_gen: NameGen::default(),
+            region_results: Vec::new(),
+            dense_constants: HashMap::new(),
+            shape_cache: OpCache::default(),
+            shape_time: Mutex::new(Duration::default()),
+        }
+    }
+
+    /// Use (and fill) `cache` for the shapes inferred on the C++ side
+    pub fn with_shape_cache(mut self, cache: OpCache<Vec<ffi::Shape>>) -> Self {
+        self.shape_cache = cache;
+        self
+    }
+
+    /// Use `region_results` as the values the regions yield
+    pub fn with_region_results(mut self, region_results: Vec<Vec<TensorData>>) -> Self {
+        self.region_results = region_results;
+        self
+    }
+
+    /// Use `dense_constants` as the values of the DenseConstantOps
+    pub fn with_dense_constants(mut self, dense_constants: HashMap<i64, DenseConstant>) -> Self {
+        self.dense_constants = dense_constants;
+        self
+    }
+
+    /// Values of the DenseConstantOp with the given handle, if registered
+    pub fn dense_values(&self, handle: i64) -> Option<&[f64]> {
+        self.dense_constants
+            .get(&handle)
+            .map(|constant| &constant.values[..])
+    }
+
+    /// Total time spent in C++ shape inference so far
+    pub fn shape_time(&self) -> Duration {
+        *self.shape_time.lock().unwrap()
+    }
+
+    /// Shapes of the results of an op, as inferred on the C++ side
+    ///
+    /// Many enodes added during saturation only reorder operands of the same
+    /// shapes (e.g. commutativity and associativity), so the inferred shapes are
+    /// memoized and C++ is only called once per distinct query.
+    pub fn infer_shape(
+        &self,
+        op: ffi::Ops,
+        args: Vec<ffi::Shape>,
+        arg_types: Vec<ffi::Type>,
+        other_vecs: Vec<ffi::Shape>,
+        int_args: Vec<i64>,
+    ) -> Vec<ffi::Shape> {
+        let query = (op, args, arg_types, other_vecs, int_args);
+        if let Some(shapes) = self.shape_cache.lock().unwrap().get(&query) {
+            return shapes.clone();
         }
+        let (op, args, arg_types, other_vecs, int_args) = query.clone();
+        let start_time = Instant::now();
+        let shapes = get_shape(op, args, arg_types, other_vecs, int_args);
+        *self.shape_time.lock().unwrap() += start_time.elapsed();
+        self.shape_cache
+            .lock()
+            .unwrap()
+            .insert(query, shapes.clone());
+        shapes
     }
 }
 
@@ -150,7 +534,10 @@ impl Analysis<Mdl> for TensorAnalysis {
     /// Merges two metadata when two eclasses are merged.
     fn merge(&self, to: &mut Self::Data, from: Self::Data) -> bool {
         assert!(to.shapes == from.shapes, "{:?}{:?}", to, from);
-        false
+        // An eclass is known at compile time if any of its enodes is
+        let changed = from.constant && !to.constant;
+        to.constant |= from.constant;
+        changed
     }
 
     fn make(egraph: &EGraph<Mdl, Self>, enode: &Mdl) -> Self::Data {
@@ -187,13 +574,15 @@ impl Analysis<Mdl> for TensorAnalysis {
         fn dim_from_name_string(name: &str) -> (Vec<[i32; MAX_DIM]>, Vec<usize>) {
             let name_vec: Vec<&str> = name.split("@").collect();
             assert!(
-                name_vec.len() == 2,
+                name_vec.len() == 2 || name_vec.len() == 3,
                 "name: {}, len: {}",
                 name,
                 name_vec.len()
             );
+            // Rank-0 inputs have nothing after the @
             let dims: Vec<i64> = name_vec[1]
                 .split("_")
+                .filter(|x| !x.is_empty())
                 .map(|x| x.parse::<i64>().unwrap())
                 .collect();
             shape_from_dim(vec![Shape { shape: dims }])
@@ -208,12 +597,6 @@ impl Analysis<Mdl> for TensorAnalysis {
             println!("{}", joined_numbers);
         }
 
-        fn map_to_i64(vec: Vec<i32>) -> ffi::Shape {
-            ffi::Shape {
-                shape: vec.into_iter().map(|x| x as i64).collect(),
-            }
-        }
-
         let get_num = |id| {
             for node in egraph[id].iter() {
                 match node {
@@ -230,14 +613,22 @@ impl Analysis<Mdl> for TensorAnalysis {
                 shapes: vec![[0; MAX_DIM]],
                 n_dims: vec![0],
                 name: Some(&"Num"),
+                dtype: ffi::Type::f32,
+                // A Vec of tensors (e.g. the inputs of a ConcatenateOp) is
+                // constant if they all are
+                constant: enode.children().iter().all(|child| x(child).constant),
             },
             Mdl::Var(name) => {
                 let (shapes, n_dims) = dim_from_name_string(name.as_str());
+                let dtype = var_dtype(name.as_str());
+                let constant = is_weight_var(name.as_str());
                 let name = Some(name.as_str());
                 TensorData {
                     shapes,
                     n_dims,
                     name,
+                    dtype,
+                    constant,
                 }
             }
             Mdl::Input([node, block_arg_number]) => x(node).clone(),
@@ -248,6 +639,46 @@ impl Analysis<Mdl> for TensorAnalysis {
                     shapes: vec![input.shapes[index as usize]],
                     n_dims: vec![input.n_dims[index as usize]],
                     name: None,
+                    dtype: input.dtype,
+                    constant: input.constant,
+                }
+            }
+            // Like Index, with the element type of the element when the tuple
+            // is built here
+            Mdl::GetTupleElementOp([tuple, index]) => {
+                let index = *get_num(*index) as usize;
+                let element = egraph[*tuple].iter().find_map(|node| match node {
+                    Mdl::TupleOp([inputs]) => get_vec(&egraph[*inputs]).get(index).copied(),
+                    _ => None,
+                });
+                let tuple = x(tuple);
+                TensorData {
+                    shapes: vec![tuple.shapes[index]],
+                    n_dims: vec![tuple.n_dims[index]],
+                    name: None,
+                    dtype: element.map_or(tuple.dtype, |id| x(&id).dtype),
+                    constant: tuple.constant,
+                }
+            }
+            Mdl::TupleOp([inputs]) => {
+                let inputs = get_vec(&egraph[*inputs]);
+                TensorData {
+                    shapes: inputs.iter().map(|id| x(id).shapes[0]).collect(),
+                    n_dims: inputs.iter().map(|id| x(id).n_dims[0]).collect(),
+                    name: None,
+                    dtype: inputs.first().map_or(ffi::Type::f32, |id| x(id).dtype),
+                    constant: inputs.iter().all(|id| x(id).constant),
+                }
+            }
+            // Never constant, so the rules folding weights stop at the barrier
+            Mdl::OptimizationBarrierOp([inputs]) => {
+                let inputs = get_vec(&egraph[*inputs]);
+                TensorData {
+                    shapes: inputs.iter().map(|id| x(id).shapes[0]).collect(),
+                    n_dims: inputs.iter().map(|id| x(id).n_dims[0]).collect(),
+                    name: None,
+                    dtype: inputs.first().map_or(ffi::Type::f32, |id| x(id).dtype),
+                    constant: false,
                 }
             }
             Mdl::BlackBox(inputs) => {
@@ -256,12 +687,13 @@ impl Analysis<Mdl> for TensorAnalysis {
                         .last()
                         .expect("Tried to call make() on a BlackBox without TensorData"),
                 );
-                let shape_vec = egraph.analysis.blackbox_cpp_num_to_shape[cpp_num]
-                    .tensor_data
+                let tensor_data = &egraph.analysis.blackbox_cpp_num_to_shape[cpp_num].tensor_data;
+                let shape_vec = tensor_data
                     .shapes
                     .iter()
-                    .map(|x| Shape {
-                        shape: x.iter().map(|x| *x as i64).collect(),
+                    .zip(tensor_data.n_dims.iter())
+                    .map(|(x, n_dims)| Shape {
+                        shape: x[..*n_dims].iter().map(|x| *x as i64).collect(),
                     })
                     .collect::<Vec<Shape>>();
                 let (shapes, n_dims) = shape_from_dim(shape_vec);
@@ -269,20 +701,169 @@ impl Analysis<Mdl> for TensorAnalysis {
                     shapes,
                     n_dims,
                     name: None,
+                    dtype: tensor_data.dtype,
+                    constant: tensor_data.constant,
+                }
+            }
+            Mdl::CompositeOp(inputs) => {
+                let (index, operands) = inputs
+                    .split_last()
+                    .expect("Tried to call make() on a CompositeOp without an index");
+                let def = &egraph.analysis.composite_ops[*get_num(*index) as usize];
+                let operand_data: Vec<&TensorData> = operands.iter().map(|id| x(id)).collect();
+                TensorData {
+                    constant: operand_data.iter().all(|data| data.constant),
+                    ..(def.shape_fn)(&operand_data)
+                }
+            }
+            Mdl::ConstantOp([_, shape]) => {
+                let dims = get_vec_of_nums(egraph, &egraph[*shape]);
+                let (shapes, n_dims) = shape_from_dim(vec![Shape { shape: dims }]);
+                TensorData {
+                    shapes,
+                    n_dims,
+                    name: None,
+                    dtype: ffi::Type::f32,
+                    constant: true,
+                }
+            }
+            Mdl::DenseConstantOp([_, shape, dtype]) => {
+                let dims = get_vec_of_nums(egraph, &egraph[*shape]);
+                let (shapes, n_dims) = shape_from_dim(vec![Shape { shape: dims }]);
+                TensorData {
+                    shapes,
+                    n_dims,
+                    name: None,
+                    dtype: dtype_from_num(*get_num(*dtype)),
+                    constant: true,
+                }
+            }
+            // Each input with the dimensions reduced over removed
+            Mdl::ReduceOp([inputs, init_values, dimensions, _]) => {
+                let inputs = get_vec(&egraph[*inputs]);
+                let init_values = get_vec(&egraph[*init_values]);
+                let dimensions = get_vec_of_nums(egraph, &egraph[*dimensions]);
+                let reduced = inputs
+                    .iter()
+                    .map(|id| Shape {
+                        shape: (0..x(id).n_dims[0])
+                            .filter(|d| !dimensions.contains(&(*d as i64)))
+                            .map(|d| x(id).shapes[0][d] as i64)
+                            .collect(),
+                    })
+                    .collect();
+                let (shapes, n_dims) = shape_from_dim(reduced);
+                TensorData {
+                    shapes,
+                    n_dims,
+                    name: None,
+                    dtype: inputs.first().map_or(ffi::Type::f32, |id| x(id).dtype),
+                    constant: inputs.iter().chain(init_values).all(|id| x(id).constant),
+                }
+            }
+            // The results of a loop have the shapes of its loop-carried values.
+            // Loops are never folded, even over weights
+            Mdl::WhileOp([inputs, _, _]) => {
+                let inputs = get_vec(&egraph[*inputs]);
+                TensorData {
+                    shapes: inputs.iter().map(|id| x(id).shapes[0]).collect(),
+                    n_dims: inputs.iter().map(|id| x(id).n_dims[0]).collect(),
+                    name: None,
+                    dtype: inputs.first().map_or(ffi::Type::f32, |id| x(id).dtype),
+                    constant: false,
+                }
+            }
+            Mdl::IfOp([_, _, true_branch, false_branch]) => {
+                let branches: Vec<&[TensorData]> = [true_branch, false_branch]
+                    .iter()
+                    .map(|branch| {
+                        let region = *get_num(**branch) as usize;
+                        egraph.analysis.region_results[region].as_slice()
+                    })
+                    .collect();
+                match unify_branch_results(&branches) {
+                    Ok(data) => data,
+                    Err(e) => panic!("Invalid IfOp: {}", e),
+                }
+            }
+            // Custom calls are opaque, they are never folded
+            Mdl::CustomCallOp([_, _, shape, dtype]) => {
+                let dims = get_vec_of_nums(egraph, &egraph[*shape]);
+                let (shapes, n_dims) = shape_from_dim(vec![Shape { shape: dims }]);
+                TensorData {
+                    shapes,
+                    n_dims,
+                    name: None,
+                    dtype: dtype_from_num(*get_num(*dtype)),
+                    constant: false,
+                }
+            }
+            // Random values are never folded
+            Mdl::RngBitGeneratorOp([initial_state, _, shape, dtype]) => {
+                let dims = get_vec_of_nums(egraph, &egraph[*shape]);
+                let state = x(initial_state);
+                let (mut shapes, mut n_dims) = shape_from_dim(vec![Shape { shape: dims }]);
+                shapes.insert(0, state.shapes[0]);
+                n_dims.insert(0, state.n_dims[0]);
+                TensorData {
+                    shapes,
+                    n_dims,
+                    name: None,
+                    dtype: dtype_from_num(*get_num(*dtype)),
+                    constant: false,
+                }
+            }
+            Mdl::RngOp([a, _, shape, _, _]) => {
+                let dims = get_vec_of_nums(egraph, &egraph[*shape]);
+                let (shapes, n_dims) = shape_from_dim(vec![Shape { shape: dims }]);
+                TensorData {
+                    shapes,
+                    n_dims,
+                    name: None,
+                    dtype: x(a).dtype,
+                    constant: false,
                 }
             }
             Mdl::ReturnOp(_) => TensorData {
                 shapes: vec![],
                 n_dims: vec![],
                 name: None,
+                dtype: ffi::Type::f32,
+                constant: false,
             },
-            x => {
-                let shape = create_stablehlo_op(egraph, x, ffi::get_shape);
+            node => {
+                let shape = create_stablehlo_op(egraph, node, |op, args, types, vecs, ints| {
+                    egraph.analysis.infer_shape(op, args, types, vecs, ints)
+                });
                 let (shapes, n_dims) = shape_from_dim(shape);
+                let dtype = match node {
+                    Mdl::CompareOp(_) => ffi::Type::i1,
+                    Mdl::ComplexOp(_) => ffi::Type::c64,
+                    Mdl::RealOp(_) | Mdl::ImagOp(_) => ffi::Type::f32,
+                    Mdl::FftOp([_, fft_type, _]) => match FftType::from_num(*get_num(*fft_type)) {
+                        Ok(fft_type) => fft_type.result_dtype(),
+                        Err(e) => panic!("Invalid FftOp: {}", e),
+                    },
+                    Mdl::ConvertOp([_, output_type]) => dtype_from_num(*get_num(*output_type)),
+                    Mdl::SelectOp([_, on_true, _]) => x(on_true).dtype,
+                    Mdl::ConcatenateOp([inputs, _]) => get_vec(&egraph[*inputs])
+                        .first()
+                        .map_or(ffi::Type::f32, |input| x(input).dtype),
+                    Mdl::IotaOp(_) => ffi::Type::f32,
+                    node => x(&node.children()[0]).dtype,
+                };
+                if let Err(e) = check_operand_dtype(node, dtype) {
+                    panic!("Invalid {}: {}", node, e);
+                }
+                // Num and Vec children are constant, so this only looks at the
+                // tensor operands (IotaOp has none)
+                let constant = node.children().iter().all(|child| x(child).constant);
                 TensorData {
                     shapes,
                     n_dims,
                     name: None,
+                    dtype,
+                    constant,
                 }
             }
         }
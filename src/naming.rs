@@ -0,0 +1,141 @@
+//! Names of tensors introduced by rewrites
+//!
+//! Rewrites that materialize a new intermediate tensor (e.g. a transposed or
+//! scaled copy of a weight, or an op of a composite expansion) name it with
+//! the converter's `NameGen`, after the tensor it is derived from:
+//! `w_0.transposed_0`, `gelu.tanh_1`, ... The names are recorded in
+//! `TensorAnalysis::names` by eclass, and are carried into the labels of the
+//! nodes of the optimized graph and the e-graph snapshots (`named_dot`).
+//!
+//! Names are numbered in the order the rewrites are applied, so saturating the
+//! same graph with the same rules gives the same names.
+
+use crate::model::*;
+use crate::provenance::canonical_nodes;
+use crate::rewrites::finish_apply;
+use egg::*;
+
+/// Name given to `class` by a rewrite, if any
+pub fn name_of(egraph: &EGraph<Mdl, TensorAnalysis>, class: Id) -> Option<&str> {
+    let class = egraph.find(class);
+    egraph
+        .analysis
+        .names
+        .iter()
+        .find(|(id, _)| egraph.find(*id) == class)
+        .map(|(_, name)| name.as_str())
+}
+
+/// Readable name of the tensor computed by `class`: the name given by a
+/// rewrite, or the name of the input it holds (without shape and type)
+pub fn tensor_name(egraph: &EGraph<Mdl, TensorAnalysis>, class: Id) -> Option<&str> {
+    name_of(egraph, class).or_else(|| {
+        egraph[class].iter().find_map(|node| match node {
+            Mdl::Input([name, _]) => egraph[*name]
+                .data
+                .name
+                .map(|name| name.split('@').next().unwrap()),
+            _ => None,
+        })
+    })
+}
+
+/// Base name of a tensor derived from `class` by `what`, e.g. `w_0.transposed`
+pub fn derived_name(egraph: &EGraph<Mdl, TensorAnalysis>, class: Id, what: &str) -> String {
+    match tensor_name(egraph, class) {
+        Some(name) => format!("{}.{}", name, what),
+        None => what.to_string(),
+    }
+}
+
+/// Adds `enode`, naming its eclass after `base` if it is a new eclass. Existing
+/// eclasses keep their name (or lack of one)
+pub fn add_named(egraph: &mut EGraph<Mdl, TensorAnalysis>, enode: Mdl, base: &str) -> Id {
+    let num_classes = egraph.number_of_classes();
+    let id = egraph.add(enode);
+    if egraph.number_of_classes() > num_classes {
+        let name = egraph.analysis.name_gen.new_tensor_name(base);
+        egraph.analysis.names.push((id, name));
+    }
+    id
+}
+
+/// Name of each node of the extracted graph, None for nodes of unnamed eclasses
+pub fn node_names(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    extracted: &RecExpr<Mdl>,
+) -> Vec<Option<String>> {
+    canonical_nodes(egraph, extracted)
+        .into_iter()
+        .map(|node| {
+            node.and_then(|(_, class)| name_of(egraph, class))
+                .map(str::to_string)
+        })
+        .collect()
+}
+
+/// Graphviz source of the e-graph, with named eclasses labelled by their name
+pub fn named_dot(egraph: &EGraph<Mdl, TensorAnalysis>) -> String {
+    let mut dot = egraph.dot().to_string();
+    for (id, name) in egraph.analysis.names.iter() {
+        let cluster = format!("subgraph cluster_{} {{\n", egraph.find(*id));
+        let labelled = format!("{}    label = \"{}\"\n", cluster, name);
+        dot = dot.replacen(&cluster, &labelled, 1);
+    }
+    dot
+}
+
+/// Lowercase name of an op, without the Op suffix, e.g. `tanh` for TanhOp
+fn op_name(enode: &Mdl) -> String {
+    enode
+        .display_op()
+        .to_string()
+        .trim_end_matches("Op")
+        .to_lowercase()
+}
+
+/// Expansion of a composite op, naming the intermediate tensors after the
+/// composite op and their op, e.g. `gelu.tanh_0`
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedExpansion {
+    pub expansion: Pattern<Mdl>,
+    /// Name of the composite op
+    pub name: String,
+}
+
+impl Applier<Mdl, TensorAnalysis> for NamedExpansion {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let pat = self.expansion.ast.as_ref();
+        let mut ids: Vec<Id> = Vec::with_capacity(pat.len());
+        for (i, node) in pat.iter().enumerate() {
+            let id = match node {
+                ENodeOrVar::Var(var) => subst[*var],
+                ENodeOrVar::ENode(node) => {
+                    let node = node.clone().map_children(|c| ids[usize::from(c)]);
+                    let inner = i + 1 < pat.len();
+                    let is_tensor = !matches!(
+                        node,
+                        Mdl::Num(_) | Mdl::Vec(_) | Mdl::Var(_) | Mdl::Input(_)
+                    );
+                    if inner && is_tensor {
+                        let base = format!("{}.{}", self.name, op_name(&node));
+                        add_named(egraph, node, &base)
+                    } else {
+                        egraph.add(node)
+                    }
+                }
+            };
+            ids.push(id);
+        }
+        finish_apply(egraph, matched_id, *ids.last().unwrap())
+    }
+
+    fn vars(&self) -> Vec<Var> {
+        self.expansion.vars()
+    }
+}
@@ -1,11 +1,40 @@
-use crate::{ffi_utils::*, input::ffi, model::*, rewrites::*};
+use crate::{
+    ffi_utils::*, input::ffi, layout::layout_factor, model::*, provenance::canonical_nodes,
+    rewrites::*, sharding::resharding_bytes, weights::is_folded,
+};
 use egg::*;
 // use cxx::UniquePtr;
+use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::fmt;
+use std::sync::RwLock;
 use std::time::{Duration, Instant};
 
+/// Bound of costs and sums of costs, which saturate here rather than
+/// overflowing to infinity: infinity stands for blacklisted enodes, and
+/// serializes as null in the ILP data
+pub const MAX_COST: f64 = f64::MAX;
+
+/// Cost of an op saturated at MAX_COST. An op can't be infinitely costly (or
+/// cost NaN), only blacklisted
+pub fn saturate_cost(cost: f64) -> f64 {
+    if cost.is_nan() {
+        MAX_COST
+    } else {
+        cost.min(MAX_COST)
+    }
+}
+
+/// Sum of two costs, saturating at MAX_COST unless either is infinite
+pub fn add_costs(a: f64, b: f64) -> f64 {
+    if a == f64::INFINITY || b == f64::INFINITY {
+        return f64::INFINITY;
+    }
+    (a + b).min(MAX_COST)
+}
+
 /// Wrapper class for egg's cost function
 pub struct TensorCost<'a> {
     pub egraph: &'a EGraph<Mdl, TensorAnalysis>,
@@ -13,21 +42,112 @@ pub struct TensorCost<'a> {
 }
 
 impl CostFunction<Mdl> for TensorCost<'_> {
-    type Cost = f32;
+    type Cost = f64;
     /// Getting total cost for the subtree rooted at enode. See egg::CostFunction
     /// trait for more information on interface.
+    ///
+    /// Blacklisted enodes cost infinity, so they are only extracted from eclasses
+    /// without any other choice.
     fn cost<C: FnMut(Id) -> Self::Cost>(&mut self, enode: &Mdl, mut costs: C) -> Self::Cost {
+        if self.egraph.analysis.blacklist_nodes.contains(enode) {
+            return f64::INFINITY;
+        }
         let self_cost = self.cost_model.get_self_cost(self.egraph, enode);
-        enode.fold(self_cost, |sum, id| sum + costs(id))
+        enode.fold(self_cost, |sum, id| add_costs(sum, costs(id)))
     }
 }
 
 /// Class for our cost model
-pub struct CostModel {}
+///
+/// Self costs are memoized by the canonical form of the enode, so each unique
+/// enode is priced once however many extractors ask for it. The canonical form
+/// refers to eclasses, so the memo is only valid for a single e-graph: use a new
+/// CostModel (or `clear_memo`) when switching to another one.
+#[derive(Default)]
+pub struct CostModel {
+    memo: RwLock<HashMap<Mdl, f64>>,
+    /// Charge DotGeneralOps for operands not in the canonical layout, see
+    /// layout::layout_factor
+    layout_aware: bool,
+    /// Cost per byte of operands resharded by an op, see
+    /// sharding::resharding_bytes
+    resharding_cost: Option<f32>,
+    /// Costs measured on the C++ side, by op and arguments. Unlike the memo,
+    /// these hold for any e-graph
+    measured: OpCache<f64>,
+    /// Charge 1 per op instead, see `op_count`
+    count_ops: bool,
+}
 
 impl CostModel {
     pub fn new() -> Self {
-        Self {}
+        Self::default()
+    }
+
+    /// Cost model that also prices the layout of DotGeneralOp operands
+    pub fn layout_aware() -> Self {
+        CostModel {
+            layout_aware: true,
+            ..Self::default()
+        }
+    }
+
+    /// Cost model charging 1 per op (0 for names, numbers and vectors), to
+    /// extract the graph with the fewest ops rather than the fastest one
+    pub fn op_count() -> Self {
+        CostModel {
+            count_ops: true,
+            ..Self::default()
+        }
+    }
+
+    /// Also charge `cost` per byte of operands an op has to reshard
+    pub fn with_resharding_cost(mut self, cost: Option<f32>) -> Self {
+        self.resharding_cost = cost;
+        self
+    }
+
+    /// Use (and fill) `cache` for the costs measured on the C++ side
+    pub fn with_measured_costs(mut self, cache: OpCache<f64>) -> Self {
+        self.measured = cache;
+        self
+    }
+
+    /// Forget the memoized costs
+    pub fn clear_memo(&self) {
+        self.memo.write().unwrap().clear();
+    }
+
+    /// Cost of the op measured on the C++ side, if it was measured already
+    fn cached_measured_cost(
+        &self,
+        op: ffi::Ops,
+        args: Vec<ffi::Shape>,
+        arg_types: Vec<ffi::Type>,
+        other_vecs: Vec<ffi::Shape>,
+        int_args: Vec<i64>,
+    ) -> Option<f64> {
+        let query = (op, args, arg_types, other_vecs, int_args);
+        self.measured.lock().unwrap().get(&query).copied()
+    }
+
+    /// Cost of the op measured on the C++ side, asking it only once per query
+    fn measured_cost(
+        &self,
+        op: ffi::Ops,
+        args: Vec<ffi::Shape>,
+        arg_types: Vec<ffi::Type>,
+        other_vecs: Vec<ffi::Shape>,
+        int_args: Vec<i64>,
+    ) -> f64 {
+        let query = (op, args, arg_types, other_vecs, int_args);
+        if let Some(cost) = self.measured.lock().unwrap().get(&query) {
+            return *cost;
+        }
+        let (op, args, arg_types, other_vecs, int_args) = query.clone();
+        let cost = get_cost(op, args, arg_types, other_vecs, int_args) as f64;
+        self.measured.lock().unwrap().insert(query, cost);
+        cost
     }
 
     pub fn tensor_data_to_shape_vec(&self, tensor_data: &TensorData) -> ffi::Shape {
@@ -40,7 +160,67 @@ impl CostModel {
         }
     }
 
-    /// Gets cost for the enode itself.
+    /// Gets cost for the enode itself, memoized.
+    ///
+    /// # Parameters
+    ///
+    /// - `egraph`: E-graph of interest
+    /// - `enode`: enode to get cost for
+    ///
+    /// # Returns
+    ///
+    /// Cost for this enode, saturated at MAX_COST.
+    pub fn get_self_cost(&self, egraph: &EGraph<Mdl, TensorAnalysis>, enode: &Mdl) -> f64 {
+        self.self_cost_with(egraph, enode, &|op, args, types, vecs, ints| {
+            Some(self.measured_cost(op, args, types, vecs, ints))
+        })
+        .unwrap()
+    }
+
+    /// Gets cost for the enode itself like `get_self_cost`, but only if that
+    /// doesn't call the C++ side: the cost is memoized, or needs no
+    /// measurement, or its measurement is cached. Unlike `get_self_cost`, this
+    /// can be called from several threads.
+    pub fn cached_self_cost(
+        &self,
+        egraph: &EGraph<Mdl, TensorAnalysis>,
+        enode: &Mdl,
+    ) -> Option<f64> {
+        self.self_cost_with(egraph, enode, &|op, args, types, vecs, ints| {
+            self.cached_measured_cost(op, args, types, vecs, ints)
+        })
+    }
+
+    /// Memoized cost of the enode, with `measure` giving the cost measured on
+    /// the C++ side of an op, or None if it can't
+    fn self_cost_with<F>(
+        &self,
+        egraph: &EGraph<Mdl, TensorAnalysis>,
+        enode: &Mdl,
+        measure: &F,
+    ) -> Option<f64>
+    where
+        F: Fn(ffi::Ops, Vec<ffi::Shape>, Vec<ffi::Type>, Vec<ffi::Shape>, Vec<i64>) -> Option<f64>,
+    {
+        let key = enode.clone().map_children(|id| egraph.find(id));
+        if let Some(cost) = self.memo.read().unwrap().get(&key) {
+            return Some(*cost);
+        }
+        // Ops on weights alone are folded at compile time
+        if is_folded(egraph, enode) {
+            self.memo.write().unwrap().insert(key, 0.0);
+            return Some(0.0);
+        }
+        let mut cost = saturate_cost(self.compute_self_cost(egraph, enode, measure)?);
+        if let Some(resharding_cost) = self.resharding_cost {
+            let resharding = resharding_cost as f64 * resharding_bytes(egraph, enode);
+            cost = add_costs(cost, saturate_cost(resharding));
+        }
+        self.memo.write().unwrap().insert(key, cost);
+        Some(cost)
+    }
+
+    /// Computes the cost for the enode itself, without memoization.
     ///
     /// This function gets the cost by calling TASO's get_or_create_{some_op}()
     /// functions with the tensor information stored in metadata. TASO side stores
@@ -54,8 +234,20 @@ impl CostModel {
     ///
     /// # Returns
     ///
-    /// Cost for this enode.
-    pub fn get_self_cost(&self, egraph: &EGraph<Mdl, TensorAnalysis>, enode: &Mdl) -> f32 {
+    /// Cost for this enode, or None if `measure` can't give the cost of its op.
+    fn compute_self_cost<F>(
+        &self,
+        egraph: &EGraph<Mdl, TensorAnalysis>,
+        enode: &Mdl,
+        measure: &F,
+    ) -> Option<f64>
+    where
+        F: Fn(ffi::Ops, Vec<ffi::Shape>, Vec<ffi::Type>, Vec<ffi::Shape>, Vec<i64>) -> Option<f64>,
+    {
+        if self.count_ops {
+            let attribute = matches!(enode, Mdl::Num(_) | Mdl::Var(_) | Mdl::Vec(_));
+            return Some(if attribute { 0.0 } else { 1.0 });
+        }
         let x = |i: &Id| &egraph[*i].data;
 
         fn dim_to_i64_vec(input: &[i32; MAX_DIM]) -> ffi::Shape {
@@ -96,9 +288,10 @@ impl CostModel {
 
         let dim_from_name_string = |name: &str| {
             let name_vec: Vec<&str> = name.split("@").collect();
-            assert!(name_vec.len() == 2);
+            assert!(name_vec.len() == 2 || name_vec.len() == 3);
             let dims: Vec<i32> = name_vec[1]
                 .split("_")
+                .filter(|x| !x.is_empty())
                 .map(|x| x.parse::<i32>().unwrap())
                 .collect();
             shape_from_dim(dims)
@@ -111,12 +304,73 @@ impl CostModel {
             | Mdl::Vec(_)
             | Mdl::BlackBox(_)
             | Mdl::Index(_)
-            | Mdl::ReturnOp(_) => 0.0,
-            x => create_stablehlo_op(egraph, x, ffi::get_cost) as f32,
+            | Mdl::TupleOp(_)
+            | Mdl::GetTupleElementOp(_)
+            | Mdl::OptimizationBarrierOp(_)
+            | Mdl::ConstantOp(_)
+            | Mdl::DenseConstantOp(_)
+            | Mdl::WhileOp(_)
+            | Mdl::IfOp(_)
+            | Mdl::CustomCallOp(_)
+            | Mdl::RngBitGeneratorOp(_)
+            | Mdl::RngOp(_)
+            | Mdl::ReturnOp(_) => Some(0.0),
+            Mdl::CompositeOp(inputs) => {
+                let index = get_num(&egraph[*inputs.last().unwrap()]);
+                Some(egraph.analysis.composite_ops[*index as usize].cost as f64)
+            }
+            x if self.layout_aware => {
+                create_stablehlo_op(egraph, x, measure).map(|cost| cost * layout_factor(egraph, x))
+            }
+            x => create_stablehlo_op(egraph, x, measure),
         }
     }
 }
 
+/// Estimated cost of a graph: the sum of the self costs of its nodes, counting
+/// shared nodes once
+///
+/// # Parameters
+///
+/// - `expr`: graph to cost
+/// - `analysis`: analysis used to infer the shapes the costs depend on
+/// - `cost_model`: cost model to use. Its memo is bypassed, as the graph is
+///   costed in an e-graph of its own
+pub fn graph_cost(expr: &RecExpr<Mdl>, analysis: TensorAnalysis, cost_model: &CostModel) -> f64 {
+    let mut egraph = EGraph::<Mdl, TensorAnalysis>::new(analysis);
+    let mut class_ids: Vec<Id> = Vec::with_capacity(expr.as_ref().len());
+    let mut nodes = HashSet::new();
+    for node in expr.as_ref() {
+        let node = node.clone().map_children(|c| class_ids[usize::from(c)]);
+        class_ids.push(egraph.add(node.clone()));
+        nodes.insert(node);
+    }
+    nodes
+        .iter()
+        .map(|node| saturate_cost(cost_model.compute_self_cost(&egraph, node)))
+        .fold(0.0, add_costs)
+}
+
+/// Bytes of the results of `node`, assuming 4-byte elements. Zero for nodes
+/// that aren't tensors (e.g. Num and Vec)
+pub fn output_bytes(egraph: &EGraph<Mdl, TensorAnalysis>, class: Id, node: &Mdl) -> f64 {
+    if matches!(node, Mdl::Num(_) | Mdl::Vec(_) | Mdl::Var(_)) {
+        return 0.0;
+    }
+    let data = &egraph[class].data;
+    data.shapes
+        .iter()
+        .zip(data.n_dims.iter())
+        .map(|(shape, n_dims)| {
+            shape[..*n_dims]
+                .iter()
+                .map(|d| (*d).max(1) as f64)
+                .product::<f64>()
+                * 4.0
+        })
+        .sum()
+}
+
 /// Prepare the data for formulation ILP
 ///
 /// # Returns
@@ -127,20 +381,20 @@ impl CostModel {
 /// - `cost_i`: self cost for each node i
 /// - `g_i`: which EClass index does node i belong to
 /// - `root_m`: EClass index of the root eclass
-/// - `i_to_nodes: Vector of enodes, ordered by index i
+/// - `i_to_nodes: Vector of references to the enodes in the e-graph, ordered by index i
 /// - `blacklist_i: Vector of indices of nodes that are blacklisted
-pub fn prep_ilp_data(
-    egraph: &EGraph<Mdl, TensorAnalysis>,
+pub fn prep_ilp_data<'a>(
+    egraph: &'a EGraph<Mdl, TensorAnalysis>,
     root: Id,
     cost_model: &CostModel,
 ) -> (
     Vec<Id>,
     Vec<Vec<usize>>,
     Vec<Vec<usize>>,
-    Vec<f32>,
+    Vec<f64>,
     Vec<usize>,
     usize,
-    Vec<Mdl>,
+    Vec<&'a Mdl>,
     Vec<usize>,
 ) {
     let m_id_map: Vec<Id> = egraph.classes().map(|c| egraph.find(c.id)).collect();
@@ -153,10 +407,8 @@ pub fn prep_ilp_data(
 
     let num_classes = egraph.number_of_classes();
     let num_nodes = egraph.total_size();
-    let mut i_to_nodes: Vec<Mdl> = Vec::with_capacity(num_nodes);
+    let mut i_to_nodes: Vec<&Mdl> = Vec::with_capacity(num_nodes);
     let mut e_m: Vec<Vec<usize>> = vec![Vec::new(); num_classes];
-    let mut h_i: Vec<Vec<usize>> = Vec::with_capacity(num_nodes);
-    let mut cost_i: Vec<f32> = Vec::with_capacity(num_nodes);
     let mut g_i: Vec<usize> = Vec::with_capacity(num_nodes);
     let mut blacklist_i: Vec<usize> = Vec::new();
 
@@ -164,23 +416,37 @@ pub fn prep_ilp_data(
     for class in egraph.classes() {
         let m = *id_m_map.get(&egraph.find(class.id)).unwrap();
         for node in class.iter() {
-            i_to_nodes.push(node.clone());
+            i_to_nodes.push(node);
             if egraph.analysis.blacklist_nodes.contains(node) {
                 blacklist_i.push(i);
             }
             e_m[m].push(i);
-            h_i.push(
-                node.children()
-                    .iter()
-                    .map(|id| *id_m_map.get(&egraph.find(*id)).unwrap())
-                    .collect(),
-            );
-            cost_i.push(cost_model.get_self_cost(egraph, node));
             g_i.push(m);
             i += 1;
         }
     }
 
+    // Costing every node dominates on saturated e-graphs, so children and the
+    // costs known on the Rust side are computed in parallel. The C++ cost model
+    // isn't thread safe, so the costs it measures are computed serially after.
+    let h_i: Vec<Vec<usize>> = i_to_nodes
+        .par_iter()
+        .map(|node| {
+            node.children()
+                .iter()
+                .map(|id| *id_m_map.get(&egraph.find(*id)).unwrap())
+                .collect()
+        })
+        .collect();
+    let cost_i: Vec<f64> = i_to_nodes
+        .par_iter()
+        .map(|node| cost_model.cached_self_cost(egraph, node))
+        .collect::<Vec<Option<f64>>>()
+        .into_iter()
+        .zip(i_to_nodes.iter())
+        .map(|(cost, node)| cost.unwrap_or_else(|| cost_model.get_self_cost(egraph, node)))
+        .collect();
+
     let root_m = *id_m_map.get(&egraph.find(root)).unwrap();
 
     (
@@ -201,14 +467,199 @@ pub struct SolvedResults {
     /// The solved values for the variables associated with each node
     pub solved_x: Vec<i32>,
     /// The minimum total cost found
-    pub cost: f32,
+    pub cost: f64,
     /// Time for solver
     pub time: f32,
+    /// (i, m, j) for each node i taking its child eclass m from node j. Only
+    /// written by the rematerialization ILP, see `construct_remat_rec`
+    #[serde(default)]
+    pub solved_z: Vec<(usize, usize, usize)>,
+}
+
+/// Reason extractor/extract.py failed to solve the extraction ILP, from the
+/// error it writes instead of the solved results
+#[derive(Debug, Clone, PartialEq)]
+pub enum IlpError {
+    /// The solver isn't available in the installed OR-Tools
+    SolverUnavailable(String),
+    /// The ILP has no solution, e.g. every node of the root is blacklisted
+    Infeasible,
+    /// The solver found no solution within the time limit
+    NoSolution(String),
+    /// The script failed otherwise (bad data, crash, ...)
+    Failed(String),
+}
+
+impl fmt::Display for IlpError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IlpError::SolverUnavailable(message) => write!(f, "Solver unavailable: {}", message),
+            IlpError::Infeasible => write!(f, "The extraction ILP is infeasible"),
+            IlpError::NoSolution(message) => write!(f, "No solution found: {}", message),
+            IlpError::Failed(message) => write!(f, "ILP script failed: {}", message),
+        }
+    }
+}
+
+/// Parse ./tmp/solved.json as written by extractor/extract.py: the solved
+/// results, or an error code and message
+pub fn parse_solved(text: &str) -> Result<SolvedResults, IlpError> {
+    let value: serde_json::Value = serde_json::from_str(text)
+        .map_err(|e| IlpError::Failed(format!("Invalid solved results: {}", e)))?;
+    let message = value["message"].as_str().unwrap_or_default().to_string();
+    match value["error"].as_str() {
+        Some("solver_unavailable") => Err(IlpError::SolverUnavailable(message)),
+        Some("infeasible") => Err(IlpError::Infeasible),
+        Some("no_solution") => Err(IlpError::NoSolution(message)),
+        Some(code) => Err(IlpError::Failed(format!("{} ({})", message, code))),
+        None => serde_json::from_value(value)
+            .map_err(|e| IlpError::Failed(format!("Invalid solved results: {}", e))),
+    }
+}
+
+/// Blacklist the enodes in the eclasses of the protected nodes of `expr` other
+/// than the protected nodes themselves, so extraction keeps their ops (their
+/// operands can still be rewritten)
+///
+/// Protected nodes that are not in the e-graph (e.g. changed when the input
+/// graph was canonicalized) or already blacklisted (e.g. closing a cycle) are
+/// reported and left unprotected.
+///
+/// # Returns
+///
+/// The number of enodes blacklisted.
+pub fn protect_nodes(
+    egraph: &mut EGraph<Mdl, TensorAnalysis>,
+    expr: &RecExpr<Mdl>,
+    protected: &HashSet<Id>,
+) -> usize {
+    let nodes = canonical_nodes(egraph, expr);
+    let mut kept: HashMap<Id, Vec<Mdl>> = HashMap::new();
+    for id in protected {
+        match &nodes[usize::from(*id)] {
+            Some((node, class)) if !egraph.analysis.blacklist_nodes.contains(node) => {
+                kept.entry(*class).or_default().push(node.clone())
+            }
+            _ => println!(
+                "WARNING: not protecting node {}, which is not in the e-graph or blacklisted",
+                id
+            ),
+        }
+    }
+    let mut num_blacklisted = 0;
+    for (class, kept) in kept {
+        let others: Vec<Mdl> = egraph[class]
+            .iter()
+            .filter(|node| !kept.contains(*node))
+            .cloned()
+            .collect();
+        for node in others {
+            num_blacklisted += egraph.analysis.blacklist_nodes.insert(node) as usize;
+        }
+    }
+    num_blacklisted
+}
+
+/// The operands of the OptimizationBarrierOps of `expr`, which optimize
+/// protects like the nodes of set_no_rewrite
+pub fn barrier_operands(expr: &RecExpr<Mdl>) -> HashSet<Id> {
+    let nodes = expr.as_ref();
+    nodes
+        .iter()
+        .filter_map(|node| match node {
+            Mdl::OptimizationBarrierOp([inputs]) => match &nodes[usize::from(*inputs)] {
+                Mdl::Vec(ids) => Some(ids.clone()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .flatten()
+        .collect()
+}
+
+/// Reject an ILP solution picking a blacklisted node, see `picked_nodes`
+pub fn check_blacklist(
+    solved_x: &[i32],
+    m_id_map: &[Id],
+    g_i: &[usize],
+    i_to_nodes: &[&Mdl],
+    blacklist_i: &[usize],
+) -> Result<(), String> {
+    match blacklist_i.iter().find(|i| solved_x[**i] == 1) {
+        Some(i) => Err(format!(
+            "ILP solution picks blacklisted node {} ({}) in eclass {}",
+            i,
+            i_to_nodes[*i].display_op(),
+            m_id_map[g_i[*i]]
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Nodes picked by an ILP solution, by eclass
+///
+/// The solver is trusted to pick one node per eclass, but not to honor the
+/// blacklist: a solution picking a blacklisted node is rejected, as the
+/// blacklisted nodes are the ones closing cycles in the e-graph.
+///
+/// # Parameters
+///
+/// - `solved_x`: solved value of the variable of each node i
+/// - `m_id_map`, `g_i`, `i_to_nodes`, `blacklist_i`: as returned by `prep_ilp_data`
+pub fn picked_nodes(
+    solved_x: &[i32],
+    m_id_map: &[Id],
+    g_i: &[usize],
+    i_to_nodes: &[&Mdl],
+    blacklist_i: &[usize],
+) -> Result<HashMap<Id, Mdl>, String> {
+    check_blacklist(solved_x, m_id_map, g_i, i_to_nodes, blacklist_i)?;
+    let mut node_picked: HashMap<Id, Mdl> = HashMap::new();
+    for (i, x_i) in solved_x.iter().enumerate() {
+        if *x_i == 1 {
+            let eclass_id = m_id_map[g_i[i]];
+            if node_picked.contains_key(&eclass_id) {
+                println!("Duplicate node in eclass");
+                println!("{}", node_picked.get(&eclass_id).unwrap().display_op());
+                println!("{}", i_to_nodes[i].display_op());
+                continue;
+            }
+            node_picked.insert(eclass_id, i_to_nodes[i].clone());
+        }
+    }
+    Ok(node_picked)
+}
+
+/// Reason the extracted graph can't be constructed
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReconstructError {
+    /// The picked nodes form a cycle through these eclasses, in order
+    Cycle(Vec<Id>),
+    /// No node is picked for an eclass the graph needs
+    NothingPicked(Id),
+}
+
+impl fmt::Display for ReconstructError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReconstructError::Cycle(classes) => write!(
+                f,
+                "Picked nodes form a cycle through eclasses {}",
+                classes
+                    .iter()
+                    .map(|id| id.to_string())
+                    .collect::<Vec<_>>()
+                    .join(" -> ")
+            ),
+            ReconstructError::NothingPicked(id) => write!(f, "No node picked for eclass {}", id),
+        }
+    }
 }
 
 /// Construct the RecExpr of the optimized graph extracted
 ///
-/// This function does the construction recursively with memoization. Call it with eclass=root
+/// This function does the construction depth first with memoization, using an explicit stack
+/// so very deep graphs don't overflow the call stack. Call it with eclass=root
 /// will construct the whole extracted graph
 ///
 /// # Parameters
@@ -221,27 +672,168 @@ pub struct SolvedResults {
 ///
 /// # Returns
 ///
-/// - The ID (index) in the output RecExpr for the eclass passed in as argument
+/// - The ID (index) in the output RecExpr for the eclass passed in as argument, or an error if
+///   the picked nodes form a cycle or miss an eclass
 pub fn construct_best_rec(
     node_picked: &HashMap<Id, Mdl>,
     eclass: Id,
     added_memo: &mut HashMap<Id, Id>,
     egraph: &EGraph<Mdl, TensorAnalysis>,
     expr: &mut RecExpr<Mdl>,
-) -> Id {
-    let id = egraph.find(eclass);
+) -> Result<Id, ReconstructError> {
+    let root = egraph.find(eclass);
+    // (eclass, whether its children were visited already)
+    let mut stack: Vec<(Id, bool)> = vec![(root, false)];
+    // Eclasses being constructed, from the root down. A child on the path is a cycle
+    let mut path: Vec<Id> = Vec::new();
+    let mut on_path: HashSet<Id> = HashSet::new();
 
-    match added_memo.get(&id) {
-        Some(id_expr) => *id_expr,
-        None => {
-            let node = node_picked.get(&id).unwrap().clone().map_children(|child| {
-                construct_best_rec(node_picked, child, added_memo, egraph, expr)
-            });
+    while let Some((id, children_done)) = stack.pop() {
+        let node = node_picked
+            .get(&id)
+            .ok_or(ReconstructError::NothingPicked(id))?;
+        if children_done {
+            let node = node
+                .clone()
+                .map_children(|child| added_memo[&egraph.find(child)]);
             let id_expr = expr.add(node);
             assert!(added_memo.insert(id, id_expr).is_none());
-            id_expr
+            path.pop();
+            on_path.remove(&id);
+            continue;
+        }
+        if added_memo.contains_key(&id) {
+            continue;
+        }
+        if on_path.contains(&id) {
+            let start = path.iter().position(|c| *c == id).unwrap();
+            return Err(ReconstructError::Cycle(path[start..].to_vec()));
+        }
+        path.push(id);
+        on_path.insert(id);
+        stack.push((id, true));
+        // Push in reverse so children are added in operand order
+        for child in node.children().iter().rev() {
+            let child = egraph.find(*child);
+            if !added_memo.contains_key(&child) {
+                stack.push((child, false));
+            }
         }
     }
+    Ok(added_memo[&root])
+}
+
+/// Share the nodes of `expr` computing the same eclass
+///
+/// egg's `Extractor::find_best` builds a tree, so an eclass with several
+/// consumers is repeated once per consumer. Greedy extraction picks one node
+/// per eclass, so the repeats are identical and are added once, like
+/// `construct_best_rec` does. Random draws in particular must not be repeated.
+pub fn share_eclasses(egraph: &EGraph<Mdl, TensorAnalysis>, expr: &RecExpr<Mdl>) -> RecExpr<Mdl> {
+    let mut shared = RecExpr::default();
+    let mut added_memo: HashMap<Id, Id> = HashMap::new();
+    let mut classes: Vec<Id> = Vec::with_capacity(expr.as_ref().len());
+    let mut ids: Vec<Id> = Vec::with_capacity(expr.as_ref().len());
+    for node in expr.as_ref() {
+        let class_node = node.clone().map_children(|c| classes[usize::from(c)]);
+        let class = egraph
+            .lookup(class_node)
+            .map(|class| egraph.find(class))
+            .expect("Extracted node not in the e-graph");
+        let id = *added_memo
+            .entry(class)
+            .or_insert_with(|| shared.add(node.clone().map_children(|c| ids[usize::from(c)])));
+        classes.push(class);
+        ids.push(id);
+    }
+    shared
+}
+
+/// Construct the RecExpr of a rematerialization ILP solution
+///
+/// Unlike `construct_best_rec`, an eclass may have several picked nodes, each
+/// computing the eclass for some of its consumers (recompute instead of reuse).
+/// Which one a consumer uses is given by `solved_z`; consumers without an entry
+/// use the first picked node of the eclass. The construction is depth first by
+/// node, with an explicit stack like `construct_best_rec`.
+///
+/// # Parameters
+///
+/// - `solved_x`: solved value of the variable of each node i
+/// - `solved_z`: (i, m, j) for each node i that takes its child eclass m from
+///   node j
+/// - `m_id_map`, `e_m`, `h_i`, `g_i`, `i_to_nodes`, `root_m`: as returned by
+///   `prep_ilp_data`
+pub fn construct_remat_rec(
+    solved_x: &[i32],
+    solved_z: &[(usize, usize, usize)],
+    m_id_map: &[Id],
+    e_m: &[Vec<usize>],
+    h_i: &[Vec<usize>],
+    g_i: &[usize],
+    i_to_nodes: &[&Mdl],
+    root_m: usize,
+) -> Result<RecExpr<Mdl>, ReconstructError> {
+    let choice: HashMap<(usize, usize), usize> =
+        solved_z.iter().map(|(i, m, j)| ((*i, *m), *j)).collect();
+    let default_node = |m: usize| -> Result<usize, ReconstructError> {
+        e_m[m]
+            .iter()
+            .copied()
+            .find(|j| solved_x[*j] == 1)
+            .ok_or(ReconstructError::NothingPicked(m_id_map[m]))
+    };
+    let child_node = |i: usize, m: usize| match choice.get(&(i, m)) {
+        Some(j) => Ok(*j),
+        None => default_node(m),
+    };
+
+    let mut expr = RecExpr::default();
+    let mut added_memo: HashMap<usize, Id> = HashMap::new();
+    let root = default_node(root_m)?;
+    // (node, whether its children were visited already)
+    let mut stack: Vec<(usize, bool)> = vec![(root, false)];
+    // Nodes being constructed, from the root down. A child on the path is a cycle
+    let mut path: Vec<usize> = Vec::new();
+    let mut on_path: HashSet<usize> = HashSet::new();
+
+    while let Some((i, children_done)) = stack.pop() {
+        if children_done {
+            // h_i lists the child eclasses in operand order
+            let children = h_i[i]
+                .iter()
+                .map(|m| child_node(i, *m).map(|j| added_memo[&j]))
+                .collect::<Result<Vec<Id>, _>>()?;
+            let mut children = children.into_iter();
+            let node = i_to_nodes[i]
+                .clone()
+                .map_children(|_| children.next().unwrap());
+            added_memo.insert(i, expr.add(node));
+            path.pop();
+            on_path.remove(&i);
+            continue;
+        }
+        if added_memo.contains_key(&i) {
+            continue;
+        }
+        if on_path.contains(&i) {
+            let start = path.iter().position(|j| *j == i).unwrap();
+            return Err(ReconstructError::Cycle(
+                path[start..].iter().map(|j| m_id_map[g_i[*j]]).collect(),
+            ));
+        }
+        path.push(i);
+        on_path.insert(i);
+        stack.push((i, true));
+        // Push in reverse so children are added in operand order
+        for m in h_i[i].iter().rev() {
+            let j = child_node(i, *m)?;
+            if !added_memo.contains_key(&j) {
+                stack.push((j, false));
+            }
+        }
+    }
+    Ok(expr)
 }
 
 /// Get the initial solution for ILP using the greedy extraction
@@ -256,7 +848,7 @@ pub fn construct_best_rec(
 /// - `costs`: Map from eclass ID to the node with the lowest subtree cost (cost, node).
 ///         Constructed by egg's Extractor
 /// - `g_i`: which EClass index does node i belong to
-/// - `nodes_to_i`: map from node to index i
+/// - `nodes_to_i`: map from node (as referenced by `i_to_nodes`) to index i
 ///
 /// # Returns
 ///
@@ -267,19 +859,22 @@ pub fn construct_best_rec(
 pub fn get_init_solution(
     egraph: &EGraph<Mdl, TensorAnalysis>,
     root: Id,
-    costs: &HashMap<Id, (f32, Mdl)>,
+    costs: &HashMap<Id, (f64, Mdl)>,
     g_i: &[usize],
-    nodes_to_i: &HashMap<Mdl, usize>,
+    nodes_to_i: &HashMap<&Mdl, usize>,
 ) -> (Vec<usize>, Vec<usize>) {
-    let mut nodes: Vec<Mdl> = Vec::new();
+    let mut i_list: Vec<usize> = Vec::new();
     // added_memo maps eclass id to id in expr
     let mut added_memo: HashSet<Id> = Default::default();
-    get_init_rec(egraph, root, &mut added_memo, costs, &mut nodes);
+    get_init_rec(
+        egraph,
+        root,
+        &mut added_memo,
+        costs,
+        nodes_to_i,
+        &mut i_list,
+    );
 
-    let i_list: Vec<usize> = nodes
-        .iter()
-        .map(|node| *nodes_to_i.get(node).unwrap())
-        .collect();
     let m_list: Vec<usize> = i_list.iter().map(|i| g_i[*i]).collect();
 
     (i_list, m_list)
@@ -294,23 +889,27 @@ pub fn get_init_solution(
 /// - `added_memo`: Stores the set of eclasses that has already been processed
 /// - `costs`: Map from eclass ID to the node with the lowest subtree cost (cost, node).
 ///         Constructed by egg's Extractor
-/// - `nodes`: List of nodes picked by greedy extraction. Constructed within this function
+/// - `nodes_to_i`: map from node to index i
+/// - `i_list`: List of indices i of the nodes picked by greedy extraction. Constructed within
+///         this function
 fn get_init_rec(
     egraph: &EGraph<Mdl, TensorAnalysis>,
     eclass: Id,
     added_memo: &mut HashSet<Id>,
-    costs: &HashMap<Id, (f32, Mdl)>,
-    nodes: &mut Vec<Mdl>,
+    costs: &HashMap<Id, (f64, Mdl)>,
+    nodes_to_i: &HashMap<&Mdl, usize>,
+    i_list: &mut Vec<usize>,
 ) {
     let id = egraph.find(eclass);
 
     if !added_memo.contains(&id) {
-        let (_, best_node) = match costs.get(&id) {
-            Some(result) => result.clone(),
+        let best_node = match costs.get(&id) {
+            Some((_, node)) => node,
             None => panic!("Failed to extract from eclass {}", id),
         };
-        best_node.for_each(|child| get_init_rec(egraph, child, added_memo, costs, nodes));
-        nodes.push(best_node);
+        best_node
+            .for_each(|child| get_init_rec(egraph, child, added_memo, costs, nodes_to_i, i_list));
+        i_list.push(*nodes_to_i.get(best_node).unwrap());
         added_memo.insert(id);
     }
 }
@@ -0,0 +1,375 @@
+use crate::model::*;
+use egg::*;
+use std::collections::{HashMap, HashSet};
+
+/// Graph being rebuilt by a pass. Structurally equal nodes are shared, so shape
+/// vectors and scalars built separately by the converter end up as one node,
+/// except for the ops `shareable` rules out. Shapes come from an e-graph kept
+/// alongside, with one eclass per distinct node.
+struct Rebuilder {
+    nodes: Vec<Mdl>,
+    memo: HashMap<Mdl, Id>,
+    egraph: EGraph<Mdl, TensorAnalysis>,
+    classes: Vec<Id>,
+}
+
+impl Rebuilder {
+    fn new(analysis: TensorAnalysis) -> Self {
+        Rebuilder {
+            nodes: vec![],
+            memo: HashMap::new(),
+            egraph: EGraph::new(analysis),
+            classes: vec![],
+        }
+    }
+
+    fn add(&mut self, node: Mdl) -> Id {
+        if let Some(id) = self.memo.get(&node) {
+            return *id;
+        }
+        let class = self
+            .egraph
+            .add(node.clone().map_children(|c| self.classes[usize::from(c)]));
+        let id = Id::from(self.nodes.len());
+        self.nodes.push(node.clone());
+        self.classes.push(class);
+        if shareable(&node) {
+            self.memo.insert(node, id);
+        }
+        id
+    }
+
+    fn num(&self, id: Id) -> Option<i64> {
+        match self.nodes[usize::from(id)] {
+            Mdl::Num(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    fn nums(&self, id: Id) -> Option<Vec<i64>> {
+        match &self.nodes[usize::from(id)] {
+            Mdl::Vec(ids) => ids.iter().map(|c| self.num(*c)).collect(),
+            _ => None,
+        }
+    }
+
+    /// Shape vector node of `dims`
+    fn shape_vec(&mut self, dims: &[i64]) -> Id {
+        let dims: Vec<Id> = dims.iter().map(|d| self.add(Mdl::Num(*d))).collect();
+        self.add(Mdl::Vec(dims))
+    }
+
+    fn shape(&self, id: Id) -> Vec<i64> {
+        let data = &self.egraph[self.classes[usize::from(id)]].data;
+        data.shapes[0][..data.n_dims[0]]
+            .iter()
+            .map(|d| i64::from(*d))
+            .collect()
+    }
+
+    /// Copy the nodes reachable from root into a RecExpr, keeping their order
+    fn finish(self, root: Id) -> RecExpr<Mdl> {
+        reachable_nodes(&self.nodes, root)
+    }
+}
+
+/// Whether structurally equal copies of `node` may be shared. Custom calls can
+/// have side effects, and random ops without a site (RngBitGeneratorOp) are
+/// told apart by nothing but their position, so each copy is kept.
+fn shareable(node: &Mdl) -> bool {
+    match node {
+        Mdl::CustomCallOp(_) => false,
+        Mdl::RngOp(_) => true,
+        node => !is_random(node),
+    }
+}
+
+/// Which nodes are reachable from `root`
+fn reachable_mask(nodes: &[Mdl], root: Id) -> Vec<bool> {
+    operand_mask(nodes, [root])
+}
+
+/// Which nodes are in `roots` or operands of them, transitively
+fn operand_mask(nodes: &[Mdl], roots: impl IntoIterator<Item = Id>) -> Vec<bool> {
+    let mut reachable = vec![false; nodes.len()];
+    for root in roots {
+        reachable[usize::from(root)] = true;
+    }
+    for i in (0..nodes.len()).rev() {
+        if reachable[i] {
+            for child in nodes[i].children() {
+                reachable[usize::from(*child)] = true;
+            }
+        }
+    }
+    reachable
+}
+
+/// Copy the nodes reachable from `root` into a RecExpr, keeping their order
+fn reachable_nodes(nodes: &[Mdl], root: Id) -> RecExpr<Mdl> {
+    let reachable = reachable_mask(nodes, root);
+    let mut new_ids = vec![Id::from(0); nodes.len()];
+    let mut expr = RecExpr::default();
+    for (i, node) in nodes.iter().enumerate() {
+        if reachable[i] {
+            new_ids[i] = expr.add(node.clone().map_children(|c| new_ids[usize::from(c)]));
+        }
+    }
+    expr
+}
+
+/// Remove the nodes not reachable from the root (the last node), e.g. scalars
+/// and vectors left over from graph construction. The order of the remaining
+/// nodes is kept, so this is idempotent.
+pub fn prune_unreachable(expr: &RecExpr<Mdl>) -> RecExpr<Mdl> {
+    match expr.as_ref().len() {
+        0 => RecExpr::default(),
+        n => reachable_nodes(expr.as_ref(), Id::from(n - 1)),
+    }
+}
+
+/// Indices of the nodes `prune_unreachable` keeps, in order
+pub fn reachable_indices(expr: &RecExpr<Mdl>) -> Vec<usize> {
+    match expr.as_ref().len() {
+        0 => vec![],
+        n => reachable_mask(expr.as_ref(), Id::from(n - 1))
+            .into_iter()
+            .enumerate()
+            .filter(|(_, reachable)| *reachable)
+            .map(|(i, _)| i)
+            .collect(),
+    }
+}
+
+/// What the elements of a Vec node are
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VecKind {
+    /// Integer attributes, e.g. permutations and shapes. Every element is a Num
+    Nums,
+    /// Tensor operands, e.g. the inputs of a ConcatenateOp. No element is a Num
+    Tensors,
+}
+
+/// Kind of Vec `node` takes as its child at position `child`, if that child is a
+/// Vec. ConcatenateOp, TupleOp, OptimizationBarrierOp and ReturnOp take lists of
+/// tensors, every other Vec is a list of integer attributes.
+pub fn expected_vec_kind(node: &Mdl, child: usize) -> VecKind {
+    match (node, child) {
+        (Mdl::ConcatenateOp(_), 0)
+        | (Mdl::TupleOp(_), 0)
+        | (Mdl::OptimizationBarrierOp(_), 0)
+        | (Mdl::ReturnOp(_), 0) => VecKind::Tensors,
+        _ => VecKind::Nums,
+    }
+}
+
+/// Check that every Vec used by an op holds the kind of elements the op expects
+/// there, so a list of tensors is never read as integer attributes (or the other
+/// way round) during saturation.
+pub fn validate_vecs(expr: &RecExpr<Mdl>) -> Result<(), String> {
+    let nodes = expr.as_ref();
+    for (i, node) in nodes.iter().enumerate() {
+        if matches!(node, Mdl::Vec(_)) {
+            continue;
+        }
+        for (j, child) in node.children().iter().enumerate() {
+            let elements = match &nodes[usize::from(*child)] {
+                Mdl::Vec(elements) => elements,
+                _ => continue,
+            };
+            let kind = expected_vec_kind(node, j);
+            for element in elements {
+                let element_node = &nodes[usize::from(*element)];
+                let is_num = matches!(element_node, Mdl::Num(_));
+                let is_tensor = !matches!(element_node, Mdl::Num(_) | Mdl::Vec(_) | Mdl::Var(_));
+                let ok = match kind {
+                    VecKind::Nums => is_num,
+                    VecKind::Tensors => is_tensor,
+                };
+                if !ok {
+                    return Err(format!(
+                        "Operand {} of node {} ({}) should be a Vec of {:?}, but contains node {} ({})",
+                        j,
+                        i,
+                        node.display_op(),
+                        kind,
+                        element,
+                        element_node.display_op()
+                    ));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Canonicalize the input graph before building the e-graph
+///
+/// The pass is deterministic and only uses local information:
+///
+/// - structurally equal nodes (in particular shape vectors and scalars) are
+///   shared, except custom calls and random ops without a site
+/// - transposes with the identity permutation and reshapes to the operand's
+///   shape are dropped
+/// - chains of transposes and of reshapes are folded into one, and reshapes,
+///   transposes and broadcasts of splat constants into a constant of the
+///   resulting shape
+/// - operands of commutative ops (AddOp, MulOp, MinOp, MaxOp) are sorted by id
+/// - nodes not reachable from the root are removed
+///
+/// # Parameters
+///
+/// - `expr`: graph as built by the converter, with the root as last node
+/// - `analysis`: analysis used to infer shapes
+/// - `keep`: nodes (e.g. protected or sharded ones) the pass leaves as they
+///   are, along with their operands, so they are found in the e-graph
+pub fn canonicalize_graph(
+    expr: &RecExpr<Mdl>,
+    analysis: TensorAnalysis,
+    keep: &HashSet<Id>,
+) -> RecExpr<Mdl> {
+    let keep_mask = operand_mask(expr.as_ref(), keep.iter().copied());
+    let mut graph = Rebuilder::new(analysis);
+    let mut new_ids: Vec<Id> = Vec::with_capacity(expr.as_ref().len());
+    // Nodes of graph that are kept, which folds don't look through
+    let mut kept: HashSet<Id> = HashSet::new();
+
+    for (i, node) in expr.as_ref().iter().enumerate() {
+        let node = node.clone().map_children(|c| new_ids[usize::from(c)]);
+        if keep_mask[i] {
+            let id = graph.add(node);
+            kept.insert(id);
+            new_ids.push(id);
+            continue;
+        }
+        let id = match node {
+            Mdl::TransposeOp([x, perm]) => match graph.nums(perm) {
+                Some(p2) => {
+                    // transpose(transpose(y, p1), p2) is transpose(y, p1 o p2)
+                    let inner = match foldable(&graph, &kept, x) {
+                        Some(Mdl::TransposeOp([y, p1])) => graph
+                            .nums(*p1)
+                            .filter(|p1| p1.len() == p2.len())
+                            .map(|p1| (*y, p1)),
+                        _ => None,
+                    };
+                    let (x, p) = match inner {
+                        Some((y, p1)) => (y, p2.iter().map(|axis| p1[*axis as usize]).collect()),
+                        None => (x, p2),
+                    };
+                    if p.iter().enumerate().all(|(i, axis)| *axis == i as i64) {
+                        x
+                    } else {
+                        let value = splat_value(&graph, &kept, x);
+                        let perm = graph.shape_vec(&p);
+                        fold_splat(&mut graph, value, Mdl::TransposeOp([x, perm]))
+                    }
+                }
+                None => graph.add(Mdl::TransposeOp([x, perm])),
+            },
+            Mdl::ReshapeOp([x, shape]) => {
+                // Reshaping a reshape only depends on the original operand
+                let x = match foldable(&graph, &kept, x) {
+                    Some(Mdl::ReshapeOp([inner, _])) => *inner,
+                    _ => x,
+                };
+                if graph.nums(shape).map_or(false, |s| s == graph.shape(x)) {
+                    x
+                } else {
+                    let value = splat_value(&graph, &kept, x);
+                    fold_splat(&mut graph, value, Mdl::ReshapeOp([x, shape]))
+                }
+            }
+            Mdl::BroadcastInDimOp([x, dims]) => {
+                let value = splat_value(&graph, &kept, x);
+                fold_splat(&mut graph, value, Mdl::BroadcastInDimOp([x, dims]))
+            }
+            Mdl::AddOp([a, b]) => graph.add(Mdl::AddOp([a.min(b), a.max(b)])),
+            Mdl::MulOp([a, b]) => graph.add(Mdl::MulOp([a.min(b), a.max(b)])),
+            Mdl::MinOp([a, b]) => graph.add(Mdl::MinOp([a.min(b), a.max(b)])),
+            Mdl::MaxOp([a, b]) => graph.add(Mdl::MaxOp([a.min(b), a.max(b)])),
+            node => graph.add(node),
+        };
+        new_ids.push(id);
+    }
+
+    let root = *new_ids.last().expect("Empty graph");
+    graph.finish(root)
+}
+
+/// Node `id` of `graph`, unless it is `kept`
+fn foldable<'a>(graph: &'a Rebuilder, kept: &HashSet<Id>, id: Id) -> Option<&'a Mdl> {
+    if kept.contains(&id) {
+        None
+    } else {
+        Some(&graph.nodes[usize::from(id)])
+    }
+}
+
+/// Value of node `id` of `graph`, if it is a splat constant that isn't `kept`
+fn splat_value(graph: &Rebuilder, kept: &HashSet<Id>, id: Id) -> Option<Id> {
+    match foldable(graph, kept, id) {
+        Some(Mdl::ConstantOp([value, _])) => Some(*value),
+        _ => None,
+    }
+}
+
+/// Add `node`, an op only changing the shape of its operand. If the operand is
+/// a splat constant of `splat_value`, add instead the constant of the shape of
+/// `node`.
+fn fold_splat(graph: &mut Rebuilder, splat_value: Option<Id>, node: Mdl) -> Id {
+    let id = graph.add(node);
+    let dims = graph.shape(id);
+    match splat_value {
+        Some(value) if dims.iter().all(|d| *d >= 0) => {
+            let shape = graph.shape_vec(&dims);
+            graph.add(Mdl::ConstantOp([value, shape]))
+        }
+        _ => id,
+    }
+}
+
+/// Cheap local cleanup of the extracted graph
+///
+/// The ILP can keep nodes that are free in the cost model but still cost compile
+/// time downstream. This pass drops identity transposes and reshapes, merges
+/// chains of reshapes into one, and removes duplicated and dead nodes.
+///
+/// # Parameters
+///
+/// - `expr`: extracted graph, with the root as last node
+/// - `analysis`: analysis used to infer shapes
+pub fn peephole(expr: &RecExpr<Mdl>, analysis: TensorAnalysis) -> RecExpr<Mdl> {
+    let mut graph = Rebuilder::new(analysis);
+    let mut new_ids: Vec<Id> = Vec::with_capacity(expr.as_ref().len());
+
+    for node in expr.as_ref() {
+        let node = node.clone().map_children(|c| new_ids[usize::from(c)]);
+        let id = match node {
+            Mdl::TransposeOp([x, perm])
+                if graph.nums(perm).map_or(false, |p| {
+                    p.iter().enumerate().all(|(i, axis)| *axis == i as i64)
+                }) =>
+            {
+                x
+            }
+            Mdl::ReshapeOp([x, shape]) => {
+                // Reshaping a reshape only depends on the original operand
+                let x = match graph.nodes[usize::from(x)] {
+                    Mdl::ReshapeOp([inner, _]) => inner,
+                    _ => x,
+                };
+                if graph.nums(shape).map_or(false, |s| s == graph.shape(x)) {
+                    x
+                } else {
+                    graph.add(Mdl::ReshapeOp([x, shape]))
+                }
+            }
+            node => graph.add(node),
+        };
+        new_ids.push(id);
+    }
+
+    let root = *new_ids.last().expect("Empty graph");
+    graph.finish(root)
+}
@@ -0,0 +1,140 @@
+use crate::model::Mdl;
+use crate::rewrites::parse_rule_line;
+use egg::{ENodeOrVar, Id, Language, Pattern, RecExpr};
+use std::collections::HashSet;
+
+/// Result of exporting rules to PDLL
+#[derive(Debug, Default)]
+pub struct PdllExport {
+    /// The PDLL file, one `Pattern` per exported rule
+    pub text: String,
+    /// Number of rules exported
+    pub num_exported: usize,
+    /// Rules that could not be exported, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+/// StableHLO op of an Mdl op in the exportable subset
+///
+/// Only elementwise ops are exported: their operands and results all have the
+/// same type, so the types of the ops built by the rewrite can be taken from
+/// the matched root. Ops with attributes (Vec and Num children) or shape
+/// changes would need their types and attributes computed in native code.
+fn stablehlo_op(node: &Mdl) -> Option<&'static str> {
+    match node {
+        Mdl::AddOp(_) => Some("stablehlo.add"),
+        Mdl::SubtractOp(_) => Some("stablehlo.subtract"),
+        Mdl::MulOp(_) => Some("stablehlo.multiply"),
+        Mdl::DivOp(_) => Some("stablehlo.divide"),
+        Mdl::MinOp(_) => Some("stablehlo.minimum"),
+        Mdl::MaxOp(_) => Some("stablehlo.maximum"),
+        Mdl::NegOp(_) => Some("stablehlo.negate"),
+        Mdl::TanhOp(_) => Some("stablehlo.tanh"),
+        Mdl::ExpOp(_) => Some("stablehlo.exponential"),
+        Mdl::SqrtOp(_) => Some("stablehlo.sqrt"),
+        Mdl::RsqrtOp(_) => Some("stablehlo.rsqrt"),
+        Mdl::LogOp(_) => Some("stablehlo.log"),
+        Mdl::SineOp(_) => Some("stablehlo.sine"),
+        Mdl::CosineOp(_) => Some("stablehlo.cosine"),
+        Mdl::AbsOp(_) => Some("stablehlo.abs"),
+        Mdl::SignOp(_) => Some("stablehlo.sign"),
+        Mdl::FloorOp(_) => Some("stablehlo.floor"),
+        Mdl::CeilOp(_) => Some("stablehlo.ceil"),
+        Mdl::RoundOp(_) => Some("stablehlo.round_nearest_afz"),
+        Mdl::PowOp(_) => Some("stablehlo.power"),
+        Mdl::RemainderOp(_) => Some("stablehlo.remainder"),
+        Mdl::AndOp(_) => Some("stablehlo.and"),
+        Mdl::OrOp(_) => Some("stablehlo.or"),
+        Mdl::XorOp(_) => Some("stablehlo.xor"),
+        Mdl::NotOp(_) => Some("stablehlo.not"),
+        Mdl::ShiftLeftOp(_) => Some("stablehlo.shift_left"),
+        Mdl::ShiftRightLogicalOp(_) => Some("stablehlo.shift_right_logical"),
+        Mdl::ShiftRightArithmeticOp(_) => Some("stablehlo.shift_right_arithmetic"),
+        _ => None,
+    }
+}
+
+/// Translate rules (lines of a rule file, "lhs=>rhs") to MLIR PDLL patterns
+///
+/// Only rules made of elementwise ops (see `stablehlo_op`) are exported; the
+/// others are listed in `skipped`. Pattern names follow the rule names of
+/// `rules_from_str` ("rule{pos}"), so they can be traced back to the rule file.
+pub fn rules_to_pdll(rules: &[&str]) -> PdllExport {
+    let mut export = PdllExport::default();
+    export.text += "// Generated by tensat from its rule file, do not edit\n";
+    for (pos, line) in rules.iter().enumerate() {
+        let (rule, _) = parse_rule_line(line);
+        match rule_to_pdll(&format!("rule{}", pos), rule) {
+            Ok(pattern) => {
+                export.text += "\n";
+                export.text += &pattern;
+                export.num_exported += 1;
+            }
+            Err(reason) => export.skipped.push((rule.to_string(), reason)),
+        }
+    }
+    export
+}
+
+fn rule_to_pdll(name: &str, rule: &str) -> Result<String, String> {
+    let (lhs, rhs) = rule
+        .split_once("=>")
+        .ok_or_else(|| "not of the form lhs=>rhs".to_string())?;
+    let lhs: Pattern<Mdl> = lhs.parse().map_err(|e| format!("{:?}", e))?;
+    let rhs: Pattern<Mdl> = rhs.parse().map_err(|e| format!("{:?}", e))?;
+    let (lhs, rhs) = (&lhs.ast, &rhs.ast);
+    let lhs_root = root(lhs);
+    if let ENodeOrVar::Var(_) = &lhs[lhs_root] {
+        return Err("left hand side is a variable".to_string());
+    }
+
+    let mut declared = HashSet::new();
+    let matched = expr_to_pdll(lhs, lhs_root, &mut declared, true)?;
+    let lhs_vars = declared.clone();
+    let replacement = expr_to_pdll(rhs, root(rhs), &mut declared, false)?;
+    if declared != lhs_vars {
+        return Err("right hand side introduces new variables".to_string());
+    }
+    Ok(format!(
+        "Pattern {} {{\n  let root = {} -> (type: Type);\n  replace root with {};\n}}\n",
+        name, matched, replacement
+    ))
+}
+
+fn root(expr: &RecExpr<ENodeOrVar<Mdl>>) -> Id {
+    Id::from(expr.as_ref().len() - 1)
+}
+
+/// PDLL expression of the subpattern rooted at `id`
+///
+/// In the matched pattern, the first occurrence of each variable declares it
+/// as a `Value`. Ops built by the replacement take the type of the root.
+fn expr_to_pdll(
+    expr: &RecExpr<ENodeOrVar<Mdl>>,
+    id: Id,
+    declared: &mut HashSet<String>,
+    matching: bool,
+) -> Result<String, String> {
+    match &expr[id] {
+        ENodeOrVar::Var(var) => {
+            let name = var.to_string().trim_start_matches('?').to_string();
+            if declared.insert(name.clone()) && matching {
+                Ok(format!("{}: Value", name))
+            } else {
+                Ok(name)
+            }
+        }
+        ENodeOrVar::ENode(node) => {
+            let op = stablehlo_op(node)
+                .ok_or_else(|| format!("{} has no elementwise StableHLO op", node.display_op()))?;
+            let operands = node
+                .children()
+                .iter()
+                .map(|child| expr_to_pdll(expr, *child, declared, matching))
+                .collect::<Result<Vec<String>, String>>()?;
+            // The type of the matched root is bound by the caller
+            let types = if matching { "" } else { " -> (type)" };
+            Ok(format!("op<{}>({}){}", op, operands.join(", "), types))
+        }
+    }
+}
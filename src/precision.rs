@@ -0,0 +1,223 @@
+//! Mixed precision
+//!
+//! With `OptimizeConfig::mixed_precision` set, the rules below let saturation
+//! move ops from f32 to bf16: the op computes on bf16 copies of its operands
+//! and its result is converted back to f32, so the lowered form is a drop-in
+//! for the original. Conversions that cancel out are dropped, so a chain of
+//! lowered ops stays in bf16 in between, and the cost model decides where the
+//! conversions pay off. DotGeneralOps on bf16 operands are expected to
+//! accumulate in f32, as XLA does.
+//!
+//! Lowered forms are not exactly equal to the original, which is why the
+//! policy is opt-in. `numeric_check` compares the optimized graph with the
+//! input with the reference interpreter, which rounds to bf16 at each
+//! conversion.
+//!
+//! Unrelated to the above, the last operand of a DotGeneralOp is its
+//! precision_config, the precision (DEFAULT, HIGH or HIGHEST, as 0, 1 and 2)
+//! of each operand. Rules from a rule file keep it consistent: a rule merging
+//! DotGeneralOps with different configs is not applied, and a rule splitting or
+//! reassociating them gives every DotGeneralOp it adds the stricter of the
+//! matched configs (see `join_precision`).
+
+use crate::config::MixedPrecision;
+use crate::input::ffi;
+use crate::model::*;
+use crate::rewrites::{finish_apply, get_vec_of_nums_option, make_num, make_vec};
+use egg::*;
+
+/// Rules lowering the ops allowed by `policy` to bf16, and dropping the
+/// conversions that cancel out. No rules if the policy is Off
+pub fn precision_rules(policy: MixedPrecision) -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    let mut rules = match policy {
+        MixedPrecision::Off => return vec![],
+        MixedPrecision::Dot | MixedPrecision::Elementwise => vec![
+            rewrite!("lower-dot-general"; "(DotGeneralOp ?x ?y ?lb ?rb ?lc ?rc ?prec)" => {
+                lower("(DotGeneralOp (ConvertOp ?x ?bf16) (ConvertOp ?y ?bf16) ?lb ?rb ?lc ?rc ?prec)", &["?x", "?y"])
+            }),
+            rewrite!("drop-identity-convert"; "(ConvertOp ?x ?t)" => {
+                DropConvert { roundtrip: false }
+            }),
+            rewrite!("drop-convert-roundtrip"; "(ConvertOp (ConvertOp ?x ?t) ?u)" => {
+                DropConvert { roundtrip: true }
+            }),
+        ],
+    };
+    if policy == MixedPrecision::Elementwise {
+        rules.append(&mut vec![
+            rewrite!("lower-add"; "(AddOp ?x ?y)" => {
+                lower("(AddOp (ConvertOp ?x ?bf16) (ConvertOp ?y ?bf16))", &["?x", "?y"])
+            }),
+            rewrite!("lower-subtract"; "(SubtractOp ?x ?y)" => {
+                lower("(SubtractOp (ConvertOp ?x ?bf16) (ConvertOp ?y ?bf16))", &["?x", "?y"])
+            }),
+            rewrite!("lower-mul"; "(MulOp ?x ?y)" => {
+                lower("(MulOp (ConvertOp ?x ?bf16) (ConvertOp ?y ?bf16))", &["?x", "?y"])
+            }),
+            rewrite!("lower-min"; "(MinOp ?x ?y)" => {
+                lower("(MinOp (ConvertOp ?x ?bf16) (ConvertOp ?y ?bf16))", &["?x", "?y"])
+            }),
+            rewrite!("lower-max"; "(MaxOp ?x ?y)" => {
+                lower("(MaxOp (ConvertOp ?x ?bf16) (ConvertOp ?y ?bf16))", &["?x", "?y"])
+            }),
+            rewrite!("lower-neg"; "(NegOp ?x)" => {
+                lower("(NegOp (ConvertOp ?x ?bf16))", &["?x"])
+            }),
+        ]);
+    }
+    rules
+}
+
+/// `op` (with its operands converted to ?bf16) converted back to f32
+fn lower(op: &str, operands: &[&str]) -> LowerToBf16 {
+    LowerToBf16 {
+        lowered: format!("(ConvertOp {} ?f32)", op).parse().unwrap(),
+        operands: operands.iter().map(|v| v.parse().unwrap()).collect(),
+    }
+}
+
+/// Rounds `x` to the nearest bf16 (ties to even), keeping it as an f32
+pub fn round_to_bf16(x: f32) -> f32 {
+    if x.is_nan() {
+        return x;
+    }
+    let bits = x.to_bits();
+    let rounding = 0x7fff + ((bits >> 16) & 1);
+    f32::from_bits(bits.wrapping_add(rounding) & 0xffff_0000)
+}
+
+/// Adds the lowered form of the matched op, if all its tensor operands are f32
+#[derive(Debug, Clone, PartialEq)]
+pub struct LowerToBf16 {
+    /// The op on bf16 operands, converted back to f32. ?bf16 and ?f32 are
+    /// bound to the output types of the conversions
+    pub lowered: Pattern<Mdl>,
+    /// Tensor operands of the matched op
+    pub operands: Vec<Var>,
+}
+
+impl Applier<Mdl, TensorAnalysis> for LowerToBf16 {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let f32_operands = self
+            .operands
+            .iter()
+            .all(|v| egraph[subst[*v]].data.dtype == ffi::Type::f32);
+        if !f32_operands || egraph[matched_id].data.dtype != ffi::Type::f32 {
+            return vec![];
+        }
+        let mut subst = subst.clone();
+        subst.insert(
+            "?bf16".parse().unwrap(),
+            make_num(egraph, dtype_to_num(ffi::Type::bf16)),
+        );
+        subst.insert(
+            "?f32".parse().unwrap(),
+            make_num(egraph, dtype_to_num(ffi::Type::f32)),
+        );
+        self.lowered.apply_one(egraph, matched_id, &subst)
+    }
+}
+
+/// Whether converting from `from` to `to` loses nothing
+fn is_exact(from: ffi::Type, to: ffi::Type) -> bool {
+    from == to || (from == ffi::Type::bf16 && to == ffi::Type::f32)
+}
+
+/// Drops a conversion to the type ?x already has (`roundtrip: false`, matching
+/// `(ConvertOp ?x ?t)`), or a round trip back to the type of ?x through a type
+/// holding all its values (`roundtrip: true`, matching
+/// `(ConvertOp (ConvertOp ?x ?t) ?u)`), e.g. bf16 to f32 and back
+#[derive(Debug, Clone, PartialEq)]
+pub struct DropConvert {
+    pub roundtrip: bool,
+}
+
+impl Applier<Mdl, TensorAnalysis> for DropConvert {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let x = subst["?x".parse().unwrap()];
+        let dtype = egraph[x].data.dtype;
+        if egraph[matched_id].data.dtype != dtype {
+            return vec![];
+        }
+        if self.roundtrip {
+            let t = subst["?t".parse().unwrap()];
+            let inner = match egraph.lookup(Mdl::ConvertOp([x, t])) {
+                Some(inner) => inner,
+                None => return vec![],
+            };
+            if !is_exact(dtype, egraph[inner].data.dtype) {
+                return vec![];
+            }
+        }
+        finish_apply(egraph, matched_id, x)
+    }
+}
+
+/// Precision_config operand of each DotGeneralOp in `pat` that is a variable
+pub fn dot_precisions(pat: &[ENodeOrVar<Mdl>]) -> Vec<Var> {
+    pat.iter()
+        .filter_map(|node| match node {
+            ENodeOrVar::ENode(Mdl::DotGeneralOp(children)) => {
+                match &pat[usize::from(children[6])] {
+                    ENodeOrVar::Var(var) => Some(*var),
+                    ENodeOrVar::ENode(_) => None,
+                }
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Elementwise stricter of two precision configs. Missing entries are DEFAULT
+pub fn stricter_precision(a: &[i64], b: &[i64]) -> Vec<i64> {
+    (0..a.len().max(b.len()))
+        .map(|i| {
+            let a = a.get(i).copied().unwrap_or(0);
+            let b = b.get(i).copied().unwrap_or(0);
+            a.max(b)
+        })
+        .collect()
+}
+
+/// Substitution to apply a rule with, given the precision configs of the
+/// DotGeneralOps of its source (`src`) and destination (`dst`) patterns
+///
+/// If the matched configs all agree, this is `subst`. Otherwise the rule is
+/// refused (`None`) if it merges DotGeneralOps, i.e. `dst` has fewer of them
+/// than `src`; if not, the configs in `dst` are bound to the stricter of the
+/// matched configs.
+pub fn join_precision(
+    egraph: &mut EGraph<Mdl, TensorAnalysis>,
+    src: &[Var],
+    dst: &[Var],
+    subst: &Subst,
+) -> Option<Subst> {
+    let classes: Vec<Id> = src.iter().map(|var| egraph.find(subst[*var])).collect();
+    if classes.windows(2).all(|pair| pair[0] == pair[1]) {
+        return Some(subst.clone());
+    }
+    if dst.len() < src.len() {
+        return None;
+    }
+    let mut joined = vec![];
+    for class in classes {
+        joined = stricter_precision(&joined, &get_vec_of_nums_option(egraph, &egraph[class])?);
+    }
+    let nums: Vec<Id> = joined.iter().map(|n| make_num(egraph, *n)).collect();
+    let joined = make_vec(egraph, &nums);
+    let mut subst = subst.clone();
+    for var in dst {
+        subst.insert(*var, joined);
+    }
+    Some(subst)
+}
@@ -0,0 +1,162 @@
+use serde_json::{json, Value};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::fs::write;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Allocator counting the allocations made through it
+///
+/// Installed as the global allocator with the `profile-alloc` feature, otherwise
+/// the allocation counters of the profile stay at zero.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        ALLOCATED_BYTES.fetch_add(layout.size() as u64, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[cfg(feature = "profile-alloc")]
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Number of allocations and allocated bytes so far
+pub fn alloc_counters() -> (u64, u64) {
+    (
+        ALLOCATIONS.load(Ordering::Relaxed),
+        ALLOCATED_BYTES.load(Ordering::Relaxed),
+    )
+}
+
+/// A timed phase of the optimization
+#[derive(Clone, Debug)]
+pub struct PhaseEvent {
+    pub name: String,
+    /// Microseconds since the profiler was created
+    pub start_us: u64,
+    pub duration_us: u64,
+    /// Allocations made during the phase, zero unless allocations are counted
+    pub allocations: u64,
+    pub allocated_bytes: u64,
+}
+
+/// Records the time (and allocations) spent in each phase of an optimization
+pub struct Profiler {
+    start: Instant,
+    pub events: Vec<PhaseEvent>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            start: Instant::now(),
+            events: Vec::new(),
+        }
+    }
+
+    /// Run `f` as the phase `name`
+    pub fn phase<T>(&mut self, name: &str, f: impl FnOnce() -> T) -> T {
+        let (allocations, allocated_bytes) = alloc_counters();
+        let start = Instant::now();
+        let result = f();
+        let duration = start.elapsed();
+        let (allocations_after, allocated_bytes_after) = alloc_counters();
+        self.push(
+            name,
+            start,
+            duration,
+            allocations_after - allocations,
+            allocated_bytes_after - allocated_bytes,
+        );
+        result
+    }
+
+    /// Record a phase timed elsewhere, e.g. by egg's runner
+    pub fn record(&mut self, name: &str, start: Instant, duration: Duration) {
+        self.push(name, start, duration, 0, 0);
+    }
+
+    fn push(
+        &mut self,
+        name: &str,
+        start: Instant,
+        duration: Duration,
+        allocations: u64,
+        allocated_bytes: u64,
+    ) {
+        self.events.push(PhaseEvent {
+            name: name.to_string(),
+            start_us: start.saturating_duration_since(self.start).as_micros() as u64,
+            duration_us: duration.as_micros() as u64,
+            allocations,
+            allocated_bytes,
+        });
+    }
+
+    /// Profile as `{"phases": [{"name", "start_us", "duration_us", ...}]}`
+    pub fn to_json(&self) -> Value {
+        let phases: Vec<Value> = self
+            .events
+            .iter()
+            .map(|e| {
+                json!({
+                    "name": e.name,
+                    "start_us": e.start_us,
+                    "duration_us": e.duration_us,
+                    "allocations": e.allocations,
+                    "allocated_bytes": e.allocated_bytes,
+                })
+            })
+            .collect();
+        json!({ "phases": phases })
+    }
+
+    /// Profile in the Chrome trace event format, viewable in chrome://tracing
+    /// or Perfetto
+    pub fn to_chrome_trace(&self) -> Value {
+        let events: Vec<Value> = self
+            .events
+            .iter()
+            .map(|e| {
+                json!({
+                    "name": e.name,
+                    "ph": "X",
+                    "ts": e.start_us,
+                    "dur": e.duration_us,
+                    "pid": 0,
+                    "tid": 0,
+                    "args": {
+                        "allocations": e.allocations,
+                        "allocated_bytes": e.allocated_bytes,
+                    },
+                })
+            })
+            .collect();
+        json!({ "traceEvents": events })
+    }
+
+    /// Write the profile to `path`, in Chrome trace format if `chrome_trace`
+    pub fn write(&self, path: &str, chrome_trace: bool) -> std::io::Result<()> {
+        let profile = if chrome_trace {
+            self.to_chrome_trace()
+        } else {
+            self.to_json()
+        };
+        write(path, profile.to_string())
+    }
+}
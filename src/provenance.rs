@@ -0,0 +1,251 @@
+use crate::{
+    model::*,
+    optimize::{add_costs, CostModel},
+    replay::*,
+};
+use egg::*;
+use std::collections::{HashMap, HashSet};
+
+/// Canonical enode and eclass of each node of `expr`, None for nodes not in the
+/// e-graph (e.g. added by a pass after extraction)
+pub(crate) fn canonical_nodes(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    expr: &RecExpr<Mdl>,
+) -> Vec<Option<(Mdl, Id)>> {
+    let mut classes: Vec<Option<Id>> = Vec::with_capacity(expr.as_ref().len());
+    let mut nodes = Vec::with_capacity(expr.as_ref().len());
+    for node in expr.as_ref() {
+        let mut complete = true;
+        let node = node.clone().map_children(|c| match classes[usize::from(c)] {
+            Some(class) => egraph.find(class),
+            None => {
+                complete = false;
+                c
+            }
+        });
+        let class = if complete {
+            egraph.lookup(node.clone())
+        } else {
+            None
+        };
+        classes.push(class);
+        nodes.push(class.map(|class| (node, egraph.find(class))));
+    }
+    nodes
+}
+
+/// Get the substitutions each node of the extracted graph was derived with
+///
+/// egg's explanations are not available in our egg version, so derivations are
+/// rebuilt from the recorded substitutions: a node is justified by the first
+/// substitution that produced it, and is derived from the node that
+/// substitution matched. Following these back to a node of the starting graph
+/// gives the derivation path. A node from the starting graph has an empty path.
+///
+/// # Parameters
+///
+/// - `egraph`: saturated e-graph
+/// - `start`: graph saturation started from
+/// - `extracted`: graph extracted from `egraph`
+/// - `log`: substitutions recorded during saturation
+///
+/// # Returns
+///
+/// The indices in `log` of the substitutions on the derivation path of each node
+/// of `extracted`, in the same order, earliest first.
+pub fn node_derivations(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    start: &RecExpr<Mdl>,
+    extracted: &RecExpr<Mdl>,
+    log: &[AppliedRewrite],
+) -> Vec<Vec<usize>> {
+    let canonical = |node: &Mdl| node.clone().map_children(|c| egraph.find(c));
+    let original: HashSet<Mdl> = canonical_nodes(egraph, start)
+        .into_iter()
+        .flatten()
+        .map(|(node, _)| node)
+        .collect();
+    let mut justification: HashMap<Mdl, usize> = HashMap::new();
+    for (k, applied) in log.iter().enumerate() {
+        for node in applied.produced.iter() {
+            justification.entry(canonical(node)).or_insert(k);
+        }
+    }
+    canonical_nodes(egraph, extracted)
+        .into_iter()
+        .map(|node| {
+            let mut steps = vec![];
+            let mut node = node.map(|(node, _)| node);
+            while let Some(current) = node.take() {
+                if original.contains(&current) {
+                    break;
+                }
+                match justification.get(&current) {
+                    Some(k) if !steps.contains(k) => {
+                        steps.push(*k);
+                        node = log[*k].source.as_ref().map(canonical);
+                    }
+                    _ => break,
+                }
+            }
+            steps.reverse();
+            steps
+        })
+        .collect()
+}
+
+/// Get the rules each node of the extracted graph was derived with
+///
+/// These are the rules of the substitutions on the derivation path of the node
+/// (see node_derivations), in order of first application.
+///
+/// # Returns
+///
+/// The rule names for each node of `extracted`, in the same order.
+pub fn node_provenance(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    start: &RecExpr<Mdl>,
+    extracted: &RecExpr<Mdl>,
+    log: &[AppliedRewrite],
+) -> Vec<Vec<String>> {
+    node_derivations(egraph, start, extracted, log)
+        .into_iter()
+        .map(|steps| {
+            let mut rules: Vec<String> = vec![];
+            for k in steps {
+                if !rules.contains(&log[k].rule) {
+                    rules.push(log[k].rule.clone());
+                }
+            }
+            rules
+        })
+        .collect()
+}
+
+/// Match the nodes of the extracted graph with identical nodes of the original
+///
+/// # Returns
+///
+/// For each node of `extracted`, the index in `original` of a node computing
+/// the same op on the same eclasses, or -1 if the node is new.
+pub fn node_origins(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    original: &RecExpr<Mdl>,
+    extracted: &RecExpr<Mdl>,
+) -> Vec<i32> {
+    let mut original_index: HashMap<Mdl, i32> = HashMap::new();
+    for (i, node) in canonical_nodes(egraph, original).into_iter().enumerate() {
+        if let Some((node, _)) = node {
+            original_index.entry(node).or_insert(i as i32);
+        }
+    }
+    canonical_nodes(egraph, extracted)
+        .into_iter()
+        .map(|node| {
+            node.and_then(|(node, _)| original_index.get(&node).copied())
+                .unwrap_or(-1)
+        })
+        .collect()
+}
+
+/// Inputs and constants of `original` that no node of the optimized graph
+/// comes from, given the origin of each optimized node (see node_origins)
+///
+/// # Returns
+///
+/// The block argument numbers of the unused inputs and the indices in
+/// `original` of the unused ConstantOps and DenseConstantOps (whose data the
+/// C++ side can then free), in increasing order.
+pub fn unused_leaves(original: &RecExpr<Mdl>, origins: &[i32]) -> (Vec<i32>, Vec<i32>) {
+    let used: HashSet<i32> = origins.iter().copied().collect();
+    let mut unused_inputs = vec![];
+    let mut unused_constants = vec![];
+    for (i, node) in original.as_ref().iter().enumerate() {
+        if used.contains(&(i as i32)) {
+            continue;
+        }
+        match node {
+            Mdl::Input([_, block_arg]) => {
+                if let Mdl::Num(n) = original[*block_arg] {
+                    unused_inputs.push(n as i32);
+                }
+            }
+            Mdl::ConstantOp(_) | Mdl::DenseConstantOp(_) => unused_constants.push(i as i32),
+            _ => (),
+        }
+    }
+    unused_inputs.sort_unstable();
+    (unused_inputs, unused_constants)
+}
+
+/// Estimated cost of the subgraph of `expr` rooted at `root`, counting shared
+/// nodes once. Nodes not in the e-graph are free
+fn subgraph_cost(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    expr: &RecExpr<Mdl>,
+    canonical: &[Option<(Mdl, Id)>],
+    root: usize,
+    cost_model: &CostModel,
+) -> f64 {
+    let mut visited = HashSet::new();
+    let mut stack = vec![root];
+    let mut cost = 0.0;
+    while let Some(i) = stack.pop() {
+        if !visited.insert(i) {
+            continue;
+        }
+        if let Some((node, _)) = &canonical[i] {
+            cost = add_costs(cost, cost_model.get_self_cost(egraph, node));
+        }
+        stack.extend(expr[Id::from(i)].children().iter().map(|c| usize::from(*c)));
+    }
+    cost
+}
+
+/// Remark for each node of the extracted graph, to attach to the op as an MLIR
+/// remark, e.g. "rewritten by rule12, rule40; estimated 1.3x faster"
+///
+/// The speedup compares the subgraph rooted at the node with the subgraph
+/// rooted at the node of the original graph in the same eclass, so it covers
+/// everything the rules replaced below the node. It is left out when the
+/// original graph has no node in the eclass. Nodes without provenance get an
+/// empty remark.
+///
+/// # Parameters
+///
+/// - `provenance`: rules of each node of `extracted`, see `node_provenance`
+pub fn node_remarks(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    start: &RecExpr<Mdl>,
+    extracted: &RecExpr<Mdl>,
+    provenance: &[Vec<String>],
+    cost_model: &CostModel,
+) -> Vec<String> {
+    let start_nodes = canonical_nodes(egraph, start);
+    let mut start_index: HashMap<Id, usize> = HashMap::new();
+    for (i, node) in start_nodes.iter().enumerate() {
+        if let Some((_, class)) = node {
+            start_index.entry(*class).or_insert(i);
+        }
+    }
+    let extracted_nodes = canonical_nodes(egraph, extracted);
+    extracted_nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| {
+            let rules = match provenance.get(i) {
+                Some(rules) if !rules.is_empty() => rules,
+                _ => return String::new(),
+            };
+            let mut remark = format!("rewritten by {}", rules.join(", "));
+            if let Some(&orig) = node.as_ref().and_then(|(_, class)| start_index.get(class)) {
+                let before = subgraph_cost(egraph, start, &start_nodes, orig, cost_model);
+                let after = subgraph_cost(egraph, extracted, &extracted_nodes, i, cost_model);
+                if before > 0.0 && after > 0.0 {
+                    remark += &format!("; estimated {:.1}x faster", before / after);
+                }
+            }
+            remark
+        })
+        .collect()
+}
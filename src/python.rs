@@ -0,0 +1,465 @@
+//! Python bindings, built with the `python` feature
+//!
+//! Mirrors the C++ interface: build a graph with the op constructors of
+//! `GraphConverter`, then `optimize` it. The `python` feature implies
+//! `no-cxx-costmodel`, so the module works without the Enzyme-JAX C++ sources.
+//! Build it with `maturin develop`, which turns on the `python` feature (see
+//! pyproject.toml) and builds the crate as a cdylib.
+
+use crate::config::OptimizeConfig;
+use crate::fx::import_fx;
+use crate::input::{ffi, graph_to_string, CppGraphConverter};
+use crate::metrics::prometheus_text;
+use crate::model::dtype_from_num;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+/// Result of an op, to pass as operand to later ops
+#[pyclass(name = "Tensor", unsendable)]
+#[derive(Clone)]
+pub struct PyTensor {
+    handle: ffi::TensorHandle,
+    shape: Vec<i32>,
+}
+
+#[pymethods]
+impl PyTensor {
+    /// Index of the tensor in the graph
+    #[getter]
+    fn id(&self) -> i32 {
+        self.handle.id
+    }
+
+    /// Shape of the (first) result
+    #[getter]
+    fn shape(&self) -> Vec<i32> {
+        self.shape.clone()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Tensor(id={}, shape={:?})", self.id(), self.shape())
+    }
+}
+
+/// The handles taken by the variadic constructors
+fn handles(tensors: &[PyRef<PyTensor>]) -> Vec<ffi::TensorHandle> {
+    tensors.iter().map(|t| t.handle).collect()
+}
+
+/// Settings of `optimize`, see OptimizeConfig
+#[pyclass(name = "Config")]
+#[derive(Clone)]
+pub struct PyConfig {
+    #[pyo3(get, set)]
+    rule_file: String,
+    #[pyo3(get, set)]
+    n_sec: u64,
+    #[pyo3(get, set)]
+    iter_limit: usize,
+    #[pyo3(get, set)]
+    node_limit: usize,
+    #[pyo3(get, set)]
+    use_multi: bool,
+    #[pyo3(get, set)]
+    max_matches: Option<usize>,
+    #[pyo3(get, set)]
+    max_match_ms: Option<u64>,
+    #[pyo3(get, set)]
+    identity_mode: bool,
+    #[pyo3(get, set)]
+    numeric_check: bool,
+    #[pyo3(get, set)]
+    numeric_tolerance: f32,
+    #[pyo3(get, set)]
+    check_against_greedy: bool,
+    #[pyo3(get, set)]
+    lp_file: Option<String>,
+    #[pyo3(get, set)]
+    profile_file: Option<String>,
+    #[pyo3(get, set)]
+    resume_from: Option<String>,
+    #[pyo3(get, set)]
+    save_state: Option<String>,
+}
+
+impl From<&OptimizeConfig> for PyConfig {
+    fn from(config: &OptimizeConfig) -> Self {
+        PyConfig {
+            rule_file: config.rule_file.clone(),
+            n_sec: config.n_sec,
+            iter_limit: config.iter_limit,
+            node_limit: config.node_limit,
+            use_multi: config.use_multi,
+            max_matches: config.max_matches,
+            max_match_ms: config.max_match_ms,
+            identity_mode: config.identity_mode,
+            numeric_check: config.numeric_check,
+            numeric_tolerance: config.numeric_tolerance,
+            check_against_greedy: config.check_against_greedy,
+            lp_file: config.lp_file.clone(),
+            profile_file: config.profile_file.clone(),
+            resume_from: config.resume_from.clone(),
+            save_state: config.save_state.clone(),
+        }
+    }
+}
+
+impl PyConfig {
+    fn apply(&self, config: &mut OptimizeConfig) {
+        config.rule_file = self.rule_file.clone();
+        config.n_sec = self.n_sec;
+        config.iter_limit = self.iter_limit;
+        config.node_limit = self.node_limit;
+        config.use_multi = self.use_multi;
+        config.max_matches = self.max_matches;
+        config.max_match_ms = self.max_match_ms;
+        config.identity_mode = self.identity_mode;
+        config.numeric_check = self.numeric_check;
+        config.numeric_tolerance = self.numeric_tolerance;
+        config.check_against_greedy = self.check_against_greedy;
+        config.lp_file = self.lp_file.clone();
+        config.profile_file = self.profile_file.clone();
+        config.resume_from = self.resume_from.clone();
+        config.save_state = self.save_state.clone();
+    }
+}
+
+#[pymethods]
+impl PyConfig {
+    #[new]
+    fn new() -> Self {
+        PyConfig::from(&OptimizeConfig::default())
+    }
+}
+
+/// Node of an optimized graph, see ffi::Node
+#[pyclass(name = "Node")]
+#[derive(Clone)]
+pub struct PyNode {
+    #[pyo3(get)]
+    name: String,
+    #[pyo3(get)]
+    label: String,
+    #[pyo3(get)]
+    operands: Vec<i32>,
+    #[pyo3(get)]
+    value: i64,
+    #[pyo3(get)]
+    shape: Vec<Vec<i64>>,
+    #[pyo3(get)]
+    block_arg: i32,
+    #[pyo3(get)]
+    origin: i32,
+    #[pyo3(get)]
+    provenance: Vec<String>,
+    #[pyo3(get)]
+    remark: String,
+    #[pyo3(get)]
+    sharding: String,
+}
+
+impl From<&ffi::Node> for PyNode {
+    fn from(node: &ffi::Node) -> Self {
+        PyNode {
+            name: node.name.clone(),
+            label: node.label.clone(),
+            operands: node.operands.clone(),
+            value: node.value,
+            shape: node.shape.iter().map(|s| s.shape.clone()).collect(),
+            block_arg: node.block_arg,
+            origin: node.origin,
+            provenance: node.provenance.clone(),
+            remark: node.remark.clone(),
+            sharding: node.sharding.clone(),
+        }
+    }
+}
+
+/// Optimized graph, see ffi::Graph
+#[pyclass(name = "Graph")]
+pub struct PyGraph {
+    #[pyo3(get)]
+    nodes: Vec<PyNode>,
+    #[pyo3(get)]
+    root: i32,
+    #[pyo3(get)]
+    results: Vec<i32>,
+    #[pyo3(get)]
+    schedule: Vec<i32>,
+    text: String,
+}
+
+#[pymethods]
+impl PyGraph {
+    fn __str__(&self) -> String {
+        self.text.clone()
+    }
+
+    fn __len__(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+impl From<ffi::Graph> for PyGraph {
+    fn from(graph: ffi::Graph) -> Self {
+        PyGraph {
+            nodes: graph.nodes.iter().map(PyNode::from).collect(),
+            root: graph.root,
+            results: graph.results.clone(),
+            schedule: graph.schedule.clone(),
+            text: graph_to_string(&graph),
+        }
+    }
+}
+
+/// Builds a graph op by op, see CppGraphConverter
+#[pyclass(name = "GraphConverter", unsendable)]
+pub struct PyGraphConverter {
+    converter: CppGraphConverter,
+}
+
+impl PyGraphConverter {
+    /// Adds an op with `build`, and wraps its result
+    fn op(&mut self, build: impl FnOnce(&mut CppGraphConverter) -> ffi::TensorHandle) -> PyTensor {
+        let handle = build(&mut self.converter);
+        PyTensor {
+            handle,
+            shape: self.converter.tensor_shape(handle),
+        }
+    }
+}
+
+#[pymethods]
+impl PyGraphConverter {
+    #[new]
+    fn new() -> Self {
+        PyGraphConverter {
+            converter: CppGraphConverter::default(),
+        }
+    }
+
+    /// Converter for an FX graph dumped to JSON, see fx::FxGraph
+    #[staticmethod]
+    fn from_fx(json: &str) -> PyResult<Self> {
+        let converter = import_fx(json).map_err(PyValueError::new_err)?;
+        Ok(PyGraphConverter { converter })
+    }
+
+    /// The current settings of `optimize`
+    #[getter]
+    fn config(&self) -> PyConfig {
+        PyConfig::from(self.converter.config())
+    }
+
+    #[setter]
+    fn set_config(&mut self, config: PyConfig) {
+        config.apply(self.converter.config_mut());
+    }
+
+    /// Replace the settings of `optimize` with the ones in a TOML file, see
+    /// OptimizeConfig::from_toml
+    fn load_config(&mut self, path: &str) -> PyResult<()> {
+        self.converter
+            .load_config_toml(path)
+            .map_err(PyValueError::new_err)
+    }
+
+    /// `dtype` is an ffi::Type discriminant: 0 for i32, 1 for f32, 2 for i1,
+    /// 3 for bf16 and 4 for c64
+    #[pyo3(signature = (block_arg_number, shape, dtype = 1))]
+    fn input(&mut self, block_arg_number: i32, shape: Vec<i32>, dtype: i32) -> PyTensor {
+        self.op(|c| c.new_input(block_arg_number, &shape, dtype_from_num(dtype.into())))
+    }
+
+    /// Input holding a weight, i.e. known at compile time
+    #[pyo3(signature = (block_arg_number, shape, dtype = 1))]
+    fn weight(&mut self, block_arg_number: i32, shape: Vec<i32>, dtype: i32) -> PyTensor {
+        self.op(|c| c.new_weight(block_arg_number, &shape, dtype_from_num(dtype.into())))
+    }
+
+    fn add(&mut self, lhs: &PyTensor, rhs: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_add_op(lhs.handle, rhs.handle, &shape))
+    }
+
+    fn subtract(&mut self, lhs: &PyTensor, rhs: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_subtract_op(lhs.handle, rhs.handle, &shape))
+    }
+
+    fn mul(&mut self, lhs: &PyTensor, rhs: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_mul_op(lhs.handle, rhs.handle, &shape))
+    }
+
+    fn div(&mut self, lhs: &PyTensor, rhs: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_div_op(lhs.handle, rhs.handle, &shape))
+    }
+
+    fn min(&mut self, lhs: &PyTensor, rhs: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_min_op(lhs.handle, rhs.handle, &shape))
+    }
+
+    fn max(&mut self, lhs: &PyTensor, rhs: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_max_op(lhs.handle, rhs.handle, &shape))
+    }
+
+    fn neg(&mut self, x: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_neg_op(x.handle, &shape))
+    }
+
+    fn tanh(&mut self, x: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_tanh_op(x.handle, &shape))
+    }
+
+    fn exp(&mut self, x: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_exp_op(x.handle, &shape))
+    }
+
+    fn erf(&mut self, x: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_erf_op(x.handle, &shape))
+    }
+
+    fn reshape(&mut self, x: &PyTensor, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_reshape_op(x.handle, &shape))
+    }
+
+    fn transpose(&mut self, x: &PyTensor, permutation: Vec<i64>, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_transpose_op(x.handle, &permutation, &shape))
+    }
+
+    fn broadcast_in_dim(
+        &mut self,
+        x: &PyTensor,
+        dimensions: Vec<i64>,
+        shape: Vec<i32>,
+    ) -> PyTensor {
+        self.op(|c| c.new_broadcast_in_dim(x.handle, &dimensions, &shape))
+    }
+
+    #[pyo3(signature = (
+        lhs,
+        rhs,
+        lhs_batching_dimensions,
+        rhs_batching_dimensions,
+        lhs_contracting_dimensions,
+        rhs_contracting_dimensions,
+        shape,
+        precision_config = vec![]
+    ))]
+    fn dot_general(
+        &mut self,
+        lhs: &PyTensor,
+        rhs: &PyTensor,
+        lhs_batching_dimensions: Vec<i64>,
+        rhs_batching_dimensions: Vec<i64>,
+        lhs_contracting_dimensions: Vec<i64>,
+        rhs_contracting_dimensions: Vec<i64>,
+        shape: Vec<i32>,
+        precision_config: Vec<i64>,
+    ) -> PyTensor {
+        self.op(|c| {
+            c.new_dot_general_op(
+                lhs.handle,
+                rhs.handle,
+                &lhs_batching_dimensions,
+                &rhs_batching_dimensions,
+                &lhs_contracting_dimensions,
+                &rhs_contracting_dimensions,
+                &precision_config,
+                &shape,
+            )
+        })
+    }
+
+    fn concatenate(
+        &mut self,
+        inputs: Vec<PyRef<PyTensor>>,
+        dimension: i32,
+        shape: Vec<i32>,
+    ) -> PyTensor {
+        self.op(|c| c.new_concatenate_op(&handles(&inputs), dimension, &shape))
+    }
+
+    fn slice(
+        &mut self,
+        x: &PyTensor,
+        start_indices: Vec<i64>,
+        limit_indices: Vec<i64>,
+        strides: Vec<i64>,
+        shape: Vec<i32>,
+    ) -> PyTensor {
+        self.op(|c| c.new_slice_op(x.handle, &start_indices, &limit_indices, &strides, &shape))
+    }
+
+    fn iota(&mut self, iota_dimension: i32, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_iota_op(iota_dimension, &shape))
+    }
+
+    fn splat_constant(&mut self, value: f32, shape: Vec<i32>) -> PyTensor {
+        self.op(|c| c.new_splat_constant_op(value, &shape))
+    }
+
+    /// Mark the values computed by the graph. Call last
+    fn return_op(&mut self, outputs: Vec<PyRef<PyTensor>>) -> PyTensor {
+        self.op(|c| c.new_return_op(&handles(&outputs)))
+    }
+
+    /// The graph built so far, as an s-expression
+    fn __str__(&self) -> String {
+        self.converter.rec_expr_ref().to_string()
+    }
+
+    /// Optimize the graph built so far
+    #[pyo3(signature = (config = None))]
+    fn optimize(&mut self, config: Option<PyConfig>) -> PyGraph {
+        if let Some(config) = config {
+            config.apply(self.converter.config_mut());
+        }
+        PyGraph::from(self.converter.optimize())
+    }
+
+    /// Optimize only the ops between `producers` and `consumers`. Returns the
+    /// optimized region, whose inputs stand for the tensors with the returned
+    /// ids (by block argument number)
+    fn optimize_region(
+        &self,
+        producers: Vec<PyRef<PyTensor>>,
+        consumers: Vec<PyRef<PyTensor>>,
+    ) -> (PyGraph, Vec<i32>) {
+        let result = self
+            .converter
+            .optimize_region(&handles(&producers), &handles(&consumers));
+        (PyGraph::from(result.graph), result.boundary)
+    }
+
+    /// Extract the graph built so far greedily from the e-graph of the last
+    /// `optimize` (or `extract_greedy`), saturating first if there is none.
+    /// Returns the graph and its estimated cost
+    fn extract_greedy(&self) -> (PyGraph, f64) {
+        let result = self.converter.extract_greedy();
+        (PyGraph::from(result.graph), result.cost)
+    }
+}
+
+/// Optimize the graph built by `converter`
+#[pyfunction]
+#[pyo3(signature = (converter, config = None))]
+fn optimize(mut converter: PyRefMut<PyGraphConverter>, config: Option<PyConfig>) -> PyGraph {
+    converter.optimize(config)
+}
+
+/// Metrics of the optimize calls so far in the Prometheus text format
+#[pyfunction]
+fn metrics_text() -> String {
+    prometheus_text()
+}
+
+#[pymodule]
+fn tensat(_py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyTensor>()?;
+    m.add_class::<PyConfig>()?;
+    m.add_class::<PyNode>()?;
+    m.add_class::<PyGraph>()?;
+    m.add_class::<PyGraphConverter>()?;
+    m.add_function(wrap_pyfunction!(optimize, m)?)?;
+    m.add_function(wrap_pyfunction!(metrics_text, m)?)?;
+    Ok(())
+}
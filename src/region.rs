@@ -0,0 +1,116 @@
+//! Cutting the region between cutpoints out of a graph
+//!
+//! To optimize part of a graph in place, the caller names producer tensors
+//! (where the region starts) and consumer tensors (whose values the region
+//! computes). The region is made of the ops that depend on a producer and that
+//! a consumer depends on. It is cut out as a graph of its own:
+//!
+//! - each producer becomes an Input, with its index as block argument number;
+//! - other tensors the region uses from outside of it (e.g. weights, or values
+//!   computed before the producers) also become Inputs, numbered after the
+//!   producers;
+//! - the consumers are returned, in order, by a ReturnOp.
+//!
+//! The Inputs of the region stand for the tensors of `Region::boundary`, so the
+//! optimized region can be spliced back in place of the original ops.
+
+use crate::model::*;
+use egg::*;
+use std::collections::{HashMap, HashSet};
+
+/// Region of a graph cut out by `cut_region`
+#[derive(Clone, Debug)]
+pub struct Region {
+    pub expr: RecExpr<Mdl>,
+    /// Id in the original graph of the tensor each Input of `expr` stands for,
+    /// by block argument number: the producers, then the other tensors used
+    /// from outside of the region
+    pub boundary: Vec<Id>,
+    /// Index in the original graph of each node of `expr`, None for the Inputs
+    /// (and their Var and Num) standing for the boundary, and for the ReturnOp
+    pub origins: Vec<Option<usize>>,
+}
+
+/// Cut the region between `producers` and `consumers` out of `expr`
+///
+/// # Parameters
+///
+/// - `expr`: the graph
+/// - `producers`, `consumers`: the cutpoints, as ids of nodes of `expr`
+/// - `boundary_var`: name (see input_var_name) of the Var of the Input standing
+///   for a tensor of the boundary, given its block argument number and its id
+pub fn cut_region(
+    expr: &RecExpr<Mdl>,
+    producers: &[Id],
+    consumers: &[Id],
+    boundary_var: impl Fn(usize, Id) -> String,
+) -> Region {
+    let nodes = expr.as_ref();
+    let is_producer: HashSet<Id> = producers.iter().copied().collect();
+    // Whether each node depends on a producer. Nodes come after their children
+    let mut depends = vec![false; nodes.len()];
+    for (i, node) in nodes.iter().enumerate() {
+        depends[i] = is_producer.contains(&Id::from(i))
+            || node.children().iter().any(|c| depends[usize::from(*c)]);
+    }
+    // Attributes are copied into the region with the ops using them, other
+    // nodes outside of it are only used through an Input
+    let in_boundary = |id: Id| {
+        let i = usize::from(id);
+        let attribute = matches!(nodes[i], Mdl::Num(_) | Mdl::Var(_) | Mdl::Vec(_));
+        is_producer.contains(&id) || !(depends[i] || attribute)
+    };
+
+    let mut used = vec![false; nodes.len()];
+    let mut stack: Vec<Id> = consumers.to_vec();
+    while let Some(id) = stack.pop() {
+        if std::mem::replace(&mut used[usize::from(id)], true) || in_boundary(id) {
+            continue;
+        }
+        stack.extend(nodes[usize::from(id)].children());
+    }
+
+    let mut boundary = producers.to_vec();
+    boundary.extend(
+        (0..nodes.len())
+            .map(Id::from)
+            .filter(|id| used[usize::from(*id)] && in_boundary(*id) && !is_producer.contains(id)),
+    );
+    let block_args: HashMap<Id, usize> = boundary
+        .iter()
+        .enumerate()
+        .map(|(k, id)| (*id, k))
+        .collect();
+
+    let mut region = RecExpr::default();
+    let mut origins = vec![];
+    let mut new_ids: HashMap<Id, Id> = HashMap::new();
+    let mut add = |region: &mut RecExpr<Mdl>, node: Mdl, origin: Option<usize>| {
+        origins.push(origin);
+        region.add(node)
+    };
+    for (i, node) in nodes.iter().enumerate() {
+        let id = Id::from(i);
+        if !used[i] {
+            continue;
+        }
+        let new_id = if in_boundary(id) {
+            let k = block_args[&id];
+            let var = add(&mut region, Mdl::Var(boundary_var(k, id).into()), None);
+            let num = add(&mut region, Mdl::Num(k as i64), None);
+            add(&mut region, Mdl::Input([var, num]), None)
+        } else {
+            let node = node.clone().map_children(|c| new_ids[&c]);
+            add(&mut region, node, Some(i))
+        };
+        new_ids.insert(id, new_id);
+    }
+    let results = consumers.iter().map(|c| new_ids[c]).collect();
+    let results = add(&mut region, Mdl::Vec(results), None);
+    add(&mut region, Mdl::ReturnOp([results]), None);
+    Region {
+        expr: region,
+        boundary,
+        origins,
+    }
+}
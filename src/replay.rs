@@ -0,0 +1,288 @@
+use crate::{interpreter::*, model::*, optimize::*, scheduler::*};
+use egg::*;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// One substitution that changed the e-graph during saturation
+#[derive(Clone, Debug)]
+pub struct AppliedRewrite {
+    /// Name of the rule applied
+    pub rule: String,
+    /// Iteration in which it was applied
+    pub iteration: usize,
+    /// EClass the source pattern matched
+    pub eclass: Id,
+    /// Node the source pattern matched at `eclass`, if the rule's patterns are
+    /// known
+    pub source: Option<Mdl>,
+    /// Nodes the substitution added to the e-graph
+    pub produced: Vec<Mdl>,
+}
+
+/// Scheduler that records every substitution that changes the e-graph
+///
+/// Searching is delegated to `inner`. Matches are applied one
+/// substitution at a time so each application can be logged, and applications
+/// stop once `limit` substitutions are recorded. With the same input and rules,
+/// a run limited to k substitutions applies exactly the first k substitutions of
+/// an unlimited run.
+///
+/// For rules whose patterns are known (see `with_patterns`), the matched node
+/// and the nodes of the instantiated right hand side that weren't in the
+/// e-graph are recorded. For other rules, the nodes the application added to
+/// the matched eclass are.
+pub struct RecordingScheduler {
+    inner: CappedScheduler,
+    log: Rc<RefCell<Vec<AppliedRewrite>>>,
+    limit: Option<usize>,
+    /// Left and right hand sides of the rules, by name
+    patterns: HashMap<String, (Pattern<Mdl>, Pattern<Mdl>)>,
+}
+
+impl RecordingScheduler {
+    pub fn new(
+        log: Rc<RefCell<Vec<AppliedRewrite>>>,
+        limit: Option<usize>,
+        inner: CappedScheduler,
+    ) -> Self {
+        RecordingScheduler {
+            inner,
+            log,
+            limit,
+            patterns: HashMap::new(),
+        }
+    }
+
+    /// Set the left and right hand sides of the rules
+    pub fn with_patterns(
+        mut self,
+        patterns: HashMap<String, (Pattern<Mdl>, Pattern<Mdl>)>,
+    ) -> Self {
+        self.patterns = patterns;
+        self
+    }
+}
+
+/// Canonical enode of each node of `pat` instantiated with `subst`, None for
+/// variables and nodes not in the e-graph
+fn pattern_nodes(
+    pat: &[ENodeOrVar<Mdl>],
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    subst: &Subst,
+) -> Vec<Option<Mdl>> {
+    let mut classes: Vec<Option<Id>> = Vec::with_capacity(pat.len());
+    let mut nodes = Vec::with_capacity(pat.len());
+    for node in pat {
+        match node {
+            ENodeOrVar::Var(var) => {
+                classes.push(Some(egraph.find(subst[*var])));
+                nodes.push(None);
+            }
+            ENodeOrVar::ENode(node) => {
+                let mut complete = true;
+                let node = node
+                    .clone()
+                    .map_children(|c| match classes[usize::from(c)] {
+                        Some(class) => class,
+                        None => {
+                            complete = false;
+                            c
+                        }
+                    });
+                let class = if complete {
+                    egraph.lookup(node.clone()).map(|class| egraph.find(class))
+                } else {
+                    None
+                };
+                classes.push(class);
+                nodes.push(class.map(|_| node));
+            }
+        }
+    }
+    nodes
+}
+
+impl RewriteScheduler<Mdl, TensorAnalysis> for RecordingScheduler {
+    fn can_stop(&mut self, iteration: usize) -> bool {
+        self.inner.can_stop(iteration)
+    }
+
+    fn search_rewrite(
+        &mut self,
+        iteration: usize,
+        egraph: &EGraph<Mdl, TensorAnalysis>,
+        rewrite: &Rewrite<Mdl, TensorAnalysis>,
+    ) -> Vec<SearchMatches> {
+        self.inner.search_rewrite(iteration, egraph, rewrite)
+    }
+
+    fn apply_rewrite(
+        &mut self,
+        iteration: usize,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        rewrite: &Rewrite<Mdl, TensorAnalysis>,
+        matches: Vec<SearchMatches>,
+    ) -> usize {
+        let mut n_applied = 0;
+        for m in matches {
+            for subst in m.substs {
+                if let Some(limit) = self.limit {
+                    if self.log.borrow().len() >= limit {
+                        return n_applied;
+                    }
+                }
+                let patterns = self.patterns.get(rewrite.name());
+                let (source, existing) = match patterns {
+                    Some((lhs, rhs)) => {
+                        let source = pattern_nodes(lhs.ast.as_ref(), egraph, &subst).pop();
+                        let existing: HashSet<Mdl> =
+                            pattern_nodes(rhs.ast.as_ref(), egraph, &subst)
+                                .into_iter()
+                                .flatten()
+                                .collect();
+                        (source.flatten(), existing)
+                    }
+                    None => (None, egraph[m.eclass].iter().cloned().collect()),
+                };
+                let single = SearchMatches {
+                    eclass: m.eclass,
+                    substs: vec![subst.clone()],
+                };
+                let applied = self
+                    .inner
+                    .apply_rewrite(iteration, egraph, rewrite, vec![single]);
+                if applied > 0 {
+                    n_applied += applied;
+                    let produced: Vec<Mdl> = match patterns {
+                        Some((_, rhs)) => pattern_nodes(rhs.ast.as_ref(), egraph, &subst)
+                            .into_iter()
+                            .flatten()
+                            .collect(),
+                        None => egraph[m.eclass].iter().cloned().collect(),
+                    };
+                    self.log.borrow_mut().push(AppliedRewrite {
+                        rule: rewrite.name().to_string(),
+                        iteration,
+                        eclass: m.eclass,
+                        source,
+                        produced: produced
+                            .into_iter()
+                            .filter(|node| !existing.contains(node))
+                            .collect(),
+                    });
+                }
+            }
+        }
+        n_applied
+    }
+}
+
+/// Get the set of eclasses reachable from root
+fn reachable_classes(egraph: &EGraph<Mdl, TensorAnalysis>, root: Id) -> HashSet<Id> {
+    let mut visited = HashSet::new();
+    let mut stack = vec![egraph.find(root)];
+    while let Some(id) = stack.pop() {
+        if visited.insert(id) {
+            for node in egraph[id].iter() {
+                for child in node.children().iter() {
+                    stack.push(egraph.find(*child));
+                }
+            }
+        }
+    }
+    visited
+}
+
+/// Find the first substitution that changes the outputs of the graph
+///
+/// Runs saturation once recording every substitution, keeps the ones applied to
+/// the root-reachable region, and bisects over them: for a prefix of k
+/// substitutions, saturation is re-run with only those applied, the result is
+/// extracted greedily and evaluated with the reference interpreter on random
+/// inputs. This assumes saturation is deterministic and that once outputs
+/// diverge they stay diverged.
+///
+/// # Parameters
+///
+/// - `start`: the original graph
+/// - `new_runner`: builds a fresh runner on (a canonical form of) `start` with the
+///   optimization settings. It shouldn't have a time limit, so all runs stop
+///   at the same iteration
+/// - `new_scheduler`: builds the scheduler used for searching
+/// - `new_analysis`: builds the analysis used by the interpreter for shapes
+/// - `rules`: rules used for saturation
+/// - `cost_model`: cost model for extraction
+/// - `tolerance`: largest elementwise difference accepted
+/// - `seed`: seed for the random inputs
+///
+/// # Returns
+///
+/// The index (in application order) and description of the first substitution
+/// after which the outputs diverge, or None if the final graph matches.
+pub fn bisect_substitutions(
+    start: &RecExpr<Mdl>,
+    new_runner: impl Fn() -> Runner<Mdl, TensorAnalysis, ()>,
+    new_scheduler: impl Fn() -> CappedScheduler,
+    new_analysis: impl Fn() -> TensorAnalysis,
+    rules: &[Rewrite<Mdl, TensorAnalysis>],
+    cost_model: &CostModel,
+    tolerance: f32,
+    seed: u64,
+) -> Result<Option<(usize, AppliedRewrite)>, String> {
+    let inputs = random_inputs(start, seed)?;
+    let expected = evaluate(start, new_analysis(), &inputs)?;
+
+    // Runs saturation applying at most `limit` substitutions and reports whether the
+    // extracted graph still computes the expected outputs
+    let run = |limit: Option<usize>| -> Result<(bool, Vec<AppliedRewrite>, Vec<usize>), String> {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        // Each run has its own e-graph, so costs memoized for the last one don't apply
+        cost_model.clear_memo();
+        let runner = new_runner()
+            .with_scheduler(RecordingScheduler::new(log.clone(), limit, new_scheduler()))
+            .run(rules);
+        let root = runner.roots[0];
+        let tnsr_cost = TensorCost {
+            egraph: &runner.egraph,
+            cost_model,
+        };
+        let mut extractor = Extractor::new(&runner.egraph, tnsr_cost);
+        let (_, best) = extractor.find_best(root);
+        let actual = evaluate(&best, new_analysis(), &inputs)?;
+        let reachable = reachable_classes(&runner.egraph, root);
+        let log = log.borrow().clone();
+        let candidates = log
+            .iter()
+            .enumerate()
+            .filter(|(_, applied)| reachable.contains(&runner.egraph.find(applied.eclass)))
+            .map(|(i, _)| i)
+            .collect();
+        Ok((outputs_match(&expected, &actual, tolerance), log, candidates))
+    };
+
+    // Indices of the substitutions applied to the root-reachable region
+    let (full_matches, log, candidates) = run(None)?;
+    println!("Recorded {} substitutions", log.len());
+    if full_matches {
+        return Ok(None);
+    }
+
+    // Find the first candidate c such that the prefix up to and including c diverges
+    let (mut lo, mut hi) = (0, candidates.len());
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        let (matches, _, _) = run(Some(candidates[mid] + 1))?;
+        if matches {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+
+    if lo == candidates.len() {
+        return Err("Outputs diverge, but no single substitution could be blamed".to_string());
+    }
+    let index = candidates[lo];
+    Ok(Some((index, log[index].clone())))
+}
@@ -1,4 +1,7 @@
+use crate::input::ffi;
 use crate::model::*;
+use crate::precision::{dot_precisions, join_precision};
+use crate::sharding::breaks_sharding;
 use egg::{rewrite as rw, *};
 use itertools::Itertools;
 use std::collections::{HashMap, HashSet};
@@ -102,9 +105,97 @@ pub fn rules<A: Analysis<Mdl>>() -> Vec<Rewrite<Mdl, A>> { vec![
         rw!("-concatenation-and-pooling-2"     ;"(poolmax ?kx ?ky ?sx ?sy ?p (concat 1 ?x ?y))"                                     => "(concat 1 (poolmax ?kx ?ky ?sx ?sy ?p ?x) (poolmax ?kx ?ky ?sx ?sy ?p ?y)) "               ),
 ]}
 
+/// Cost class of a rule, each class gets its own budget per iteration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RuleClass {
+    /// Local algebraic rules that keep the graph canonical (`class=cheap`)
+    Cheap,
+    /// Rules exploring new graphs, e.g. multi-pattern rules or rules with a
+    /// large right hand side (`class=expensive`)
+    Expensive,
+}
+
+impl Default for RuleClass {
+    fn default() -> Self {
+        RuleClass::Expensive
+    }
+}
+
+/// Options that can follow a rule in a rule file, as `lhs=>rhs;key=value;...`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RuleOptions {
+    /// Max number of matches applied per iteration (`max_matches=N`)
+    pub max_matches: Option<usize>,
+    /// Cost class of the rule (`class=cheap` or `class=expensive`)
+    pub class: RuleClass,
+    /// Max time in milliseconds the rule may spend e-matching per iteration
+    /// (`max_match_ms=N`)
+    pub max_match_ms: Option<u64>,
+}
+
+/// Split a rule line into the rule itself and its options
+pub fn parse_rule_line(line: &str) -> (&str, RuleOptions) {
+    let mut parts = line.split(';');
+    let rule = parts.next().unwrap();
+    let mut options = RuleOptions::default();
+    for option in parts.map(|x| x.trim()).filter(|x| !x.is_empty()) {
+        match option.split_once('=') {
+            Some(("max_matches", n)) => {
+                options.max_matches = Some(n.trim().parse().expect("max_matches is not a number"))
+            }
+            Some(("max_match_ms", ms)) => {
+                options.max_match_ms =
+                    Some(ms.trim().parse().expect("max_match_ms is not a number"))
+            }
+            Some(("class", class)) => {
+                options.class = match class.trim() {
+                    "cheap" => RuleClass::Cheap,
+                    "expensive" => RuleClass::Expensive,
+                    _ => panic!("Unknown rule class {}", class),
+                }
+            }
+            _ => panic!("Unknown rule option {}", option),
+        }
+    }
+    (rule, options)
+}
+
+/// Get the options of the rules, keyed by the names `rules_from_str` gives the
+/// rules
+pub fn rule_options_from_str(rs: &[&str]) -> HashMap<String, RuleOptions> {
+    rs.iter()
+        .enumerate()
+        .map(|(pos, rule)| (format!("rule{}", pos), parse_rule_line(rule).1))
+        .collect()
+}
+
+/// Get the left hand sides of the rules, keyed by the names `rules_from_str`
+/// gives the rules
+pub fn rule_patterns_from_str(rs: &[&str]) -> HashMap<String, Pattern<Mdl>> {
+    rule_sides_from_str(rs)
+        .into_iter()
+        .map(|(name, (lhs, _))| (name, lhs))
+        .collect()
+}
+
+/// Get the left and right hand sides of the rules, keyed by the names
+/// `rules_from_str` gives the rules
+pub fn rule_sides_from_str(rs: &[&str]) -> HashMap<String, (Pattern<Mdl>, Pattern<Mdl>)> {
+    rs.iter()
+        .enumerate()
+        .map(|(pos, rule)| {
+            let (rule, _) = parse_rule_line(rule);
+            let eqn: Vec<&str> = rule.split("=>").collect();
+            let sides = (eqn[0].parse().unwrap(), eqn[1].parse().unwrap());
+            (format!("rule{}", pos), sides)
+        })
+        .collect()
+}
+
 pub fn rules_from_str(rs: Vec<&str>, filter_after: bool) -> Vec<Rewrite<Mdl, TensorAnalysis>> {
     let mut rule_vec = Vec::new();
     for (pos, rule) in rs.iter().enumerate() {
+        let (rule, _) = parse_rule_line(rule);
         let eqn: Vec<&str> = rule.split("=>").collect();
         let lhs: Pattern<Mdl> = eqn[0].parse().unwrap();
         let rhs: Pattern<Mdl> = eqn[1].parse().unwrap();
@@ -145,14 +236,19 @@ pub static PRE_DEFINED_MULTI: &[&str] = &[
 pub fn get_vec_of_nums(
     egraph: &EGraph<Mdl, TensorAnalysis>,
     eclass: &EClass<Mdl, TensorData>,
-) -> Vec<i32> {
+) -> Vec<i64> {
     let vec = get_vec(eclass);
     let mut result = Vec::new();
 
     for id in vec.iter() {
-        let num_eclass = &egraph[*id];
-        let num = get_num(&num_eclass);
-        result.push(*num);
+        match get_num_option(&egraph[*id]) {
+            Some(num) => result.push(num),
+            None => panic!(
+                "Expected a Vec of Nums, but element {} is {}: is this a list of tensors?",
+                id,
+                egraph[*id].nodes[0].display_op()
+            ),
+        }
     }
 
     result
@@ -161,12 +257,12 @@ pub fn get_vec_of_nums(
 pub fn get_vec_of_nums_option(
     egraph: &EGraph<Mdl, TensorAnalysis>,
     eclass: &EClass<Mdl, TensorData>,
-) -> Option<Vec<i32>> {
+) -> Option<Vec<i64>> {
     get_vec_option(eclass)
         .map(|vec| {
             vec.iter()
                 .map(|&id| get_num_option(&egraph[id]))
-                .collect::<Option<Vec<i32>>>()
+                .collect::<Option<Vec<i64>>>()
         })
         .flatten()
 }
@@ -193,7 +289,7 @@ pub fn get_vec(eclass: &EClass<Mdl, TensorData>) -> &Vec<Id> {
     panic!("no vec found");
 }
 
-pub fn get_num_option(eclass: &EClass<Mdl, TensorData>) -> Option<i32> {
+pub fn get_num_option(eclass: &EClass<Mdl, TensorData>) -> Option<i64> {
     for node in eclass.iter() {
         match node {
             Mdl::Num(n) => return Some(*n),
@@ -204,7 +300,7 @@ pub fn get_num_option(eclass: &EClass<Mdl, TensorData>) -> Option<i32> {
     return None;
 }
 
-pub fn get_num(eclass: &EClass<Mdl, TensorData>) -> &i32 {
+pub fn get_num(eclass: &EClass<Mdl, TensorData>) -> &i64 {
     for node in eclass.iter() {
         match node {
             Mdl::Num(n) => return n,
@@ -215,11 +311,11 @@ pub fn get_num(eclass: &EClass<Mdl, TensorData>) -> &i32 {
     panic!("no num found");
 }
 
-fn make_num(egraph: &mut EGraph<Mdl, TensorAnalysis>, num: i32) -> Id {
+pub(crate) fn make_num(egraph: &mut EGraph<Mdl, TensorAnalysis>, num: i64) -> Id {
     egraph.add(Mdl::Num(num))
 }
 
-fn make_vec(egraph: &mut EGraph<Mdl, TensorAnalysis>, seq: &[Id]) -> Id {
+pub(crate) fn make_vec(egraph: &mut EGraph<Mdl, TensorAnalysis>, seq: &[Id]) -> Id {
     egraph.add(Mdl::Vec((*seq).to_vec()))
 }
 
@@ -242,7 +338,11 @@ pub fn decreasing_perm<'a>(
     }
 }
 
-fn finish_apply(egraph: &mut EGraph<Mdl, TensorAnalysis>, matched_id: Id, new_id: Id) -> Vec<Id> {
+pub(crate) fn finish_apply(
+    egraph: &mut EGraph<Mdl, TensorAnalysis>,
+    matched_id: Id,
+    new_id: Id,
+) -> Vec<Id> {
     if egraph.union(matched_id, new_id).1 {
         vec![new_id]
     } else {
@@ -250,6 +350,85 @@ fn finish_apply(egraph: &mut EGraph<Mdl, TensorAnalysis>, matched_id: Id, new_id
     }
 }
 
+/// Dimensions of the result of a PadOp on an operand with dimensions `dims`
+///
+/// As in StableHLO, edge padding can be negative (removing elements from that
+/// edge), interior padding can't. Unknown dimensions (-1) stay unknown. Fails
+/// if the padding has the wrong rank, the interior padding is negative or the
+/// edge padding removes more than the whole dimension.
+pub fn padded_dims(
+    dims: &[i64],
+    low: &[i64],
+    high: &[i64],
+    interior: &[i64],
+) -> Result<Vec<i64>, String> {
+    if low.len() != dims.len() || high.len() != dims.len() || interior.len() != dims.len() {
+        return Err(format!(
+            "padding of rank {}, {}, {} for an operand of rank {}",
+            low.len(),
+            high.len(),
+            interior.len(),
+            dims.len()
+        ));
+    }
+    (0..dims.len())
+        .map(|i| {
+            if interior[i] < 0 {
+                return Err(format!("negative interior padding {}", interior[i]));
+            }
+            if dims[i] < 0 {
+                return Ok(-1);
+            }
+            let padded = low[i] + high[i] + dims[i] + (dims[i] - 1).max(0) * interior[i];
+            if padded < 0 {
+                return Err(format!("edge padding removes {} elements", -padded));
+            }
+            Ok(padded)
+        })
+        .collect()
+}
+
+/// Condition that the SliceOp with `start`, `limit` and `strides` on a PadOp of
+/// `x` with `low` and `interior` padding takes exactly the elements of `x`,
+/// i.e. removes the padding
+pub fn slice_removes_padding(
+    x: &'static str,
+    low: &'static str,
+    interior: &'static str,
+    start: &'static str,
+    limit: &'static str,
+    strides: &'static str,
+) -> impl Fn(&mut EGraph<Mdl, TensorAnalysis>, Id, &Subst) -> bool {
+    let x: Var = x.parse().unwrap();
+    let vars: Vec<Var> = [low, interior, start, limit, strides]
+        .iter()
+        .map(|v| v.parse().unwrap())
+        .collect();
+
+    move |egraph, _, subst| {
+        let data = &egraph[subst[x]].data;
+        let dims = &data.shapes[0][..data.n_dims[0]];
+        let nums: Vec<Vec<i64>> = vars
+            .iter()
+            .map(|v| get_vec_of_nums(egraph, &egraph[subst[*v]]))
+            .collect();
+        if nums.iter().any(|v| v.len() != dims.len()) {
+            return false;
+        }
+        let (low, interior, start, limit, strides) =
+            (&nums[0], &nums[1], &nums[2], &nums[3], &nums[4]);
+        (0..dims.len()).all(|i| {
+            // The slice takes start, start + stride, ... up to limit; the
+            // elements of x are at low, low + interior + 1, ...
+            dims[i] >= 0
+                && start[i] == low[i]
+                && strides[i] > 0
+                && strides[i] == interior[i] + 1
+                && (limit[i] - start[i] + strides[i] - 1) / strides[i] == i64::from(dims[i])
+        })
+    }
+}
+
 pub fn concat_dot_compatible(
     lc: &'static str,
     d1: &'static str,
@@ -364,8 +543,8 @@ impl Applier<Mdl, TensorAnalysis> for MergeSlices {
         // limiting indices should be the same. The concat dim, we need to do
         // a bit of maths to ensure they are "contiguous" wrt the stride.
 
-        let mut new_starting: Vec<i32> = vec![];
-        let mut new_limiting: Vec<i32> = vec![];
+        let mut new_starting: Vec<i64> = vec![];
+        let mut new_limiting: Vec<i64> = vec![];
 
         for i in 0..n {
             let s1 = *get_num(&egraph[starting_1[i]]);
@@ -391,7 +570,7 @@ impl Applier<Mdl, TensorAnalysis> for MergeSlices {
                 // x     x    [x]
                 // So s2 = 7 for the two slices to be contiguous.
 
-                let numbers_picked = (l1 - s1) / stride + i32::from((l1 - s1) % stride != 0); // ceil
+                let numbers_picked = (l1 - s1) / stride + i64::from((l1 - s1) % stride != 0); // ceil
                 let next_unchosen_index = s1 + numbers_picked * stride;
                 if next_unchosen_index != s2 {
                     return vec![];
@@ -415,6 +594,524 @@ impl Applier<Mdl, TensorAnalysis> for MergeSlices {
     }
 }
 
+/// Value of a splat ConstantOp in the eclass, or of a DenseConstantOp whose
+/// values are all the same (and exactly an f32), if there is one
+pub fn get_splat_value_option(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    eclass: &EClass<Mdl, TensorData>,
+) -> Option<f32> {
+    for node in eclass.iter() {
+        match node {
+            Mdl::ConstantOp([value, _]) => {
+                return get_num_option(&egraph[*value]).map(|bits| f32::from_bits(bits as u32))
+            }
+            Mdl::DenseConstantOp([handle, ..]) => {
+                let values = get_num_option(&egraph[*handle])
+                    .and_then(|handle| egraph.analysis.dense_values(handle));
+                match values.and_then(splat_value) {
+                    Some(value) if f64::from(value as f32) == value => return Some(value as f32),
+                    _ => {}
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// The value of all of `values`, if they are all the same (and not NaN)
+fn splat_value(values: &[f64]) -> Option<f64> {
+    let first = *values.first()?;
+    if values.iter().all(|v| *v == first) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+/// Condition that each variable is bound to a splat constant with (approximately)
+/// the given value
+pub fn splat_constants_are(
+    expected: &'static [(&'static str, f32)],
+) -> impl Fn(&mut EGraph<Mdl, TensorAnalysis>, Id, &Subst) -> bool {
+    let expected: Vec<(Var, f32)> = expected
+        .iter()
+        .map(|(var, value)| (var.parse().unwrap(), *value))
+        .collect();
+    move |egraph, _, subst| {
+        expected.iter().all(|(var, value)| {
+            match get_splat_value_option(egraph, &egraph[subst[*var]]) {
+                Some(v) => (v - value).abs() <= 1e-4 * value.abs().max(1.0),
+                None => false,
+            }
+        })
+    }
+}
+
+const GELU_HALF: f32 = 0.5;
+const GELU_ONE: f32 = 1.0;
+// 1 / sqrt(2)
+const GELU_ERF_SCALE: f32 = 0.70710678;
+// sqrt(2 / pi)
+const GELU_TANH_SCALE: f32 = 0.79788456;
+const GELU_TANH_CUBIC: f32 = 0.044715;
+
+static GELU_ERF_PATTERN: &str = "(MulOp (MulOp ?x (ConstantOp ?half ?s)) (AddOp (ConstantOp ?one ?s) (ErfOp (MulOp ?x (ConstantOp ?scale ?s)))))";
+static GELU_TANH_PATTERN: &str = "(MulOp (MulOp ?x (ConstantOp ?half ?s)) (AddOp (ConstantOp ?one ?s) (TanhOp (MulOp (ConstantOp ?scale ?s) (AddOp ?x (MulOp (ConstantOp ?cubic ?s) (MulOp ?x (MulOp ?x ?x))))))))";
+
+/// Rules recognizing the erf- and tanh-based GELU formulas and folding them into
+/// a GeluOp node. The expansions go the other way, so a GeluOp coming from the
+/// frontend can still be fused with surrounding elementwise ops.
+pub fn gelu_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    let erf_pattern: Pattern<Mdl> = GELU_ERF_PATTERN.parse().unwrap();
+    let tanh_pattern: Pattern<Mdl> = GELU_TANH_PATTERN.parse().unwrap();
+    vec![
+        rewrite!("gelu-erf-recognize"; { erf_pattern } => "(GeluOp ?x 0)"
+                 if splat_constants_are(&[("?half", GELU_HALF), ("?one", GELU_ONE), ("?scale", GELU_ERF_SCALE)])),
+        rewrite!("gelu-tanh-recognize"; { tanh_pattern } => "(GeluOp ?x 1)"
+                 if splat_constants_are(&[("?half", GELU_HALF), ("?one", GELU_ONE), ("?scale", GELU_TANH_SCALE), ("?cubic", GELU_TANH_CUBIC)])),
+        rewrite!("gelu-erf-expand"; "(GeluOp ?x 0)" => { ExpandGelu {
+                 x: "?x".parse().unwrap(),
+                 approximate: false,
+        }}),
+        rewrite!("gelu-tanh-expand"; "(GeluOp ?x 1)" => { ExpandGelu {
+                 x: "?x".parse().unwrap(),
+                 approximate: true,
+        }}),
+    ]
+}
+
+/// Rules switching between the exact (erf) and approximate (tanh) GELU. These are
+/// not exact rewrites, so optimize only uses them with
+/// `OptimizeConfig::approximate_gelu`.
+pub fn gelu_approximation_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    vec![
+        rewrite!("gelu-erf-to-tanh"; "(GeluOp ?x 0)" => "(GeluOp ?x 1)"),
+        rewrite!("gelu-tanh-to-erf"; "(GeluOp ?x 1)" => "(GeluOp ?x 0)"),
+    ]
+}
+
+/// Rules simplifying ClampOps: nested clamps, and clamps with an infinite
+/// bound, which only bound the other side
+pub fn clamp_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    vec![
+        rewrite!("clamp-of-clamp";
+                 "(ClampOp ?lo (ClampOp ?lo ?x ?hi) ?hi)" => "(ClampOp ?lo ?x ?hi)"),
+        rewrite!("clamp-of-clamp-intersect";
+                 "(ClampOp ?lo2 (ClampOp ?lo1 ?x ?hi1) ?hi2)" => "(ClampOp (MaxOp ?lo1 ?lo2) ?x (MinOp ?hi1 ?hi2))"
+                 if clamp_ranges_overlap("?lo1", "?hi1", "?lo2", "?hi2")),
+        rewrite!("clamp-unbounded-below";
+                 "(ClampOp ?lo ?x ?hi)" => "(MinOp ?x ?hi)"
+                 if clamp_bound_is("?lo", f32::NEG_INFINITY, "?x", "?hi")),
+        rewrite!("clamp-unbounded-above";
+                 "(ClampOp ?lo ?x ?hi)" => "(MaxOp ?x ?lo)"
+                 if clamp_bound_is("?hi", f32::INFINITY, "?x", "?lo")),
+    ]
+}
+
+fn same_shape(a: &TensorData, b: &TensorData) -> bool {
+    a.shapes[0][..a.n_dims[0]] == b.shapes[0][..b.n_dims[0]]
+}
+
+/// Condition that `bound` is a splat constant of exactly `value` (unlike
+/// splat_constants_are, this works for infinities), and `x` has the shape of
+/// the other bound `other`, so they can be operands of a MinOp or MaxOp
+pub fn clamp_bound_is(
+    bound: &'static str,
+    value: f32,
+    x: &'static str,
+    other: &'static str,
+) -> impl Fn(&mut EGraph<Mdl, TensorAnalysis>, Id, &Subst) -> bool {
+    let bound: Var = bound.parse().unwrap();
+    let x: Var = x.parse().unwrap();
+    let other: Var = other.parse().unwrap();
+    move |egraph, _, subst| {
+        get_splat_value_option(egraph, &egraph[subst[bound]]) == Some(value)
+            && same_shape(&egraph[subst[x]].data, &egraph[subst[other]].data)
+    }
+}
+
+/// Condition that the bounds of nested clamps are splat constants whose ranges
+/// overlap, so clamping to their intersection is the same, and the lower (and
+/// upper) bounds have the same shape
+pub fn clamp_ranges_overlap(
+    lo1: &'static str,
+    hi1: &'static str,
+    lo2: &'static str,
+    hi2: &'static str,
+) -> impl Fn(&mut EGraph<Mdl, TensorAnalysis>, Id, &Subst) -> bool {
+    let vars: Vec<Var> = [lo1, hi1, lo2, hi2]
+        .iter()
+        .map(|v| v.parse().unwrap())
+        .collect();
+    move |egraph, _, subst| {
+        let data = |k: usize| &egraph[subst[vars[k]]].data;
+        if !same_shape(data(0), data(2)) || !same_shape(data(1), data(3)) {
+            return false;
+        }
+        let bounds: Option<Vec<f32>> = vars
+            .iter()
+            .map(|v| get_splat_value_option(egraph, &egraph[subst[*v]]))
+            .collect();
+        match bounds.as_deref() {
+            Some([lo1, hi1, lo2, hi2]) => {
+                lo1 <= hi1 && lo2 <= hi2 && lo1.max(*lo2) <= hi1.min(*hi2)
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Rules between the unary ops: inverse functions, rsqrt as the reciprocal of
+/// sqrt, the symmetries of sine, cosine and abs, squares as PowOps, and
+/// idempotent ops (abs, sign and rounding) applied twice
+pub fn unary_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    vec![
+        rewrite!("log-of-exp"; "(LogOp (ExpOp ?x))" => "?x"),
+        rewrite!("rsqrt-recognize"; "(DivOp ?one (SqrtOp ?x))" => "(RsqrtOp ?x)"
+                 if splat_constants_are(&[("?one", 1.0)])),
+        rewrite!("div-sqrt-to-mul-rsqrt"; "(DivOp ?y (SqrtOp ?x))" => "(MulOp ?y (RsqrtOp ?x))"),
+        rewrite!("mul-rsqrt-to-div-sqrt"; "(MulOp ?y (RsqrtOp ?x))" => "(DivOp ?y (SqrtOp ?x))"),
+        rewrite!("sine-of-neg"; "(SineOp (NegOp ?x))" => "(NegOp (SineOp ?x))"),
+        rewrite!("neg-of-sine"; "(NegOp (SineOp ?x))" => "(SineOp (NegOp ?x))"),
+        rewrite!("cosine-of-neg"; "(CosineOp (NegOp ?x))" => "(CosineOp ?x)"),
+        rewrite!("pow-two-to-mul"; "(PowOp ?x ?two)" => "(MulOp ?x ?x)"
+                 if splat_constants_are(&[("?two", 2.0)])),
+        rewrite!("abs-of-neg"; "(AbsOp (NegOp ?x))" => "(AbsOp ?x)"),
+        rewrite!("abs-of-abs"; "(AbsOp (AbsOp ?x))" => "(AbsOp ?x)"),
+        rewrite!("sign-of-sign"; "(SignOp (SignOp ?x))" => "(SignOp ?x)"),
+        rewrite!("sign-times-abs"; "(MulOp (SignOp ?x) (AbsOp ?x))" => "?x"),
+        rewrite!("floor-of-floor"; "(FloorOp (FloorOp ?x))" => "(FloorOp ?x)"),
+        rewrite!("ceil-of-ceil"; "(CeilOp (CeilOp ?x))" => "(CeilOp ?x)"),
+        rewrite!("round-of-round"; "(RoundOp (RoundOp ?x))" => "(RoundOp ?x)"),
+        // Integers stay the same under any rounding
+        rewrite!("floor-of-ceil"; "(FloorOp (CeilOp ?x))" => "(CeilOp ?x)"),
+        rewrite!("ceil-of-floor"; "(CeilOp (FloorOp ?x))" => "(FloorOp ?x)"),
+        rewrite!("round-of-floor"; "(RoundOp (FloorOp ?x))" => "(FloorOp ?x)"),
+        rewrite!("round-of-ceil"; "(RoundOp (CeilOp ?x))" => "(CeilOp ?x)"),
+    ]
+}
+
+/// Rules simplifying RemainderOps: a remainder by the same divisor twice, and
+/// integer remainders by ±1, which are 0
+pub fn remainder_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    vec![
+        rewrite!("remainder-of-remainder";
+                 "(RemainderOp (RemainderOp ?x ?y) ?y)" => "(RemainderOp ?x ?y)"),
+        // Splat constants are f32, so integer ones are converted
+        rewrite!("integer-remainder-by-one";
+                 "(RemainderOp ?x (ConvertOp (ConstantOp ?v ?s) ?t))" => { IntegerZero {
+                 s: "?s".parse().unwrap(),
+        }} if integer_divided_by_one("?x", "?v", "?t")),
+        rewrite!("integer-remainder-by-dense-one";
+                 "(RemainderOp ?x (DenseConstantOp ?h ?s ?t))" => { IntegerZero {
+                 s: "?s".parse().unwrap(),
+        }} if integer_divided_by_dense_one("?x", "?h", "?t")),
+    ]
+}
+
+/// Whether `x` has integer elements and `dtype` is the Num of the integer
+/// element type
+fn integer_typed(x: &EClass<Mdl, TensorData>, dtype: &EClass<Mdl, TensorData>) -> bool {
+    x.data.dtype == ffi::Type::i32 && get_num_option(dtype) == Some(dtype_to_num(ffi::Type::i32))
+}
+
+/// Condition that `x` has integer elements and `value` is the Num of a splat
+/// constant of 1 or -1, converted to integers (`dtype`)
+pub fn integer_divided_by_one(
+    x: &'static str,
+    value: &'static str,
+    dtype: &'static str,
+) -> impl Fn(&mut EGraph<Mdl, TensorAnalysis>, Id, &Subst) -> bool {
+    let x: Var = x.parse().unwrap();
+    let value: Var = value.parse().unwrap();
+    let dtype: Var = dtype.parse().unwrap();
+    move |egraph, _, subst| {
+        let bits = get_num_option(&egraph[subst[value]]);
+        integer_typed(&egraph[subst[x]], &egraph[subst[dtype]])
+            && bits.map_or(false, |bits| f32::from_bits(bits as u32).abs() == 1.0)
+    }
+}
+
+/// Condition that `x` has integer elements and `handle` is the Num of an
+/// integer (`dtype`) DenseConstantOp whose values are all 1 or -1
+pub fn integer_divided_by_dense_one(
+    x: &'static str,
+    handle: &'static str,
+    dtype: &'static str,
+) -> impl Fn(&mut EGraph<Mdl, TensorAnalysis>, Id, &Subst) -> bool {
+    let x: Var = x.parse().unwrap();
+    let handle: Var = handle.parse().unwrap();
+    let dtype: Var = dtype.parse().unwrap();
+    move |egraph, _, subst| {
+        let values = get_num_option(&egraph[subst[handle]])
+            .and_then(|handle| egraph.analysis.dense_values(handle));
+        integer_typed(&egraph[subst[x]], &egraph[subst[dtype]])
+            && values.map_or(false, |values| values.iter().all(|v| v.abs() == 1.0))
+    }
+}
+
+/// Adds an i32 splat constant of 0 of shape `s`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IntegerZero {
+    pub s: Var,
+}
+
+impl Applier<Mdl, TensorAnalysis> for IntegerZero {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let zero = make_num(egraph, 0);
+        let constant = egraph.add(Mdl::ConstantOp([zero, subst[self.s]]));
+        let i32_num = make_num(egraph, dtype_to_num(ffi::Type::i32));
+        let new_id = egraph.add(Mdl::ConvertOp([constant, i32_num]));
+        finish_apply(egraph, matched_id, new_id)
+    }
+}
+
+/// Rules taking complex tensors apart and back together, and cancelling FFTs
+/// with their inverses of the same length (see fft.rs for the fft_type Nums)
+pub fn complex_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    vec![
+        rewrite!("real-of-complex"; "(RealOp (ComplexOp ?x ?y))" => "?x"),
+        rewrite!("imag-of-complex"; "(ImagOp (ComplexOp ?x ?y))" => "?y"),
+        rewrite!("complex-of-parts"; "(ComplexOp (RealOp ?z) (ImagOp ?z))" => "?z"),
+        rewrite!("ifft-of-fft"; "(FftOp (FftOp ?x 0 ?l) 1 ?l)" => "?x"),
+        rewrite!("fft-of-ifft"; "(FftOp (FftOp ?x 1 ?l) 0 ?l)" => "?x"),
+        // The shape inference makes ?l match the last dimensions of ?x
+        rewrite!("irfft-of-rfft"; "(FftOp (FftOp ?x 2 ?l) 3 ?l)" => "?x"),
+    ]
+}
+
+/// Rules reading an element of a tuple built in the graph, with
+/// GetTupleElementOp or Index, as the element itself
+pub fn tuple_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    vec![
+        rewrite!("get-tuple-element-of-tuple";
+                 "(GetTupleElementOp (TupleOp ?inputs) ?i)" => { TupleElement {
+                 inputs: "?inputs".parse().unwrap(),
+                 index: "?i".parse().unwrap(),
+        }}),
+        rewrite!("index-of-tuple";
+                 "(Index ?i (TupleOp ?inputs))" => { TupleElement {
+                 inputs: "?inputs".parse().unwrap(),
+                 index: "?i".parse().unwrap(),
+        }}),
+    ]
+}
+
+/// Element `index` of the Vec of `inputs` of a TupleOp
+#[derive(Debug, Clone, PartialEq)]
+pub struct TupleElement {
+    pub inputs: Var,
+    pub index: Var,
+}
+
+impl Applier<Mdl, TensorAnalysis> for TupleElement {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let index = *get_num(&egraph[subst[self.index]]) as usize;
+        match get_vec(&egraph[subst[self.inputs]]).get(index) {
+            Some(&element) => finish_apply(egraph, matched_id, element),
+            None => vec![],
+        }
+    }
+}
+
+/// Rules depending on the values of constants: DenseConstantOps with all values
+/// the same are splats, so the rules matching ConstantOps apply to them, and
+/// sums with zeros and products with ones and zeros simplify. As with
+/// fast-math, products with zeros ignore NaN and infinite operands.
+pub fn constant_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    vec![
+        rewrite!("dense-constant-to-splat";
+                 "(DenseConstantOp ?h ?s ?t)" => { SplatOfDense {
+                 handle: "?h".parse().unwrap(),
+                 shape: "?s".parse().unwrap(),
+        }}),
+        rewrite!("add-zero"; "(AddOp ?x ?zero)" => "?x"
+                 if splat_constants_are(&[("?zero", 0.0)])),
+        rewrite!("zero-add"; "(AddOp ?zero ?x)" => "?x"
+                 if splat_constants_are(&[("?zero", 0.0)])),
+        rewrite!("mul-one"; "(MulOp ?x ?one)" => "?x"
+                 if splat_constants_are(&[("?one", 1.0)])),
+        rewrite!("one-mul"; "(MulOp ?one ?x)" => "?x"
+                 if splat_constants_are(&[("?one", 1.0)])),
+        rewrite!("mul-zero"; "(MulOp ?x ?zero)" => "?zero"
+                 if splat_constants_are(&[("?zero", 0.0)])),
+        rewrite!("zero-mul"; "(MulOp ?zero ?x)" => "?zero"
+                 if splat_constants_are(&[("?zero", 0.0)])),
+    ]
+}
+
+/// Splat ConstantOp of the `shape` of an f32 DenseConstantOp whose values (by
+/// `handle`) are all the same. ConstantOps are f32, so other element types
+/// stay dense
+#[derive(Debug, Clone, PartialEq)]
+pub struct SplatOfDense {
+    pub handle: Var,
+    pub shape: Var,
+}
+
+impl Applier<Mdl, TensorAnalysis> for SplatOfDense {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let handle = *get_num(&egraph[subst[self.handle]]);
+        let value = match egraph.analysis.dense_constants.get(&handle) {
+            Some(constant) if constant.dtype == ffi::Type::f32 => splat_value(&constant.values),
+            _ => None,
+        };
+        let value = match value {
+            Some(value) => value as f32,
+            None => return vec![],
+        };
+        let value_id = make_num(egraph, i64::from(value.to_bits() as i32));
+        let id = egraph.add(Mdl::ConstantOp([value_id, subst[self.shape]]));
+        finish_apply(egraph, matched_id, id)
+    }
+}
+
+/// Rules moving elementwise ops before collectives (see collective.rs for the
+/// reduction Nums), so that gathers move the smaller tensors and two
+/// collectives with the same groups become one. All gathers and all to alls
+/// only move elements, so any elementwise op goes before them. Collective sums
+/// go after negations, sums and differences, collective maxima (minima) after
+/// maxima (minima).
+pub fn collective_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    vec![
+        rewrite!("neg-before-all-gather";
+                 "(NegOp (AllGatherOp ?x ?d ?g ?n))" => "(AllGatherOp (NegOp ?x) ?d ?g ?n)"),
+        rewrite!("exp-before-all-gather";
+                 "(ExpOp (AllGatherOp ?x ?d ?g ?n))" => "(AllGatherOp (ExpOp ?x) ?d ?g ?n)"),
+        rewrite!("tanh-before-all-gather";
+                 "(TanhOp (AllGatherOp ?x ?d ?g ?n))" => "(AllGatherOp (TanhOp ?x) ?d ?g ?n)"),
+        rewrite!("convert-before-all-gather";
+                 "(ConvertOp (AllGatherOp ?x ?d ?g ?n) ?t)"
+                 => "(AllGatherOp (ConvertOp ?x ?t) ?d ?g ?n)"),
+        rewrite!("add-before-all-gather";
+                 "(AddOp (AllGatherOp ?x ?d ?g ?n) (AllGatherOp ?y ?d ?g ?n))"
+                 => "(AllGatherOp (AddOp ?x ?y) ?d ?g ?n)"),
+        rewrite!("mul-before-all-gather";
+                 "(MulOp (AllGatherOp ?x ?d ?g ?n) (AllGatherOp ?y ?d ?g ?n))"
+                 => "(AllGatherOp (MulOp ?x ?y) ?d ?g ?n)"),
+        rewrite!("convert-before-all-to-all";
+                 "(ConvertOp (AllToAllOp ?x ?s ?c ?g ?n) ?t)"
+                 => "(AllToAllOp (ConvertOp ?x ?t) ?s ?c ?g ?n)"),
+        rewrite!("add-before-all-to-all";
+                 "(AddOp (AllToAllOp ?x ?s ?c ?g ?n) (AllToAllOp ?y ?s ?c ?g ?n))"
+                 => "(AllToAllOp (AddOp ?x ?y) ?s ?c ?g ?n)"),
+        rewrite!("mul-before-all-to-all";
+                 "(MulOp (AllToAllOp ?x ?s ?c ?g ?n) (AllToAllOp ?y ?s ?c ?g ?n))"
+                 => "(AllToAllOp (MulOp ?x ?y) ?s ?c ?g ?n)"),
+        rewrite!("neg-before-all-reduce-sum";
+                 "(NegOp (AllReduceOp ?x ?g ?n 0))" => "(AllReduceOp (NegOp ?x) ?g ?n 0)"),
+        rewrite!("add-before-all-reduce-sum";
+                 "(AddOp (AllReduceOp ?x ?g ?n 0) (AllReduceOp ?y ?g ?n 0))"
+                 => "(AllReduceOp (AddOp ?x ?y) ?g ?n 0)"),
+        rewrite!("subtract-before-all-reduce-sum";
+                 "(SubtractOp (AllReduceOp ?x ?g ?n 0) (AllReduceOp ?y ?g ?n 0))"
+                 => "(AllReduceOp (SubtractOp ?x ?y) ?g ?n 0)"),
+        rewrite!("min-before-all-reduce-min";
+                 "(MinOp (AllReduceOp ?x ?g ?n 2) (AllReduceOp ?y ?g ?n 2))"
+                 => "(AllReduceOp (MinOp ?x ?y) ?g ?n 2)"),
+        rewrite!("max-before-all-reduce-max";
+                 "(MaxOp (AllReduceOp ?x ?g ?n 3) (AllReduceOp ?y ?g ?n 3))"
+                 => "(AllReduceOp (MaxOp ?x ?y) ?g ?n 3)"),
+        rewrite!("add-before-reduce-scatter-sum";
+                 "(AddOp (ReduceScatterOp ?x ?d ?g ?n 0) (ReduceScatterOp ?y ?d ?g ?n 0))"
+                 => "(ReduceScatterOp (AddOp ?x ?y) ?d ?g ?n 0)"),
+        // Each replica gathers back the parts of the reduction the others kept
+        rewrite!("all-gather-of-reduce-scatter";
+                 "(AllGatherOp (ReduceScatterOp ?x ?d ?g ?n ?r) ?d ?g ?n)"
+                 => "(AllReduceOp ?x ?g ?n ?r)"),
+    ]
+}
+
+/// Boolean algebra rules of the bitwise ops. They hold bit by bit, so for
+/// integers as well as booleans
+pub fn bitwise_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    vec![
+        rewrite!("and-idempotent"; "(AndOp ?x ?x)" => "?x"),
+        rewrite!("or-idempotent"; "(OrOp ?x ?x)" => "?x"),
+        rewrite!("and-commutative"; "(AndOp ?x ?y)" => "(AndOp ?y ?x)"),
+        rewrite!("or-commutative"; "(OrOp ?x ?y)" => "(OrOp ?y ?x)"),
+        rewrite!("xor-commutative"; "(XorOp ?x ?y)" => "(XorOp ?y ?x)"),
+        rewrite!("and-associative"; "(AndOp ?x (AndOp ?y ?z))" => "(AndOp (AndOp ?x ?y) ?z)"),
+        rewrite!("or-associative"; "(OrOp ?x (OrOp ?y ?z))" => "(OrOp (OrOp ?x ?y) ?z)"),
+        rewrite!("not-not"; "(NotOp (NotOp ?x))" => "?x"),
+        rewrite!("not-of-and"; "(NotOp (AndOp ?x ?y))" => "(OrOp (NotOp ?x) (NotOp ?y))"),
+        rewrite!("or-of-nots"; "(OrOp (NotOp ?x) (NotOp ?y))" => "(NotOp (AndOp ?x ?y))"),
+        rewrite!("not-of-or"; "(NotOp (OrOp ?x ?y))" => "(AndOp (NotOp ?x) (NotOp ?y))"),
+        rewrite!("and-of-nots"; "(AndOp (NotOp ?x) (NotOp ?y))" => "(NotOp (OrOp ?x ?y))"),
+        rewrite!("and-absorbs-or"; "(AndOp ?x (OrOp ?x ?y))" => "?x"),
+        rewrite!("or-absorbs-and"; "(OrOp ?x (AndOp ?x ?y))" => "?x"),
+        rewrite!("xor-of-nots"; "(XorOp (NotOp ?x) (NotOp ?y))" => "(XorOp ?x ?y)"),
+    ]
+}
+
+/// Expands a GeluOp into base ops, creating splat constants of the input shape.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpandGelu {
+    pub x: Var,
+    pub approximate: bool,
+}
+
+impl Applier<Mdl, TensorAnalysis> for ExpandGelu {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let x = subst[self.x];
+        let data = &egraph[x].data;
+        let dims: Vec<i64> = data.shapes[0][..data.n_dims[0]]
+            .iter()
+            .map(|d| i64::from(*d))
+            .collect();
+        let dim_ids: Vec<Id> = dims.iter().map(|d| make_num(egraph, *d)).collect();
+        let shape_id = make_vec(egraph, &dim_ids);
+        let constant = |egraph: &mut EGraph<Mdl, TensorAnalysis>, value: f32| {
+            let value_id = make_num(egraph, i64::from(value.to_bits() as i32));
+            egraph.add(Mdl::ConstantOp([value_id, shape_id]))
+        };
+
+        let half = constant(egraph, GELU_HALF);
+        let one = constant(egraph, GELU_ONE);
+        let inner = if self.approximate {
+            let scale = constant(egraph, GELU_TANH_SCALE);
+            let cubic = constant(egraph, GELU_TANH_CUBIC);
+            let x2 = egraph.add(Mdl::MulOp([x, x]));
+            let x3 = egraph.add(Mdl::MulOp([x, x2]));
+            let cubic_term = egraph.add(Mdl::MulOp([cubic, x3]));
+            let sum = egraph.add(Mdl::AddOp([x, cubic_term]));
+            let scaled = egraph.add(Mdl::MulOp([scale, sum]));
+            egraph.add(Mdl::TanhOp([scaled]))
+        } else {
+            let scale = constant(egraph, GELU_ERF_SCALE);
+            let scaled = egraph.add(Mdl::MulOp([x, scale]));
+            egraph.add(Mdl::ErfOp([scaled]))
+        };
+        let one_plus = egraph.add(Mdl::AddOp([one, inner]));
+        let half_x = egraph.add(Mdl::MulOp([x, half]));
+        let id = egraph.add(Mdl::MulOp([half_x, one_plus]));
+
+        finish_apply(egraph, matched_id, id)
+    }
+}
+
 /// Custom struct implementing the Applier trait, checking the new nodes to
 /// construct are all valid before actually apply.
 #[derive(Debug, Clone, PartialEq)]
@@ -444,15 +1141,24 @@ impl Applier<Mdl, TensorAnalysis> for CheckApply {
                 return vec![];
             }
         }
+        if breaks_sharding(self.src_pat.ast.as_ref(), egraph, subst) {
+            return vec![];
+        }
+        let src_precisions = dot_precisions(self.src_pat.ast.as_ref());
+        let dst_precisions = dot_precisions(self.pat.ast.as_ref());
+        let subst = match join_precision(egraph, &src_precisions, &dst_precisions, subst) {
+            Some(subst) => subst,
+            None => return vec![],
+        };
         let (valid, _, existing) =
-            check_pat(self.pat.ast.as_ref(), egraph, subst, self.filter_after);
+            check_pat(self.pat.ast.as_ref(), egraph, &subst, self.filter_after);
         if valid {
-            let result = self.pat.apply_one(egraph, matched_id, subst);
+            let result = self.pat.apply_one(egraph, matched_id, &subst);
 
             // Add the newly added nodes to the ordering vector
             if self.filter_after {
                 let existing = existing.unwrap();
-                add_newly_added(self.pat.ast.as_ref(), egraph, subst, &existing);
+                add_newly_added(self.pat.ast.as_ref(), egraph, &subst, &existing);
             }
             result
         } else {
@@ -657,7 +1363,7 @@ struct MapToCanonical {
 /// applying the rewrite.
 #[derive(Debug)]
 pub struct MultiPatterns {
-    /// Vec of (src_1, src_2, dst_1, dst_2, symmetric)
+    /// Vec of (src_1, src_2, dst_1, dst_2, symmetric), see is_symmetric_rule
     rules: Vec<(Pattern<Mdl>, Pattern<Mdl>, Pattern<Mdl>, Pattern<Mdl>, bool)>,
     /// Vec of all unique canonical source patterns (for src_1's and src_2's)
     canonical_src_pat: Vec<Pattern<Mdl>>,
@@ -685,6 +1391,8 @@ impl MultiPatterns {
     /// # Parameters
     ///
     /// - `rules`: every adjacent pair of entries should belong to the same multi-pattern rule.
+    ///         Rules found symmetric by is_symmetric_rule get their mirrored matches
+    ///         deduplicated.
     /// - `no_cycle`: whether or not to do cycle filtering
     /// - `iter_limit`: Number of iterations to apply multi-pattern rules
     /// - `filter_after`: if true, do efficient filtering (filter cycle after the iteration);
@@ -692,7 +1400,7 @@ impl MultiPatterns {
     /// - `node_limit`: Maximum number of nodes to added here
     /// - `n_sec`: Maximum number of seconds to run
     pub fn with_rules(
-        rules: Vec<(&str, bool)>,
+        rules: Vec<&str>,
         no_cycle: bool,
         iter_limit: usize,
         filter_after: bool,
@@ -730,15 +1438,13 @@ impl MultiPatterns {
         };
 
         for i in 0..(rules.len() / 2) {
-            let (src_1, dst_1) = get_pats(rules[2 * i].0);
-            let (src_2, dst_2) = get_pats(rules[2 * i + 1].0);
+            let (src_1, dst_1) = get_pats(rules[2 * i]);
+            let (src_2, dst_2) = get_pats(rules[2 * i + 1]);
 
             let src_1_map = canonicalize_and_add(&src_1);
             let src_2_map = canonicalize_and_add(&src_2);
 
-            assert!(rules[2 * i].1 == rules[2 * i + 1].1);
-            let symmetric = rules[2 * i].1;
-
+            let symmetric = is_symmetric_rule(&src_1, &src_2, &dst_1, &dst_2);
             multi_rules.push((src_1, src_2, dst_1, dst_2, symmetric));
             src_pat_maps.push((src_1_map, src_2_map));
         }
@@ -759,6 +1465,11 @@ impl MultiPatterns {
         }
     }
 
+    /// Number of successfully applied matches so far
+    pub fn num_applied(&self) -> usize {
+        self.num_applied
+    }
+
     /// Search and apply all multi-pattern rules for one iteration
     ///
     /// This function is used as hook function to egg::Runner. It first searches for matches
@@ -797,8 +1508,10 @@ impl MultiPatterns {
             'outer: for (i, rule) in self.rules.iter().enumerate() {
                 let map_1 = &self.src_pat_maps[i].0;
                 let map_2 = &self.src_pat_maps[i].1;
-                // If the rule is fully symmetrical
-                if map_1.index == map_2.index && rule.4 {
+                // If the rule is fully symmetrical, the pairs (a, b) and (b, a) apply the
+                // same rewrite, so only visit each unordered pair once
+                if rule.4 {
+                    assert!(map_1.index == map_2.index);
                     let matches_both = &matches[map_1.index];
                     for (i, match_1) in matches_both.iter().enumerate() {
                         for match_2 in (&matches_both[(i + 1)..]).iter() {
@@ -808,7 +1521,7 @@ impl MultiPatterns {
                             }
                             let n_applied =
                                 self.apply_match_pair(rule, match_1, match_2, map_1, map_2, runner);
-                            //num_applied += n_applied;
+                            num_applied += n_applied;
                             //let num_nodes = runner.egraph.analysis.newly_added.len();
                             //if num_nodes - starting_num_nodes > self.node_limit {
                             //    break 'outer;
@@ -829,7 +1542,7 @@ impl MultiPatterns {
                             }
                             let n_applied =
                                 self.apply_match_pair(rule, match_1, match_2, map_1, map_2, runner);
-                            //num_applied += n_applied;
+                            num_applied += n_applied;
                             //let num_nodes = runner.egraph.analysis.newly_added.len();
                             //if num_nodes - starting_num_nodes > self.node_limit {
                             //    break 'outer;
@@ -868,6 +1581,7 @@ impl MultiPatterns {
                 self.n_sec - time_taken
             };
 
+            self.num_applied += num_applied;
             //println!("Number of applied: {}", num_applied);
         }
 
@@ -886,6 +1600,14 @@ impl MultiPatterns {
         runner: &mut Runner<Mdl, TensorAnalysis, ()>,
     ) -> usize {
         let mut num_applied = 0;
+        let src_precisions: Vec<Var> = [&rule.0, &rule.1]
+            .iter()
+            .flat_map(|pat| dot_precisions(pat.ast.as_ref()))
+            .collect();
+        let dst_precisions: Vec<Var> = [&rule.2, &rule.3]
+            .iter()
+            .flat_map(|pat| dot_precisions(pat.ast.as_ref()))
+            .collect();
         for subst_1 in &match_1.substs {
             for subst_2 in &match_2.substs {
                 // De-canonicalize the substitutions
@@ -895,6 +1617,17 @@ impl MultiPatterns {
                 if compatible(&subst_1_dec, &subst_2_dec, &map_1.var_map) {
                     // If so, merge two substitutions
                     let merged_subst = merge_subst(subst_1_dec, subst_2_dec, &map_1.var_map);
+                    // Refuse merging DotGeneralOps with different precision
+                    // configs, and give split ones the stricter config
+                    let merged_subst = match join_precision(
+                        &mut runner.egraph,
+                        &src_precisions,
+                        &dst_precisions,
+                        &merged_subst,
+                    ) {
+                        Some(subst) => subst,
+                        None => continue,
+                    };
                     // Check if any source pattern contains blacklisted nodes
                     if self.filter_after {
                         let condition_tmp = contains_blacklist(
@@ -998,6 +1731,7 @@ impl MultiPatterns {
 
                             runner.egraph.union(id_1, match_1.eclass);
                             runner.egraph.union(id_2, match_2.eclass);
+                            num_applied += 1;
                         }
                     }
                 }
@@ -1440,6 +2174,48 @@ fn canonicalize(pat: &Pattern<Mdl>) -> (Pattern<Mdl>, HashMap<egg::Var, egg::Var
     (canonical_pat, var_map)
 }
 
+/// Check if a multi-pattern rule is symmetric
+///
+/// A rule (src_1, src_2) => (dst_1, dst_2) is symmetric if swapping the two halves gives the
+/// same rule up to renaming of variables. For such rules, applying the rule to the pair of
+/// matches (a, b) is the same as applying it to (b, a).
+pub fn is_symmetric_rule(
+    src_1: &Pattern<Mdl>,
+    src_2: &Pattern<Mdl>,
+    dst_1: &Pattern<Mdl>,
+    dst_2: &Pattern<Mdl>,
+) -> bool {
+    canonicalize_all(&[src_1, src_2, dst_1, dst_2]) == canonicalize_all(&[src_2, src_1, dst_2, dst_1])
+}
+
+/// Canonicalize a sequence of patterns, sharing the variable renaming between them
+///
+/// Variables are renamed to ?i_0, ?i_1 ... in order of first appearance across all patterns.
+fn canonicalize_all(pats: &[&Pattern<Mdl>]) -> Vec<Pattern<Mdl>> {
+    let mut var_map = HashMap::<egg::Var, egg::Var>::new();
+    pats.iter()
+        .map(|pat| {
+            let substituted: Vec<_> = pat
+                .ast
+                .as_ref()
+                .iter()
+                .cloned()
+                .map(|x| match x {
+                    ENodeOrVar::ENode(_) => x,
+                    ENodeOrVar::Var(v) => {
+                        let count = var_map.len();
+                        let var = var_map
+                            .entry(v)
+                            .or_insert_with(|| format!("?i_{}", count).parse().unwrap());
+                        ENodeOrVar::Var(*var)
+                    }
+                })
+                .collect();
+            Pattern::<Mdl>::from(RecExpr::from(substituted))
+        })
+        .collect()
+}
+
 /// Merge two substitutions
 ///
 /// This function merges two substitutions. The merged one contains substitutions (egg::Var -> Id)
@@ -0,0 +1,253 @@
+use crate::metrics::{self, names};
+use crate::model::*;
+use crate::rewrites::{RuleClass, RuleOptions};
+use egg::*;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Nodes and time a class of rules may spend in one iteration
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct RuleBudget {
+    /// Max number of e-graph nodes the rules may add
+    pub nodes: Option<usize>,
+    /// Max time the rules may spend searching and applying
+    pub time: Option<Duration>,
+}
+
+/// What a class of rules spent so far in the current iteration
+#[derive(Clone, Copy, Debug, Default)]
+struct Spent {
+    nodes: usize,
+    time: Duration,
+}
+
+impl Spent {
+    fn exceeds(&self, budget: &RuleBudget) -> bool {
+        budget.nodes.map_or(false, |nodes| self.nodes >= nodes)
+            || budget.time.map_or(false, |time| self.time >= time)
+    }
+}
+
+/// Scheduler capping the number of matches a rule applies per iteration
+///
+/// Searching is delegated to egg's BackoffScheduler, whose bans only kick in
+/// after a rule has already matched too much. The cap is applied on top of it,
+/// so a single rule whose right hand side grows the graph can't use up the
+/// node budget in one iteration.
+///
+/// Rules are also split into cheap and expensive classes, each with its own
+/// budget per iteration. Once a class is over budget its rules are skipped for
+/// the rest of the iteration, so cheap canonicalization rules keep running
+/// after the expensive rules used up the exploration budget. egg searches all
+/// rules before applying any, so the node budget is enforced while applying:
+/// the matches of an eclass are only applied while the class is in budget.
+///
+/// Finally, a rule may be given a time limit for e-matching. Rules whose left
+/// hand side is known (see `with_patterns`) are searched one eclass at a time
+/// until the limit, keeping the matches found so far, instead of through the
+/// BackoffScheduler. Other rules can't be interrupted, so a rule over its
+/// limit still finishes searching, but its matches are dropped for that
+/// iteration. Either way the timeout is counted in
+/// `names::RULE_MATCH_TIMEOUTS`.
+pub struct CappedScheduler {
+    inner: BackoffScheduler,
+    /// Options of each rule, by name
+    options: HashMap<String, RuleOptions>,
+    /// Cap for rules without their own
+    default_cap: Option<usize>,
+    /// Time limit of e-matching for rules without their own
+    default_match_limit: Option<Duration>,
+    /// Left hand side of each rule, by name, for the rules searched under a
+    /// time limit
+    patterns: HashMap<String, Pattern<Mdl>>,
+    /// Budget of each rule class
+    budgets: HashMap<RuleClass, RuleBudget>,
+    /// Iteration `spent` refers to
+    iteration: usize,
+    spent: HashMap<RuleClass, Spent>,
+}
+
+impl Default for CappedScheduler {
+    fn default() -> Self {
+        CappedScheduler::new(HashMap::new(), None)
+    }
+}
+
+impl CappedScheduler {
+    pub fn new(options: HashMap<String, RuleOptions>, default_cap: Option<usize>) -> Self {
+        CappedScheduler {
+            inner: BackoffScheduler::default(),
+            options,
+            default_cap,
+            default_match_limit: None,
+            patterns: HashMap::new(),
+            budgets: HashMap::new(),
+            iteration: 0,
+            spent: HashMap::new(),
+        }
+    }
+
+    /// Set the per-iteration budget of a rule class
+    pub fn with_budget(mut self, class: RuleClass, budget: RuleBudget) -> Self {
+        self.budgets.insert(class, budget);
+        self
+    }
+
+    /// Set the time limit of e-matching for rules without a `max_match_ms`
+    /// option
+    pub fn with_match_limit(mut self, limit: Option<Duration>) -> Self {
+        self.default_match_limit = limit;
+        self
+    }
+
+    /// Set the left hand sides of the rules, so that the ones with a time
+    /// limit are searched eclass by eclass
+    pub fn with_patterns(mut self, patterns: HashMap<String, Pattern<Mdl>>) -> Self {
+        self.patterns = patterns;
+        self
+    }
+
+    fn match_limit(&self, rule: &str) -> Option<Duration> {
+        self.options
+            .get(rule)
+            .and_then(|options| options.max_match_ms)
+            .map(Duration::from_millis)
+            .or(self.default_match_limit)
+    }
+
+    fn cap(&self, rule: &str) -> Option<usize> {
+        self.options
+            .get(rule)
+            .and_then(|options| options.max_matches)
+            .or(self.default_cap)
+    }
+
+    fn class(&self, rule: &str) -> RuleClass {
+        self.options
+            .get(rule)
+            .map(|options| options.class)
+            .unwrap_or_default()
+    }
+
+    fn start_iteration(&mut self, iteration: usize) {
+        if iteration != self.iteration {
+            self.iteration = iteration;
+            self.spent.clear();
+        }
+    }
+
+    fn over_budget(&self, class: RuleClass) -> bool {
+        match (self.spent.get(&class), self.budgets.get(&class)) {
+            (Some(spent), Some(budget)) => spent.exceeds(budget),
+            _ => false,
+        }
+    }
+
+    fn charge(&mut self, class: RuleClass, nodes: usize, time: Duration) {
+        let spent = self.spent.entry(class).or_default();
+        spent.nodes += nodes;
+        spent.time += time;
+    }
+}
+
+/// Matches of `pattern` in the eclasses of `egraph`, searched one eclass at a
+/// time until `deadline`. Also returns whether the deadline passed first
+fn search_until(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    pattern: &Pattern<Mdl>,
+    deadline: Instant,
+) -> (Vec<SearchMatches>, bool) {
+    let mut matches = vec![];
+    for eclass in egraph.classes() {
+        if Instant::now() >= deadline {
+            return (matches, true);
+        }
+        matches.extend(pattern.search_eclass(egraph, eclass.id));
+    }
+    (matches, false)
+}
+
+impl RewriteScheduler<Mdl, TensorAnalysis> for CappedScheduler {
+    fn can_stop(&mut self, iteration: usize) -> bool {
+        self.inner.can_stop(iteration)
+    }
+
+    fn search_rewrite(
+        &mut self,
+        iteration: usize,
+        egraph: &EGraph<Mdl, TensorAnalysis>,
+        rewrite: &Rewrite<Mdl, TensorAnalysis>,
+    ) -> Vec<SearchMatches> {
+        self.start_iteration(iteration);
+        let class = self.class(rewrite.name());
+        if self.over_budget(class) {
+            return vec![];
+        }
+
+        let start_time = Instant::now();
+        let limit = self.match_limit(rewrite.name());
+        let (mut matches, timed_out) = match (limit, self.patterns.get(rewrite.name())) {
+            (Some(limit), Some(pattern)) => search_until(egraph, pattern, start_time + limit),
+            _ => {
+                let matches = self.inner.search_rewrite(iteration, egraph, rewrite);
+                let timed_out = limit.map_or(false, |limit| start_time.elapsed() > limit);
+                (matches, timed_out)
+            }
+        };
+        let elapsed = start_time.elapsed();
+        self.charge(class, 0, elapsed);
+        if timed_out {
+            metrics::increment(names::RULE_MATCH_TIMEOUTS, 1.0);
+            if !self.patterns.contains_key(rewrite.name()) {
+                println!(
+                    "Skipping rule {} in iteration {}: matching took {:?}",
+                    rewrite.name(),
+                    iteration,
+                    elapsed
+                );
+                return vec![];
+            }
+            println!(
+                "Stopped matching rule {} in iteration {} after {:?}, {} eclasses matched",
+                rewrite.name(),
+                iteration,
+                elapsed,
+                matches.len()
+            );
+        }
+        if let Some(cap) = self.cap(rewrite.name()) {
+            let mut left = cap;
+            for m in matches.iter_mut() {
+                m.substs.truncate(left);
+                left -= m.substs.len();
+            }
+            matches.retain(|m| !m.substs.is_empty());
+        }
+        matches
+    }
+
+    fn apply_rewrite(
+        &mut self,
+        iteration: usize,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        rewrite: &Rewrite<Mdl, TensorAnalysis>,
+        matches: Vec<SearchMatches>,
+    ) -> usize {
+        self.start_iteration(iteration);
+        let class = self.class(rewrite.name());
+        let mut n_applied = 0;
+        for m in matches {
+            if self.over_budget(class) {
+                break;
+            }
+            let size_before = egraph.total_size();
+            let start_time = Instant::now();
+            n_applied += self
+                .inner
+                .apply_rewrite(iteration, egraph, rewrite, vec![m]);
+            let added = egraph.total_size().saturating_sub(size_before);
+            self.charge(class, added, start_time.elapsed());
+        }
+        n_applied
+    }
+}
@@ -0,0 +1,115 @@
+//! Optimizing many graphs in one session
+//!
+//! A compilation optimizes one graph per function of the module, and each
+//! `CppGraphConverter::optimize` call starts from scratch: it reads and parses
+//! the rule file, infers shapes and measures op costs on the C++ side, even for
+//! ops seen in earlier functions. An `OptimizerSession` keeps this state alive
+//! across the graphs it optimizes: the parsed rules, the inferred shapes and
+//! the measured costs (both only depend on the op and its arguments), and the
+//! optimized graphs themselves, so a function identical to an earlier one
+//! (e.g. another layer of the same model) is not optimized again.
+//!
+//! All graphs of a session are optimized with the config of the session.
+
+use crate::config::OptimizeConfig;
+use crate::input::{base_rules, ffi, read_learned_rules, CppGraphConverter};
+use crate::metrics::{self, names};
+use crate::model::*;
+use egg::Rewrite;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// State of a session shared with the converters it optimizes
+#[derive(Clone)]
+pub struct SharedState {
+    /// Contents of the rule file
+    pub(crate) learned_rules: Rc<String>,
+    /// Rules of optimize that don't depend on the graph, see input::base_rules
+    pub(crate) rules: Rc<Vec<Rewrite<Mdl, TensorAnalysis>>>,
+    pub(crate) shapes: OpCache<Vec<ffi::Shape>>,
+    pub(crate) costs: OpCache<f64>,
+}
+
+pub struct OptimizerSession {
+    config: OptimizeConfig,
+    shared: SharedState,
+    /// Optimized graphs, by CppGraphConverter::session_key of their input
+    optimized: HashMap<String, ffi::Graph>,
+}
+
+pub fn new_session() -> Box<OptimizerSession> {
+    Box::new(OptimizerSession::new(OptimizeConfig::default()))
+}
+
+/// Session with the settings in the TOML file at `path`, see
+/// OptimizeConfig::from_toml
+pub fn new_session_from_toml(path: &str) -> Result<Box<OptimizerSession>, String> {
+    Ok(Box::new(OptimizerSession::new(OptimizeConfig::from_toml(
+        path,
+    )?)))
+}
+
+impl OptimizerSession {
+    /// Session optimizing with `config`. Reads the rule file
+    pub fn new(config: OptimizeConfig) -> Self {
+        let learned_rules = read_learned_rules(&config);
+        let rules = base_rules(&config, &learned_rules);
+        OptimizerSession {
+            config,
+            shared: SharedState {
+                learned_rules: Rc::new(learned_rules),
+                rules: Rc::new(rules),
+                shapes: OpCache::default(),
+                costs: OpCache::default(),
+            },
+            optimized: HashMap::new(),
+        }
+    }
+
+    pub fn config(&self) -> &OptimizeConfig {
+        &self.config
+    }
+
+    /// Read the rule file again, e.g. after it was edited
+    ///
+    /// The inferred shapes and measured costs don't depend on the rules and
+    /// are kept, the graphs optimized with the old rules are forgotten.
+    pub fn reload_rules(&mut self) {
+        let learned_rules = read_learned_rules(&self.config);
+        self.shared.rules = Rc::new(base_rules(&self.config, &learned_rules));
+        self.shared.learned_rules = Rc::new(learned_rules);
+        self.optimized.clear();
+    }
+
+    /// Optimize the graph of `converter` with the config of the session (which
+    /// replaces the config of the converter), reusing the state of the session
+    ///
+    /// Returns the graph optimized earlier if the converter holds the same
+    /// graph as an earlier one. Graphs with blackboxes or composite ops are
+    /// always optimized. Files written by optimize (profiles, traces, reports)
+    /// are only written for graphs that are optimized.
+    pub fn optimize_graph(&mut self, converter: &mut CppGraphConverter) -> ffi::Graph {
+        converter.join_session(self.config.clone(), self.shared.clone());
+        let key = converter.session_key();
+        if let Some(graph) = key.as_ref().and_then(|key| self.optimized.get(key)) {
+            println!("Reusing the graph optimized earlier in the session");
+            metrics::increment(names::SESSION_CACHE_HITS, 1.0);
+            return graph.clone();
+        }
+        let graph = converter.optimize();
+        if let Some(key) = key {
+            self.optimized.insert(key, graph.clone());
+        }
+        graph
+    }
+
+    /// Number of optimized graphs kept for reuse
+    pub fn num_cached(&self) -> usize {
+        self.optimized.len()
+    }
+
+    /// Number of distinct ops whose costs were measured so far
+    pub fn num_measured(&self) -> usize {
+        self.shared.costs.lock().unwrap().len()
+    }
+}
@@ -0,0 +1,136 @@
+//! SPMD sharding annotations
+//!
+//! The C++ side annotates tensors with their sharding (the `mhlo.sharding`
+//! attribute, kept as an opaque string) through `set_sharding`. The eclasses of
+//! annotated tensors are recorded in `TensorAnalysis::shardings`, and rewrites
+//! whose left hand side passes through one of them are not applied: the
+//! annotated tensor would no longer be computed by the rewritten form, so its
+//! sharding contract would be lost. Rewrites producing the annotated tensor
+//! itself are fine, the new form computes the same tensor. The annotations are
+//! copied to the nodes of the optimized graph computing annotated eclasses.
+//!
+//! Only rules applied through `CheckApply` (the learned rules) are guarded.
+
+use crate::model::*;
+use crate::optimize::output_bytes;
+use crate::provenance::canonical_nodes;
+use egg::*;
+use std::collections::HashMap;
+
+/// Sharding of `class`, if it holds an annotated tensor
+pub fn sharding_of(egraph: &EGraph<Mdl, TensorAnalysis>, class: Id) -> Option<&str> {
+    let class = egraph.find(class);
+    egraph
+        .analysis
+        .shardings
+        .iter()
+        .find(|(id, _)| egraph.find(*id) == class)
+        .map(|(_, sharding)| sharding.as_str())
+}
+
+/// Eclasses of the annotated nodes of `expr`, with their sharding
+///
+/// # Parameters
+///
+/// - `shardings`: sharding of nodes of `expr`, by index
+///
+/// Annotated nodes that are not in the e-graph (e.g. changed when the input
+/// graph was canonicalized) are reported and dropped.
+pub fn sharded_classes(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    expr: &RecExpr<Mdl>,
+    shardings: &HashMap<Id, String>,
+) -> Vec<(Id, String)> {
+    let classes = canonical_nodes(egraph, expr);
+    let mut sharded = Vec::with_capacity(shardings.len());
+    for (id, sharding) in shardings {
+        match &classes[usize::from(*id)] {
+            Some((_, class)) => sharded.push((*class, sharding.clone())),
+            None => println!(
+                "WARNING: dropping sharding {} of node {}, which is not in the e-graph",
+                sharding, id
+            ),
+        }
+    }
+    sharded
+}
+
+/// Whether the match of `pat` passes through an annotated eclass, i.e. one of
+/// its inner (not root, not variable) nodes computes an annotated tensor
+pub fn breaks_sharding(
+    pat: &[ENodeOrVar<Mdl>],
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    subst: &Subst,
+) -> bool {
+    if egraph.analysis.shardings.is_empty() {
+        return false;
+    }
+    let mut classes: Vec<Option<Id>> = Vec::with_capacity(pat.len());
+    for (i, node) in pat.iter().enumerate() {
+        let class = match node {
+            ENodeOrVar::Var(var) => Some(subst[*var]),
+            ENodeOrVar::ENode(node) => {
+                let mut complete = true;
+                let node = node
+                    .clone()
+                    .map_children(|c| match classes[usize::from(c)] {
+                        Some(class) => class,
+                        None => {
+                            complete = false;
+                            c
+                        }
+                    });
+                let class = if complete { egraph.lookup(node) } else { None };
+                let inner = i + 1 < pat.len();
+                if let Some(class) = class {
+                    if inner && sharding_of(egraph, class).is_some() {
+                        return true;
+                    }
+                }
+                class
+            }
+        };
+        classes.push(class);
+    }
+    false
+}
+
+/// Bytes moved between devices by `enode` to bring its operands to a common
+/// sharding: if its annotated operands disagree, the operands not sharded like
+/// the first annotated one are resharded
+pub fn resharding_bytes(egraph: &EGraph<Mdl, TensorAnalysis>, enode: &Mdl) -> f64 {
+    if egraph.analysis.shardings.is_empty() {
+        return 0.0;
+    }
+    let mut first: Option<&str> = None;
+    let mut bytes = 0.0;
+    for child in enode.children() {
+        if let Some(sharding) = sharding_of(egraph, *child) {
+            match first {
+                None => first = Some(sharding),
+                Some(first) if first != sharding => {
+                    let class = &egraph[*child];
+                    bytes += output_bytes(egraph, class.id, &class.nodes[0]);
+                }
+                Some(_) => {}
+            }
+        }
+    }
+    bytes
+}
+
+/// Sharding of each node of the extracted graph, empty for nodes of eclasses
+/// without an annotation
+pub fn node_shardings(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    extracted: &RecExpr<Mdl>,
+) -> Vec<String> {
+    canonical_nodes(egraph, extracted)
+        .into_iter()
+        .map(|node| {
+            node.and_then(|(_, class)| sharding_of(egraph, class))
+                .unwrap_or_default()
+                .to_string()
+        })
+        .collect()
+}
@@ -0,0 +1,159 @@
+use crate::parse::{EqParser, Rule};
+use pest::{iterators::Pair, Parser};
+use std::collections::HashSet;
+
+/// Result of converting a file of TASO learned substitutions
+#[derive(Debug, Default)]
+pub struct ConvertedRules {
+    /// Converted rules, one "lhs=>rhs" per entry, using the Mdl (StableHLO) ops
+    pub rules: Vec<String>,
+    /// Original substitutions that could not be converted, with the reason
+    pub skipped: Vec<(String, String)>,
+}
+
+/// Convert TASO's learned substitutions (the `lhs==rhs` text dump, e.g.
+/// single_rules.txt) into rules over the StableHLO ops in Mdl.
+///
+/// TASO ops are mapped as follows:
+///
+/// - `ewadd` -> `AddOp`, `ewmul` -> `MulOp`
+/// - `smul(x, s)` -> `MulOp x (BroadcastInDimOp s (Vec))`, broadcasting the
+///   scalar `s` to the shape of `x`
+/// - `matmul` -> `DotGeneralOp` sharing the dimension variables between all
+///   dot_generals of the rule, as in converted.txt
+/// - `concat(axis, [ndim,] a, b, ...)` -> `ConcatenateOp (Vec a b ...) axis`
+/// - `transpose(x)` -> `TransposeOp x (Vec 1 0)`
+///
+/// Substitutions using any other op (conv2d, pooling, relu, ...) are skipped,
+/// as are substitutions whose right hand side introduces new variables.
+pub fn convert_taso_rules(rs_s: &str) -> ConvertedRules {
+    let rs = EqParser::parse(Rule::prog, rs_s)
+        .expect("parse error")
+        .next()
+        .unwrap();
+
+    let mut converted = ConvertedRules::default();
+    for eq in rs.into_inner() {
+        let original = eq.as_str().to_owned();
+        match convert_taso_eq(eq) {
+            Ok(rule) => converted.rules.push(rule),
+            Err(reason) => converted.skipped.push((original, reason)),
+        }
+    }
+    converted
+}
+
+fn convert_taso_eq(e: Pair<Rule>) -> Result<String, String> {
+    match e.as_rule() {
+        Rule::eq => {
+            let mut inner_rules = e.into_inner();
+            let lhs = convert_taso_exp(inner_rules.next().unwrap())?;
+            let rhs = convert_taso_exp(inner_rules.next().unwrap())?;
+            let lhs_vars = pattern_vars(&lhs);
+            if let Some(var) = pattern_vars(&rhs).difference(&lhs_vars).next() {
+                return Err(format!("{} only appears on the right hand side", var));
+            }
+            Ok(format!("{}=>{}", lhs, rhs))
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn convert_taso_exp(e: Pair<Rule>) -> Result<String, String> {
+    match e.as_rule() {
+        Rule::name => {
+            let name = e.as_str();
+            if name.parse::<i32>().is_ok() {
+                Ok(name.to_owned())
+            } else {
+                Ok(format!("?{}", name))
+            }
+        }
+        Rule::expr => convert_taso_exp(e.into_inner().next().unwrap()),
+        Rule::apply => {
+            let mut inner_rules = e.into_inner();
+            let op = inner_rules.next().unwrap().as_str().to_owned();
+            let args = inner_rules
+                .next()
+                .unwrap()
+                .into_inner()
+                .map(convert_taso_exp)
+                .collect::<Result<Vec<String>, String>>()?;
+            convert_taso_op(&op, &args)
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn convert_taso_op(op: &str, args: &[String]) -> Result<String, String> {
+    let wrong_arity = || Err(format!("unexpected number of arguments for {}", op));
+    match op {
+        "ewadd" | "ewmul" => {
+            if args.len() != 2 {
+                return wrong_arity();
+            }
+            let new_op = if op == "ewadd" { "AddOp" } else { "MulOp" };
+            Ok(format!("({} {} {})", new_op, args[0], args[1]))
+        }
+        "smul" => {
+            if args.len() != 2 {
+                return wrong_arity();
+            }
+            Ok(format!(
+                "(MulOp {} (BroadcastInDimOp {} (Vec)))",
+                args[0], args[1]
+            ))
+        }
+        "matmul" => {
+            // The multi-pattern dumps carry the fused activation as first argument
+            let (activation, operands) = match args.len() {
+                2 => ("0", args),
+                3 => (args[0].as_str(), &args[1..]),
+                _ => return wrong_arity(),
+            };
+            if activation != "0" {
+                return Err("matmul with fused activation".to_owned());
+            }
+            Ok(format!(
+                "(DotGeneralOp {} {} ?lb ?rb ?lc ?rc ?p)",
+                operands[0], operands[1]
+            ))
+        }
+        "concat" => {
+            let is_num = |arg: &String| arg.parse::<i32>().is_ok();
+            if args.is_empty() || !is_num(&args[0]) {
+                return wrong_arity();
+            }
+            // The number of dimensions, if given, comes right after the axis
+            let operands = match args.get(1) {
+                Some(ndim) if is_num(ndim) => &args[2..],
+                _ => &args[1..],
+            };
+            if operands.len() < 2 {
+                return wrong_arity();
+            }
+            if operands.iter().any(is_num) {
+                return Err("concat of a number".to_owned());
+            }
+            Ok(format!(
+                "(ConcatenateOp (Vec {}) {})",
+                operands.join(" "),
+                args[0]
+            ))
+        }
+        "transpose" => {
+            if args.len() != 1 {
+                return wrong_arity();
+            }
+            Ok(format!("(TransposeOp {} (Vec 1 0))", args[0]))
+        }
+        _ => Err(format!("no StableHLO equivalent for {}", op)),
+    }
+}
+
+fn pattern_vars(pat: &str) -> HashSet<String> {
+    pat.split(|c: char| c.is_whitespace() || c == '(' || c == ')')
+        .filter(|token| token.starts_with('?'))
+        .map(|token| token.to_owned())
+        .collect()
+}
@@ -0,0 +1,78 @@
+use crate::{model::*, optimize::*, provenance::*, replay::*};
+use egg::*;
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::prelude::*;
+
+/// Write one JSON line per recorded substitution that survives into the
+/// extracted graph
+///
+/// A substitution survives if it is on the derivation path of a node of the
+/// extracted graph (see node_derivations). Each line has the rule name, the
+/// iteration, the shapes of the eclass of the extracted node it leads to and
+/// the estimated cost of the op computing that eclass before and after.
+///
+/// # Parameters
+///
+/// - `egraph`: saturated e-graph
+/// - `start`: graph saturation started from
+/// - `extracted`: graph extracted from `egraph`
+/// - `log`: substitutions recorded during saturation
+/// - `cost_model`: cost model used to estimate the cost delta
+/// - `trace_file`: file to write the trace to
+pub fn write_rule_trace(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    start: &RecExpr<Mdl>,
+    extracted: &RecExpr<Mdl>,
+    log: &[AppliedRewrite],
+    cost_model: &CostModel,
+    trace_file: &str,
+) -> std::io::Result<usize> {
+    let original: HashMap<Id, Mdl> = canonical_nodes(egraph, start)
+        .into_iter()
+        .flatten()
+        .map(|(node, class)| (class, node))
+        .collect();
+    // The extracted node each surviving substitution leads to
+    let mut survivors: BTreeMap<usize, (Mdl, Id)> = BTreeMap::new();
+    let derivations = node_derivations(egraph, start, extracted, log);
+    for (node, steps) in canonical_nodes(egraph, extracted)
+        .into_iter()
+        .zip(derivations)
+    {
+        if let Some(node) = node {
+            for k in steps {
+                survivors.entry(k).or_insert_with(|| node.clone());
+            }
+        }
+    }
+
+    let mut file = File::create(trace_file)?;
+    let mut num_written = 0;
+    for (k, (node, class)) in survivors.iter() {
+        let applied = &log[*k];
+        let data = &egraph[*class].data;
+        let shapes: Vec<Vec<i32>> = data
+            .shapes
+            .iter()
+            .zip(data.n_dims.iter())
+            .map(|(shape, n_dims)| shape[..*n_dims].to_vec())
+            .collect();
+        let cost_before = original
+            .get(class)
+            .map_or(0.0, |node| cost_model.get_self_cost(egraph, node));
+        let cost_after = cost_model.get_self_cost(egraph, node);
+        let record = json!({
+            "rule": applied.rule,
+            "iteration": applied.iteration,
+            "shapes": shapes,
+            "cost_before": cost_before,
+            "cost_after": cost_after,
+            "cost_delta": cost_after - cost_before,
+        });
+        writeln!(file, "{}", record)?;
+        num_written += 1;
+    }
+    Ok(num_written)
+}
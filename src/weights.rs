@@ -0,0 +1,267 @@
+//! Weights
+//!
+//! Inputs created with `CppGraphConverter::new_weight` are known at compile
+//! time, and so is any op whose operands all are (`TensorData::constant`).
+//! Such ops are folded by the compiler, so the cost model doesn't charge for
+//! them (see `is_folded`), which makes the rewrites below pay off: they move
+//! work from the activations onto the weights.
+
+use crate::convolution::ConvDimensionNumbers;
+use crate::layout::{CanonicalizeDotOperand, Operand};
+use crate::model::*;
+use crate::naming::{add_named, derived_name};
+use crate::rewrites::{finish_apply, get_num, get_vec_of_nums, make_num, make_vec};
+use egg::*;
+
+/// Whether `enode` is an op computed at compile time, i.e. all its children
+/// are known at compile time
+pub fn is_folded(egraph: &EGraph<Mdl, TensorAnalysis>, enode: &Mdl) -> bool {
+    !enode.is_leaf()
+        && enode
+            .children()
+            .iter()
+            .all(|child| egraph[*child].data.constant)
+}
+
+/// Condition that `var` is known at compile time
+pub fn is_constant<'a>(
+    var: &'a str,
+) -> impl Fn(&mut EGraph<Mdl, TensorAnalysis>, Id, &Subst) -> bool + 'a {
+    let var = var.parse().unwrap();
+    move |egraph, _, subst: &Subst| egraph[subst[var]].data.constant
+}
+
+/// Condition that all of `vars` are known at compile time
+pub fn are_constant<'a>(
+    vars: &'a [&'a str],
+) -> impl Fn(&mut EGraph<Mdl, TensorAnalysis>, Id, &Subst) -> bool + 'a {
+    let vars: Vec<Var> = vars.iter().map(|v| v.parse().unwrap()).collect();
+    move |egraph, _, subst: &Subst| vars.iter().all(|v| egraph[subst[*v]].data.constant)
+}
+
+/// Rules that only pay off when an operand is a weight: transposing a weight
+/// operand of a DotGeneralOp into the canonical layout (see
+/// `layout::canonical_perm`), folding a constant scale of the result of a
+/// DotGeneralOp into its weight operand, and folding an inference batch norm
+/// of the result of a ConvolutionOp or DotGeneralOp into its weight
+pub fn weight_rules() -> Vec<Rewrite<Mdl, TensorAnalysis>> {
+    let dot = "(DotGeneralOp ?x ?y ?lb ?rb ?lc ?rc ?prec)";
+    let conv = "(ConvolutionOp ?x ?y ?ws ?pad ?ld ?rd ?dn ?fg ?bg ?prec)";
+    let pattern = |pattern: &str| -> Pattern<Mdl> { pattern.parse().unwrap() };
+    let scaled_dot = format!("(MulOp {} (ConstantOp ?v ?s))", dot);
+    let batch_norm = |op: &str| {
+        format!(
+            "(BatchNormInferenceOp {} ?scale ?offset ?mean ?var ?eps ?f)",
+            op
+        )
+    };
+    let batch_norm_weights = &["?y", "?scale", "?offset", "?mean", "?var"];
+    vec![
+        rewrite!("pre-transpose-weight-lhs"; { pattern(dot) } => {
+            CanonicalizeDotOperand { operand: Operand::Lhs }
+        } if is_constant("?x")),
+        rewrite!("pre-transpose-weight-rhs"; { pattern(dot) } => {
+            CanonicalizeDotOperand { operand: Operand::Rhs }
+        } if is_constant("?y")),
+        rewrite!("fold-scale-into-weight-lhs"; { pattern(&scaled_dot) } => {
+            FoldScaleIntoWeight { operand: Operand::Lhs }
+        } if is_constant("?x")),
+        rewrite!("fold-scale-into-weight-rhs"; { pattern(&scaled_dot) } => {
+            FoldScaleIntoWeight { operand: Operand::Rhs }
+        } if is_constant("?y")),
+        rewrite!("fold-batch-norm-into-convolution"; { pattern(&batch_norm(conv)) } => {
+            FoldBatchNorm { op: WeightedOp::Convolution }
+        } if are_constant(batch_norm_weights)),
+        rewrite!("fold-batch-norm-into-dot"; { pattern(&batch_norm(dot)) } => {
+            FoldBatchNorm { op: WeightedOp::DotGeneral }
+        } if are_constant(batch_norm_weights)),
+    ]
+}
+
+fn var(name: &str) -> Var {
+    name.parse().unwrap()
+}
+
+/// Multiplies the weight `operand` of a DotGeneralOp, instead of its result, by
+/// the constant (a splat, so a scalar) scaling the result
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoldScaleIntoWeight {
+    pub operand: Operand,
+}
+
+impl Applier<Mdl, TensorAnalysis> for FoldScaleIntoWeight {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let (mut lhs, mut rhs) = (subst[var("?x")], subst[var("?y")]);
+        let weight = match self.operand {
+            Operand::Lhs => lhs,
+            Operand::Rhs => rhs,
+        };
+        let data = &egraph[weight].data;
+        let dims: Vec<i64> = data.shapes[0][..data.n_dims[0]]
+            .iter()
+            .map(|d| i64::from(*d))
+            .collect();
+        let dims: Vec<Id> = dims.iter().map(|d| make_num(egraph, *d)).collect();
+        let shape = make_vec(egraph, &dims);
+        let scale = egraph.add(Mdl::ConstantOp([subst[var("?v")], shape]));
+        let name = derived_name(egraph, weight, "scaled");
+        let scaled = add_named(egraph, Mdl::MulOp([weight, scale]), &name);
+        match self.operand {
+            Operand::Lhs => lhs = scaled,
+            Operand::Rhs => rhs = scaled,
+        }
+        let id = egraph.add(Mdl::DotGeneralOp([
+            lhs,
+            rhs,
+            subst[var("?lb")],
+            subst[var("?rb")],
+            subst[var("?lc")],
+            subst[var("?rc")],
+            subst[var("?prec")],
+        ]));
+        finish_apply(egraph, matched_id, id)
+    }
+}
+
+/// Op whose weight operand (?y) an inference batch norm of its result is
+/// folded into
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WeightedOp {
+    Convolution,
+    DotGeneral,
+}
+
+/// Replaces an inference batch norm of the result of a ConvolutionOp or
+/// DotGeneralOp by the op on its weight scaled per feature, plus a per-feature
+/// bias. Both the scaled weight and the bias are batch norms of values known at
+/// compile time, so they are folded; the bias one is a broadcast (a batch norm
+/// of zeros with unit variance and no epsilon), as BroadcastInDimOp doesn't
+/// record the shape to broadcast to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FoldBatchNorm {
+    pub op: WeightedOp,
+}
+
+impl FoldBatchNorm {
+    /// Dimension of the weight that becomes the feature dimension `feature` of
+    /// the result, if there is one
+    fn weight_feature(
+        &self,
+        egraph: &EGraph<Mdl, TensorAnalysis>,
+        subst: &Subst,
+        feature: usize,
+        rank: usize,
+    ) -> Option<i64> {
+        let nums = |name: &str| get_vec_of_nums(egraph, &egraph[subst[var(name)]]);
+        match self.op {
+            WeightedOp::Convolution => {
+                let num_spatial = nums("?ws").len();
+                let dims = ConvDimensionNumbers::from_flat(&nums("?dn"), num_spatial).ok()?;
+                (dims.output_feature == feature).then_some(dims.kernel_output_feature as i64)
+            }
+            // The free dimensions of the rhs come last in the result, so the
+            // feature has to be the last dimension and the only free one of ?y
+            WeightedOp::DotGeneral => {
+                let (rb, rc) = (nums("?rb"), nums("?rc"));
+                let free: Vec<i64> = (0..egraph[subst[var("?y")]].data.n_dims[0] as i64)
+                    .filter(|d| !rb.contains(d) && !rc.contains(d))
+                    .collect();
+                match free[..] {
+                    [dim] if feature + 1 == rank => Some(dim),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+impl Applier<Mdl, TensorAnalysis> for FoldBatchNorm {
+    fn apply_one(
+        &self,
+        egraph: &mut EGraph<Mdl, TensorAnalysis>,
+        matched_id: Id,
+        subst: &Subst,
+    ) -> Vec<Id> {
+        let feature = *get_num(&egraph[subst[var("?f")]]);
+        let rank = egraph[matched_id].data.n_dims[0];
+        let weight_feature = match self.weight_feature(egraph, subst, feature as usize, rank) {
+            Some(dim) => dim,
+            None => return vec![],
+        };
+        let shape_of = |egraph: &mut EGraph<Mdl, TensorAnalysis>, id: Id| {
+            let data = &egraph[id].data;
+            let dims: Vec<i64> = data.shapes[0][..data.n_dims[0]]
+                .iter()
+                .map(|d| i64::from(*d))
+                .collect();
+            let dims: Vec<Id> = dims.iter().map(|d| make_num(egraph, *d)).collect();
+            make_vec(egraph, &dims)
+        };
+        let features = shape_of(egraph, subst[var("?scale")]);
+        let zero = make_num(egraph, 0);
+        let one = make_num(egraph, i64::from(1.0f32.to_bits() as i32));
+        let zeros = egraph.add(Mdl::ConstantOp([zero, features]));
+        let ones = egraph.add(Mdl::ConstantOp([one, features]));
+        let (scale, variance, eps) = (subst[var("?scale")], subst[var("?var")], subst[var("?eps")]);
+
+        // The weight scaled by scale / sqrt(var + eps) along its feature
+        let weight = subst[var("?y")];
+        let weight_feature = make_num(egraph, weight_feature);
+        let name = derived_name(egraph, weight, "batch_norm_folded");
+        let folded = add_named(
+            egraph,
+            Mdl::BatchNormInferenceOp([weight, scale, zeros, zeros, variance, eps, weight_feature]),
+            &name,
+        );
+        let x = subst[var("?x")];
+        let op = match self.op {
+            WeightedOp::Convolution => egraph.add(Mdl::ConvolutionOp([
+                x,
+                folded,
+                subst[var("?ws")],
+                subst[var("?pad")],
+                subst[var("?ld")],
+                subst[var("?rd")],
+                subst[var("?dn")],
+                subst[var("?fg")],
+                subst[var("?bg")],
+                subst[var("?prec")],
+            ])),
+            WeightedOp::DotGeneral => egraph.add(Mdl::DotGeneralOp([
+                x,
+                folded,
+                subst[var("?lb")],
+                subst[var("?rb")],
+                subst[var("?lc")],
+                subst[var("?rc")],
+                subst[var("?prec")],
+            ])),
+        };
+
+        // offset - mean * scale / sqrt(var + eps), broadcast along the feature
+        let epsilons = egraph.add(Mdl::ConstantOp([eps, features]));
+        let shifted = egraph.add(Mdl::AddOp([variance, epsilons]));
+        let rsqrt = egraph.add(Mdl::RsqrtOp([shifted]));
+        let factor = egraph.add(Mdl::MulOp([scale, rsqrt]));
+        let scaled_mean = egraph.add(Mdl::MulOp([subst[var("?mean")], factor]));
+        let bias = egraph.add(Mdl::SubtractOp([subst[var("?offset")], scaled_mean]));
+        let result_shape = shape_of(egraph, matched_id);
+        let result_zeros = egraph.add(Mdl::ConstantOp([zero, result_shape]));
+        let broadcast = egraph.add(Mdl::BatchNormInferenceOp([
+            result_zeros,
+            ones,
+            bias,
+            zeros,
+            ones,
+            zero,
+            subst[var("?f")],
+        ]));
+        let id = egraph.add(Mdl::AddOp([op, broadcast]));
+        finish_apply(egraph, matched_id, id)
+    }
+}
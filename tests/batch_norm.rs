@@ -0,0 +1,153 @@
+mod common;
+
+use common::shape;
+use egg::{EGraph, RecExpr, Runner};
+use std::collections::HashMap;
+use tensat::input::{ffi, CppGraphConverter};
+use tensat::interpreter::numerically_equivalent;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::weights::weight_rules;
+
+/// Epsilon of 0.5, as the bits of the f32
+const EPS: i32 = 0x3f00_0000;
+
+/// Inference batch norm of `op` along `feature`, with weights of `features`
+/// elements. The variance is a square, so it is never negative.
+fn batch_norm(op: &str, features: usize, feature: usize) -> String {
+    format!(
+        "(BatchNormInferenceOp {} (input w_1@{n} 2) (input w_2@{n} 3) (input w_3@{n} 4) \
+         (MulOp (input w_4@{n} 5) (input w_4@{n} 5)) {} {})",
+        op,
+        EPS,
+        feature,
+        n = features
+    )
+}
+
+/// Saturates `expr` with the weight rules, returning whether the root became
+/// equal to an AddOp (the folded form) and to `other`, if given
+fn fold(expr: &str, other: Option<&str>) -> (bool, bool) {
+    let expr: RecExpr<Mdl> = expr.parse().unwrap();
+    let mut runner = Runner::default()
+        .with_egraph(EGraph::new(TensorAnalysis::new(&HashMap::new(), &[])))
+        .with_expr(&expr)
+        .with_iter_limit(2)
+        .run(&weight_rules());
+    let root = runner.egraph.find(runner.roots[0]);
+    let folded = runner.egraph[root]
+        .iter()
+        .any(|node| matches!(node, Mdl::AddOp(_)));
+    let equal = other.map_or(false, |other| {
+        let other = runner.egraph.add_expr(&other.parse().unwrap());
+        runner.egraph.find(other) == root
+    });
+    (folded, equal)
+}
+
+#[test]
+fn batch_norms_have_their_results() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[8, 4, 6], ffi::Type::f32);
+    let scale = converter.new_weight(1, &[4], ffi::Type::f32);
+    let offset = converter.new_weight(2, &[4], ffi::Type::f32);
+    let shapes = vec![shape(&[8, 4, 6]), shape(&[4]), shape(&[4])];
+    let training = converter.new_batch_norm_training_op(x, scale, offset, 1e-5, 1, &shapes);
+    let output = converter.new_index(0, training);
+    let mean = converter.new_index(1, training);
+    let variance = converter.new_index(2, training);
+    assert_eq!(converter.tensor_shape(output), vec![8, 4, 6]);
+    assert_eq!(converter.tensor_shape(variance), vec![4]);
+
+    let grad = converter.new_batch_norm_grad_op(x, scale, mean, variance, output, 1e-5, 1, &shapes);
+    let grad_scale = converter.new_index(1, grad);
+    assert_eq!(converter.tensor_shape(grad_scale), vec![4]);
+
+    let inference = converter.new_batch_norm_inference_op(
+        x,
+        scale,
+        offset,
+        mean,
+        variance,
+        1e-5,
+        1,
+        &[8, 4, 6],
+    );
+    assert_eq!(converter.tensor_shape(inference), vec![8, 4, 6]);
+    assert!(converter.tensor_dtype(inference) == ffi::Type::f32);
+    assert!(!converter.tensor(inference).tensor_data.constant);
+    match &converter.rec_expr_ref().as_ref()[inference.id as usize] {
+        Mdl::BatchNormInferenceOp([.., epsilon, _]) => assert_eq!(
+            converter.rec_expr_ref().as_ref()[usize::from(*epsilon)],
+            Mdl::Num(i64::from(1e-5f32.to_bits() as i32))
+        ),
+        other => panic!("expected a BatchNormInferenceOp, got {}", other),
+    }
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn batch_norm_folds_into_dot_weights() {
+    let dot = "(DotGeneralOp (input x@4_3 0) (input w_0@3_5 1) (Vec) (Vec) (Vec 1) (Vec 0) (Vec))";
+    let expr = batch_norm(dot, 5, 1);
+    let variance = "(MulOp (input w_4@5 5) (input w_4@5 5))";
+    let (zeros, ones) = (
+        "(ConstantOp 0 (Vec 5))",
+        format!("(ConstantOp {} (Vec 5))", 1.0f32.to_bits()),
+    );
+    let weight = format!(
+        "(BatchNormInferenceOp (input w_0@3_5 1) (input w_1@5 2) {z} {z} {} {} 1)",
+        variance,
+        EPS,
+        z = zeros
+    );
+    let bias = format!(
+        "(SubtractOp (input w_2@5 3) (MulOp (input w_3@5 4) \
+         (MulOp (input w_1@5 2) (RsqrtOp (AddOp {} (ConstantOp {} (Vec 5)))))))",
+        variance, EPS
+    );
+    let folded = format!(
+        "(AddOp (DotGeneralOp (input x@4_3 0) {} (Vec) (Vec) (Vec 1) (Vec 0) (Vec)) \
+         (BatchNormInferenceOp (ConstantOp 0 (Vec 4 5)) {o} {} {} {o} 0 1))",
+        weight,
+        bias,
+        zeros,
+        o = ones
+    );
+    assert_eq!(fold(&expr, Some(&folded)), (true, true));
+
+    let expr: RecExpr<Mdl> = expr.parse().unwrap();
+    let folded: RecExpr<Mdl> = folded.parse().unwrap();
+    let analysis = || TensorAnalysis::new(&HashMap::new(), &[]);
+    assert_eq!(
+        numerically_equivalent(&expr, &folded, analysis, 1e-4, 0),
+        Ok(true)
+    );
+
+    // Only the last dimension of the result comes from the weight
+    let expr = batch_norm(dot, 4, 0);
+    assert_eq!(fold(&expr, None), (false, false));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn batch_norm_folds_into_convolution_weights() {
+    // NCHW input, OIHW kernel: the output features come from dimension 0 of
+    // the kernel
+    let conv = "(ConvolutionOp (input x@1_2_5_5 0) (input w_0@3_2_3_3 1) (Vec 1 1) \
+                (Vec 0 0 0 0) (Vec) (Vec) (Vec 0 1 2 3 1 0 2 3 0 1 2 3) 1 1 (Vec))";
+    let (folded, _) = fold(&batch_norm(conv, 3, 1), None);
+    assert!(folded);
+    // A batch norm along a spatial dimension isn't per output feature
+    let (folded, _) = fold(&batch_norm(conv, 3, 2), None);
+    assert!(!folded);
+    // Nor is one whose statistics aren't known at compile time
+    let activations = batch_norm(conv, 3, 1).replace("w_3@3 4", "mean@3 4");
+    let (folded, _) = fold(&activations, None);
+    assert!(!folded);
+}
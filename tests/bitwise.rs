@@ -0,0 +1,70 @@
+mod common;
+
+use common::equal;
+use tensat::input::{ffi, infer_dtypes, CppGraphConverter};
+use tensat::model::{check_operand_dtype, Mdl};
+use tensat::rewrites::bitwise_rules;
+
+const X: &str = "(input x@8@i32 0)";
+const Y: &str = "(input y@8@i32 1)";
+
+#[test]
+fn bitwise_ops_keep_the_type_of_their_operands() {
+    let mut converter = CppGraphConverter::default();
+    let mask = converter.new_input(0, &[8], ffi::Type::i1);
+    let x = converter.new_input(1, &[8], ffi::Type::i32);
+    let bits = converter.new_input(2, &[8], ffi::Type::i32);
+    let not = converter.new_not_op(mask, &[8]);
+    let shifted = converter.new_shift_right_logical_op(x, bits, &[8]);
+    let masked = converter.new_and_op(shifted, x, &[8]);
+    assert!(converter.tensor_dtype(not) == ffi::Type::i1);
+    assert!(converter.tensor_dtype(masked) == ffi::Type::i32);
+    converter.new_return_op(&[not, masked]);
+
+    let dtypes = infer_dtypes(converter.rec_expr_ref());
+    assert!(dtypes[not.id as usize] == ffi::Type::i1);
+    assert!(dtypes[masked.id as usize] == ffi::Type::i32);
+}
+
+#[test]
+fn shifts_only_take_integers() {
+    let shift = Mdl::ShiftLeftOp([0.into(), 1.into()]);
+    assert!(check_operand_dtype(&shift, ffi::Type::i32).is_ok());
+    assert!(check_operand_dtype(&shift, ffi::Type::i1).is_err());
+    let xor = Mdl::XorOp([0.into(), 1.into()]);
+    assert!(check_operand_dtype(&xor, ffi::Type::i1).is_ok());
+    assert!(check_operand_dtype(&xor, ffi::Type::bf16).is_err());
+}
+
+#[test]
+#[should_panic(expected = "Invalid OrOp")]
+fn bitwise_ops_on_floats_are_rejected() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[8], ffi::Type::f32);
+    converter.new_or_op(x, x, &[8]);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn boolean_algebra_simplifies() {
+    assert!(equal(&bitwise_rules(), &format!("(AndOp {} {})", X, X), X));
+    assert!(equal(
+        &bitwise_rules(),
+        &format!("(NotOp (NotOp {}))", X),
+        X
+    ));
+    assert!(equal(
+        &bitwise_rules(),
+        &format!("(OrOp {} (AndOp {} {}))", X, X, Y),
+        X
+    ));
+    assert!(equal(
+        &bitwise_rules(),
+        &format!("(NotOp (AndOp {} {}))", X, Y),
+        &format!("(OrOp (NotOp {}) (NotOp {}))", Y, X)
+    ));
+    assert!(!equal(&bitwise_rules(), &format!("(XorOp {} {})", X, X), X));
+}
@@ -0,0 +1,105 @@
+use egg::{EGraph, Extractor, Id, RecExpr, Symbol};
+use std::collections::{HashMap, HashSet};
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::optimize::{
+    add_costs, picked_nodes, prep_ilp_data, protect_nodes, saturate_cost, CostModel, TensorCost,
+    MAX_COST,
+};
+
+/// E-graph where `(input x@2_3 0)` and `(input y@2_3 1)` were found equal,
+/// with the ids of the merged eclass and the two input nodes
+fn merged_inputs() -> (EGraph<Mdl, TensorAnalysis>, Id, Mdl, Mdl) {
+    let mut egraph = EGraph::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    let x = egraph.add(Mdl::Var(Symbol::from("x@2_3")));
+    let zero = egraph.add(Mdl::Num(0));
+    let y = egraph.add(Mdl::Var(Symbol::from("y@2_3")));
+    let one = egraph.add(Mdl::Num(1));
+    let input_x = Mdl::Input([x, zero]);
+    let input_y = Mdl::Input([y, one]);
+    let a = egraph.add(input_x.clone());
+    let b = egraph.add(input_y.clone());
+    egraph.union(a, b);
+    egraph.rebuild();
+    let root = egraph.find(a);
+    (egraph, root, input_x, input_y)
+}
+
+fn greedy(egraph: &EGraph<Mdl, TensorAnalysis>, root: Id) -> Mdl {
+    let cost_model = CostModel::new();
+    let mut extractor = Extractor::new(
+        egraph,
+        TensorCost {
+            egraph,
+            cost_model: &cost_model,
+        },
+    );
+    let (_, best) = extractor.find_best(root);
+    best.as_ref().last().unwrap().clone()
+}
+
+#[test]
+fn greedy_extraction_avoids_blacklisted_nodes() {
+    for (blacklisted, allowed) in [(0, 1), (1, 0)] {
+        let (mut egraph, root, input_x, input_y) = merged_inputs();
+        let inputs = [input_x, input_y];
+        egraph
+            .analysis
+            .blacklist_nodes
+            .insert(inputs[blacklisted].clone());
+        assert_eq!(greedy(&egraph, root), inputs[allowed]);
+    }
+}
+
+#[test]
+fn ilp_data_lists_blacklisted_nodes() {
+    let (mut egraph, root, input_x, _) = merged_inputs();
+    egraph.analysis.blacklist_nodes.insert(input_x.clone());
+    let cost_model = CostModel::new();
+    let (_, _, _, _, _, _, i_to_nodes, blacklist_i) = prep_ilp_data(&egraph, root, &cost_model);
+    assert_eq!(blacklist_i.len(), 1);
+    assert_eq!(*i_to_nodes[blacklist_i[0]], input_x);
+}
+
+#[test]
+fn solution_picking_blacklisted_node_is_rejected() {
+    let (mut egraph, root, input_x, input_y) = merged_inputs();
+    egraph.analysis.blacklist_nodes.insert(input_x.clone());
+    let cost_model = CostModel::new();
+    let (m_id_map, _, _, _, g_i, _, i_to_nodes, blacklist_i) =
+        prep_ilp_data(&egraph, root, &cost_model);
+
+    // Pick every node but the other input of the root eclass
+    let pick = |node: &Mdl| -> Vec<i32> {
+        i_to_nodes
+            .iter()
+            .map(|n| (*n == node || !(**n == input_x || **n == input_y)) as i32)
+            .collect()
+    };
+
+    let err =
+        picked_nodes(&pick(&input_x), &m_id_map, &g_i, &i_to_nodes, &blacklist_i).unwrap_err();
+    assert!(err.contains("blacklisted"), "{}", err);
+
+    let picked = picked_nodes(&pick(&input_y), &m_id_map, &g_i, &i_to_nodes, &blacklist_i).unwrap();
+    assert_eq!(picked[&root], input_y);
+}
+
+#[test]
+fn protected_nodes_keep_their_op() {
+    let (mut egraph, root, input_x, input_y) = merged_inputs();
+    let expr: RecExpr<Mdl> = "(input y@2_3 1)".parse().unwrap();
+    let protected: HashSet<Id> = [Id::from(expr.as_ref().len() - 1)].into_iter().collect();
+    assert_eq!(protect_nodes(&mut egraph, &expr, &protected), 1);
+    assert!(egraph.analysis.blacklist_nodes.contains(&input_x));
+    assert_eq!(greedy(&egraph, root), input_y);
+}
+
+#[test]
+fn cost_sums_saturate_below_infinity() {
+    assert_eq!(add_costs(MAX_COST, MAX_COST), MAX_COST);
+    assert_eq!(add_costs(1.0, 2.0), 3.0);
+    // Only blacklisted enodes are infinitely costly
+    assert_eq!(add_costs(f64::INFINITY, 1.0), f64::INFINITY);
+    assert_eq!(saturate_cost(f64::INFINITY), MAX_COST);
+    assert_eq!(saturate_cost(f64::NAN), MAX_COST);
+}
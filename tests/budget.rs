@@ -0,0 +1,32 @@
+use std::time::Duration;
+use tensat::budget::{region_share, stop_saturation};
+
+#[test]
+fn saturation_continues_without_history() {
+    assert_eq!(stop_saturation(&[100], &[], 10.0), None);
+    assert_eq!(stop_saturation(&[100, 200], &[1.0], 10.0), None);
+}
+
+#[test]
+fn saturation_stops_once_the_egraph_is_stable() {
+    let reason = stop_saturation(&[100, 1000, 1005], &[1.0, 2.0], 100.0).unwrap();
+    assert!(reason.contains("stabilized"), "{}", reason);
+}
+
+#[test]
+fn saturation_stops_before_overrunning_its_share() {
+    // 1000 -> 2000 nodes predicts 3000 after the next iteration, so it takes
+    // about 1.5 times as long as the last one
+    assert_eq!(stop_saturation(&[1000, 2000], &[4.0], 7.0), None);
+    let reason = stop_saturation(&[1000, 2000], &[4.0], 5.0).unwrap();
+    assert!(reason.contains("Time budget"), "{}", reason);
+}
+
+#[test]
+fn regions_share_the_budget_with_the_enclosing_graph() {
+    // Two regions then the enclosing graph split 60s
+    assert_eq!(region_share(Duration::from_secs(60), 2), 20);
+    // Time a region didn't use goes to the ones after it
+    assert_eq!(region_share(Duration::from_secs(50), 1), 25);
+    assert_eq!(region_share(Duration::from_millis(500), 3), 1);
+}
@@ -0,0 +1,113 @@
+use egg::{Id, RecExpr};
+use std::collections::{HashMap, HashSet};
+use tensat::input::{ffi, CppGraphConverter};
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::passes::canonicalize_graph;
+
+/// Canonical form of the graph returning `op`, keeping the first (innermost)
+/// node of each op named in `keep`
+fn canonicalize(op: &str, keep: &[&str]) -> String {
+    let expr: RecExpr<Mdl> = format!("(ReturnOp (Vec {}))", op).parse().unwrap();
+    let nodes = expr.as_ref();
+    let keep: HashSet<Id> = keep
+        .iter()
+        .map(|op| {
+            nodes
+                .iter()
+                .position(|node| node.to_string() == *op)
+                .unwrap()
+        })
+        .map(Id::from)
+        .collect();
+    let analysis = TensorAnalysis::new(&HashMap::new(), &[]);
+    canonicalize_graph(&expr, analysis, &keep).to_string()
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn identity_ops_are_dropped_unless_kept() {
+    let x = "(input x@2_3 0)";
+    let transpose = format!("(TransposeOp {} (Vec 0 1))", x);
+    let reshape = format!("(ReshapeOp {} (Vec 2 3))", x);
+    for op in [&transpose, &reshape] {
+        assert_eq!(canonicalize(op, &[]), format!("(ReturnOp (Vec {}))", x));
+    }
+    assert!(canonicalize(&transpose, &["TransposeOp"]).contains("TransposeOp"));
+    assert!(canonicalize(&reshape, &["ReshapeOp"]).contains("ReshapeOp"));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn chains_of_shape_ops_fold() {
+    let x = "(input x@2_3_4 0)";
+    let transposes = format!("(TransposeOp (TransposeOp {} (Vec 1 2 0)) (Vec 1 2 0))", x);
+    assert_eq!(
+        canonicalize(&transposes, &[]),
+        format!("(ReturnOp (Vec (TransposeOp {} (Vec 2 0 1))))", x)
+    );
+    let reshapes = format!("(ReshapeOp (ReshapeOp {} (Vec 24)) (Vec 4 6))", x);
+    assert_eq!(
+        canonicalize(&reshapes, &[]),
+        format!("(ReturnOp (Vec (ReshapeOp {} (Vec 4 6))))", x)
+    );
+    // A kept inner op is not folded away
+    assert_eq!(
+        canonicalize(&reshapes, &["ReshapeOp"]),
+        format!(
+            "(ReturnOp (Vec (ReshapeOp (ReshapeOp {} (Vec 24)) (Vec 4 6))))",
+            x
+        )
+    );
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn shape_ops_of_splats_fold_into_the_constant() {
+    let one = 1.0f32.to_bits() as i32;
+    let splat = format!("(ConstantOp {} (Vec 6))", one);
+    assert_eq!(
+        canonicalize(&format!("(ReshapeOp {} (Vec 2 3))", splat), &[]),
+        format!("(ReturnOp (Vec (ConstantOp {} (Vec 2 3))))", one)
+    );
+    let splat = format!("(ConstantOp {} (Vec 2 3))", one);
+    assert_eq!(
+        canonicalize(&format!("(TransposeOp {} (Vec 1 0))", splat), &[]),
+        format!("(ReturnOp (Vec (ConstantOp {} (Vec 3 2))))", one)
+    );
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn custom_calls_and_bit_generators_are_not_shared() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let gemm = converter.register_custom_call("cublas_gemm", "");
+    let y = converter.new_custom_call_op(&[x], gemm, ffi::Type::f32, &[2, 3]);
+    let z = converter.new_custom_call_op(&[x], gemm, ffi::Type::f32, &[2, 3]);
+    let state = converter.new_input(1, &[2], ffi::Type::i32);
+    let a = converter.new_rng_bit_generator_op(state, 2, ffi::Type::f32, &[2, 3]);
+    let b = converter.new_rng_bit_generator_op(state, 2, ffi::Type::f32, &[2, 3]);
+    let (a, b) = (converter.new_index(1, a), converter.new_index(1, b));
+    converter.new_return_op(&[y, z, a, b]);
+
+    let analysis = TensorAnalysis::new(&HashMap::new(), &[]);
+    let canonical = canonicalize_graph(converter.rec_expr_ref(), analysis, &HashSet::new());
+    let count =
+        |matches: fn(&Mdl) -> bool| canonical.as_ref().iter().filter(|n| matches(n)).count();
+    assert_eq!(count(|n| matches!(n, Mdl::CustomCallOp(_))), 2);
+    assert_eq!(count(|n| matches!(n, Mdl::RngBitGeneratorOp(_))), 2);
+    // The operands of the calls are still shared
+    assert_eq!(count(|n| matches!(n, Mdl::Vec(v) if v.len() == 1)), 1);
+}
@@ -0,0 +1,63 @@
+mod common;
+
+use common::{analysis, commute_add, commute_mul, saturate, MUL_ADD};
+use egg::{RecExpr, Rewrite, Runner};
+use tensat::checkpoint::SaturationState;
+use tensat::merge::SerializedEGraph;
+use tensat::model::{Mdl, TensorAnalysis};
+
+/// Saturate MUL_ADD with `rules` and save the state through json
+fn saved_state(rules: &[Rewrite<Mdl, TensorAnalysis>]) -> SaturationState {
+    let runner = saturate(rules);
+    let iterations = runner.iterations.len() - 1;
+    let state = SaturationState::new(&runner.egraph, runner.roots[0], iterations);
+    SaturationState::from_json(&state.to_json()).unwrap()
+}
+
+#[test]
+fn states_with_out_of_range_eclasses_are_rejected() {
+    let state = SaturationState {
+        egraph: SerializedEGraph {
+            classes: vec![vec![("Num".to_string(), vec![])]],
+            root: 1,
+        },
+        iterations: 3,
+    };
+    assert!(SaturationState::from_json(&state.to_json()).is_err());
+    assert!(SaturationState::from_json("{}").is_err());
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn resumed_saturation_keeps_earlier_rewrites() {
+    let expr: RecExpr<Mdl> = MUL_ADD.parse().unwrap();
+    let state = saved_state(&[commute_add()]);
+    let (egraph, root) = state.restore(&expr, analysis()).unwrap();
+    let mut egraph = Runner::default()
+        .with_egraph(egraph)
+        .with_iter_limit(2)
+        .run(&[commute_mul()])
+        .egraph;
+    for found in [
+        "(MulOp (AddOp (input b@2_3 1) (input a@2_3 0)) (input c@2_3 2))",
+        "(MulOp (input c@2_3 2) (AddOp (input b@2_3 1) (input a@2_3 0)))",
+    ] {
+        let found = egraph.add_expr(&found.parse().unwrap());
+        egraph.rebuild();
+        assert_eq!(egraph.find(found), egraph.find(root));
+    }
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn states_of_other_graphs_are_not_resumed() {
+    let other: RecExpr<Mdl> = "(AddOp (input a@2_3 0) (input c@2_3 2))".parse().unwrap();
+    let state = saved_state(&[commute_add()]);
+    assert!(state.restore(&other, analysis()).is_err());
+}
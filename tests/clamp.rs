@@ -0,0 +1,123 @@
+mod common;
+
+use common::equal;
+use egg::RecExpr;
+use std::collections::HashMap;
+use tensat::interpreter::numerically_equivalent;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::rewrites::clamp_rules;
+
+/// Splat constant of `value` with dimensions `dims` ("" for a scalar)
+fn constant(value: f32, dims: &str) -> String {
+    format!("(ConstantOp {} (Vec {}))", value.to_bits() as i32, dims)
+}
+
+fn clamp(lo: &str, x: &str, hi: &str) -> String {
+    format!("(ClampOp {} {} {})", lo, x, hi)
+}
+
+const X: &str = "(input x@2_3 0)";
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn clamp_of_the_same_clamp_is_the_clamp() {
+    let (lo, hi) = ("(input lo@ 1)", "(input hi@ 2)");
+    let inner = clamp(lo, X, hi);
+    assert!(equal(&clamp_rules(), &clamp(lo, &inner, hi), &inner));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn nested_clamps_clamp_to_the_intersection() {
+    let c = |v: f32| constant(v, "");
+    let nested = clamp(&c(0.0), &clamp(&c(-1.0), X, &c(6.0)), &c(8.0));
+    let merged = format!(
+        "(ClampOp (MaxOp {} {}) {} (MinOp {} {}))",
+        c(-1.0),
+        c(0.0),
+        X,
+        c(6.0),
+        c(8.0)
+    );
+    assert!(equal(&clamp_rules(), &nested, &merged));
+
+    // Disjoint ranges clamp to the outer range
+    let disjoint = clamp(&c(2.0), &clamp(&c(0.0), X, &c(1.0)), &c(3.0));
+    let merged = format!(
+        "(ClampOp (MaxOp {} {}) {} (MinOp {} {}))",
+        c(0.0),
+        c(2.0),
+        X,
+        c(1.0),
+        c(3.0)
+    );
+    assert!(!equal(&clamp_rules(), &disjoint, &merged));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn infinite_bounds_become_min_and_max() {
+    let hi = "(input hi@2_3 1)";
+    let below = clamp(&constant(f32::NEG_INFINITY, ""), X, hi);
+    assert!(equal(
+        &clamp_rules(),
+        &below,
+        &format!("(MinOp {} {})", X, hi)
+    ));
+    let lo = "(input lo@2_3 1)";
+    let above = clamp(lo, X, &constant(f32::INFINITY, "2 3"));
+    assert!(equal(
+        &clamp_rules(),
+        &above,
+        &format!("(MaxOp {} {})", X, lo)
+    ));
+
+    // A MinOp can't take a scalar bound
+    let scalar = "(input hi@ 1)";
+    let below = clamp(&constant(f32::NEG_INFINITY, ""), X, scalar);
+    assert!(!equal(
+        &clamp_rules(),
+        &below,
+        &format!("(MinOp {} {})", X, scalar)
+    ));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn clamp_evaluates_like_min_of_max() {
+    let (lo, hi) = ("(input lo@2_3 1)", "(input hi@2_3 2)");
+    let a: RecExpr<Mdl> = clamp(lo, X, hi).parse().unwrap();
+    let b: RecExpr<Mdl> = format!("(MinOp (MaxOp {} {}) {})", X, lo, hi)
+        .parse()
+        .unwrap();
+    let analysis = || TensorAnalysis::new(&HashMap::new(), &[]);
+    assert_eq!(numerically_equivalent(&a, &b, analysis, 1e-6, 0), Ok(true));
+
+    let relu6: RecExpr<Mdl> = clamp(&constant(0.0, ""), X, &constant(6.0, ""))
+        .parse()
+        .unwrap();
+    let expanded: RecExpr<Mdl> = format!(
+        "(MinOp (MaxOp {} {}) {})",
+        X,
+        constant(0.0, "2 3"),
+        constant(6.0, "2 3")
+    )
+    .parse()
+    .unwrap();
+    assert_eq!(
+        numerically_equivalent(&relu6, &expanded, analysis, 1e-6, 0),
+        Ok(true)
+    );
+}
@@ -0,0 +1,125 @@
+mod common;
+
+use common::{equal, shape};
+use egg::Id;
+use tensat::collective::{
+    all_gather_dims, all_to_all_dims, reduce_scatter_dims, replica_groups, Reduction,
+};
+use tensat::input::{ffi, CppGraphConverter};
+use tensat::mock_cost::get_cost;
+use tensat::model::Mdl;
+use tensat::rewrites::collective_rules;
+
+#[test]
+fn replica_groups_split_evenly() {
+    assert_eq!(
+        replica_groups(&[0, 2, 1, 3], 2),
+        Ok(vec![vec![0, 2], vec![1, 3]])
+    );
+    assert!(replica_groups(&[0, 1, 2], 2).is_err());
+    assert!(replica_groups(&[0, 1, 1, 2], 2).is_err());
+    assert!(replica_groups(&[-1, 0], 2).is_err());
+    assert!(replica_groups(&[0, 1], 0).is_err());
+    assert_eq!(Reduction::from_num(3), Ok(Reduction::Max));
+    assert!(Reduction::from_num(4).is_err());
+}
+
+#[test]
+fn collectives_resize_their_dimension() {
+    assert_eq!(all_gather_dims(&[2, 8], 0, 4), Ok(vec![8, 8]));
+    assert_eq!(reduce_scatter_dims(&[2, 8], 1, 4), Ok(vec![2, 2]));
+    assert!(reduce_scatter_dims(&[2, 8], 0, 4).is_err());
+    assert!(all_gather_dims(&[2, 8], 2, 4).is_err());
+    assert_eq!(all_to_all_dims(&[2, 8], 1, 0, 4), Ok(vec![8, 2]));
+    assert_eq!(all_to_all_dims(&[2, 8], 1, 1, 4), Ok(vec![2, 8]));
+}
+
+#[test]
+fn collectives_keep_their_replica_groups() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 8], ffi::Type::bf16);
+    let gathered = converter.new_all_gather_op(x, 0, &[0, 2, 1, 3], 2, &[4, 8]);
+    assert_eq!(converter.tensor_shape(gathered), vec![4, 8]);
+    assert!(converter.tensor_dtype(gathered) == ffi::Type::bf16);
+
+    let nodes = converter.rec_expr_ref().as_ref();
+    let num = |id: &Id| match &nodes[usize::from(*id)] {
+        Mdl::Num(n) => *n,
+        other => panic!("expected a Num, got {}", other),
+    };
+    match &nodes[gathered.id as usize] {
+        Mdl::AllGatherOp([_, dim, groups, group_size]) => {
+            assert_eq!((num(dim), num(group_size)), (0, 2));
+            match &nodes[usize::from(*groups)] {
+                Mdl::Vec(ids) => assert_eq!(ids.iter().map(num).collect::<Vec<_>>(), [0, 2, 1, 3]),
+                other => panic!("expected a Vec, got {}", other),
+            }
+        }
+        other => panic!("expected an AllGatherOp, got {}", other),
+    }
+}
+
+#[test]
+#[should_panic(expected = "Invalid AllReduceOp")]
+fn uneven_replica_groups_are_rejected() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 8], ffi::Type::f32);
+    converter.new_all_reduce_op(x, &[0, 1, 2], 2, 0, &[2, 8]);
+}
+
+#[test]
+fn cost_is_the_communication() {
+    let cost = |op: ffi::Ops, int_args: Vec<i64>| {
+        get_cost(
+            op,
+            vec![shape(&[4, 8])],
+            vec![ffi::Type::f32],
+            vec![shape(&[0, 1, 2, 3])],
+            int_args,
+        )
+    };
+    let all_reduce = cost(ffi::Ops::AllReduceOp, vec![4, 0]);
+    let reduce_scatter = cost(ffi::Ops::ReduceScatterOp, vec![0, 4, 0]);
+    let all_gather = cost(ffi::Ops::AllGatherOp, vec![0, 4]);
+    assert_eq!(all_reduce, 2 * reduce_scatter);
+    // A gather sends all of the operand to each other replica
+    assert_eq!(all_gather, 4 * reduce_scatter);
+    assert!(all_reduce > 0);
+    // A group of one doesn't communicate
+    assert_eq!(cost(ffi::Ops::AllReduceOp, vec![1, 0]), 0);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn elementwise_ops_move_before_collectives() {
+    let (x, y) = ("(input x@2_8 0)", "(input y@2_8 1)");
+    let all_reduce =
+        |x: &str, reduction: i64| format!("(AllReduceOp {} (Vec 0 1 2 3) 4 {})", x, reduction);
+    assert!(equal(
+        &collective_rules(),
+        &format!("(AddOp {} {})", all_reduce(x, 0), all_reduce(y, 0)),
+        &all_reduce(&format!("(AddOp {} {})", x, y), 0)
+    ));
+    // The sum of maxima isn't the maximum of sums
+    assert!(!equal(
+        &collective_rules(),
+        &format!("(AddOp {} {})", all_reduce(x, 3), all_reduce(y, 3)),
+        &all_reduce(&format!("(AddOp {} {})", x, y), 3)
+    ));
+    assert!(equal(
+        &collective_rules(),
+        &format!("(ExpOp (AllGatherOp {} 0 (Vec 0 1) 2))", x),
+        &format!("(AllGatherOp (ExpOp {}) 0 (Vec 0 1) 2)", x)
+    ));
+    assert!(equal(
+        &collective_rules(),
+        &format!(
+            "(AllGatherOp (ReduceScatterOp {} 1 (Vec 0 1 2 3) 4 0) 1 (Vec 0 1 2 3) 4)",
+            x
+        ),
+        &all_reduce(x, 0)
+    ));
+}
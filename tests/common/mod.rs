@@ -0,0 +1,60 @@
+//! Helpers shared by the integration tests. Each test crate uses only some of
+//! them.
+#![allow(dead_code)]
+
+use egg::{rewrite, EGraph, RecExpr, Rewrite, Runner};
+use std::collections::HashMap;
+use tensat::input::ffi;
+use tensat::model::{Mdl, TensorAnalysis};
+
+pub fn shape(dims: &[i64]) -> ffi::Shape {
+    ffi::Shape {
+        shape: dims.to_vec(),
+    }
+}
+
+/// Whether `rules` find `a` and `b` equal
+pub fn equal(rules: &[Rewrite<Mdl, TensorAnalysis>], a: &str, b: &str) -> bool {
+    equal_with(TensorAnalysis::new(&HashMap::new(), &[]), rules, a, b)
+}
+
+/// Whether `rules` find `a` and `b` equal, starting from `analysis`
+pub fn equal_with(
+    analysis: TensorAnalysis,
+    rules: &[Rewrite<Mdl, TensorAnalysis>],
+    a: &str,
+    b: &str,
+) -> bool {
+    let a: RecExpr<Mdl> = a.parse().unwrap();
+    let mut runner = Runner::default()
+        .with_egraph(EGraph::new(analysis))
+        .with_expr(&a)
+        .run(rules);
+    let b = runner.egraph.add_expr(&b.parse().unwrap());
+    runner.egraph.find(b) == runner.egraph.find(runner.roots[0])
+}
+
+/// Graph saturated by `saturate`
+pub const MUL_ADD: &str = "(MulOp (AddOp (input a@2_3 0) (input b@2_3 1)) (input c@2_3 2))";
+
+pub fn analysis() -> TensorAnalysis {
+    TensorAnalysis::new(&HashMap::new(), &[])
+}
+
+pub fn commute_add() -> Rewrite<Mdl, TensorAnalysis> {
+    rewrite!("commute-add"; "(AddOp ?x ?y)" => "(AddOp ?y ?x)")
+}
+
+pub fn commute_mul() -> Rewrite<Mdl, TensorAnalysis> {
+    rewrite!("commute-mul"; "(MulOp ?x ?y)" => "(MulOp ?y ?x)")
+}
+
+/// Runner after saturating MUL_ADD with `rules` for 2 iterations
+pub fn saturate(rules: &[Rewrite<Mdl, TensorAnalysis>]) -> Runner<Mdl, TensorAnalysis, ()> {
+    let expr: RecExpr<Mdl> = MUL_ADD.parse().unwrap();
+    Runner::default()
+        .with_egraph(EGraph::new(analysis()))
+        .with_expr(&expr)
+        .with_iter_limit(2)
+        .run(rules)
+}
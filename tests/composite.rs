@@ -0,0 +1,113 @@
+mod common;
+
+use common::{equal_with, shape};
+use std::collections::HashMap;
+use tensat::composite::{composite_expansion_rules, same_shape_as_first_operand, CompositeOpDef};
+use tensat::input::{ffi, CppGraphConverter};
+use tensat::model::{TensorAnalysis, TensorData};
+
+/// Shape function of a 2D transpose
+fn transposed(operands: &[&TensorData]) -> TensorData {
+    let mut shape = operands[0].shapes[0];
+    shape.swap(0, 1);
+    TensorData {
+        shapes: vec![shape],
+        n_dims: vec![2],
+        name: None,
+        dtype: operands[0].dtype,
+        constant: operands[0].constant,
+    }
+}
+
+/// Composite ops 0 (doubling, as a sum) and 1 (2D transpose)
+fn composite_ops() -> Vec<CompositeOpDef> {
+    vec![
+        CompositeOpDef {
+            name: "double".to_string(),
+            num_operands: 1,
+            shape_fn: same_shape_as_first_operand,
+            expansion: "(AddOp ?x0 ?x0)".to_string(),
+            cost: 1.0,
+        },
+        CompositeOpDef {
+            name: "transpose2d".to_string(),
+            num_operands: 1,
+            shape_fn: transposed,
+            expansion: "(TransposeOp ?x0 (Vec 1 0))".to_string(),
+            cost: 1.0,
+        },
+    ]
+}
+
+fn converter() -> CppGraphConverter {
+    let mut converter = CppGraphConverter::default();
+    for def in composite_ops() {
+        converter.register_composite_op_def(def);
+    }
+    converter
+}
+
+#[test]
+fn composite_ops_have_the_inferred_shape() {
+    let mut converter = converter();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::i32);
+    let doubled = converter
+        .new_composite_op(&[x], 0, &vec![shape(&[2, 3])])
+        .unwrap();
+    let t = converter
+        .new_composite_op(&[x], 1, &vec![shape(&[3, 2])])
+        .unwrap();
+    assert_eq!(converter.tensor_shape(doubled), vec![2, 3]);
+    assert_eq!(converter.tensor_shape(t), vec![3, 2]);
+    assert!(converter.tensor_dtype(t) == ffi::Type::i32);
+}
+
+#[test]
+fn invalid_composite_ops_are_errors() {
+    let mut converter = converter();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let error = converter
+        .new_composite_op(&[x], 1, &vec![shape(&[2, 3])])
+        .unwrap_err();
+    assert!(error.contains("transpose2d has shapes"), "{}", error);
+    for composite_id in [2, -1] {
+        let error = converter
+            .new_composite_op(&[x], composite_id, &vec![shape(&[2, 3])])
+            .unwrap_err();
+        assert!(error.contains("unknown composite op"), "{}", error);
+    }
+    let error = converter
+        .new_composite_op(&[x, x], 0, &vec![shape(&[2, 3])])
+        .unwrap_err();
+    assert!(error.contains("expects 1 operands"), "{}", error);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn composite_ops_are_equal_to_their_expansion() {
+    let defs = composite_ops();
+    let analysis = || TensorAnalysis::new(&HashMap::new(), &defs);
+    let rules = composite_expansion_rules(&defs);
+    let x = "(input x@2_3 0)";
+    assert!(equal_with(
+        analysis(),
+        &rules,
+        &format!("(CompositeOp {} 0)", x),
+        &format!("(AddOp {} {})", x, x)
+    ));
+    assert!(equal_with(
+        analysis(),
+        &rules,
+        &format!("(CompositeOp {} 1)", x),
+        &format!("(TransposeOp {} (Vec 1 0))", x)
+    ));
+    assert!(!equal_with(
+        analysis(),
+        &rules,
+        &format!("(CompositeOp {} 0)", x),
+        &format!("(TransposeOp {} (Vec 1 0))", x)
+    ));
+}
@@ -0,0 +1,130 @@
+use tensat::config::ExtractorKind;
+use tensat::input::{ffi, infer_dtypes, infer_dtypes_with_regions, CppGraphConverter};
+use tensat::model::{unify_branch_results, Mdl, TensorData, MAX_DIM};
+
+fn value(dims: &[i32], dtype: ffi::Type) -> TensorData {
+    let mut shape = [0; MAX_DIM];
+    shape[..dims.len()].copy_from_slice(dims);
+    TensorData {
+        shapes: vec![shape],
+        n_dims: vec![dims.len()],
+        name: None,
+        dtype,
+        constant: false,
+    }
+}
+
+fn dims(data: &TensorData, i: usize) -> Vec<i32> {
+    data.shapes[i][..data.n_dims[i]].to_vec()
+}
+
+#[test]
+fn branch_results_unify_to_unknown_dims() {
+    let f32 = ffi::Type::f32;
+    let then = [value(&[4, 8], f32), value(&[], ffi::Type::i32)];
+    let otherwise = [value(&[4, 2], f32), value(&[], ffi::Type::i32)];
+    let unified = unify_branch_results(&[&then[..], &otherwise[..]]).unwrap();
+    assert_eq!(dims(&unified, 0), vec![4, -1]);
+    assert_eq!(dims(&unified, 1), Vec::<i32>::new());
+    assert!(unified.dtype == f32);
+
+    let unified = unify_branch_results(&[&then[..], &then[..]]).unwrap();
+    assert_eq!(dims(&unified, 0), vec![4, 8]);
+}
+
+#[test]
+fn branches_must_yield_matching_values() {
+    let f32 = ffi::Type::f32;
+    let then = [value(&[4, 8], f32)];
+    assert!(unify_branch_results(&[&then[..], &[value(&[4], f32)][..]]).is_err());
+    assert!(unify_branch_results(&[&then[..], &[value(&[4, 8], ffi::Type::bf16)][..]]).is_err());
+    assert!(unify_branch_results(&[&then[..], &[]]).is_err());
+    assert!(unify_branch_results(&[]).is_err());
+}
+
+/// `(x + y if p else x) * x`, with the handle of the if
+fn converter() -> (CppGraphConverter, ffi::TensorHandle) {
+    let mut converter = CppGraphConverter::default();
+    let p = converter.new_input(0, &[], ffi::Type::i1);
+    let x = converter.new_input(1, &[4, 8], ffi::Type::f32);
+    let y = converter.new_input(2, &[4, 8], ffi::Type::f32);
+
+    converter.begin_region();
+    let bx = converter.new_input(0, &[4, 8], ffi::Type::f32);
+    let by = converter.new_input(1, &[4, 8], ffi::Type::f32);
+    let sum = converter.new_add_op(bx, by, &[4, 8]);
+    let then = converter.end_region(&[sum]);
+
+    converter.begin_region();
+    let bx = converter.new_input(0, &[4, 8], ffi::Type::f32);
+    converter.new_input(1, &[4, 8], ffi::Type::f32);
+    let otherwise = converter.end_region(&[bx]);
+
+    let if_op = converter.new_if_op(p, &[x, y], then, otherwise);
+    let result = converter.new_index(0, if_op);
+    let product = converter.new_mul_op(result, x, &[4, 8]);
+    converter.new_return_op(&[product]);
+    (converter, if_op)
+}
+
+#[test]
+fn if_op_has_the_unified_shape_of_its_branches() {
+    let (converter, if_op) = converter();
+    assert_eq!(converter.tensor_shape(if_op), vec![4, 8]);
+    let nodes = converter.rec_expr_ref().as_ref();
+    match &nodes[if_op.id as usize] {
+        Mdl::IfOp([_, inputs, then, otherwise]) => {
+            assert!(matches!(&nodes[usize::from(*inputs)], Mdl::Vec(v) if v.len() == 2));
+            assert_eq!(nodes[usize::from(*then)], Mdl::Num(0));
+            assert_eq!(nodes[usize::from(*otherwise)], Mdl::Num(1));
+        }
+        other => panic!("expected an IfOp, got {}", other),
+    }
+}
+
+#[test]
+fn if_results_are_typed_by_their_branches() {
+    let expr = "(Index 0 (IfOp (input p@@i1 0) (Vec (input x@4@i32 1)) 0 1))"
+        .parse()
+        .unwrap();
+    let region_dtypes = vec![vec![ffi::Type::bf16], vec![ffi::Type::bf16]];
+    let dtypes = infer_dtypes_with_regions(&expr, &region_dtypes);
+    assert!(*dtypes.last().unwrap() == ffi::Type::bf16);
+    assert!(dtypes[dtypes.len() - 2] == ffi::Type::bf16);
+    // Without the regions the results are f32, not the type of the predicate
+    assert!(*infer_dtypes(&expr).last().unwrap() == ffi::Type::f32);
+}
+
+#[test]
+#[should_panic(expected = "Invalid IfOp")]
+fn branches_of_different_ranks_are_rejected() {
+    let mut converter = CppGraphConverter::default();
+    let p = converter.new_input(0, &[], ffi::Type::i1);
+    converter.begin_region();
+    let a = converter.new_input(0, &[4], ffi::Type::f32);
+    let then = converter.end_region(&[a]);
+    converter.begin_region();
+    let b = converter.new_input(0, &[4, 1], ffi::Type::f32);
+    let otherwise = converter.end_region(&[b]);
+    converter.new_if_op(p, &[], then, otherwise);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn if_op_converts_back_to_nodes() {
+    let (mut converter, if_op) = converter();
+    let config = converter.config_mut();
+    config.rule_file = format!("{}/converted.txt", env!("CARGO_MANIFEST_DIR"));
+    config.iter_limit = 3;
+    config.extractor = ExtractorKind::Greedy;
+    let graph = converter.optimize();
+    let node = graph.nodes.iter().find(|n| n.name == "IfOp").unwrap();
+    assert_eq!(node.origin, if_op.id);
+    assert_eq!(node.shape.len(), 1);
+    assert_eq!(node.shape[0].shape, vec![4, 8]);
+    assert!(node.dtype == ffi::Type::f32);
+    assert_eq!(converter.region_graph(1).results.len(), 1);
+}
@@ -0,0 +1,91 @@
+use std::env::temp_dir;
+use std::fs::write;
+use tensat::config::*;
+
+#[test]
+fn missing_keys_keep_their_default() {
+    let config = OptimizeConfig::from_toml_str(
+        r#"
+        rule_file = "rules.txt"
+        n_sec = 5
+        node_limit = 1000
+        extractor = "greedy"
+        ilp_num_threads = 2
+        log_filter = "egg=info"
+        seed = 42
+        "#,
+    )
+    .unwrap();
+    let default = OptimizeConfig::default();
+    assert_eq!(config.rule_file, "rules.txt");
+    assert_eq!(config.n_sec, 5);
+    assert_eq!(config.node_limit, 1000);
+    assert_eq!(config.extractor, ExtractorKind::Greedy);
+    assert_eq!(config.ilp_num_threads, 2);
+    assert_eq!(config.log_filter.as_deref(), Some("egg=info"));
+    assert_eq!(config.seed, 42);
+    assert_eq!(config.iter_limit, default.iter_limit);
+    assert_eq!(config.ilp_time_sec, default.ilp_time_sec);
+    assert_eq!(config.cheap_budget, default.cheap_budget);
+}
+
+#[test]
+fn unknown_keys_are_rejected() {
+    let err = OptimizeConfig::from_toml_str("node_limt = 10").unwrap_err();
+    assert!(err.contains("node_limt"), "{}", err);
+    assert!(OptimizeConfig::from_toml_str("extractor = \"beam\"").is_err());
+}
+
+#[test]
+fn loads_from_a_file() {
+    let path = temp_dir().join("tensat_config_test.toml");
+    write(&path, "iter_limit = 3\nnumeric_check = true\n").unwrap();
+    let config = OptimizeConfig::from_toml(path.to_str().unwrap()).unwrap();
+    assert_eq!(config.iter_limit, 3);
+    assert!(config.numeric_check);
+
+    let err = OptimizeConfig::from_toml("/nonexistent/tensat.toml").unwrap_err();
+    assert!(err.contains("/nonexistent/tensat.toml"), "{}", err);
+}
+
+#[test]
+fn sharing_bonus_is_off_by_default() {
+    assert_eq!(OptimizeConfig::default().sharing_bonus, None);
+    let config = OptimizeConfig::from_toml_str("sharing_bonus = 0.5").unwrap();
+    assert_eq!(config.sharing_bonus, Some(0.5));
+}
+
+#[test]
+fn objective_defaults_to_cost() {
+    let default = OptimizeConfig::default();
+    assert_eq!(default.objective, Objective::Cost);
+    assert_eq!(default.max_cost_regression, None);
+    let config =
+        OptimizeConfig::from_toml_str("objective = \"node_count\"\nmax_cost_regression = 0.05")
+            .unwrap();
+    assert_eq!(config.objective, Objective::NodeCount);
+    assert_eq!(config.max_cost_regression, Some(0.05));
+}
+
+#[test]
+fn ilp_solver_is_scip_by_default() {
+    assert_eq!(OptimizeConfig::default().ilp_solver, IlpSolver::Scip);
+    let config = OptimizeConfig::from_toml_str("ilp_solver = \"cbc\"\nilp_mip_gap = 0.01").unwrap();
+    assert_eq!(config.ilp_solver.name(), "cbc");
+    assert_eq!(config.ilp_mip_gap, Some(0.01));
+    assert!(OptimizeConfig::from_toml_str("ilp_solver = \"cplex\"").is_err());
+}
+
+#[test]
+fn time_budget_is_off_by_default() {
+    assert_eq!(OptimizeConfig::default().time_budget_sec, None);
+    let config = OptimizeConfig::from_toml_str("time_budget_sec = 120").unwrap();
+    assert_eq!(config.time_budget_sec, Some(120));
+}
+
+#[test]
+fn gelu_approximation_is_off_by_default() {
+    assert!(!OptimizeConfig::default().approximate_gelu);
+    let config = OptimizeConfig::from_toml_str("approximate_gelu = true").unwrap();
+    assert!(config.approximate_gelu);
+}
@@ -0,0 +1,233 @@
+use egg::{Id, Language, RecExpr};
+use tensat::composite::{same_shape_as_first_operand, CompositeOpDef};
+use tensat::input::{
+    ffi, infer_dtypes, nodes_to_graph, nodes_to_rec_expr, rec_expr_to_nodes, validate_graph,
+};
+use tensat::model::Mdl;
+
+/// Every op of Mdl with its number of operands
+const OPS: &[(&str, usize)] = &[
+    ("input", 2),
+    ("CompareOp", 4),
+    ("BroadcastInDimOp", 2),
+    ("ConvertOp", 2),
+    ("ReduceOp", 4),
+    ("ReshapeOp", 2),
+    ("GatherOp", 10),
+    ("SelectOp", 3),
+    ("ConcatenateOp", 2),
+    ("DotGeneralOp", 7),
+    ("ConvolutionOp", 10),
+    ("PadOp", 5),
+    ("SliceOp", 4),
+    ("TransposeOp", 2),
+    ("MulOp", 2),
+    ("AddOp", 2),
+    ("DivOp", 2),
+    ("SubtractOp", 2),
+    ("MinOp", 2),
+    ("MaxOp", 2),
+    ("ClampOp", 3),
+    ("PowOp", 2),
+    ("RemainderOp", 2),
+    ("AndOp", 2),
+    ("OrOp", 2),
+    ("XorOp", 2),
+    ("NotOp", 1),
+    ("ShiftLeftOp", 2),
+    ("ShiftRightLogicalOp", 2),
+    ("ShiftRightArithmeticOp", 2),
+    ("NegOp", 1),
+    ("TanhOp", 1),
+    ("ExpOp", 1),
+    ("SqrtOp", 1),
+    ("RsqrtOp", 1),
+    ("LogOp", 1),
+    ("SineOp", 1),
+    ("CosineOp", 1),
+    ("AbsOp", 1),
+    ("SignOp", 1),
+    ("FloorOp", 1),
+    ("CeilOp", 1),
+    ("RoundOp", 1),
+    ("ErfOp", 1),
+    ("ComplexOp", 2),
+    ("RealOp", 1),
+    ("ImagOp", 1),
+    ("FftOp", 3),
+    ("CholeskyOp", 2),
+    ("TriangularSolveOp", 6),
+    ("BatchNormInferenceOp", 7),
+    ("BatchNormTrainingOp", 5),
+    ("BatchNormGradOp", 7),
+    ("AllReduceOp", 4),
+    ("AllGatherOp", 4),
+    ("ReduceScatterOp", 5),
+    ("AllToAllOp", 5),
+    ("GeluOp", 2),
+    ("IotaOp", 2),
+    ("ConstantOp", 2),
+    ("DenseConstantOp", 3),
+    ("DynamicUpdateSliceOp", 3),
+    ("DynamicSliceOp", 3),
+    ("ScatterOp", 4),
+    ("SelectAndScatterOp", 6),
+    ("SortOp", 5),
+    ("WhileOp", 3),
+    ("IfOp", 4),
+    ("CustomCallOp", 4),
+    ("RngBitGeneratorOp", 4),
+    ("RngOp", 5),
+    ("TupleOp", 1),
+    ("GetTupleElementOp", 2),
+    ("OptimizationBarrierOp", 1),
+    ("ReturnOp", 1),
+    ("BlackBox", 3),
+    ("Vec", 3),
+    ("Index", 2),
+];
+
+fn composite_ops() -> Vec<CompositeOpDef> {
+    vec![CompositeOpDef {
+        name: "gelu".to_string(),
+        num_operands: 1,
+        shape_fn: same_shape_as_first_operand,
+        expansion: "(GeluOp ?x0 0)".to_string(),
+        cost: 1.0,
+    }]
+}
+
+fn round_trip(expr: &RecExpr<Mdl>) -> RecExpr<Mdl> {
+    let nodes = rec_expr_to_nodes(expr, &composite_ops());
+    assert_eq!(nodes.len(), expr.as_ref().len());
+    nodes_to_rec_expr(&nodes, &composite_ops()).unwrap()
+}
+
+#[test]
+fn every_op_round_trips() {
+    for (op, arity) in OPS {
+        let mut expr = RecExpr::default();
+        let x = expr.add(Mdl::Var("x@2_3".into()));
+        let n = expr.add(Mdl::Num(7));
+        let children: Vec<Id> = (0..*arity).map(|i| if i % 2 == 0 { x } else { n }).collect();
+        expr.add(Mdl::from_op_str(op, children).unwrap());
+        assert_eq!(round_trip(&expr).to_string(), expr.to_string(), "{}", op);
+    }
+}
+
+#[test]
+fn composite_op_round_trips() {
+    let expr: RecExpr<Mdl> = "(ReturnOp (CompositeOp (input x@2_3 0) 0))".parse().unwrap();
+    let nodes = rec_expr_to_nodes(&expr, &composite_ops());
+    let composite = nodes.iter().find(|n| n.name == "CompositeOp").unwrap();
+    assert_eq!(composite.label, "gelu");
+    assert_eq!(composite.operands.len(), 1);
+    assert_eq!(round_trip(&expr).to_string(), expr.to_string());
+}
+
+#[test]
+fn ops_keep_the_names_expected_by_cpp() {
+    let expr: RecExpr<Mdl> = "(BlackBox (input x@2_3 0) 1)".parse().unwrap();
+    let names: Vec<String> = rec_expr_to_nodes(&expr, &[])
+        .into_iter()
+        .map(|n| n.name)
+        .collect();
+    assert_eq!(names, vec!["Var", "Num", "Input", "Num", "blackbox"]);
+}
+
+#[test]
+fn dtypes_follow_compare_and_convert() {
+    let expr: RecExpr<Mdl> =
+        "(SelectOp (CompareOp (input x@2 0) (input y@2 1) 0 0) (ConvertOp (input x@2 0) 0) (input y@2 1))"
+            .parse()
+            .unwrap();
+    let dtypes = infer_dtypes(&expr);
+    let dtype_of = |op: &str| {
+        let i = expr
+            .as_ref()
+            .iter()
+            .position(|n| n.display_op().to_string() == op)
+            .unwrap();
+        dtypes[i]
+    };
+    assert!(dtype_of("input") == ffi::Type::f32);
+    assert!(dtype_of("CompareOp") == ffi::Type::i1);
+    assert!(dtype_of("ConvertOp") == ffi::Type::i32);
+    assert!(dtype_of("SelectOp") == ffi::Type::i32);
+}
+
+#[test]
+fn sort_results_have_the_dtypes_of_their_inputs() {
+    let expr: RecExpr<Mdl> = "(Index 1 (SortOp (Vec (input x@4 0) (input i@4@i32 1)) 0 1 2 0))"
+        .parse()
+        .unwrap();
+    let dtypes = infer_dtypes(&expr);
+    assert!(dtypes[dtypes.len() - 2] == ffi::Type::f32);
+    assert!(*dtypes.last().unwrap() == ffi::Type::i32);
+}
+
+#[test]
+fn graph_has_explicit_results() {
+    let expr: RecExpr<Mdl> = "(ReturnOp (Vec (NegOp (input x@2 0)) (input x@2 0)))"
+        .parse()
+        .unwrap();
+    let graph = nodes_to_graph(rec_expr_to_nodes(&expr, &[]));
+    assert!(validate_graph(&graph).is_ok());
+    assert_eq!(graph.root as usize, graph.nodes.len() - 1);
+    let returned = graph.nodes[graph.root as usize].operands[0] as usize;
+    assert_eq!(graph.results, graph.nodes[returned].operands);
+    assert_eq!(graph.results.len(), 2);
+
+    let mut shuffled = nodes_to_graph(rec_expr_to_nodes(&expr, &[]));
+    shuffled.nodes.swap(0, 2);
+    assert!(validate_graph(&shuffled).is_err());
+}
+
+#[test]
+fn unreachable_nodes_are_not_emitted() {
+    let mut expr = RecExpr::default();
+    let x = expr.add(Mdl::Var("x@2".into()));
+    let n = expr.add(Mdl::Num(0));
+    let orphan = expr.add(Mdl::Num(5));
+    expr.add(Mdl::Vec(vec![orphan, n]));
+    let input = expr.add(Mdl::Input([x, n]));
+    expr.add(Mdl::NegOp([input]));
+
+    let nodes = rec_expr_to_nodes(&expr, &[]);
+    let names: Vec<&str> = nodes.iter().map(|n| n.name.as_str()).collect();
+    assert_eq!(names, vec!["Var", "Num", "Input", "NegOp"]);
+    assert_eq!(nodes[2].operands, vec![0, 1]);
+    assert_eq!(nodes[3].operands, vec![2]);
+}
+
+#[test]
+fn inputs_carry_their_block_arg() {
+    let expr: RecExpr<Mdl> = "(AddOp (input input_3@ 3) (input input_3@ 3))".parse().unwrap();
+    let nodes = rec_expr_to_nodes(&expr, &[]);
+    for node in nodes.iter() {
+        match node.name.as_str() {
+            "Var" | "Input" => assert_eq!(node.block_arg, 3),
+            _ => assert_eq!(node.block_arg, -1),
+        }
+    }
+}
+
+#[test]
+fn converted_nodes_have_no_remark() {
+    let expr: RecExpr<Mdl> = "(NegOp (input input_0@ 0))".parse().unwrap();
+    let nodes = rec_expr_to_nodes(&expr, &[]);
+    assert!(nodes.iter().all(|node| node.remark.is_empty()));
+}
+
+#[test]
+fn nums_wider_than_i32_round_trip() {
+    let big = i64::from(i32::MAX) * 4;
+    let expr: RecExpr<Mdl> = format!("(SliceOp (input x@2 0) (Vec 0) (Vec {}) (Vec 1))", big)
+        .parse()
+        .unwrap();
+    let nodes = rec_expr_to_nodes(&expr, &[]);
+    assert!(nodes
+        .iter()
+        .any(|n| n.name == "Num" && n.value == big && n.operands.is_empty()));
+    assert_eq!(round_trip(&expr).to_string(), expr.to_string());
+}
@@ -0,0 +1,96 @@
+use tensat::convolution::{convolution_dims, ConvDimensionNumbers};
+use tensat::input::{ffi, CppGraphConverter};
+
+/// NCHW input, OIHW kernel, NCHW output
+const NCHW: [i64; 12] = [0, 1, 2, 3, 1, 0, 2, 3, 0, 1, 2, 3];
+
+#[test]
+fn dimension_numbers_are_unflattened() {
+    let dims = ConvDimensionNumbers::from_flat(&NCHW, 2).unwrap();
+    assert_eq!(dims.input_feature, 1);
+    assert_eq!(dims.input_spatial, vec![2, 3]);
+    assert_eq!(dims.kernel_input_feature, 1);
+    assert_eq!(dims.kernel_output_feature, 0);
+    assert_eq!(dims.output_spatial, vec![2, 3]);
+    assert!(ConvDimensionNumbers::from_flat(&NCHW, 1).is_err());
+    assert!(ConvDimensionNumbers::from_flat(&[0, 1, 4, 0, 1, 2, 0, 1, 2], 1).is_err());
+}
+
+#[test]
+fn strided_padded_and_dilated_convolutions() {
+    let conv = |strides: &[i64], padding: &[i64], lhs_dilation: &[i64], rhs_dilation: &[i64]| {
+        convolution_dims(
+            &[8, 3, 32, 32],
+            &[16, 3, 3, 3],
+            strides,
+            padding,
+            lhs_dilation,
+            rhs_dilation,
+            &NCHW,
+            1,
+            1,
+        )
+        .unwrap()
+    };
+    assert_eq!(conv(&[1, 1], &[0; 4], &[], &[]), vec![8, 16, 30, 30]);
+    assert_eq!(conv(&[1, 1], &[1; 4], &[], &[]), vec![8, 16, 32, 32]);
+    assert_eq!(conv(&[2, 2], &[1; 4], &[], &[]), vec![8, 16, 16, 16]);
+    assert_eq!(conv(&[1, 1], &[0; 4], &[], &[2, 2]), vec![8, 16, 28, 28]);
+    // Transposed convolutions dilate the input
+    assert_eq!(conv(&[1, 1], &[2; 4], &[2, 2], &[]), vec![8, 16, 65, 65]);
+}
+
+#[test]
+fn grouped_convolutions() {
+    // Depthwise: one group per input feature
+    let dims = convolution_dims(
+        &[1, 4, 5, 5],
+        &[4, 1, 3, 3],
+        &[1, 1],
+        &[0; 4],
+        &[],
+        &[],
+        &NCHW,
+        4,
+        1,
+    );
+    assert_eq!(dims, Ok(vec![1, 4, 3, 3]));
+    let dims = convolution_dims(
+        &[1, 4, 5, 5],
+        &[4, 1, 3, 3],
+        &[1, 1],
+        &[0; 4],
+        &[],
+        &[],
+        &NCHW,
+        3,
+        1,
+    );
+    assert!(dims.is_err());
+}
+
+#[test]
+fn converter_builds_convolutions() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[8, 3, 32, 32], ffi::Type::f32);
+    let w = converter.new_weight(1, &[16, 3, 3, 3], ffi::Type::f32);
+    let y = converter.new_convolution_op(
+        x,
+        w,
+        &[1, 1],
+        &[1, 1, 1, 1],
+        &[],
+        &[],
+        &NCHW,
+        1,
+        1,
+        &[],
+        &[8, 16, 32, 32],
+    );
+    converter.new_return_op(&[y]);
+    assert_eq!(converter.tensor_shape(y), vec![8, 16, 32, 32]);
+    assert!(converter
+        .rec_expr_ref()
+        .to_string()
+        .contains("(Vec 1 1 1 1) (Vec) (Vec) (Vec 0 1 2 3 1 0 2 3 0 1 2 3) 1 1 (Vec))"));
+}
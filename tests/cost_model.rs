@@ -0,0 +1,29 @@
+use egg::{EGraph, RecExpr};
+use std::collections::HashMap;
+use tensat::model::{Mdl, OpCache, TensorAnalysis};
+use tensat::optimize::CostModel;
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn cached_costs_only_cover_what_was_measured() {
+    let expr: RecExpr<Mdl> = "(AddOp (input x@2_3 0) (input y@2_3 1))".parse().unwrap();
+    let mut egraph = EGraph::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    let root = egraph.add_expr(&expr);
+    let add = egraph[root].nodes[0].clone();
+    let x = egraph.add_expr(&"(input x@2_3 0)".parse().unwrap());
+    let input = egraph[x].nodes[0].clone();
+
+    let cache: OpCache<f64> = OpCache::default();
+    let cost_model = CostModel::new().with_measured_costs(cache.clone());
+    // Inputs are free, the AddOp needs a measurement
+    assert_eq!(cost_model.cached_self_cost(&egraph, &input), Some(0.0));
+    assert_eq!(cost_model.cached_self_cost(&egraph, &add), None);
+    let cost = cost_model.get_self_cost(&egraph, &add);
+    assert_eq!(cost_model.cached_self_cost(&egraph, &add), Some(cost));
+    // Measurements are shared with other cost models
+    let other = CostModel::new().with_measured_costs(cache);
+    assert_eq!(other.cached_self_cost(&egraph, &add), Some(cost));
+}
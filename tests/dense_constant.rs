@@ -0,0 +1,175 @@
+mod common;
+
+use common::equal_with;
+use egg::RecExpr;
+use std::collections::HashMap;
+use tensat::input::{ffi, infer_dtypes, CppGraphConverter};
+use tensat::interpreter::{evaluate, Tensor};
+use tensat::model::{DenseConstant, Mdl, TensorAnalysis};
+use tensat::rewrites::constant_rules;
+
+/// Analysis with the values of f32 dense constants 0 (zeros), 1 (ones) and 2
+/// (counting up), each of 4 elements, and of the i32 dense constant 3 (ones)
+fn analysis() -> TensorAnalysis {
+    let dense_constants: HashMap<i64, DenseConstant> = [
+        (0, ffi::Type::f32, vec![0.0; 4]),
+        (1, ffi::Type::f32, vec![1.0; 4]),
+        (2, ffi::Type::f32, vec![1.0, 2.0, 3.0, 4.0]),
+        (3, ffi::Type::i32, vec![1.0; 4]),
+    ]
+    .into_iter()
+    .map(|(handle, dtype, values)| {
+        let values = values.into();
+        (handle, DenseConstant { dtype, values })
+    })
+    .collect();
+    TensorAnalysis::new(&HashMap::new(), &[]).with_dense_constants(dense_constants)
+}
+
+/// In-memory layout of `values`
+fn f32_bytes(values: &[f32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_ne_bytes()).collect()
+}
+
+#[test]
+fn dense_constants_have_their_shape() {
+    let mut converter = CppGraphConverter::default();
+    converter
+        .register_dense_constant(
+            3,
+            ffi::Type::f32,
+            &f32_bytes(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0]),
+        )
+        .unwrap();
+    let c = converter.new_dense_constant_op(3, &[2, 3]).unwrap();
+    assert_eq!(converter.tensor_shape(c), vec![2, 3]);
+    assert!(converter.tensor(c).tensor_data.constant);
+    match &converter.rec_expr_ref().as_ref()[c.id as usize] {
+        Mdl::DenseConstantOp([handle, ..]) => assert_eq!(
+            converter.rec_expr_ref().as_ref()[usize::from(*handle)],
+            Mdl::Num(3)
+        ),
+        other => panic!("expected a DenseConstantOp, got {}", other),
+    }
+}
+
+#[test]
+fn dense_constants_have_their_element_type() {
+    let mut converter = CppGraphConverter::default();
+    // Not exactly an f32
+    let big = (1 << 24) + 1;
+    let data: Vec<u8> = [big, 2]
+        .iter()
+        .flat_map(|v: &i32| v.to_ne_bytes())
+        .collect();
+    converter
+        .register_dense_constant(0, ffi::Type::i32, &data)
+        .unwrap();
+    converter
+        .register_dense_constant(1, ffi::Type::i1, &[1, 0, 0])
+        .unwrap();
+    let ints = converter.new_dense_constant_op(0, &[2]).unwrap();
+    let bools = converter.new_dense_constant_op(1, &[3]).unwrap();
+    assert!(converter.tensor(ints).tensor_data.dtype == ffi::Type::i32);
+    assert!(converter.tensor(bools).tensor_data.dtype == ffi::Type::i1);
+    let dtypes = infer_dtypes(converter.rec_expr_ref());
+    assert!(dtypes[ints.id as usize] == ffi::Type::i32);
+    assert!(dtypes[bools.id as usize] == ffi::Type::i1);
+
+    let values = DenseConstant::from_bytes(ffi::Type::i32, &data)
+        .unwrap()
+        .values;
+    assert_eq!(&values[..], &[f64::from(big), 2.0]);
+}
+
+#[test]
+fn invalid_dense_constants_are_errors() {
+    let mut converter = CppGraphConverter::default();
+    converter
+        .register_dense_constant(3, ffi::Type::f32, &f32_bytes(&[1.0, 2.0, 3.0]))
+        .unwrap();
+    let error = converter.new_dense_constant_op(3, &[2, 3]).unwrap_err();
+    assert!(error.contains("Invalid DenseConstantOp"), "{}", error);
+    let error = converter.new_dense_constant_op(4, &[2, 3]).unwrap_err();
+    assert!(error.contains("unknown handle"), "{}", error);
+    assert!(converter
+        .register_dense_constant(5, ffi::Type::f32, &[0; 6])
+        .is_err());
+    assert!(converter
+        .register_dense_constant(5, ffi::Type::c64, &[0; 8])
+        .is_err());
+}
+
+#[test]
+fn uniform_dense_constants_are_splats() {
+    assert!(equal_with(
+        analysis(),
+        &constant_rules(),
+        "(DenseConstantOp 1 (Vec 2 2) 1)",
+        &format!("(ConstantOp {} (Vec 2 2))", 1.0f32.to_bits())
+    ));
+    assert!(equal_with(
+        analysis(),
+        &constant_rules(),
+        "(DenseConstantOp 0 (Vec 4) 1)",
+        "(ConstantOp 0 (Vec 4))"
+    ));
+    assert!(!equal_with(
+        analysis(),
+        &constant_rules(),
+        "(DenseConstantOp 2 (Vec 4) 1)",
+        &format!("(ConstantOp {} (Vec 4))", 1.0f32.to_bits())
+    ));
+    // ConstantOps are f32, so i32 ones stay dense
+    assert!(!equal_with(
+        analysis(),
+        &constant_rules(),
+        "(DenseConstantOp 3 (Vec 4) 0)",
+        &format!("(ConstantOp {} (Vec 4))", 1.0f32.to_bits())
+    ));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn products_with_dense_zeros_and_ones_simplify() {
+    let x = "(input x@4 0)";
+    assert!(equal_with(
+        analysis(),
+        &constant_rules(),
+        &format!("(MulOp {} (DenseConstantOp 0 (Vec 4) 1))", x),
+        "(DenseConstantOp 0 (Vec 4) 1)"
+    ));
+    assert!(equal_with(
+        analysis(),
+        &constant_rules(),
+        &format!("(MulOp (DenseConstantOp 1 (Vec 4) 1) {})", x),
+        x
+    ));
+    assert!(equal_with(
+        analysis(),
+        &constant_rules(),
+        &format!("(AddOp {} (DenseConstantOp 0 (Vec 4) 1))", x),
+        x
+    ));
+    assert!(!equal_with(
+        analysis(),
+        &constant_rules(),
+        &format!("(MulOp {} (DenseConstantOp 2 (Vec 4) 1))", x),
+        x
+    ));
+}
+
+#[test]
+fn dense_constants_evaluate_to_their_values() {
+    let expr: RecExpr<Mdl> = "(ReturnOp (Vec (DenseConstantOp 2 (Vec 2 2) 1)))"
+        .parse()
+        .unwrap();
+    let results = evaluate(&expr, analysis(), &HashMap::new()).unwrap();
+    assert_eq!(
+        results,
+        vec![Tensor::new(vec![2, 2], vec![1.0, 2.0, 3.0, 4.0])]
+    );
+}
@@ -0,0 +1,25 @@
+use egg::{EGraph, RecExpr};
+use std::collections::HashMap;
+use tensat::diff::diff_graphs;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::optimize::CostModel;
+
+#[test]
+fn diffs_only_look_up_the_e_graph() {
+    let original: RecExpr<Mdl> = "(ReturnOp (Vec (input x@2 0)))".parse().unwrap();
+    let mut egraph = EGraph::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    egraph.add_expr(&original);
+    egraph.rebuild();
+    let num_nodes = egraph.total_size();
+
+    // Only the Var of this graph is in the e-graph
+    let other: RecExpr<Mdl> = "(ReturnOp (Vec (input x@2 1)))".parse().unwrap();
+    let diff = diff_graphs(&egraph, &original, &other, &CostModel::new());
+    assert_eq!(egraph.total_size(), num_nodes);
+    assert_eq!(diff.removed.len(), 2);
+    assert!(diff.added.is_empty());
+
+    let diff = diff_graphs(&egraph, &original, &original, &CostModel::new());
+    assert_eq!(diff.num_unchanged, 2);
+    assert!(diff.removed.is_empty() && diff.changed.is_empty());
+}
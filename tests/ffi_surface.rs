@@ -0,0 +1,245 @@
+//! Tests of the functions C++ calls to build a graph, driven from Rust with
+//! the handles C++ gets back.
+
+mod common;
+
+use common::shape;
+use egg::Id;
+use tensat::input::{ffi, infer_dtypes, CppGraphConverter};
+use tensat::model::Mdl;
+
+/// Id of the node a handle refers to
+fn id(tensor: ffi::TensorHandle) -> Id {
+    Id::from(tensor.id as usize)
+}
+
+fn node(converter: &CppGraphConverter, id: Id) -> &Mdl {
+    &converter.rec_expr_ref().as_ref()[usize::from(id)]
+}
+
+fn nums(converter: &CppGraphConverter, id: Id) -> Vec<i64> {
+    match node(converter, id) {
+        Mdl::Vec(ids) => ids
+            .iter()
+            .map(|id| match node(converter, *id) {
+                Mdl::Num(n) => *n,
+                other => panic!("expected a Num, got {}", other),
+            })
+            .collect(),
+        other => panic!("expected a Vec, got {}", other),
+    }
+}
+
+#[test]
+fn input_names_its_shape_and_block_arg() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(3, &[2, 5], ffi::Type::f32);
+    assert_eq!(converter.tensor_shape(x), vec![2, 5]);
+    match node(&converter, id(x)) {
+        Mdl::Input([name, block_arg]) => {
+            assert_eq!(node(&converter, *name).to_string(), "input_3@2_5");
+            assert_eq!(node(&converter, *block_arg), &Mdl::Num(3));
+        }
+        other => panic!("expected an input, got {}", other),
+    }
+    assert_eq!(converter.tensor(x).tensor_id(), x.id);
+}
+
+#[test]
+fn input_keeps_its_dtype() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::bf16);
+    assert_eq!(converter.tensor_dtype(x), ffi::Type::bf16);
+    let y = converter.new_neg_op(x, &[2, 3]);
+    assert_eq!(converter.tensor_dtype(y), ffi::Type::bf16);
+    match node(&converter, id(x)) {
+        Mdl::Input([name, _]) => {
+            assert_eq!(node(&converter, *name).to_string(), "input_0@2_3@bf16");
+        }
+        other => panic!("expected an input, got {}", other),
+    }
+    let dtypes = infer_dtypes(converter.rec_expr_ref());
+    assert_eq!(dtypes[x.id as usize], ffi::Type::bf16);
+    assert_eq!(dtypes[y.id as usize], ffi::Type::bf16);
+}
+
+#[test]
+fn scalars_are_shared() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[4], ffi::Type::f32);
+    let y = converter.new_input(1, &[4], ffi::Type::f32);
+    let a = converter.new_concatenate_op(&[x, y], 0, &[8]);
+    let b = converter.new_concatenate_op(&[x, y], 0, &[8]);
+    match (node(&converter, id(a)), node(&converter, id(b))) {
+        (Mdl::ConcatenateOp([_, dim_a]), Mdl::ConcatenateOp([_, dim_b])) => {
+            assert_eq!(dim_a, dim_b)
+        }
+        _ => panic!("expected two concatenates"),
+    }
+}
+
+#[test]
+fn concatenate_reads_its_operands_through_handles() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let y = converter.new_input(1, &[2, 4], ffi::Type::f32);
+    let z = converter.new_concatenate_op(&[x, y], 1, &[2, 7]);
+    assert_eq!(converter.tensor_shape(z), vec![2, 7]);
+    match node(&converter, id(z)) {
+        Mdl::ConcatenateOp([inputs, dim]) => {
+            assert_eq!(node(&converter, *inputs), &Mdl::Vec(vec![id(x), id(y)]));
+            assert_eq!(node(&converter, *dim), &Mdl::Num(1));
+        }
+        other => panic!("expected a concatenate, got {}", other),
+    }
+}
+
+#[test]
+fn blackbox_keeps_operands_and_results() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let y = converter.new_input(1, &[3], ffi::Type::f32);
+    let shapes = vec![shape(&[2, 3]), shape(&[6])];
+    let bb = converter.new_blackbox_op(&[x, y], 42, &shapes);
+    assert_eq!(converter.tensor(bb).tensor_data.n_dims, vec![2, 1]);
+    match node(&converter, id(bb)) {
+        Mdl::BlackBox(ids) => {
+            assert_eq!(&ids[..2], &[id(x), id(y)]);
+            assert_eq!(node(&converter, ids[2]), &Mdl::Num(42));
+        }
+        other => panic!("expected a blackbox, got {}", other),
+    }
+
+    let second = converter.new_index(1, bb);
+    assert_eq!(converter.tensor_shape(second), vec![6]);
+}
+
+#[test]
+fn vector_attributes_become_vecs_of_nums() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3, 4], ffi::Type::f32);
+    let t = converter.new_transpose_op(x, &[2, 0, 1], &[4, 2, 3]);
+    match node(&converter, id(t)) {
+        Mdl::TransposeOp([input, perm]) => {
+            assert_eq!(*input, id(x));
+            assert_eq!(nums(&converter, *perm), vec![2, 0, 1]);
+        }
+        other => panic!("expected a transpose, got {}", other),
+    }
+}
+
+#[test]
+fn convert_to_node_fills_shapes_from_the_converter() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let shapes = vec![shape(&[2, 3]), shape(&[6])];
+    let bb = converter.new_blackbox_op(&[x], 7, &shapes);
+    let second = converter.new_index(1, bb);
+    converter.new_return_op(&[second]);
+
+    let expr = converter.rec_expr_ref().clone();
+    let nodes = converter.convert_to_node(expr);
+    let find = |name: &str| nodes.iter().find(|n| n.name == name).unwrap();
+
+    assert_eq!(find("Input").shape, vec![shape(&[2, 3])]);
+    assert_eq!(find("blackbox").shape, shapes);
+    assert_eq!(find("Index").shape, vec![shape(&[6])]);
+    assert!(find("ReturnOp").shape.is_empty());
+}
+
+#[test]
+fn handles_refer_to_records_kept_by_the_converter() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::i32);
+    let y = converter.new_reshape_op(x, &[6]);
+    assert_ne!(x, y);
+    // The record outlives the handle it was handed out with
+    let copy = ffi::TensorHandle { id: y.id };
+    assert_eq!(converter.tensor_shape(copy), vec![6]);
+    assert_eq!(converter.tensor_dtype(copy), ffi::Type::i32);
+    assert_eq!(converter.tensor(copy).id, id(y));
+}
+
+#[test]
+#[should_panic(expected = "Unknown tensor handle")]
+fn unknown_handles_are_rejected() {
+    let converter = CppGraphConverter::default();
+    converter.tensor(ffi::TensorHandle { id: 7 });
+}
+
+#[test]
+fn custom_calls_keep_their_target_and_result() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let gemm = converter.register_custom_call("cublas_gemm", "{alpha = 1.0}");
+    let other = converter.register_custom_call("cublas_gemm", "{alpha = 1.0}");
+    let y = converter.new_custom_call_op(&[x, x], gemm, ffi::Type::bf16, &[2, 2]);
+    // Separately registered call sites stay apart
+    let z = converter.new_custom_call_op(&[x, x], other, ffi::Type::bf16, &[2, 2]);
+    assert_ne!(y, z);
+    assert_eq!(converter.tensor_shape(y), vec![2, 2]);
+    assert_eq!(converter.tensor_dtype(y), ffi::Type::bf16);
+    match node(&converter, id(y)) {
+        Mdl::CustomCallOp([inputs, call, shape, _]) => {
+            assert!(matches!(node(&converter, *inputs), Mdl::Vec(v) if v.len() == 2));
+            assert_eq!(node(&converter, *call), &Mdl::Num(gemm as i64));
+            assert_eq!(nums(&converter, *shape), vec![2, 2]);
+        }
+        other => panic!("expected a CustomCallOp, got {}", other),
+    }
+    let call = converter.custom_call(gemm);
+    assert_eq!(call.call_target, "cublas_gemm");
+    assert_eq!(call.backend_config, "{alpha = 1.0}");
+
+    converter.new_return_op(&[y]);
+    let expr = converter.rec_expr_ref().clone();
+    let nodes = converter.convert_to_node(expr);
+    let custom_call = nodes.iter().find(|n| n.name == "CustomCallOp").unwrap();
+    assert_eq!(custom_call.shape, vec![shape(&[2, 2])]);
+    assert!(custom_call.dtype == ffi::Type::bf16);
+}
+
+fn descriptor(op: ffi::Ops, operands: &[i32], int_args: &[i64]) -> ffi::OpDescriptor {
+    ffi::OpDescriptor {
+        op,
+        operands: operands.to_vec(),
+        int_args: int_args.to_vec(),
+        vec_args: vec![],
+        shapes: vec![shape(&[4])],
+    }
+}
+
+#[test]
+fn build_graph_builds_each_descriptor() {
+    let mut converter = CppGraphConverter::default();
+    let f32_num = i64::from(ffi::Type::f32.repr);
+    let ops = vec![
+        descriptor(ffi::Ops::Input, &[], &[0, f32_num]),
+        descriptor(ffi::Ops::NegOp, &[0], &[]),
+        descriptor(ffi::Ops::AddOp, &[0, 1], &[]),
+    ];
+    let ids = converter.build_graph(&ops).unwrap();
+    assert_eq!(ids.len(), 3);
+    let add = ffi::TensorHandle { id: ids[2] };
+    assert_eq!(converter.tensor_shape(add), vec![4]);
+    assert!(matches!(node(&converter, id(add)), Mdl::AddOp(_)));
+}
+
+#[test]
+fn build_graph_rejects_invalid_descriptors() {
+    let invalid = [
+        // Operands must be earlier descriptors
+        descriptor(ffi::Ops::NegOp, &[1], &[]),
+        descriptor(ffi::Ops::NegOp, &[-1], &[]),
+        descriptor(ffi::Ops::AddOp, &[0], &[]),
+        descriptor(ffi::Ops::Input, &[], &[0]),
+        descriptor(ffi::Ops::ReduceOp, &[0], &[0]),
+        descriptor(ffi::Ops { repr: 200 }, &[0], &[]),
+    ];
+    for desc in invalid {
+        let mut converter = CppGraphConverter::default();
+        let input = descriptor(ffi::Ops::Input, &[], &[0, 1]);
+        let error = converter.build_graph(&vec![input, desc]).unwrap_err();
+        assert!(error.starts_with("Op 1:"), "{}", error);
+    }
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::equal;
+use tensat::fft::{fft_dims, FftType};
+use tensat::input::{ffi, infer_dtypes, CppGraphConverter};
+use tensat::model::{input_var_name, var_dtype};
+use tensat::rewrites::complex_rules;
+
+#[test]
+fn fft_dims_follow_the_fft_type() {
+    assert_eq!(fft_dims(&[2, 8], FftType::Fft, &[8]), Ok(vec![2, 8]));
+    assert_eq!(fft_dims(&[2, 8], FftType::Ifft, &[8]), Ok(vec![2, 8]));
+    assert_eq!(
+        fft_dims(&[2, 4, 8], FftType::Rfft, &[4, 8]),
+        Ok(vec![2, 4, 5])
+    );
+    assert_eq!(
+        fft_dims(&[2, 4, 5], FftType::Irfft, &[4, 8]),
+        Ok(vec![2, 4, 8])
+    );
+    assert_eq!(
+        fft_dims(&[2, 4, 5], FftType::Irfft, &[4, 9]),
+        Ok(vec![2, 4, 9])
+    );
+}
+
+#[test]
+fn fft_lengths_must_match_the_operand() {
+    assert!(fft_dims(&[2, 8], FftType::Fft, &[4]).is_err());
+    assert!(fft_dims(&[8], FftType::Fft, &[2, 8]).is_err());
+    assert!(fft_dims(&[8], FftType::Fft, &[]).is_err());
+    assert!(fft_dims(&[2, 8], FftType::Irfft, &[8]).is_err());
+    assert!(FftType::from_num(4).is_err());
+}
+
+#[test]
+fn complex_inputs_keep_their_type() {
+    let name = input_var_name("z", &[4], ffi::Type::c64);
+    assert_eq!(name, "z@4@c64");
+    assert!(var_dtype(&name) == ffi::Type::c64);
+}
+
+#[test]
+fn complex_ops_are_typed() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 8], ffi::Type::f32);
+    let y = converter.new_input(1, &[2, 8], ffi::Type::f32);
+    let z = converter.new_complex_op(x, y, &[2, 8]);
+    assert!(converter.tensor_dtype(z) == ffi::Type::c64);
+    let f = converter.new_fft_op(z, 0, &[8], &[2, 8]);
+    assert!(converter.tensor_dtype(f) == ffi::Type::c64);
+    let re = converter.new_real_op(f, &[2, 8]);
+    assert!(converter.tensor_dtype(re) == ffi::Type::f32);
+
+    let r = converter.new_fft_op(x, 2, &[8], &[2, 5]);
+    assert!(converter.tensor_dtype(r) == ffi::Type::c64);
+    assert_eq!(converter.tensor_shape(r), vec![2, 5]);
+    let back = converter.new_fft_op(r, 3, &[8], &[2, 8]);
+    assert!(converter.tensor_dtype(back) == ffi::Type::f32);
+    converter.new_return_op(&[re, back]);
+
+    let dtypes = infer_dtypes(converter.rec_expr_ref());
+    assert!(dtypes[z.id as usize] == ffi::Type::c64);
+    assert!(dtypes[re.id as usize] == ffi::Type::f32);
+    assert!(dtypes[r.id as usize] == ffi::Type::c64);
+    assert!(dtypes[back.id as usize] == ffi::Type::f32);
+}
+
+#[test]
+#[should_panic(expected = "Invalid FftOp")]
+fn unknown_fft_types_are_rejected() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[8], ffi::Type::c64);
+    converter.new_fft_op(x, 7, &[8], &[8]);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn complex_parts_round_trip() {
+    let (x, y, z) = ("(input x@8 0)", "(input y@8 1)", "(input z@8@c64 2)");
+    let complex = format!("(ComplexOp {} {})", x, y);
+    assert!(equal(&complex_rules(), &format!("(RealOp {})", complex), x));
+    assert!(equal(&complex_rules(), &format!("(ImagOp {})", complex), y));
+    assert!(equal(
+        &complex_rules(),
+        &format!("(ComplexOp (RealOp {}) (ImagOp {}))", z, z),
+        z
+    ));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn ffts_cancel_with_their_inverses() {
+    let (x, z) = ("(input x@2_8 0)", "(input z@2_8@c64 1)");
+    assert!(equal(
+        &complex_rules(),
+        &format!("(FftOp (FftOp {} 0 (Vec 8)) 1 (Vec 8))", z),
+        z
+    ));
+    assert!(equal(
+        &complex_rules(),
+        &format!("(FftOp (FftOp {} 1 (Vec 8)) 0 (Vec 8))", z),
+        z
+    ));
+    assert!(equal(
+        &complex_rules(),
+        &format!("(FftOp (FftOp {} 2 (Vec 8)) 3 (Vec 8))", x),
+        x
+    ));
+    // Transforms over different dimensions don't cancel
+    assert!(!equal(
+        &complex_rules(),
+        &format!("(FftOp (FftOp {} 0 (Vec 2 8)) 1 (Vec 8))", z),
+        z
+    ));
+}
@@ -0,0 +1,57 @@
+use egg::RecExpr;
+use tensat::fusion::fusion_groups;
+use tensat::model::Mdl;
+
+fn groups_of(expr: &str) -> Vec<(String, i32)> {
+    let expr: RecExpr<Mdl> = expr.parse().unwrap();
+    let groups = fusion_groups(&expr);
+    expr.as_ref()
+        .iter()
+        .zip(groups)
+        .map(|(node, group)| (node.to_string(), group))
+        .collect()
+}
+
+fn group(groups: &[(String, i32)], op: &str) -> i32 {
+    groups.iter().find(|(name, _)| name == op).unwrap().1
+}
+
+#[test]
+fn elementwise_chain_into_reduce_is_one_group() {
+    let groups = groups_of(
+        "(ReduceOp (Vec (NegOp (ExpOp (input x 0)))) (Vec (ConstantOp 0 (Vec))) (Vec 1) 0)",
+    );
+    let reduce = group(&groups, "ReduceOp");
+    assert!(reduce >= 0);
+    assert_eq!(group(&groups, "ExpOp"), reduce);
+    assert_eq!(group(&groups, "NegOp"), reduce);
+    assert_eq!(group(&groups, "input"), -1);
+}
+
+#[test]
+fn shared_producer_is_not_fused() {
+    // Parsing doesn't share subexpressions, so build the graph by hand
+    let mut expr = RecExpr::default();
+    let name = expr.add(Mdl::Var("x".into()));
+    let num = expr.add(Mdl::Num(0));
+    let input = expr.add(Mdl::Input([name, num]));
+    let exp = expr.add(Mdl::ExpOp([input]));
+    let tanh = expr.add(Mdl::TanhOp([exp]));
+    let neg = expr.add(Mdl::NegOp([exp]));
+    expr.add(Mdl::AddOp([tanh, neg]));
+
+    let groups = fusion_groups(&expr);
+    assert_eq!(groups[usize::from(exp)], -1);
+    let add = groups[groups.len() - 1];
+    assert!(add >= 0);
+    assert_eq!(groups[usize::from(tanh)], add);
+    assert_eq!(groups[usize::from(neg)], add);
+}
+
+#[test]
+fn reduce_is_not_fused_into_consumer() {
+    let groups =
+        groups_of("(ExpOp (ReduceOp (Vec (input x 0)) (Vec (ConstantOp 0 (Vec))) (Vec 1) 0))");
+    assert_eq!(group(&groups, "ReduceOp"), -1);
+    assert_eq!(group(&groups, "ExpOp"), -1);
+}
@@ -0,0 +1,61 @@
+use tensat::fx::import_fx;
+
+/// FX graph of `torch.relu(torch.nn.functional.linear(x, w, b)) * 2.0`
+const LINEAR_RELU: &str = r#"{"nodes": [
+    {"name": "x", "op": "placeholder", "shape": [4, 8]},
+    {"name": "w", "op": "get_attr", "shape": [16, 8]},
+    {"name": "b", "op": "get_attr", "shape": [16]},
+    {"name": "linear", "op": "call_function", "target": "aten.linear.default",
+     "args": [{"node": "x"}, {"node": "w"}, {"node": "b"}], "shape": [4, 16]},
+    {"name": "relu", "op": "call_function", "target": "aten.relu.default",
+     "args": [{"node": "linear"}], "shape": [4, 16]},
+    {"name": "mul", "op": "call_function", "target": "aten.mul.Tensor",
+     "args": [{"node": "relu"}, 2.0], "shape": [4, 16]},
+    {"name": "softmax", "op": "call_function", "target": "aten._softmax.default",
+     "args": [{"node": "mul"}, -1, false], "shape": [4, 16]},
+    {"name": "output", "op": "output", "args": [[{"node": "softmax"}]]}
+]}"#;
+
+#[test]
+fn linear_relu_is_lowered() {
+    let converter = import_fx(LINEAR_RELU).unwrap();
+    let expr = converter.rec_expr_ref().to_string();
+    assert!(
+        expr.starts_with("(ReturnOp (Vec (BlackBox (MulOp (MaxOp (AddOp (DotGeneralOp"),
+        "{}",
+        expr
+    );
+    // x @ w.T contracts dim 1 of both operands
+    assert!(expr.contains("Vec Vec (Vec 1) (Vec 1)"), "{}", expr);
+    assert!(
+        expr.contains("(BroadcastInDimOp (input input_2@16 2) (Vec 1))"),
+        "{}",
+        expr
+    );
+}
+
+#[test]
+fn transpose_and_cat_are_lowered() {
+    let graph = r#"{"nodes": [
+        {"name": "x", "op": "placeholder", "shape": [2, 3]},
+        {"name": "xt", "op": "call_function", "target": "aten.transpose.int",
+         "args": [{"node": "x"}, 0, -1], "shape": [3, 2]},
+        {"name": "cat", "op": "call_function", "target": "aten.cat.default",
+         "args": [[{"node": "xt"}, {"node": "xt"}], 1], "shape": [3, 4]},
+        {"name": "output", "op": "output", "args": [[{"node": "cat"}]]}
+    ]}"#;
+    let expr = import_fx(graph).unwrap().rec_expr_ref().to_string();
+    assert!(
+        expr.contains("(ConcatenateOp (Vec (TransposeOp"),
+        "{}",
+        expr
+    );
+    assert!(expr.contains("(Vec 1 0)"), "{}", expr);
+}
+
+#[test]
+fn graph_without_output_is_rejected() {
+    let graph = r#"{"nodes": [{"name": "x", "op": "placeholder", "shape": [2]}]}"#;
+    let err = import_fx(graph).err().unwrap();
+    assert!(err.contains("no output"), "{}", err);
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::equal;
+use tensat::rewrites::{gelu_approximation_rules, gelu_rules};
+
+const X: &str = "(input x@2_3 0)";
+
+/// Splat constant of `value` of the shape of X
+fn constant(value: f32) -> String {
+    format!("(ConstantOp {} (Vec 2 3))", value.to_bits() as i32)
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn exact_and_approximate_gelu_stay_apart_by_default() {
+    assert!(!equal(
+        &gelu_rules(),
+        &format!("(GeluOp {} 0)", X),
+        &format!("(GeluOp {} 1)", X)
+    ));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn approximation_rules_merge_exact_and_approximate_gelu() {
+    let rules = [gelu_rules(), gelu_approximation_rules()].concat();
+    assert!(equal(
+        &rules,
+        &format!("(GeluOp {} 0)", X),
+        &format!("(GeluOp {} 1)", X)
+    ));
+    // The tanh formula is recognized, then approximates the exact GELU
+    let tanh = format!(
+        "(MulOp (MulOp {x} {half}) (AddOp {one} (TanhOp (MulOp {scale} \
+         (AddOp {x} (MulOp {cubic} (MulOp {x} (MulOp {x} {x}))))))))",
+        x = X,
+        half = constant(0.5),
+        one = constant(1.0),
+        scale = constant(0.79788456),
+        cubic = constant(0.044715),
+    );
+    assert!(equal(&rules, &tanh, &format!("(GeluOp {} 0)", X)));
+}
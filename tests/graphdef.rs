@@ -0,0 +1,84 @@
+use tensat::graphdef::import_graphdef;
+
+fn shape(dims: &[i64]) -> String {
+    let dims: Vec<String> = dims
+        .iter()
+        .map(|d| format!("{{\"size\": \"{}\"}}", d))
+        .collect();
+    format!("{{\"dim\": [{}]}}", dims.join(", "))
+}
+
+fn output_shapes(dims: &[i64]) -> String {
+    format!(
+        "\"_output_shapes\": {{\"list\": {{\"shape\": [{}]}}}}",
+        shape(dims)
+    )
+}
+
+/// relu(transpose(x) @ w + b), followed by a Conv2D, which has no Mdl op
+fn dense_graph() -> String {
+    format!(
+        r#"{{"node": [
+            {{"name": "out", "op": "Conv2D", "input": ["relu", "filter"], "attr": {{{conv}}}}},
+            {{"name": "x", "op": "Placeholder", "attr": {{"shape": {{"shape": {x}}}}}}},
+            {{"name": "w", "op": "Placeholder", "attr": {{"shape": {{"shape": {w}}}}}}},
+            {{"name": "b", "op": "Placeholder", "attr": {{"shape": {{"shape": {b}}}}}}},
+            {{"name": "filter", "op": "Placeholder", "attr": {{"shape": {{"shape": {filter}}}}}}},
+            {{"name": "perm", "op": "Const", "attr": {{"value": {{"tensor": {{
+                "dtype": "DT_INT32", "tensorShape": {perm_shape}, "tensorContent": "AQAAAAAAAAA="}}}}}}}},
+            {{"name": "xt", "op": "Transpose", "input": ["x", "perm"], "attr": {{{xt}}}}},
+            {{"name": "mm", "op": "MatMul", "input": ["xt", "w"], "attr": {{{mm}}}}},
+            {{"name": "bias", "op": "BiasAdd", "input": ["mm", "b"], "attr": {{{mm}}}}},
+            {{"name": "relu", "op": "Relu", "input": ["bias"], "attr": {{{mm}}}}}
+        ]}}"#,
+        x = shape(&[8, 4]),
+        w = shape(&[8, 16]),
+        b = shape(&[16]),
+        filter = shape(&[1, 1, 16, 16]),
+        perm_shape = shape(&[2]),
+        xt = output_shapes(&[4, 8]),
+        mm = output_shapes(&[4, 16]),
+        conv = output_shapes(&[4, 16]),
+    )
+}
+
+#[test]
+fn dense_layer_is_lowered() {
+    let converter = import_graphdef(&dense_graph(), &[]).unwrap();
+    let expr = converter.rec_expr_ref().to_string();
+    assert!(
+        expr.starts_with("(ReturnOp (Vec (BlackBox (MaxOp (AddOp (DotGeneralOp (TransposeOp"),
+        "{}",
+        expr
+    );
+    assert!(
+        expr.contains("(TransposeOp (input input_0@8_4 0) (Vec 1 0))"),
+        "{}",
+        expr
+    );
+    assert!(
+        expr.contains("(BroadcastInDimOp (input input_2@16 2) (Vec 1))"),
+        "{}",
+        expr
+    );
+}
+
+#[test]
+fn outputs_can_be_named() {
+    let converter = import_graphdef(&dense_graph(), &["mm"]).unwrap();
+    let expr = converter.rec_expr_ref().to_string();
+    assert!(expr.starts_with("(ReturnOp (Vec (DotGeneralOp"), "{}", expr);
+}
+
+#[test]
+fn missing_output_shapes_are_reported() {
+    let graph = format!(
+        r#"{{"node": [
+            {{"name": "x", "op": "Placeholder", "attr": {{"shape": {{"shape": {}}}}}}},
+            {{"name": "y", "op": "Relu", "input": ["x"]}}
+        ]}}"#,
+        shape(&[4])
+    );
+    let err = import_graphdef(&graph, &[]).err().unwrap();
+    assert!(err.contains("add_shapes=True"), "{}", err);
+}
@@ -0,0 +1,24 @@
+use tensat::growth::predict_next_size;
+
+#[test]
+fn no_prediction_without_history() {
+    assert_eq!(predict_next_size(&[]), None);
+    assert_eq!(predict_next_size(&[100]), None);
+}
+
+#[test]
+fn linear_growth_is_extrapolated() {
+    assert_eq!(predict_next_size(&[100, 200]), Some(300));
+    assert_eq!(predict_next_size(&[100, 200, 300]), Some(400));
+}
+
+#[test]
+fn accelerating_growth_is_scaled() {
+    // Growth went 100 -> 300, so the next one is predicted as 900
+    assert_eq!(predict_next_size(&[100, 200, 500]), Some(1400));
+}
+
+#[test]
+fn slowing_growth_is_not_scaled_down() {
+    assert_eq!(predict_next_size(&[100, 500, 600]), Some(700));
+}
@@ -0,0 +1,137 @@
+use egg::Id;
+use std::env::temp_dir;
+use std::fs::read_to_string;
+use tensat::ilp_export::{write_lp, LpOptions, LpRemat, LpSharing};
+use tensat::model::Mdl;
+
+/// Write the ILP of a root eclass 0 whose nodes 0 and 1 use eclass 1, of the
+/// leaf node 2, with `options`. Returns the LP and the variable map.
+fn write(name: &str, options: &LpOptions) -> (String, String) {
+    let path = temp_dir().join(format!("tensat_{}.lp", name));
+    let lp_file = path.to_str().unwrap();
+    let nodes = [Mdl::Num(0), Mdl::Num(1), Mdl::Num(2)];
+    let node_refs: Vec<&Mdl> = nodes.iter().collect();
+    write_lp(
+        lp_file,
+        &[Id::from(4), Id::from(7)],
+        &[vec![0, 1], vec![2]],
+        &[vec![1], vec![1, 1], vec![]],
+        &[1.0, 2.0, 3.0],
+        &[0, 0, 1],
+        0,
+        &node_refs,
+        &[1],
+        options,
+    )
+    .unwrap();
+    let lp = read_to_string(lp_file).unwrap();
+    let map = read_to_string(format!("{}.map", lp_file)).unwrap();
+    (lp, map)
+}
+
+#[test]
+fn default_ilp_has_no_optional_constraints() {
+    let (lp, map) = write("default", &LpOptions::default());
+    assert!(
+        lp.contains("  root:\n    x0\n    + x1\n    = 1\n"),
+        "{}",
+        lp
+    );
+    assert!(
+        lp.contains("  child_1_1:\n    x2\n    - x1\n    >= 0\n"),
+        "{}",
+        lp
+    );
+    assert!(lp.contains("  blacklist_1:\n    x1\n    = 0\n"), "{}", lp);
+    for absent in ["order_", "eclass_", "z0_", "consumed_", "Bounds"] {
+        assert!(!lp.contains(absent), "{}", lp);
+    }
+    assert!(map.contains("x2\t7\t2\n"), "{}", map);
+}
+
+#[test]
+fn ordering_constraints_bound_the_order_variables() {
+    let options = LpOptions {
+        order: true,
+        order_var_int: true,
+        ..LpOptions::default()
+    };
+    let (lp, map) = write("order", &options);
+    // t[g(i)] - t[m] + A (1 - x[i]) >= 1, with A the number of eclasses
+    assert!(
+        lp.contains("  order_0_0:\n    t0\n    - t1\n    - 2 x0\n    >= -1\n"),
+        "{}",
+        lp
+    );
+    assert!(lp.contains("Bounds\n  0 <= t0 <= 1\n"), "{}", lp);
+    assert!(lp.contains("General\n  t0\n  t1\n"), "{}", lp);
+    assert!(map.contains("t1\t7\t\n"), "{}", map);
+}
+
+#[test]
+fn remat_and_sharing_add_their_variables_and_terms() {
+    let mem_i = [0.0, 0.0, 8.0];
+    let options = LpOptions {
+        remat: Some(LpRemat {
+            mem_i: &mem_i,
+            remat_weight: 0.5,
+            single_m: &[1],
+        }),
+        ..LpOptions::default()
+    };
+    let (lp, _) = write("remat", &options);
+    // Repeated children get one z variable
+    assert!(
+        lp.contains("  remat_child_1_1:\n    - x1\n    + z1_1_2\n    >= 0\n"),
+        "{}",
+        lp
+    );
+    assert!(
+        lp.contains("  remat_used_2:\n    z0_1_2\n    + z1_1_2\n    - x2\n"),
+        "{}",
+        lp
+    );
+    assert!(lp.contains("  single_1:\n"), "{}", lp);
+    // The memory term charges 4 per consumer of node 2 beyond the first
+    assert!(lp.contains("    + 4 z0_1_2\n    + 4 z1_1_2\n"), "{}", lp);
+    assert!(lp.contains("Binary\n  x0\n  x1\n  x2\n  z0_1_2\n  z1_1_2\nEnd\n"));
+
+    let options = LpOptions {
+        sharing: Some(LpSharing {
+            tensor_m: &[1],
+            sharing_bonus: 0.5,
+        }),
+        ..LpOptions::default()
+    };
+    let (lp, _) = write("sharing", &options);
+    assert!(lp.contains("  eclass_0:\n"), "{}", lp);
+    assert!(
+        lp.contains("  consumed_1:\n    x0\n    + x1\n    - x2\n    >= 0\n"),
+        "{}",
+        lp
+    );
+    // Costs minus the bonus for each consumer of eclass 1 beyond the first
+    assert!(
+        lp.contains("  obj:\n    0.5 x0\n    + 1.5 x1\n    + 3.5 x2\n"),
+        "{}",
+        lp
+    );
+}
+
+#[test]
+fn unwritable_files_are_errors() {
+    let nodes = [Mdl::Num(0)];
+    let result = write_lp(
+        "/nonexistent/tensat.lp",
+        &[Id::from(0)],
+        &[vec![0]],
+        &[vec![]],
+        &[1.0],
+        &[0],
+        0,
+        &[&nodes[0]],
+        &[],
+        &LpOptions::default(),
+    );
+    assert!(result.is_err());
+}
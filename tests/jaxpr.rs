@@ -0,0 +1,124 @@
+use tensat::jaxpr::import_jaxpr;
+
+/// jaxpr of `lambda x, w: jnp.tanh(x @ w + 1.0).sum(axis=1)`
+const MLP: &str = r#"{
+    "invars": [
+        {"name": "a", "shape": [4, 8], "dtype": "float32"},
+        {"name": "b", "shape": [8, 16], "dtype": "float32"}
+    ],
+    "eqns": [
+        {"primitive": "dot_general", "invars": ["a", "b"],
+         "outvars": [{"name": "c", "shape": [4, 16], "dtype": "float32"}],
+         "params": {"dimension_numbers": [[[1], [0]], [[], []]], "precision": null}},
+        {"primitive": "add", "invars": ["c", {"literal": 1.0}],
+         "outvars": [{"name": "d", "shape": [4, 16], "dtype": "float32"}]},
+        {"primitive": "tanh", "invars": ["d"],
+         "outvars": [{"name": "e", "shape": [4, 16], "dtype": "float32"}]},
+        {"primitive": "reduce_sum", "invars": ["e"],
+         "outvars": [{"name": "f", "shape": [4], "dtype": "float32"}],
+         "params": {"axes": [1]}}
+    ],
+    "outvars": ["f"]
+}"#;
+
+#[test]
+fn mlp_is_lowered() {
+    let converter = import_jaxpr(MLP).unwrap();
+    let expr = converter.rec_expr_ref().to_string();
+    assert!(
+        expr.starts_with("(ReturnOp (Vec (ReduceOp (Vec (TanhOp (AddOp (DotGeneralOp"),
+        "{}",
+        expr
+    );
+    assert!(expr.contains("(input input_0@4_8 0)"), "{}", expr);
+    assert!(expr.contains("(input input_1@8_16 1)"), "{}", expr);
+    assert!(expr.contains("(Vec 1) (Vec 0)"), "{}", expr);
+    assert!(expr.contains("ConstantOp"), "{}", expr);
+}
+
+#[test]
+fn unsupported_primitive_is_rejected() {
+    let jaxpr = r#"{
+        "invars": [{"name": "a", "shape": [4]}],
+        "eqns": [{"primitive": "cumsum", "invars": ["a"], "outvars": [{"name": "b", "shape": [4]}]}],
+        "outvars": ["b"]
+    }"#;
+    let err = import_jaxpr(jaxpr).err().unwrap();
+    assert!(err.contains("cumsum"), "{}", err);
+}
+
+#[test]
+fn unary_primitives_are_lowered() {
+    let jaxpr = r#"{
+        "invars": [{"name": "a", "shape": [4]}],
+        "eqns": [
+            {"primitive": "sin", "invars": ["a"], "outvars": [{"name": "b", "shape": [4]}]},
+            {"primitive": "rsqrt", "invars": ["b"], "outvars": [{"name": "c", "shape": [4]}]},
+            {"primitive": "pow", "invars": ["c", {"literal": 2.0}], "outvars": [{"name": "d", "shape": [4]}]}
+        ],
+        "outvars": ["d"]
+    }"#;
+    let converter = import_jaxpr(jaxpr).unwrap();
+    let expr = converter.rec_expr_ref().to_string();
+    assert!(
+        expr.starts_with("(ReturnOp (Vec (PowOp (RsqrtOp (SineOp (input input_0@4 0)))"),
+        "{}",
+        expr
+    );
+}
+
+#[test]
+fn fft_primitives_are_lowered() {
+    let jaxpr = r#"{
+        "invars": [{"name": "a", "shape": [2, 8]}],
+        "eqns": [
+            {"primitive": "fft", "invars": ["a"], "outvars": [{"name": "b", "shape": [2, 5]}],
+             "params": {"fft_type": "FftType.RFFT", "fft_lengths": [8]}},
+            {"primitive": "real", "invars": ["b"], "outvars": [{"name": "c", "shape": [2, 5]}]}
+        ],
+        "outvars": ["c"]
+    }"#;
+    let converter = import_jaxpr(jaxpr).unwrap();
+    let expr = converter.rec_expr_ref().to_string();
+    assert!(
+        expr.starts_with("(ReturnOp (Vec (RealOp (FftOp (input input_0@2_8 0) 2 (Vec 8)))"),
+        "{}",
+        expr
+    );
+}
+
+#[test]
+fn linear_algebra_primitives_are_lowered() {
+    let jaxpr = r#"{
+        "invars": [{"name": "a", "shape": [4, 4]}, {"name": "b", "shape": [4, 2]}],
+        "eqns": [
+            {"primitive": "cholesky", "invars": ["a"], "outvars": [{"name": "l", "shape": [4, 4]}]},
+            {"primitive": "triangular_solve", "invars": ["l", "b"],
+             "outvars": [{"name": "x", "shape": [4, 2]}],
+             "params": {"left_side": true, "lower": true, "transpose_a": true,
+                        "conjugate_a": false, "unit_diagonal": false}}
+        ],
+        "outvars": ["x"]
+    }"#;
+    let converter = import_jaxpr(jaxpr).unwrap();
+    let expr = converter.rec_expr_ref().to_string();
+    assert!(
+        expr.starts_with(
+            "(ReturnOp (Vec (TriangularSolveOp (CholeskyOp (input input_0@4_4 0) 1) \
+             (input input_1@4_2 1) 1 1 0 2)"
+        ),
+        "{}",
+        expr
+    );
+}
+
+#[test]
+fn undefined_variable_is_rejected() {
+    let jaxpr = r#"{
+        "invars": [],
+        "eqns": [{"primitive": "neg", "invars": ["a"], "outvars": [{"name": "b", "shape": [4]}]}],
+        "outvars": ["b"]
+    }"#;
+    let err = import_jaxpr(jaxpr).err().unwrap();
+    assert!(err.contains("undefined variable a"), "{}", err);
+}
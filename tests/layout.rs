@@ -0,0 +1,88 @@
+use egg::{EGraph, Id, RecExpr, Runner};
+use std::collections::HashMap;
+use tensat::layout::*;
+use tensat::model::{Mdl, TensorAnalysis};
+
+fn egraph_of(expr: &str) -> (EGraph<Mdl, TensorAnalysis>, Vec<Id>) {
+    let expr: RecExpr<Mdl> = expr.parse().unwrap();
+    let mut egraph = EGraph::<Mdl, TensorAnalysis>::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    let mut ids: Vec<Id> = Vec::new();
+    for node in expr.as_ref() {
+        ids.push(egraph.add(node.clone().map_children(|c| ids[usize::from(c)])));
+    }
+    (egraph, ids)
+}
+
+fn saturate(expr: &str) -> (EGraph<Mdl, TensorAnalysis>, Id) {
+    let (egraph, ids) = egraph_of(expr);
+    let root = *ids.last().unwrap();
+    let runner = Runner::default()
+        .with_egraph(egraph)
+        .with_iter_limit(5)
+        .run(&layout_rules());
+    let root = runner.egraph.find(root);
+    (runner.egraph, root)
+}
+
+/// Lhs contracting dimensions of the DotGeneralOps in `class`
+fn lhs_contracting(egraph: &EGraph<Mdl, TensorAnalysis>, class: Id) -> Vec<Vec<Id>> {
+    egraph[class]
+        .iter()
+        .filter_map(|node| match node {
+            Mdl::DotGeneralOp(children) => Some(children[4]),
+            _ => None,
+        })
+        .map(|lc| match &egraph[lc].nodes[0] {
+            Mdl::Vec(dims) => dims.clone(),
+            _ => panic!("Contracting dimensions are not a Vec"),
+        })
+        .collect()
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn transpose_folds_into_dot_general() {
+    let (egraph, root) = saturate(
+        "(DotGeneralOp (TransposeOp (input x@4_3 0) (Vec 1 0)) (input y@4_5 1) (Vec) (Vec) (Vec 1) (Vec 0) (Vec))",
+    );
+    let zero = egraph.lookup(Mdl::Num(0)).unwrap();
+    let one = egraph.lookup(Mdl::Num(1)).unwrap();
+    let contracting = lhs_contracting(&egraph, root);
+    assert!(contracting.contains(&vec![one]), "{:?}", contracting);
+    assert!(contracting.contains(&vec![zero]), "{:?}", contracting);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn inverse_transposes_cancel_out() {
+    let (egraph, root) =
+        saturate("(TransposeOp (TransposeOp (input x@2_3_4 0) (Vec 1 2 0)) (Vec 2 0 1))");
+    assert!(egraph[root]
+        .iter()
+        .any(|node| matches!(node, Mdl::Input(_))));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn layout_factor_charges_non_canonical_operands() {
+    let (egraph, ids) = egraph_of(
+        "(DotGeneralOp (input x@4_3 0) (input y@4_5 1) (Vec) (Vec) (Vec 0) (Vec 0) (Vec))",
+    );
+    let dot = &egraph[*ids.last().unwrap()].nodes[0];
+    assert_eq!(layout_factor(&egraph, dot), 1.0 + LAYOUT_PENALTY);
+
+    let (egraph, ids) = egraph_of(
+        "(DotGeneralOp (input x@3_4 0) (input y@4_5 1) (Vec) (Vec) (Vec 1) (Vec 0) (Vec))",
+    );
+    let dot = &egraph[*ids.last().unwrap()].nodes[0];
+    assert_eq!(layout_factor(&egraph, dot), 1.0);
+}
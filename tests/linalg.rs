@@ -0,0 +1,70 @@
+mod common;
+
+use common::shape;
+use egg::Id;
+use tensat::input::{ffi, CppGraphConverter};
+use tensat::mock_cost::get_cost;
+use tensat::model::Mdl;
+
+#[test]
+fn triangular_solve_keeps_its_attributes() {
+    let mut converter = CppGraphConverter::default();
+    let a = converter.new_input(0, &[3, 3], ffi::Type::f32);
+    let b = converter.new_input(1, &[3, 5], ffi::Type::f32);
+    let l = converter.new_cholesky_op(a, true, &[3, 3]);
+    let x = converter.new_triangular_solve_op(l, b, true, true, false, 3, &[3, 5]);
+    assert_eq!(converter.tensor_shape(x), vec![3, 5]);
+    assert!(converter.tensor_dtype(x) == ffi::Type::f32);
+
+    let nodes = converter.rec_expr_ref().as_ref();
+    let num = |id: &Id| match nodes[usize::from(*id)] {
+        Mdl::Num(n) => n,
+        ref other => panic!("expected a Num, got {}", other),
+    };
+    match &nodes[x.id as usize] {
+        Mdl::TriangularSolveOp([a, _, left_side, lower, unit_diagonal, transpose_a]) => {
+            assert!(matches!(nodes[usize::from(*a)], Mdl::CholeskyOp(_)));
+            assert_eq!(
+                [left_side, lower, unit_diagonal, transpose_a].map(num),
+                [1, 1, 0, 3]
+            );
+        }
+        other => panic!("expected a TriangularSolveOp, got {}", other),
+    }
+}
+
+#[test]
+#[should_panic(expected = "Invalid TriangularSolveOp")]
+fn unknown_transposes_are_rejected() {
+    let mut converter = CppGraphConverter::default();
+    let a = converter.new_input(0, &[3, 3], ffi::Type::f32);
+    let b = converter.new_input(1, &[3, 5], ffi::Type::f32);
+    converter.new_triangular_solve_op(a, b, true, true, false, 0, &[3, 5]);
+}
+
+#[test]
+fn cost_is_cubic_in_the_matrix_size() {
+    let cholesky = |n: i64| {
+        get_cost(
+            ffi::Ops::CholeskyOp,
+            vec![shape(&[2, n, n])],
+            vec![ffi::Type::f32],
+            vec![],
+            vec![1],
+        )
+    };
+    assert_eq!(cholesky(4), 2 * 64);
+    assert_eq!(cholesky(8), 8 * cholesky(4));
+
+    let solve = |n: i64, m: i64| {
+        get_cost(
+            ffi::Ops::TriangularSolveOp,
+            vec![shape(&[n, n]), shape(&[n, m])],
+            vec![ffi::Type::f32, ffi::Type::f32],
+            vec![],
+            vec![1, 1, 0, 1],
+        )
+    };
+    assert_eq!(solve(4, 4), 64);
+    assert_eq!(solve(8, 1), 64);
+}
@@ -0,0 +1,66 @@
+use egg::RecExpr;
+use tensat::input::{ffi, nodes_to_graph, rec_expr_to_nodes, validate_graph};
+use tensat::liveness::*;
+use tensat::model::Mdl;
+
+/// Two results, each a small op on a large intermediate, with both
+/// intermediates emitted before either small op
+fn interleaved_graph() -> ffi::Graph {
+    let mut expr = RecExpr::default();
+    let name = expr.add(Mdl::Var("x@10".into()));
+    let arg = expr.add(Mdl::Num(0));
+    let x = expr.add(Mdl::Input([name, arg]));
+    let large_1 = expr.add(Mdl::NegOp([x]));
+    let large_2 = expr.add(Mdl::ExpOp([x]));
+    let small_1 = expr.add(Mdl::TanhOp([large_1]));
+    let small_2 = expr.add(Mdl::TanhOp([large_2]));
+    let results = expr.add(Mdl::Vec(vec![small_1, small_2]));
+    expr.add(Mdl::ReturnOp([results]));
+
+    let mut nodes = rec_expr_to_nodes(&expr, &[]);
+    for node in nodes.iter_mut() {
+        let dims = match node.name.as_str() {
+            "NegOp" | "ExpOp" => vec![1000],
+            "Input" | "TanhOp" => vec![10],
+            _ => continue,
+        };
+        node.shape = vec![ffi::Shape { shape: dims }];
+    }
+    nodes_to_graph(nodes)
+}
+
+#[test]
+fn schedule_frees_intermediates_early() {
+    let graph = interleaved_graph();
+    validate_graph(&graph).unwrap();
+    let mut emission_order = graph.schedule.clone();
+    emission_order.sort();
+    let scheduled = peak_memory(&graph.nodes, &graph.results, &graph.schedule);
+    let emitted = peak_memory(&graph.nodes, &graph.results, &emission_order);
+    // The input, both small results and one large intermediate
+    assert_eq!(scheduled, 4.0 * (10.0 + 10.0 + 10.0 + 1000.0));
+    assert!(scheduled < emitted, "{} vs {}", scheduled, emitted);
+
+    // Each small op runs right after its intermediate
+    let names: Vec<&str> = graph
+        .schedule
+        .iter()
+        .map(|i| graph.nodes[*i as usize].name.as_str())
+        .collect();
+    assert_eq!(
+        names,
+        ["Input", "NegOp", "TanhOp", "ExpOp", "TanhOp", "ReturnOp"]
+    );
+}
+
+#[test]
+fn invalid_schedules_are_rejected() {
+    let graph = interleaved_graph();
+    let mut reversed = graph.schedule.clone();
+    reversed.reverse();
+    assert!(check_schedule(&graph.nodes, &reversed).is_err());
+    assert!(check_schedule(&graph.nodes, &graph.schedule[1..]).is_err());
+    let mut twice = graph.schedule.clone();
+    twice.push(graph.schedule[0]);
+    assert!(check_schedule(&graph.nodes, &twice).is_err());
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use common::{analysis, commute_add, commute_mul, saturate};
+use egg::{EGraph, Id, RecExpr, Rewrite, Runner};
+use tensat::merge::*;
+use tensat::model::{Mdl, TensorAnalysis};
+
+/// Saturate MUL_ADD with `rules` and serialize the e-graph through json
+fn serialized(rules: &[Rewrite<Mdl, TensorAnalysis>]) -> SerializedEGraph {
+    let runner = saturate(rules);
+    let serialized = SerializedEGraph::new(&runner.egraph, runner.roots[0]);
+    SerializedEGraph::from_json(&serialized.to_json()).unwrap()
+}
+
+fn saturate_both() -> Vec<SerializedEGraph> {
+    vec![serialized(&[commute_add()]), serialized(&[commute_mul()])]
+}
+
+/// Enodes of the eclass of `class` extraction may pick
+fn num_allowed(egraph: &EGraph<Mdl, TensorAnalysis>, class: Id) -> usize {
+    egraph[class]
+        .iter()
+        .filter(|node| !egraph.analysis.blacklist_nodes.contains(*node))
+        .count()
+}
+
+fn add_class(egraph: &EGraph<Mdl, TensorAnalysis>, root: Id) -> Id {
+    match egraph[root].nodes[0] {
+        Mdl::MulOp([x, y]) => {
+            if egraph[x].iter().any(|n| matches!(n, Mdl::AddOp(_))) {
+                x
+            } else {
+                y
+            }
+        }
+        _ => panic!("Root is not a MulOp"),
+    }
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn union_keeps_rewrites_of_every_run() {
+    let (egraph, root) = merge_egraphs(&saturate_both(), MergeMode::Union, analysis()).unwrap();
+    assert_eq!(num_allowed(&egraph, root), 2);
+    assert_eq!(num_allowed(&egraph, add_class(&egraph, root)), 2);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn intersection_keeps_the_common_enodes() {
+    let (egraph, root) = merge_egraphs(&saturate_both(), MergeMode::Intersect, analysis()).unwrap();
+    assert_eq!(num_allowed(&egraph, root), 1);
+    assert_eq!(num_allowed(&egraph, add_class(&egraph, root)), 1);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn graphs_must_share_the_root() {
+    let mut graphs = saturate_both();
+    let other: RecExpr<Mdl> = "(NegOp (input a@2_3 0))".parse().unwrap();
+    let runner = Runner::default()
+        .with_egraph(EGraph::new(analysis()))
+        .with_expr(&other);
+    graphs.push(SerializedEGraph::new(&runner.egraph, runner.roots[0]));
+    assert!(merge_egraphs(&graphs, MergeMode::Union, analysis()).is_err());
+}
+
+#[test]
+fn malformed_egraphs_are_rejected() {
+    assert!(
+        SerializedEGraph::from_json(r#"{"classes": [[["input", [0, 3]]]], "root": 0}"#).is_err()
+    );
+    assert!(SerializedEGraph::from_json(r#"{"classes": [], "root": 0}"#).is_err());
+    assert!("sum".parse::<MergeMode>().is_err());
+}
@@ -0,0 +1,51 @@
+use std::sync::{Arc, Mutex};
+use tensat::metrics::*;
+
+// The metrics are process-wide, so each test uses its own metric names
+
+#[test]
+fn counters_and_histograms_are_exported() {
+    increment("test_export_total", 2.0);
+    increment("test_export_total", 1.0);
+    observe("test_export_seconds", 0.3);
+    observe("test_export_seconds", 20.0);
+    assert_eq!(counter_value("test_export_total"), 3.0);
+    assert_eq!(histogram_count("test_export_seconds"), 2);
+
+    let text = prometheus_text();
+    assert!(text.contains("# TYPE test_export_total counter\ntest_export_total 3\n"));
+    assert!(text.contains("# TYPE test_export_seconds histogram\n"));
+    assert!(text.contains("test_export_seconds_bucket{le=\"0.1\"} 0\n"));
+    assert!(text.contains("test_export_seconds_bucket{le=\"0.5\"} 1\n"));
+    assert!(text.contains("test_export_seconds_bucket{le=\"30\"} 2\n"));
+    assert!(text.contains("test_export_seconds_bucket{le=\"+Inf\"} 2\n"));
+    assert!(text.contains("test_export_seconds_sum 20.3\n"));
+    assert!(text.contains("test_export_seconds_count 2\n"));
+}
+
+#[test]
+fn callback_sees_updates() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let sink = seen.clone();
+    set_metrics_callback(Box::new(move |name, kind, value| {
+        if name.starts_with("test_callback") {
+            sink.lock().unwrap().push((name.to_string(), kind, value));
+        }
+    }));
+    increment("test_callback_total", 1.0);
+    observe("test_callback_seconds", 4.0);
+    clear_metrics_callback();
+    increment("test_callback_total", 1.0);
+
+    assert_eq!(
+        *seen.lock().unwrap(),
+        vec![
+            ("test_callback_total".to_string(), MetricKind::Counter, 1.0),
+            (
+                "test_callback_seconds".to_string(),
+                MetricKind::Histogram,
+                4.0
+            ),
+        ]
+    );
+}
@@ -0,0 +1,203 @@
+//! Regression suite over fragments of real models, stored in tests/models/.
+//!
+//! Each `<name>.expr` holds a graph as a RecExpr, `<name>.graph` its text as
+//! passed to C++ (see graph_to_string) and `<name>.json` the expected facts
+//! about it:
+//!
+//! - `results`: number of results of the graph
+//! - `input_ops`: op counts of the fixture itself, to catch accidental edits
+//! - `max_ops`: upper bounds on op counts of the optimized graph
+//! - `optimized_cost`: golden cost of the optimized graph under the Rust cost
+//!   model (see mock_cost.rs), which unlike the C++ one doesn't depend on the
+//!   machine
+//!
+//! Optimizing needs the shape inference and cost model, so those tests only
+//! run with `cargo test --features no-cxx-costmodel --test models`. Set
+//! TENSAT_BLESS=1 to record the graph texts and optimized costs as the new
+//! golden ones.
+
+use egg::RecExpr;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::{read_to_string, write};
+use tensat::input::{
+    ffi, graph_to_string, nodes_to_graph, rec_expr_to_nodes, validate_graph, CppGraphConverter,
+};
+use tensat::model::Mdl;
+
+const FIXTURES: &[&str] = &[
+    "attention",
+    "mlp",
+    "dense_stack",
+    "conv_stack",
+    "kv_cache_update",
+];
+
+/// Relative tolerance on the golden cost
+const COST_TOLERANCE: f64 = 1e-3;
+
+fn fixture_path(name: &str, ext: &str) -> String {
+    format!("{}/tests/models/{}.{}", env!("CARGO_MANIFEST_DIR"), name, ext)
+}
+
+fn load(name: &str) -> (RecExpr<Mdl>, Value) {
+    let expr = read_to_string(fixture_path(name, "expr"))
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|e| panic!("{}: malformed graph: {}", name, e));
+    let golden = serde_json::from_str(&read_to_string(fixture_path(name, "json")).unwrap())
+        .unwrap_or_else(|e| panic!("{}: malformed golden file: {}", name, e));
+    (expr, golden)
+}
+
+fn op_counts(graph: &ffi::Graph) -> HashMap<String, u64> {
+    let mut counts = HashMap::new();
+    for node in &graph.nodes {
+        *counts.entry(node.name.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn expected_counts(golden: &Value, key: &str) -> HashMap<String, u64> {
+    golden[key]
+        .as_object()
+        .unwrap()
+        .iter()
+        .map(|(op, count)| (op.clone(), count.as_u64().unwrap()))
+        .collect()
+}
+
+#[test]
+fn fixtures_are_valid_graphs() {
+    for name in FIXTURES {
+        let (expr, golden) = load(name);
+        let graph = nodes_to_graph(rec_expr_to_nodes(&expr, &[]));
+        validate_graph(&graph).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        assert_eq!(
+            graph.results.len() as u64,
+            golden["results"].as_u64().unwrap(),
+            "{}",
+            name
+        );
+        let counts = op_counts(&graph);
+        for (op, count) in expected_counts(&golden, "input_ops") {
+            assert_eq!(counts.get(&op).copied().unwrap_or(0), count, "{}: {}", name, op);
+        }
+    }
+}
+
+fn converter(expr: RecExpr<Mdl>) -> CppGraphConverter {
+    let mut converter = CppGraphConverter::from_rec_expr(expr);
+    converter.config_mut().rule_file = format!("{}/converted.txt", env!("CARGO_MANIFEST_DIR"));
+    converter
+}
+
+fn bless() -> bool {
+    std::env::var("TENSAT_BLESS").is_ok()
+}
+
+#[test]
+fn graph_text_matches_golden() {
+    for name in FIXTURES {
+        let (expr, _) = load(name);
+        let text = graph_to_string(&nodes_to_graph(rec_expr_to_nodes(&expr, &[])));
+        if bless() {
+            write(fixture_path(name, "graph"), text).unwrap();
+        } else {
+            let expected = read_to_string(fixture_path(name, "graph")).unwrap();
+            assert_eq!(text, expected, "{}", name);
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "golden costs are for the Rust cost model"
+)]
+fn optimization_is_deterministic() {
+    for name in FIXTURES {
+        let (expr, _) = load(name);
+        assert!(converter(expr).check_determinism(), "{}", name);
+    }
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "golden costs are for the Rust cost model"
+)]
+fn optimized_fixtures_match_golden() {
+    for name in FIXTURES {
+        let (expr, mut golden) = load(name);
+        let result = converter(expr).optimize_with_original();
+        let optimized = &result.optimized;
+
+        assert_eq!(
+            optimized.results.len() as u64,
+            golden["results"].as_u64().unwrap(),
+            "{}",
+            name
+        );
+        assert!(
+            result.optimized_cost <= result.original_cost * (1.0 + COST_TOLERANCE),
+            "{}: optimized cost {} is worse than the original {}",
+            name,
+            result.optimized_cost,
+            result.original_cost
+        );
+        let counts = op_counts(optimized);
+        for (op, max) in expected_counts(&golden, "max_ops") {
+            let count = counts.get(&op).copied().unwrap_or(0);
+            assert!(count <= max, "{}: {} {} ops, expected at most {}", name, count, op, max);
+        }
+
+        if bless() {
+            golden["optimized_cost"] = result.optimized_cost.into();
+            write(
+                fixture_path(name, "json"),
+                serde_json::to_string_pretty(&golden).unwrap() + "\n",
+            )
+            .unwrap();
+        } else {
+            let expected = golden["optimized_cost"].as_f64().unwrap_or_else(|| {
+                panic!("{}: no golden cost recorded, run with TENSAT_BLESS=1", name)
+            });
+            assert!(
+                (result.optimized_cost - expected).abs() <= expected.abs() * COST_TOLERANCE,
+                "{}: optimized cost {} differs from the golden {}",
+                name,
+                result.optimized_cost,
+                expected
+            );
+        }
+    }
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "golden costs are for the Rust cost model"
+)]
+fn greedy_extraction_reuses_the_saturated_egraph() {
+    for name in FIXTURES {
+        let (expr, golden) = load(name);
+        let converter = converter(expr);
+        let first = converter.extract_greedy();
+        validate_graph(&first.graph).unwrap_or_else(|e| panic!("{}: {}", name, e));
+        assert_eq!(
+            first.graph.results.len() as u64,
+            golden["results"].as_u64().unwrap(),
+            "{}",
+            name
+        );
+        let second = converter.extract_greedy();
+        assert_eq!(
+            graph_to_string(&first.graph),
+            graph_to_string(&second.graph),
+            "{}",
+            name
+        );
+        assert_eq!(first.cost, second.cost, "{}", name);
+    }
+}
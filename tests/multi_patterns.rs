@@ -0,0 +1,89 @@
+use egg::{Pattern, RecExpr, Rewrite, Runner};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::rewrites::{is_symmetric_rule, MultiPatterns};
+
+fn pats(rule_1: &str, rule_2: &str) -> (Pattern<Mdl>, Pattern<Mdl>, Pattern<Mdl>, Pattern<Mdl>) {
+    let split = |rule: &str| {
+        let mut it = rule.split("=>").map(|x| x.parse::<Pattern<Mdl>>().unwrap());
+        (it.next().unwrap(), it.next().unwrap())
+    };
+    let (src_1, dst_1) = split(rule_1);
+    let (src_2, dst_2) = split(rule_2);
+    (src_1, src_2, dst_1, dst_2)
+}
+
+#[test]
+fn only_mirrored_rules_are_symmetric() {
+    let (src_1, src_2, dst_1, dst_2) = pats(
+        "(DotGeneralOp ?x ?y ?lb ?rb ?lc ?rc ?p)=>(Index 0 (BlackBox ?x ?y ?z 0))",
+        "(DotGeneralOp ?x ?z ?lb ?rb ?lc ?rc ?p)=>(Index 1 (BlackBox ?x ?y ?z 0))",
+    );
+    assert!(!is_symmetric_rule(&src_1, &src_2, &dst_1, &dst_2));
+
+    let (src_1, src_2, dst_1, dst_2) = pats(
+        "(AddOp ?x ?y)=>(Index 0 (BlackBox ?x ?y ?z 0))",
+        "(AddOp ?x ?z)=>(Index 0 (BlackBox ?x ?z ?y 0))",
+    );
+    assert!(is_symmetric_rule(&src_1, &src_2, &dst_1, &dst_2));
+}
+
+#[test]
+fn different_sources_are_not_symmetric() {
+    let (src_1, src_2, dst_1, dst_2) = pats(
+        "(AddOp ?x ?y)=>(MulOp ?x ?y)",
+        "(SubtractOp ?x ?y)=>(MulOp ?x ?y)",
+    );
+    assert!(!is_symmetric_rule(&src_1, &src_2, &dst_1, &dst_2));
+}
+
+/// Number of times the multi-pattern rule (`rule_1`, `rule_2`) applies during
+/// saturation of a graph with two AddOps sharing their first operand
+fn applications(rule_1: &'static str, rule_2: &'static str) -> usize {
+    let expr: RecExpr<Mdl> = "(ReturnOp (Vec (AddOp (input x@2_3 0) (input y@2_3 1)) \
+                              (AddOp (input x@2_3 0) (input z@2_3 2))))"
+        .parse()
+        .unwrap();
+    let multi_patterns = Rc::new(RefCell::new(MultiPatterns::with_rules(
+        vec![rule_1, rule_2],
+        /*no_cycle=*/ false,
+        /*iter_limit=*/ 1,
+        /*filter_after=*/ false,
+        /*node_limit=*/ usize::MAX,
+        /*n_sec=*/ 60,
+    )));
+    let hook = multi_patterns.clone();
+    let rules: Vec<Rewrite<Mdl, TensorAnalysis>> = vec![];
+    Runner::<Mdl, TensorAnalysis, ()>::new(TensorAnalysis::new(&HashMap::new(), &[]))
+        .with_expr(&expr)
+        .with_hook(move |runner| hook.borrow_mut().run_one(runner))
+        .run(&rules);
+    let num_applied = multi_patterns.borrow().num_applied();
+    num_applied
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn symmetric_rules_apply_once_per_unordered_pair() {
+    // Both assignments of the two AddOps to the sources apply the same rewrite
+    assert_eq!(
+        applications(
+            "(AddOp ?x ?y)=>(MulOp ?x ?y)",
+            "(AddOp ?x ?z)=>(MulOp ?x ?z)"
+        ),
+        1
+    );
+    // Here they don't, so both are applied
+    assert_eq!(
+        applications(
+            "(AddOp ?x ?y)=>(MulOp ?x ?y)",
+            "(AddOp ?x ?z)=>(SubtractOp ?x ?z)"
+        ),
+        2
+    );
+}
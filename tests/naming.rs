@@ -0,0 +1,44 @@
+use egg::{EGraph, RecExpr, Runner};
+use std::collections::HashMap;
+use tensat::input::NameGen;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::naming::*;
+use tensat::weights::weight_rules;
+
+#[test]
+fn tensor_names_are_numbered_in_order() {
+    let mut name_gen = NameGen::default();
+    assert_eq!(
+        name_gen.new_tensor_name("w_0.transposed"),
+        "w_0.transposed_0"
+    );
+    assert_eq!(name_gen.new_tensor_name("gelu.tanh"), "gelu.tanh_1");
+    // Weights are numbered separately
+    assert_eq!(name_gen.new_weight_name(), "w_0");
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn transposed_weight_is_named_after_the_weight() {
+    let expr: RecExpr<Mdl> =
+        "(DotGeneralOp (input x@4_3 0) (input w_0@5_3 1) (Vec) (Vec) (Vec 1) (Vec 1) (Vec))"
+            .parse()
+            .unwrap();
+    let runner = Runner::default()
+        .with_egraph(EGraph::new(TensorAnalysis::new(&HashMap::new(), &[])))
+        .with_expr(&expr)
+        .with_iter_limit(2)
+        .run(&weight_rules());
+    let egraph = &runner.egraph;
+    let transposed = egraph
+        .classes()
+        .find(|class| class.iter().any(|node| matches!(node, Mdl::TransposeOp(_))))
+        .unwrap()
+        .id;
+    assert_eq!(name_of(egraph, transposed), Some("w_0.transposed_0"));
+    assert_eq!(tensor_name(egraph, runner.roots[0]), None);
+    assert!(named_dot(egraph).contains("label = \"w_0.transposed_0\""));
+}
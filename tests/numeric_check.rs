@@ -0,0 +1,41 @@
+use egg::RecExpr;
+use std::collections::HashMap;
+use tensat::interpreter::numerically_equivalent;
+use tensat::model::{Mdl, TensorAnalysis};
+
+fn check(a: &str, b: &str) -> Result<bool, String> {
+    let a: RecExpr<Mdl> = a.parse().unwrap();
+    let b: RecExpr<Mdl> = b.parse().unwrap();
+    numerically_equivalent(&a, &b, || TensorAnalysis::new(&HashMap::new(), &[]), 1e-5, 0)
+}
+
+#[test]
+fn swapped_results_differ() {
+    let a = "(ReturnOp (Vec (input input_0@4 0) (input input_1@4 1)))";
+    let b = "(ReturnOp (Vec (input input_1@4 1) (input input_0@4 0)))";
+    assert_eq!(check(a, a), Ok(true));
+    assert_eq!(check(a, b), Ok(false));
+}
+
+#[test]
+fn unknown_dims_are_not_evaluated() {
+    let a = "(ReturnOp (Vec (input input_0@-1_4 0)))";
+    let error = check(a, a).unwrap_err();
+    assert!(error.contains("unknown size"), "{}", error);
+}
+
+#[test]
+#[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+fn commuted_add_is_equivalent() {
+    let a = "(AddOp (input input_0@2_3 0) (input input_1@2_3 1))";
+    let b = "(AddOp (input input_1@2_3 1) (input input_0@2_3 0))";
+    assert_eq!(check(a, b), Ok(true));
+}
+
+#[test]
+#[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+fn wrong_rewrite_is_caught() {
+    let a = "(AddOp (input input_0@2_3 0) (input input_1@2_3 1))";
+    let b = "(SubtractOp (input input_0@2_3 0) (input input_1@2_3 1))";
+    assert_eq!(check(a, b), Ok(false));
+}
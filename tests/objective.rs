@@ -0,0 +1,49 @@
+use egg::{EGraph, Extractor, RecExpr};
+use std::collections::HashMap;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::optimize::{graph_cost, CostModel, TensorCost};
+
+const FACTORED: &str = "(MulOp (input x@2_3 0) (AddOp (input y@2_3 1) (input z@2_3 2)))";
+
+fn analysis() -> TensorAnalysis {
+    TensorAnalysis::new(&HashMap::new(), &[])
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn op_count_charges_one_per_op() {
+    let expr: RecExpr<Mdl> = FACTORED.parse().unwrap();
+    assert_eq!(graph_cost(&expr, analysis(), &CostModel::op_count()), 5.0);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn op_count_extracts_the_graph_with_the_fewest_ops() {
+    let expanded: RecExpr<Mdl> =
+        "(AddOp (MulOp (input x@2_3 0) (input y@2_3 1)) (MulOp (input x@2_3 0) (input z@2_3 2)))"
+            .parse()
+            .unwrap();
+    let mut egraph = EGraph::new(analysis());
+    let root = egraph.add_expr(&expanded);
+    let factored = egraph.add_expr(&FACTORED.parse().unwrap());
+    egraph.union(root, factored);
+    egraph.rebuild();
+
+    let cost_model = CostModel::op_count();
+    let mut extractor = Extractor::new(
+        &egraph,
+        TensorCost {
+            egraph: &egraph,
+            cost_model: &cost_model,
+        },
+    );
+    let (cost, best) = extractor.find_best(root);
+    assert_eq!(cost, 5.0);
+    assert_eq!(best.to_string(), FACTORED);
+}
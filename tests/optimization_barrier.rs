@@ -0,0 +1,72 @@
+mod common;
+
+use common::equal;
+use egg::RecExpr;
+use std::collections::HashMap;
+use tensat::input::{ffi, infer_dtypes, CppGraphConverter};
+use tensat::interpreter::numerically_equivalent;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::optimize::barrier_operands;
+use tensat::passes::validate_vecs;
+use tensat::rewrites::{tuple_rules, unary_rules};
+
+#[test]
+fn barriers_yield_their_inputs() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let y = converter.new_input(1, &[4], ffi::Type::i32);
+    let x = converter.new_abs_op(x, &[2, 3]);
+    let barrier = converter.new_optimization_barrier_op(&[x, y]);
+    let first = converter.new_index(0, barrier);
+    let second = converter.new_index(1, barrier);
+    assert_eq!(converter.tensor_shape(first), vec![2, 3]);
+    assert_eq!(converter.tensor_shape(second), vec![4]);
+    assert!(!converter.tensor(barrier).tensor_data.constant);
+    converter.new_return_op(&[first, second]);
+
+    let dtypes = infer_dtypes(converter.rec_expr_ref());
+    assert!(dtypes[second.id as usize] == ffi::Type::i32);
+    assert_eq!(validate_vecs(converter.rec_expr_ref()), Ok(()));
+    let mut operands: Vec<usize> = barrier_operands(converter.rec_expr_ref())
+        .iter()
+        .map(|id| usize::from(*id))
+        .collect();
+    operands.sort_unstable();
+    assert_eq!(operands, vec![y.id as usize, x.id as usize]);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn rules_dont_cross_barriers() {
+    let x = "(input x@2_3 0)";
+    let fenced = |op: &str| format!("(Index 0 (OptimizationBarrierOp (Vec {})))", op);
+    let rules = [unary_rules(), tuple_rules()].concat();
+    assert!(equal(
+        &rules,
+        &format!("(AbsOp (AbsOp {}))", x),
+        &format!("(AbsOp {})", x)
+    ));
+    assert!(!equal(
+        &rules,
+        &format!("(AbsOp {})", fenced(&format!("(AbsOp {})", x))),
+        &format!("(AbsOp {})", x)
+    ));
+    assert!(!equal(&rules, &fenced(x), x));
+}
+
+#[test]
+fn barriers_evaluate_to_their_inputs() {
+    let expr: RecExpr<Mdl> = "(ReturnOp (Vec (Index 1 \
+                              (OptimizationBarrierOp (Vec (input x@2_3 0) (input y@4 1))))))"
+        .parse()
+        .unwrap();
+    let direct: RecExpr<Mdl> = "(ReturnOp (Vec (input y@4 1)))".parse().unwrap();
+    let analysis = || TensorAnalysis::new(&HashMap::new(), &[]);
+    assert_eq!(
+        numerically_equivalent(&expr, &direct, analysis, 0.0, 0),
+        Ok(true)
+    );
+}
@@ -0,0 +1,75 @@
+use egg::{rewrite, EGraph, RecExpr, Rewrite, Runner};
+use std::collections::HashMap;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::rewrites::{padded_dims, slice_removes_padding};
+
+fn slice_of_pad() -> Rewrite<Mdl, TensorAnalysis> {
+    rewrite!("slice-of-pad";
+             "(SliceOp (PadOp ?x ?v ?lo ?hi ?int) ?start ?limit ?s)" => "?x"
+             if slice_removes_padding("?x", "?lo", "?int", "?start", "?limit", "?s"))
+}
+
+/// Whether the slice-of-pad rule finds `expr` equal to its input x
+fn cancels(expr: &str) -> bool {
+    let expr: RecExpr<Mdl> = expr.parse().unwrap();
+    let mut runner = Runner::default()
+        .with_egraph(EGraph::new(TensorAnalysis::new(&HashMap::new(), &[])))
+        .with_expr(&expr)
+        .run(&[slice_of_pad()]);
+    let x = runner.egraph.add_expr(&"(input x@4_6 0)".parse().unwrap());
+    runner.egraph.find(x) == runner.egraph.find(runner.roots[0])
+}
+
+fn pad_then_slice(interior: &str, start: &str, limit: &str, strides: &str) -> String {
+    format!(
+        "(SliceOp (PadOp (input x@4_6 0) (input v@ 1) (Vec 1 2) (Vec 3 0) {}) {} {} {})",
+        interior, start, limit, strides
+    )
+}
+
+#[test]
+fn negative_edge_padding() {
+    assert_eq!(
+        padded_dims(&[4, 6], &[-1, 2], &[0, -3], &[0, 0]),
+        Ok(vec![3, 5])
+    );
+    assert_eq!(
+        padded_dims(&[4, 6], &[1, 0], &[1, 0], &[2, 0]),
+        Ok(vec![12, 6])
+    );
+    assert_eq!(
+        padded_dims(&[-1, 6], &[-1, 0], &[0, 0], &[0, 0]),
+        Ok(vec![-1, 6])
+    );
+    assert!(padded_dims(&[4], &[-3], &[-2], &[0]).is_err());
+    assert!(padded_dims(&[4], &[0], &[0], &[-1]).is_err());
+    assert!(padded_dims(&[4, 6], &[0], &[0], &[0]).is_err());
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn slice_removing_the_padding_cancels() {
+    assert!(cancels(&pad_then_slice(
+        "(Vec 0 0)",
+        "(Vec 1 2)",
+        "(Vec 5 8)",
+        "(Vec 1 1)"
+    )));
+    // With interior padding, the slice steps over it
+    assert!(cancels(&pad_then_slice(
+        "(Vec 1 0)",
+        "(Vec 1 2)",
+        "(Vec 8 8)",
+        "(Vec 2 1)"
+    )));
+    // Keeps one element of the padding
+    assert!(!cancels(&pad_then_slice(
+        "(Vec 0 0)",
+        "(Vec 0 2)",
+        "(Vec 5 8)",
+        "(Vec 1 1)"
+    )));
+}
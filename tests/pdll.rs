@@ -0,0 +1,51 @@
+use tensat::pdll::rules_to_pdll;
+
+#[test]
+fn elementwise_rules_are_exported() {
+    let export = rules_to_pdll(&["(AddOp ?x (MulOp ?y ?z))=>(AddOp (MulOp ?z ?y) ?x);class=cheap"]);
+    assert_eq!(export.num_exported, 1);
+    assert!(export.skipped.is_empty());
+    assert!(export.text.contains(
+        "Pattern rule0 {\n  let root = op<stablehlo.add>(x: Value, op<stablehlo.multiply>(y: Value, z: Value)) -> (type: Type);\n  \
+         replace root with op<stablehlo.add>(op<stablehlo.multiply>(z, y) -> (type), x) -> (type);\n}\n"
+    ), "{}", export.text);
+}
+
+#[test]
+fn repeated_variables_are_declared_once() {
+    let export = rules_to_pdll(&["(SubtractOp ?x (NegOp ?x))=>(AddOp ?x ?x)"]);
+    assert!(
+        export
+            .text
+            .contains("op<stablehlo.subtract>(x: Value, op<stablehlo.negate>(x))"),
+        "{}",
+        export.text
+    );
+}
+
+#[test]
+fn rules_outside_the_subset_are_skipped() {
+    let export = rules_to_pdll(&[
+        "(TransposeOp (TransposeOp ?x ?p) ?p)=>?x",
+        "(MulOp ?x ?y)=>(MulOp ?y ?z)",
+        "(NegOp (NegOp ?x))=>?x",
+    ]);
+    assert_eq!(export.num_exported, 1);
+    assert_eq!(export.skipped.len(), 2);
+    assert!(
+        export.skipped[0].1.contains("TransposeOp"),
+        "{:?}",
+        export.skipped
+    );
+    assert!(
+        export.skipped[1].1.contains("new variables"),
+        "{:?}",
+        export.skipped
+    );
+    assert!(export.text.contains("Pattern rule2"), "{}", export.text);
+    assert!(
+        export.text.contains("replace root with x;"),
+        "{}",
+        export.text
+    );
+}
@@ -0,0 +1,83 @@
+use egg::{Id, RecExpr};
+use std::collections::HashMap;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::passes::peephole;
+
+fn analysis() -> TensorAnalysis {
+    TensorAnalysis::new(&HashMap::new(), &[])
+}
+
+/// Cleaned up graph returning `op`
+fn clean(op: &str) -> RecExpr<Mdl> {
+    let expr: RecExpr<Mdl> = format!("(ReturnOp (Vec {}))", op).parse().unwrap();
+    peephole(&expr, analysis())
+}
+
+fn returning(op: &str) -> String {
+    format!("(ReturnOp (Vec {}))", op)
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn identity_transposes_are_dropped() {
+    let x = "(input x@2_3 0)";
+    let identity = format!("(TransposeOp {} (Vec 0 1))", x);
+    assert_eq!(clean(&identity).to_string(), returning(x));
+    let swap = format!("(TransposeOp {} (Vec 1 0))", x);
+    assert_eq!(clean(&swap).to_string(), returning(&swap));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn reshape_chains_are_merged() {
+    let x = "(input x@2_3_4 0)";
+    let chain = format!("(ReshapeOp (ReshapeOp {} (Vec 24)) (Vec 4 6))", x);
+    assert_eq!(
+        clean(&chain).to_string(),
+        returning(&format!("(ReshapeOp {} (Vec 4 6))", x))
+    );
+    // Merged into a reshape to the operand's shape, the chain is dropped
+    let round_trip = format!("(ReshapeOp (ReshapeOp {} (Vec 24)) (Vec 2 3 4))", x);
+    assert_eq!(clean(&round_trip).to_string(), returning(x));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn identical_nodes_are_deduplicated() {
+    let mul = "(MulOp (input x@2_3 0) (input y@2_3 1))";
+    let expr = clean(&format!("{} {}", mul, mul));
+    assert_eq!(expr.to_string(), returning(&format!("{} {}", mul, mul)));
+    let muls = expr
+        .as_ref()
+        .iter()
+        .filter(|node| matches!(node, Mdl::MulOp(_)))
+        .count();
+    assert_eq!(muls, 1);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn unreachable_nodes_are_pruned() {
+    let mut expr: RecExpr<Mdl> = "(input x@2_3 0)".parse().unwrap();
+    let x = Id::from(expr.as_ref().len() - 1);
+    expr.add(Mdl::Num(5));
+    expr.add(Mdl::MulOp([x, x]));
+    let outputs = expr.add(Mdl::Vec(vec![x]));
+    expr.add(Mdl::ReturnOp([outputs]));
+
+    let cleaned = peephole(&expr, analysis());
+    assert_eq!(cleaned.to_string(), returning("(input x@2_3 0)"));
+    assert_eq!(cleaned.as_ref().len(), 5);
+}
@@ -0,0 +1,186 @@
+use egg::{EGraph, Id, Pattern, RecExpr, Runner, Var};
+use std::collections::HashMap;
+use tensat::config::MixedPrecision;
+use tensat::input::ffi;
+use tensat::interpreter::numerically_equivalent;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::precision::*;
+use tensat::rewrites::rules_from_str;
+
+const DOT: &str =
+    "(DotGeneralOp (input x@4_3 0) (input y@3_5 1) (Vec) (Vec) (Vec 1) (Vec 0) (Vec))";
+
+fn saturate(expr: &str, policy: MixedPrecision) -> (EGraph<Mdl, TensorAnalysis>, Id) {
+    let expr: RecExpr<Mdl> = expr.parse().unwrap();
+    let runner = Runner::default()
+        .with_egraph(EGraph::new(TensorAnalysis::new(&HashMap::new(), &[])))
+        .with_expr(&expr)
+        .with_iter_limit(5)
+        .run(&precision_rules(policy));
+    let root = runner.egraph.find(runner.roots[0]);
+    (runner.egraph, root)
+}
+
+fn dot(x: &str, y: &str, precision: &str) -> String {
+    format!(
+        "(DotGeneralOp {} {} (Vec) (Vec) (Vec 1) (Vec 0) {})",
+        x, y, precision
+    )
+}
+
+/// Whether applying `rule` once to `expr` makes it equal to `other`
+fn rewrites_to(rule: &str, expr: &str, other: &str) -> bool {
+    let expr: RecExpr<Mdl> = expr.parse().unwrap();
+    let mut runner = Runner::default()
+        .with_egraph(EGraph::new(TensorAnalysis::new(&HashMap::new(), &[])))
+        .with_expr(&expr)
+        .with_iter_limit(1)
+        .run(&rules_from_str(vec![rule], false));
+    let other = runner.egraph.add_expr(&other.parse().unwrap());
+    runner.egraph.rebuild();
+    runner.egraph.find(other) == runner.egraph.find(runner.roots[0])
+}
+
+/// Whether `class` has an enode matching `pred` computing a bf16 tensor
+fn has_bf16(egraph: &EGraph<Mdl, TensorAnalysis>, class: Id, pred: impl Fn(&Mdl) -> bool) -> bool {
+    egraph[class].data.dtype == ffi::Type::bf16 && egraph[class].iter().any(pred)
+}
+
+#[test]
+fn rounds_to_nearest_bf16() {
+    assert_eq!(round_to_bf16(1.0), 1.0);
+    // Halfway cases round to the even mantissa
+    assert_eq!(round_to_bf16(1.0 + 2f32.powi(-8)), 1.0);
+    assert_eq!(
+        round_to_bf16(1.0 + 3.0 * 2f32.powi(-8)),
+        1.0 + 2f32.powi(-6)
+    );
+    assert_eq!(round_to_bf16(-1.0 - 2f32.powi(-7)), -1.0 - 2f32.powi(-7));
+    assert!(round_to_bf16(f32::NAN).is_nan());
+}
+
+#[test]
+fn off_policy_has_no_rules() {
+    assert!(precision_rules(MixedPrecision::Off).is_empty());
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn dot_general_is_lowered() {
+    let (egraph, root) = saturate(DOT, MixedPrecision::Dot);
+    assert_eq!(egraph[root].data.dtype, ffi::Type::f32);
+    let lowered = egraph[root].iter().any(|node| match node {
+        Mdl::ConvertOp([x, _]) => has_bf16(&egraph, *x, |n| matches!(n, Mdl::DotGeneralOp(_))),
+        _ => false,
+    });
+    assert!(lowered);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn lowered_chain_stays_in_bf16() {
+    let expr = format!("(AddOp {} (input b@4_5 2))", DOT);
+    let (egraph, root) = saturate(&expr, MixedPrecision::Elementwise);
+    // The bf16 add takes the bf16 dot directly, the conversions in between
+    // cancel out
+    let chained = egraph[root].iter().any(|node| match node {
+        Mdl::ConvertOp([x, _]) => has_bf16(&egraph, *x, |n| match n {
+            Mdl::AddOp([a, _]) => has_bf16(&egraph, *a, |n| matches!(n, Mdl::DotGeneralOp(_))),
+            _ => false,
+        }),
+        _ => false,
+    });
+    assert!(chained);
+
+    // Dot-only policy leaves the add alone
+    let (egraph, root) = saturate(&expr, MixedPrecision::Dot);
+    let add = egraph[root]
+        .iter()
+        .find(|node| matches!(node, Mdl::AddOp(_)))
+        .unwrap();
+    assert!(add
+        .children()
+        .iter()
+        .all(|c| egraph[*c].data.dtype == ffi::Type::f32));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn interpreter_rounds_at_conversions() {
+    let input: RecExpr<Mdl> = "(input x@2_3 0)".parse().unwrap();
+    let rounded: RecExpr<Mdl> = "(ConvertOp (ConvertOp (input x@2_3 0) 3) 1)"
+        .parse()
+        .unwrap();
+    let check = |tolerance| {
+        numerically_equivalent(
+            &input,
+            &rounded,
+            || TensorAnalysis::new(&HashMap::new(), &[]),
+            tolerance,
+            0,
+        )
+    };
+    assert_eq!(check(1e-6), Ok(false));
+    assert_eq!(check(1e-2), Ok(true));
+}
+
+#[test]
+fn stricter_precision_is_elementwise() {
+    assert_eq!(stricter_precision(&[0, 2], &[1, 1]), vec![1, 2]);
+    // An empty config is DEFAULT for both operands
+    assert_eq!(stricter_precision(&[], &[0, 1]), vec![0, 1]);
+}
+
+#[test]
+fn dot_precisions_are_found() {
+    let pat: Pattern<Mdl> = "(AddOp (DotGeneralOp ?x ?z ?lb ?rb ?lc ?rc ?p) (DotGeneralOp ?y ?z ?lb ?rb ?lc ?rc (Vec)))"
+        .parse()
+        .unwrap();
+    let p: Var = "?p".parse().unwrap();
+    assert_eq!(dot_precisions(pat.ast.as_ref()), vec![p]);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn dots_with_different_precisions_are_not_merged() {
+    let rule = "(AddOp (DotGeneralOp ?x ?z ?lb ?rb ?lc ?rc ?p) (DotGeneralOp ?y ?z ?lb ?rb ?lc ?rc ?q))=>(DotGeneralOp (AddOp ?x ?y) ?z ?lb ?rb ?lc ?rc ?p)";
+    let (x, y, z) = ("(input x@4_3 0)", "(input y@4_3 1)", "(input z@3_5 2)");
+    let sum = format!("(AddOp {} {})", x, y);
+    let merged = dot(&sum, z, "(Vec 2 2)");
+    let same = format!(
+        "(AddOp {} {})",
+        dot(x, z, "(Vec 2 2)"),
+        dot(y, z, "(Vec 2 2)")
+    );
+    assert!(rewrites_to(rule, &same, &merged));
+    let mixed = format!("(AddOp {} {})", dot(x, z, "(Vec 2 2)"), dot(y, z, "(Vec)"));
+    assert!(!rewrites_to(rule, &mixed, &merged));
+    assert!(!rewrites_to(rule, &mixed, &dot(&sum, z, "(Vec)")));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn reassociated_dots_take_the_stricter_precision() {
+    let rule = "(DotGeneralOp (DotGeneralOp ?x ?y ?lb ?rb ?lc ?rc ?p) ?z ?lb ?rb ?lc ?rc ?q)=>(DotGeneralOp ?x (DotGeneralOp ?y ?z ?lb ?rb ?lc ?rc ?p) ?lb ?rb ?lc ?rc ?q)";
+    let (x, y, z) = ("(input x@4_3 0)", "(input y@3_3 1)", "(input z@3_5 2)");
+    let expr = dot(&dot(x, y, "(Vec 1 0)"), z, "(Vec 0 2)");
+    let strict = dot(x, &dot(y, z, "(Vec 1 2)"), "(Vec 1 2)");
+    assert!(rewrites_to(rule, &expr, &strict));
+    let loose = dot(x, &dot(y, z, "(Vec 1 0)"), "(Vec 0 2)");
+    assert!(!rewrites_to(rule, &expr, &loose));
+}
@@ -0,0 +1,29 @@
+use std::time::{Duration, Instant};
+use tensat::profile::Profiler;
+
+#[test]
+fn phases_are_recorded_in_order() {
+    let mut profiler = Profiler::new();
+    let x = profiler.phase("first", || 1 + 1);
+    profiler.record("second", Instant::now(), Duration::from_millis(5));
+    assert_eq!(x, 2);
+
+    let json = profiler.to_json();
+    let phases = json["phases"].as_array().unwrap();
+    assert_eq!(phases.len(), 2);
+    assert_eq!(phases[0]["name"], "first");
+    assert_eq!(phases[1]["name"], "second");
+    assert_eq!(phases[1]["duration_us"], 5000);
+}
+
+#[test]
+fn chrome_trace_has_complete_events() {
+    let mut profiler = Profiler::new();
+    profiler.record("saturation", Instant::now(), Duration::from_micros(42));
+
+    let trace = profiler.to_chrome_trace();
+    let events = trace["traceEvents"].as_array().unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["ph"], "X");
+    assert_eq!(events[0]["dur"], 42);
+}
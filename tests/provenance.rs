@@ -0,0 +1,60 @@
+use egg::{EGraph, RecExpr};
+use std::collections::HashMap;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::provenance::node_provenance;
+use tensat::replay::AppliedRewrite;
+
+const X: &str = "(input x@2_3 0)";
+const Y: &str = "(input y@2_3 1)";
+
+fn graph(op: &str) -> RecExpr<Mdl> {
+    format!("(ReturnOp (Vec {}))", op).parse().unwrap()
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn only_rules_on_the_derivation_path_are_attributed() {
+    let start = graph(&format!("(AddOp {} {})", X, Y));
+    let mut egraph = EGraph::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    egraph.add_expr(&start);
+    let x = egraph.add_expr(&X.parse().unwrap());
+    let y = egraph.add_expr(&Y.parse().unwrap());
+    let sum = egraph.add(Mdl::AddOp([x, y]));
+
+    // All three rules apply at the eclass of the sum: "commute" turns x + y into
+    // y + x, "scale" turns x + y into x * y and "swap" turns y + x into y * x
+    let mut log = vec![];
+    for (rule, source, produced) in [
+        ("commute", Mdl::AddOp([x, y]), Mdl::AddOp([y, x])),
+        ("scale", Mdl::AddOp([x, y]), Mdl::MulOp([x, y])),
+        ("swap", Mdl::AddOp([y, x]), Mdl::MulOp([y, x])),
+    ] {
+        let id = egraph.add(produced.clone());
+        egraph.union(sum, id);
+        log.push(AppliedRewrite {
+            rule: rule.to_string(),
+            iteration: 0,
+            eclass: sum,
+            source: Some(source),
+            produced: vec![produced],
+        });
+    }
+    egraph.rebuild();
+
+    let provenance = |op: &str| {
+        let extracted = graph(op);
+        let provenance = node_provenance(&egraph, &start, &extracted, &log);
+        // The op is the node before the Vec and the ReturnOp
+        provenance[provenance.len() - 3].clone()
+    };
+    assert!(provenance(&format!("(AddOp {} {})", X, Y)).is_empty());
+    assert_eq!(provenance(&format!("(AddOp {} {})", Y, X)), ["commute"]);
+    assert_eq!(provenance(&format!("(MulOp {} {})", X, Y)), ["scale"]);
+    assert_eq!(
+        provenance(&format!("(MulOp {} {})", Y, X)),
+        ["commute", "swap"]
+    );
+}
@@ -0,0 +1,107 @@
+use egg::{EGraph, Id, RecExpr, Symbol};
+use std::collections::HashMap;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::optimize::{construct_best_rec, construct_remat_rec, ReconstructError};
+
+/// E-graph holding `(input x@2_3 0)`, with the ids of the var, num and input
+fn input_egraph() -> (EGraph<Mdl, TensorAnalysis>, Id, Id, Id) {
+    let mut egraph = EGraph::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    let var = egraph.add(Mdl::Var(Symbol::from("x@2_3")));
+    let num = egraph.add(Mdl::Num(0));
+    let input = egraph.add(Mdl::Input([var, num]));
+    (egraph, var, num, input)
+}
+
+fn reconstruct(
+    egraph: &EGraph<Mdl, TensorAnalysis>,
+    picked: &HashMap<Id, Mdl>,
+    root: Id,
+) -> Result<RecExpr<Mdl>, ReconstructError> {
+    let mut expr = RecExpr::default();
+    let mut added_memo = HashMap::new();
+    construct_best_rec(picked, root, &mut added_memo, egraph, &mut expr)?;
+    Ok(expr)
+}
+
+#[test]
+fn acyclic_selection_is_reconstructed() {
+    let (egraph, var, num, input) = input_egraph();
+    let picked: HashMap<Id, Mdl> = vec![
+        (var, Mdl::Var(Symbol::from("x@2_3"))),
+        (num, Mdl::Num(0)),
+        (input, Mdl::Input([var, num])),
+    ]
+    .into_iter()
+    .collect();
+    let expr = reconstruct(&egraph, &picked, input).unwrap();
+    assert_eq!(expr.to_string(), "(input x@2_3 0)");
+}
+
+#[test]
+fn cyclic_selection_names_the_cycle() {
+    let (egraph, var, num, input) = input_egraph();
+    let picked: HashMap<Id, Mdl> = vec![
+        (var, Mdl::Var(Symbol::from("x@2_3"))),
+        (num, Mdl::NegOp([input])),
+        (input, Mdl::Input([var, num])),
+    ]
+    .into_iter()
+    .collect();
+    let err = reconstruct(&egraph, &picked, input).unwrap_err();
+    assert_eq!(err, ReconstructError::Cycle(vec![input, num]));
+}
+
+#[test]
+fn missing_pick_is_reported() {
+    let (egraph, _, num, input) = input_egraph();
+    let picked: HashMap<Id, Mdl> = vec![(input, Mdl::NegOp([num]))].into_iter().collect();
+    let err = reconstruct(&egraph, &picked, input).unwrap_err();
+    assert_eq!(err, ReconstructError::NothingPicked(num));
+}
+
+#[test]
+fn remat_consumers_use_their_own_node() {
+    // Eclass 3 is computed twice: by ExpOp for NegOp and by TanhOp for MulOp
+    let c = |m: usize| Id::from(m);
+    let nodes = vec![
+        Mdl::Var(Symbol::from("x@2_3")),
+        Mdl::Num(0),
+        Mdl::Input([c(0), c(1)]),
+        Mdl::ExpOp([c(2)]),
+        Mdl::TanhOp([c(2)]),
+        Mdl::NegOp([c(3)]),
+        Mdl::MulOp([c(3), c(4)]),
+    ];
+    let i_to_nodes: Vec<&Mdl> = nodes.iter().collect();
+    let m_id_map: Vec<Id> = (0..6).map(c).collect();
+    let e_m = vec![vec![0], vec![1], vec![2], vec![3, 4], vec![5], vec![6]];
+    let g_i = vec![0, 1, 2, 3, 3, 4, 5];
+    let h_i = vec![
+        vec![],
+        vec![],
+        vec![0, 1],
+        vec![2],
+        vec![2],
+        vec![3],
+        vec![3, 4],
+    ];
+    let solved_x = vec![1; 7];
+    let solved_z = vec![(5, 3, 3), (6, 3, 4)];
+    let expr = construct_remat_rec(
+        &solved_x,
+        &solved_z,
+        &m_id_map,
+        &e_m,
+        &h_i,
+        &g_i,
+        &i_to_nodes,
+        5,
+    )
+    .unwrap();
+    assert_eq!(
+        expr.to_string(),
+        "(MulOp (TanhOp (input x@2_3 0)) (NegOp (ExpOp (input x@2_3 0))))"
+    );
+    // The input is shared by both recomputations
+    assert_eq!(expr.as_ref().len(), 7);
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use common::shape;
+use egg::{EGraph, RecExpr};
+use std::collections::HashMap;
+use tensat::input::{ffi, infer_dtypes, CppGraphConverter};
+use tensat::mock_cost::get_shape;
+use tensat::model::{Mdl, TensorAnalysis};
+
+/// Region of an argmax: the larger of two (value, index) pairs
+fn argmax_region(converter: &mut CppGraphConverter) -> i32 {
+    converter.begin_region();
+    let value = converter.new_input(0, &[], ffi::Type::f32);
+    let index = converter.new_input(1, &[], ffi::Type::i32);
+    let other_value = converter.new_input(2, &[], ffi::Type::f32);
+    let other_index = converter.new_input(3, &[], ffi::Type::i32);
+    // GE of floats, numbered as in StableHLO
+    let pred = converter.new_compare_op(value, other_value, 2, 1, &[]);
+    let value = converter.new_select_op(pred, value, other_value, &[]);
+    let index = converter.new_select_op(pred, index, other_index, &[]);
+    converter.end_region(&[value, index])
+}
+
+#[test]
+fn reduces_keep_their_init_values_and_body() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[4, 6], ffi::Type::f32);
+    let indices = converter.new_iota_op(1, &[4, 6]);
+    let indices = converter.new_convert_op(indices, 0, &[4, 6]);
+    let lowest = converter.new_splat_constant_op(f32::MIN, &[]);
+    let zero = converter.new_splat_constant_op(0.0, &[]);
+    let zero = converter.new_convert_op(zero, 0, &[]);
+    let body = argmax_region(&mut converter);
+    let shapes = vec![shape(&[4]), shape(&[4])];
+    let argmax = converter.new_reduce_op(&[x, indices], &[lowest, zero], &[1], body, &shapes);
+    let max = converter.new_index(0, argmax);
+    let index = converter.new_index(1, argmax);
+    assert_eq!(converter.tensor_shape(max), vec![4]);
+    converter.new_return_op(&[max, index]);
+
+    let nodes = converter.rec_expr_ref().as_ref();
+    match &nodes[argmax.id as usize] {
+        Mdl::ReduceOp([inputs, init_values, _, body_id]) => {
+            for (vec, expected) in [(inputs, [x, indices]), (init_values, [lowest, zero])] {
+                match &nodes[usize::from(*vec)] {
+                    Mdl::Vec(ids) => assert_eq!(
+                        ids.iter().map(|id| usize::from(*id)).collect::<Vec<_>>(),
+                        expected.map(|t| t.id as usize)
+                    ),
+                    other => panic!("expected a Vec, got {}", other),
+                }
+            }
+            assert_eq!(nodes[usize::from(*body_id)], Mdl::Num(body.into()));
+        }
+        other => panic!("expected a ReduceOp, got {}", other),
+    }
+    let dtypes = infer_dtypes(converter.rec_expr_ref());
+    assert!(dtypes[max.id as usize] == ffi::Type::f32);
+    assert!(dtypes[index.id as usize] == ffi::Type::i32);
+}
+
+#[test]
+#[should_panic(expected = "Invalid ReduceOp")]
+fn every_input_needs_an_init_value() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[4, 6], ffi::Type::f32);
+    let body = argmax_region(&mut converter);
+    converter.new_reduce_op(&[x, x], &[x], &[1], body, &vec![shape(&[4])]);
+}
+
+#[test]
+fn sums_share_their_body() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[4, 6], ffi::Type::f32);
+    let rows = converter.new_reduce_sum(x, &[1], &vec![shape(&[4])]);
+    let columns = converter.new_reduce_sum(x, &[0], &vec![shape(&[6])]);
+    assert_eq!(converter.tensor_shape(columns), vec![6]);
+    assert_eq!(converter.regions().len(), 1);
+    let body = |t: ffi::TensorHandle| match &converter.rec_expr_ref().as_ref()[t.id as usize] {
+        Mdl::ReduceOp([.., body]) => *body,
+        other => panic!("expected a ReduceOp, got {}", other),
+    };
+    assert_eq!(body(rows), body(columns));
+    let region = converter.regions()[0].to_string();
+    assert!(region.starts_with("(ReturnOp (Vec (AddOp"), "{}", region);
+}
+
+#[test]
+fn results_drop_the_reduced_dimensions() {
+    let expr: RecExpr<Mdl> = "(ReduceOp (Vec (input x@2_3_4 0) (input y@2_3_4 1)) \
+                              (Vec (input a@ 2) (input b@ 3)) (Vec 0 2) 0)"
+        .parse()
+        .unwrap();
+    let mut egraph = EGraph::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    let root = egraph.add_expr(&expr);
+    let data = &egraph[root].data;
+    assert_eq!(data.n_dims, vec![1, 1]);
+    assert_eq!(data.shapes[1][0], 3);
+    assert!(!data.constant);
+
+    let shapes = get_shape(
+        ffi::Ops::ReduceOp,
+        vec![shape(&[2, 3, 4]), shape(&[2, 3, 4]), shape(&[]), shape(&[])],
+        vec![ffi::Type::f32; 4],
+        vec![shape(&[0, 2])],
+        vec![0],
+    );
+    assert_eq!(
+        shapes.iter().map(|s| s.shape.clone()).collect::<Vec<_>>(),
+        vec![vec![3], vec![3]]
+    );
+}
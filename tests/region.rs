@@ -0,0 +1,48 @@
+use egg::Id;
+use tensat::input::{ffi, CppGraphConverter};
+use tensat::region::cut_region;
+
+fn id(tensor: ffi::TensorHandle) -> Id {
+    Id::from(tensor.id as usize)
+}
+
+/// x -> neg (producer) -> add w -> tanh (consumer), and exp x outside of the
+/// region
+fn converter() -> (CppGraphConverter, [ffi::TensorHandle; 3]) {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let w = converter.new_weight(1, &[2, 3], ffi::Type::f32);
+    let y = converter.new_neg_op(x, &[2, 3]);
+    let z = converter.new_add_op(y, w, &[2, 3]);
+    let r = converter.new_tanh_op(z, &[2, 3]);
+    let other = converter.new_exp_op(x, &[2, 3]);
+    converter.new_return_op(&[r, other]);
+    (converter, [w, y, r])
+}
+
+#[test]
+fn region_starts_at_the_producers() {
+    let (converter, [w, y, r]) = converter();
+    let region = cut_region(converter.rec_expr_ref(), &[id(y)], &[id(r)], |k, _| {
+        format!("b{}@2_3", k)
+    });
+    assert_eq!(
+        region.expr.to_string(),
+        "(ReturnOp (Vec (TanhOp (AddOp (input b0@2_3 0) (input b1@2_3 1)))))"
+    );
+    // The weight is used from outside of the region
+    assert_eq!(region.boundary, vec![id(y), id(w)]);
+    let tanh = region.expr.as_ref().len() - 3;
+    assert_eq!(region.origins[tanh], Some(usize::from(id(r))));
+    assert_eq!(region.origins.last(), Some(&None));
+}
+
+#[test]
+fn consumers_not_depending_on_producers_are_inputs() {
+    let (converter, [w, y, _]) = converter();
+    let region = cut_region(converter.rec_expr_ref(), &[id(y)], &[id(w)], |k, _| {
+        format!("b{}@2_3", k)
+    });
+    assert_eq!(region.expr.to_string(), "(ReturnOp (Vec (input b1@2_3 1)))");
+    assert_eq!(region.boundary, vec![id(y), id(w)]);
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::equal_with;
+use egg::RecExpr;
+use std::collections::HashMap;
+use tensat::input::{ffi, infer_dtypes, CppGraphConverter};
+use tensat::interpreter::numerically_equivalent;
+use tensat::model::{DenseConstant, Mdl, TensorAnalysis};
+use tensat::rewrites::remainder_rules;
+
+/// Splat constant of `value` of shape [4]
+fn constant(value: f32) -> String {
+    format!("(ConstantOp {} (Vec 4))", value.to_bits() as i32)
+}
+
+/// Splat constant of `value` of shape [4], converted to i32
+fn int_constant(value: f32) -> String {
+    format!("(ConvertOp {} 0)", constant(value))
+}
+
+/// Analysis with the i32 dense constants 0 (ones and minus ones) and 1
+/// (ones and twos), each of 4 elements
+fn analysis() -> TensorAnalysis {
+    let dense_constants: HashMap<i64, DenseConstant> = [
+        (0, vec![1.0, -1.0, 1.0, -1.0]),
+        (1, vec![1.0, 2.0, 1.0, 2.0]),
+    ]
+    .into_iter()
+    .map(|(handle, values)| {
+        let values = values.into();
+        let dtype = ffi::Type::i32;
+        (handle, DenseConstant { dtype, values })
+    })
+    .collect();
+    TensorAnalysis::new(&HashMap::new(), &[]).with_dense_constants(dense_constants)
+}
+
+/// Whether the remainder rules find `a` and `b` equal
+fn equal(a: &str, b: &str) -> bool {
+    // The operands of each RemainderOp have the same element type
+    let expr: RecExpr<Mdl> = a.parse().unwrap();
+    let dtypes = infer_dtypes(&expr);
+    for node in expr.as_ref() {
+        if let Mdl::RemainderOp([x, y]) = node {
+            assert!(
+                dtypes[usize::from(*x)] == dtypes[usize::from(*y)],
+                "{}",
+                expr
+            );
+        }
+    }
+    equal_with(analysis(), &remainder_rules(), a, b)
+}
+
+#[test]
+fn remainder_has_the_type_of_its_dividend() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[4], ffi::Type::i32);
+    let n = converter.new_input(1, &[4], ffi::Type::i32);
+    let rem = converter.new_remainder_op(x, n, &[4]);
+    assert_eq!(converter.tensor_shape(rem), vec![4]);
+    assert!(converter.tensor_dtype(rem) == ffi::Type::i32);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn integer_remainder_by_one_is_zero() {
+    let zero = "(ConvertOp (ConstantOp 0 (Vec 4)) 0)";
+    let x = "(input x@4@i32 0)";
+    assert!(equal(
+        &format!("(RemainderOp {} {})", x, int_constant(1.0)),
+        zero
+    ));
+    assert!(equal(
+        &format!("(RemainderOp {} {})", x, int_constant(-1.0)),
+        zero
+    ));
+    assert!(!equal(
+        &format!("(RemainderOp {} {})", x, int_constant(2.0)),
+        zero
+    ));
+    assert!(equal(
+        &format!("(RemainderOp {} (DenseConstantOp 0 (Vec 4) 0))", x),
+        zero
+    ));
+    assert!(!equal(
+        &format!("(RemainderOp {} (DenseConstantOp 1 (Vec 4) 0))", x),
+        zero
+    ));
+    // The remainder of a float by 1 is its fractional part
+    let y = "(input y@4 0)";
+    assert!(!equal(
+        &format!("(RemainderOp {} {})", y, constant(1.0)),
+        zero
+    ));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn remainder_by_the_same_divisor_twice() {
+    let (x, n) = ("(input x@4 0)", "(input n@4 1)");
+    let once = format!("(RemainderOp {} {})", x, n);
+    let twice = format!("(RemainderOp {} {})", once, n);
+    assert!(equal(&twice, &once));
+
+    let once: RecExpr<Mdl> = once.parse().unwrap();
+    let twice: RecExpr<Mdl> = twice.parse().unwrap();
+    let analysis = || TensorAnalysis::new(&HashMap::new(), &[]);
+    assert_eq!(
+        numerically_equivalent(&twice, &once, analysis, 0.0, 0),
+        Ok(true)
+    );
+}
@@ -0,0 +1,61 @@
+use tensat::input::{ffi, validate_graph, CppGraphConverter};
+
+/// Converter returning `(y, x)`, with an input added after the ReturnOp
+fn converter() -> (CppGraphConverter, [ffi::TensorHandle; 3]) {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let y = converter.new_input(1, &[4], ffi::Type::i32);
+    let returned = converter.new_return_op(&[y, x]);
+    converter.new_input(2, &[5], ffi::Type::f32);
+    (converter, [x, y, returned])
+}
+
+#[test]
+fn declared_results_are_the_root() {
+    let (mut converter, [x, y, returned]) = converter();
+    converter.config_mut().identity_mode = true;
+    let graph = converter.optimize();
+    assert!(validate_graph(&graph).is_ok());
+    let root = &graph.nodes[graph.root as usize];
+    assert_eq!(root.name, "ReturnOp");
+    assert_eq!(root.origin, returned.id);
+    let origins: Vec<i32> = graph
+        .results
+        .iter()
+        .map(|r| graph.nodes[*r as usize].origin)
+        .collect();
+    assert_eq!(origins, vec![y.id, x.id]);
+    // The input added after the ReturnOp isn't returned, so it is dropped
+    assert_eq!(graph.nodes.iter().filter(|n| n.name == "Input").count(), 2);
+}
+
+#[test]
+fn region_returns_dont_replace_the_root() {
+    let (mut converter, [x, _, returned]) = converter();
+    converter.begin_region();
+    let bx = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    converter.end_region(&[bx]);
+    converter.new_input(3, &[2, 3], ffi::Type::f32);
+    converter.config_mut().identity_mode = true;
+    let graph = converter.optimize();
+    assert_eq!(graph.nodes[graph.root as usize].origin, returned.id);
+    assert_eq!(graph.nodes[graph.results[1] as usize].origin, x.id);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn replay_checks_the_declared_results() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[4], ffi::Type::f32);
+    let neg = converter.new_neg_op(x, &[4]);
+    converter.new_return_op(&[neg]);
+    // Not returned, and computes something else
+    converter.new_exp_op(x, &[4]);
+    let config = converter.config_mut();
+    config.rule_file = format!("{}/converted.txt", env!("CARGO_MANIFEST_DIR"));
+    config.iter_limit = 2;
+    assert_eq!(converter.verify_by_replay(), -1);
+}
@@ -0,0 +1,145 @@
+use egg::{EGraph, Extractor, Id, RecExpr};
+use std::collections::HashMap;
+use tensat::config::ExtractorKind;
+use tensat::input::{ffi, infer_dtypes, CppGraphConverter};
+use tensat::model::{is_random, Mdl, TensorAnalysis};
+use tensat::optimize::{construct_best_rec, share_eclasses, CostModel, TensorCost};
+
+#[test]
+fn rng_ops_are_never_merged() {
+    let mut converter = CppGraphConverter::default();
+    let a = converter.new_input(0, &[], ffi::Type::f32);
+    let b = converter.new_input(1, &[], ffi::Type::f32);
+    let x = converter.new_rng_op(a, b, 1, &[4, 8]);
+    let y = converter.new_rng_op(a, b, 1, &[4, 8]);
+    assert_ne!(x.id, y.id);
+    assert_eq!(converter.tensor_shape(x), vec![4, 8]);
+    assert!(converter.tensor_dtype(x) == ffi::Type::f32);
+    assert!(is_random(&converter.rec_expr_ref().as_ref()[x.id as usize]));
+}
+
+#[test]
+fn bit_generators_return_the_state_and_the_bits() {
+    let mut converter = CppGraphConverter::default();
+    let state = converter.new_input(0, &[2], ffi::Type::i32);
+    let x = converter.new_rng_bit_generator_op(state, 2, ffi::Type::bf16, &[16]);
+    let new_state = converter.new_index(0, x);
+    let bits = converter.new_index(1, x);
+    assert_eq!(converter.tensor_shape(new_state), vec![2]);
+    assert_eq!(converter.tensor_shape(bits), vec![16]);
+    converter.new_return_op(&[new_state, bits]);
+
+    let dtypes = infer_dtypes(converter.rec_expr_ref());
+    assert!(dtypes[new_state.id as usize] == ffi::Type::i32);
+    assert!(dtypes[bits.id as usize] == ffi::Type::bf16);
+}
+
+/// E-graph of the converter's graph, with the eclass of each of its nodes
+fn egraph_of(converter: &CppGraphConverter) -> (EGraph<Mdl, TensorAnalysis>, Vec<Id>) {
+    let mut egraph = EGraph::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    let mut classes: Vec<Id> = vec![];
+    for node in converter.rec_expr_ref().as_ref() {
+        let node = node.clone().map_children(|c| classes[usize::from(c)]);
+        classes.push(egraph.add(node));
+    }
+    egraph.rebuild();
+    (egraph, classes)
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn bit_generators_of_the_same_state_are_merged() {
+    let mut converter = CppGraphConverter::default();
+    let state = converter.new_input(0, &[2], ffi::Type::i32);
+    let x = converter.new_rng_bit_generator_op(state, 2, ffi::Type::bf16, &[16]);
+    let y = converter.new_rng_bit_generator_op(state, 2, ffi::Type::bf16, &[16]);
+    let a = converter.new_input(1, &[], ffi::Type::f32);
+    let b = converter.new_input(2, &[], ffi::Type::f32);
+    let u = converter.new_rng_op(a, b, 1, &[4, 8]);
+    let v = converter.new_rng_op(a, b, 1, &[4, 8]);
+    let (egraph, classes) = egraph_of(&converter);
+    let class = |t: ffi::TensorHandle| egraph.find(classes[t.id as usize]);
+    // The bits are a function of the state, but each RngOp is its own draw
+    assert_eq!(class(x), class(y));
+    assert_ne!(class(u), class(v));
+}
+
+/// Graph using a draw `x` twice: x * (x + x), returned
+fn reused_draw(draw: impl Fn(&mut CppGraphConverter) -> ffi::TensorHandle) -> CppGraphConverter {
+    let mut converter = CppGraphConverter::default();
+    let x = draw(&mut converter);
+    let shape = converter.tensor_shape(x);
+    let sum = converter.new_add_op(x, x, &shape);
+    let product = converter.new_mul_op(x, sum, &shape);
+    converter.new_return_op(&[product]);
+    converter
+}
+
+fn num_draws(expr: &RecExpr<Mdl>) -> usize {
+    expr.as_ref().iter().filter(|node| is_random(node)).count()
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn extraction_never_duplicates_a_draw() {
+    let bits = reused_draw(|converter| {
+        let state = converter.new_input(0, &[2], ffi::Type::i32);
+        let x = converter.new_rng_bit_generator_op(state, 2, ffi::Type::f32, &[16]);
+        converter.new_index(1, x)
+    });
+    let uniform = reused_draw(|converter| {
+        let a = converter.new_input(0, &[], ffi::Type::f32);
+        let b = converter.new_input(1, &[], ffi::Type::f32);
+        converter.new_rng_op(a, b, 1, &[4, 8])
+    });
+    for converter in [bits, uniform] {
+        let (egraph, classes) = egraph_of(&converter);
+        let root = *classes.last().unwrap();
+        let cost_model = CostModel::new();
+
+        let tnsr_cost = TensorCost {
+            egraph: &egraph,
+            cost_model: &cost_model,
+        };
+        let (_, tree) = Extractor::new(&egraph, tnsr_cost).find_best(root);
+        assert_eq!(num_draws(&share_eclasses(&egraph, &tree)), 1);
+
+        // The ILP picks nodes by eclass, and each eclass is constructed once
+        let node_picked: HashMap<Id, Mdl> = egraph
+            .classes()
+            .map(|class| (class.id, class.nodes[0].clone()))
+            .collect();
+        let mut expr = RecExpr::default();
+        construct_best_rec(&node_picked, root, &mut HashMap::new(), &egraph, &mut expr).unwrap();
+        assert_eq!(num_draws(&expr), 1);
+    }
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn optimize_keeps_every_draw() {
+    let mut converter = CppGraphConverter::default();
+    let a = converter.new_input(0, &[], ffi::Type::f32);
+    let b = converter.new_input(1, &[], ffi::Type::f32);
+    let x = converter.new_rng_op(a, b, 2, &[4, 8]);
+    let y = converter.new_rng_op(a, b, 2, &[4, 8]);
+    let sum = converter.new_add_op(x, y, &[4, 8]);
+    converter.new_return_op(&[sum]);
+    let config = converter.config_mut();
+    config.rule_file = format!("{}/converted.txt", env!("CARGO_MANIFEST_DIR"));
+    config.iter_limit = 3;
+    config.extractor = ExtractorKind::Greedy;
+    let graph = converter.optimize();
+    let draws: Vec<&ffi::Node> = graph.nodes.iter().filter(|n| n.name == "RngOp").collect();
+    assert_eq!(draws.len(), 2);
+    assert_eq!(draws[0].shape[0].shape, vec![4, 8]);
+}
@@ -0,0 +1,47 @@
+use tensat::rewrites::{parse_rule_line, rule_options_from_str, RuleClass, RuleOptions};
+
+#[test]
+fn rule_without_options() {
+    let (rule, options) = parse_rule_line("(AddOp ?x ?y)=>(AddOp ?y ?x)");
+    assert_eq!(rule, "(AddOp ?x ?y)=>(AddOp ?y ?x)");
+    assert_eq!(options, RuleOptions::default());
+    assert_eq!(options.class, RuleClass::Expensive);
+}
+
+#[test]
+fn max_matches_is_parsed() {
+    let (rule, options) = parse_rule_line("(AddOp ?x ?y)=>(AddOp ?y ?x);max_matches=50");
+    assert_eq!(rule, "(AddOp ?x ?y)=>(AddOp ?y ?x)");
+    assert_eq!(options.max_matches, Some(50));
+}
+
+#[test]
+fn class_is_parsed() {
+    let (_, options) = parse_rule_line("(AddOp ?x ?y)=>(AddOp ?y ?x);class=cheap;max_matches=3");
+    assert_eq!(options.class, RuleClass::Cheap);
+    assert_eq!(options.max_matches, Some(3));
+}
+
+#[test]
+fn options_are_keyed_by_rule_name() {
+    let options = rule_options_from_str(&[
+        "(AddOp ?x ?y)=>(AddOp ?y ?x)",
+        "(MulOp ?x ?y)=>(MulOp ?y ?x);max_matches=7",
+    ]);
+    assert_eq!(options.len(), 2);
+    assert_eq!(options["rule0"].max_matches, None);
+    assert_eq!(options["rule1"].max_matches, Some(7));
+}
+
+#[test]
+#[should_panic]
+fn unknown_option_is_rejected() {
+    parse_rule_line("(AddOp ?x ?y)=>(AddOp ?y ?x);max_match=50");
+}
+
+#[test]
+fn max_match_ms_is_parsed() {
+    let (_, options) = parse_rule_line("(AddOp ?x ?y)=>(AddOp ?y ?x);max_match_ms=200");
+    assert_eq!(options.max_match_ms, Some(200));
+    assert_eq!(options.max_matches, None);
+}
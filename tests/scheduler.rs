@@ -0,0 +1,114 @@
+use egg::{rewrite, EGraph, Id, Pattern, RecExpr, Rewrite, RewriteScheduler, Runner};
+use std::collections::HashMap;
+use tensat::metrics::{counter_value, names};
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::rewrites::{RuleClass, RuleOptions};
+use tensat::scheduler::{CappedScheduler, RuleBudget};
+
+/// Whether saturating with a rule swapping Vecs, with the given options,
+/// makes `(Vec 1 2)` equal to `(Vec 2 1)`
+fn swaps(options: RuleOptions) -> bool {
+    let rules: Vec<Rewrite<Mdl, TensorAnalysis>> =
+        vec![rewrite!("swap"; "(Vec ?x ?y)" => "(Vec ?y ?x)")];
+    let options: HashMap<String, RuleOptions> =
+        vec![("swap".to_string(), options)].into_iter().collect();
+    let expr: RecExpr<Mdl> = "(Vec 1 2)".parse().unwrap();
+    let mut runner = Runner::default()
+        .with_egraph(EGraph::new(TensorAnalysis::new(&HashMap::new(), &[])))
+        .with_expr(&expr)
+        .with_iter_limit(2)
+        .with_scheduler(CappedScheduler::new(options, None))
+        .run(&rules);
+    let swapped = runner.egraph.add_expr(&"(Vec 2 1)".parse().unwrap());
+    runner.egraph.rebuild();
+    runner.egraph.find(swapped) == runner.egraph.find(runner.roots[0])
+}
+
+#[test]
+fn rule_over_match_time_limit_is_skipped() {
+    assert!(swaps(RuleOptions::default()));
+
+    let timeouts = counter_value(names::RULE_MATCH_TIMEOUTS);
+    let options = RuleOptions {
+        max_match_ms: Some(0),
+        ..RuleOptions::default()
+    };
+    assert!(!swaps(options));
+    assert!(counter_value(names::RULE_MATCH_TIMEOUTS) > timeouts);
+}
+
+/// Number of the Vecs `(Vec 1 2)`, `(Vec 3 4)` and `(Vec 5 6)` a rule swapping
+/// Vecs swaps in one iteration, with the given budget for its class
+fn swapped(budget: RuleBudget) -> usize {
+    let rules: Vec<Rewrite<Mdl, TensorAnalysis>> =
+        vec![rewrite!("swap"; "(Vec ?x ?y)" => "(Vec ?y ?x)")];
+    let expr: RecExpr<Mdl> = "(Vec (Vec 1 2) (Vec 3 4) (Vec 5 6))".parse().unwrap();
+    let scheduler =
+        CappedScheduler::new(HashMap::new(), None).with_budget(RuleClass::default(), budget);
+    let mut runner = Runner::default()
+        .with_egraph(EGraph::new(TensorAnalysis::new(&HashMap::new(), &[])))
+        .with_expr(&expr)
+        .with_iter_limit(1)
+        .with_scheduler(scheduler)
+        .run(&rules);
+    let egraph = &mut runner.egraph;
+    [("1 2", "2 1"), ("3 4", "4 3"), ("5 6", "6 5")]
+        .iter()
+        .filter(|(pair, swapped)| {
+            let pair = egraph.add_expr(&format!("(Vec {})", pair).parse().unwrap());
+            let swapped = egraph.add_expr(&format!("(Vec {})", swapped).parse().unwrap());
+            egraph.find(pair) == egraph.find(swapped)
+        })
+        .count()
+}
+
+#[test]
+fn node_budget_limits_the_matches_applied() {
+    assert_eq!(swapped(RuleBudget::default()), 3);
+    // Each swap adds a node, so the budget runs out after the first
+    let budget = RuleBudget {
+        nodes: Some(1),
+        time: None,
+    };
+    assert_eq!(swapped(budget), 1);
+}
+
+#[test]
+fn matching_stops_at_the_time_limit() {
+    // 100 Vecs in one eclass, so each of the 200 outer Vecs matches the
+    // pattern 10^4 times
+    let mut egraph = EGraph::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    let leaves: Vec<Id> = (0..100)
+        .map(|i| egraph.add_expr(&format!("(Vec {})", i).parse().unwrap()))
+        .collect();
+    for leaf in &leaves[1..] {
+        egraph.union(leaves[0], *leaf);
+    }
+    egraph.rebuild();
+    for i in 0..200 {
+        let n = egraph.add(Mdl::Num(1000 + i));
+        egraph.add(Mdl::Vec(vec![leaves[0], leaves[0], n]));
+    }
+    egraph.rebuild();
+
+    let lhs = "(Vec (Vec ?a) (Vec ?b) ?n)";
+    let rule: Rewrite<Mdl, TensorAnalysis> = rewrite!("pathological"; lhs => "?n");
+    let options = RuleOptions {
+        max_match_ms: Some(1),
+        ..RuleOptions::default()
+    };
+    let options: HashMap<String, RuleOptions> = vec![("pathological".to_string(), options)]
+        .into_iter()
+        .collect();
+    let patterns: HashMap<String, Pattern<Mdl>> =
+        vec![("pathological".to_string(), lhs.parse().unwrap())]
+            .into_iter()
+            .collect();
+    let mut scheduler = CappedScheduler::new(options, None).with_patterns(patterns);
+    let timeouts = counter_value(names::RULE_MATCH_TIMEOUTS);
+    let matches = scheduler.search_rewrite(0, &egraph, &rule);
+    // The search stopped early, keeping the matches found until then
+    assert!(matches.len() < 200, "{} eclasses matched", matches.len());
+    assert!(matches.iter().all(|m| m.substs.len() == 10_000));
+    assert!(counter_value(names::RULE_MATCH_TIMEOUTS) > timeouts);
+}
@@ -0,0 +1,73 @@
+use tensat::config::OptimizeConfig;
+use tensat::input::{graph_to_string, CppGraphConverter};
+use tensat::metrics::{counter_value, names};
+use tensat::session::OptimizerSession;
+
+const DENSE: &str =
+    "(TanhOp (DotGeneralOp (input x@4_3 0) (input w@3_5 1) (Vec) (Vec) (Vec 1) (Vec 0) (Vec)))";
+
+fn session() -> OptimizerSession {
+    let mut config = OptimizeConfig::default();
+    config.rule_file = format!("{}/converted.txt", env!("CARGO_MANIFEST_DIR"));
+    config.iter_limit = 3;
+    OptimizerSession::new(config)
+}
+
+fn converter(expr: &str) -> CppGraphConverter {
+    CppGraphConverter::from_rec_expr(expr.parse().unwrap())
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn repeated_graphs_are_reused() {
+    let mut session = session();
+    let first = session.optimize_graph(&mut converter(DENSE));
+    let measured = session.num_measured();
+    assert!(measured > 0);
+    assert_eq!(session.num_cached(), 1);
+
+    let hits = counter_value(names::SESSION_CACHE_HITS);
+    let second = session.optimize_graph(&mut converter(DENSE));
+    assert_eq!(graph_to_string(&first), graph_to_string(&second));
+    assert_eq!(counter_value(names::SESSION_CACHE_HITS), hits + 1.0);
+    assert_eq!(session.num_cached(), 1);
+    assert_eq!(session.num_measured(), measured);
+
+    // Another function with the same ops only measures the new ones
+    let negated = format!("(NegOp {})", DENSE);
+    session.optimize_graph(&mut converter(&negated));
+    assert_eq!(session.num_cached(), 2);
+    assert!(session.num_measured() > measured);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn session_config_replaces_converter_config() {
+    let mut session = session();
+    let mut converter = converter(DENSE);
+    converter.config_mut().iter_limit = 100;
+    session.optimize_graph(&mut converter);
+    assert_eq!(converter.config().iter_limit, 3);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn reloading_rules_keeps_measured_costs() {
+    let mut session = session();
+    session.optimize_graph(&mut converter(DENSE));
+    let measured = session.num_measured();
+    session.reload_rules();
+    assert_eq!(session.num_cached(), 0);
+    assert_eq!(session.num_measured(), measured);
+    session.optimize_graph(&mut converter(DENSE));
+    assert_eq!(session.num_cached(), 1);
+}
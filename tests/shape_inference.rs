@@ -0,0 +1,321 @@
+//! Property tests comparing the shapes inferred by TensorAnalysis against an
+//! independent implementation of the StableHLO shape rules.
+//!
+//! Shape inference goes through the C++ side, so the tests are ignored by
+//! default; run them with `cargo test --test shape_inference -- --ignored`. With
+//! the `no-cxx-costmodel` feature they check the Rust stand-ins instead.
+
+use egg::{EGraph, Id, RecExpr};
+use itertools::Itertools;
+use proptest::prelude::*;
+use std::collections::HashMap;
+use tensat::model::{Mdl, TensorAnalysis};
+
+/// Shape of the root of `expr`, as inferred by TensorAnalysis
+fn infer(expr: &str) -> Vec<i64> {
+    let expr: RecExpr<Mdl> = expr.parse().unwrap();
+    let mut egraph = EGraph::<Mdl, TensorAnalysis>::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    let mut ids: Vec<Id> = Vec::new();
+    for node in expr.as_ref() {
+        ids.push(egraph.add(node.clone().map_children(|c| ids[usize::from(c)])));
+    }
+    let data = &egraph[*ids.last().unwrap()].data;
+    data.shapes[0][..data.n_dims[0]]
+        .iter()
+        .map(|d| *d as i64)
+        .collect()
+}
+
+fn input(k: usize, shape: &[i64]) -> String {
+    format!("(input input_{}@{} {})", k, shape.iter().join("_"), k)
+}
+
+fn vec_node(nums: &[i64]) -> String {
+    if nums.is_empty() {
+        "(Vec)".to_string()
+    } else {
+        format!("(Vec {})", nums.iter().join(" "))
+    }
+}
+
+// Reference shape rules, following the StableHLO spec
+
+fn transpose_shape(shape: &[i64], perm: &[i64]) -> Vec<i64> {
+    perm.iter().map(|p| shape[*p as usize]).collect()
+}
+
+fn concatenate_shape(a: &[i64], b: &[i64], dim: usize) -> Vec<i64> {
+    let mut shape = a.to_vec();
+    shape[dim] += b[dim];
+    shape
+}
+
+fn slice_shape(start: &[i64], limit: &[i64], strides: &[i64]) -> Vec<i64> {
+    start
+        .iter()
+        .zip(limit)
+        .zip(strides)
+        .map(|((s, l), st)| (l - s + st - 1) / st)
+        .collect()
+}
+
+fn pad_shape(shape: &[i64], low: &[i64], high: &[i64], interior: &[i64]) -> Vec<i64> {
+    (0..shape.len())
+        .map(|i| low[i] + shape[i] + (shape[i] - 1) * interior[i] + high[i])
+        .collect()
+}
+
+fn dot_general_shape(
+    lhs: &[i64],
+    rhs: &[i64],
+    lb: &[i64],
+    rb: &[i64],
+    lc: &[i64],
+    rc: &[i64],
+) -> Vec<i64> {
+    let free = |shape: &[i64], batch: &[i64], contract: &[i64]| -> Vec<i64> {
+        (0..shape.len() as i64)
+            .filter(|d| !batch.contains(d) && !contract.contains(d))
+            .map(|d| shape[d as usize])
+            .collect()
+    };
+    lb.iter()
+        .map(|d| lhs[*d as usize])
+        .chain(free(lhs, lb, lc))
+        .chain(free(rhs, rb, rc))
+        .collect()
+}
+
+/// NHWC convolution by an HWIO kernel, with the same stride, padding and
+/// kernel dilation in both spatial dimensions
+fn conv2d_shape(input: &[i64], kernel: &[i64], stride: i64, pad: i64, dilation: i64) -> Vec<i64> {
+    let out = |size: i64, window: i64| (size + 2 * pad - dilation * (window - 1) - 1) / stride + 1;
+    vec![
+        input[0],
+        out(input[1], kernel[0]),
+        out(input[2], kernel[1]),
+        kernel[3],
+    ]
+}
+
+fn shape_strategy() -> impl Strategy<Value = Vec<i64>> {
+    prop::collection::vec(1i64..6, 1..5)
+}
+
+/// (size, start, limit, stride) for each dimension
+fn slice_strategy() -> impl Strategy<Value = Vec<(i64, i64, i64, i64)>> {
+    prop::collection::vec(
+        (1i64..8)
+            .prop_flat_map(|size| (Just(size), 0..size))
+            .prop_flat_map(|(size, start)| (Just(size), Just(start), start + 1..=size, 1i64..4)),
+        1..5,
+    )
+}
+
+/// (size, low, high, interior) for each dimension. Edge padding can be
+/// negative, but removes at most half of the interior-padded dimension on
+/// each side
+fn pad_strategy() -> impl Strategy<Value = Vec<(i64, i64, i64, i64)>> {
+    prop::collection::vec(
+        (1i64..6, 0i64..3).prop_flat_map(|(size, interior)| {
+            let half = (size + (size - 1) * interior) / 2;
+            (Just(size), -half..3, -half..3, Just(interior))
+        }),
+        1..5,
+    )
+}
+
+proptest! {
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn transpose(
+        (shape, perm) in shape_strategy().prop_flat_map(|shape| {
+            let n = shape.len() as i64;
+            (Just(shape), Just((0..n).collect::<Vec<i64>>()).prop_shuffle())
+        })
+    ) {
+        let expr = format!("(TransposeOp {} {})", input(0, &shape), vec_node(&perm));
+        prop_assert_eq!(infer(&expr), transpose_shape(&shape, &perm));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn concatenate(
+        (a, dim, size) in shape_strategy()
+            .prop_flat_map(|a| { let n = a.len(); (Just(a), 0..n, 1i64..6) })
+    ) {
+        let mut b = a.clone();
+        b[dim] = size;
+        let expr = format!(
+            "(ConcatenateOp (Vec {} {}) {})",
+            input(0, &a),
+            input(1, &b),
+            dim
+        );
+        prop_assert_eq!(infer(&expr), concatenate_shape(&a, &b, dim));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn slice(dims in slice_strategy()) {
+        let shape: Vec<i64> = dims.iter().map(|d| d.0).collect();
+        let start: Vec<i64> = dims.iter().map(|d| d.1).collect();
+        let limit: Vec<i64> = dims.iter().map(|d| d.2).collect();
+        let strides: Vec<i64> = dims.iter().map(|d| d.3).collect();
+        let expr = format!(
+            "(SliceOp {} {} {} {})",
+            input(0, &shape),
+            vec_node(&start),
+            vec_node(&limit),
+            vec_node(&strides)
+        );
+        prop_assert_eq!(infer(&expr), slice_shape(&start, &limit, &strides));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn pad(dims in pad_strategy()) {
+        let shape: Vec<i64> = dims.iter().map(|d| d.0).collect();
+        let low: Vec<i64> = dims.iter().map(|d| d.1).collect();
+        let high: Vec<i64> = dims.iter().map(|d| d.2).collect();
+        let interior: Vec<i64> = dims.iter().map(|d| d.3).collect();
+        let expr = format!(
+            "(PadOp {} {} {} {} {})",
+            input(0, &shape),
+            input(1, &[]),
+            vec_node(&low),
+            vec_node(&high),
+            vec_node(&interior)
+        );
+        prop_assert_eq!(infer(&expr), pad_shape(&shape, &low, &high, &interior));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn dot_general(
+        batch in prop::collection::vec(1i64..4, 0..3),
+        m in 1i64..6,
+        k in 1i64..6,
+        n in 1i64..6,
+        transpose_rhs in any::<bool>(),
+    ) {
+        let nb = batch.len() as i64;
+        let lhs: Vec<i64> = batch.iter().copied().chain([m, k]).collect();
+        let (rhs, rc): (Vec<i64>, i64) = if transpose_rhs {
+            (batch.iter().copied().chain([n, k]).collect(), nb + 1)
+        } else {
+            (batch.iter().copied().chain([k, n]).collect(), nb)
+        };
+        let dims: Vec<i64> = (0..nb).collect();
+        let expr = format!(
+            "(DotGeneralOp {} {} {} {} {} {} (Vec))",
+            input(0, &lhs),
+            input(1, &rhs),
+            vec_node(&dims),
+            vec_node(&dims),
+            vec_node(&[nb + 1]),
+            vec_node(&[rc])
+        );
+        prop_assert_eq!(
+            infer(&expr),
+            dot_general_shape(&lhs, &rhs, &dims, &dims, &[nb + 1], &[rc])
+        );
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn reshape(shape in shape_strategy(), flatten in any::<bool>()) {
+        // Flattening and reversing the dimensions both keep the number of elements
+        let target: Vec<i64> = if flatten {
+            vec![shape.iter().product()]
+        } else {
+            shape.iter().rev().copied().collect()
+        };
+        let expr = format!("(ReshapeOp {} {})", input(0, &shape), vec_node(&target));
+        prop_assert_eq!(infer(&expr), target);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn convolution(
+        (n, h, w, c) in (1i64..3, 1i64..10, 1i64..10, 1i64..4),
+        (kh, kw, f) in (1i64..4, 1i64..4, 1i64..4),
+        stride in 1i64..3,
+        pad in 0i64..2,
+        dilation in 1i64..3,
+    ) {
+        prop_assume!(h + 2 * pad > dilation * (kh - 1) && w + 2 * pad > dilation * (kw - 1));
+        let (lhs, rhs) = ([n, h, w, c], [kh, kw, c, f]);
+        let expr = format!(
+            "(ConvolutionOp {} {} {} {} (Vec) {} {} 1 1 (Vec))",
+            input(0, &lhs),
+            input(1, &rhs),
+            vec_node(&[stride, stride]),
+            vec_node(&[pad; 4]),
+            vec_node(&[dilation, dilation]),
+            vec_node(&[0, 3, 1, 2, 2, 3, 0, 1, 0, 3, 1, 2])
+        );
+        prop_assert_eq!(infer(&expr), conv2d_shape(&lhs, &rhs, stride, pad, dilation));
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn select_and_scatter((n, h, w, c) in (1i64..3, 1i64..6, 1i64..6, 1i64..4)) {
+        // Gradient of a 2x2 max pool with stride 2 over an NHWC operand
+        let operand = [n, 2 * h, 2 * w, c];
+        let expr = format!(
+            "(SelectAndScatterOp {} {} {} {} {} {})",
+            input(0, &operand),
+            input(1, &[n, h, w, c]),
+            input(2, &[]),
+            vec_node(&[1, 2, 2, 1]),
+            vec_node(&[1, 2, 2, 1]),
+            vec_node(&[0; 8])
+        );
+        prop_assert_eq!(infer(&expr), operand.to_vec());
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn sort(shape in shape_strategy(), dim in any::<prop::sample::Index>()) {
+        // Sorting keys and values along a dimension keeps the shape of both
+        let dim = dim.index(shape.len());
+        let expr = format!(
+            "(Index 1 (SortOp (Vec {} {}) {} 1 2 0))",
+            input(0, &shape),
+            input(1, &shape),
+            dim
+        );
+        prop_assert_eq!(infer(&expr), shape);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn clamp(shape in shape_strategy(), scalar_bounds in any::<bool>()) {
+        // Bounds are scalars or of the operand's shape
+        let bounds: &[i64] = if scalar_bounds { &[] } else { &shape };
+        let expr = format!(
+            "(ClampOp {} {} {})",
+            input(0, bounds),
+            input(1, &shape),
+            input(2, bounds)
+        );
+        prop_assert_eq!(infer(&expr), shape);
+    }
+
+    #[test]
+    #[cfg_attr(not(feature = "no-cxx-costmodel"), ignore = "needs the C++ shape inference")]
+    fn linear_algebra(n in 1..6i64, m in 1..6i64, left_side in any::<bool>()) {
+        // The factor has the shape of the matrix, the solution that of b
+        let a = input(0, &[2, n, n]);
+        prop_assert_eq!(infer(&format!("(CholeskyOp {} 1)", a)), vec![2, n, n]);
+        let b = if left_side { vec![2, n, m] } else { vec![2, m, n] };
+        let expr = format!(
+            "(TriangularSolveOp {} {} {} 1 0 1)",
+            a,
+            input(1, &b),
+            left_side as i64
+        );
+        prop_assert_eq!(infer(&expr), b);
+    }
+}
@@ -0,0 +1,51 @@
+use egg::{EGraph, Id, Pattern, RecExpr, Searcher};
+use std::collections::HashMap;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::sharding::*;
+
+/// E-graph of `(NegOp (NegOp (input x@4_4 0)))` with the inner NegOp sharded
+fn sharded_egraph() -> (EGraph<Mdl, TensorAnalysis>, RecExpr<Mdl>, Id) {
+    let expr: RecExpr<Mdl> = "(NegOp (NegOp (input x@4_4 0)))".parse().unwrap();
+    let mut egraph = EGraph::<Mdl, TensorAnalysis>::new(TensorAnalysis::new(&HashMap::new(), &[]));
+    let root = egraph.add_expr(&expr);
+    let inner = Id::from(expr.as_ref().len() - 2);
+    let shardings: HashMap<Id, String> = vec![(inner, "{devices=[2,1]0,1}".to_string())]
+        .into_iter()
+        .collect();
+    egraph.analysis.shardings = sharded_classes(&egraph, &expr, &shardings);
+    (egraph, expr, root)
+}
+
+fn breaks(egraph: &EGraph<Mdl, TensorAnalysis>, pattern: &str, root: Id) -> bool {
+    let pattern: Pattern<Mdl> = pattern.parse().unwrap();
+    let matches = pattern.search_eclass(egraph, root).unwrap();
+    breaks_sharding(pattern.ast.as_ref(), egraph, &matches.substs[0])
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn rewrites_through_a_sharded_tensor_are_rejected() {
+    let (egraph, _, root) = sharded_egraph();
+    assert!(breaks(&egraph, "(NegOp (NegOp ?x))", root));
+    // The sharded tensor is a variable or the root of the match
+    assert!(!breaks(&egraph, "(NegOp ?x)", root));
+    let inner = egraph[root].nodes[0].children()[0];
+    assert!(!breaks(&egraph, "(NegOp (input ?x ?n))", inner));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn extracted_nodes_carry_the_sharding() {
+    let (egraph, expr, _) = sharded_egraph();
+    let shardings = node_shardings(&egraph, &expr);
+    let n = shardings.len();
+    assert_eq!(shardings[n - 2], "{devices=[2,1]0,1}");
+    assert_eq!(shardings[n - 1], "");
+    assert_eq!(shardings[n - 3], "");
+}
@@ -0,0 +1,15 @@
+use tensat::input::CppGraphConverter;
+
+#[test]
+fn snapshot_settings_are_checked() {
+    let mut converter = CppGraphConverter::default();
+    assert!(converter.set_snapshots("snapshots", 0, 3).is_err());
+    assert!(converter.set_snapshots("snapshots", -2, 3).is_err());
+    assert!(converter.set_snapshots("snapshots", 2, -1).is_err());
+    assert_eq!(converter.config_mut().snapshot_dir, None);
+
+    converter.set_snapshots("snapshots", 2, 3).unwrap();
+    let config = converter.config_mut();
+    assert_eq!(config.snapshot_dir.as_deref(), Some("snapshots"));
+    assert_eq!((config.snapshot_every, config.max_snapshots), (2, 3));
+}
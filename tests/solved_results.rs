@@ -0,0 +1,32 @@
+use tensat::optimize::{parse_solved, IlpError};
+
+#[test]
+fn solved_results_are_parsed() {
+    let solved = parse_solved(r#"{"solved_x": [1, 0, 1], "cost": 2.5, "time": 0.1}"#).unwrap();
+    assert_eq!(solved.solved_x, vec![1, 0, 1]);
+    assert_eq!(solved.cost, 2.5);
+    assert!(solved.solved_z.is_empty());
+}
+
+#[test]
+fn script_errors_map_to_ilp_errors() {
+    let error = |text: &str| parse_solved(text).unwrap_err();
+    assert_eq!(
+        error(r#"{"error": "infeasible", "message": "The extraction ILP is infeasible"}"#),
+        IlpError::Infeasible
+    );
+    assert_eq!(
+        error(r#"{"error": "solver_unavailable", "message": "no gurobi"}"#),
+        IlpError::SolverUnavailable("no gurobi".to_string())
+    );
+    assert_eq!(
+        error(r#"{"error": "no_solution", "message": "time limit"}"#),
+        IlpError::NoSolution("time limit".to_string())
+    );
+    assert!(matches!(
+        error(r#"{"error": "internal", "message": "KeyError('x')"}"#),
+        IlpError::Failed(_)
+    ));
+    assert!(matches!(error("not json"), IlpError::Failed(_)));
+    assert!(matches!(error(r#"{"cost": 1.0}"#), IlpError::Failed(_)));
+}
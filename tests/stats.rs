@@ -0,0 +1,54 @@
+use tensat::config::ExtractorKind;
+use tensat::input::CppGraphConverter;
+
+const DENSE: &str =
+    "(TanhOp (DotGeneralOp (input x@4_3 0) (input w@3_5 1) (Vec) (Vec) (Vec 1) (Vec 0) (Vec)))";
+
+fn converter(stats_every: i32) -> CppGraphConverter {
+    let mut converter = CppGraphConverter::from_rec_expr(DENSE.parse().unwrap());
+    let config = converter.config_mut();
+    config.rule_file = format!("{}/converted.txt", env!("CARGO_MANIFEST_DIR"));
+    config.iter_limit = 3;
+    config.extractor = ExtractorKind::Greedy;
+    converter.set_stats_every(stats_every);
+    converter
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn stats_are_taken_after_saturation() {
+    let converter = converter(0);
+    assert!(converter.egraph_stats().is_empty());
+    converter.optimize();
+    let stats = converter.egraph_stats();
+    assert_eq!(stats.len(), 1);
+    let last = &stats[0];
+    assert!(last.iteration > 0);
+    assert!(last.num_classes > 0 && last.num_enodes >= last.num_classes);
+    let avg = last.num_enodes as f32 / last.num_classes as f32;
+    assert!((last.avg_nodes_per_class - avg).abs() < 1e-6);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn stats_can_be_taken_during_saturation() {
+    let converter = converter(1);
+    converter.optimize();
+    let stats = converter.egraph_stats();
+    assert!(stats.len() > 1);
+    assert_eq!(stats[0].iteration, 0);
+    for (before, after) in stats.iter().zip(&stats[1..]) {
+        assert!(before.iteration < after.iteration);
+        assert!(before.num_enodes <= after.num_enodes);
+    }
+
+    // Taken again by every optimize
+    converter.optimize();
+    assert_eq!(converter.egraph_stats().len(), stats.len());
+}
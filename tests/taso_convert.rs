@@ -0,0 +1,58 @@
+use tensat::taso_convert::*;
+
+#[test]
+fn converts_elementwise_and_matmul() {
+    let converted = convert_taso_rules(
+        "ewadd(input_1,input_2)==ewadd(input_2,input_1)matmul(input_1,ewadd(input_4,input_5))==ewadd(matmul(input_1,input_4),matmul(input_1,input_5))",
+    );
+    assert!(converted.skipped.is_empty());
+    assert_eq!(
+        converted.rules,
+        vec![
+            "(AddOp ?input_1 ?input_2)=>(AddOp ?input_2 ?input_1)",
+            "(DotGeneralOp ?input_1 (AddOp ?input_4 ?input_5) ?lb ?rb ?lc ?rc ?p)=>(AddOp (DotGeneralOp ?input_1 ?input_4 ?lb ?rb ?lc ?rc ?p) (DotGeneralOp ?input_1 ?input_5 ?lb ?rb ?lc ?rc ?p))",
+        ]
+    );
+}
+
+#[test]
+fn converts_concat_and_transpose() {
+    let converted =
+        convert_taso_rules("transpose(concat(1,input_1,input_2))==concat(0,transpose(input_1),transpose(input_2))");
+    assert_eq!(
+        converted.rules,
+        vec!["(TransposeOp (ConcatenateOp (Vec ?input_1 ?input_2) 1) (Vec 1 0))=>(ConcatenateOp (Vec (TransposeOp ?input_1 (Vec 1 0)) (TransposeOp ?input_2 (Vec 1 0))) 0)"]
+    );
+}
+
+#[test]
+fn converts_smul_to_a_scalar_broadcast() {
+    let converted = convert_taso_rules("smul(smul(input_1,input_2),input_3)==smul(input_1,input_3)");
+    assert_eq!(
+        converted.rules,
+        vec!["(MulOp (MulOp ?input_1 (BroadcastInDimOp ?input_2 (Vec))) (BroadcastInDimOp ?input_3 (Vec)))=>(MulOp ?input_1 (BroadcastInDimOp ?input_3 (Vec)))"]
+    );
+}
+
+#[test]
+fn converts_every_operand_of_concat() {
+    let converted = convert_taso_rules(
+        "concat(1,2,input_1,input_2,input_3)==concat(1,input_1,concat(1,input_2,input_3))",
+    );
+    assert_eq!(
+        converted.rules,
+        vec!["(ConcatenateOp (Vec ?input_1 ?input_2 ?input_3) 1)=>(ConcatenateOp (Vec ?input_1 (ConcatenateOp (Vec ?input_2 ?input_3) 1)) 1)"]
+    );
+    let converted = convert_taso_rules("concat(1,input_1)==input_1");
+    assert!(converted.rules.is_empty());
+    assert_eq!(converted.skipped.len(), 1);
+}
+
+#[test]
+fn skips_unsupported_rules() {
+    let converted = convert_taso_rules(
+        "relu(input_1)==relu(relu(input_1))ewadd(input_1,input_1)==smul(input_1,input_2)",
+    );
+    assert!(converted.rules.is_empty());
+    assert_eq!(converted.skipped.len(), 2);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::equal;
+use egg::RecExpr;
+use std::collections::HashMap;
+use tensat::input::{ffi, infer_dtypes, CppGraphConverter};
+use tensat::interpreter::numerically_equivalent;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::passes::validate_vecs;
+use tensat::rewrites::tuple_rules;
+
+#[test]
+fn elements_keep_their_shapes_and_types() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let y = converter.new_input(1, &[4], ffi::Type::i32);
+    let tuple = converter.new_tuple_op(&[x, y]);
+    assert_eq!(converter.tensor(tuple).tensor_data.shapes.len(), 2);
+    let first = converter.new_get_tuple_element_op(tuple, 0);
+    let second = converter.new_get_tuple_element_op(tuple, 1);
+    assert_eq!(converter.tensor_shape(first), vec![2, 3]);
+    assert_eq!(converter.tensor_shape(second), vec![4]);
+    assert!(converter.tensor_dtype(first) == ffi::Type::f32);
+    assert!(converter.tensor_dtype(second) == ffi::Type::i32);
+    converter.new_return_op(&[first, second]);
+
+    let dtypes = infer_dtypes(converter.rec_expr_ref());
+    assert!(dtypes[second.id as usize] == ffi::Type::i32);
+    assert_eq!(validate_vecs(converter.rec_expr_ref()), Ok(()));
+}
+
+#[test]
+#[should_panic(expected = "Invalid GetTupleElementOp")]
+fn elements_past_the_end_are_rejected() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[2, 3], ffi::Type::f32);
+    let tuple = converter.new_tuple_op(&[x]);
+    converter.new_get_tuple_element_op(tuple, 1);
+}
+
+#[test]
+fn elements_of_built_tuples_are_forwarded() {
+    let (x, y) = ("(input x@2_3 0)", "(input y@4 1)");
+    let tuple = format!("(TupleOp (Vec {} {}))", x, y);
+    assert!(equal(
+        &tuple_rules(),
+        &format!("(GetTupleElementOp {} 1)", tuple),
+        y
+    ));
+    assert!(equal(&tuple_rules(), &format!("(Index 0 {})", tuple), x));
+    assert!(!equal(
+        &tuple_rules(),
+        &format!("(GetTupleElementOp {} 0)", tuple),
+        y
+    ));
+}
+
+#[test]
+fn tuples_evaluate_to_their_elements() {
+    let expr: RecExpr<Mdl> = "(ReturnOp (Vec (GetTupleElementOp \
+                              (TupleOp (Vec (input x@2_3 0) (input y@4 1))) 0)))"
+        .parse()
+        .unwrap();
+    let direct: RecExpr<Mdl> = "(ReturnOp (Vec (input x@2_3 0)))".parse().unwrap();
+    let analysis = || TensorAnalysis::new(&HashMap::new(), &[]);
+    assert_eq!(
+        numerically_equivalent(&expr, &direct, analysis, 0.0, 0),
+        Ok(true)
+    );
+}
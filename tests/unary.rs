@@ -0,0 +1,152 @@
+mod common;
+
+use common::equal;
+use egg::RecExpr;
+use std::collections::HashMap;
+use tensat::interpreter::numerically_equivalent;
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::rewrites::unary_rules;
+
+const X: &str = "(input x@2_3 0)";
+
+/// Splat constant of `value` of the shape of X
+fn constant(value: f32) -> String {
+    format!("(ConstantOp {} (Vec 2 3))", value.to_bits() as i32)
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn log_of_exp_cancels() {
+    assert!(equal(&unary_rules(), &format!("(LogOp (ExpOp {}))", X), X));
+    assert!(!equal(&unary_rules(), &format!("(ExpOp (LogOp {}))", X), X));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn reciprocal_of_sqrt_is_rsqrt() {
+    let rsqrt = format!("(RsqrtOp {})", X);
+    assert!(equal(
+        &unary_rules(),
+        &format!("(DivOp {} (SqrtOp {}))", constant(1.0), X),
+        &rsqrt
+    ));
+    assert!(!equal(
+        &unary_rules(),
+        &format!("(DivOp {} (SqrtOp {}))", constant(2.0), X),
+        &rsqrt
+    ));
+    let y = "(input y@2_3 1)";
+    assert!(equal(
+        &unary_rules(),
+        &format!("(DivOp {} (SqrtOp {}))", y, X),
+        &format!("(MulOp {} {})", y, rsqrt)
+    ));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn sine_is_odd_and_cosine_even() {
+    let neg = format!("(NegOp {})", X);
+    assert!(equal(
+        &unary_rules(),
+        &format!("(SineOp {})", neg),
+        &format!("(NegOp (SineOp {}))", X)
+    ));
+    assert!(equal(
+        &unary_rules(),
+        &format!("(CosineOp {})", neg),
+        &format!("(CosineOp {})", X)
+    ));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn rules_hold_numerically() {
+    let analysis = || TensorAnalysis::new(&HashMap::new(), &[]);
+    let check = |a: &str, b: &str| {
+        let a: RecExpr<Mdl> = a.parse().unwrap();
+        let b: RecExpr<Mdl> = b.parse().unwrap();
+        numerically_equivalent(&a, &b, analysis, 1e-5, 0)
+    };
+    // Random inputs are in [-1, 1]
+    let positive = format!("(AddOp {} {})", X, constant(2.0));
+    assert_eq!(
+        check(
+            &format!("(RsqrtOp {})", positive),
+            &format!("(DivOp {} (SqrtOp {}))", constant(1.0), positive)
+        ),
+        Ok(true)
+    );
+    assert_eq!(
+        check(
+            &format!("(PowOp {} {})", X, constant(2.0)),
+            &format!("(MulOp {} {})", X, X)
+        ),
+        Ok(true)
+    );
+    assert_eq!(check(&format!("(LogOp (ExpOp {}))", X), X), Ok(true));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn idempotent_ops_applied_twice_simplify() {
+    for op in ["AbsOp", "SignOp", "FloorOp", "CeilOp", "RoundOp"] {
+        let once = format!("({} {})", op, X);
+        assert!(
+            equal(&unary_rules(), &format!("({} {})", op, once), &once),
+            "{}",
+            op
+        );
+    }
+    let floor = format!("(FloorOp {})", X);
+    assert!(equal(
+        &unary_rules(),
+        &format!("(CeilOp {})", floor),
+        &floor
+    ));
+    assert!(equal(
+        &unary_rules(),
+        &format!("(RoundOp {})", floor),
+        &floor
+    ));
+    assert!(!equal(
+        &unary_rules(),
+        &format!("(FloorOp (RoundOp {}))", X),
+        &floor
+    ));
+    assert!(equal(
+        &unary_rules(),
+        &format!("(AbsOp (NegOp {}))", X),
+        &format!("(AbsOp {})", X)
+    ));
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn sign_times_abs_is_the_input() {
+    let analysis = || TensorAnalysis::new(&HashMap::new(), &[]);
+    let a: RecExpr<Mdl> = format!("(MulOp (SignOp {}) (AbsOp {}))", X, X)
+        .parse()
+        .unwrap();
+    let b: RecExpr<Mdl> = X.parse().unwrap();
+    assert_eq!(numerically_equivalent(&a, &b, analysis, 0.0, 0), Ok(true));
+    assert!(equal(&unary_rules(), &a.to_string(), X));
+}
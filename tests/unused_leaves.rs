@@ -0,0 +1,33 @@
+use egg::RecExpr;
+use tensat::model::Mdl;
+use tensat::provenance::unused_leaves;
+
+const EXPR: &str = "(ReturnOp (Vec (AddOp (input a@2 0) (ConstantOp 1 (Vec 2))) (MulOp (input b@2 1) (ConstantOp 3 (Vec 2)))))";
+
+/// Indices in `expr` of the nodes with op `op`
+fn indices_of(expr: &RecExpr<Mdl>, op: &str) -> Vec<i32> {
+    (0..expr.as_ref().len())
+        .filter(|i| expr.as_ref()[*i].display_op().to_string() == op)
+        .map(|i| i as i32)
+        .collect()
+}
+
+#[test]
+fn leaves_without_an_optimized_node_are_unused() {
+    let expr: RecExpr<Mdl> = EXPR.parse().unwrap();
+    // The optimized graph kept the first input and constant, and computes the
+    // rest with new nodes (-1)
+    let constants = indices_of(&expr, "ConstantOp");
+    let origins = vec![-1, indices_of(&expr, "input")[0], constants[0], -1];
+    assert_eq!(
+        unused_leaves(&expr, &origins),
+        (vec![1], vec![constants[1]])
+    );
+}
+
+#[test]
+fn nothing_is_unused_when_every_leaf_survives() {
+    let expr: RecExpr<Mdl> = EXPR.parse().unwrap();
+    let origins: Vec<i32> = (0..expr.as_ref().len() as i32).collect();
+    assert_eq!(unused_leaves(&expr, &origins), (vec![], vec![]));
+}
@@ -0,0 +1,35 @@
+use egg::RecExpr;
+use tensat::model::Mdl;
+use tensat::passes::validate_vecs;
+
+fn validate(expr: &str) -> Result<(), String> {
+    let expr: RecExpr<Mdl> = expr.parse().unwrap();
+    validate_vecs(&expr)
+}
+
+#[test]
+fn attribute_and_operand_lists_are_accepted() {
+    validate(
+        "(ReturnOp (Vec (ConcatenateOp (Vec (input x@2_3 0) (input y@2_3 1)) 0) \
+         (TransposeOp (input x@2_3 0) (Vec 1 0))))",
+    )
+    .unwrap();
+}
+
+#[test]
+fn empty_vecs_are_accepted() {
+    validate("(DotGeneralOp (input x@2_3 0) (input y@3_4 1) (Vec) (Vec) (Vec 1) (Vec 0) (Vec))")
+        .unwrap();
+}
+
+#[test]
+fn tensor_in_attribute_list_is_rejected() {
+    let err = validate("(TransposeOp (input x@2_3 0) (Vec 1 (input y@2 1)))").unwrap_err();
+    assert!(err.contains("TransposeOp"), "{}", err);
+}
+
+#[test]
+fn num_in_operand_list_is_rejected() {
+    let err = validate("(ConcatenateOp (Vec (input x@2_3 0) 3) 0)").unwrap_err();
+    assert!(err.contains("ConcatenateOp"), "{}", err);
+}
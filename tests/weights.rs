@@ -0,0 +1,84 @@
+use egg::{EGraph, RecExpr, Runner};
+use std::collections::HashMap;
+use tensat::input::{ffi, CppGraphConverter};
+use tensat::model::{Mdl, TensorAnalysis};
+use tensat::optimize::{graph_cost, CostModel};
+use tensat::weights::*;
+
+/// Scale of 2.0, as the bits of the f32
+const TWO: i32 = 0x4000_0000;
+
+fn egraph() -> EGraph<Mdl, TensorAnalysis> {
+    EGraph::new(TensorAnalysis::new(&HashMap::new(), &[]))
+}
+
+fn scaled_dot(weight: &str) -> String {
+    format!(
+        "(MulOp (DotGeneralOp (input x@4_3 0) (input {}@3_5 1) (Vec) (Vec) (Vec 1) (Vec 0) (Vec)) (ConstantOp {} (Vec 4 5)))",
+        weight, TWO
+    )
+}
+
+fn folded_dot(weight: &str) -> String {
+    format!(
+        "(DotGeneralOp (input x@4_3 0) (MulOp (input {}@3_5 1) (ConstantOp {} (Vec 3 5))) (Vec) (Vec) (Vec 1) (Vec 0) (Vec))",
+        weight, TWO
+    )
+}
+
+/// Whether saturating `expr` with the weight rules makes it equal to `other`
+fn rewrites_to(expr: &str, other: &str) -> bool {
+    let expr: RecExpr<Mdl> = expr.parse().unwrap();
+    let mut runner = Runner::default()
+        .with_egraph(egraph())
+        .with_expr(&expr)
+        .with_iter_limit(2)
+        .run(&weight_rules());
+    let other = runner.egraph.add_expr(&other.parse().unwrap());
+    runner.egraph.rebuild();
+    runner.egraph.find(other) == runner.egraph.find(runner.roots[0])
+}
+
+#[test]
+fn weights_are_known_at_compile_time() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[4, 3], ffi::Type::f32);
+    let w = converter.new_weight(1, &[3, 5], ffi::Type::f32);
+    let neg = converter.new_neg_op(w, &[3, 5]);
+    let add = converter.new_add_op(x, x, &[4, 3]);
+    let constant = |t| converter.tensor(t).tensor_data.constant;
+    assert!(!constant(x));
+    assert!(constant(w));
+    assert!(constant(neg));
+    assert!(!constant(add));
+
+    let mut egraph = egraph();
+    let x = egraph.add_expr(&"(input x@4_3 0)".parse().unwrap());
+    let w = egraph.add_expr(&"(input w_0@3_5 1)".parse().unwrap());
+    let both = egraph.add_expr(&"(Vec (input x@4_3 0) (input w_0@3_5 1))".parse().unwrap());
+    assert!(!egraph[x].data.constant);
+    assert!(egraph[w].data.constant);
+    assert!(!egraph[both].data.constant);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn ops_on_weights_are_free() {
+    let analysis = || TensorAnalysis::new(&HashMap::new(), &[]);
+    let cost = |expr: &str| graph_cost(&expr.parse().unwrap(), analysis(), &CostModel::new());
+    assert_eq!(cost("(NegOp (input w_0@3_5 1))"), 0.0);
+    assert!(cost("(NegOp (input x@3_5 1))") > 0.0);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn scale_is_folded_into_weights_only() {
+    assert!(rewrites_to(&scaled_dot("w_0"), &folded_dot("w_0")));
+    assert!(!rewrites_to(&scaled_dot("y"), &folded_dot("y")));
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use common::shape;
+use tensat::config::ExtractorKind;
+use tensat::input::{ffi, infer_dtypes, CppGraphConverter};
+use tensat::model::Mdl;
+
+/// Loop over (i, limit, x) whose body transposes x twice, with the handles of
+/// x and the loop
+fn converter() -> (CppGraphConverter, ffi::TensorHandle, ffi::TensorHandle) {
+    let mut converter = CppGraphConverter::default();
+    let i = converter.new_input(0, &[], ffi::Type::i32);
+    let limit = converter.new_input(1, &[], ffi::Type::i32);
+    let x = converter.new_input(2, &[4, 4], ffi::Type::f32);
+
+    converter.begin_region();
+    let ci = converter.new_input(0, &[], ffi::Type::i32);
+    let climit = converter.new_input(1, &[], ffi::Type::i32);
+    converter.new_input(2, &[4, 4], ffi::Type::f32);
+    let less = converter.new_compare_op(ci, climit, 2, 0, &[]);
+    let cond = converter.end_region(&[less]);
+
+    converter.begin_region();
+    let bi = converter.new_input(0, &[], ffi::Type::i32);
+    let blimit = converter.new_input(1, &[], ffi::Type::i32);
+    let bx = converter.new_input(2, &[4, 4], ffi::Type::f32);
+    let t = converter.new_transpose_op(bx, &[1, 0], &[4, 4]);
+    let tt = converter.new_transpose_op(t, &[1, 0], &[4, 4]);
+    let body = converter.end_region(&[bi, blimit, tt]);
+
+    let shapes = vec![shape(&[]), shape(&[]), shape(&[4, 4])];
+    let loop_op = converter.new_while_op(&[i, limit, x], cond, body, &shapes);
+    let result = converter.new_index(2, loop_op);
+    converter.new_return_op(&[result]);
+    (converter, x, loop_op)
+}
+
+fn ops(graph: &ffi::Graph) -> Vec<&str> {
+    graph.nodes.iter().map(|n| n.name.as_str()).collect()
+}
+
+#[test]
+fn regions_are_graphs_of_their_own() {
+    let (converter, x, loop_op) = converter();
+    // Handles of the enclosing graph stay valid across regions
+    assert_eq!(converter.tensor_shape(x), vec![4, 4]);
+    let nodes = converter.rec_expr_ref().as_ref();
+    assert!(!nodes.iter().any(|n| matches!(n, Mdl::TransposeOp(_))));
+    match &nodes[loop_op.id as usize] {
+        Mdl::WhileOp([_, cond, body]) => {
+            assert_eq!(nodes[usize::from(*cond)], Mdl::Num(0));
+            assert_eq!(nodes[usize::from(*body)], Mdl::Num(1));
+        }
+        other => panic!("expected a WhileOp, got {}", other),
+    }
+
+    let body = converter.regions()[1].to_string();
+    assert!(body.starts_with("(ReturnOp (Vec (input input_0@@i32 0) (input input_1@@i32 1)"));
+    assert!(body.contains("(TransposeOp (TransposeOp (input input_2@4_4 2)"));
+}
+
+#[test]
+fn loop_results_have_the_dtypes_of_their_values() {
+    let (converter, _, loop_op) = converter();
+    let dtypes = infer_dtypes(converter.rec_expr_ref());
+    // The loop is typed by its first value, its results by theirs
+    assert!(dtypes[loop_op.id as usize] == ffi::Type::i32);
+    let index = dtypes.len() - 3;
+    assert!(matches!(
+        converter.rec_expr_ref().as_ref()[index],
+        Mdl::Index(_)
+    ));
+    assert!(dtypes[index] == ffi::Type::f32);
+}
+
+#[test]
+#[should_panic(expected = "Unknown region 2")]
+fn loops_need_built_regions() {
+    let mut converter = CppGraphConverter::default();
+    let x = converter.new_input(0, &[4], ffi::Type::f32);
+    converter.new_while_op(&[x], 0, 2, &vec![shape(&[4])]);
+}
+
+#[test]
+#[cfg_attr(
+    not(feature = "no-cxx-costmodel"),
+    ignore = "needs the C++ shape inference"
+)]
+fn optimize_simplifies_loop_bodies() {
+    let (mut converter, _, loop_op) = converter();
+    let config = converter.config_mut();
+    config.rule_file = format!("{}/converted.txt", env!("CARGO_MANIFEST_DIR"));
+    config.iter_limit = 3;
+    config.extractor = ExtractorKind::Greedy;
+    let graph = converter.optimize();
+    // The loop itself is kept, with the same regions
+    let node = graph.nodes.iter().find(|n| n.name == "WhileOp").unwrap();
+    assert_eq!(node.origin, loop_op.id);
+
+    let body = converter.region_graph(1);
+    assert!(!ops(&body).contains(&"TransposeOp"));
+    assert_eq!(body.results.len(), 3);
+    let x = body.results[2] as usize;
+    assert_eq!(body.nodes[x].name, "Input");
+    assert_eq!(body.nodes[x].operands.len(), 2);
+    assert_eq!(body.nodes[x].block_arg, 2);
+}